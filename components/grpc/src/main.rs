@@ -0,0 +1,55 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use clap::Parser;
+
+use dynamo_llm::discovery::{ModelWatcher, MODEL_ROOT_PATH};
+use dynamo_llm::grpc::service::GrpcService;
+use dynamo_runtime::{
+    logging, pipeline::RouterMode, transports::etcd::PrefixWatcher, DistributedRuntime, Result,
+    Runtime, Worker,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Host for the gRPC service
+    #[arg(long, default_value = "0.0.0.0")]
+    host: String,
+
+    /// Port number for the gRPC service
+    #[arg(short, long, default_value = "50051")]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init();
+    let worker = Worker::from_current()?;
+    worker.execute_async(app).await
+}
+
+async fn app(runtime: Runtime) -> Result<()> {
+    let distributed = DistributedRuntime::from_settings(runtime.clone()).await?;
+    let args = Args::parse();
+
+    let grpc_service = GrpcService::builder()
+        .port(args.port)
+        .host(args.host)
+        .build()?;
+    let manager = grpc_service.manager_clone();
+
+    let watch_obj = ModelWatcher::new(distributed.clone(), manager, RouterMode::Random, None);
+
+    if let Some(etcd_client) = distributed.etcd_client() {
+        let models_watcher: PrefixWatcher =
+            etcd_client.kv_get_and_watch_prefix(MODEL_ROOT_PATH).await?;
+
+        let (_prefix, _watcher, receiver) = models_watcher.dissolve();
+        tokio::spawn(async move {
+            watch_obj.watch(receiver).await;
+        });
+    }
+
+    grpc_service.run(runtime.child_token()).await
+}
@@ -1,13 +1,19 @@
 // SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use dynamo_llm::discovery::{ModelWatcher, MODEL_ROOT_PATH};
-use dynamo_llm::http::service::service_v2::HttpService;
+use clap::{Parser, ValueEnum};
+
+use dynamo_llm::discovery::{
+    routing::{self, RoutingPolicy},
+    ModelWatcher, MODEL_ROOT_PATH,
+};
+use dynamo_llm::http::service::{service_v2::HttpService, tls::TlsConfig};
 use dynamo_runtime::{
-    logging, pipeline::RouterMode, transports::etcd::PrefixWatcher, DistributedRuntime, Result,
-    Runtime, Worker,
+    logging, observability, pipeline::RouterMode, transports::etcd::PrefixWatcher,
+    DistributedRuntime, Result, Runtime, Worker,
 };
 
 #[derive(Parser)]
@@ -21,6 +27,18 @@ struct Args {
     #[arg(short, long, default_value = "8080")]
     port: u16,
 
+    /// PEM certificate chain. Serve HTTPS instead of HTTP, requires --tls-key.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Seconds between keep-alive comments on idle SSE streams, 0 disables them
+    #[arg(long, default_value = "15")]
+    sse_keep_alive_secs: u64,
+
     /// Namespace for the distributed component
     #[arg(long, default_value = "public")]
     namespace: String,
@@ -28,6 +46,33 @@ struct Args {
     /// Component name for the service
     #[arg(long, default_value = "http")]
     component: String,
+
+    /// How to pick the worker of a request. `least-loaded` picks the one with the fewest requests
+    /// in flight from this service, `kv` the one with the most of the prompt in its KV cache.
+    /// With `pull` the workers started with a pull capacity take the requests from a work queue.
+    #[arg(long, default_value = "random")]
+    router_mode: RouterModeArg,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum RouterModeArg {
+    Random,
+    RoundRobin,
+    LeastLoaded,
+    Pull,
+    Kv,
+}
+
+impl From<RouterModeArg> for RouterMode {
+    fn from(mode: RouterModeArg) -> RouterMode {
+        match mode {
+            RouterModeArg::Random => RouterMode::Random,
+            RouterModeArg::RoundRobin => RouterMode::RoundRobin,
+            RouterModeArg::LeastLoaded => RouterMode::LeastLoaded,
+            RouterModeArg::Pull => RouterMode::Pull,
+            RouterModeArg::Kv => RouterMode::KV,
+        }
+    }
 }
 
 #[tokio::main]
@@ -40,10 +85,20 @@ async fn main() -> Result<()> {
 async fn app(runtime: Runtime) -> Result<()> {
     let distributed = DistributedRuntime::from_settings(runtime.clone()).await?;
     let args = Args::parse();
+    // Exported on the /metrics of the HTTP service
+    observability::start(&runtime);
 
     let http_service = HttpService::builder()
         .port(args.port)
         .host(args.host)
+        .with_tls(
+            args.tls_cert
+                .zip(args.tls_key)
+                .map(|(cert, key)| TlsConfig::new(cert, key)),
+        )
+        .with_sse_keep_alive(
+            (args.sse_keep_alive_secs > 0).then(|| Duration::from_secs(args.sse_keep_alive_secs)),
+        )
         .build()?;
     let manager = http_service.state().manager_clone();
 
@@ -56,9 +111,21 @@ async fn app(runtime: Runtime) -> Result<()> {
     // the cli when operating on an `http` component will validate the namespace.component is
     // registered with HttpServiceComponentDefinition
 
-    let watch_obj = ModelWatcher::new(distributed.clone(), manager, RouterMode::Random, None);
+    // In KV mode each model gets a KV router with the default config
+    let watch_obj = ModelWatcher::new(
+        distributed.clone(),
+        manager.clone(),
+        args.router_mode.into(),
+        None,
+    );
 
     if let Some(etcd_client) = distributed.etcd_client() {
+        // Model aliases, splits and default model
+        distributed.runtime().tasks().spawn(
+            "model routing",
+            routing::follow(manager, etcd_client.clone(), RoutingPolicy::default()),
+        );
+
         let models_watcher: PrefixWatcher =
             etcd_client.kv_get_and_watch_prefix(MODEL_ROOT_PATH).await?;
 
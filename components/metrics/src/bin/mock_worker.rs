@@ -123,6 +123,9 @@ fn mock_stats_handler(_stats: EndpointStats) -> serde_json::Value {
         num_requests_waiting,
         gpu_cache_usage_perc,
         gpu_prefix_cache_hit_rate,
+        decode_tokens_per_sec: None,
+        lora_ids: None,
+        kv_block_size: None,
     };
     tracing::info!("Stats: {stats:?}");
     serde_json::to_value(stats).unwrap()
@@ -20,34 +20,93 @@
 // 2. Update the backend component to produce a config in a standard location.
 // 3. Update the KvRouter to read the config from the backend component.
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 
 use dynamo_llm::kv_router::{
+    circuit_breaker::CircuitBreakerConfig,
+    cluster::HierarchicalRouter,
+    indexer::{IndexerConfig, IndexerLimits},
     protocols::WorkerSelectionResult,
-    scheduler::{DefaultWorkerSelector, KvSchedulerError, SchedulingRequest},
-    scoring::ProcessedEndpoints,
-    KvRouter, WorkerSelector,
-};
-use dynamo_runtime::{
-    logging, pipeline::network::Ingress, DistributedRuntime, Result, Runtime, Worker,
+    recorder,
+    scheduler::{
+        BalancedWorkerSelector, DefaultWorkerSelector, KvSchedulerError, SchedulingRequest,
+    },
+    scoring::{BalancedWeights, ProcessedEndpoints},
+    serve_scheduler,
+    snapshot::{IndexSnapshotConfig, SnapshotStore},
+    KvRouter, KvRouterConfig, WorkerSelector,
 };
+use dynamo_runtime::{logging, DistributedRuntime, Result, Runtime, Worker};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Namespace for the distributed component
-    #[arg(long)]
-    namespace: String,
+    #[arg(long, required_unless_present = "replay")]
+    namespace: Option<String>,
 
     /// Component name for the service
     #[arg(long, default_value = "kv_aware_router")]
     component: String,
 
+    /// Route over several clusters: pick one of these namespaces for each request, then a worker
+    /// of `--component` in it. The scheduler is still served in `--namespace`.
+    #[arg(long = "cluster", conflicts_with_all = ["replay", "record", "index_snapshot"])]
+    clusters: Vec<String>,
+
     /// Block size for the router
     #[arg(long)]
     block_size: usize,
+
+    /// Most KV blocks to index, counted once per worker. Unbounded if not set.
+    #[arg(long)]
+    index_max_entries: Option<usize>,
+
+    /// Most memory the index of KV blocks may take, estimated. Unbounded if not set.
+    #[arg(long)]
+    index_max_mib: Option<usize>,
+
+    /// Forget KV blocks not stored or matched for this many seconds. Never if not set.
+    #[arg(long)]
+    index_block_ttl_secs: Option<u64>,
+
+    /// Split the index of KV blocks into this many shards, each applying the events of its
+    /// workers on its own thread. Raise it when the router can't keep up with the KV events of
+    /// hundreds of workers.
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    index_shards: u32,
+
+    /// Save the index of KV blocks to this directory, or `nats://<bucket>` in the NATS object
+    /// store, and restore it at start. Not saved if not set.
+    #[arg(long)]
+    index_snapshot: Option<SnapshotStore>,
+
+    /// How often to save the index of KV blocks
+    #[arg(long, default_value = "60")]
+    index_snapshot_interval_secs: u64,
+
+    /// At start, ask a running router for its index of KV blocks
+    #[arg(long)]
+    index_bootstrap: bool,
+
+    /// Above 0, pick workers at random with probability proportional to
+    /// exp(score / temperature) instead of always the best one, so that several routers
+    /// spread their requests over workers that score about the same
+    #[arg(long, default_value = "0.0")]
+    temperature: f64,
+
+    /// Record the KV events, worker load and routing decisions to this JSONL file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Don't route: replay a file written with `--record` through each worker selector and
+    /// print how they compare with the recorded decisions
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -58,31 +117,106 @@ fn main() -> Result<()> {
 
 async fn app(runtime: Runtime) -> Result<()> {
     let args = Args::parse();
+    if let Some(path) = &args.replay {
+        return replay(path, args.block_size).await;
+    }
     let runtime = DistributedRuntime::from_settings(runtime).await?;
 
-    let component = runtime
-        .namespace(&args.namespace)?
-        .component(&args.component)?;
+    // safety: clap requires it unless replaying
+    let namespace = args.namespace.as_deref().unwrap();
+    let component = runtime.namespace(namespace)?.component(&args.component)?;
+
+    let selector = Box::new(CustomWorkerSelector::new(args.temperature));
+
+    let indexer = IndexerConfig {
+        limits: IndexerLimits {
+            max_entries: args.index_max_entries,
+            max_memory_bytes: args.index_max_mib.map(|mib| mib * 1024 * 1024),
+            block_ttl: args.index_block_ttl_secs.map(Duration::from_secs),
+        },
+        shards: args.index_shards as usize,
+    };
 
-    let selector = Box::new(CustomWorkerSelector::default());
+    let index_snapshot = IndexSnapshotConfig {
+        store: args.index_snapshot,
+        interval: Duration::from_secs(args.index_snapshot_interval_secs.max(1)),
+        bootstrap: args.index_bootstrap,
+    };
 
-    let router = KvRouter::new(component.clone(), args.block_size, Some(selector)).await?;
-    let router = Ingress::for_engine(Arc::new(router))?;
+    if !args.clusters.is_empty() {
+        let mut clusters = Vec::with_capacity(args.clusters.len());
+        for cluster in &args.clusters {
+            let cluster_component = runtime.namespace(cluster)?.component(&args.component)?;
+            let router = KvRouter::new(
+                cluster_component,
+                args.block_size,
+                Some(Box::new(CustomWorkerSelector::new(args.temperature))),
+                indexer,
+                IndexSnapshotConfig::default(),
+                None,
+                CircuitBreakerConfig::default(),
+            )
+            .await?;
+            clusters.push((cluster.clone(), Arc::new(router)));
+        }
+        let router = HierarchicalRouter::new(clusters, None)?;
+        return serve_scheduler(component, Arc::new(router)).await;
+    }
+
+    let router = KvRouter::new(
+        component.clone(),
+        args.block_size,
+        Some(selector),
+        indexer,
+        index_snapshot,
+        args.record,
+        // Callers send the requests, so only the blacklist applies
+        CircuitBreakerConfig::default(),
+    )
+    .await?;
+    serve_scheduler(component, Arc::new(router)).await
+}
 
-    component
-        .service_builder()
-        .create()
-        .await?
-        .endpoint("generate")
-        .endpoint_builder()
-        .handler(router)
-        .start()
-        .await
+/// Replay the recording at `path` through the built-in selectors and [`CustomWorkerSelector`]
+async fn replay(path: &Path, block_size: usize) -> Result<()> {
+    let selectors: Vec<(&str, Box<dyn WorkerSelector + Send + Sync>)> = vec![
+        ("default", Box::new(DefaultWorkerSelector::default())),
+        (
+            "balanced",
+            Box::new(BalancedWorkerSelector::new(BalancedWeights::default())),
+        ),
+        ("custom", Box::new(CustomWorkerSelector::default())),
+    ];
+    println!(
+        "{:<10} {:>9} {:>9} {:>8} {:>9} {:>9}",
+        "selector", "decisions", "unplaced", "same", "hit rate", "recorded"
+    );
+    for (name, selector) in selectors {
+        let report = recorder::replay(path, block_size, selector.as_ref()).await?;
+        println!(
+            "{:<10} {:>9} {:>9} {:>8} {:>8.1}% {:>8.1}%",
+            name,
+            report.decisions,
+            report.unplaced,
+            report.same_worker,
+            report.replayed_hit_rate() * 100.0,
+            report.recorded_hit_rate() * 100.0,
+        );
+    }
+    Ok(())
 }
 
 #[derive(Default)]
 pub struct CustomWorkerSelector(DefaultWorkerSelector);
 
+impl CustomWorkerSelector {
+    /// Sample workers at `temperature`, 0 for the best one
+    pub fn new(temperature: f64) -> Self {
+        let config = KvRouterConfig::default().with_temperature(temperature.max(0.0));
+        CustomWorkerSelector(DefaultWorkerSelector::new(Some(config)))
+    }
+}
+
 impl WorkerSelector for CustomWorkerSelector {
     fn select_worker(
         &self,
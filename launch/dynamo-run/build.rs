@@ -17,6 +17,16 @@ use std::env;
 use std::process::Command;
 
 fn main() {
+    // Reported by `--version --verbose`. Builds outside a git checkout can set it themselves.
+    println!("cargo:rerun-if-env-changed=DYNAMO_GIT_SHA");
+    if env::var("DYNAMO_GIT_SHA").is_err() {
+        if let Some((sha, git_dir)) = git_sha() {
+            println!("cargo:rustc-env=DYNAMO_GIT_SHA={sha}");
+            println!("cargo:rerun-if-changed={git_dir}/HEAD");
+            println!("cargo:rerun-if-changed={git_dir}/index");
+        }
+    }
+
     if has_cuda_toolkit() && !has_feature("cuda") && is_cuda_engine() {
         println!("cargo:warning=CUDA not enabled, re-run with `--features cuda`");
     }
@@ -29,6 +39,21 @@ fn has_feature(s: &str) -> bool {
     env::var(format!("CARGO_FEATURE_{}", s.to_uppercase())).is_ok()
 }
 
+/// The commit checked out and the git directory, if building in a git checkout
+fn git_sha() -> Option<(String, String)> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    Some((
+        git(&["rev-parse", "HEAD"])?,
+        git(&["rev-parse", "--absolute-git-dir"])?,
+    ))
+}
+
 fn has_cuda_toolkit() -> bool {
     if let Ok(output) = Command::new("nvcc").arg("--version").output() {
         output.status.success()
@@ -13,12 +13,48 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::ValueEnum;
-use dynamo_llm::kv_router::KvRouterConfig;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, ValueEnum};
+use dynamo_llm::audit::Redaction;
+use dynamo_llm::budget_router::{self, BudgetPolicy};
+use dynamo_llm::discovery::worker_admission::WorkerAdmissionPolicy;
+use dynamo_llm::embedding_router::EmbeddingBatchConfig;
+use dynamo_llm::http::service::admission::{AdmissionConfig, AdmissionLimit};
+use dynamo_llm::http::service::cors::{AllowedOrigins, CorsConfig};
+use dynamo_llm::http::service::limits::{RequestTimeouts, TimeoutConfig};
+use dynamo_llm::http::service::output_rate::OutputRateConfig;
+use dynamo_llm::http::service::rate_limit::RateLimitConfig;
+use dynamo_llm::http::service::spillover::SpilloverConfig;
+use dynamo_llm::kv_router::{
+    affinity::SessionAffinityConfig,
+    circuit_breaker::CircuitBreakerConfig,
+    indexer::IndexerLimits,
+    snapshot::{IndexSnapshotConfig, SnapshotStore},
+    KvRouterConfig, WorkerSelectorKind,
+};
+use dynamo_llm::local_model::DuplicateRegistration;
+use dynamo_llm::metrics_recorder::MetricsRecorderConfig;
+use dynamo_llm::model_card::model::{
+    GenerationLimits, PromptCompression, SamplingPresets, SpecialTokens,
+};
+use dynamo_llm::prefill_queue::PrefillQueueConfig;
+use dynamo_llm::prefill_router::DisaggConfig;
+use dynamo_llm::protocols::openai::nvext::{self, NvExtPolicy, UnknownKeyPolicy};
+use dynamo_llm::request_log::{RedactionPolicy, RequestLog};
+use dynamo_llm::reschedule::RescheduleConfig;
+use dynamo_llm::response_tee::{ResponseTee, ResponseTeeConfig};
+use dynamo_llm::speculative::SpeculativeConfig;
 use dynamo_runtime::pipeline::RouterMode as RuntimeRouterMode;
+use url::Url;
+
+use crate::input::batch::ColumnMapping;
+use crate::timeouts::{TimeoutProfile, Timeouts};
 
 /// Required options depend on the in and out choices
 #[derive(clap::Parser, Debug, Clone)]
@@ -42,6 +78,282 @@ pub struct Flags {
     #[arg(long, default_value = "8080")]
     pub http_port: u16,
 
+    /// gRPC port. `in=grpc` only
+    #[arg(long, default_value = "50051")]
+    pub grpc_port: u16,
+
+    /// Serve the metrics of the process, such as tokio tasks, memory and file descriptors, on
+    /// `/metrics` of this port. For workers and the other inputs without an HTTP service;
+    /// `in=http` has them on its own `/metrics`.
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// Record the per-model metrics of `in=http` in this SQLite database, for deployments without
+    /// Prometheus. Export them with `dynamo-run metrics export`.
+    #[arg(long)]
+    pub metrics_record_db: Option<PathBuf>,
+
+    /// How often `--metrics-record-db` takes a snapshot of the metrics.
+    #[arg(long, default_value = "60")]
+    pub metrics_record_interval_secs: u64,
+
+    /// Delete recorded metrics older than this many hours. Kept forever if not set.
+    #[arg(long)]
+    pub metrics_record_retention_hours: Option<u64>,
+
+    /// Keep at most this many recorded samples, deleting the oldest. One sample is one metric of
+    /// one model.
+    #[arg(long)]
+    pub metrics_record_max_rows: Option<u64>,
+
+    /// Allow browsers on these origins to call the HTTP service directly. `in=http` only.
+    /// Comma separated, e.g. `http://localhost:3000,https://playground.example.com`, or `*` for
+    /// any origin. CORS is disabled if not set.
+    #[arg(long, value_delimiter = ',')]
+    pub http_cors_allowed_origins: Vec<String>,
+
+    /// Methods browsers may use across origins, comma separated. Default: GET,POST,OPTIONS.
+    #[arg(long, value_delimiter = ',')]
+    pub http_cors_allowed_methods: Vec<String>,
+
+    /// Request headers browsers may send across origins, comma separated, e.g.
+    /// `authorization,content-type`. Default: whatever the browser asks for.
+    #[arg(long, value_delimiter = ',')]
+    pub http_cors_allowed_headers: Vec<String>,
+
+    /// Response headers browsers may read across origins, comma separated, e.g. `x-request-id`
+    #[arg(long, value_delimiter = ',')]
+    pub http_cors_exposed_headers: Vec<String>,
+
+    /// Let browsers send cookies and HTTP authentication across origins. With `*` as the origin
+    /// the request's origin is allowed instead, as browsers refuse `*` with credentials.
+    #[arg(long)]
+    pub http_cors_allow_credentials: bool,
+
+    /// Serve a minimal web chat UI on `/playground`. `in=http` only. Intended for demos, do not
+    /// enable in production. With API keys, enter one in the page.
+    #[arg(long)]
+    pub http_playground: bool,
+
+    /// Also serve the Ollama API: `/api/chat`, `/api/generate` and `/api/tags`. `in=http` only.
+    /// For tools that only speak Ollama.
+    #[arg(long)]
+    pub http_ollama: bool,
+
+    /// How long, in seconds, to remember `Idempotency-Key` request headers so that retried POSTs
+    /// get the original response instead of a new generation. `in=http` only. 0 disables it.
+    #[arg(long, default_value = "600")]
+    pub http_idempotency_ttl_secs: u64,
+
+    /// Send an SSE comment every this many seconds on a streaming response that has nothing else
+    /// to send, so that proxies don't close the connection while the model prepares the first
+    /// token. `in=http` only. 0 disables it.
+    #[arg(long, default_value = "15")]
+    pub http_sse_keep_alive_secs: u64,
+
+    /// Require `Authorization: Bearer <key>` using the keys in this file, one per line. The file
+    /// is re-read when it changes. `in=http` and `in=grpc` only, gRPC clients send it as
+    /// `authorization` metadata.
+    #[arg(long)]
+    pub http_api_keys_file: Option<PathBuf>,
+
+    /// Require `Authorization: Bearer <key>` using the keys stored as values under this etcd
+    /// prefix, e.g. `/dynamo/api_keys/`. Can be combined with `--http-api-keys-file`. `in=http`
+    /// and `in=grpc` only.
+    #[arg(long)]
+    pub http_api_keys_etcd_prefix: Option<String>,
+
+    /// Serve the admin API, to load and unload models on workers, under `/admin/`. Requests need
+    /// `Authorization: Bearer <key>` with a key from this file, one per line. `in=http out=dyn`
+    /// only.
+    #[arg(long)]
+    pub http_admin_keys_file: Option<PathBuf>,
+
+    /// PEM certificate chain. Serve HTTPS instead of HTTP, requires `--http-tls-key`.
+    /// `in=http` only.
+    #[arg(long, requires = "http_tls_key")]
+    pub http_tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--http-tls-cert`. `in=http` only.
+    #[arg(long, requires = "http_tls_cert")]
+    pub http_tls_key: Option<PathBuf>,
+
+    /// Maximum requests per minute across all clients. `in=http` only.
+    #[arg(long)]
+    pub http_rate_limit_rpm: Option<u32>,
+
+    /// Maximum requests in progress at once across all clients, further requests get a 429.
+    /// `in=http` only.
+    #[arg(long)]
+    pub http_max_concurrent_requests: Option<u32>,
+
+    /// JSON file with global, per API key and per model rate limits, e.g.
+    /// `{"per_key": {"requests_per_minute": 600}, "per_model": {"llama": {"max_concurrent": 8}}}`.
+    /// `--http-rate-limit-rpm` and `--http-max-concurrent-requests` override its global limits.
+    /// `in=http` only.
+    #[arg(long)]
+    pub http_rate_limit_config: Option<PathBuf>,
+
+    /// Send the tokens of streamed responses at most this fast, also to requests that ask for
+    /// more with `nvext.max_tokens_per_sec`. `in=http` only.
+    #[arg(long)]
+    pub http_max_tokens_per_sec: Option<f64>,
+
+    /// JSON file with output rate caps per API key, e.g.
+    /// `{"default": {"max_tokens_per_sec": 200}, "per_key": {"key-3b9a5c21f0e4": {"tokens_per_sec": 25}}}`.
+    /// `--http-max-tokens-per-sec` overrides its default maximum. `in=http` only.
+    #[arg(long)]
+    pub http_output_rate_config: Option<PathBuf>,
+
+    /// Maximum requests in progress at once across all clients, further requests wait in a queue
+    /// of `--http-admission-max-queue`. `in=http` only.
+    #[arg(long)]
+    pub http_admission_max_concurrent: Option<usize>,
+
+    /// Length of the queue of `--http-admission-max-concurrent`. Requests that find it full get a
+    /// 503. `in=http` only.
+    #[arg(long, default_value = "0")]
+    pub http_admission_max_queue: usize,
+
+    /// Reject requests, with a 503, that waited this many seconds in the admission queue.
+    /// `in=http` only.
+    #[arg(long)]
+    pub http_admission_queue_timeout_secs: Option<u64>,
+
+    /// JSON file with global and per model admission queues, e.g.
+    /// `{"global": {"max_concurrent": 256, "max_queue": 1024}, "per_model": {"llama": {"max_concurrent": 32, "max_queue": 64}}}`.
+    /// The other `--http-admission-*` flags override its global settings. `in=http` only.
+    #[arg(long)]
+    pub http_admission_config: Option<PathBuf>,
+
+    /// The timeouts to start from, for the environment dynamo-run runs in. The flags of single
+    /// timeouts override it.
+    #[arg(long, default_value = "dev")]
+    pub timeout_profile: TimeoutProfile,
+
+    /// Give up on a request, with a `504 Gateway Timeout`, if no worker accepted it within this
+    /// many seconds. `in=http` only.
+    #[arg(long)]
+    pub http_connect_timeout_secs: Option<u64>,
+
+    /// Give up on a request if its first token didn't come within this many seconds of it
+    /// arriving. The worker stops generating. `in=http` only.
+    #[arg(long)]
+    pub http_first_token_timeout_secs: Option<u64>,
+
+    /// Give up on a request if its response isn't complete within this many seconds. A streamed
+    /// response ends with an error event. `in=http` only.
+    #[arg(long)]
+    pub http_request_timeout_secs: Option<u64>,
+
+    /// Give up on a streamed request if the next token didn't come within this many seconds of
+    /// the previous one. `in=http` only.
+    #[arg(long)]
+    pub http_stream_idle_timeout_secs: Option<u64>,
+
+    /// How long a draining worker waits for its requests in flight, in seconds. Replaces
+    /// DYN_WORKER_DRAIN_TIMEOUT.
+    #[arg(long)]
+    pub drain_timeout_secs: Option<u64>,
+
+    /// How long an engine sub-process (vllm, sglang, trtllm) has to exit at shutdown before it is
+    /// killed, in seconds.
+    #[arg(long)]
+    pub subprocess_stop_timeout_secs: Option<u64>,
+
+    /// Reject requests with a body larger than this many MiB. `in=http` only.
+    #[arg(long, default_value = "2")]
+    pub http_max_body_mib: usize,
+
+    /// JSON file with model aliases, weighted splits between models and a default model, e.g.
+    /// `{"aliases": {"gpt-4o": "llama"}, "default_model": "llama"}`. The policy in etcd under
+    /// `/dynamo/model_routing` replaces it while it exists. `in=http` and `in=grpc` only.
+    #[arg(long)]
+    pub model_routing: Option<PathBuf>,
+
+    /// Cache responses to identical non-streaming requests with `temperature` 0 or a `seed`, for
+    /// this many seconds. `in=http` only. 0 disables the cache.
+    #[arg(long, default_value = "0")]
+    pub http_response_cache_ttl_secs: u64,
+
+    /// Maximum number of responses in the in-process response cache.
+    #[arg(long, default_value = "10000")]
+    pub http_response_cache_max_entries: usize,
+
+    /// Maximum size in MiB of the responses in the in-process response cache.
+    #[arg(long, default_value = "256")]
+    pub http_response_cache_max_mib: usize,
+
+    /// Keep the response cache in Redis at this URL, e.g. `redis://127.0.0.1:6379`, shared by
+    /// every ingress, instead of in this process. Needs the `redis` feature.
+    #[arg(long)]
+    pub http_response_cache_redis_url: Option<String>,
+
+    /// While a model has no workers, write its non-streaming requests with
+    /// `nvext.deferrable: true` to this directory and answer `202 Accepted`. They are sent once
+    /// workers are back, the result is at `/v1/deferred/{id}`. `in=http` only.
+    #[arg(long)]
+    pub http_spillover_dir: Option<PathBuf>,
+
+    /// Maximum number of deferred requests waiting or in progress.
+    #[arg(long, default_value = "10000")]
+    pub http_spillover_max_requests: usize,
+
+    /// Maximum number of deferred requests sent to the workers at once.
+    #[arg(long, default_value = "16")]
+    pub http_spillover_max_dispatching: usize,
+
+    /// Fail a deferred request that waited this many seconds for workers.
+    #[arg(long, default_value = "3600")]
+    pub http_spillover_max_wait_secs: u64,
+
+    /// Keep the result of a deferred request for this many seconds.
+    #[arg(long, default_value = "3600")]
+    pub http_spillover_result_ttl_secs: u64,
+
+    /// Append a JSON usage record for each request, with its API key, model, token counts,
+    /// latency and worker, to this file. `in=http` only.
+    #[arg(long)]
+    pub http_usage_log: Option<PathBuf>,
+
+    /// Publish the usage records as JSON on this NATS subject, e.g. `dynamo.usage`. `in=http`
+    /// only.
+    #[arg(long)]
+    pub http_usage_nats_subject: Option<String>,
+
+    /// POST each usage record as JSON to this URL. `in=http` only.
+    #[arg(long)]
+    pub http_usage_webhook_url: Option<String>,
+
+    /// in=http and in=grpc only
+    ///
+    /// What to do with `nvext` keys in requests that Dynamo doesn't know about: drop them,
+    /// reject the request, or pass them through to the workers.
+    #[arg(long, default_value = "drop")]
+    pub nvext_unknown_keys: NvExtUnknownKeys,
+
+    /// in=http and in=grpc only
+    ///
+    /// Extra `nvext` keys the workers of this deployment understand. Always passed through.
+    #[arg(long, value_delimiter = ',')]
+    pub nvext_allowed_keys: Vec<String>,
+
+    /// in=http only
+    ///
+    /// The principals, as logged (`key-` and the start of the hash of the API key), that may pin
+    /// requests to a worker with `nvext.routing.backend_instance_id`. `*` for anybody. Nobody if
+    /// not set, the field is dropped.
+    #[arg(long, value_delimiter = ',')]
+    pub nvext_pinning_principals: Vec<String>,
+
+    /// in=http and in=grpc only
+    ///
+    /// The highest `nvext.priority`, or `x-dynamo-priority`, each principal may ask for, as
+    /// `<principal>=<max>`, e.g. `key-0123456789ab=interactive`. `*` for the principals not
+    /// listed. Higher priorities are lowered to it; without an entry the maximum is 0.
+    #[arg(long, value_delimiter = ',', value_parser = parse_max_priority)]
+    pub nvext_max_priority: Vec<(String, i32)>,
+
     /// The name of the model we are serving
     #[arg(long)]
     pub model_name: Option<String>,
@@ -59,12 +371,13 @@ pub struct Flags {
     #[arg(long)]
     pub model_config: Option<PathBuf>,
 
-    /// sglang, vllm
+    /// sglang, vllm, trtllm
     ///
     /// How many GPUs to use at once, total across all nodes.
     /// This must divide by num_nodes, and each node must use the same number of GPUs.
-    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..256))]
-    pub tensor_parallel_size: u32,
+    /// Defaults to all the visible GPUs on each node.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..256))]
+    pub tensor_parallel_size: Option<u32>,
 
     /// sglang only
     /// vllm uses CUDA_VISIBLE_DEVICES env var
@@ -101,11 +414,120 @@ pub struct Flags {
 
     /// If using `out=dyn` with multiple instances, this says how to route the requests.
     ///
-    /// Mostly interesting for KV-aware routing.
+    /// Mostly interesting for KV-aware routing. `least-loaded` picks the worker with the fewest
+    /// requests in flight from this process. `pull` puts the requests on a work queue, which the
+    /// workers started with `--pull-capacity` take them from as they have room. `budget` prefers
+    /// the cheaper workers, by their `--cost-per-gpu-hour`, as `--budget-policy` allows.
     /// Defaults to RouterMode::RoundRobin
     #[arg(long, default_value = "round-robin")]
     pub router_mode: RouterMode,
 
+    /// JSON file of the engine, and its oldest version, the workers of each model must run, e.g.
+    /// `{"per_model": {"llama": {"engine": "vllm", "min_engine_version": "0.9.1"}}}`. Workers
+    /// that don't get no requests. `out=dyn` only.
+    #[arg(long)]
+    pub worker_admission_policy: Option<PathBuf>,
+
+    /// JSON file of how much queueing each request class takes for cheaper workers with
+    /// `--router-mode budget`, e.g. `{"classes": {"batch": {"max_inflight": 32}}}`. Requests
+    /// set their class with `nvext.routing.request_class`. `out=dyn` only.
+    #[arg(long)]
+    pub budget_policy: Option<PathBuf>,
+
+    /// Region this process runs in, e.g. `us-east-1`. Published with our endpoints so routers can
+    /// prefer workers close to them. Same as `DYN_LOCALITY_REGION`.
+    #[arg(long)]
+    pub region: Option<String>,
+
+    /// Zone this process runs in, e.g. `us-east-1a`. Same as `DYN_LOCALITY_ZONE`.
+    #[arg(long)]
+    pub zone: Option<String>,
+
+    /// Which workers a router may pick relative to its own zone: `any`, `prefer-local`
+    /// (default, spill over to other zones only when ours has no workers) or `local-only`.
+    /// Same as `DYN_LOCALITY_POLICY`.
+    #[arg(long)]
+    pub zone_policy: Option<String>,
+
+    /// NATS server, or a comma separated list of a cluster's servers, e.g.
+    /// `tls://nats-0:4222,tls://nats-1:4222`. Same as `NATS_SERVER`.
+    #[arg(long)]
+    pub nats_server: Option<String>,
+
+    /// NATS credentials file with the user JWT and nkey seed, for servers using accounts. Same as
+    /// `NATS_AUTH_CREDENTIALS_FILE`.
+    #[arg(long, conflicts_with = "nats_nkey_file")]
+    pub nats_creds_file: Option<PathBuf>,
+
+    /// File with the nkey seed to authenticate to NATS with. Same as `NATS_AUTH_NKEY_FILE`.
+    #[arg(long)]
+    pub nats_nkey_file: Option<PathBuf>,
+
+    /// PEM CA certificate to verify the NATS servers with, in addition to the system roots. Same
+    /// as `NATS_TLS_CA_FILE`.
+    #[arg(long)]
+    pub nats_tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate for NATS servers that verify clients, requires `--nats-tls-key`.
+    /// Same as `NATS_TLS_CERT_FILE`.
+    #[arg(long, requires = "nats_tls_key")]
+    pub nats_tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--nats-tls-cert`. Same as `NATS_TLS_KEY_FILE`.
+    #[arg(long, requires = "nats_tls_cert")]
+    pub nats_tls_key: Option<PathBuf>,
+
+    /// Refuse to connect to NATS without TLS. Same as `NATS_TLS_REQUIRED`.
+    #[arg(long)]
+    pub nats_tls_required: bool,
+
+    /// Give up after this many failed attempts in a row to reconnect to NATS. Retries forever by
+    /// default. Same as `NATS_MAX_RECONNECTS`.
+    #[arg(long)]
+    pub nats_max_reconnects: Option<usize>,
+
+    /// JSON file with a key per namespace, e.g. `{"dynamo": "<64 hex digits>"}`. Requests and
+    /// responses of those namespaces are encrypted between router and worker, the NATS servers
+    /// can't read them. Same as `DYN_PAYLOAD_ENCRYPTION_KEYS_FILE`.
+    #[arg(long)]
+    pub payload_keys_file: Option<PathBuf>,
+
+    /// File with the cluster key, 64 hex digits. Drain, activation and other control messages are
+    /// signed with it, and workers refuse the ones that aren't. The ingress signs continuation
+    /// tokens with a key derived from it. Same as `DYN_CONTROL_SIGNING_KEY_FILE`.
+    #[arg(long)]
+    pub control_key_file: Option<PathBuf>,
+
+    /// etcd endpoints, comma separated, e.g. `https://etcd-0:2379,https://etcd-1:2379`. Same as
+    /// `ETCD_ENDPOINTS`.
+    #[arg(long)]
+    pub etcd_endpoints: Option<String>,
+
+    /// Username to authenticate to etcd with, requires a password. Same as `ETCD_AUTH_USERNAME`.
+    #[arg(long)]
+    pub etcd_username: Option<String>,
+
+    /// File with the password for `--etcd-username`. Same as `ETCD_AUTH_PASSWORD_FILE`.
+    #[arg(long)]
+    pub etcd_password_file: Option<PathBuf>,
+
+    /// PEM CA certificate to verify the etcd servers with. Same as `ETCD_TLS_CA_FILE`.
+    #[arg(long)]
+    pub etcd_tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate for etcd servers that verify clients, requires `--etcd-tls-key`.
+    /// Same as `ETCD_TLS_CERT_FILE`.
+    #[arg(long, requires = "etcd_tls_key")]
+    pub etcd_tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--etcd-tls-cert`. Same as `ETCD_TLS_KEY_FILE`.
+    #[arg(long, requires = "etcd_tls_cert")]
+    pub etcd_tls_key: Option<PathBuf>,
+
+    /// How long connecting to an etcd endpoint may take. Same as `ETCD_DIAL_TIMEOUT_SECS`.
+    #[arg(long)]
+    pub etcd_dial_timeout_secs: Option<u64>,
+
     /// KV Router: Weight for overlap score in worker selection.
     /// Higher values prioritize KV cache reuse. Default: 2.0
     #[arg(long)]
@@ -121,6 +543,332 @@ pub struct Flags {
     #[arg(long)]
     pub kv_waiting_requests_weight: Option<f64>,
 
+    /// KV Router: How to score workers. `default` weighs prompt overlap, GPU cache usage and
+    /// waiting requests. `balanced` weighs prompt overlap, waiting requests, KV blocks in use
+    /// and decode throughput, and takes new weights from etcd while running.
+    #[arg(long, default_value = "default")]
+    pub kv_selector: KvSelector,
+
+    /// KV Router: Weight for the share of KV blocks in use, with `--kv-selector balanced`.
+    /// Higher values spread the load over the workers. Default: 1.0
+    #[arg(long)]
+    pub kv_active_blocks_weight: Option<f64>,
+
+    /// KV Router: Weight for decode throughput, with `--kv-selector balanced`.
+    /// Higher values prefer workers that generate faster. Default: 0.5
+    #[arg(long)]
+    pub kv_decode_throughput_weight: Option<f64>,
+
+    /// KV Router: Above 0, pick workers at random with probability proportional to
+    /// exp(score / temperature) instead of always the best one, so that several routers spread
+    /// their requests over workers that score about the same. Higher values spread more.
+    #[arg(long, default_value = "0.0")]
+    pub kv_temperature: f64,
+
+    /// KV Router: Weight for not having the request's LoRA adapter loaded in worker selection.
+    /// Higher values keep the requests of an adapter on the workers that have it. Default: 1.0
+    #[arg(long)]
+    pub kv_lora_miss_weight: Option<f64>,
+
+    /// KV Router: Most KV blocks to index, counted once for each worker that has them. The least
+    /// recently used are evicted beyond it. Default: unbounded
+    #[arg(long)]
+    pub kv_index_max_entries: Option<usize>,
+
+    /// KV Router: Most memory in MiB the index of KV blocks may take, estimated. Evicts like
+    /// `--kv-index-max-entries`. Default: unbounded
+    #[arg(long)]
+    pub kv_index_max_mib: Option<usize>,
+
+    /// KV Router: Forget KV blocks that no worker stored and no request matched for this many
+    /// seconds. Default: never
+    #[arg(long)]
+    pub kv_index_block_ttl_secs: Option<u64>,
+
+    /// KV Router: Split the index of KV blocks into this many shards, each applying the events of
+    /// its workers on its own thread. Raise it when the router can't keep up with the KV events
+    /// of hundreds of workers.
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub kv_index_shards: u32,
+
+    /// KV Router: Save the index of KV blocks to this directory, or `nats://<bucket>` in the NATS
+    /// object store, and restore it at start, so that a restarted router doesn't start cold.
+    #[arg(long)]
+    pub kv_index_snapshot: Option<SnapshotStore>,
+
+    /// KV Router: How often `--kv-index-snapshot` saves the index.
+    #[arg(long, default_value = "60")]
+    pub kv_index_snapshot_interval_secs: u64,
+
+    /// KV Router: At start, ask a running router for its index of KV blocks. Waits up to 10s
+    /// for one to answer.
+    #[arg(long)]
+    pub kv_index_bootstrap: bool,
+
+    /// KV Router: Record the KV events, worker load and routing decisions to this JSONL file.
+    /// Replay it with `router --replay <file>` to compare worker selectors offline.
+    #[arg(long)]
+    pub kv_record: Option<PathBuf>,
+
+    /// KV Router: Failures in a row after which a worker gets no requests, until a probe request
+    /// succeeds. A failure is a request that couldn't be sent, or whose response had an error or
+    /// timed out. 0 disables.
+    #[arg(long, default_value = "5")]
+    pub kv_breaker_failures: u32,
+
+    /// KV Router: How many seconds a failing worker gets no requests before a probe.
+    #[arg(long, default_value = "30")]
+    pub kv_breaker_open_secs: u64,
+
+    /// KV Router: A first response slower than this many seconds counts as a failure of the
+    /// worker. The request isn't cancelled. Default: not a failure
+    #[arg(long)]
+    pub kv_breaker_first_response_timeout_secs: Option<u64>,
+
+    /// KV Router: How many seconds a session, named with the `x-dynamo-session-id` header or
+    /// `nvext.routing.session_id`, keeps its worker after its last request.
+    #[arg(long, default_value = "600")]
+    pub kv_session_ttl_secs: u64,
+
+    /// KV Router: Most sessions that keep their worker, the least recently used are forgotten
+    /// beyond it. 0 disables session affinity.
+    #[arg(long, default_value = "100000")]
+    pub kv_max_sessions: usize,
+
+    /// `out=dyn` only.
+    ///
+    /// If a worker sends nothing within this many milliseconds, cancel the request there and send
+    /// it to another worker. Protects against stuck engines. Disabled if not set.
+    #[arg(long)]
+    pub first_token_timeout_ms: Option<u64>,
+
+    /// How many workers to try when `--first-token-timeout-ms` is set, including the first one.
+    #[arg(long, default_value = "3")]
+    pub first_token_max_attempts: u32,
+
+    /// `in=dyn out=vllm` only.
+    ///
+    /// Disaggregated serving: `prefill` workers prefill the long prompts of the `decode` workers,
+    /// which fetch the KV cache with vLLM's NixlConnector. Prefill and decode workers of a model
+    /// must be on different components, e.g. `dyn://dynamo.prefill.generate` and
+    /// `dyn://dynamo.backend.generate`.
+    #[arg(long, default_value = "aggregated")]
+    pub disagg_role: DisaggRole,
+
+    /// `out=dyn` only.
+    ///
+    /// Prompts longer than this many tokens are prefilled on a prefill worker when the model has
+    /// some. Can be changed per model at runtime in etcd.
+    #[arg(long, default_value = "1000")]
+    pub max_local_prefill_length: i32,
+
+    /// `out=dyn` only.
+    ///
+    /// Most remote prefills in progress at once per model, the prompts beyond are prefilled by
+    /// the decode workers.
+    #[arg(long, default_value = "64")]
+    pub max_inflight_prefills: usize,
+
+    /// `out=dyn` only.
+    ///
+    /// Most requests waiting per model for a remote prefill when `--max-inflight-prefills` are in
+    /// progress.
+    #[arg(long, default_value = "256")]
+    pub max_waiting_prefills: usize,
+
+    /// `out=dyn` only.
+    ///
+    /// How many milliseconds a request waits for a remote prefill before it is prefilled by its
+    /// decode worker.
+    #[arg(long, default_value = "1000")]
+    pub max_prefill_wait_ms: u64,
+
+    /// `in=dyn` only, with an engine running in dynamo-run, e.g. `out=llamacpp`.
+    ///
+    /// Speculative decoding: run the small model at this path as the draft model of the model
+    /// path's model, guessing its next tokens. The draft worker registers the model path's model,
+    /// on its own component, e.g. `dyn://dynamo.draft.generate`.
+    #[arg(long)]
+    pub draft_model_path: Option<PathBuf>,
+
+    /// `out=dyn` only.
+    ///
+    /// How many tokens the draft workers of a model guess at once, for its workers to check in
+    /// one forward pass. 0 turns speculative decoding off.
+    #[arg(long, default_value = "4")]
+    pub num_speculative_tokens: u32,
+
+    /// Embedding models only, `out=dyn`.
+    ///
+    /// Coalesce concurrent embedding requests into batches of up to this many inputs before
+    /// sending them to a worker. Batching is disabled if not set.
+    #[arg(long)]
+    pub embedding_batch_max_size: Option<usize>,
+
+    /// Embedding models only, `out=dyn`.
+    ///
+    /// How long in milliseconds a request may wait for others to join its batch. Default 5.
+    #[arg(long, default_value = "5")]
+    pub embedding_batch_max_latency_ms: u64,
+
+    /// Hard limit on `max_tokens` per request. Requests asking for more get a 400.
+    /// On a worker this is published in the model deployment card. With `in=http out=dyn` it
+    /// overrides the limit in the card of every model the ingress discovers.
+    #[arg(long)]
+    pub max_tokens_limit: Option<u32>,
+
+    /// `max_tokens` to use when a request doesn't set one. Published / overridden like
+    /// `--max-tokens-limit`.
+    #[arg(long)]
+    pub default_max_tokens: Option<u32>,
+
+    /// Hard limit in seconds on how long a single request may generate for. The response is
+    /// stopped when it runs out. Published / overridden like `--max-tokens-limit`.
+    #[arg(long)]
+    pub max_generation_secs: Option<u64>,
+
+    /// `true` starts every prompt with the model's BOS token, `false` removes it if the chat
+    /// template added it. Published / overridden like `--max-tokens-limit`, as are the other
+    /// special token flags.
+    #[arg(long)]
+    pub add_bos: Option<bool>,
+
+    /// Whether chat prompts end with the start of an assistant message. By default they do
+    /// unless the last message is from the assistant.
+    #[arg(long)]
+    pub add_generation_prompt: Option<bool>,
+
+    /// Comma separated token ids to use instead of the EOS tokens in the model's config
+    #[arg(long, value_delimiter = ',')]
+    pub eos_token_ids: Option<Vec<u32>>,
+
+    /// Comma separated token ids that also end generation, hidden from the output like EOS
+    #[arg(long, value_delimiter = ',')]
+    pub stop_token_ids: Vec<u32>,
+
+    /// JSON file of named sampling parameters that requests pick with `nvext.sampling_preset`,
+    /// e.g. `{"support": {"temperature": 0.3, "top_p": 0.9}}`. Adds to, or replaces, the built in
+    /// `creative`, `precise` and `deterministic`. Published / overridden like
+    /// `--max-tokens-limit`.
+    #[arg(long)]
+    pub sampling_presets: Option<PathBuf>,
+
+    /// Shorten prompts at the ingress before tokenizing them, comma separated: `whitespace`
+    /// collapses runs of spaces and blank lines outside code blocks, `dedup` drops paragraphs
+    /// that appeared earlier in the request. Published / overridden like `--max-tokens-limit`.
+    #[arg(long, value_delimiter = ',')]
+    pub prompt_compression: Vec<PromptCompressionStrategy>,
+
+    /// Send the text of all messages but the last to this LLMLingua-style compressor before
+    /// tokenizing. It gets `{"model", "texts", "rate"}` and answers `{"texts"}`.
+    #[arg(long)]
+    pub prompt_compressor_url: Option<Url>,
+
+    /// The share of tokens `--prompt-compressor-url` should keep, from 0 to 1
+    #[arg(long)]
+    pub prompt_compressor_rate: Option<f32>,
+
+    /// in=dyn only
+    ///
+    /// What to do when the component already serves another model: fail, take it over if the
+    /// instances of the other model are gone, or serve on `<component>-v2`,
+    /// `-v3`, ... next to it.
+    #[arg(long, default_value = "fail")]
+    pub duplicate_registration: DuplicateRegistrationPolicy,
+
+    /// in=dyn only
+    ///
+    /// Most requests the worker serves at once. Routers hold back the requests beyond it
+    /// instead of queueing them on the worker. Unlimited if not set.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_concurrent_requests: Option<u32>,
+
+    /// in=dyn only
+    ///
+    /// Also take requests from the work queue of the endpoint, at most this many at once, for
+    /// the routers with `--router-mode pull`. Suits offline fleets of unlike workers, each takes
+    /// as much work as it has room for.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub pull_capacity: Option<u32>,
+
+    /// in=dyn only
+    ///
+    /// What an hour of a GPU of this worker costs, e.g. `0.8` on spot capacity. Published with
+    /// our endpoints for the routers with `--router-mode budget`.
+    #[arg(long, value_parser = parse_cost_per_gpu_hour)]
+    pub cost_per_gpu_hour: Option<f64>,
+
+    /// in=dyn only
+    ///
+    /// How many recent requests the worker remembers. They are written to `--request-log-dir`
+    /// if the worker panics or is stopped, to help debug engine crashes. 0 disables.
+    #[arg(long, default_value = "256")]
+    pub request_log_size: usize,
+
+    /// Where to write the recent requests. Defaults to the system temp directory.
+    #[arg(long)]
+    pub request_log_dir: Option<PathBuf>,
+
+    /// What to keep of each prompt in the request log.
+    #[arg(long, default_value = "hash")]
+    pub request_log_redaction: RequestLogRedaction,
+
+    /// Audit full requests and responses, as JSON lines in rotating files in this directory.
+    /// `in=http` and `in=dyn://` only.
+    #[arg(long)]
+    pub audit_log_dir: Option<PathBuf>,
+
+    /// Start a new audit log file when the current one reaches this size.
+    #[arg(long, default_value = "100")]
+    pub audit_log_max_mib: u64,
+
+    /// Delete the oldest audit log files beyond this many.
+    #[arg(long, default_value = "10")]
+    pub audit_log_max_files: usize,
+
+    /// Publish the audit records as JSON on this NATS subject. `in=http` and `in=dyn://` only.
+    #[arg(long)]
+    pub audit_nats_subject: Option<String>,
+
+    /// Share of the requests to audit, from 0 to 1.
+    #[arg(long, default_value = "1.0")]
+    pub audit_sample_rate: f64,
+
+    /// Fields to redact from audit records, as comma separated dotted paths where `*` matches
+    /// any key or array element, e.g. `request.messages.*.content,principal`.
+    #[arg(long)]
+    pub audit_redact: Option<Redaction>,
+
+    /// Write the generated text of each request to this path, with `{request_id}` and `{model}`
+    /// replaced, e.g. `/data/out/{model}/{request_id}.txt`. An `http://` or `https://` URL gets
+    /// each complete response with a PUT. `in=batch:` and `in=dyn://` only.
+    #[arg(long)]
+    pub tee_responses: Option<String>,
+
+    /// Where to write the manifest of the teed responses, JSON lines. Defaults to
+    /// `manifest.jsonl` in the directory of a local `--tee-responses`.
+    #[arg(long, requires = "tee_responses")]
+    pub tee_manifest: Option<PathBuf>,
+
+    /// File with a bearer token to upload the responses of an `http(s)://` `--tee-responses`
+    /// with.
+    #[arg(long, requires = "tee_responses")]
+    pub tee_upload_token_file: Option<PathBuf>,
+
+    /// in=dyn only
+    ///
+    /// Let the admin API of an ingress load and unload models on this worker. Only engines that
+    /// run in the dynamo-run process can load models.
+    #[arg(long)]
+    pub enable_model_control: bool,
+
+    /// in=dyn only
+    ///
+    /// Load the model and serve, but stay out of the routers until activated through the admin
+    /// API. Same as DYN_WORKER_STANDBY=true.
+    #[arg(long)]
+    pub standby: bool,
+
     /// Max model context length. Reduce this if you don't have enough VRAM for the full model
     /// context length (e.g. Llama 4).
     /// Defaults to the model's max, which is usually model_max_length in tokenizer_config.json.
@@ -128,14 +876,55 @@ pub struct Flags {
     pub context_length: Option<usize>,
 
     /// KV cache block size (vllm only)
+    /// Defaults to the engine's own default.
     #[arg(long)]
     pub kv_cache_block_size: Option<usize>,
 
+    /// sglang, vllm, trtllm
+    ///
+    /// Max number of tokens the engine processes in one batch (prefill chunk size).
+    /// Defaults to a value based on the memory of the smallest visible GPU.
+    #[arg(long)]
+    pub max_num_batched_tokens: Option<usize>,
+
     /// Additional engine-specific arguments from a JSON file.
     /// Contains a mapping of parameter names to values.
     #[arg(long)]
     pub extra_engine_args: Option<PathBuf>,
 
+    /// Pass `--extra-engine-args` to the engine without checking the names and types of the
+    /// arguments first. For arguments of engine versions dynamo-run doesn't know yet.
+    #[arg(long)]
+    pub skip_engine_args_validation: bool,
+
+    /// vllm, sglang
+    ///
+    /// Serve LoRA adapters on top of the model. The admin API of an ingress loads and unloads
+    /// them on this worker, and requests for an adapter's name use it.
+    #[arg(long)]
+    pub enable_lora: bool,
+
+    /// vllm, sglang. Needs `--enable-lora`.
+    ///
+    /// Max number of LoRA adapters in one batch. Defaults to the engine's own default.
+    #[arg(long, requires = "enable_lora")]
+    pub max_loras: Option<u32>,
+
+    /// vllm, sglang. Needs `--enable-lora`.
+    ///
+    /// Max rank of the LoRA adapters. Defaults to the engine's own default.
+    #[arg(long, requires = "enable_lora")]
+    pub max_lora_rank: Option<u32>,
+
+    /// in=http and in=grpc only
+    ///
+    /// Serve several models from this process, each with its own engine, e.g. a GGUF with
+    /// llamacpp next to a safetensors model with vllm. Path to a JSON manifest listing the
+    /// models, replaces `out=` and the model path:
+    /// {"models": [{"path": "/data/model.gguf", "engine": "llamacpp"}, {"path": "Qwen/Qwen3-8B", "engine": "vllm"}]}
+    #[arg(long)]
+    pub models: Option<PathBuf>,
+
     /// Path to a JSON file containing default request fields.
     /// These fields will be merged with each request, but can be overridden by the request.
     /// Example file contents:
@@ -147,13 +936,92 @@ pub struct Flags {
     #[arg(long)]
     pub request_template: Option<PathBuf>,
 
+    /// in=text only
+    ///
+    /// Print the answers as the model generates them, instead of rendering their markdown with
+    /// colors and syntax highlighting. Output that isn't a terminal is never rendered.
+    #[arg(long)]
+    pub text_raw: bool,
+
+    /// in=text only
+    ///
+    /// Don't print the time to first token and the tokens per second after each answer.
+    #[arg(long)]
+    pub text_no_stats: bool,
+
+    /// in=batch only
+    ///
+    /// The dataset columns holding each part of a request, as `field=column` pairs, e.g.
+    /// `prompt=question,system=instruction,temperature=temp`. The fields are `prompt`, `system`,
+    /// `temperature`, `top_p` and `max_tokens`. The prompt defaults to the `text` column.
+    #[arg(long)]
+    pub batch_columns: Option<ColumnMapping>,
+
+    /// in=kafka only
+    ///
+    /// Consumer group of the requests topic. The processes of a group share its partitions.
+    #[arg(long, default_value = "dynamo")]
+    pub kafka_group_id: String,
+
+    /// in=kafka only
+    ///
+    /// Topic of the responses to requests without a `reply_topic` header. Such requests are
+    /// skipped if not set.
+    #[arg(long)]
+    pub kafka_reply_topic: Option<String>,
+
+    /// in=kafka only
+    ///
+    /// Most requests to run at once.
+    #[arg(long, default_value = "64", value_parser = clap::value_parser!(u32).range(1..))]
+    pub kafka_max_in_flight: u32,
+
+    /// in=kafka only
+    ///
+    /// A librdkafka client setting, as `key=value`, e.g. `security.protocol=SASL_SSL`. Repeat
+    /// for each setting.
+    #[arg(long)]
+    pub kafka_option: Vec<String>,
+
     /// Everything after a `--`.
     /// These are the command line arguments to the python engine when using `pystr` or `pytok`.
     #[arg(index = 2, last = true, hide = true, allow_hyphen_values = true)]
     pub last: Vec<String>,
+
+    /// The ids of the flags that were set rather than defaulted, e.g. `base_gpu_id`. Filled by
+    /// [`Flags::try_parse_explicit`].
+    #[arg(skip)]
+    pub explicit: HashSet<String>,
 }
 
 impl Flags {
+    /// Parse like [`clap::Parser::try_parse_from`], and remember which flags the command line or
+    /// the environment gave, for [`Flags::is_explicit`].
+    pub fn try_parse_explicit<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(args)?;
+        let mut flags = Self::from_arg_matches(&matches)?;
+        flags.explicit = matches
+            .ids()
+            .filter(|id| {
+                matches!(
+                    matches.value_source(id.as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+            .map(|id| id.to_string())
+            .collect();
+        Ok(flags)
+    }
+
+    /// Whether the flag with this id was set rather than defaulted
+    pub fn is_explicit(&self, id: &str) -> bool {
+        self.explicit.contains(id)
+    }
+
     /// Get KV router configuration
     pub fn kv_router_config(&self) -> KvRouterConfig {
         KvRouterConfig::new(
@@ -161,6 +1029,398 @@ impl Flags {
             self.kv_gpu_cache_usage_weight,
             self.kv_waiting_requests_weight,
         )
+        .with_indexer_limits(IndexerLimits {
+            max_entries: self.kv_index_max_entries,
+            max_memory_bytes: self.kv_index_max_mib.map(|mib| mib * 1024 * 1024),
+            block_ttl: self.kv_index_block_ttl_secs.map(Duration::from_secs),
+        })
+        .with_index_shards(self.kv_index_shards as usize)
+        .with_index_snapshot(IndexSnapshotConfig {
+            store: self.kv_index_snapshot.clone(),
+            interval: Duration::from_secs(self.kv_index_snapshot_interval_secs.max(1)),
+            bootstrap: self.kv_index_bootstrap,
+        })
+        .with_selector(
+            self.kv_selector.into(),
+            self.kv_active_blocks_weight,
+            self.kv_decode_throughput_weight,
+        )
+        .with_lora_miss_weight(self.kv_lora_miss_weight)
+        .with_temperature(self.kv_temperature.max(0.0))
+        .with_record_path(self.kv_record.clone())
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: self.kv_breaker_failures,
+            open_duration: Duration::from_secs(self.kv_breaker_open_secs.max(1)),
+            first_response_timeout: self
+                .kv_breaker_first_response_timeout_secs
+                .map(Duration::from_secs),
+        })
+        .with_session_affinity(SessionAffinityConfig {
+            ttl: Duration::from_secs(self.kv_session_ttl_secs),
+            max_sessions: self.kv_max_sessions,
+        })
+    }
+
+    /// Recording of the metrics, if enabled
+    pub fn metrics_recorder(&self) -> Option<MetricsRecorderConfig> {
+        let path = self.metrics_record_db.clone()?;
+        Some(MetricsRecorderConfig {
+            path,
+            interval: Duration::from_secs(self.metrics_record_interval_secs.max(1)),
+            max_age: self
+                .metrics_record_retention_hours
+                .map(|hours| Duration::from_secs(hours * 3600)),
+            max_rows: self.metrics_record_max_rows,
+        })
+    }
+
+    /// Where deferrable requests wait for workers, if enabled
+    pub fn spillover(&self) -> Option<SpilloverConfig> {
+        let dir = self.http_spillover_dir.clone()?;
+        Some(SpilloverConfig {
+            dir,
+            max_requests: self.http_spillover_max_requests,
+            max_dispatching: self.http_spillover_max_dispatching.max(1),
+            max_wait: Duration::from_secs(self.http_spillover_max_wait_secs),
+            result_ttl: Duration::from_secs(self.http_spillover_result_ttl_secs),
+        })
+    }
+
+    /// Get embedding batching configuration, if enabled
+    pub fn embedding_batch_config(&self) -> Option<EmbeddingBatchConfig> {
+        self.embedding_batch_max_size
+            .map(|max_batch_size| EmbeddingBatchConfig {
+                max_batch_size,
+                max_latency: Duration::from_millis(self.embedding_batch_max_latency_ms),
+            })
+    }
+
+    /// First token deadline rescheduling, if enabled
+    pub fn reschedule_config(&self) -> Option<RescheduleConfig> {
+        self.first_token_timeout_ms
+            .map(|timeout_ms| RescheduleConfig {
+                first_token_timeout: Duration::from_millis(timeout_ms),
+                max_attempts: self.first_token_max_attempts,
+            })
+    }
+
+    /// When to prefill on the prefill workers of a model
+    pub fn disagg_config(&self) -> DisaggConfig {
+        DisaggConfig {
+            max_local_prefill_length: self.max_local_prefill_length,
+            queue: PrefillQueueConfig {
+                max_inflight: self.max_inflight_prefills,
+                max_waiting: self.max_waiting_prefills,
+                max_wait: Duration::from_millis(self.max_prefill_wait_ms),
+            },
+        }
+    }
+
+    /// How the models with draft workers generate
+    pub fn speculative_config(&self) -> SpeculativeConfig {
+        SpeculativeConfig {
+            num_speculative_tokens: self.num_speculative_tokens,
+        }
+    }
+
+    /// Per-request output limits, enforced at the ingress
+    pub fn generation_limits(&self) -> GenerationLimits {
+        GenerationLimits {
+            max_tokens: self.max_tokens_limit,
+            default_max_tokens: self.default_max_tokens,
+            max_generation_secs: self.max_generation_secs,
+        }
+    }
+
+    /// BOS, generation prompt and stop token handling, applied at the ingress
+    pub fn special_tokens(&self) -> SpecialTokens {
+        SpecialTokens {
+            add_bos: self.add_bos,
+            add_generation_prompt: self.add_generation_prompt,
+            eos_token_ids: self.eos_token_ids.clone(),
+            stop_token_ids: self.stop_token_ids.clone(),
+        }
+    }
+
+    /// Named sampling parameters, from `--sampling-presets`
+    pub fn sampling_presets(&self) -> anyhow::Result<SamplingPresets> {
+        match &self.sampling_presets {
+            Some(path) => SamplingPresets::from_json_file(path),
+            None => Ok(SamplingPresets::default()),
+        }
+    }
+
+    /// How prompts are shortened, from `--prompt-compression` and `--prompt-compressor-url`
+    pub fn prompt_compression(&self) -> PromptCompression {
+        let enabled = |strategy| self.prompt_compression.contains(&strategy).then_some(true);
+        PromptCompression {
+            collapse_whitespace: enabled(PromptCompressionStrategy::Whitespace),
+            dedup_chunks: enabled(PromptCompressionStrategy::Dedup),
+            compressor_url: self.prompt_compressor_url.clone(),
+            compressor_rate: self.prompt_compressor_rate,
+        }
+    }
+
+    /// How the HTTP service validates and filters `nvext`
+    pub fn nvext_policy(&self) -> NvExtPolicy {
+        let policy = self
+            .nvext_allowed_keys
+            .iter()
+            .fold(NvExtPolicy::new(self.nvext_unknown_keys.into()), |p, k| {
+                p.allow(k)
+            });
+        let policy = self
+            .nvext_pinning_principals
+            .iter()
+            .fold(policy, |p, principal| p.allow_pinning(principal));
+        self.nvext_max_priority
+            .iter()
+            .fold(policy, |p, (principal, max)| {
+                p.allow_priority(principal, *max)
+            })
+    }
+
+    /// Pass the locality flags on to the runtime, which reads them from the environment.
+    /// Must be called before the runtime's threads start, changing the environment isn't safe
+    /// while other threads may read it.
+    pub fn export_locality(&self) {
+        for (name, value) in [
+            ("DYN_LOCALITY_REGION", &self.region),
+            ("DYN_LOCALITY_ZONE", &self.zone),
+            ("DYN_LOCALITY_POLICY", &self.zone_policy),
+        ] {
+            if let Some(value) = value {
+                std::env::set_var(name, value);
+            }
+        }
+    }
+
+    /// Pass the NATS flags on to the runtime, which reads them from the environment. An
+    /// authentication flag replaces any NATS authentication set in the environment.
+    /// Must be called before the runtime's threads start, changing the environment isn't safe
+    /// while other threads may read it.
+    pub fn export_nats(&self) {
+        let auth = [
+            ("NATS_AUTH_CREDENTIALS_FILE", &self.nats_creds_file),
+            ("NATS_AUTH_NKEY_FILE", &self.nats_nkey_file),
+        ];
+        if auth.iter().any(|(_, value)| value.is_some()) {
+            for name in [
+                "NATS_AUTH_USERNAME",
+                "NATS_AUTH_PASSWORD",
+                "NATS_AUTH_TOKEN",
+                "NATS_AUTH_NKEY",
+                "NATS_AUTH_NKEY_FILE",
+                "NATS_AUTH_CREDENTIALS_FILE",
+            ] {
+                std::env::remove_var(name);
+            }
+        }
+        for (name, value) in auth.into_iter().chain([
+            ("NATS_TLS_CA_FILE", &self.nats_tls_ca),
+            ("NATS_TLS_CERT_FILE", &self.nats_tls_cert),
+            ("NATS_TLS_KEY_FILE", &self.nats_tls_key),
+            ("DYN_PAYLOAD_ENCRYPTION_KEYS_FILE", &self.payload_keys_file),
+            ("DYN_CONTROL_SIGNING_KEY_FILE", &self.control_key_file),
+        ]) {
+            if let Some(value) = value {
+                std::env::set_var(name, value);
+            }
+        }
+        if let Some(server) = &self.nats_server {
+            std::env::set_var("NATS_SERVER", server);
+        }
+        if self.nats_tls_required {
+            std::env::set_var("NATS_TLS_REQUIRED", "true");
+        }
+        if let Some(max_reconnects) = self.nats_max_reconnects {
+            std::env::set_var("NATS_MAX_RECONNECTS", max_reconnects.to_string());
+        }
+    }
+
+    /// Pass the etcd flags on to the runtime, which reads them from the environment.
+    /// Must be called before the runtime's threads start, changing the environment isn't safe
+    /// while other threads may read it.
+    pub fn export_etcd(&self) {
+        if self.etcd_password_file.is_some() {
+            // It would take precedence over the file
+            std::env::remove_var("ETCD_AUTH_PASSWORD");
+        }
+        for (name, value) in [
+            ("ETCD_AUTH_PASSWORD_FILE", &self.etcd_password_file),
+            ("ETCD_TLS_CA_FILE", &self.etcd_tls_ca),
+            ("ETCD_TLS_CERT_FILE", &self.etcd_tls_cert),
+            ("ETCD_TLS_KEY_FILE", &self.etcd_tls_key),
+        ] {
+            if let Some(value) = value {
+                std::env::set_var(name, value);
+            }
+        }
+        if let Some(endpoints) = &self.etcd_endpoints {
+            std::env::set_var("ETCD_ENDPOINTS", endpoints);
+        }
+        if let Some(username) = &self.etcd_username {
+            std::env::set_var("ETCD_AUTH_USERNAME", username);
+        }
+        if let Some(secs) = self.etcd_dial_timeout_secs {
+            std::env::set_var("ETCD_DIAL_TIMEOUT_SECS", secs.to_string());
+        }
+    }
+
+    /// Pass the drain timeout on to the runtime, which reads it from the environment. The
+    /// profile's only applies if DYN_WORKER_DRAIN_TIMEOUT isn't set, `--drain-timeout-secs`
+    /// always does. Must be called before the runtime's threads start, like [`Self::export_nats`].
+    pub fn export_timeouts(&self) {
+        const DRAIN_TIMEOUT_VAR: &str = "DYN_WORKER_DRAIN_TIMEOUT";
+        if self.drain_timeout_secs.is_none() && std::env::var_os(DRAIN_TIMEOUT_VAR).is_some() {
+            return;
+        }
+        std::env::set_var(
+            DRAIN_TIMEOUT_VAR,
+            self.timeouts().drain.as_secs().to_string(),
+        );
+    }
+
+    /// Cross-origin access to the HTTP service, if any origins are allowed
+    pub fn cors(&self) -> anyhow::Result<Option<CorsConfig>> {
+        if self.http_cors_allowed_origins.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = CorsConfig::builder()
+            .allowed_origins(AllowedOrigins::from_origins(
+                &self.http_cors_allowed_origins,
+            ))
+            .allowed_headers(self.http_cors_allowed_headers.clone())
+            .exposed_headers(self.http_cors_exposed_headers.clone())
+            .allow_credentials(self.http_cors_allow_credentials);
+        if !self.http_cors_allowed_methods.is_empty() {
+            builder = builder
+                .allowed_methods(CorsConfig::parse_methods(&self.http_cors_allowed_methods)?);
+        }
+        Ok(Some(builder.build()?))
+    }
+
+    /// Rate and concurrency limits for the HTTP service, if any are set
+    pub fn rate_limits(&self) -> anyhow::Result<Option<RateLimitConfig>> {
+        let mut config = match self.http_rate_limit_config.as_ref() {
+            Some(path) => RateLimitConfig::from_file(path)?,
+            None => RateLimitConfig::default(),
+        };
+        if self.http_rate_limit_rpm.is_some() {
+            config.global.requests_per_minute = self.http_rate_limit_rpm;
+        }
+        if self.http_max_concurrent_requests.is_some() {
+            config.global.max_concurrent = self.http_max_concurrent_requests;
+        }
+        Ok((!config.is_unlimited()).then_some(config))
+    }
+
+    /// Caps on how fast the HTTP service sends streamed responses
+    pub fn output_rate(&self) -> anyhow::Result<OutputRateConfig> {
+        let mut config = match self.http_output_rate_config.as_ref() {
+            Some(path) => OutputRateConfig::from_file(path)?,
+            None => OutputRateConfig::default(),
+        };
+        if self.http_max_tokens_per_sec.is_some() {
+            config.default.max_tokens_per_sec = self.http_max_tokens_per_sec;
+        }
+        config
+            .validate()
+            .map_err(|err| anyhow::anyhow!("--http-max-tokens-per-sec: {err}"))?;
+        Ok(config)
+    }
+
+    /// Admission control for the HTTP service, if it is enabled
+    pub fn admission(&self) -> anyhow::Result<Option<AdmissionConfig>> {
+        let mut config = match self.http_admission_config.as_ref() {
+            Some(path) => AdmissionConfig::from_file(path)?,
+            None => AdmissionConfig::default(),
+        };
+        if let Some(max_concurrent) = self.http_admission_max_concurrent {
+            config.global = Some(AdmissionLimit {
+                max_concurrent,
+                max_queue: self.http_admission_max_queue,
+            });
+        } else if let Some(global) = config.global.as_mut() {
+            if self.http_admission_max_queue > 0 {
+                global.max_queue = self.http_admission_max_queue;
+            }
+        }
+        if let Some(secs) = self.http_admission_queue_timeout_secs {
+            config.max_queue_wait_ms = Some(secs * 1000);
+        }
+        Ok((!config.is_unlimited()).then_some(config))
+    }
+
+    /// The timeouts of `--timeout-profile`, with the ones that have their own flag set replaced
+    pub fn timeouts(&self) -> Timeouts {
+        let profile = self.timeout_profile.timeouts();
+        let secs = |flag: Option<u64>| flag.map(Duration::from_secs);
+        Timeouts {
+            connect: secs(self.http_connect_timeout_secs).or(profile.connect),
+            request: secs(self.http_request_timeout_secs).or(profile.request),
+            stream_idle: secs(self.http_stream_idle_timeout_secs).or(profile.stream_idle),
+            drain: secs(self.drain_timeout_secs).unwrap_or(profile.drain),
+            subprocess_stop: secs(self.subprocess_stop_timeout_secs)
+                .unwrap_or(profile.subprocess_stop),
+        }
+    }
+
+    /// Timeouts of the HTTP service's generation requests, the same for every endpoint
+    pub fn http_timeouts(&self) -> TimeoutConfig {
+        let timeouts = self.timeouts();
+        TimeoutConfig::new(RequestTimeouts {
+            connect: timeouts.connect,
+            first_token: self.http_first_token_timeout_secs.map(Duration::from_secs),
+            total: timeouts.request,
+            stream_idle: timeouts.stream_idle,
+        })
+    }
+
+    /// Ring buffer of recent requests for post-mortems, if enabled
+    pub fn request_log(&self) -> Option<Arc<RequestLog>> {
+        (self.request_log_size > 0)
+            .then(|| RequestLog::new(self.request_log_size, self.request_log_redaction.into()))
+    }
+
+    /// Where to tee responses to, if enabled
+    pub fn response_tee(&self) -> anyhow::Result<Option<Arc<ResponseTee>>> {
+        let Some(pattern) = &self.tee_responses else {
+            return Ok(None);
+        };
+        let mut config = ResponseTeeConfig::new(pattern.clone());
+        if let Some(manifest) = &self.tee_manifest {
+            config.manifest = Some(manifest.clone());
+        }
+        if let Some(path) = &self.tee_upload_token_file {
+            let token = std::fs::read_to_string(path).map_err(|err| {
+                anyhow::anyhow!("Failed reading upload token from {}: {err}", path.display())
+            })?;
+            config.upload_token = Some(token.trim().to_string());
+        }
+        Ok(Some(ResponseTee::new(config)?))
+    }
+
+    /// The workers each model may be routed to, from `--worker-admission-policy`
+    pub fn worker_admission(&self) -> anyhow::Result<Option<Arc<WorkerAdmissionPolicy>>> {
+        self.worker_admission_policy
+            .as_deref()
+            .map(|path| WorkerAdmissionPolicy::from_file(path).map(Arc::new))
+            .transpose()
+    }
+
+    /// How each request class trades latency for cost, from `--budget-policy`
+    pub fn budget_policy(&self) -> anyhow::Result<Option<Arc<BudgetPolicy>>> {
+        self.budget_policy
+            .as_deref()
+            .map(|path| BudgetPolicy::from_file(path).map(Arc::new))
+            .transpose()
+    }
+
+    pub fn request_log_dir(&self) -> PathBuf {
+        self.request_log_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir)
     }
 
     /// Convert the flags back to a command line. Including only the non-null values, but
@@ -177,7 +1437,7 @@ impl Flags {
             self.http_port.to_string(),
             // Default 1
             "--tensor-parallel-size".to_string(),
-            self.tensor_parallel_size.to_string(),
+            self.tensor_parallel_size.unwrap_or(1).to_string(),
             // Default 0
             "--base-gpu-id".to_string(),
             self.base_gpu_id.to_string(),
@@ -196,6 +1456,10 @@ impl Flags {
             out.push("--leader-addr".to_string());
             out.push(leader.to_string());
         }
+        if let Some(max_num_batched_tokens) = self.max_num_batched_tokens {
+            out.push("--max-num-batched-tokens".to_string());
+            out.push(max_num_batched_tokens.to_string());
+        }
         if let Some(extra_engine_args) = self.extra_engine_args.as_ref() {
             out.push("--extra-engine-args".to_string());
             out.push(extra_engine_args.display().to_string());
@@ -237,6 +1501,10 @@ pub enum RouterMode {
     #[value(name = "round-robin")]
     RoundRobin,
     Random,
+    #[value(name = "least-loaded")]
+    LeastLoaded,
+    Pull,
+    Budget,
     #[value(name = "kv")]
     KV,
 }
@@ -246,7 +1514,130 @@ impl From<RouterMode> for RuntimeRouterMode {
         match r {
             RouterMode::RoundRobin => RuntimeRouterMode::RoundRobin,
             RouterMode::Random => RuntimeRouterMode::Random,
+            RouterMode::LeastLoaded => RuntimeRouterMode::LeastLoaded,
+            RouterMode::Pull => RuntimeRouterMode::Pull,
+            RouterMode::Budget => RuntimeRouterMode::Budget,
             RouterMode::KV => RuntimeRouterMode::KV,
         }
     }
 }
+
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum DisaggRole {
+    /// Prefill and decode on the same worker
+    #[default]
+    Aggregated,
+    /// Only prefill, for decode workers
+    Prefill,
+    /// Decode, and prefill the prompts the prefill workers don't take
+    Decode,
+}
+
+impl DisaggRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisaggRole::Aggregated => "aggregated",
+            DisaggRole::Prefill => "prefill",
+            DisaggRole::Decode => "decode",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum PromptCompressionStrategy {
+    Whitespace,
+    Dedup,
+}
+
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum KvSelector {
+    #[default]
+    Default,
+    Balanced,
+}
+
+impl From<KvSelector> for WorkerSelectorKind {
+    fn from(s: KvSelector) -> WorkerSelectorKind {
+        match s {
+            KvSelector::Default => WorkerSelectorKind::Default,
+            KvSelector::Balanced => WorkerSelectorKind::Balanced,
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum RequestLogRedaction {
+    /// Only the number of prompt tokens
+    Count,
+    /// A short hash of the prompt tokens
+    #[default]
+    Hash,
+    /// The first few prompt token ids
+    Tokens,
+}
+
+impl From<RequestLogRedaction> for RedactionPolicy {
+    fn from(r: RequestLogRedaction) -> RedactionPolicy {
+        match r {
+            RequestLogRedaction::Count => RedactionPolicy::CountOnly,
+            RequestLogRedaction::Hash => RedactionPolicy::Hash,
+            RequestLogRedaction::Tokens => RedactionPolicy::Tokens,
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum DuplicateRegistrationPolicy {
+    #[default]
+    Fail,
+    TakeOver,
+    Coexist,
+}
+
+impl From<DuplicateRegistrationPolicy> for DuplicateRegistration {
+    fn from(p: DuplicateRegistrationPolicy) -> DuplicateRegistration {
+        match p {
+            DuplicateRegistrationPolicy::Fail => DuplicateRegistration::Fail,
+            DuplicateRegistrationPolicy::TakeOver => DuplicateRegistration::TakeOver,
+            DuplicateRegistrationPolicy::Coexist => DuplicateRegistration::Coexist,
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum NvExtUnknownKeys {
+    #[default]
+    Drop,
+    Reject,
+    PassThrough,
+}
+
+impl From<NvExtUnknownKeys> for UnknownKeyPolicy {
+    fn from(u: NvExtUnknownKeys) -> UnknownKeyPolicy {
+        match u {
+            NvExtUnknownKeys::Drop => UnknownKeyPolicy::Drop,
+            NvExtUnknownKeys::Reject => UnknownKeyPolicy::Reject,
+            NvExtUnknownKeys::PassThrough => UnknownKeyPolicy::PassThrough,
+        }
+    }
+}
+
+/// `--nvext-max-priority`: `<principal>=<max>`, the maximum being a priority like in
+/// `x-dynamo-priority`
+fn parse_max_priority(value: &str) -> Result<(String, i32), String> {
+    let Some((principal, max)) = value.split_once('=') else {
+        return Err(format!("expected <principal>=<max>, got '{value}'"));
+    };
+    if principal.is_empty() {
+        return Err(format!("missing principal in '{value}'"));
+    }
+    Ok((principal.to_string(), nvext::parse_priority(max)?))
+}
+
+/// `--cost-per-gpu-hour`: a finite number, at least 0
+fn parse_cost_per_gpu_hour(value: &str) -> Result<f64, String> {
+    let cost = value
+        .parse::<f64>()
+        .map_err(|err| format!("invalid cost per GPU-hour '{value}': {err}"))?;
+    budget_router::check_cost(cost)
+}
@@ -0,0 +1,245 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Infer engine settings from the hardware when the user doesn't provide them.
+//!
+//! New users regularly get tensor parallel size, KV block size and batch size wrong. If the flag
+//! is omitted we pick a value based on the visible GPUs and the engine, log it, and record it in
+//! the model deployment card so the ingress (and operators) can see what the worker is using.
+
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use crate::{Flags, Output};
+
+/// What `nvidia-smi` tells us about the GPUs this process may use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuInfo {
+    /// Number of visible GPUs, after applying CUDA_VISIBLE_DEVICES
+    pub count: u32,
+
+    /// Memory of the smallest visible GPU, in MiB. Engines size per-GPU, so the smallest wins.
+    pub min_memory_mib: u64,
+}
+
+/// The NVIDIA driver, from the header of `nvidia-smi`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverInfo {
+    pub driver_version: String,
+
+    /// The newest CUDA version the driver supports
+    pub cuda_version: Option<String>,
+}
+
+/// Values we chose because the user did not set the flag
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InferredDefaults {
+    pub tensor_parallel_size: Option<u32>,
+    pub kv_cache_block_size: Option<usize>,
+    pub max_num_batched_tokens: Option<usize>,
+}
+
+impl InferredDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.tensor_parallel_size.is_none()
+            && self.kv_cache_block_size.is_none()
+            && self.max_num_batched_tokens.is_none()
+    }
+
+    /// Flat representation for the model deployment card
+    pub fn as_map(&self) -> BTreeMap<String, String> {
+        let mut out = BTreeMap::new();
+        if let Some(tp) = self.tensor_parallel_size {
+            out.insert("tensor_parallel_size".to_string(), tp.to_string());
+        }
+        if let Some(bs) = self.kv_cache_block_size {
+            out.insert("kv_cache_block_size".to_string(), bs.to_string());
+        }
+        if let Some(mbt) = self.max_num_batched_tokens {
+            out.insert("max_num_batched_tokens".to_string(), mbt.to_string());
+        }
+        out
+    }
+}
+
+/// Ask `nvidia-smi` for the GPU memory sizes. Returns None if there are no NVIDIA GPUs or the
+/// tool isn't installed.
+pub fn detect_gpus() -> Option<GpuInfo> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=memory.total", "--format=csv,noheader,nounits"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let visible = std::env::var("CUDA_VISIBLE_DEVICES").ok();
+    parse_nvidia_smi(&stdout, visible.as_deref())
+}
+
+/// Ask `nvidia-smi` for the driver and CUDA versions. Returns None if there are no NVIDIA GPUs or
+/// the tool isn't installed.
+pub fn detect_driver() -> Option<DriverInfo> {
+    let output = Command::new("nvidia-smi").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_nvidia_smi_header(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The header has a line `| NVIDIA-SMI 550.54.15   Driver Version: 550.54.15   CUDA Version: 12.4 |`
+fn parse_nvidia_smi_header(stdout: &str) -> Option<DriverInfo> {
+    let value = |label: &str| {
+        stdout
+            .split(label)
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(str::to_string)
+    };
+    Some(DriverInfo {
+        driver_version: value("Driver Version:")?,
+        cuda_version: value("CUDA Version:"),
+    })
+}
+
+fn parse_nvidia_smi(stdout: &str, cuda_visible_devices: Option<&str>) -> Option<GpuInfo> {
+    let all: Vec<u64> = stdout
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect();
+
+    // CUDA_VISIBLE_DEVICES can be indexes or UUIDs. We can only filter on indexes, for UUIDs we
+    // only trust the count.
+    let visible: Vec<u64> = match cuda_visible_devices {
+        None => all,
+        Some(s) if s.trim().is_empty() => vec![],
+        Some(s) => {
+            let ids: Vec<&str> = s.split(',').map(|id| id.trim()).collect();
+            let indexes: Option<Vec<usize>> = ids.iter().map(|id| id.parse().ok()).collect();
+            match indexes {
+                Some(indexes) => indexes
+                    .into_iter()
+                    .filter_map(|idx| all.get(idx).copied())
+                    .collect(),
+                None => {
+                    let min = all.iter().copied().min().unwrap_or(0);
+                    vec![min; ids.len()]
+                }
+            }
+        }
+    };
+
+    let min_memory_mib = visible.iter().copied().min()?;
+    Some(GpuInfo {
+        count: visible.len() as u32,
+        min_memory_mib,
+    })
+}
+
+/// Fill in the flags the user didn't set, based on the engine and the GPUs.
+/// Returns what we inferred so it can be logged and recorded.
+pub fn infer_defaults(
+    flags: &mut Flags,
+    out_opt: &Output,
+    gpus: Option<&GpuInfo>,
+) -> InferredDefaults {
+    let mut inferred = InferredDefaults::default();
+
+    if flags.tensor_parallel_size.is_none() {
+        // Only the Python engines shard across GPUs
+        let tp = match (out_opt, gpus) {
+            (Output::Vllm | Output::SgLang | Output::Trtllm, Some(gpus)) if gpus.count > 0 => {
+                // Must divide by the number of nodes, each node the same number of GPUs
+                gpus.count * flags.num_nodes
+            }
+            _ => 1,
+        };
+        flags.tensor_parallel_size = Some(tp);
+        inferred.tensor_parallel_size = Some(tp);
+    }
+
+    if flags.kv_cache_block_size.is_none() && !matches!(out_opt, Output::Dynamic) {
+        let block_size = default_kv_cache_block_size(out_opt);
+        flags.kv_cache_block_size = Some(block_size);
+        inferred.kv_cache_block_size = Some(block_size);
+    }
+
+    if flags.max_num_batched_tokens.is_none() {
+        if let (Output::Vllm | Output::SgLang | Output::Trtllm, Some(gpus)) = (out_opt, gpus) {
+            let mbt = max_num_batched_tokens_for(gpus.min_memory_mib);
+            flags.max_num_batched_tokens = Some(mbt);
+            inferred.max_num_batched_tokens = Some(mbt);
+        }
+    }
+
+    inferred
+}
+
+/// The block size each engine uses when not told otherwise. The KV router must use the same one.
+fn default_kv_cache_block_size(out_opt: &Output) -> usize {
+    match out_opt {
+        // TensorRT-LLM's default `tokens_per_block`
+        Output::Trtllm => 32,
+        _ => crate::DEFAULT_KV_CACHE_BLOCK_SIZE,
+    }
+}
+
+/// Larger prefill batches need more activation memory, scale with the smallest GPU
+fn max_num_batched_tokens_for(memory_mib: u64) -> usize {
+    const GIB: u64 = 1024;
+    match memory_mib {
+        m if m >= 80 * GIB => 16384,
+        m if m >= 40 * GIB => 8192,
+        m if m >= 24 * GIB => 4096,
+        _ => 2048,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMI: &str = "81920\n81920\n24576\n81920\n";
+
+    #[test]
+    fn test_parse_all_visible() {
+        let info = parse_nvidia_smi(SMI, None).unwrap();
+        assert_eq!(info.count, 4);
+        assert_eq!(info.min_memory_mib, 24576);
+    }
+
+    #[test]
+    fn test_parse_cuda_visible_devices() {
+        let info = parse_nvidia_smi(SMI, Some("0,3")).unwrap();
+        assert_eq!(info.count, 2);
+        assert_eq!(info.min_memory_mib, 81920);
+    }
+
+    #[test]
+    fn test_parse_no_gpus() {
+        assert_eq!(parse_nvidia_smi("", None), None);
+        assert_eq!(parse_nvidia_smi(SMI, Some("")), None);
+    }
+
+    #[test]
+    fn test_parse_driver() {
+        let header = "+------------------------------------------------------------------+\n\
+            | NVIDIA-SMI 550.54.15    Driver Version: 550.54.15    CUDA Version: 12.4  |\n";
+        assert_eq!(
+            parse_nvidia_smi_header(header),
+            Some(DriverInfo {
+                driver_version: "550.54.15".to_string(),
+                cuda_version: Some("12.4".to_string()),
+            })
+        );
+        assert_eq!(parse_nvidia_smi_header("No devices were found"), None);
+    }
+
+    #[test]
+    fn test_max_num_batched_tokens() {
+        assert_eq!(max_num_batched_tokens_for(81920), 16384);
+        assert_eq!(max_num_batched_tokens_for(49152), 8192);
+        assert_eq!(max_num_batched_tokens_for(24576), 4096);
+        assert_eq!(max_num_batched_tokens_for(8192), 2048);
+    }
+}
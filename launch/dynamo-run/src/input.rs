@@ -16,5 +16,8 @@
 pub mod batch;
 mod common;
 pub mod endpoint;
+pub mod grpc;
 pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
 pub mod text;
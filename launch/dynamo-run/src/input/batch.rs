@@ -1,38 +1,43 @@
 // SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Context as _;
 use async_openai::types::FinishReason;
 use dynamo_llm::model_card::model::ModelDeploymentCard;
 use dynamo_llm::preprocessor::OpenAIPreprocessor;
 use dynamo_llm::request_template::RequestTemplate;
+use dynamo_llm::response_tee::ResponseTee;
 use dynamo_llm::types::openai::chat_completions::{
     NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
 };
 use dynamo_runtime::{pipeline::Context, runtime::CancellationToken, Runtime};
 use futures::StreamExt;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::cmp;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 
 use crate::input::common;
 use crate::{EngineConfig, Flags};
 
+mod dataset;
+pub use dataset::ColumnMapping;
+
 /// Max tokens in each response.
 /// TODO: For batch mode this should be the full context size of the model
 const MAX_TOKENS: u32 = 8192;
 
 const OUTPUT_FILENAME: &str = "output.jsonl";
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Default, Debug)]
 struct Entry {
-    // The input files only have this
     text: String,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+
     response: Option<String>,
 
     #[serde(default)]
@@ -47,26 +52,37 @@ struct Entry {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     finish_reason: Option<FinishReason>,
 
-    #[serde(skip, default)]
+    #[serde(skip)]
     request_id: usize,
+
+    #[serde(skip)]
+    temperature: Option<f32>,
+
+    #[serde(skip)]
+    top_p: Option<f32>,
+
+    #[serde(skip)]
+    max_tokens: Option<u32>,
 }
 
 pub async fn run(
     runtime: Runtime,
-    _flags: Flags,
+    flags: Flags,
     card: ModelDeploymentCard,
-    input_jsonl: PathBuf,
+    input_path: PathBuf,
     engine_config: EngineConfig,
     template: Option<RequestTemplate>,
 ) -> anyhow::Result<()> {
     let cancel_token = runtime.primary_token();
     // Check if the path exists and is a directory
-    if !input_jsonl.exists() || !input_jsonl.is_file() {
+    if !input_path.exists() || !input_path.is_file() {
         anyhow::bail!(
-            "Missing or not a file: {}. Should be a JSON Lines file.",
-            input_jsonl.display()
+            "Missing or not a file: {}. Should be a JSON Lines, CSV or Parquet file.",
+            input_path.display()
         );
     }
+    let mapping = flags.batch_columns.clone().unwrap_or_default();
+    let response_tee = flags.response_tee()?;
 
     let prepared_engine = common::prepare_engine(runtime, engine_config).await?;
     let service_name_ref = Arc::new(prepared_engine.service_name);
@@ -78,7 +94,7 @@ pub async fn run(
     };
     let (done_entries_tx, done_entries_rx) = tokio::sync::mpsc::channel(64);
     let dw_cancel_token = cancel_token.clone();
-    let mut output_file = input_jsonl.clone();
+    let mut output_file = input_path.clone();
     output_file.set_file_name(OUTPUT_FILENAME);
     tokio::spawn(async move {
         if let Err(err) = output_writer(dw_cancel_token, done_entries_rx, &output_file).await {
@@ -90,31 +106,27 @@ pub async fn run(
     let tokens_out = Arc::new(AtomicU64::new(0));
     let mut handles = vec![];
     let mut num_entries = 0;
-    let input_file = tokio::fs::File::open(&input_jsonl)
-        .await
-        .with_context(|| input_jsonl.display().to_string())?;
-    let buffered_input = tokio::io::BufReader::new(input_file);
+    let mut prompts = dataset::read(input_path, mapping);
 
     tracing::info!("Timer start.");
     let start = Instant::now();
-    let mut lines = buffered_input.lines();
     let template: Option<Arc<RequestTemplate>> = template.map(Arc::new);
-    while let Ok(Some(line)) = lines.next_line().await {
+    while let Some(prompt) = prompts.recv().await {
         if cancel_token.is_cancelled() {
             break;
         }
-        if line.is_empty() {
-            continue;
-        }
+        let prompt = prompt?;
         let request_id = num_entries;
         num_entries += 1;
-        let mut entry: Entry = match serde_json::from_str(&line) {
-            Ok(entry) => entry,
-            Err(err) => {
-                anyhow::bail!("Error parsing entry: '{line}'. {err}");
-            }
+        let mut entry = Entry {
+            text: prompt.text,
+            system: prompt.system,
+            request_id,
+            temperature: prompt.temperature,
+            top_p: prompt.top_p,
+            max_tokens: prompt.max_tokens,
+            ..Default::default()
         };
-        entry.request_id = request_id;
 
         let engine = prepared_engine.engine.clone();
         let pre_processor = pre_processor.clone();
@@ -123,6 +135,7 @@ pub async fn run(
         let done_entries_tx = done_entries_tx.clone();
         let service_name_ref = service_name_ref.clone();
         let template_clone = template.clone();
+        let response_tee = response_tee.clone();
         let handle = tokio::spawn(async move {
             let local_start = Instant::now();
             let response = match evaluate(
@@ -131,6 +144,7 @@ pub async fn run(
                 engine,
                 &mut entry,
                 template_clone,
+                response_tee,
             )
             .await
             {
@@ -176,6 +190,9 @@ pub async fn run(
         _ = futures::future::join_all(handles) => {
         }
     }
+    if let Some(tee) = &response_tee {
+        tee.finish().await;
+    }
     let elapsed = Instant::now() - start;
     let elapsed_clean = Duration::from_millis(elapsed.as_millis() as u64);
     let tokens_in = Arc::into_inner(tokens_in).unwrap().into_inner();
@@ -202,32 +219,55 @@ async fn evaluate(
     engine: OpenAIChatCompletionsStreamingEngine,
     entry: &mut Entry,
     template: Option<Arc<RequestTemplate>>,
+    response_tee: Option<Arc<ResponseTee>>,
 ) -> anyhow::Result<String> {
-    let user_message = async_openai::types::ChatCompletionRequestMessage::User(
+    let mut messages = Vec::with_capacity(2);
+    if let Some(system) = &entry.system {
+        messages.push(async_openai::types::ChatCompletionRequestMessage::System(
+            async_openai::types::ChatCompletionRequestSystemMessage {
+                content: async_openai::types::ChatCompletionRequestSystemMessageContent::Text(
+                    system.clone(),
+                ),
+                name: None,
+            },
+        ));
+    }
+    messages.push(async_openai::types::ChatCompletionRequestMessage::User(
         async_openai::types::ChatCompletionRequestUserMessage {
             content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
                 entry.text.clone(),
             ),
             name: None,
         },
-    );
-    let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
-        .messages(vec![user_message])
+    ));
+    let mut args = async_openai::types::CreateChatCompletionRequestArgs::default();
+    if let Some(top_p) = entry.top_p {
+        args.top_p(top_p);
+    }
+    let inner = args
+        .messages(messages)
         .model(
             template
                 .as_ref()
                 .map_or_else(|| service_name.to_string(), |t| t.model.clone()),
         )
         .stream(true)
-        .max_completion_tokens(
+        .max_completion_tokens(entry.max_tokens.unwrap_or_else(|| {
             template
                 .as_ref()
-                .map_or(MAX_TOKENS, |t| t.max_completion_tokens),
+                .map_or(MAX_TOKENS, |t| t.max_completion_tokens)
+        }))
+        .temperature(
+            entry
+                .temperature
+                .unwrap_or_else(|| template.as_ref().map_or(0.7, |t| t.temperature)),
         )
-        .temperature(template.as_ref().map_or(0.7, |t| t.temperature))
         .build()?;
     let req = NvCreateChatCompletionRequest { inner, nvext: None };
     let mut stream = engine.generate(Context::new(req)).await?;
+    if let Some(tee) = &response_tee {
+        stream = tee.track(&request_id.to_string(), service_name, stream);
+    }
     let mut output = String::new();
     while let Some(item) = stream.next().await {
         match (item.data.as_ref(), item.event.as_deref()) {
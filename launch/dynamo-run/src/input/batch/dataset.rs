@@ -0,0 +1,346 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read the prompts of a batch run from a JSON Lines, CSV or Parquet dataset. The format comes
+//! from the file extension. A [`ColumnMapping`] says which columns hold the prompt, the system
+//! prompt and the sampling parameters, so datasets can be used as they are.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context as _;
+#[cfg(feature = "parquet")]
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde_json::{Map, Value};
+
+/// Rows read ahead of the requests
+const READ_AHEAD: usize = 256;
+
+type Row = Map<String, Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    JsonLines,
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    fn of(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("csv") => Format::Csv,
+            Some("parquet") => Format::Parquet,
+            _ => Format::JsonLines,
+        }
+    }
+}
+
+/// The dataset columns to take each part of a request from. Only the prompt is required.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+    pub prompt: String,
+    pub system: Option<String>,
+    pub temperature: Option<String>,
+    pub top_p: Option<String>,
+    pub max_tokens: Option<String>,
+}
+
+impl Default for ColumnMapping {
+    /// The `text` column of the original batch format
+    fn default() -> Self {
+        ColumnMapping {
+            prompt: "text".to_string(),
+            system: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+        }
+    }
+}
+
+impl FromStr for ColumnMapping {
+    type Err = anyhow::Error;
+
+    /// Parse `field=column` pairs separated by commas, e.g. `prompt=question,system=instruction`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut mapping = ColumnMapping::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((field, column)) = pair.split_once('=') else {
+                anyhow::bail!("Invalid column mapping '{pair}', expected field=column");
+            };
+            let column = column.trim().to_string();
+            match field.trim() {
+                "prompt" => mapping.prompt = column,
+                "system" => mapping.system = Some(column),
+                "temperature" => mapping.temperature = Some(column),
+                "top_p" => mapping.top_p = Some(column),
+                "max_tokens" => mapping.max_tokens = Some(column),
+                other => anyhow::bail!(
+                    "Unknown field '{other}' in column mapping, expected one of prompt, system, temperature, top_p, max_tokens"
+                ),
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+/// One request of the dataset
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prompt {
+    pub text: String,
+    pub system: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+impl ColumnMapping {
+    fn prompt(&self, row: &Row) -> anyhow::Result<Prompt> {
+        let text = match row.get(&self.prompt) {
+            Some(Value::String(text)) => text.clone(),
+            Some(Value::Null) | None => {
+                anyhow::bail!("Missing prompt column '{}'", self.prompt)
+            }
+            Some(other) => other.to_string(),
+        };
+        let system = match self.system.as_ref().and_then(|c| row.get(c)) {
+            Some(Value::String(system)) if !system.is_empty() => Some(system.clone()),
+            _ => None,
+        };
+        Ok(Prompt {
+            text,
+            system,
+            temperature: number(row, self.temperature.as_deref())?.map(|n| n as f32),
+            top_p: number(row, self.top_p.as_deref())?.map(|n| n as f32),
+            max_tokens: count(row, self.max_tokens.as_deref())?,
+        })
+    }
+}
+
+/// The number in `column`, if it has one. CSV columns are strings.
+fn number(row: &Row, column: Option<&str>) -> anyhow::Result<Option<f64>> {
+    let Some(column) = column else {
+        return Ok(None);
+    };
+    match row.get(column) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => Ok(n.as_f64()),
+        Some(Value::String(s)) if s.trim().is_empty() => Ok(None),
+        Some(Value::String(s)) => s
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Column '{column}' is not a number: '{s}'")),
+        Some(other) => anyhow::bail!("Column '{column}' is not a number: {other}"),
+    }
+}
+
+/// The whole, non-negative number in `column`, if it has one
+fn count(row: &Row, column: Option<&str>) -> anyhow::Result<Option<u32>> {
+    let Some(n) = number(row, column)? else {
+        return Ok(None);
+    };
+    let column = column.unwrap_or_default();
+    if n < 0.0 || n.fract() != 0.0 {
+        anyhow::bail!("Column '{column}' must be a whole number of at least 0, got {n}");
+    }
+    // Saturates, which try_from then rejects
+    let count =
+        u32::try_from(n as u64).with_context(|| format!("Column '{column}' is too large: {n}"))?;
+    Ok(Some(count))
+}
+
+/// Read the prompts of `path` on a blocking thread, in order. Stops at the first error, which is
+/// the last item.
+pub fn read(
+    path: PathBuf,
+    mapping: ColumnMapping,
+) -> tokio::sync::mpsc::Receiver<anyhow::Result<Prompt>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(READ_AHEAD);
+    tokio::task::spawn_blocking(move || {
+        let mut send = |row: anyhow::Result<Row>| {
+            let prompt = row.and_then(|row| mapping.prompt(&row));
+            let failed = prompt.is_err();
+            // The receiver is gone if the run was cancelled
+            tx.blocking_send(prompt).is_ok() && !failed
+        };
+        let result = match Format::of(&path) {
+            Format::JsonLines => read_jsonl(&path, &mut send),
+            Format::Csv => read_csv(&path, &mut send),
+            Format::Parquet => read_parquet(&path, &mut send),
+        };
+        if let Err(err) = result {
+            let _ = tx.blocking_send(Err(err.context(path.display().to_string())));
+        }
+    });
+    rx
+}
+
+/// `send` returns false to stop reading
+fn read_jsonl(
+    path: &Path,
+    send: &mut impl FnMut(anyhow::Result<Row>) -> bool,
+) -> anyhow::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row =
+            serde_json::from_str(&line).with_context(|| format!("Error parsing entry: '{line}'"));
+        if !send(row) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_csv(path: &Path, send: &mut impl FnMut(anyhow::Result<Row>) -> bool) -> anyhow::Result<()> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    for record in reader.records() {
+        let row = record.map_err(anyhow::Error::from).map(|record| {
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+                .collect()
+        });
+        if !send(row) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn read_parquet(
+    path: &Path,
+    send: &mut impl FnMut(anyhow::Result<Row>) -> bool,
+) -> anyhow::Result<()> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    for row in reader.get_row_iter(None)? {
+        let row = row
+            .map_err(anyhow::Error::from)
+            .and_then(|row| match row.to_json_value() {
+                Value::Object(row) => Ok(row),
+                other => anyhow::bail!("Unexpected Parquet row: {other}"),
+            });
+        if !send(row) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn read_parquet(
+    _path: &Path,
+    _send: &mut impl FnMut(anyhow::Result<Row>) -> bool,
+) -> anyhow::Result<()> {
+    anyhow::bail!("Reading Parquet needs dynamo-run built with the `parquet` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    async fn collect(path: PathBuf, mapping: ColumnMapping) -> Vec<anyhow::Result<Prompt>> {
+        let mut rx = read(path, mapping);
+        let mut prompts = Vec::new();
+        while let Some(prompt) = rx.recv().await {
+            prompts.push(prompt);
+        }
+        prompts
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping: ColumnMapping = "prompt=question, system=instruction,max_tokens=len"
+            .parse()
+            .unwrap();
+        assert_eq!(mapping.prompt, "question");
+        assert_eq!(mapping.system.as_deref(), Some("instruction"));
+        assert_eq!(mapping.max_tokens.as_deref(), Some("len"));
+        assert_eq!(mapping.temperature, None);
+
+        assert!("question".parse::<ColumnMapping>().is_err());
+        assert!("answer=response".parse::<ColumnMapping>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_csv() {
+        let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+        writeln!(file, "id,question,instruction,temp").unwrap();
+        writeln!(file, "1,\"Capital of France, in one word?\",Be brief,0.2").unwrap();
+        writeln!(file, "2,Capital of Spain?,,").unwrap();
+        let mapping = "prompt=question,system=instruction,temperature=temp"
+            .parse()
+            .unwrap();
+
+        let prompts: Vec<Prompt> = collect(file.path().to_path_buf(), mapping)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            prompts,
+            vec![
+                Prompt {
+                    text: "Capital of France, in one word?".to_string(),
+                    system: Some("Be brief".to_string()),
+                    temperature: Some(0.2),
+                    ..Default::default()
+                },
+                Prompt {
+                    text: "Capital of Spain?".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_jsonl() {
+        let mut file = tempfile::Builder::new()
+            .suffix(".jsonl")
+            .tempfile()
+            .unwrap();
+        writeln!(file, r#"{{"text": "Hello", "max_tokens": 16}}"#).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, r#"{{"prompt": "No text"}}"#).unwrap();
+        writeln!(file, r#"{{"text": "Not read"}}"#).unwrap();
+        let mapping = "max_tokens=max_tokens".parse().unwrap();
+
+        let prompts = collect(file.path().to_path_buf(), mapping).await;
+        assert_eq!(prompts.len(), 2);
+        assert_eq!(prompts[0].as_ref().unwrap().max_tokens, Some(16));
+        assert!(prompts[1].is_err());
+    }
+
+    #[test]
+    fn test_count() {
+        let mapping: ColumnMapping = "max_tokens=len".parse().unwrap();
+        let prompt = |len: Value| {
+            let row: Row = [("text", Value::from("Hi")), ("len", len)]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect();
+            mapping.prompt(&row).map(|p| p.max_tokens)
+        };
+        assert_eq!(prompt(Value::from(16)).unwrap(), Some(16));
+        assert_eq!(prompt(Value::from("32")).unwrap(), Some(32));
+        assert_eq!(prompt(Value::Null).unwrap(), None);
+        assert!(prompt(Value::from(-1)).is_err());
+        assert!(prompt(Value::from(1.5)).is_err());
+        assert!(prompt(Value::from(5_000_000_000u64)).is_err());
+    }
+}
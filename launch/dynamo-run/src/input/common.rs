@@ -4,28 +4,39 @@
 use std::pin::Pin;
 
 use dynamo_llm::{
+    audit::{self, AuditLogger, AuditSink, RotatingFileSink},
     backend::{Backend, ExecutionContext},
-    discovery::{ModelManager, ModelWatcher, MODEL_ROOT_PATH},
+    discovery::{
+        routing::{self, RoutingPolicy},
+        ModelManager, ModelWatcher, MODEL_ROOT_PATH,
+    },
     engines::StreamingEngineAdapter,
+    http::service::auth::AuthKeys,
+    local_model::LocalModel,
+    lora,
     model_card::ModelDeploymentCard,
     preprocessor::OpenAIPreprocessor,
     protocols::common::llm_backend::{BackendOutput, PreprocessedRequest},
+    token_timing::TokenTimingEngine,
+    tokenizers::lazy::LazyTokenizer,
     types::{
         openai::chat_completions::{
             NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
             OpenAIChatCompletionsStreamingEngine,
         },
+        openai::completions::{CompletionResponse, NvCreateCompletionRequest},
         Annotated,
     },
 };
 use dynamo_runtime::{
     engine::{AsyncEngineStream, Data},
     pipeline::{Context, ManyOut, Operator, ServiceBackend, ServiceFrontend, SingleIn, Source},
+    transports::etcd,
     DistributedRuntime, Runtime,
 };
 use std::sync::Arc;
 
-use crate::EngineConfig;
+use crate::{flags, EngineConfig, Flags};
 
 pub struct PreparedEngine {
     pub service_name: String,
@@ -101,7 +112,203 @@ pub async fn prepare_engine(
                 inspect_template: true,
             })
         }
+        EngineConfig::Multi(_) => {
+            anyhow::bail!("This input serves a single model, --models needs in=http or in=grpc");
+        }
+    }
+}
+
+/// Serve the models of `engine_config` from `manager`. Used by the HTTP and gRPC inputs.
+pub async fn register_engines(
+    runtime: &Runtime,
+    manager: Arc<ModelManager>,
+    engine_config: EngineConfig,
+    flags: &Flags,
+) -> anyhow::Result<()> {
+    let routing = match &flags.model_routing {
+        Some(path) => RoutingPolicy::from_file(path)?,
+        None => RoutingPolicy::default(),
+    };
+    manager.set_routing_policy(routing.clone());
+
+    let engine_configs = match engine_config {
+        EngineConfig::Multi(engine_configs) => engine_configs,
+        engine_config => vec![engine_config],
+    };
+    let mut watching = false;
+    for engine_config in engine_configs {
+        match engine_config {
+            EngineConfig::Dynamic => {
+                // The sub-process engines of a models manifest all register in etcd, one watcher
+                // finds them all.
+                if !watching {
+                    watch_models(runtime, manager.clone(), flags, routing.clone()).await?;
+                    watching = true;
+                }
+            }
+            EngineConfig::StaticFull { engine, model } => {
+                add_tokenizer(&manager, &model);
+                let engine = Arc::new(StreamingEngineAdapter::new(engine));
+                manager.add_completions_model(model.service_name(), engine.clone())?;
+                manager.add_chat_completions_model(model.service_name(), engine)?;
+            }
+            EngineConfig::StaticCore {
+                engine: inner_engine,
+                model,
+            } => {
+                add_tokenizer(&manager, &model);
+                let chat_pipeline = build_pipeline::<
+                    NvCreateChatCompletionRequest,
+                    NvCreateChatCompletionStreamResponse,
+                >(model.card(), inner_engine.clone())
+                .await?;
+                manager.add_chat_completions_model(model.service_name(), chat_pipeline)?;
+
+                let cmpl_pipeline =
+                    build_pipeline::<NvCreateCompletionRequest, CompletionResponse>(
+                        model.card(),
+                        inner_engine,
+                    )
+                    .await?;
+                manager.add_completions_model(model.service_name(), cmpl_pipeline)?;
+            }
+            EngineConfig::Multi(_) => {
+                anyhow::bail!("A models manifest can't contain another one");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the models of remote workers to `manager` as they come and go
+async fn watch_models(
+    runtime: &Runtime,
+    manager: Arc<ModelManager>,
+    flags: &Flags,
+    routing: RoutingPolicy,
+) -> anyhow::Result<()> {
+    let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+    match distributed_runtime.etcd_client() {
+        Some(etcd_client) => {
+            distributed_runtime.runtime().tasks().spawn(
+                "model routing",
+                routing::follow(manager.clone(), etcd_client.clone(), routing),
+            );
+            distributed_runtime.runtime().tasks().spawn(
+                "lora adapters",
+                lora::follow(manager.loras(), etcd_client.clone()),
+            );
+
+            // Listen for models registering themselves in etcd, add them to the frontend
+            run_watcher(
+                distributed_runtime,
+                manager,
+                etcd_client.clone(),
+                MODEL_ROOT_PATH,
+                flags,
+            )
+            .await?;
+        }
+        None => {
+            // Static endpoints don't need discovery
+        }
     }
+    Ok(())
+}
+
+/// Let `/v1/tokenize` use the local model's tokenizer
+fn add_tokenizer(manager: &ModelManager, model: &LocalModel) {
+    if model.card().has_tokenizer() {
+        let tokenizer = LazyTokenizer::new(model.card().clone(), None);
+        manager.add_tokenizer(model.service_name(), Arc::new(tokenizer));
+    }
+}
+
+/// Spawns a task that watches for new models in etcd at network_prefix,
+/// and registers them with the ModelManager so that the frontend can use them.
+async fn run_watcher(
+    runtime: DistributedRuntime,
+    model_manager: Arc<ModelManager>,
+    etcd_client: etcd::Client,
+    network_prefix: &str,
+    flags: &Flags,
+) -> anyhow::Result<()> {
+    if flags.router_mode == flags::RouterMode::Budget && flags.first_token_timeout_ms.is_some() {
+        anyhow::bail!("--first-token-timeout-ms is not supported with --router-mode budget");
+    }
+    let watch_obj = ModelWatcher::new(
+        runtime,
+        model_manager,
+        flags.router_mode.into(),
+        Some(flags.kv_router_config()),
+    )
+    .with_embedding_batching(flags.embedding_batch_config())
+    .with_generation_limits(flags.generation_limits())
+    .with_special_tokens(flags.special_tokens())
+    .with_sampling_presets(flags.sampling_presets()?)
+    .with_prompt_compression(flags.prompt_compression())
+    .with_rescheduling(flags.reschedule_config())
+    .with_worker_admission(flags.worker_admission()?)
+    .with_budget_policy(flags.budget_policy()?)
+    .with_disaggregation(flags.disagg_config())
+    .with_speculative(flags.speculative_config());
+    tracing::info!("Watching for remote model at {network_prefix}");
+    let models_watcher = etcd_client.kv_get_and_watch_prefix(network_prefix).await?;
+    let (_prefix, _watcher, receiver) = models_watcher.dissolve();
+    let _watcher_task = tokio::spawn(async move {
+        watch_obj.watch(receiver).await;
+    });
+    Ok(())
+}
+
+/// The API keys to require, if any were configured
+pub async fn api_keys(runtime: &Runtime, flags: &Flags) -> anyhow::Result<Option<Arc<AuthKeys>>> {
+    if flags.http_api_keys_file.is_none() && flags.http_api_keys_etcd_prefix.is_none() {
+        return Ok(None);
+    }
+    let keys = AuthKeys::new();
+    if let Some(path) = flags.http_api_keys_file.clone() {
+        keys.watch_file(path, runtime.primary_token())?;
+    }
+    if let Some(prefix) = &flags.http_api_keys_etcd_prefix {
+        let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+        let Some(etcd_client) = distributed_runtime.etcd_client() else {
+            anyhow::bail!("--http-api-keys-etcd-prefix requires etcd");
+        };
+        keys.watch_etcd(&etcd_client, prefix).await?;
+    }
+    // etcd keys arrive asynchronously, so only check the file
+    if flags.http_api_keys_etcd_prefix.is_none() && keys.is_empty() {
+        tracing::warn!("API key authentication is enabled but there are no keys yet, all requests will be rejected");
+    }
+    Ok(Some(keys))
+}
+
+/// The audit logger, if an audit sink was configured
+pub async fn audit_logger(
+    runtime: &Runtime,
+    flags: &Flags,
+) -> anyhow::Result<Option<Arc<AuditLogger>>> {
+    let mut sinks: Vec<Arc<dyn AuditSink>> = Vec::new();
+    if let Some(dir) = &flags.audit_log_dir {
+        let max_bytes = flags.audit_log_max_mib * 1024 * 1024;
+        let sink = RotatingFileSink::new(dir, max_bytes, flags.audit_log_max_files).await?;
+        sinks.push(Arc::new(sink));
+    }
+    if let Some(subject) = &flags.audit_nats_subject {
+        let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+        let client = distributed_runtime.nats_client().client().clone();
+        sinks.push(Arc::new(audit::NatsSink::new(client, subject.clone())));
+    }
+    if sinks.is_empty() {
+        return Ok(None);
+    }
+    let redaction = flags.audit_redact.clone().unwrap_or_default();
+    Ok(Some(AuditLogger::new(
+        flags.audit_sample_rate,
+        redaction,
+        sinks,
+    )?))
 }
 
 pub async fn build_pipeline<Req, Resp>(
@@ -123,7 +330,7 @@ where
         .await?
         .into_operator();
     let backend = Backend::from_mdc((*card).clone()).await?.into_operator();
-    let engine = ServiceBackend::from_engine(engine);
+    let engine = ServiceBackend::from_engine(TokenTimingEngine::new(engine));
 
     Ok(frontend
         .link(preprocessor.forward_edge())?
@@ -137,10 +344,6 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use dynamo_llm::types::openai::{
-        chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
-        completions::{CompletionResponse, NvCreateCompletionRequest},
-    };
 
     const HF_PATH: &str = concat!(
         env!("CARGO_MANIFEST_DIR"),
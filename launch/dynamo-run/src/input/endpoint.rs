@@ -13,13 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{future::Future, pin::Pin, sync::Arc};
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
 
 use dynamo_llm::{
+    audit::{AuditEngine, AuditLogger},
     backend::Backend,
+    discovery::model_control,
     engines::StreamingEngineAdapter,
+    local_model::{DuplicateRegistration, LocalModel},
+    model_card::ModelDeploymentCard,
     model_type::ModelType,
     preprocessor::{BackendOutput, PreprocessedRequest},
+    request_log::{RequestLog, RequestLogEngine},
+    response_tee::{ResponseTee, ResponseTeeEngine},
+    token_timing::TokenTimingEngine,
     types::{
         openai::chat_completions::{
             NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
@@ -29,17 +36,37 @@ use dynamo_llm::{
 };
 use dynamo_runtime::engine::AsyncEngineStream;
 use dynamo_runtime::pipeline::{
-    network::Ingress, Context, ManyOut, Operator, SegmentSource, ServiceBackend, SingleIn, Source,
+    network::Ingress, Context, ManyOut, Operator, SegmentSource, ServiceBackend, ServiceEngine,
+    SingleIn, Source,
 };
-use dynamo_runtime::{protocols::Endpoint as EndpointId, DistributedRuntime};
+use dynamo_runtime::{
+    component::{Endpoint, WorkerDescription},
+    discovery::Lease,
+    protocols::Endpoint as EndpointId,
+    traits::DistributedRuntimeProvider,
+    DistributedRuntime,
+};
+
+use crate::input::common;
+use crate::model_loader::WorkerModelLoader;
+use crate::{EngineConfig, Flags, Output};
 
-use crate::EngineConfig;
+/// How long after starting the endpoint it must be reachable through discovery
+pub(crate) const ROUTABLE_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub async fn run(
     distributed_runtime: DistributedRuntime,
     path: String,
     engine_config: EngineConfig,
+    out_opt: Output,
+    flags: Flags,
 ) -> anyhow::Result<()> {
+    let request_log = flags.request_log();
+    let request_log_dir = flags.request_log_dir();
+    if let Some(request_log) = request_log.as_ref() {
+        request_log.dump_on_panic(request_log_dir.clone());
+    }
+
     let cancel_token = distributed_runtime.primary_token().clone();
     let endpoint_id: EndpointId = path.parse()?;
 
@@ -51,18 +78,207 @@ pub async fn run(
         .create()
         .await?
         .endpoint(&endpoint_id.name);
+    let endpoint = claim_endpoint(
+        &endpoint,
+        &engine_config,
+        flags.duplicate_registration.into(),
+    )
+    .await?;
+
+    let audit_logger = common::audit_logger(distributed_runtime.runtime(), &flags).await?;
+    let response_tee = flags.response_tee()?;
+    let (rt_fut, card) = start(
+        &endpoint,
+        engine_config,
+        None,
+        request_log.clone(),
+        audit_logger.clone(),
+        response_tee.clone(),
+        InstanceAdvert {
+            draft: flags.draft_model_path.is_some(),
+            ..InstanceAdvert::new(&flags, out_opt)
+        },
+    )
+    .await?;
+
+    if flags.enable_model_control {
+        let loader = Arc::new(WorkerModelLoader::new(
+            distributed_runtime.clone(),
+            endpoint_id.clone(),
+            out_opt,
+            flags.clone(),
+            audit_logger,
+            response_tee,
+        ));
+        distributed_runtime
+            .runtime()
+            .tasks()
+            .spawn(format!("model control {component}"), async move {
+                model_control::serve(&component, loader).await
+            });
+    }
+
+    // Only report ready once a frontend could actually reach us
+    let registered = card.is_some();
+    let ready = async {
+        if !registered {
+            return never_ready().await;
+        }
+        if distributed_runtime.is_standby() {
+            tracing::info!("Model loaded, standing by until activated");
+            distributed_runtime.activated_token().cancelled().await;
+        }
+        LocalModel::verify_routable(&endpoint, ROUTABLE_TIMEOUT).await?;
+        tracing::info!("Model is ready and routable at {}", endpoint.path());
+        never_ready().await
+    };
+
+    let mut result = Ok(());
+    tokio::select! {
+        _ = rt_fut => {
+            tracing::debug!("Endpoint ingress ended");
+            // Drained. Models loaded at runtime may still be finishing their requests, the
+            // runtime shuts down once they are done.
+            if distributed_runtime.runtime().drain_token().is_cancelled() {
+                cancel_token.cancelled().await;
+            }
+        }
+        Err(err) = ready => {
+            result = Err(err);
+        }
+        _ = cancel_token.cancelled() => {
+            // Stopped by a signal, possibly an orchestrator killing an unhealthy worker
+            if let Some(request_log) = request_log.as_ref() {
+                match request_log.dump(&request_log_dir) {
+                    Ok(path) => tracing::info!("Recent requests written to {}", path.display()),
+                    Err(err) => tracing::warn!(%err, "Failed writing recent requests"),
+                }
+            }
+        }
+    }
+
+    // Cleanup on shutdown
+    if let Some(mut card) = card {
+        if let Err(err) = card
+            .delete_from_nats(distributed_runtime.nats_client())
+            .await
+        {
+            tracing::error!(%err, "delete_from_nats error on shutdown");
+        }
+    }
+
+    result
+}
+
+/// The endpoint to serve the model of `engine_config` on, by `policy` if the component of
+/// `endpoint` already serves another model
+pub(crate) async fn claim_endpoint(
+    endpoint: &Endpoint,
+    engine_config: &EngineConfig,
+    policy: DuplicateRegistration,
+) -> anyhow::Result<Endpoint> {
+    match engine_config {
+        EngineConfig::StaticFull { model, .. } | EngineConfig::StaticCore { model, .. } => {
+            model.claim_endpoint(endpoint, policy).await
+        }
+        EngineConfig::Dynamic | EngineConfig::Multi(_) => Ok(endpoint.clone()),
+    }
+}
+
+/// What the worker publishes with its instance, for the routers
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InstanceAdvert {
+    /// Routers send at most this many requests at once
+    pub max_concurrent_requests: Option<u32>,
+
+    /// Take at most this many requests at once from the work queue of the endpoint
+    pub pull_capacity: Option<u32>,
+
+    /// What an hour of a GPU of the worker costs, for budget routing
+    pub cost_per_gpu_hour: Option<f64>,
+
+    /// The engine and build serving, for the worker admission policy of the ingress
+    pub description: Option<WorkerDescription>,
+
+    /// Register the model as [`ModelType::Draft`], for speculative decoding
+    pub draft: bool,
+}
+
+impl InstanceAdvert {
+    pub(crate) fn new(flags: &Flags, out_opt: Output) -> Self {
+        let build = crate::build_info();
+        let driver = crate::hardware::detect_driver();
+        let description = WorkerDescription {
+            engine: Some(out_opt.to_string()),
+            // The in-process engines are built into dynamo-run
+            engine_version: Some(build.version.clone()),
+            build: Some(build.git_sha.unwrap_or(build.version)),
+            cuda_version: driver.as_ref().and_then(|d| d.cuda_version.clone()),
+            driver_version: driver.map(|d| d.driver_version),
+        };
+        InstanceAdvert {
+            max_concurrent_requests: flags.max_concurrent_requests,
+            pull_capacity: flags.pull_capacity,
+            cost_per_gpu_hour: flags.cost_per_gpu_hour,
+            description: Some(description),
+            draft: false,
+        }
+    }
+}
 
-    let (rt_fut, card): (Pin<Box<dyn Future<Output = _> + Send + 'static>>, _) = match engine_config
-    {
+type ServeFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'static>>;
+
+/// Serve `engine_config` on `endpoint` and register its model, tied to etcd lease `lease`, the
+/// primary lease if None. Returns the future serving requests, and the model card unless the
+/// engine is [`EngineConfig::Dynamic`]. Requests are audited with `audit_logger` and their
+/// responses teed with `response_tee` if given. The instance carries `advert`.
+pub(crate) async fn start(
+    endpoint: &Endpoint,
+    engine_config: EngineConfig,
+    lease: Option<Lease>,
+    request_log: Option<Arc<RequestLog>>,
+    audit_logger: Option<Arc<AuditLogger>>,
+    response_tee: Option<Arc<ResponseTee>>,
+    advert: InstanceAdvert,
+) -> anyhow::Result<(ServeFuture, Option<ModelDeploymentCard>)> {
+    let Some(lease_id) = lease
+        .clone()
+        .or_else(|| endpoint.drt().primary_lease())
+        .map(|lease| lease.id())
+    else {
+        anyhow::bail!("Cannot serve a model on a static endpoint");
+    };
+    let started: (ServeFuture, _) = match engine_config {
         EngineConfig::StaticFull { engine, mut model } => {
-            let engine = Arc::new(StreamingEngineAdapter::new(engine));
+            let engine: ServiceEngine<
+                SingleIn<NvCreateChatCompletionRequest>,
+                ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+            > = Arc::new(StreamingEngineAdapter::new(engine));
+            let engine = match response_tee {
+                Some(tee) => ResponseTeeEngine::new(engine, tee, model.service_name().to_string()),
+                None => engine,
+            };
+            let engine = match audit_logger {
+                Some(logger) => AuditEngine::new(engine, logger, endpoint.path()),
+                None => engine,
+            };
             let ingress_chat = Ingress::<
                 Context<NvCreateChatCompletionRequest>,
                 Pin<Box<dyn AsyncEngineStream<Annotated<NvCreateChatCompletionStreamResponse>>>>,
             >::for_engine(engine)?;
 
-            model.attach(&endpoint, ModelType::Chat).await?;
-            let fut_chat = endpoint.endpoint_builder().handler(ingress_chat).start();
+            model
+                .attach_with_lease(endpoint, ModelType::Chat, lease_id)
+                .await?;
+            let fut_chat = endpoint
+                .endpoint_builder()
+                .lease(lease)
+                .max_concurrent_requests(advert.max_concurrent_requests)
+                .pull_capacity(advert.pull_capacity)
+                .cost_per_gpu_hour(advert.cost_per_gpu_hour)
+                .description(advert.description)
+                .handler(ingress_chat)
+                .start();
 
             (Box::pin(fut_chat), Some(model.card().clone()))
         }
@@ -78,16 +294,50 @@ pub async fn run(
             let backend = Backend::from_mdc(model.card().clone())
                 .await?
                 .into_operator();
-            let engine = ServiceBackend::from_engine(inner_engine);
+            let inner_engine = match request_log.as_ref() {
+                Some(request_log) => RequestLogEngine::new(inner_engine, request_log.clone()),
+                None => inner_engine,
+            };
+            let engine = ServiceBackend::from_engine(TokenTimingEngine::new(inner_engine));
             let pipeline = frontend
                 .link(backend.forward_edge())?
                 .link(engine)?
                 .link(backend.backward_edge())?
                 .link(frontend)?;
-            let ingress = Ingress::for_pipeline(pipeline)?;
+            let ingress = if audit_logger.is_none() && response_tee.is_none() {
+                Ingress::for_pipeline(pipeline)?
+            } else {
+                let mut engine: ServiceEngine<
+                    SingleIn<PreprocessedRequest>,
+                    ManyOut<Annotated<BackendOutput>>,
+                > = pipeline;
+                if let Some(tee) = response_tee {
+                    let model_name = model.service_name().to_string();
+                    engine = ResponseTeeEngine::new(engine, tee, model_name);
+                }
+                if let Some(logger) = audit_logger {
+                    engine = AuditEngine::new(engine, logger, endpoint.path());
+                }
+                Ingress::for_engine(engine)?
+            };
 
-            model.attach(&endpoint, ModelType::Backend).await?;
-            let fut = endpoint.endpoint_builder().handler(ingress).start();
+            let model_type = if advert.draft {
+                ModelType::Draft
+            } else {
+                ModelType::Backend
+            };
+            model
+                .attach_with_lease(endpoint, model_type, lease_id)
+                .await?;
+            let fut = endpoint
+                .endpoint_builder()
+                .lease(lease)
+                .max_concurrent_requests(advert.max_concurrent_requests)
+                .pull_capacity(advert.pull_capacity)
+                .cost_per_gpu_hour(advert.cost_per_gpu_hour)
+                .description(advert.description)
+                .handler(ingress)
+                .start();
 
             (Box::pin(fut), Some(model.card().clone()))
         }
@@ -97,27 +347,11 @@ pub async fn run(
             // That means the vllm/sglang subprocess is doing all the work, we are idle.
             (never_ready(), None)
         }
-    };
-
-    tokio::select! {
-        _ = rt_fut => {
-            tracing::debug!("Endpoint ingress ended");
+        EngineConfig::Multi(_) => {
+            anyhow::bail!("in=dyn serves a single model, --models needs in=http or in=grpc");
         }
-        _ = cancel_token.cancelled() => {
-        }
-    }
-
-    // Cleanup on shutdown
-    if let Some(mut card) = card {
-        if let Err(err) = card
-            .delete_from_nats(distributed_runtime.nats_client())
-            .await
-        {
-            tracing::error!(%err, "delete_from_nats error on shutdown");
-        }
-    }
-
-    Ok(())
+    };
+    Ok(started)
 }
 
 fn never_ready() -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'static>> {
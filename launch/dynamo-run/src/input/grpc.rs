@@ -0,0 +1,33 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::input::common;
+use crate::{EngineConfig, Flags};
+use dynamo_llm::{grpc::service::GrpcService, request_template::RequestTemplate};
+use dynamo_runtime::Runtime;
+
+/// Build and run a gRPC service
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    engine_config: EngineConfig,
+    template: Option<RequestTemplate>,
+) -> anyhow::Result<()> {
+    let auth_keys = common::api_keys(&runtime, &flags).await?;
+    let grpc_service = GrpcService::builder()
+        .port(flags.grpc_port)
+        .with_request_template(template)
+        .with_nvext_policy(flags.nvext_policy())
+        .with_auth_keys(auth_keys)
+        .build()?;
+    common::register_engines(
+        &runtime,
+        grpc_service.manager_clone(),
+        engine_config,
+        &flags,
+    )
+    .await?;
+    grpc_service.run(runtime.primary_token()).await?;
+    runtime.shutdown(); // Cancel primary token
+    Ok(())
+}
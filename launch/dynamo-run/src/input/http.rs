@@ -2,26 +2,29 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::input::common;
 use crate::{EngineConfig, Flags};
-use dynamo_llm::kv_router::KvRouterConfig;
 use dynamo_llm::{
-    discovery::{ModelManager, ModelWatcher, MODEL_ROOT_PATH},
-    engines::StreamingEngineAdapter,
-    http::service::service_v2,
-    request_template::RequestTemplate,
-    types::{
-        openai::chat_completions::{
-            NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
-        },
-        openai::completions::{CompletionResponse, NvCreateCompletionRequest},
+    http::service::{
+        admin::AdminConfig,
+        admission::{self, AdmissionController},
+        auth::AuthKeys,
+        response_cache::ResponseCache,
+        service_v2,
+        tls::TlsConfig,
+        usage::{JsonlSink, NatsSink, UsageAccounting, UsageSink, WebhookSink},
     },
+    metrics_recorder,
+    request_template::RequestTemplate,
 };
-use dynamo_runtime::pipeline::RouterMode;
-use dynamo_runtime::transports::etcd;
 use dynamo_runtime::{DistributedRuntime, Runtime};
 
+/// Where the frontend serves its stats, see [`serve_admission_stats`]
+const FRONTEND_NAMESPACE: &str = "dynamo";
+const FRONTEND_COMPONENT: &str = "http";
+
 /// Build and run an HTTP service
 pub async fn run(
     runtime: Runtime,
@@ -29,61 +32,64 @@ pub async fn run(
     engine_config: EngineConfig,
     template: Option<RequestTemplate>,
 ) -> anyhow::Result<()> {
+    let cors = flags.cors()?;
+    let idempotency_ttl = (flags.http_idempotency_ttl_secs > 0)
+        .then(|| Duration::from_secs(flags.http_idempotency_ttl_secs));
+    let sse_keep_alive = (flags.http_sse_keep_alive_secs > 0)
+        .then(|| Duration::from_secs(flags.http_sse_keep_alive_secs));
+    let auth_keys = common::api_keys(&runtime, &flags).await?;
+    let admin = admin_config(&runtime, &flags, &engine_config).await?;
+    let response_cache = response_cache(&flags).await?;
+    let usage_accounting = usage_accounting(&runtime, &flags).await?;
+    let audit_logger = common::audit_logger(&runtime, &flags).await?;
+    let tls = flags
+        .http_tls_cert
+        .clone()
+        .zip(flags.http_tls_key.clone())
+        .map(|(cert, key)| TlsConfig::new(cert, key));
     let http_service = service_v2::HttpService::builder()
         .port(flags.http_port)
         .enable_chat_endpoints(true)
         .enable_cmpl_endpoints(true)
         .enable_embeddings_endpoints(true)
+        .enable_responses_endpoints(true)
+        .enable_ollama_endpoints(flags.http_ollama)
+        .enable_playground(flags.http_playground)
         .with_request_template(template)
+        .with_tls(tls)
+        .with_cors(cors)
+        .with_idempotency_ttl(idempotency_ttl)
+        .with_nvext_policy(flags.nvext_policy())
+        .with_sse_keep_alive(sse_keep_alive)
+        .with_auth_keys(auth_keys)
+        .with_rate_limits(flags.rate_limits()?)
+        .with_admission(flags.admission()?)
+        .with_timeouts(flags.http_timeouts())
+        .with_output_rate(flags.output_rate()?)
+        .max_body_bytes(flags.http_max_body_mib * 1024 * 1024)
+        .with_response_cache(response_cache)
+        .with_spillover(flags.spillover())
+        .with_admin(admin)
+        .with_usage_accounting(usage_accounting)
+        .with_audit_logger(audit_logger)
         .build()?;
-    match engine_config {
-        EngineConfig::Dynamic => {
-            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
-            match distributed_runtime.etcd_client() {
-                Some(etcd_client) => {
-                    // Listen for models registering themselves in etcd, add them to HTTP service
-                    run_watcher(
-                        distributed_runtime,
-                        http_service.state().manager_clone(),
-                        etcd_client.clone(),
-                        MODEL_ROOT_PATH,
-                        flags.router_mode.into(),
-                        Some(flags.kv_router_config()),
-                    )
-                    .await?;
-                }
-                None => {
-                    // Static endpoints don't need discovery
-                }
-            }
-        }
-        EngineConfig::StaticFull { engine, model } => {
-            let engine = Arc::new(StreamingEngineAdapter::new(engine));
-            let manager = http_service.model_manager();
-            manager.add_completions_model(model.service_name(), engine.clone())?;
-            manager.add_chat_completions_model(model.service_name(), engine)?;
-        }
-        EngineConfig::StaticCore {
-            engine: inner_engine,
-            model,
-        } => {
-            let manager = http_service.model_manager();
-
-            let chat_pipeline = common::build_pipeline::<
-                NvCreateChatCompletionRequest,
-                NvCreateChatCompletionStreamResponse,
-            >(model.card(), inner_engine.clone())
-            .await?;
-            manager.add_chat_completions_model(model.service_name(), chat_pipeline)?;
-
-            let cmpl_pipeline = common::build_pipeline::<
-                NvCreateCompletionRequest,
-                CompletionResponse,
-            >(model.card(), inner_engine)
-            .await?;
-            manager.add_completions_model(model.service_name(), cmpl_pipeline)?;
-        }
+    if let Some(controller) = http_service.admission() {
+        serve_admission_stats(&runtime, &engine_config, controller).await?;
+    }
+    if let Some(config) = flags.metrics_recorder() {
+        metrics_recorder::start(
+            http_service.metrics_registry().clone(),
+            config,
+            runtime.primary_token(),
+        )?;
     }
+    common::register_engines(
+        &runtime,
+        http_service.state().manager_clone(),
+        engine_config,
+        &flags,
+    )
+    .await?;
     tracing::debug!(
         "Supported routes: {:?}",
         http_service
@@ -97,22 +103,94 @@ pub async fn run(
     Ok(())
 }
 
-/// Spawns a task that watches for new models in etcd at network_prefix,
-/// and registers them with the ModelManager so that the HTTP service can use them.
-async fn run_watcher(
-    runtime: DistributedRuntime,
-    model_manager: Arc<ModelManager>,
-    etcd_client: etcd::Client,
-    network_prefix: &str,
-    router_mode: RouterMode,
-    kv_router_config: Option<KvRouterConfig>,
+/// The admin API, if admin keys were configured
+async fn admin_config(
+    runtime: &Runtime,
+    flags: &Flags,
+    engine_config: &EngineConfig,
+) -> anyhow::Result<Option<AdminConfig>> {
+    let Some(path) = flags.http_admin_keys_file.clone() else {
+        return Ok(None);
+    };
+    if !matches!(engine_config, EngineConfig::Dynamic) {
+        anyhow::bail!("--http-admin-keys-file requires out=dyn, there are no workers to manage");
+    }
+    let keys = AuthKeys::new();
+    keys.watch_file(path, runtime.primary_token())?;
+    let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+    Ok(Some(
+        AdminConfig::new(keys, distributed_runtime)
+            .with_router_mode(flags.router_mode.into())
+            .with_build_info(crate::build_info()),
+    ))
+}
+
+/// The response cache, if enabled
+async fn response_cache(flags: &Flags) -> anyhow::Result<Option<ResponseCache>> {
+    if flags.http_response_cache_ttl_secs == 0 {
+        return Ok(None);
+    }
+    let ttl = Duration::from_secs(flags.http_response_cache_ttl_secs);
+    let Some(url) = flags.http_response_cache_redis_url.as_deref() else {
+        return Ok(Some(ResponseCache::in_memory(
+            ttl,
+            flags.http_response_cache_max_entries,
+            flags.http_response_cache_max_mib * 1024 * 1024,
+        )));
+    };
+    #[cfg(feature = "redis")]
+    {
+        Ok(Some(ResponseCache::redis(url, ttl).await?))
+    }
+    #[cfg(not(feature = "redis"))]
+    {
+        let _ = url;
+        anyhow::bail!(
+            "--http-response-cache-redis-url requires dynamo-run built with the redis feature"
+        );
+    }
+}
+
+/// Report the admission queues in `scrape_stats` of the frontend component, with out=dyn
+async fn serve_admission_stats(
+    runtime: &Runtime,
+    engine_config: &EngineConfig,
+    controller: Arc<AdmissionController>,
 ) -> anyhow::Result<()> {
-    let watch_obj = ModelWatcher::new(runtime, model_manager, router_mode, kv_router_config);
-    tracing::info!("Watching for remote model at {network_prefix}");
-    let models_watcher = etcd_client.kv_get_and_watch_prefix(network_prefix).await?;
-    let (_prefix, _watcher, receiver) = models_watcher.dissolve();
-    let _watcher_task = tokio::spawn(async move {
-        watch_obj.watch(receiver).await;
+    if !matches!(engine_config, EngineConfig::Dynamic) {
+        return Ok(());
+    }
+    let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+    let component = distributed_runtime
+        .namespace(FRONTEND_NAMESPACE)?
+        .component(FRONTEND_COMPONENT)?;
+    tokio::spawn(async move {
+        if let Err(err) = admission::serve_stats(component, controller).await {
+            tracing::error!(%err, "Failed serving admission control stats");
+        }
     });
     Ok(())
 }
+
+/// Usage accounting, if a usage sink was configured
+async fn usage_accounting(
+    runtime: &Runtime,
+    flags: &Flags,
+) -> anyhow::Result<Option<Arc<UsageAccounting>>> {
+    let mut sinks: Vec<Arc<dyn UsageSink>> = Vec::new();
+    if let Some(path) = &flags.http_usage_log {
+        sinks.push(Arc::new(JsonlSink::new(path).await?));
+    }
+    if let Some(subject) = &flags.http_usage_nats_subject {
+        let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+        let client = distributed_runtime.nats_client().client().clone();
+        sinks.push(Arc::new(NatsSink::new(client, subject.clone())));
+    }
+    if let Some(url) = &flags.http_usage_webhook_url {
+        sinks.push(Arc::new(WebhookSink::new(url)?));
+    }
+    if sinks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(UsageAccounting::new(sinks)))
+}
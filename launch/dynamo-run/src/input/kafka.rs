@@ -0,0 +1,499 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `in=kafka://<brokers>/<topic>`: take chat completion requests from a Kafka topic and produce
+//! the responses to a reply topic, for asynchronous pipelines without an HTTP client.
+//!
+//! The value of a request message is an OpenAI chat completion request in JSON. Its
+//! `reply_topic` header names the topic of the response, `--kafka-reply-topic` otherwise. The
+//! response is one message with the key of the request and its `correlation_id` header: the
+//! whole chat completion, also for `"stream": true`, or `{"error": {...}}`.
+//!
+//! Like the HTTP service it can require an API key, sent as an `authorization: Bearer <key>`
+//! header, and applies the [`NvExtPolicy`] and the principal to `nvext`.
+//!
+//! Delivery is at least once. The offset of a request is committed once its response was
+//! delivered, or failed to be, and those of all the requests before it in its partition were
+//! too. A request whose process stopped before is run again, by this process or another of its
+//! consumer group.
+
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use dynamo_llm::http::service::auth::{AuthKeys, Principal};
+use dynamo_llm::protocols::openai::nvext::{NvExt, NvExtPolicy};
+use dynamo_llm::request_template::RequestTemplate;
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+    OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_runtime::{pipeline::Context, Runtime};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use tokio::task::JoinSet;
+
+use crate::input::common;
+use crate::opt::KAFKA_PREFIX;
+use crate::{EngineConfig, Flags};
+
+/// Header of a request with the topic to produce its response to
+pub const REPLY_TOPIC_HEADER: &str = "reply_topic";
+
+/// Header copied from a request to its response
+pub const CORRELATION_ID_HEADER: &str = "correlation_id";
+
+/// Header of a request with its API key, `Bearer <key>`
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// How long to try delivering a response before giving up on it
+const PRODUCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the requests come from: `kafka://broker1:9092,broker2:9092/requests`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaSource {
+    pub brokers: String,
+    pub topic: String,
+}
+
+impl FromStr for KafkaSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let Some((brokers, topic)) = s
+            .strip_prefix(KAFKA_PREFIX)
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            anyhow::bail!("Invalid Kafka source '{s}', expected {KAFKA_PREFIX}<brokers>/<topic>");
+        };
+        if brokers.is_empty() || topic.is_empty() || topic.contains('/') {
+            anyhow::bail!("Invalid Kafka source '{s}', expected {KAFKA_PREFIX}<brokers>/<topic>");
+        }
+        Ok(KafkaSource {
+            brokers: brokers.to_string(),
+            topic: topic.to_string(),
+        })
+    }
+}
+
+/// A message of the requests topic
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Position {
+    topic: String,
+    partition: i32,
+    offset: i64,
+}
+
+impl Position {
+    fn of(message: &OwnedMessage) -> Self {
+        Position {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PartitionOffsets {
+    in_flight: BTreeSet<i64>,
+    /// The highest offset that finished
+    finished: Option<i64>,
+    /// The last offset given to commit, or the first consumed
+    stored: Option<i64>,
+}
+
+/// The offsets that are safe to commit: below every request still running
+#[derive(Default)]
+struct OffsetTracker {
+    partitions: HashMap<(String, i32), PartitionOffsets>,
+}
+
+impl OffsetTracker {
+    fn start(&mut self, position: &Position) {
+        let partition = self
+            .partitions
+            .entry((position.topic.clone(), position.partition))
+            .or_default();
+        partition.in_flight.insert(position.offset);
+        // A partition is consumed in order, the first request is where it was committed
+        partition.stored.get_or_insert(position.offset);
+    }
+
+    /// The request at `position` is done. Returns the offset to commit in its partition, the
+    /// next one to consume, if it moved.
+    fn finish(&mut self, position: &Position) -> Option<i64> {
+        let partition = self
+            .partitions
+            .get_mut(&(position.topic.clone(), position.partition))?;
+        partition.in_flight.remove(&position.offset);
+        partition.finished = partition.finished.max(Some(position.offset));
+        let next = match partition.in_flight.first() {
+            Some(first) => *first,
+            None => partition.finished? + 1,
+        };
+        if partition.stored.is_some_and(|stored| stored >= next) {
+            return None;
+        }
+        partition.stored = Some(next);
+        Some(next)
+    }
+}
+
+/// What the HTTP service checks of a request before running it
+struct RequestPolicy {
+    nvext_policy: NvExtPolicy,
+    auth_keys: Option<Arc<AuthKeys>>,
+}
+
+impl RequestPolicy {
+    /// Check the request's API key if keys are required, and return whose it is
+    fn authorize(&self, message: &OwnedMessage) -> Result<Option<Principal>, String> {
+        let Some(keys) = &self.auth_keys else {
+            return Ok(None);
+        };
+        let Some(value) = header(message, AUTHORIZATION_HEADER) else {
+            return Err(format!(
+                "You didn't provide an API key. \
+                 Provide it in the {AUTHORIZATION_HEADER} header as 'Bearer <key>'."
+            ));
+        };
+        let key = std::str::from_utf8(value)
+            .ok()
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        match key {
+            Some(key) if keys.is_valid(key) => Ok(Some(Principal::from_key(key))),
+            Some(_) => Err("Incorrect API key provided.".to_string()),
+            None => Err(format!(
+                "Malformed {AUTHORIZATION_HEADER} header, expected 'Bearer <key>'."
+            )),
+        }
+    }
+
+    /// Validate `nvext` and set its principal, as the HTTP service does. Only the API key
+    /// decides the principal, whatever the request says.
+    fn apply(&self, principal: Option<Principal>, nvext: &mut Option<NvExt>) -> Result<(), String> {
+        if let Some(nvext) = nvext.as_mut() {
+            self.nvext_policy.apply(nvext)?;
+        }
+        match principal {
+            Some(Principal(principal)) => {
+                nvext.get_or_insert_with(NvExt::default).principal = Some(principal);
+            }
+            None => {
+                if let Some(nvext) = nvext.as_mut() {
+                    nvext.principal = None;
+                }
+            }
+        }
+        if let Some(nvext) = nvext.as_mut() {
+            self.nvext_policy.apply_pinning(nvext);
+            self.nvext_policy.apply_priority(nvext);
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    source: KafkaSource,
+    engine_config: EngineConfig,
+    template: Option<RequestTemplate>,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let policy = Arc::new(RequestPolicy {
+        nvext_policy: flags.nvext_policy(),
+        auth_keys: common::api_keys(&runtime, &flags).await?,
+    });
+    let prepared_engine = common::prepare_engine(runtime, engine_config).await?;
+    let engine = prepared_engine.engine;
+
+    let mut client_config = ClientConfig::new();
+    client_config.set("bootstrap.servers", &source.brokers);
+    for option in &flags.kafka_option {
+        let Some((key, value)) = option.split_once('=') else {
+            anyhow::bail!("Invalid --kafka-option '{option}', expected key=value");
+        };
+        client_config.set(key, value);
+    }
+    let consumer: StreamConsumer = client_config
+        .clone()
+        .set("group.id", &flags.kafka_group_id)
+        // Offsets are stored once their response was delivered, and committed in the background
+        .set("enable.auto.offset.store", "false")
+        .set("enable.auto.commit", "true")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .context("Failed creating the Kafka consumer")?;
+    consumer.subscribe(&[&source.topic])?;
+    let producer: FutureProducer = client_config
+        .set("enable.idempotence", "true")
+        .create()
+        .context("Failed creating the Kafka producer")?;
+    tracing::info!(
+        brokers = %source.brokers,
+        topic = %source.topic,
+        group = %flags.kafka_group_id,
+        "Consuming requests from Kafka"
+    );
+
+    let default_reply_topic = flags.kafka_reply_topic.map(Arc::<str>::from);
+    let template = template.map(Arc::new);
+    let max_in_flight = flags.kafka_max_in_flight as usize;
+    let mut offsets = OffsetTracker::default();
+    let mut in_flight = JoinSet::new();
+    let result = loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => {
+                break Ok(());
+            }
+            Some(done) = in_flight.join_next() => {
+                let position = match done {
+                    Ok(position) => position,
+                    Err(err) => break Err(err.into()),
+                };
+                if let Some(next) = offsets.finish(&position) {
+                    if let Err(err) = consumer.store_offset(&position.topic, position.partition, next) {
+                        // The partition was given to another consumer meanwhile
+                        tracing::debug!(%err, ?position, "Failed storing Kafka offset");
+                    }
+                }
+            }
+            message = consumer.recv(), if in_flight.len() < max_in_flight => {
+                let message = match message {
+                    Ok(message) => message.detach(),
+                    Err(err) => {
+                        tracing::warn!(%err, "Failed consuming from Kafka");
+                        continue;
+                    }
+                };
+                offsets.start(&Position::of(&message));
+                in_flight.spawn(handle(
+                    engine.clone(),
+                    producer.clone(),
+                    message,
+                    default_reply_topic.clone(),
+                    template.clone(),
+                    policy.clone(),
+                ));
+            }
+        }
+    };
+
+    // The requests still running are consumed again, their offsets weren't stored
+    in_flight.abort_all();
+    if let Err(err) = consumer.commit_consumer_state(CommitMode::Sync) {
+        tracing::warn!(%err, "Failed committing Kafka offsets");
+    }
+    result
+}
+
+/// Run the request of `message` and produce its response. A response that can't be delivered is
+/// logged and dropped, so that a request that can't be answered doesn't stop the consumer.
+async fn handle(
+    engine: OpenAIChatCompletionsStreamingEngine,
+    producer: FutureProducer,
+    message: OwnedMessage,
+    default_reply_topic: Option<Arc<str>>,
+    template: Option<Arc<RequestTemplate>>,
+    policy: Arc<RequestPolicy>,
+) -> Position {
+    let position = Position::of(&message);
+    let reply_topic = match header(&message, REPLY_TOPIC_HEADER) {
+        Some(topic) => String::from_utf8_lossy(topic).into_owned(),
+        None => match default_reply_topic {
+            Some(topic) => topic.to_string(),
+            None => {
+                tracing::warn!(?position, "Request without a reply topic, skipped");
+                return position;
+            }
+        },
+    };
+
+    let reply = match prepare(&message, &policy) {
+        Ok(request) => match complete(&engine, request, template.as_deref()).await {
+            Ok(response) => serde_json::to_vec(&response)
+                .unwrap_or_else(|err| error_reply(&err.to_string(), "server_error")),
+            Err(err) => {
+                tracing::warn!(?position, "Request failed: {err:#}");
+                error_reply(&format!("{err:#}"), "server_error")
+            }
+        },
+        Err((err, error_type)) => {
+            tracing::warn!(?position, %err, "Invalid request");
+            error_reply(&err, error_type)
+        }
+    };
+
+    let mut headers = OwnedHeaders::new();
+    if let Some(correlation_id) = header(&message, CORRELATION_ID_HEADER) {
+        headers = headers.insert(Header {
+            key: CORRELATION_ID_HEADER,
+            value: Some(correlation_id),
+        });
+    }
+    let mut record = FutureRecord::<[u8], Vec<u8>>::to(&reply_topic)
+        .payload(&reply)
+        .headers(headers);
+    if let Some(key) = message.key() {
+        record = record.key(key);
+    }
+    if let Err((err, _)) = producer.send(record, Timeout::After(PRODUCE_TIMEOUT)).await {
+        tracing::error!(?position, %reply_topic, %err, "Failed producing a response, dropped");
+    }
+    position
+}
+
+/// The request of `message`, checked like the HTTP service does. Otherwise the error message
+/// and type to reply with.
+fn prepare(
+    message: &OwnedMessage,
+    policy: &RequestPolicy,
+) -> Result<NvCreateChatCompletionRequest, (String, &'static str)> {
+    let principal = policy
+        .authorize(message)
+        .map_err(|err| (err, "authentication_error"))?;
+    let payload = message.payload().unwrap_or_default();
+    let mut request =
+        serde_json::from_slice::<NvCreateChatCompletionRequest>(payload).map_err(|err| {
+            (
+                format!("Invalid chat completion request: {err}"),
+                "invalid_request_error",
+            )
+        })?;
+    policy
+        .apply(principal, &mut request.nvext)
+        .map_err(|err| (err, "invalid_request_error"))?;
+    Ok(request)
+}
+
+async fn complete(
+    engine: &OpenAIChatCompletionsStreamingEngine,
+    mut request: NvCreateChatCompletionRequest,
+    template: Option<&RequestTemplate>,
+) -> anyhow::Result<NvCreateChatCompletionResponse> {
+    if let Some(template) = template {
+        if request.inner.temperature.unwrap_or(0.0) == 0.0 {
+            request.inner.temperature = Some(template.temperature);
+        }
+        if request.inner.max_completion_tokens.unwrap_or(0) == 0 {
+            request.inner.max_completion_tokens = Some(template.max_completion_tokens);
+        }
+    }
+    let stream = engine.generate(Context::new(request)).await?;
+    NvCreateChatCompletionResponse::from_annotated_stream(stream.into())
+        .await
+        .map_err(|err| anyhow::anyhow!(err))
+}
+
+fn error_reply(message: &str, error_type: &str) -> Vec<u8> {
+    serde_json::json!({
+        "error": {"message": message, "type": error_type}
+    })
+    .to_string()
+    .into_bytes()
+}
+
+fn header<'a>(message: &'a OwnedMessage, name: &str) -> Option<&'a [u8]> {
+    message
+        .headers()?
+        .iter()
+        .find(|header| header.key == name)?
+        .value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(offset: i64) -> Position {
+        Position {
+            topic: "requests".to_string(),
+            partition: 0,
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_kafka_source() {
+        let source: KafkaSource = "kafka://b1:9092,b2:9092/requests".parse().unwrap();
+        assert_eq!(source.brokers, "b1:9092,b2:9092");
+        assert_eq!(source.topic, "requests");
+        assert!("kafka://b1:9092".parse::<KafkaSource>().is_err());
+        assert!("kafka:///requests".parse::<KafkaSource>().is_err());
+    }
+
+    fn message(headers: &[(&str, &str)], payload: &str) -> OwnedMessage {
+        let headers = headers
+            .iter()
+            .fold(OwnedHeaders::new(), |headers, &(key, value)| {
+                headers.insert(Header {
+                    key,
+                    value: Some(value),
+                })
+            });
+        OwnedMessage::new(
+            Some(payload.as_bytes().to_vec()),
+            None,
+            "requests".to_string(),
+            rdkafka::message::Timestamp::NotAvailable,
+            0,
+            0,
+            Some(headers),
+        )
+    }
+
+    #[test]
+    fn test_prepare() {
+        let body = r#"{"model": "m", "messages": [], "nvext": {"principal": "someone-else"}}"#;
+        let policy = RequestPolicy {
+            nvext_policy: NvExtPolicy::default(),
+            auth_keys: Some(AuthKeys::from_keys(["sk-one"])),
+        };
+        let (_, error_type) = prepare(&message(&[], body), &policy).unwrap_err();
+        assert_eq!(error_type, "authentication_error");
+        let wrong = message(&[(AUTHORIZATION_HEADER, "Bearer sk-two")], body);
+        assert!(prepare(&wrong, &policy).is_err());
+
+        let right = message(&[(AUTHORIZATION_HEADER, "Bearer sk-one")], body);
+        let request = prepare(&right, &policy).unwrap();
+        let principal = request.nvext.and_then(|nvext| nvext.principal);
+        assert_eq!(principal, Some(Principal::from_key("sk-one").0));
+
+        // Without keys nobody is a principal
+        let policy = RequestPolicy {
+            nvext_policy: NvExtPolicy::default(),
+            auth_keys: None,
+        };
+        let request = prepare(&message(&[], body), &policy).unwrap();
+        assert_eq!(request.nvext.and_then(|nvext| nvext.principal), None);
+    }
+
+    #[test]
+    fn test_offset_tracker() {
+        let mut offsets = OffsetTracker::default();
+        for offset in 5..9 {
+            offsets.start(&at(offset));
+        }
+        // 5 is still running
+        assert_eq!(offsets.finish(&at(6)), None);
+        assert_eq!(offsets.finish(&at(8)), None);
+        assert_eq!(offsets.finish(&at(5)), Some(7));
+        assert_eq!(offsets.finish(&at(7)), Some(9));
+
+        // Another partition is tracked on its own
+        let other = Position {
+            partition: 1,
+            ..at(2)
+        };
+        offsets.start(&other);
+        assert_eq!(offsets.finish(&other), Some(3));
+    }
+}
@@ -7,24 +7,33 @@ use dynamo_llm::types::openai::chat_completions::{
 };
 use dynamo_runtime::{pipeline::Context, runtime::CancellationToken, Runtime};
 use futures::StreamExt;
-use std::io::{ErrorKind, Write};
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::time::Instant;
 
 use crate::input::common;
 use crate::{EngineConfig, Flags, RequestTemplate};
 
+mod markdown;
+
+use markdown::MarkdownRenderer;
+
 /// Max response tokens for each single query. Must be less than model context size.
 /// TODO: Cmd line flag to overwrite this
 const MAX_TOKENS: u32 = 8192;
 
 pub async fn run(
     runtime: Runtime,
-    _flags: Flags,
+    flags: Flags,
     single_prompt: Option<String>,
     engine_config: EngineConfig,
     template: Option<RequestTemplate>,
 ) -> anyhow::Result<()> {
     let cancel_token = runtime.primary_token();
     let prepared_engine = common::prepare_engine(runtime, engine_config).await?;
+    let output = OutputOptions {
+        markdown: !flags.text_raw && std::io::stdout().is_terminal(),
+        stats: !flags.text_no_stats,
+    };
     main_loop(
         cancel_token,
         &prepared_engine.service_name,
@@ -32,10 +41,65 @@ pub async fn run(
         single_prompt,
         prepared_engine.inspect_template,
         template,
+        output,
     )
     .await
 }
 
+/// How the answers are printed
+struct OutputOptions {
+    /// Render the markdown instead of printing the raw text
+    markdown: bool,
+
+    /// Print timing stats after each answer
+    stats: bool,
+}
+
+/// Timing of one answer
+struct TurnStats {
+    start: Instant,
+    first_token: Option<Instant>,
+    last_token: Option<Instant>,
+    tokens: usize,
+}
+
+impl TurnStats {
+    fn start() -> Self {
+        TurnStats {
+            start: Instant::now(),
+            first_token: None,
+            last_token: None,
+            tokens: 0,
+        }
+    }
+
+    /// Text arrived, `tokens` were generated so far
+    fn record(&mut self, tokens: usize) {
+        let now = Instant::now();
+        self.first_token.get_or_insert(now);
+        self.last_token = Some(now);
+        self.tokens = tokens;
+    }
+
+    fn summary(&self) -> Option<String> {
+        let (first_token, last_token) = (self.first_token?, self.last_token?);
+        let mut summary = format!(
+            "{} tokens, time to first token {} ms",
+            self.tokens,
+            (first_token - self.start).as_millis()
+        );
+        // The rate after the first token, which also waited for the prompt
+        let decode = last_token - first_token;
+        if self.tokens > 1 && !decode.is_zero() {
+            summary += &format!(
+                ", {:.1} tokens/s",
+                (self.tokens - 1) as f64 / decode.as_secs_f64()
+            );
+        }
+        Some(summary)
+    }
+}
+
 async fn main_loop(
     cancel_token: CancellationToken,
     service_name: &str,
@@ -43,6 +107,7 @@ async fn main_loop(
     mut initial_prompt: Option<String>,
     _inspect_template: bool,
     template: Option<RequestTemplate>,
+    output: OutputOptions,
 ) -> anyhow::Result<()> {
     if initial_prompt.is_none() {
         tracing::info!("Ctrl-c to exit");
@@ -122,6 +187,7 @@ async fn main_loop(
         };
 
         // Call the model
+        let mut stats = TurnStats::start();
         let mut stream = match engine.generate(Context::new(req)).await {
             Ok(stream) => stream,
             Err(err) => {
@@ -132,6 +198,7 @@ async fn main_loop(
 
         // Stream the output to stdout
         let mut stdout = std::io::stdout();
+        let mut renderer = output.markdown.then(MarkdownRenderer::default);
         let mut assistant_message = String::new();
         while let Some(item) = stream.next().await {
             if cancel_token.is_cancelled() {
@@ -143,7 +210,20 @@ async fn main_loop(
                     let entry = data.inner.choices.first();
                     let chat_comp = entry.as_ref().unwrap();
                     if let Some(c) = &chat_comp.delta.content {
-                        let _ = stdout.write(c.as_bytes());
+                        let tokens = data
+                            .inner
+                            .usage
+                            .as_ref()
+                            .map_or(stats.tokens + 1, |u| u.completion_tokens as usize);
+                        stats.record(tokens);
+                        match renderer.as_mut() {
+                            Some(renderer) => {
+                                let _ = stdout.write(renderer.push(c).as_bytes());
+                            }
+                            None => {
+                                let _ = stdout.write(c.as_bytes());
+                            }
+                        }
                         let _ = stdout.flush();
                         assistant_message += c;
                     }
@@ -166,7 +246,20 @@ async fn main_loop(
                 }
             }
         }
+        if let Some(renderer) = renderer.as_mut() {
+            let _ = stdout.write(renderer.finish().as_bytes());
+        }
         println!();
+        if output.stats {
+            if let Some(summary) = stats.summary() {
+                // On stderr, so that it stays out of piped answers
+                if std::io::stderr().is_terminal() {
+                    eprintln!("\x1b[2m{summary}\x1b[0m");
+                } else {
+                    eprintln!("{summary}");
+                }
+            }
+        }
 
         let assistant_content =
             async_openai::types::ChatCompletionRequestAssistantMessageContent::Text(
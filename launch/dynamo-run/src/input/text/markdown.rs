@@ -0,0 +1,296 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Render the markdown of an answer for the terminal while it streams in.
+//!
+//! Text is printed as soon as it is known how it looks. Only the start of a line is held back,
+//! until it is clear whether it is a header, a list item or a code fence, and code blocks, which
+//! are syntax highlighted a line at a time. Headers, list bullets, `**bold**` and `inline code`
+//! are styled, anything else is printed as is.
+
+use std::sync::LazyLock;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const HEADER: &str = "\x1b[1;35m";
+const INLINE_CODE: &str = "\x1b[36m";
+const BULLET: &str = "\x1b[33m";
+const FENCE: &str = "\x1b[2m";
+
+const THEME: &str = "base16-ocean.dark";
+
+/// Longest list number we wait for, `123456789.`
+const MAX_LIST_NUMBER_DIGITS: usize = 9;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+pub struct MarkdownRenderer {
+    /// The start of the current line while it is held back, or the current line of code
+    line: String,
+
+    /// Whether the start of the current line is held back
+    at_line_start: bool,
+
+    /// Set inside a code block
+    code: Option<HighlightLines<'static>>,
+
+    header: bool,
+    bold: bool,
+    inline_code: bool,
+
+    /// A `*` that could be the start of `**`
+    star: bool,
+}
+
+impl Default for MarkdownRenderer {
+    fn default() -> Self {
+        MarkdownRenderer {
+            line: String::new(),
+            at_line_start: true,
+            code: None,
+            header: false,
+            bold: false,
+            inline_code: false,
+            star: false,
+        }
+    }
+}
+
+impl MarkdownRenderer {
+    /// Take the next piece of the answer, returns what to print
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        for c in text.chars() {
+            if self.at_line_start || self.code.is_some() {
+                self.line.push(c);
+                if c == '\n' {
+                    self.end_held_line(&mut out);
+                } else if self.code.is_none() {
+                    self.classify_line(&mut out);
+                }
+            } else {
+                self.inline(c, &mut out);
+            }
+        }
+        out
+    }
+
+    /// The answer is complete, returns what is left to print
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if !self.line.is_empty() {
+            if self.code.is_some() {
+                self.highlight_line(&mut out);
+            } else {
+                self.inline_str(&std::mem::take(&mut self.line), &mut out);
+            }
+        }
+        if self.star {
+            out.push('*');
+        }
+        out.push_str(RESET);
+        *self = MarkdownRenderer::default();
+        out
+    }
+
+    /// Decide what kind of line the held back start is, if it is known yet
+    fn classify_line(&mut self, out: &mut String) {
+        let trimmed = self.line.trim_start();
+        let indent = &self.line[..self.line.len() - trimmed.len()];
+        if trimmed.is_empty() || "```".starts_with(trimmed) || trimmed.starts_with("```") {
+            // Code fences are handled when the line is complete
+            return;
+        }
+
+        let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+        let digits = trimmed.len()
+            - trimmed
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let (prefix, rest) = if hashes > 0 {
+            match trimmed[hashes..].chars().next() {
+                None => return,
+                Some(' ') => {
+                    self.header = true;
+                    (HEADER.to_string(), trimmed[hashes + 1..].to_string())
+                }
+                Some(_) => (String::new(), self.line.clone()),
+            }
+        } else if matches!(trimmed.chars().next(), Some('-' | '*' | '+')) {
+            match trimmed[1..].chars().next() {
+                None => return,
+                Some(' ') => (
+                    format!("{indent}{BULLET}•{RESET} "),
+                    trimmed[2..].to_string(),
+                ),
+                Some(_) => (String::new(), self.line.clone()),
+            }
+        } else if digits > 0 && digits <= MAX_LIST_NUMBER_DIGITS {
+            match (
+                trimmed[digits..].chars().next(),
+                trimmed[digits..].chars().nth(1),
+            ) {
+                (None, _) | (Some('.'), None) => return,
+                (Some('.'), Some(' ')) => (
+                    format!("{indent}{BULLET}{}{RESET} ", &trimmed[..digits + 1]),
+                    trimmed[digits + 2..].to_string(),
+                ),
+                _ => (String::new(), self.line.clone()),
+            }
+        } else {
+            (String::new(), self.line.clone())
+        };
+
+        self.line.clear();
+        self.at_line_start = false;
+        out.push_str(&prefix);
+        self.inline_str(&rest, out);
+    }
+
+    /// A complete line that was held back: a code fence, a line of code, or one too short to
+    /// classify
+    fn end_held_line(&mut self, out: &mut String) {
+        let trimmed = self.line.trim_start();
+        if let Some(language) = trimmed.strip_prefix("```") {
+            if self.code.is_some() {
+                self.code = None;
+            } else {
+                let syntax = SYNTAXES
+                    .find_syntax_by_token(language.trim())
+                    .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+                self.code = Some(HighlightLines::new(syntax, &THEMES.themes[THEME]));
+            }
+            out.push_str(FENCE);
+            out.push_str(self.line.trim_end());
+            out.push_str(RESET);
+            out.push('\n');
+            self.line.clear();
+        } else if self.code.is_some() {
+            self.highlight_line(out);
+        } else {
+            let line = std::mem::take(&mut self.line);
+            self.inline_str(&line, out);
+        }
+    }
+
+    fn highlight_line(&mut self, out: &mut String) {
+        let line = std::mem::take(&mut self.line);
+        let Some(highlighter) = self.code.as_mut() else {
+            return;
+        };
+        match highlighter.highlight_line(&line, &SYNTAXES) {
+            Ok(ranges) => {
+                out.push_str(&as_24_bit_terminal_escaped(&ranges, false));
+                out.push_str(RESET);
+            }
+            Err(err) => {
+                tracing::debug!(%err, "Syntax highlighting failed");
+                out.push_str(&line);
+            }
+        }
+    }
+
+    fn inline_str(&mut self, text: &str, out: &mut String) {
+        for c in text.chars() {
+            self.inline(c, out);
+        }
+    }
+
+    /// Bold and inline code in a line that isn't held back
+    fn inline(&mut self, c: char, out: &mut String) {
+        if self.star {
+            self.star = false;
+            if c == '*' {
+                self.bold = !self.bold;
+                self.restyle(out);
+                return;
+            }
+            out.push('*');
+        }
+        match c {
+            '*' if !self.inline_code => self.star = true,
+            '`' => {
+                self.inline_code = !self.inline_code;
+                self.restyle(out);
+            }
+            '\n' => {
+                // Unclosed styles end with the line
+                self.header = false;
+                self.bold = false;
+                self.inline_code = false;
+                out.push_str(RESET);
+                out.push('\n');
+                self.at_line_start = true;
+            }
+            c => out.push(c),
+        }
+    }
+
+    /// Switch to the styles that are on
+    fn restyle(&self, out: &mut String) {
+        out.push_str(RESET);
+        if self.header {
+            out.push_str(HEADER);
+        }
+        if self.bold {
+            out.push_str(BOLD);
+        }
+        if self.inline_code {
+            out.push_str(INLINE_CODE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_styles(s: &str) -> String {
+        regex::Regex::new("\x1b\\[[0-9;]*m")
+            .unwrap()
+            .replace_all(s, "")
+            .to_string()
+    }
+
+    /// Render `text` streamed a character at a time, and in one piece
+    fn render(text: &str) -> String {
+        let mut renderer = MarkdownRenderer::default();
+        let mut streamed = String::new();
+        for c in text.chars() {
+            streamed.push_str(&renderer.push(&c.to_string()));
+        }
+        streamed.push_str(&renderer.finish());
+
+        let mut whole = renderer.push(text);
+        whole.push_str(&renderer.finish());
+        assert_eq!(streamed, whole);
+        streamed
+    }
+
+    #[test]
+    fn test_markdown() {
+        let rendered = render("## Title\n- one **bold** word\n2. `code` *not bold*\n#hashtag\n");
+        assert_eq!(
+            strip_styles(&rendered),
+            "Title\n• one bold word\n2. code *not bold*\n#hashtag\n"
+        );
+        assert!(rendered.contains(&format!("{HEADER}Title")));
+        assert!(rendered.contains(&format!("{BOLD}bold{RESET}")));
+        assert!(rendered.contains(&format!("{INLINE_CODE}code{RESET}")));
+    }
+
+    #[test]
+    fn test_code_block() {
+        let text = "Example:\n```rust\nfn main() {\n    let x = 1 * 2;\n}\n```\nDone";
+        let rendered = render(text);
+        // Code keeps its stars and the fences are shown
+        assert_eq!(strip_styles(&rendered), text);
+        assert!(rendered.contains("\x1b[38;2;"));
+    }
+}
@@ -5,23 +5,33 @@ use std::{future::Future, pin::Pin};
 use std::{io::Read, sync::Arc, time::Duration};
 
 use anyhow::Context;
+use dynamo_llm::build_info::BuildInfo;
+use dynamo_llm::http::service::metrics;
+use dynamo_llm::model_card::model::EngineCapabilities;
 use dynamo_llm::{backend::ExecutionContext, engines::StreamingEngine, local_model::LocalModel};
+use dynamo_runtime::distributed::DistributedConfig;
+use dynamo_runtime::error_reporting::{self, ReportKind};
+use dynamo_runtime::fault_injection;
+use dynamo_runtime::observability;
 use dynamo_runtime::protocols::Endpoint as EndpointId;
 use dynamo_runtime::slug::Slug;
 use dynamo_runtime::{CancellationToken, DistributedRuntime};
 
 mod flags;
 pub use flags::Flags;
+mod hardware;
 mod input;
+mod manifest;
+pub mod metrics_export;
+mod model_loader;
 mod opt;
 pub use dynamo_llm::request_template::RequestTemplate;
 pub use opt::{Input, Output};
 mod subprocess;
-
-const CHILD_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+mod timeouts;
 
 /// Default size of a KV cache block. Override with --kv-cache-block-size
-const DEFAULT_KV_CACHE_BLOCK_SIZE: usize = 16;
+pub(crate) const DEFAULT_KV_CACHE_BLOCK_SIZE: usize = 16;
 
 pub enum EngineConfig {
     /// Remote networked engines
@@ -38,6 +48,9 @@ pub enum EngineConfig {
         engine: ExecutionContext,
         model: Box<LocalModel>,
     },
+
+    /// Several models, each with its own engine. From `--models`.
+    Multi(Vec<EngineConfig>),
 }
 
 fn is_in_dynamic(in_opt: &Input) -> bool {
@@ -48,6 +61,29 @@ fn is_out_dynamic(out_opt: &Option<Output>) -> bool {
     matches!(out_opt, Some(Output::Dynamic))
 }
 
+/// What this binary is, for `--version --verbose` and `/admin/build-info`
+pub fn build_info() -> BuildInfo {
+    BuildInfo::new(env!("CARGO_PKG_VERSION"), option_env!("DYNAMO_GIT_SHA"))
+        .with_features(&[
+            ("mistralrs", cfg!(feature = "mistralrs")),
+            ("llamacpp", cfg!(feature = "llamacpp")),
+            ("cuda", cfg!(feature = "cuda")),
+            ("metal", cfg!(feature = "metal")),
+            ("vulkan", cfg!(feature = "vulkan")),
+            ("openmp", cfg!(feature = "openmp")),
+            ("redis", cfg!(feature = "redis")),
+            ("kafka", cfg!(feature = "kafka")),
+            ("parquet", cfg!(feature = "parquet")),
+            ("fault-injection", cfg!(feature = "fault-injection")),
+        ])
+        .with_engine_script("sglang", subprocess::sglang::PY)
+        .with_engine_script("vllm", subprocess::vllm::PY)
+        .with_engine_script("trtllm", subprocess::trtllm::PY)
+}
+
+/// A future the main thread waits on before exiting, e.g. to stop an engine sub-process
+type Extra = Pin<Box<dyn Future<Output = ()> + Send>>;
+
 pub async fn run(
     runtime: dynamo_runtime::Runtime,
     in_opt: Input,
@@ -57,14 +93,171 @@ pub async fn run(
     if is_in_dynamic(&in_opt) && is_out_dynamic(&out_opt) {
         anyhow::bail!("Cannot use endpoint for both in and out");
     }
+    start_metrics(&runtime, &flags);
+    if let Some(path) = flags.models.clone() {
+        return run_manifest(runtime, in_opt, out_opt, flags, &path).await;
+    }
 
     let cancel_token = runtime.primary_token();
+    let local_model = prepare_model(&flags, is_out_dynamic(&out_opt)).await?;
+    let template = request_template(&flags)?;
+
+    // We may need it later
+    let card = local_model.card().clone();
+
+    let out_opt = out_opt.unwrap_or_else(|| default_engine(&local_model));
+    print_cuda(&out_opt);
+
+    // Sanity check before we start filling in defaults
+    if matches!(out_opt, Output::Dynamic) {
+        if flags.context_length.is_some() {
+            anyhow::bail!("'--content-length' flag should only be used on the worker node, not on the ingress");
+        }
+        if flags.kv_cache_block_size.is_some() {
+            anyhow::bail!("'--kv-cache-block-size' flag should only be used on the worker node, not on the ingress");
+        }
+    }
+
+    let mut flags = flags;
+    let (engine_config, extra) =
+        start_engine(out_opt, local_model, &in_opt, &mut flags, &cancel_token).await?;
+
+    match in_opt {
+        Input::Http => {
+            crate::input::http::run(runtime.clone(), flags, engine_config, template).await?;
+        }
+        Input::Grpc => {
+            crate::input::grpc::run(runtime.clone(), flags, engine_config, template).await?;
+        }
+        Input::Text => {
+            crate::input::text::run(runtime.clone(), flags, None, engine_config, template).await?;
+        }
+        Input::Stdin => {
+            let mut prompt = String::new();
+            std::io::stdin().read_to_string(&mut prompt).unwrap();
+            crate::input::text::run(
+                runtime.clone(),
+                flags,
+                Some(prompt),
+                engine_config,
+                template,
+            )
+            .await?;
+        }
+        Input::Batch(path) => {
+            crate::input::batch::run(runtime.clone(), flags, card, path, engine_config, template)
+                .await?;
+        }
+        Input::Endpoint(path) => {
+            let mut config = DistributedConfig::from_settings(false);
+            config.standby |= flags.standby;
+            let distributed_runtime = DistributedRuntime::new(runtime.clone(), config).await?;
+            crate::input::endpoint::run(distributed_runtime, path, engine_config, out_opt, flags)
+                .await?;
+        }
+        #[cfg(feature = "kafka")]
+        Input::Kafka(source) => {
+            crate::input::kafka::run(runtime.clone(), flags, source, engine_config, template)
+                .await?;
+        }
+    }
+
+    // Allow engines to ask main thread to wait on an extra future.
+    // We use this to stop the vllm and sglang sub-process
+    if let Some(extra) = extra {
+        extra.await;
+    }
+
+    Ok(())
+}
+
+/// Sample the tokio and process metrics, and serve them on `--metrics-port` if given
+fn start_metrics(runtime: &dynamo_runtime::Runtime, flags: &Flags) {
+    observability::start(runtime);
+    let Some(port) = flags.metrics_port else {
+        return;
+    };
+    let registry = metrics::Registry::new();
+    if let Err(err) = observability::register_metrics(&registry) {
+        tracing::error!(%err, "Failed registering process metrics");
+        return;
+    }
+    runtime.tasks().spawn(
+        "metrics",
+        metrics::serve(registry, "0.0.0.0", port, runtime.child_token()),
+    );
+}
+
+/// Serve every model of the manifest at `path` from this process, each with its own engine
+async fn run_manifest(
+    runtime: dynamo_runtime::Runtime,
+    in_opt: Input,
+    out_opt: Option<Output>,
+    flags: Flags,
+    path: &std::path::Path,
+) -> anyhow::Result<()> {
+    if out_opt.is_some() || flags.model_path_pos.is_some() || flags.model_path_flag.is_some() {
+        anyhow::bail!("--models replaces out= and the model path, the manifest sets them");
+    }
+    if !matches!(in_opt, Input::Http | Input::Grpc) {
+        anyhow::bail!("--models needs in=http or in=grpc, in={in_opt} serves a single model");
+    }
+    let manifest = manifest::Manifest::load(path)?;
+    let cancel_token = runtime.primary_token();
+    let template = request_template(&flags)?;
+
+    let mut engine_configs = Vec::with_capacity(manifest.models.len());
+    let mut extras = Vec::new();
+    for model in &manifest.models {
+        let name = model.display_name();
+        let mut model_flags = model.flags(&flags);
+        let local_model = prepare_model(&model_flags, false)
+            .await
+            .with_context(|| format!("Failed preparing model '{name}'"))?;
+        let out_opt = match model.engine()? {
+            Some(out_opt) => out_opt,
+            None => default_engine(&local_model),
+        };
+        print_cuda(&out_opt);
+        tracing::info!(model = name, engine = %out_opt, "Starting model from manifest");
+        let (engine_config, extra) = start_engine(
+            out_opt,
+            local_model,
+            &in_opt,
+            &mut model_flags,
+            &cancel_token,
+        )
+        .await
+        .with_context(|| format!("Failed starting model '{name}'"))?;
+        engine_configs.push(engine_config);
+        extras.extend(extra);
+    }
+    let engine_config = EngineConfig::Multi(engine_configs);
+
+    match in_opt {
+        Input::Http => {
+            crate::input::http::run(runtime.clone(), flags, engine_config, template).await?;
+        }
+        Input::Grpc => {
+            crate::input::grpc::run(runtime.clone(), flags, engine_config, template).await?;
+        }
+        _ => unreachable!("Checked above"),
+    }
+
+    // Stop the engine sub-processes
+    futures::future::join_all(extras).await;
+    Ok(())
+}
+
+/// The model of `flags`, with the flags that override its card applied. An ingress with
+/// `out=dyn` (`dynamic`) has no local model.
+async fn prepare_model(flags: &Flags, dynamic: bool) -> anyhow::Result<LocalModel> {
     let maybe_path = flags
         .model_path_pos
         .clone()
         .or(flags.model_path_flag.clone());
 
-    let mut local_model: LocalModel = if is_out_dynamic(&out_opt) {
+    let mut local_model: LocalModel = if dynamic {
         // If output is dynamic we are ingress and don't have a local model, but making an
         // empty one cleans up the code.
         Default::default()
@@ -93,73 +286,105 @@ pub async fn run(
     if let Some(context_length) = flags.context_length {
         local_model.set_context_length(context_length);
     }
-    // Always set, there is no engine provided default
-    local_model.set_kv_cache_block_size(
-        flags
-            .kv_cache_block_size
-            .unwrap_or(DEFAULT_KV_CACHE_BLOCK_SIZE),
-    );
+    let generation_limits = flags.generation_limits();
+    if !generation_limits.is_empty() {
+        local_model.set_generation_limits(generation_limits);
+    }
+    let special_tokens = flags.special_tokens();
+    if !special_tokens.is_empty() {
+        local_model.set_special_tokens(special_tokens);
+    }
+    let sampling_presets = flags.sampling_presets()?;
+    if !sampling_presets.is_empty() {
+        local_model.set_sampling_presets(sampling_presets);
+    }
+    let prompt_compression = flags.prompt_compression();
+    if !prompt_compression.is_empty() {
+        local_model.set_prompt_compression(prompt_compression);
+    }
+    Ok(local_model)
+}
 
-    let mut extra: Option<Pin<Box<dyn Future<Output = ()> + Send>>> = None; // vllm and sglang sub-process
+fn request_template(flags: &Flags) -> anyhow::Result<Option<RequestTemplate>> {
+    let Some(path) = flags.request_template.as_ref() else {
+        return Ok(None);
+    };
+    let template = RequestTemplate::load(path)?;
+    tracing::debug!("Using request template: {template:?}");
+    Ok(Some(template))
+}
 
-    let template = if let Some(path) = flags.request_template.as_ref() {
-        let template = RequestTemplate::load(path)?;
-        tracing::debug!("Using request template: {template:?}");
-        Some(template)
+/// The engine for a model when `out=` isn't given
+fn default_engine(local_model: &LocalModel) -> Output {
+    let default_engine = if local_model.card().is_gguf() {
+        gguf_default()
     } else {
-        None
+        safetensors_default()
     };
+    tracing::info!(
+        "Using default engine: {default_engine}. Use out=<engine> to specify one of {}",
+        Output::available_engines().join(", ")
+    );
+    default_engine
+}
 
-    // We may need it later
-    let card = local_model.card().clone();
+/// Start the engine matching `out_opt` for `local_model`. Fills in the `flags` the user omitted
+/// based on the engine and the hardware. Engines in a sub-process come with the future that
+/// stops it.
+async fn start_engine(
+    out_opt: Output,
+    mut local_model: LocalModel,
+    in_opt: &Input,
+    flags: &mut Flags,
+    cancel_token: &CancellationToken,
+) -> anyhow::Result<(EngineConfig, Option<Extra>)> {
+    if flags.disagg_role != flags::DisaggRole::Aggregated
+        && !(matches!(out_opt, Output::Vllm) && is_in_dynamic(in_opt))
+    {
+        anyhow::bail!("--disagg-role is only supported with in=dyn://.. out=vllm");
+    }
+    if flags.draft_model_path.is_some() && !is_in_dynamic(in_opt) {
+        anyhow::bail!("--draft-model-path is only supported with in=dyn://..");
+    }
+    if flags.enable_lora
+        && !(matches!(out_opt, Output::Vllm | Output::SgLang) && is_in_dynamic(in_opt))
+    {
+        anyhow::bail!("--enable-lora is only supported with in=dyn://.. out=vllm or out=sglang");
+    }
 
-    let out_opt = out_opt.unwrap_or_else(|| {
-        let default_engine = if card.is_gguf() {
-            gguf_default()
-        } else {
-            safetensors_default()
-        };
+    // Fill in the flags the user omitted based on the engine and the hardware
+    let gpus = hardware::detect_gpus();
+    let inferred = hardware::infer_defaults(flags, &out_opt, gpus.as_ref());
+    if !inferred.is_empty() {
         tracing::info!(
-            "Using default engine: {default_engine}. Use out=<engine> to specify one of {}",
-            Output::available_engines().join(", ")
+            ?gpus,
+            tensor_parallel_size = ?inferred.tensor_parallel_size,
+            kv_cache_block_size = ?inferred.kv_cache_block_size,
+            max_num_batched_tokens = ?inferred.max_num_batched_tokens,
+            "Inferred defaults for flags not provided"
         );
-        default_engine
-    });
-    print_cuda(&out_opt);
+        local_model.set_inferred_defaults(inferred.as_map());
+    }
+    // So the ingress removes what the engine can't do from requests
+    local_model.set_engine_capabilities(EngineCapabilities::for_engine(&out_opt.to_string()));
+    // Always set, there is no engine provided default
+    local_model.set_kv_cache_block_size(
+        flags
+            .kv_cache_block_size
+            .unwrap_or(DEFAULT_KV_CACHE_BLOCK_SIZE),
+    );
+
+    if let Some(draft_model_path) = flags.draft_model_path.clone() {
+        let engine_config =
+            draft_engine(out_opt, local_model, &draft_model_path, cancel_token).await?;
+        return Ok((engine_config, None));
+    }
+
+    let mut extra: Option<Extra> = None; // vllm and sglang sub-process
 
     // Create the engine matching `out`
     let engine_config = match out_opt {
-        Output::Dynamic => {
-            // Sanity check - TODO probably make a general sanity check at start of method
-            if flags.context_length.is_some() {
-                anyhow::bail!("'--content-length' flag should only be used on the worker node, not on the ingress");
-            }
-            if flags.kv_cache_block_size.is_some() {
-                anyhow::bail!("'--kv-cache-block-size' flag should only be used on the worker node, not on the ingress");
-            }
-            EngineConfig::Dynamic
-        }
-        Output::EchoFull => EngineConfig::StaticFull {
-            model: Box::new(local_model),
-            engine: dynamo_llm::engines::make_engine_full(),
-        },
-        Output::EchoCore => {
-            let card = local_model.card();
-            if !card.has_tokenizer() {
-                anyhow::bail!(
-                    "out=echo_core need to find the tokenizer. Pass flag --model-path <path>"
-                );
-            };
-            EngineConfig::StaticCore {
-                engine: dynamo_llm::engines::make_engine_core(),
-                model: Box::new(local_model),
-            }
-        }
-        #[cfg(feature = "mistralrs")]
-        Output::MistralRs => EngineConfig::StaticFull {
-            engine: dynamo_engine_mistralrs::make_engine(&local_model).await?,
-            model: Box::new(local_model),
-        },
+        Output::Dynamic => EngineConfig::Dynamic,
         Output::SgLang => {
             if !local_model.path().is_dir() {
                 // TODO Does sglang support GGUF? Can we make it work?
@@ -168,7 +393,7 @@ pub async fn run(
 
             // If `in=dyn` we want the sglang subprocess to listen on that endpoint.
             // If not, then the endpoint isn't exposed so we invent an internal one.
-            let endpoint = match &in_opt {
+            let endpoint = match in_opt {
                 Input::Endpoint(path) => path.parse()?,
                 _ => internal_endpoint("sglang"),
             };
@@ -180,6 +405,7 @@ pub async fn run(
             };
             let (py_script, child) = match subprocess::start(
                 subprocess::sglang::PY,
+                Some(&subprocess::sglang::ENGINE_ARGS),
                 &local_model,
                 &endpoint,
                 flags.clone(),
@@ -193,14 +419,15 @@ pub async fn run(
             {
                 Ok(x) => x,
                 Err(err) => {
-                    anyhow::bail!("Failed starting sglang sub-process: {err}");
+                    anyhow::bail!("Failed starting sglang sub-process: {err:#}");
                 }
             };
             let cancel_token = cancel_token.clone();
+            let stop_timeout = flags.timeouts().subprocess_stop;
 
             // Sub-process cleanup
             extra = Some(Box::pin(async move {
-                stopper(cancel_token, child, py_script).await;
+                stopper(cancel_token, child, py_script, stop_timeout).await;
             }));
             EngineConfig::Dynamic
         }
@@ -211,13 +438,14 @@ pub async fn run(
 
             // If `in=dyn` we want the vllm subprocess to listen on that endpoint.
             // If not, then the endpoint isn't exposed so we invent an internal one.
-            let endpoint = match &in_opt {
+            let endpoint = match in_opt {
                 Input::Endpoint(path) => path.parse()?,
                 _ => internal_endpoint("vllm"),
             };
 
             let (py_script, child) = match subprocess::start(
                 subprocess::vllm::PY,
+                Some(&subprocess::vllm::ENGINE_ARGS),
                 &local_model,
                 &endpoint,
                 flags.clone(),
@@ -227,14 +455,15 @@ pub async fn run(
             {
                 Ok(x) => x,
                 Err(err) => {
-                    anyhow::bail!("Failed starting vllm sub-process: {err}");
+                    anyhow::bail!("Failed starting vllm sub-process: {err:#}");
                 }
             };
             let cancel_token = cancel_token.clone();
+            let stop_timeout = flags.timeouts().subprocess_stop;
 
             // Sub-process cleanup
             extra = Some(Box::pin(async move {
-                stopper(cancel_token, child, py_script).await;
+                stopper(cancel_token, child, py_script, stop_timeout).await;
             }));
             EngineConfig::Dynamic
         }
@@ -245,13 +474,14 @@ pub async fn run(
 
             // If `in=dyn` we want the trtllm subprocess to listen on that endpoint.
             // If not, then the endpoint isn't exposed so we invent an internal one.
-            let endpoint = match &in_opt {
+            let endpoint = match in_opt {
                 Input::Endpoint(path) => path.parse()?,
                 _ => internal_endpoint("trtllm"),
             };
 
             let (py_script, child) = match subprocess::start(
                 subprocess::trtllm::PY,
+                None, // --extra-engine-args is a YAML file checked by trtllm itself
                 &local_model,
                 &endpoint,
                 flags.clone(),
@@ -261,78 +491,131 @@ pub async fn run(
             {
                 Ok(x) => x,
                 Err(err) => {
-                    anyhow::bail!("Failed starting trtllm sub-process: {err}");
+                    anyhow::bail!("Failed starting trtllm sub-process: {err:#}");
                 }
             };
             let cancel_token = cancel_token.clone();
+            let stop_timeout = flags.timeouts().subprocess_stop;
 
             // Sub-process cleanup
             extra = Some(Box::pin(async move {
-                stopper(cancel_token, child, py_script).await;
+                stopper(cancel_token, child, py_script, stop_timeout).await;
             }));
             EngineConfig::Dynamic
         }
+        in_process => in_process_engine(in_process, local_model, cancel_token.clone()).await?,
+    };
+    Ok((engine_config, extra))
+}
+
+/// The engine of a draft worker of `target`'s model: runs the draft model at `draft_model_path`,
+/// but registers as `target` so that the ingress finds it next to the target workers
+async fn draft_engine(
+    out_opt: Output,
+    target: LocalModel,
+    draft_model_path: &std::path::Path,
+    cancel_token: &CancellationToken,
+) -> anyhow::Result<EngineConfig> {
+    if matches!(
+        out_opt,
+        Output::Dynamic | Output::SgLang | Output::Vllm | Output::Trtllm
+    ) {
+        anyhow::bail!(
+            "--draft-model-path needs an engine running in dynamo-run, e.g. out=llamacpp"
+        );
+    }
+    let draft = LocalModel::prepare(
+        draft_model_path
+            .to_str()
+            .context("Invalid UTF-8 in draft model path")?,
+        None,
+        None,
+    )
+    .await?;
+    match in_process_engine(out_opt, draft, cancel_token.clone()).await? {
+        EngineConfig::StaticCore { engine, .. } => Ok(EngineConfig::StaticCore {
+            engine,
+            model: Box::new(target),
+        }),
+        _ => anyhow::bail!(
+            "out={out_opt} takes chat requests, a draft worker needs an engine taking pre-processed requests, e.g. out=llamacpp"
+        ),
+    }
+}
 
+/// The engine for `out_opt`, if it runs in this process. Engines in a sub-process need more set up.
+#[cfg_attr(not(feature = "llamacpp"), allow(unused_variables))]
+pub(crate) async fn in_process_engine(
+    out_opt: Output,
+    local_model: LocalModel,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<EngineConfig> {
+    let engine_config = match out_opt {
+        Output::EchoFull => EngineConfig::StaticFull {
+            model: Box::new(local_model),
+            engine: dynamo_llm::engines::make_engine_full(),
+        },
+        Output::EchoCore => {
+            let card = local_model.card();
+            if !card.has_tokenizer() {
+                anyhow::bail!(
+                    "out=echo_core need to find the tokenizer. Pass flag --model-path <path>"
+                );
+            };
+            EngineConfig::StaticCore {
+                engine: dynamo_llm::engines::make_engine_core(),
+                model: Box::new(local_model),
+            }
+        }
+        #[cfg(feature = "mistralrs")]
+        Output::MistralRs => EngineConfig::StaticFull {
+            engine: dynamo_engine_mistralrs::make_engine(&local_model).await?,
+            model: Box::new(local_model),
+        },
         #[cfg(feature = "llamacpp")]
         Output::LlamaCpp => {
             if !local_model.path().is_file() {
                 anyhow::bail!("--model-path should refer to a GGUF file. llama_cpp does not support safetensors.");
             }
-            let engine =
-                dynamo_engine_llamacpp::make_engine(cancel_token.clone(), &local_model).await?;
+            let engine = dynamo_engine_llamacpp::make_engine(cancel_token, &local_model).await?;
             EngineConfig::StaticCore {
                 engine,
                 model: Box::new(local_model),
             }
         }
-    };
-
-    match in_opt {
-        Input::Http => {
-            crate::input::http::run(runtime.clone(), flags, engine_config, template).await?;
-        }
-        Input::Text => {
-            crate::input::text::run(runtime.clone(), flags, None, engine_config, template).await?;
-        }
-        Input::Stdin => {
-            let mut prompt = String::new();
-            std::io::stdin().read_to_string(&mut prompt).unwrap();
-            crate::input::text::run(
-                runtime.clone(),
-                flags,
-                Some(prompt),
-                engine_config,
-                template,
-            )
-            .await?;
-        }
-        Input::Batch(path) => {
-            crate::input::batch::run(runtime.clone(), flags, card, path, engine_config, template)
-                .await?;
-        }
-        Input::Endpoint(path) => {
-            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
-            crate::input::endpoint::run(distributed_runtime, path, engine_config).await?;
+        Output::Dynamic | Output::SgLang | Output::Vllm | Output::Trtllm => {
+            anyhow::bail!("out={out_opt} does not run in the dynamo-run process");
         }
-    }
-
-    // Allow engines to ask main thread to wait on an extra future.
-    // We use this to stop the vllm and sglang sub-process
-    if let Some(extra) = extra {
-        extra.await;
-    }
-
-    Ok(())
+    };
+    Ok(engine_config)
 }
 
-/// Wait for cancel_token to be cancelled, then stop the child as gracefully as possible.
-/// Keeps the TempPath alive until the child is stopped.
+/// Wait for cancel_token to be cancelled, then stop the child as gracefully as possible, killing
+/// it if it didn't exit within `stop_timeout`. Keeps the TempPath alive until the child is stopped.
+/// If the child exits by itself first that's a crash. Report it and shut down.
 async fn stopper(
     cancel_token: CancellationToken,
     mut child: tokio::process::Child,
     py_script: tempfile::TempPath,
+    stop_timeout: Duration,
 ) {
-    cancel_token.cancelled().await;
+    if let Some(pid) = child.id() {
+        fault_injection::spawn_subprocess_killer(pid, cancel_token.child_token());
+    }
+    tokio::select! {
+        _ = cancel_token.cancelled() => {}
+        exit = child.wait() => {
+            let message = match exit {
+                Ok(exit_status) => format!("Engine sub-process exited unexpectedly: {exit_status}"),
+                Err(err) => format!("Engine sub-process failed: {err}"),
+            };
+            error_reporting::report(ReportKind::SubprocessCrash, message.clone(), None);
+            tracing::error!("{message}");
+            // Nothing left to serve requests
+            cancel_token.cancel();
+            return;
+        }
+    }
 
     // Ask subprocess to stop gracefully
     if let Some(pid) = child.id() {
@@ -353,7 +636,7 @@ async fn stopper(
                 }
             }
         }
-        _ = tokio::time::sleep(CHILD_STOP_TIMEOUT) => {
+        _ = tokio::time::sleep(stop_timeout) => {
             // It didn't stop in time, kill it
             child.kill().await.expect("Failed killing vllm subprocess");
             let _ = child.wait().await;
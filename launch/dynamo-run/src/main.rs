@@ -33,11 +33,27 @@ Example:
 - cd target/debug
 - ./dynamo-run Qwen/Qwen3-0.6B
 - OR: ./dynamo-run /data/models/Llama-3.2-1B-Instruct-Q4_K_M.gguf
+
+What this build is, with its cargo features, git sha and engine script hashes:
+- ./dynamo-run --version --verbose
+
+Metrics recorded with --metrics-record-db:
+- ./dynamo-run metrics export --db metrics.db --output metrics.parquet
 "#;
 
-const USAGE: &str = "USAGE: dynamo-run in=[http|text|dyn://<path>|batch:<folder>] out=ENGINE_LIST|dyn [--http-port 8080] [--model-path <path>] [--model-name <served-model-name>] [--model-config <hf-repo>] [--tensor-parallel-size=1] [--context-length=N] [--kv-cache-block-size=16] [--num-nodes=1] [--node-rank=0] [--leader-addr=127.0.0.1:9876] [--base-gpu-id=0] [--extra-engine-args=args.json] [--router-mode random|round-robin|kv] [--kv-overlap-score-weight=2.0] [--kv-gpu-cache-usage-weight=1.0] [--kv-waiting-requests-weight=1.0] [--verbosity (-v|-vv)]";
+const USAGE: &str = "USAGE: dynamo-run in=[http|grpc|text|dyn://<path>|batch:<folder>|kafka://<brokers>/<topic>] out=ENGINE_LIST|dyn [--http-port 8080] [--grpc-port 50051] [--model-path <path>] [--model-name <served-model-name>] [--model-config <hf-repo>] [--tensor-parallel-size=1] [--context-length=N] [--kv-cache-block-size=16] [--num-nodes=1] [--node-rank=0] [--leader-addr=127.0.0.1:9876] [--base-gpu-id=0] [--extra-engine-args=args.json] [--router-mode random|round-robin|least-loaded|kv] [--kv-overlap-score-weight=2.0] [--kv-gpu-cache-usage-weight=1.0] [--kv-waiting-requests-weight=1.0] [--verbosity (-v|-vv)]";
 
 fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--version" || arg == "-V") {
+        if args.iter().any(|arg| arg == "--verbose") {
+            print!("{}", dynamo_run::build_info());
+        } else {
+            println!("dynamo-run {}", env!("CARGO_PKG_VERSION"));
+        }
+        return Ok(());
+    }
+
     // Set log level based on verbosity flag
     let log_level = match dynamo_run::Flags::try_parse() {
         Ok(flags) => match flags.verbosity {
@@ -57,6 +73,21 @@ fn main() -> anyhow::Result<()> {
         std::env::set_var("DYN_LOG", log_level);
     }
 
+    if env::args().nth(1).as_deref() == Some("metrics") {
+        logging::init();
+        return dynamo_run::metrics_export::run(env::args().skip(1));
+    }
+
+    let Some((in_opt, out_opt, flags)) = parse_args()? else {
+        return Ok(());
+    };
+    // The runtime reads these from the environment. Set them while this is the only thread,
+    // changing the environment isn't safe once the logging and tokio threads run.
+    flags.export_locality();
+    flags.export_nats();
+    flags.export_etcd();
+    flags.export_timeouts();
+
     logging::init();
 
     // max_worker_threads and max_blocking_threads from env vars or config file.
@@ -65,10 +96,11 @@ fn main() -> anyhow::Result<()> {
     // One per process. Wraps a Runtime with holds two tokio runtimes.
     let worker = dynamo_runtime::Worker::from_config(rt_config)?;
 
-    worker.execute(wrapper)
+    worker.execute(move |runtime| dynamo_run::run(runtime, in_opt, out_opt, flags))
 }
 
-async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
+/// The input, output and flags, or None if we only printed the usage
+fn parse_args() -> anyhow::Result<Option<(Input, Option<Output>, dynamo_run::Flags)>> {
     let mut in_opt = None;
     let mut out_opt = None;
     let args: Vec<String> = env::args().skip(1).collect();
@@ -81,7 +113,7 @@ async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
         let usage = USAGE.replace("ENGINE_LIST", &engine_list);
         println!("{usage}");
         println!("{HELP}");
-        return Ok(());
+        return Ok(None);
     }
     for arg in env::args().skip(1).take(2) {
         let Some((in_out, val)) = arg.split_once('=') else {
@@ -114,11 +146,10 @@ async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
 
     // Clap skips the first argument expecting it to be the binary name, so add it back
     // Note `--model-path` has index=1 (in lib.rs) so that doesn't need a flag.
-    let flags = dynamo_run::Flags::try_parse_from(
+    let flags = dynamo_run::Flags::try_parse_explicit(
         ["dynamo-run".to_string()]
             .into_iter()
             .chain(env::args().skip(non_flag_params)),
     )?;
-
-    dynamo_run::run(runtime, in_opt, out_opt, flags).await
+    Ok(Some((in_opt, out_opt, flags)))
 }
@@ -0,0 +1,179 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A models manifest lets one `dynamo-run` process serve several models, each with the engine
+//! that suits it, e.g. a GGUF file with llamacpp next to a Hugging Face checkout with vllm. It is a
+//! JSON file passed with `--models`, instead of `out=` and a model path:
+//!
+//! ```text
+//! {"models": [
+//!   {"path": "/data/Qwen3-0.6B-Q8_0.gguf", "engine": "llamacpp", "context_length": 8192},
+//!   {"path": "Qwen/Qwen3-8B", "name": "qwen3-8b", "engine": "vllm", "base_gpu_id": 1}
+//! ]}
+//! ```
+//!
+//! An entry has the model's path, name, engine, config and extra engine arguments, and can
+//! override the context length, KV cache block size, tensor parallel size and base GPU. The other
+//! flags apply to every model.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+use crate::{Flags, Output};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub models: Vec<ManifestModel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestModel {
+    /// Like `--model-path`. Only `echo_full` doesn't need one.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+
+    /// Like `--model-name`
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Like `out=`. Defaults to the engine for the model's format.
+    #[serde(default)]
+    pub engine: Option<String>,
+
+    #[serde(default)]
+    pub model_config: Option<PathBuf>,
+
+    #[serde(default)]
+    pub context_length: Option<usize>,
+
+    #[serde(default)]
+    pub kv_cache_block_size: Option<usize>,
+
+    #[serde(default)]
+    pub tensor_parallel_size: Option<u32>,
+
+    #[serde(default)]
+    pub base_gpu_id: Option<u32>,
+
+    #[serde(default)]
+    pub extra_engine_args: Option<PathBuf>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed reading models manifest {}", path.display()))?;
+        let manifest: Manifest = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid models manifest {}", path.display()))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.models.is_empty() {
+            anyhow::bail!("The models manifest has no models");
+        }
+        let mut names = HashSet::new();
+        for model in &self.models {
+            if let Some(engine) = model.engine()? {
+                if matches!(engine, Output::Dynamic) {
+                    anyhow::bail!("engine 'dyn' can't be used in a models manifest");
+                }
+            }
+            if model.path.is_none() && model.name.is_none() {
+                anyhow::bail!("Every model in the manifest needs a path or a name");
+            }
+            let name = model.display_name();
+            if !names.insert(name.clone()) {
+                anyhow::bail!("Model '{name}' is in the manifest twice");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ManifestModel {
+    /// The engine, if the manifest sets one
+    pub fn engine(&self) -> anyhow::Result<Option<Output>> {
+        self.engine.as_deref().map(Output::try_from).transpose()
+    }
+
+    /// The name to refer to the model by in logs and errors. The served name can differ when it
+    /// comes from the model's files.
+    pub fn display_name(&self) -> String {
+        match (&self.name, &self.path) {
+            (Some(name), _) => name.clone(),
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// The flags to start this model with: `base` with this entry's settings. The model and its
+    /// engine arguments always come from the entry.
+    pub fn flags(&self, base: &Flags) -> Flags {
+        let mut flags = base.clone();
+        flags.model_path_pos = None;
+        flags.model_path_flag = self.path.clone();
+        flags.model_name = self.name.clone();
+        flags.model_config = self.model_config.clone();
+        flags.extra_engine_args = self.extra_engine_args.clone();
+        flags.context_length = self.context_length.or(base.context_length);
+        flags.kv_cache_block_size = self.kv_cache_block_size.or(base.kv_cache_block_size);
+        flags.tensor_parallel_size = self.tensor_parallel_size.or(base.tensor_parallel_size);
+        flags.base_gpu_id = self.base_gpu_id.unwrap_or(base.base_gpu_id);
+        if self.base_gpu_id.is_some() {
+            flags.explicit.insert("base_gpu_id".to_string());
+        }
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn parse(json: &str) -> anyhow::Result<Manifest> {
+        let manifest: Manifest = serde_json::from_str(json)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = parse(
+            r#"{"models": [
+                {"name": "echo", "engine": "echo_full"},
+                {"path": "/models/qwen", "engine": "vllm", "base_gpu_id": 2, "context_length": 4096}
+            ]}"#,
+        )
+        .unwrap();
+        let base = Flags::try_parse_from(["dynamo-run", "--context-length", "1024"]).unwrap();
+        let flags = manifest.models[1].flags(&base);
+        assert_eq!(flags.model_path_flag, Some(PathBuf::from("/models/qwen")));
+        assert_eq!(flags.base_gpu_id, 2);
+        assert_eq!(flags.context_length, Some(4096));
+        assert!(matches!(
+            manifest.models[1].engine(),
+            Ok(Some(Output::Vllm))
+        ));
+
+        let flags = manifest.models[0].flags(&base);
+        assert_eq!(flags.context_length, Some(1024));
+        assert_eq!(flags.model_name.as_deref(), Some("echo"));
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        assert!(parse(r#"{"models": []}"#).is_err());
+        assert!(parse(r#"{"models": [{"name": "a", "engine": "dyn"}]}"#).is_err());
+        assert!(parse(r#"{"models": [{"name": "a"}, {"name": "a"}]}"#).is_err());
+        assert!(parse(r#"{"models": [{"name": "a", "engine": "nope"}]}"#).is_err());
+        assert!(parse(r#"{"models": [{"name": "a", "gpu": 1}]}"#).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `dynamo-run metrics export`: write the metrics recorded with `--metrics-record-db` to a
+//! Parquet, CSV or JSON Lines file, for analysis elsewhere.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use clap::Parser;
+use dynamo_llm::metrics_recorder::{MetricsStore, Sample};
+#[cfg(feature = "parquet")]
+use parquet::{
+    basic::{Compression, ZstdLevel},
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+/// Rows per Parquet row group
+#[cfg(feature = "parquet")]
+const ROW_GROUP_ROWS: usize = 1024 * 1024;
+
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "
+message sample {
+    REQUIRED INT64 timestamp_ms (TIMESTAMP_MILLIS);
+    REQUIRED BINARY model (UTF8);
+    REQUIRED BINARY metric (UTF8);
+    REQUIRED BINARY labels (UTF8);
+    REQUIRED DOUBLE value;
+}
+";
+
+#[derive(Parser, Debug)]
+#[command(name = "dynamo-run metrics")]
+struct MetricsArgs {
+    #[command(subcommand)]
+    command: MetricsCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum MetricsCommand {
+    /// Write the recorded metrics to a file, `.parquet`, `.csv` or `.jsonl`
+    Export {
+        /// The database `--metrics-record-db` wrote
+        #[arg(long)]
+        db: PathBuf,
+
+        /// The file to write. Its extension picks the format.
+        #[arg(long, short)]
+        output: PathBuf,
+
+        /// Only the samples of the last this many hours
+        #[arg(long)]
+        since_hours: Option<u64>,
+
+        /// Only the samples of this model
+        #[arg(long)]
+        model: Option<String>,
+    },
+}
+
+/// Run `dynamo-run metrics ...`. `args` start with `metrics`.
+pub fn run(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    match MetricsArgs::parse_from(args).command {
+        MetricsCommand::Export {
+            db,
+            output,
+            since_hours,
+            model,
+        } => {
+            if !db.exists() {
+                anyhow::bail!("Metrics database {} does not exist", db.display());
+            }
+            let store = MetricsStore::open(&db)?;
+            let since_ms = since_hours
+                .map(|hours| chrono::Utc::now().timestamp_millis() - (hours as i64) * 3600 * 1000);
+            let samples = store.read(since_ms, model.as_deref())?;
+            export(&output, &samples)
+                .with_context(|| format!("Failed writing {}", output.display()))?;
+            println!("Exported {} samples to {}", samples.len(), output.display());
+            Ok(())
+        }
+    }
+}
+
+fn export(path: &Path, samples: &[Sample]) -> anyhow::Result<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("parquet") => write_parquet(path, samples),
+        Some("csv") => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for sample in samples {
+                writer.serialize(sample)?;
+            }
+            writer.flush()?;
+            Ok(())
+        }
+        Some("jsonl") => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            for sample in samples {
+                serde_json::to_writer(&mut writer, sample)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+            Ok(())
+        }
+        _ => anyhow::bail!("The output file must end in .parquet, .csv or .jsonl"),
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, samples: &[Sample]) -> anyhow::Result<()> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, properties)?;
+    for chunk in samples.chunks(ROW_GROUP_ROWS) {
+        let strings = |field: fn(&Sample) -> &str| -> Vec<ByteArray> {
+            chunk.iter().map(|s| ByteArray::from(field(s))).collect()
+        };
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match index {
+                0 => {
+                    let timestamps: Vec<i64> = chunk.iter().map(|s| s.timestamp_ms).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&timestamps, None, None)?
+                }
+                1 => column.typed::<ByteArrayType>().write_batch(
+                    &strings(|s| s.model.as_str()),
+                    None,
+                    None,
+                )?,
+                2 => column.typed::<ByteArrayType>().write_batch(
+                    &strings(|s| s.metric.as_str()),
+                    None,
+                    None,
+                )?,
+                3 => column.typed::<ByteArrayType>().write_batch(
+                    &strings(|s| s.labels.as_str()),
+                    None,
+                    None,
+                )?,
+                _ => {
+                    let values: Vec<f64> = chunk.iter().map(|s| s.value).collect();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&values, None, None)?
+                }
+            };
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_path: &Path, _samples: &[Sample]) -> anyhow::Result<()> {
+    anyhow::bail!("Writing Parquet needs dynamo-run built with the `parquet` feature")
+}
@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Models loaded and unloaded while the worker runs, with `--enable-model-control`.
+//!
+//! A component serves a single model, so each loaded model gets its own component named after
+//! it, next to the worker's: `ns.backend.generate` loading `Qwen3-0.6B` serves it on
+//! `ns.backend_qwen3-0_6b.generate`. Its registration in etcd has its own lease, revoking the
+//! lease unregisters the model and stops its endpoint.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use dynamo_llm::audit::AuditLogger;
+use dynamo_llm::discovery::model_control::ModelLoader;
+use dynamo_llm::local_model::LocalModel;
+use dynamo_llm::model_card::ModelDeploymentCard;
+use dynamo_llm::response_tee::ResponseTee;
+use dynamo_runtime::discovery::Lease;
+use dynamo_runtime::protocols::Endpoint as EndpointId;
+use dynamo_runtime::slug::Slug;
+use dynamo_runtime::DistributedRuntime;
+
+use crate::input::endpoint;
+use crate::{Flags, Output, DEFAULT_KV_CACHE_BLOCK_SIZE};
+
+/// Same as the primary lease
+const LEASE_TTL_SECS: i64 = 10;
+
+struct LoadedModel {
+    lease: Lease,
+    card: ModelDeploymentCard,
+}
+
+pub struct WorkerModelLoader {
+    drt: DistributedRuntime,
+    /// The endpoint the worker was started with
+    endpoint_id: EndpointId,
+    out_opt: Output,
+    flags: Flags,
+    /// Shared with the worker's own model
+    audit_logger: Option<Arc<AuditLogger>>,
+    response_tee: Option<Arc<ResponseTee>>,
+    loaded: Mutex<HashMap<String, LoadedModel>>,
+}
+
+impl WorkerModelLoader {
+    pub fn new(
+        drt: DistributedRuntime,
+        endpoint_id: EndpointId,
+        out_opt: Output,
+        flags: Flags,
+        audit_logger: Option<Arc<AuditLogger>>,
+        response_tee: Option<Arc<ResponseTee>>,
+    ) -> Self {
+        WorkerModelLoader {
+            drt,
+            endpoint_id,
+            out_opt,
+            flags,
+            audit_logger,
+            response_tee,
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_loaded(&self, model_name: &str) -> bool {
+        self.loaded.lock().unwrap().contains_key(model_name)
+    }
+}
+
+#[async_trait]
+impl ModelLoader for WorkerModelLoader {
+    async fn load(&self, model_path: &str, model_name: Option<String>) -> anyhow::Result<String> {
+        let Some(etcd_client) = self.drt.etcd_client() else {
+            anyhow::bail!("Loading models needs etcd");
+        };
+        let mut local_model = LocalModel::prepare(model_path, None, model_name).await?;
+        let name = local_model.display_name().to_string();
+        if self.is_loaded(&name) {
+            anyhow::bail!("Model {name} is already loaded");
+        }
+        if let Some(context_length) = self.flags.context_length {
+            local_model.set_context_length(context_length);
+        }
+        local_model.set_kv_cache_block_size(
+            self.flags
+                .kv_cache_block_size
+                .unwrap_or(DEFAULT_KV_CACHE_BLOCK_SIZE),
+        );
+
+        let lease = etcd_client.create_lease(LEASE_TTL_SECS).await?;
+        let component_name = format!("{}_{}", self.endpoint_id.component, Slug::slugify(&name));
+        let component = self
+            .drt
+            .namespace(&self.endpoint_id.namespace)?
+            .component(component_name)?;
+        // The service outlives the model, it is still there if the model was loaded before
+        if let Err(err) = component.service_builder().create().await {
+            tracing::debug!(%err, "Re-using service of {component}");
+        }
+        let endpoint = component.endpoint(&self.endpoint_id.name);
+
+        let started = async {
+            let engine_config =
+                crate::in_process_engine(self.out_opt, local_model, lease.child_token()).await?;
+            let endpoint = endpoint::claim_endpoint(
+                &endpoint,
+                &engine_config,
+                self.flags.duplicate_registration.into(),
+            )
+            .await?;
+            let (serve, card) = endpoint::start(
+                &endpoint,
+                engine_config,
+                Some(lease.clone()),
+                None,
+                self.audit_logger.clone(),
+                self.response_tee.clone(),
+                endpoint::InstanceAdvert::new(&self.flags, self.out_opt),
+            )
+            .await?;
+            let Some(card) = card else {
+                anyhow::bail!("out={} can't load models on demand", self.out_opt);
+            };
+            tokio::spawn(async move {
+                if let Err(err) = serve.await {
+                    tracing::error!(%err, "Loaded model endpoint failed");
+                }
+            });
+            LocalModel::verify_routable_with_lease(
+                &endpoint,
+                lease.id(),
+                endpoint::ROUTABLE_TIMEOUT,
+            )
+            .await?;
+            anyhow::Ok(card)
+        }
+        .await;
+        let card = match started {
+            Ok(card) => card,
+            Err(err) => {
+                lease.revoke();
+                return Err(err);
+            }
+        };
+
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.contains_key(&name) {
+            // Lost a race with a concurrent load of the same model
+            lease.revoke();
+            anyhow::bail!("Model {name} is already loaded");
+        }
+        loaded.insert(name.clone(), LoadedModel { lease, card });
+        Ok(name)
+    }
+
+    async fn unload(&self, model_name: &str) -> anyhow::Result<()> {
+        let Some(mut model) = self.loaded.lock().unwrap().remove(model_name) else {
+            anyhow::bail!("Model {model_name} was not loaded with model control");
+        };
+        // etcd drops the model's registration, ingresses stop routing to it
+        model.lease.revoke();
+        if let Err(err) = model.card.delete_from_nats(self.drt.nats_client()).await {
+            tracing::warn!(%err, model_name, "Failed deleting model card from NATS");
+        }
+        Ok(())
+    }
+
+    fn models(&self) -> Vec<String> {
+        let mut models: Vec<_> = self.loaded.lock().unwrap().keys().cloned().collect();
+        models.sort();
+        models
+    }
+}
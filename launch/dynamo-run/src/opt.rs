@@ -19,11 +19,16 @@ use dynamo_runtime::protocols::ENDPOINT_SCHEME;
 
 const BATCH_PREFIX: &str = "batch:";
 
+pub(crate) const KAFKA_PREFIX: &str = "kafka://";
+
 #[derive(PartialEq)]
 pub enum Input {
     /// Run an OpenAI compatible HTTP server
     Http,
 
+    /// Run a gRPC server with the OpenAI compatible API of `lib/llm/proto/openai.proto`
+    Grpc,
+
     /// Single prompt on stdin
     Stdin,
 
@@ -35,6 +40,10 @@ pub enum Input {
 
     /// Batch mode. Run all the prompts, write the outputs, exit.
     Batch(PathBuf),
+
+    #[cfg(feature = "kafka")]
+    /// Consume requests from a Kafka topic, produce the responses to their reply topic
+    Kafka(crate::input::kafka::KafkaSource),
 }
 
 impl TryFrom<&str> for Input {
@@ -43,6 +52,7 @@ impl TryFrom<&str> for Input {
     fn try_from(s: &str) -> anyhow::Result<Self> {
         match s {
             "http" => Ok(Input::Http),
+            "grpc" => Ok(Input::Grpc),
             "text" => Ok(Input::Text),
             "stdin" => Ok(Input::Stdin),
             endpoint_path if endpoint_path.starts_with(ENDPOINT_SCHEME) => {
@@ -52,6 +62,14 @@ impl TryFrom<&str> for Input {
                 let path = batch_patch.strip_prefix(BATCH_PREFIX).unwrap();
                 Ok(Input::Batch(PathBuf::from(path)))
             }
+            #[cfg(feature = "kafka")]
+            kafka_source if kafka_source.starts_with(KAFKA_PREFIX) => {
+                Ok(Input::Kafka(kafka_source.parse()?))
+            }
+            #[cfg(not(feature = "kafka"))]
+            kafka_source if kafka_source.starts_with(KAFKA_PREFIX) => Err(anyhow::anyhow!(
+                "in={kafka_source} needs dynamo-run built with the `kafka` feature"
+            )),
             e => Err(anyhow::anyhow!("Invalid in= option '{e}'")),
         }
     }
@@ -61,10 +79,13 @@ impl fmt::Display for Input {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = match self {
             Input::Http => "http",
+            Input::Grpc => "grpc",
             Input::Text => "text",
             Input::Stdin => "stdin",
             Input::Endpoint(path) => path,
             Input::Batch(path) => &path.display().to_string(),
+            #[cfg(feature = "kafka")]
+            Input::Kafka(source) => &format!("{KAFKA_PREFIX}{}/{}", source.brokers, source.topic),
         };
         write!(f, "{s}")
     }
@@ -80,6 +101,7 @@ impl Default for Input {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Output {
     /// Accept un-preprocessed requests, echo the prompt back as the response
     EchoFull,
@@ -2,19 +2,23 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::Write;
 use std::process::Stdio;
 use std::sync::LazyLock;
 
 use anyhow::Context;
 use regex::Regex;
+use serde_json::{json, Value};
 use tokio::io::AsyncBufReadExt;
 
-use crate::flags::RouterMode;
+use crate::flags::{DisaggRole, RouterMode};
 use dynamo_llm::engines::MultiNodeConfig;
 use dynamo_llm::local_model::LocalModel;
 use dynamo_runtime::protocols::Endpoint as EndpointId;
+use engine_args::EngineArgs;
 
+pub mod engine_args;
 pub mod sglang;
 pub mod trtllm;
 pub mod vllm;
@@ -22,6 +26,8 @@ pub mod vllm;
 pub async fn start(
     // The Python code to run
     py_script: &'static str,
+    // The arguments the engine accepts, to validate --extra-engine-args. None to not validate.
+    engine_args: Option<&EngineArgs>,
     // Model info
     local_model: &LocalModel,
     // Endpoint to connect the subprocess over etcd/nats
@@ -31,6 +37,19 @@ pub async fn start(
     // sglang multi-node config. vllm uses `ray` externally
     multi_node_config: Option<MultiNodeConfig>,
 ) -> anyhow::Result<(tempfile::TempPath, tokio::process::Child)> {
+    // Fail now rather than after the engine spent minutes loading the model
+    if let Some(schema) = engine_args.filter(|_| !flags.skip_engine_args_validation) {
+        let extra = flags
+            .load_extra_engine_args()
+            .context("Failed reading --extra-engine-args")?;
+        if let Some(extra) = extra {
+            schema.validate(
+                &extra,
+                &passed_values(local_model, &flags, &multi_node_config),
+            )?;
+        }
+    }
+
     let mut tmp = tempfile::NamedTempFile::new()?;
     // Writes on Linux don't block
     tmp.write_all(py_script.as_bytes())?;
@@ -46,7 +65,7 @@ pub async fn start(
         "--model-name".to_string(),
         local_model.display_name().to_string(),
         "--tensor-parallel-size".to_string(),
-        flags.tensor_parallel_size.to_string(),
+        flags.tensor_parallel_size.unwrap_or(1).to_string(),
         "--kv-block-size".to_string(),
         card.kv_cache_block_size.to_string(),
         "--context-length".to_string(),
@@ -73,6 +92,27 @@ pub async fn start(
         args.push("--dist-init-addr".to_string());
         args.push(multi_node_config.leader_addr);
     }
+    if let Some(max_num_batched_tokens) = flags.max_num_batched_tokens {
+        args.push("--max-num-batched-tokens".to_string());
+        args.push(max_num_batched_tokens.to_string());
+    }
+    // vllm only
+    if flags.disagg_role != DisaggRole::Aggregated {
+        args.push("--disagg-role".to_string());
+        args.push(flags.disagg_role.as_str().to_string());
+    }
+    // vllm and sglang only
+    if flags.enable_lora {
+        args.push("--enable-lora".to_string());
+    }
+    if let Some(max_loras) = flags.max_loras {
+        args.push("--max-loras".to_string());
+        args.push(max_loras.to_string());
+    }
+    if let Some(max_lora_rank) = flags.max_lora_rank {
+        args.push("--max-lora-rank".to_string());
+        args.push(max_lora_rank.to_string());
+    }
     if let Some(extra_engine_args) = flags.extra_engine_args {
         args.push("--extra-engine-args".to_string());
         args.push(extra_engine_args.to_string_lossy().to_string());
@@ -106,6 +146,65 @@ pub async fn start(
     Ok((script_path, child))
 }
 
+/// The values the engine gets from the flags that were set, by flag, for the conflict check of
+/// [`EngineArgs`]. Defaulted flags don't count, `--extra-engine-args` may set those.
+fn passed_values(
+    local_model: &LocalModel,
+    flags: &super::Flags,
+    multi_node_config: &Option<MultiNodeConfig>,
+) -> HashMap<&'static str, Value> {
+    let mut passed = HashMap::from([("--model-path", json!(local_model.path().to_string_lossy()))]);
+    if let Some(tensor_parallel_size) = flags.tensor_parallel_size {
+        passed.insert("--tensor-parallel-size", json!(tensor_parallel_size));
+    }
+    if flags.is_explicit("base_gpu_id") {
+        passed.insert("--base-gpu-id", json!(flags.base_gpu_id));
+    }
+    // Not the card's, which defaults to the model's
+    if let Some(kv_cache_block_size) = flags.kv_cache_block_size {
+        passed.insert("--kv-cache-block-size", json!(kv_cache_block_size));
+    }
+    if let Some(context_length) = flags.context_length {
+        passed.insert("--context-length", json!(context_length));
+    }
+    if let Some(max_num_batched_tokens) = flags.max_num_batched_tokens {
+        passed.insert("--max-num-batched-tokens", json!(max_num_batched_tokens));
+    }
+    if flags.enable_lora {
+        passed.insert("--enable-lora", json!(true));
+    }
+    if let Some(max_loras) = flags.max_loras {
+        passed.insert("--max-loras", json!(max_loras));
+    }
+    if let Some(max_lora_rank) = flags.max_lora_rank {
+        passed.insert("--max-lora-rank", json!(max_lora_rank));
+    }
+    if let Some(multi_node_config) = multi_node_config {
+        for (flag, id, value) in [
+            (
+                "--num-nodes",
+                "num_nodes",
+                json!(multi_node_config.num_nodes),
+            ),
+            (
+                "--node-rank",
+                "node_rank",
+                json!(multi_node_config.node_rank),
+            ),
+            (
+                "--leader-addr",
+                "leader_addr",
+                json!(multi_node_config.leader_addr),
+            ),
+        ] {
+            if flags.is_explicit(id) {
+                passed.insert(flag, value);
+            }
+        }
+    }
+    passed
+}
+
 pub fn pretty_cmd(c: &tokio::process::Command) -> String {
     format!(
         "{} {}",
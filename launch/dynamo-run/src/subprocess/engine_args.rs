@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Validation of `--extra-engine-args` before the engine sub-process starts. Engines take minutes
+//! to load a model, and only then fail on a misspelled argument. Each engine declares the
+//! arguments it accepts and their type in an [`EngineArgs`] schema, and the JSON file is checked
+//! against it up front.
+//!
+//! An argument that dynamo-run already passes from one of its own flags, like the KV block size,
+//! is a conflict if the file gives it a different value: the file would win in the engine, and
+//! disagree with what dynamo-run publishes about the model.
+//!
+//! The schemas are maintained by hand and can lag behind new engine versions, so
+//! `--skip-engine-args-validation` passes the file through unchecked.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_json::Value;
+
+/// What values an engine argument takes. `null` is always accepted, it means the engine's
+/// default.
+#[derive(Debug, Clone, Copy)]
+pub enum ArgType {
+    Bool,
+    Int,
+    Float,
+    String,
+    /// One of these strings
+    Choice(&'static [&'static str]),
+    /// Lists, objects, or values of several types. Not checked.
+    Any,
+}
+
+impl ArgType {
+    fn accepts(&self, value: &Value) -> bool {
+        match (self, value) {
+            (_, Value::Null) | (ArgType::Any, _) => true,
+            (ArgType::Bool, Value::Bool(_)) => true,
+            (ArgType::Int, Value::Number(n)) => n.is_i64() || n.is_u64(),
+            (ArgType::Float, Value::Number(_)) => true,
+            (ArgType::String, Value::String(_)) => true,
+            (ArgType::Choice(choices), Value::String(s)) => choices.contains(&s.as_str()),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgType::Bool => write!(f, "a boolean"),
+            ArgType::Int => write!(f, "an integer"),
+            ArgType::Float => write!(f, "a number"),
+            ArgType::String => write!(f, "a string"),
+            ArgType::Choice(choices) => write!(f, "one of {}", choices.join(", ")),
+            ArgType::Any => write!(f, "anything"),
+        }
+    }
+}
+
+/// The arguments an engine accepts in `--extra-engine-args`
+#[derive(Debug)]
+pub struct EngineArgs {
+    /// Engine name for messages
+    pub engine: &'static str,
+
+    /// Argument names and types
+    pub args: &'static [(&'static str, ArgType)],
+
+    /// Arguments dynamo-run sets itself, and the flag they come from
+    pub managed: &'static [(&'static str, &'static str)],
+}
+
+impl EngineArgs {
+    /// Check the contents of the extra engine args file. `passed` has the value dynamo-run gives
+    /// each flag in [`EngineArgs::managed`] that it passes to the engine. Reports every problem
+    /// at once.
+    pub fn validate(
+        &self,
+        extra: &HashMap<String, Value>,
+        passed: &HashMap<&str, Value>,
+    ) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        let mut keys: Vec<&String> = extra.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &extra[key];
+            let Some((_, ty)) = self.args.iter().find(|(name, _)| name == key) else {
+                let mut problem = format!("unknown argument '{key}'");
+                if let Some(suggestion) = self.closest(key) {
+                    problem.push_str(&format!(", did you mean '{suggestion}'?"));
+                }
+                problems.push(problem);
+                continue;
+            };
+            if !ty.accepts(value) {
+                problems.push(format!("'{key}' must be {ty}, got {value}"));
+                continue;
+            }
+            let flag = self
+                .managed
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, flag)| *flag);
+            if let Some(flag) = flag {
+                if let Some(ours) = passed.get(flag).filter(|ours| *ours != value) {
+                    problems.push(format!(
+                        "'{key}' is {value} but {flag} is {ours}, set only one of them"
+                    ));
+                }
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Invalid --extra-engine-args for {}:\n  {}\nUse --skip-engine-args-validation if your {} version accepts them.",
+            self.engine,
+            problems.join("\n  "),
+            self.engine
+        );
+    }
+
+    /// The known argument closest to a misspelled one, if any is close
+    fn closest(&self, key: &str) -> Option<&'static str> {
+        self.args
+            .iter()
+            .map(|(name, _)| (*name, edit_distance(key, name)))
+            .filter(|(_, distance)| *distance <= 1 + key.len() / 4)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(name, _)| name)
+    }
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TEST_ARGS: EngineArgs = EngineArgs {
+        engine: "test",
+        args: &[
+            ("block_size", ArgType::Int),
+            ("dtype", ArgType::Choice(&["auto", "half"])),
+            ("enforce_eager", ArgType::Bool),
+            ("gpu_memory_utilization", ArgType::Float),
+            ("hf_overrides", ArgType::Any),
+        ],
+        managed: &[("block_size", "--kv-cache-block-size")],
+    };
+
+    fn extra(value: Value) -> HashMap<String, Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_valid_args() {
+        let passed = HashMap::from([("--kv-cache-block-size", json!(16))]);
+        let args = extra(json!({
+            "block_size": 16,
+            "dtype": "half",
+            "enforce_eager": null,
+            "gpu_memory_utilization": 1,
+            "hf_overrides": {"rope_scaling": {}}
+        }));
+        TEST_ARGS.validate(&args, &passed).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        let passed = HashMap::from([("--kv-cache-block-size", json!(16))]);
+        let args = extra(json!({
+            "block_size": 32,
+            "dtype": "float64",
+            "enforce_eagre": true,
+            "gpu_memory_utilization": "0.9"
+        }));
+        let err = TEST_ARGS.validate(&args, &passed).unwrap_err().to_string();
+        assert!(err.contains("'block_size' is 32 but --kv-cache-block-size is 16"));
+        assert!(err.contains("'dtype' must be one of auto, half"));
+        assert!(err.contains("unknown argument 'enforce_eagre', did you mean 'enforce_eager'?"));
+        assert!(err.contains("'gpu_memory_utilization' must be a number"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("block_size", "block_size"), 0);
+        assert_eq!(edit_distance("blok_size", "block_size"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -13,5 +13,140 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::engine_args::{ArgType::*, EngineArgs};
+
 /// Source code of the SGLang sub-process
 pub const PY: &str = include_str!("sglang_inc.py");
+
+/// sglang's `ServerArgs`
+pub const ENGINE_ARGS: EngineArgs = EngineArgs {
+    engine: "sglang",
+    args: &[
+        ("allow_auto_truncate", Bool),
+        ("api_key", String),
+        ("attention_backend", String),
+        ("base_gpu_id", Int),
+        ("chat_template", String),
+        ("chunked_prefill_size", Int),
+        ("completion_template", String),
+        ("constrained_json_whitespace_pattern", String),
+        ("context_length", Int),
+        ("cpu_offload_gb", Int),
+        ("cuda_graph_bs", Any),
+        ("cuda_graph_max_bs", Int),
+        ("decode_log_interval", Int),
+        ("delete_ckpt_after_loading", Bool),
+        ("device", String),
+        ("disable_cuda_graph", Bool),
+        ("disable_cuda_graph_padding", Bool),
+        ("disable_custom_all_reduce", Bool),
+        ("disable_mla", Bool),
+        ("disable_outlines_disk_cache", Bool),
+        ("disable_overlap_schedule", Bool),
+        ("disable_radix_cache", Bool),
+        ("disaggregation_bootstrap_port", Int),
+        (
+            "disaggregation_mode",
+            Choice(&["null", "prefill", "decode"]),
+        ),
+        ("dist_init_addr", String),
+        ("dist_timeout", Int),
+        ("download_dir", String),
+        ("dp_size", Int),
+        (
+            "dtype",
+            Choice(&["auto", "half", "float16", "bfloat16", "float", "float32"]),
+        ),
+        ("enable_cache_report", Bool),
+        ("enable_custom_logit_processor", Bool),
+        ("enable_double_sparsity", Bool),
+        ("enable_dp_attention", Bool),
+        ("enable_ep_moe", Bool),
+        ("enable_hierarchical_cache", Bool),
+        ("enable_lora", Bool),
+        ("enable_memory_saver", Bool),
+        ("enable_metrics", Bool),
+        ("enable_mixed_chunk", Bool),
+        ("enable_nan_detection", Bool),
+        ("enable_nccl_nvls", Bool),
+        ("enable_p2p_check", Bool),
+        ("enable_torch_compile", Bool),
+        ("ep_size", Int),
+        ("file_storage_path", String),
+        ("gpu_id_step", Int),
+        ("grammar_backend", String),
+        ("hicache_ratio", Float),
+        ("host", String),
+        ("is_embedding", Bool),
+        ("json_model_override_args", String),
+        ("kv_cache_dtype", String),
+        ("load_balance_method", String),
+        ("load_format", String),
+        ("log_level", String),
+        ("log_level_http", String),
+        ("log_requests", Bool),
+        ("log_requests_level", Int),
+        ("lora_backend", String),
+        ("lora_paths", Any),
+        ("lora_target_modules", Any),
+        ("max_lora_rank", Int),
+        ("max_loras_per_batch", Int),
+        ("max_prefill_tokens", Int),
+        ("max_running_requests", Int),
+        ("max_total_tokens", Int),
+        ("mem_fraction_static", Float),
+        ("model_path", String),
+        ("nnodes", Int),
+        ("node_rank", Int),
+        ("num_continuous_decode_steps", Int),
+        ("page_size", Int),
+        ("port", Int),
+        ("pp_size", Int),
+        ("quantization", String),
+        ("quantization_param_path", String),
+        ("random_seed", Int),
+        ("reasoning_parser", String),
+        ("revision", String),
+        ("sampling_backend", String),
+        ("schedule_conservativeness", Float),
+        (
+            "schedule_policy",
+            Choice(&["lpm", "random", "fcfs", "dfs-weight"]),
+        ),
+        ("served_model_name", String),
+        ("show_time_cost", Bool),
+        ("skip_tokenizer_init", Bool),
+        ("speculative_algorithm", String),
+        ("speculative_draft_model_path", String),
+        ("speculative_eagle_topk", Int),
+        ("speculative_num_draft_tokens", Int),
+        ("speculative_num_steps", Int),
+        ("stream_interval", Int),
+        ("stream_output", Bool),
+        ("tokenizer_mode", Choice(&["auto", "slow"])),
+        ("tokenizer_path", String),
+        ("tool_call_parser", String),
+        ("torch_compile_max_bs", Int),
+        ("torchao_config", String),
+        ("tp_size", Int),
+        ("triton_attention_num_kv_splits", Int),
+        ("triton_attention_reduce_in_fp32", Bool),
+        ("trust_remote_code", Bool),
+        ("warmups", String),
+        ("watchdog_timeout", Float),
+    ],
+    managed: &[
+        ("base_gpu_id", "--base-gpu-id"),
+        ("chunked_prefill_size", "--max-num-batched-tokens"),
+        ("context_length", "--context-length"),
+        ("dist_init_addr", "--leader-addr"),
+        ("enable_lora", "--enable-lora"),
+        ("max_lora_rank", "--max-lora-rank"),
+        ("max_loras_per_batch", "--max-loras"),
+        ("model_path", "--model-path"),
+        ("nnodes", "--num-nodes"),
+        ("node_rank", "--node-rank"),
+        ("page_size", "--kv-cache-block-size"),
+        ("tp_size", "--tensor-parallel-size"),
+    ],
+};
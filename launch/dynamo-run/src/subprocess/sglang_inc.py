@@ -13,6 +13,10 @@ from typing import Optional
 
 import sglang
 import uvloop
+from sglang.srt.managers.io_struct import (
+    LoadLoRAAdapterReqInput,
+    UnloadLoRAAdapterReqInput,
+)
 from sglang.srt.server_args import ServerArgs
 
 from dynamo.llm import ModelType, register_llm
@@ -21,6 +25,8 @@ from dynamo.runtime import DistributedRuntime, dynamo_worker
 # Only used if you run it manually from the command line
 DEFAULT_ENDPOINT = "dyn://dynamo.backend.generate"
 DEFAULT_MODEL = "Qwen/Qwen3-0.6B"
+# The admin API of the ingress loads LoRA adapters here, see lib/llm/src/lora.rs
+LORA_ENDPOINT = "lora"
 
 logging.basicConfig(level=logging.DEBUG)
 
@@ -36,11 +42,63 @@ class Config:
     base_gpu_id: int
     tensor_parallel_size: int
     kv_block_size: int
+    max_num_batched_tokens: Optional[int] = None
     context_length: int
     nnodes: int
     node_rank: int
     dist_init_addr: str
     extra_engine_args: str
+    enable_lora: bool = False
+    max_loras: Optional[int] = None
+    max_lora_rank: Optional[int] = None
+
+
+class LoraHandler:
+    """
+    Request handler for the lora endpoint: loads and unloads LoRA adapters. sglang
+    refers to a loaded adapter by its name.
+    """
+
+    def __init__(self, engine):
+        self.engine_client = engine
+        # Adapter name to its path
+        self.loras = {}
+        self.lock = asyncio.Lock()
+
+    async def load(self, lora):
+        async with self.lock:
+            if self.loras.get(lora["name"]) == lora["path"]:
+                return
+            result = await self.engine_client.tokenizer_manager.load_lora_adapter(
+                LoadLoRAAdapterReqInput(lora_name=lora["name"], lora_path=lora["path"]),
+                None,
+            )
+            if not result.success:
+                raise RuntimeError(
+                    f"Failed loading LoRA adapter {lora['name']}: "
+                    f"{result.error_message}"
+                )
+            self.loras[lora["name"]] = lora["path"]
+            logging.info(f"Loaded LoRA adapter {lora['name']} from {lora['path']}")
+
+    async def unload(self, lora_name):
+        async with self.lock:
+            if self.loras.pop(lora_name, None) is None:
+                return
+            await self.engine_client.tokenizer_manager.unload_lora_adapter(
+                UnloadLoRAAdapterReqInput(lora_name=lora_name), None
+            )
+            logging.info(f"Unloaded LoRA adapter {lora_name}")
+
+    async def control(self, request):
+        action = request["action"]
+        if action == "load":
+            await self.load(request["lora"])
+        elif action == "unload":
+            await self.unload(request["lora_name"])
+        else:
+            raise ValueError(f"Unknown LoRA action '{action}'")
+        yield {"loras": sorted(self.loras)}
 
 
 class RequestHandler:
@@ -48,20 +106,43 @@ class RequestHandler:
     Request handler for the generate endpoint
     """
 
-    def __init__(self, engine):
+    def __init__(self, engine, lora_handler=None):
         self.engine_client = engine
+        self.lora_handler = lora_handler
 
     async def generate(self, request):
-        sampling_params = {}
-        if request["sampling_options"]["temperature"] is not None:
-            sampling_params["temperature"] = request["sampling_options"]["temperature"]
         sampling_params = {
             # sglang defaults this to 128
             "max_new_tokens": request["stop_conditions"]["max_tokens"],
         }
+        sampling_options = request["sampling_options"]
+        if sampling_options["temperature"] is not None:
+            sampling_params["temperature"] = sampling_options["temperature"]
+        if sampling_options.get("seed") is not None:
+            sampling_params["sampling_seed"] = sampling_options["seed"]
+        guided_decoding = request.get("guided_decoding")
+        if guided_decoding:
+            schema = guided_decoding.get("json") or {"type": "object"}
+            sampling_params["json_schema"] = json.dumps(schema)
+        # sglang takes the images as data URLs
+        image_data = [
+            f"data:{image['mime_type']};base64,{image['data']}"
+            for image in request.get("images") or []
+        ]
+        # The ingress resolved the requested model to one of our LoRA adapters, load it
+        # on first use
+        lora_path = None
+        lora = request.get("lora")
+        if lora and self.lora_handler is not None:
+            await self.lora_handler.load(lora)
+            lora_path = lora["name"]
         num_output_tokens_so_far = 0
         gen = await self.engine_client.async_generate(
-            input_ids=request["token_ids"], sampling_params=sampling_params, stream=True
+            input_ids=request["token_ids"],
+            image_data=image_data or None,
+            sampling_params=sampling_params,
+            lora_path=lora_path,
+            stream=True,
         )
         async for res in gen:
             # res is a dict
@@ -143,6 +224,16 @@ async def init(runtime: DistributedRuntime, config: Config):
         # In practice this is always 0 because Dynamo only manages the leader
         arg_map["node_rank"] = config.node_rank
 
+    if config.max_num_batched_tokens:
+        arg_map["chunked_prefill_size"] = config.max_num_batched_tokens
+
+    if config.enable_lora:
+        arg_map["enable_lora"] = True
+    if config.max_loras:
+        arg_map["max_loras_per_batch"] = config.max_loras
+    if config.max_lora_rank:
+        arg_map["max_lora_rank"] = config.max_lora_rank
+
     if config.extra_engine_args != "":
         json_map = {}
         # extra_engine_args is a filename
@@ -170,15 +261,25 @@ async def init(runtime: DistributedRuntime, config: Config):
     )
     await register_llm(model_type, endpoint, config.model_path, config.model_name)
 
+    lora_handler = LoraHandler(engine_client) if config.enable_lora else None
+
     # the server will gracefully shutdown (i.e., keep opened TCP streams finishes)
     # after the lease is revoked
-    await endpoint.serve_endpoint(
-        RequestHandler(engine_client).generate
-        if not engine_args.is_embedding
-        else EmbeddingRequestHandler(
-            engine_client, model_name=config.model_name or config.model_path
-        ).generate
-    )
+    serving = [
+        endpoint.serve_endpoint(
+            RequestHandler(engine_client, lora_handler).generate
+            if not engine_args.is_embedding
+            else EmbeddingRequestHandler(
+                engine_client, model_name=config.model_name or config.model_path
+            ).generate,
+            description={"engine": "sglang", "engine_version": sglang.__version__},
+        )
+    ]
+    if lora_handler is not None:
+        serving.append(
+            component.endpoint(LORA_ENDPOINT).serve_endpoint(lora_handler.control)
+        )
+    await asyncio.gather(*serving)
 
 
 def cmd_line_args():
@@ -236,12 +337,35 @@ def cmd_line_args():
         default="",
         help="Host address (e.g., `192.168.0.2:25000`) of the node with rank 0",
     )
+    parser.add_argument(
+        "--max-num-batched-tokens",
+        type=int,
+        default=None,
+        help="Max number of tokens to process in one batch. Defaults to the engine's default.",
+    )
     parser.add_argument(
         "--extra-engine-args",
         type=str,
         default="",
         help="Path to a JSON file containing additional keyword arguments to pass to the SGLang Engine.",
     )
+    parser.add_argument(
+        "--enable-lora",
+        action="store_true",
+        help="Serve LoRA adapters, loaded and unloaded on the lora endpoint.",
+    )
+    parser.add_argument(
+        "--max-loras",
+        type=int,
+        default=None,
+        help="Max number of LoRA adapters in one batch. Defaults to the engine's default.",
+    )
+    parser.add_argument(
+        "--max-lora-rank",
+        type=int,
+        default=None,
+        help="Max rank of the LoRA adapters. Defaults to the engine's default.",
+    )
     args = parser.parse_args()
 
     config = Config()
@@ -268,11 +392,15 @@ def cmd_line_args():
     config.base_gpu_id = args.base_gpu_id
     config.tensor_parallel_size = args.tensor_parallel_size
     config.kv_block_size = args.kv_block_size
+    config.max_num_batched_tokens = args.max_num_batched_tokens
     config.context_length = args.context_length
     config.nnodes = args.nnodes
     config.node_rank = args.node_rank
     config.dist_init_addr = args.dist_init_addr
     config.extra_engine_args = args.extra_engine_args
+    config.enable_lora = args.enable_lora
+    config.max_loras = args.max_loras
+    config.max_lora_rank = args.max_lora_rank
     return config
 
 
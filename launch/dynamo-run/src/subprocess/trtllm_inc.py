@@ -31,6 +31,7 @@ from tensorrt_llm import SamplingParams
 from tensorrt_llm.llmapi import DisaggregatedParams
 from tensorrt_llm.llmapi.llm_utils import update_llm_args_with_extra_options
 from tensorrt_llm.llmapi.tokenizer import tokenizer_factory
+from tensorrt_llm.sampling_params import GuidedDecodingParams
 
 from dynamo.llm import (
     ModelType,
@@ -121,6 +122,7 @@ class Config:
     model_name: Optional[str] = None
     tensor_parallel_size: int
     kv_block_size: int
+    max_num_batched_tokens: Optional[int] = None
     extra_engine_args: str
     publish_events_and_metrics: bool
     disaggregation_mode: str
@@ -268,10 +270,19 @@ class RequestHandler:
             # Set the disaggregated params to generation_only for the rest of the generation
             disaggregated_params.request_type = "generation_only"
 
-        sampling_params = self.default_sampling_params
+        # Copy so per-request settings don't leak into the next request
+        sampling_params = copy.copy(self.default_sampling_params)
         for key, value in request["sampling_options"].items():
-            if not value:
+            if value is None:
                 continue
+            if key == "temperature" and value == 0:
+                # Greedy
+                sampling_params.top_k = 1
+                sampling_params.top_p = None
+                continue
+            if key == "seed" and not hasattr(sampling_params, "seed"):
+                # Older TensorRT-LLM releases
+                key = "random_seed"
             if hasattr(sampling_params, key):
                 setattr(sampling_params, key, value)
 
@@ -279,6 +290,14 @@ class RequestHandler:
         if max_tokens:
             sampling_params.max_tokens = max_tokens
 
+        guided_decoding = request.get("guided_decoding")
+        if guided_decoding:
+            # Needs a guided_decoding_backend (e.g. xgrammar) in --extra-engine-args
+            sampling_params.guided_decoding = GuidedDecodingParams(
+                json=guided_decoding.get("json"),
+                json_object=guided_decoding.get("json_object", False),
+            )
+
         # TODO: Disable streaming for context only requests when adding disagg support
         async for res in self.engine.llm.generate_async(
             inputs=inputs,
@@ -358,6 +377,9 @@ async def init(runtime: DistributedRuntime, config: Config):
         # KV routing relies on logging KV metrics
         "disable_log_stats": False,
     }
+    if config.max_num_batched_tokens:
+        arg_map["max_num_tokens"] = config.max_num_batched_tokens
+
     if config.extra_engine_args != "":
         # TODO: Support extra engine args from json file as well.
         arg_map = update_llm_args_with_extra_options(arg_map, config.extra_engine_args)
@@ -478,6 +500,12 @@ def cmd_line_args():
         default=None,
         help="This argument is not used by TRTLLM. Please provide max_input_len, max_seq_len and max_output_len in yaml file and point --extra-engine-args to the yaml file.",
     )
+    parser.add_argument(
+        "--max-num-batched-tokens",
+        type=int,
+        default=None,
+        help="Max number of tokens to process in one batch. Defaults to the engine's default.",
+    )
     parser.add_argument(
         "--extra-engine-args",
         type=str,
@@ -559,6 +587,7 @@ def cmd_line_args():
     config.endpoint = parsed_endpoint_name
     config.tensor_parallel_size = args.tensor_parallel_size
     config.kv_block_size = args.kv_block_size
+    config.max_num_batched_tokens = args.max_num_batched_tokens
     config.extra_engine_args = args.extra_engine_args
     config.publish_events_and_metrics = args.publish_events_and_metrics
     config.disaggregation_mode = disaggregation_mode
@@ -13,5 +13,135 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::engine_args::{ArgType::*, EngineArgs};
+
 /// Source code of the VLLM sub-process
 pub const PY: &str = include_str!("vllm_inc.py");
+
+/// vllm's `AsyncEngineArgs`
+pub const ENGINE_ARGS: EngineArgs = EngineArgs {
+    engine: "vllm",
+    args: &[
+        ("additional_config", Any),
+        ("allowed_local_media_path", String),
+        ("block_size", Int),
+        ("calculate_kv_scales", Bool),
+        ("code_revision", String),
+        ("collect_detailed_traces", String),
+        ("compilation_config", Any),
+        ("config_format", String),
+        ("cpu_offload_gb", Float),
+        ("data_parallel_size", Int),
+        ("device", String),
+        ("disable_async_output_proc", Bool),
+        ("disable_cascade_attn", Bool),
+        ("disable_chunked_mm_input", Bool),
+        ("disable_custom_all_reduce", Bool),
+        ("disable_log_requests", Bool),
+        ("disable_log_stats", Bool),
+        ("disable_mm_preprocessor_cache", Bool),
+        ("disable_sliding_window", Bool),
+        ("distributed_executor_backend", String),
+        ("download_dir", String),
+        (
+            "dtype",
+            Choice(&["auto", "half", "float16", "bfloat16", "float", "float32"]),
+        ),
+        ("enable_chunked_prefill", Bool),
+        ("enable_expert_parallel", Bool),
+        ("enable_lora", Bool),
+        ("enable_lora_bias", Bool),
+        ("enable_prefix_caching", Bool),
+        ("enable_prompt_adapter", Bool),
+        ("enable_reasoning", Bool),
+        ("enable_sleep_mode", Bool),
+        ("enforce_eager", Bool),
+        ("fully_sharded_loras", Bool),
+        ("generation_config", String),
+        ("gpu_memory_utilization", Float),
+        ("guided_decoding_backend", String),
+        ("hf_config_path", String),
+        ("hf_overrides", Any),
+        ("hf_token", Any),
+        ("ignore_patterns", Any),
+        ("kv_cache_dtype", String),
+        ("kv_transfer_config", Any),
+        ("limit_mm_per_prompt", Any),
+        ("load_format", String),
+        ("logits_processor_pattern", String),
+        ("long_lora_scaling_factors", Any),
+        ("long_prefill_token_threshold", Int),
+        ("lora_dtype", String),
+        ("lora_extra_vocab_size", Int),
+        ("max_cpu_loras", Int),
+        ("max_logprobs", Int),
+        ("max_long_partial_prefills", Int),
+        ("max_lora_rank", Int),
+        ("max_loras", Int),
+        ("max_model_len", Int),
+        ("max_num_batched_tokens", Int),
+        ("max_num_partial_prefills", Int),
+        ("max_num_seqs", Int),
+        ("max_parallel_loading_workers", Int),
+        ("max_prompt_adapter_token", Int),
+        ("max_prompt_adapters", Int),
+        ("max_seq_len_to_capture", Int),
+        ("mm_processor_kwargs", Any),
+        ("model", String),
+        ("model_impl", Choice(&["auto", "vllm", "transformers"])),
+        ("model_loader_extra_config", Any),
+        ("multi_step_stream_outputs", Bool),
+        ("num_gpu_blocks_override", Int),
+        ("num_lookahead_slots", Int),
+        ("num_scheduler_steps", Int),
+        ("otlp_traces_endpoint", String),
+        ("override_generation_config", Any),
+        ("override_neuron_config", Any),
+        ("override_pooler_config", Any),
+        ("pipeline_parallel_size", Int),
+        ("preemption_mode", Choice(&["recompute", "swap"])),
+        ("prefix_caching_hash_algo", String),
+        ("qlora_adapter_name_or_path", String),
+        ("quantization", String),
+        ("ray_workers_use_nsight", Bool),
+        ("reasoning_parser", String),
+        ("revision", String),
+        ("rope_scaling", Any),
+        ("rope_theta", Float),
+        ("scheduler_cls", String),
+        ("scheduler_delay_factor", Float),
+        ("scheduling_policy", Choice(&["fcfs", "priority"])),
+        ("seed", Int),
+        ("served_model_name", Any),
+        ("show_hidden_metrics_for_version", String),
+        ("skip_tokenizer_init", Bool),
+        ("speculative_config", Any),
+        ("swap_space", Float),
+        ("task", String),
+        ("tensor_parallel_size", Int),
+        ("tokenizer", String),
+        (
+            "tokenizer_mode",
+            Choice(&["auto", "slow", "mistral", "custom"]),
+        ),
+        ("tokenizer_pool_extra_config", Any),
+        ("tokenizer_pool_size", Int),
+        ("tokenizer_pool_type", String),
+        ("tokenizer_revision", String),
+        ("trust_remote_code", Bool),
+        ("use_tqdm_on_load", Bool),
+        ("use_v2_block_manager", Bool),
+        ("worker_cls", String),
+        ("worker_extension_cls", String),
+    ],
+    managed: &[
+        ("block_size", "--kv-cache-block-size"),
+        ("enable_lora", "--enable-lora"),
+        ("max_lora_rank", "--max-lora-rank"),
+        ("max_loras", "--max-loras"),
+        ("max_model_len", "--context-length"),
+        ("max_num_batched_tokens", "--max-num-batched-tokens"),
+        ("model", "--model-path"),
+        ("tensor_parallel_size", "--tensor-parallel-size"),
+    ],
+};
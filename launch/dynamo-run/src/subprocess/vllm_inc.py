@@ -11,6 +11,8 @@
 
 import argparse
 import asyncio
+import base64
+import io
 import json
 import logging
 import os
@@ -19,12 +21,16 @@ import uuid
 from typing import Optional
 
 import uvloop
+from PIL import Image
 from vllm import SamplingParams
+from vllm.config import KVTransferConfig
 from vllm.engine.arg_utils import AsyncEngineArgs
 from vllm.entrypoints.openai.api_server import (
     build_async_engine_client_from_engine_args,
 )
 from vllm.inputs import TokensPrompt
+from vllm.lora.request import LoRARequest
+from vllm.sampling_params import GuidedDecodingParams
 
 from dynamo.llm import ModelType, WorkerMetricsPublisher, register_llm
 from dynamo.runtime import DistributedRuntime, dynamo_worker
@@ -32,10 +38,20 @@ from dynamo.runtime import DistributedRuntime, dynamo_worker
 # Only used if you run it manually from the command line
 DEFAULT_ENDPOINT = "dyn://dynamo.backend.generate"
 DEFAULT_MODEL = "Qwen/Qwen3-0.6B"
+# The admin API of the ingress loads LoRA adapters here, see lib/llm/src/lora.rs
+LORA_ENDPOINT = "lora"
 
 logging.basicConfig(level=logging.DEBUG)
 
 
+def load_images(request):
+    """The images of the chat messages, decoded for vllm's multi-modal input"""
+    return [
+        Image.open(io.BytesIO(base64.b64decode(image["data"])))
+        for image in request.get("images") or []
+    ]
+
+
 class Config:
     """Command line parameters or defaults"""
 
@@ -46,8 +62,13 @@ class Config:
     model_name: Optional[str]
     tensor_parallel_size: int
     kv_block_size: int
+    max_num_batched_tokens: Optional[int] = None
     context_length: int
     extra_engine_args: str
+    disagg_role: str
+    enable_lora: bool = False
+    max_loras: Optional[int] = None
+    max_lora_rank: Optional[int] = None
 
 
 class RequestHandler:
@@ -55,10 +76,13 @@ class RequestHandler:
     Request handler for the generate endpoint
     """
 
-    def __init__(self, component, engine, default_sampling_params):
+    def __init__(
+        self, component, engine, default_sampling_params, priority_scheduling=False
+    ):
         self.component = component
         self.engine_client = engine
         self.default_sampling_params = default_sampling_params
+        self.priority_scheduling = priority_scheduling
         self.metrics_publisher = WorkerMetricsPublisher()
 
     def setup_kv_metrics(self):
@@ -92,10 +116,14 @@ class RequestHandler:
         request_id = str(uuid.uuid4().hex)
 
         prompt = TokensPrompt(prompt_token_ids=request["token_ids"])
+        images = load_images(request)
+        if images:
+            prompt["multi_modal_data"] = {"image": images}
 
         sampling_params = SamplingParams(**self.default_sampling_params)
         for key, value in request["sampling_options"].items():
-            if not value:
+            # temperature 0 (greedy) and seed 0 are set too
+            if value is None:
                 continue
             if hasattr(sampling_params, key):
                 setattr(sampling_params, key, value)
@@ -104,15 +132,48 @@ class RequestHandler:
         if max_tokens:
             sampling_params.max_tokens = max_tokens
 
+        # Disaggregated serving: what the prefill worker prepares for the decode worker, or
+        # where the decode worker fetches the KV cache. vllm's KV connector reads it.
+        kv_transfer_params = request.get("kv_transfer_params")
+        if kv_transfer_params:
+            sampling_params.extra_args = {"kv_transfer_params": kv_transfer_params}
+
+        guided_decoding = request.get("guided_decoding")
+        if guided_decoding:
+            sampling_params.guided_decoding = GuidedDecodingParams(
+                json=guided_decoding.get("json"),
+                json_object=guided_decoding.get("json_object") or None,
+            )
+
+        # vllm serves lower values first, and rejects priorities unless its scheduling
+        # policy is "priority"
+        kwargs = {}
+        priority = request.get("priority")
+        if priority is not None and self.priority_scheduling:
+            kwargs["priority"] = -priority
+
+        # The ingress resolved the requested model to one of our LoRA adapters. vllm
+        # loads it from its path if it wasn't loaded yet.
+        lora = request.get("lora")
+        if lora:
+            kwargs["lora_request"] = LoRARequest(lora["name"], lora["id"], lora["path"])
+
         num_output_tokens_so_far = 0
-        gen = self.engine_client.generate(prompt, sampling_params, request_id)
+        gen = self.engine_client.generate(
+            prompt, sampling_params, request_id, **kwargs
+        )
         async for res in gen:
             # res is vllm's RequestOutput
 
             # This is the expected way for a request to end.
             # The new token ID will be eos, don't forward it.
             if res.finished:
-                yield {"finish_reason": "stop", "token_ids": []}
+                out = {"finish_reason": "stop", "token_ids": []}
+                # A prefill worker tells the decode worker where the KV cache is
+                kv_transfer_params = getattr(res, "kv_transfer_params", None)
+                if kv_transfer_params:
+                    out["kv_transfer_params"] = kv_transfer_params
+                yield out
                 break
 
             if not res.outputs:
@@ -130,6 +191,34 @@ class RequestHandler:
             num_output_tokens_so_far = next_total_toks
 
 
+class LoraHandler:
+    """
+    Request handler for the lora endpoint: loads and unloads LoRA adapters
+    """
+
+    def __init__(self, engine):
+        self.engine_client = engine
+        # Adapter name to its LoRARequest
+        self.loras = {}
+
+    async def control(self, request):
+        action = request["action"]
+        if action == "load":
+            lora = request["lora"]
+            lora_request = LoRARequest(lora["name"], lora["id"], lora["path"])
+            await self.engine_client.add_lora(lora_request)
+            self.loras[lora["name"]] = lora_request
+            logging.info(f"Loaded LoRA adapter {lora['name']} from {lora['path']}")
+        elif action == "unload":
+            lora_request = self.loras.pop(request["lora_name"], None)
+            if lora_request is not None:
+                await self.engine_client.remove_lora(lora_request.lora_int_id)
+                logging.info(f"Unloaded LoRA adapter {request['lora_name']}")
+        else:
+            raise ValueError(f"Unknown LoRA action '{action}'")
+        yield {"loras": sorted(self.loras)}
+
+
 @dynamo_worker(static=False)
 async def worker(runtime: DistributedRuntime):
     await init(runtime, cmd_line_args())
@@ -165,6 +254,16 @@ async def init(runtime: DistributedRuntime, config: Config):
         # Usually we want it to default to the max (from tokenizer_config.json)
         arg_map["max_model_len"] = config.context_length
 
+    if config.max_num_batched_tokens:
+        arg_map["max_num_batched_tokens"] = config.max_num_batched_tokens
+
+    if config.enable_lora:
+        arg_map["enable_lora"] = True
+    if config.max_loras:
+        arg_map["max_loras"] = config.max_loras
+    if config.max_lora_rank:
+        arg_map["max_lora_rank"] = config.max_lora_rank
+
     if config.extra_engine_args != "":
         json_map = {}
         # extra_engine_args is a filename
@@ -178,6 +277,17 @@ async def init(runtime: DistributedRuntime, config: Config):
         logging.debug(f"Adding extra engine arguments: {json_map}")
         arg_map = {**arg_map, **json_map}  # json_map gets precedence
 
+    if config.disagg_role != "aggregated":
+        # Prefill and decode workers exchange the KV cache over NIXL
+        arg_map.setdefault(
+            "kv_transfer_config",
+            {"kv_connector": "NixlConnector", "kv_role": "kv_both"},
+        )
+    if isinstance(arg_map.get("kv_transfer_config"), dict):
+        arg_map["kv_transfer_config"] = KVTransferConfig(
+            **arg_map["kv_transfer_config"]
+        )
+
     # Patch won't start KVCacheEventManager unless these four are set
 
     component = runtime.namespace(config.namespace).component(config.component)
@@ -202,7 +312,7 @@ async def init(runtime: DistributedRuntime, config: Config):
     engine_client = await engine_context.__aenter__()
 
     await register_llm(
-        ModelType.Backend,
+        ModelType.Prefill if config.disagg_role == "prefill" else ModelType.Backend,
         endpoint,
         config.model_path,
         config.model_name,
@@ -211,12 +321,24 @@ async def init(runtime: DistributedRuntime, config: Config):
         ),  # if None, takes length from tokenizer
         kv_cache_block_size=arg_map["block_size"],
     )
-    handler = RequestHandler(component, engine_client, default_sampling_params)
+    handler = RequestHandler(
+        component,
+        engine_client,
+        default_sampling_params,
+        priority_scheduling=engine_args.scheduling_policy == "priority",
+    )
     handler.setup_kv_metrics()
 
     # the server will gracefully shutdown (i.e., keep opened TCP streams finishes)
     # after the lease is revoked
-    await endpoint.serve_endpoint(handler.generate)
+    if not config.enable_lora:
+        await endpoint.serve_endpoint(handler.generate)
+        return
+    lora_handler = LoraHandler(engine_client)
+    await asyncio.gather(
+        endpoint.serve_endpoint(handler.generate),
+        component.endpoint(LORA_ENDPOINT).serve_endpoint(lora_handler.control),
+    )
 
 
 def cmd_line_args():
@@ -253,12 +375,42 @@ def cmd_line_args():
         default=None,
         help="Max model context length. Defaults to models max, usually model_max_length from tokenizer_config.json. Reducing this reduces VRAM requirements.",
     )
+    parser.add_argument(
+        "--max-num-batched-tokens",
+        type=int,
+        default=None,
+        help="Max number of tokens to process in one batch. Defaults to the engine's default.",
+    )
     parser.add_argument(
         "--extra-engine-args",
         type=str,
         default="",
         help="Path to a JSON file containing additional keyword arguments to pass to the vLLM AsyncLLMEngine.",
     )
+    parser.add_argument(
+        "--disagg-role",
+        type=str,
+        choices=["aggregated", "prefill", "decode"],
+        default="aggregated",
+        help="Disaggregated serving: prefill workers prefill the long prompts of the decode workers. Default: aggregated",
+    )
+    parser.add_argument(
+        "--enable-lora",
+        action="store_true",
+        help="Serve LoRA adapters, loaded and unloaded on the lora endpoint.",
+    )
+    parser.add_argument(
+        "--max-loras",
+        type=int,
+        default=None,
+        help="Max number of LoRA adapters in one batch. Defaults to the engine's default.",
+    )
+    parser.add_argument(
+        "--max-lora-rank",
+        type=int,
+        default=None,
+        help="Max rank of the LoRA adapters. Defaults to the engine's default.",
+    )
     args = parser.parse_args()
 
     config = Config()
@@ -284,8 +436,13 @@ def cmd_line_args():
     config.endpoint = parsed_endpoint_name
     config.tensor_parallel_size = args.tensor_parallel_size
     config.kv_block_size = args.kv_block_size
+    config.max_num_batched_tokens = args.max_num_batched_tokens
     config.context_length = args.context_length
     config.extra_engine_args = args.extra_engine_args
+    config.disagg_role = args.disagg_role
+    config.enable_lora = args.enable_lora
+    config.max_loras = args.max_loras
+    config.max_lora_rank = args.max_lora_rank
 
     return config
 
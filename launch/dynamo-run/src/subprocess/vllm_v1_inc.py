@@ -14,6 +14,8 @@
 
 import argparse
 import asyncio
+import base64
+import io
 import json
 import logging
 import os
@@ -21,12 +23,15 @@ import sys
 import uuid
 from typing import Optional
 
+import torch
 import uvloop
+import vllm
+from PIL import Image
 from vllm.config import VllmConfig
 from vllm.distributed.kv_events import KVEventsConfig
 from vllm.engine.arg_utils import AsyncEngineArgs
 from vllm.inputs import TokensPrompt
-from vllm.sampling_params import SamplingParams
+from vllm.sampling_params import GuidedDecodingParams, SamplingParams
 from vllm.usage.usage_lib import UsageContext
 from vllm.v1.engine.async_llm import AsyncLLM
 from vllm.v1.metrics.loggers import StatLoggerBase
@@ -49,6 +54,14 @@ logging.basicConfig(level=logging.DEBUG)
 logger = logging.getLogger(__name__)
 
 
+def load_images(request):
+    """The images of the chat messages, decoded for vllm's multi-modal input"""
+    return [
+        Image.open(io.BytesIO(base64.b64decode(image["data"])))
+        for image in request.get("images") or []
+    ]
+
+
 class Config:
     """Command line parameters or defaults"""
 
@@ -59,6 +72,7 @@ class Config:
     model_name: Optional[str]
     tensor_parallel_size: int
     kv_block_size: int
+    max_num_batched_tokens: Optional[int] = None
     context_length: int
     extra_engine_args: str
 
@@ -66,10 +80,13 @@ class Config:
 class DynamoStatLoggerPublisher(StatLoggerBase):
     """Stat logger publisher. Wrapper for the WorkerMetricsPublisher to match the StatLoggerBase interface."""
 
-    def __init__(self, component: Component, dp_rank: int) -> None:
+    def __init__(
+        self, component: Component, dp_rank: int, kv_block_size: Optional[int] = None
+    ) -> None:
         self.inner = WorkerMetricsPublisher()
         self.inner.create_endpoint(component)
         self.dp_rank = dp_rank
+        self.kv_block_size = kv_block_size
 
     def record(
         self, scheduler_stats: SchedulerStats, iteration_stats: Optional[IterationStats]
@@ -94,6 +111,7 @@ class DynamoStatLoggerPublisher(StatLoggerBase):
             gpu_cache_usage_perc=scheduler_stats.gpu_cache_usage,  # used in current cost function
             gpu_prefix_cache_hit_rate=hit_rate,
             data_parallel_rank=self.dp_rank,
+            kv_block_size=self.kv_block_size,
         )
 
     def log_engine_initialized(self) -> None:
@@ -106,11 +124,15 @@ class StatLoggerFactory:
     def __init__(self, component: Component) -> None:
         self.component = component
 
-    def create_stat_logger(self, dp_rank: int) -> StatLoggerBase:
-        return DynamoStatLoggerPublisher(self.component, dp_rank)
+    def create_stat_logger(
+        self, dp_rank: int, kv_block_size: Optional[int] = None
+    ) -> StatLoggerBase:
+        return DynamoStatLoggerPublisher(self.component, dp_rank, kv_block_size)
 
     def __call__(self, vllm_config: VllmConfig, dp_rank: int) -> StatLoggerBase:
-        return self.create_stat_logger(dp_rank=dp_rank)
+        return self.create_stat_logger(
+            dp_rank=dp_rank, kv_block_size=vllm_config.cache_config.block_size
+        )
 
 
 class RequestHandler:
@@ -118,19 +140,26 @@ class RequestHandler:
     Request handler for the generate endpoint
     """
 
-    def __init__(self, component, engine, default_sampling_params):
+    def __init__(
+        self, component, engine, default_sampling_params, priority_scheduling=False
+    ):
         self.component = component
         self.engine_client = engine
         self.default_sampling_params = default_sampling_params
+        self.priority_scheduling = priority_scheduling
 
     async def generate(self, request):
         request_id = str(uuid.uuid4().hex)
 
         prompt = TokensPrompt(prompt_token_ids=request["token_ids"])
+        images = load_images(request)
+        if images:
+            prompt["multi_modal_data"] = {"image": images}
 
         sampling_params = SamplingParams(**self.default_sampling_params)
         for key, value in request["sampling_options"].items():
-            if not value:
+            # temperature 0 (greedy) and seed 0 are set too
+            if value is None:
                 continue
             if hasattr(sampling_params, key):
                 setattr(sampling_params, key, value)
@@ -139,8 +168,24 @@ class RequestHandler:
         if max_tokens:
             sampling_params.max_tokens = max_tokens
 
+        guided_decoding = request.get("guided_decoding")
+        if guided_decoding:
+            sampling_params.guided_decoding = GuidedDecodingParams(
+                json=guided_decoding.get("json"),
+                json_object=guided_decoding.get("json_object") or None,
+            )
+
+        # vllm serves lower values first, and rejects priorities unless its scheduling
+        # policy is "priority"
+        kwargs = {}
+        priority = request.get("priority")
+        if priority is not None and self.priority_scheduling:
+            kwargs["priority"] = -priority
+
         num_output_tokens_so_far = 0
-        gen = self.engine_client.generate(prompt, sampling_params, request_id)
+        gen = self.engine_client.generate(
+            prompt, sampling_params, request_id, **kwargs
+        )
         async for res in gen:
             # res is vllm's RequestOutput
 
@@ -207,6 +252,9 @@ async def init(runtime: DistributedRuntime, config: Config):
     if config.kv_block_size > 0:
         arg_map["block_size"] = config.kv_block_size
 
+    if config.max_num_batched_tokens:
+        arg_map["max_num_batched_tokens"] = config.max_num_batched_tokens
+
     if config.extra_engine_args != "":
         json_map = {}
         # extra_engine_args is a filename
@@ -253,11 +301,22 @@ async def init(runtime: DistributedRuntime, config: Config):
 
     _ = ZmqKvEventPublisher(component=component, config=zmq_config)
 
-    handler = RequestHandler(component, engine_client, default_sampling_params)
+    handler = RequestHandler(
+        component,
+        engine_client,
+        default_sampling_params,
+        priority_scheduling=engine_args.scheduling_policy == "priority",
+    )
 
     # the server will gracefully shutdown (i.e., keep opened TCP streams finishes)
     # after the lease is revoked
-    await endpoint.serve_endpoint(handler.generate)
+    # The ingress's worker admission policy can require an engine version
+    description = {
+        "engine": "vllm",
+        "engine_version": vllm.__version__,
+        "cuda_version": torch.version.cuda,
+    }
+    await endpoint.serve_endpoint(handler.generate, description=description)
 
 
 def cmd_line_args():
@@ -294,6 +353,12 @@ def cmd_line_args():
         default=None,
         help="Max model context length. Defaults to models max, usually model_max_length from tokenizer_config.json. Reducing this reduces VRAM requirements.",
     )
+    parser.add_argument(
+        "--max-num-batched-tokens",
+        type=int,
+        default=None,
+        help="Max number of tokens to process in one batch. Defaults to the engine's default.",
+    )
     parser.add_argument(
         "--extra-engine-args",
         type=str,
@@ -325,6 +390,7 @@ def cmd_line_args():
     config.endpoint = parsed_endpoint_name
     config.tensor_parallel_size = args.tensor_parallel_size
     config.kv_block_size = args.kv_block_size
+    config.max_num_batched_tokens = args.max_num_batched_tokens
     config.context_length = args.context_length
     config.extra_engine_args = args.extra_engine_args
 
@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The timeouts of dynamo-run, in one place.
+//!
+//! `--timeout-profile` picks a set of them for the environment dynamo-run runs in. A flag of a
+//! single timeout overrides the profile, and `DYN_WORKER_DRAIN_TIMEOUT` overrides its drain
+//! timeout.
+
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+/// A set of timeouts for an environment
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Copy)]
+pub enum TimeoutProfile {
+    /// No limits on requests, quick restarts
+    #[default]
+    Dev,
+    /// Interactive traffic: give up on slow requests early
+    ProdLatency,
+    /// Offline traffic: long requests and slow engine shutdowns are fine
+    ProdBatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Until a worker accepted an HTTP request. None means no limit.
+    pub connect: Option<Duration>,
+
+    /// Until an HTTP response is complete. None means no limit.
+    pub request: Option<Duration>,
+
+    /// Between two responses of an HTTP stream. None means no limit.
+    pub stream_idle: Option<Duration>,
+
+    /// How long a worker waits for its requests in flight when it drains
+    pub drain: Duration,
+
+    /// How long an engine sub-process has to exit after SIGTERM, before it is killed
+    pub subprocess_stop: Duration,
+}
+
+impl TimeoutProfile {
+    pub fn timeouts(self) -> Timeouts {
+        match self {
+            TimeoutProfile::Dev => Timeouts {
+                connect: None,
+                request: None,
+                stream_idle: None,
+                drain: Duration::from_secs(30),
+                subprocess_stop: Duration::from_secs(2),
+            },
+            TimeoutProfile::ProdLatency => Timeouts {
+                connect: Some(Duration::from_secs(5)),
+                request: Some(Duration::from_secs(120)),
+                stream_idle: Some(Duration::from_secs(30)),
+                drain: Duration::from_secs(30),
+                subprocess_stop: Duration::from_secs(10),
+            },
+            TimeoutProfile::ProdBatch => Timeouts {
+                connect: Some(Duration::from_secs(60)),
+                request: Some(Duration::from_secs(3600)),
+                stream_idle: Some(Duration::from_secs(300)),
+                drain: Duration::from_secs(600),
+                subprocess_stop: Duration::from_secs(30),
+            },
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        TimeoutProfile::default().timeouts()
+    }
+}
@@ -3,9 +3,9 @@
 
 use std::sync::Arc;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use dynamo_llm::discovery::{ModelManager, ModelWatcher};
+use dynamo_llm::discovery::{topology::Topology, ModelManager, ModelWatcher};
 use dynamo_llm::local_model::{LocalModel, ModelNetworkName};
 use dynamo_llm::model_type::ModelType;
 use dynamo_runtime::component::Endpoint;
@@ -117,6 +117,19 @@ enum Commands {
         #[command(subcommand)]
         command: HttpCommands,
     },
+
+    /// Print the components, worker instances and models of the namespace as a graph
+    Topology {
+        /// Graphviz, e.g. `llmctl topology | dot -Tsvg > topology.svg`, or JSON
+        #[arg(long, value_enum, default_value_t = TopologyFormat::Dot)]
+        format: TopologyFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TopologyFormat {
+    Dot,
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -209,6 +222,13 @@ async fn handle_command(runtime: Runtime, namespace: String, command: Commands)
                 }
             }
         }
+        Commands::Topology { format } => {
+            let topology = Topology::discover(&distributed, Some(&namespace)).await?;
+            match format {
+                TopologyFormat::Dot => print!("{}", topology.to_dot()),
+                TopologyFormat::Json => println!("{}", serde_json::to_string_pretty(&topology)?),
+            }
+        }
     }
     Ok(())
 }
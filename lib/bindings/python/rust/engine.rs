@@ -14,7 +14,9 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
+use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
 use pyo3_async_runtimes::TaskLocals;
 use pythonize::{depythonize, pythonize};
@@ -31,6 +33,11 @@ pub use dynamo_runtime::{
 };
 pub use serde::{Deserialize, Serialize};
 
+/// How long to wait for a python async generator that is still producing an item before closing
+/// it, checking every [`ACLOSE_RETRY_INTERVAL`]
+const ACLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const ACLOSE_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
 /// Add bingings from this crate to the provided module
 pub fn add_to_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PythonAsyncEngine>()?;
@@ -174,12 +181,16 @@ where
         //
         // Since we cannot predict the GIL contention, we will always use the blocking task and pay the
         // cost. The Python GIL is the gift that keeps on giving -- performance hits...
-        let stream = tokio::task::spawn_blocking(move || {
-            Python::with_gil(|py| {
+        let (stream, gen, locals) = tokio::task::spawn_blocking(move || {
+            Python::with_gil(|py| -> PyResult<_> {
                 let py_request = pythonize(py, &request)?;
                 let gen = generator.call1(py, (py_request,))?;
                 let locals = TaskLocals::new(event_loop.bind(py).clone());
-                pyo3_async_runtimes::tokio::into_stream_with_locals_v1(locals, gen.into_bound(py))
+                let stream = pyo3_async_runtimes::tokio::into_stream_with_locals_v1(
+                    locals.clone(),
+                    gen.clone_ref(py).into_bound(py),
+                )?;
+                Ok((stream, gen, locals))
             })
         })
         .await??;
@@ -200,8 +211,22 @@ where
 
             let mut stream = stream;
             let mut count = 0;
-
-            while let Some(item) = stream.next().await {
+            let mut exhausted = false;
+
+            loop {
+                let item = tokio::select! {
+                    item = stream.next() => item,
+                    // The caller stopped the request, e.g. the HTTP client disconnected. The
+                    // python async generator is closed below, and the engine aborts the request.
+                    _ = ctx.stopped() => {
+                        tracing::debug!(request_id, "request stopped, cancelling python async generator");
+                        break;
+                    }
+                };
+                let Some(item) = item else {
+                    exhausted = true;
+                    break;
+                };
                 count += 1;
                 tracing::trace!(
                     request_id,
@@ -256,6 +281,12 @@ where
                 }
             }
 
+            if !exhausted {
+                // Stops pulling items, so that the generator is idle and can be closed
+                drop(stream);
+                aclose(gen, locals, &request_id).await;
+            }
+
             tracing::debug!(
                 request_id,
                 "finished processing python async generator stream"
@@ -268,6 +299,43 @@ where
     }
 }
 
+/// Close a python async generator that was not run to the end, so that its `finally` blocks and
+/// `async with` exits run now, e.g. to abort the request in the engine, instead of whenever it is
+/// garbage collected. While the generator is producing an item `aclose()` fails, so we retry
+/// until it yielded it.
+async fn aclose(gen: PyObject, locals: TaskLocals, request_id: &str) {
+    let deadline = tokio::time::Instant::now() + ACLOSE_TIMEOUT;
+    loop {
+        let gen = Python::with_gil(|py| gen.clone_ref(py));
+        let locals = locals.clone();
+        let closed = tokio::task::spawn_blocking(move || {
+            Python::with_gil(|py| {
+                let coroutine = gen.bind(py).call_method0("aclose")?;
+                pyo3_async_runtimes::into_future_with_locals(&locals, coroutine)
+            })
+        })
+        .await;
+        let result = match closed {
+            Ok(Ok(closing)) => closing.await.map(|_| ()),
+            Ok(Err(e)) => Err(e),
+            Err(e) => {
+                tracing::warn!(request_id, error = %e, "failed closing python async generator");
+                return;
+            }
+        };
+        let Err(e) = result else {
+            tracing::debug!(request_id, "closed python async generator");
+            return;
+        };
+        let running = Python::with_gil(|py| e.is_instance_of::<PyRuntimeError>(py));
+        if !running || tokio::time::Instant::now() >= deadline {
+            tracing::warn!(request_id, error = %e, "failed closing python async generator");
+            return;
+        }
+        tokio::time::sleep(ACLOSE_RETRY_INTERVAL).await;
+    }
+}
+
 async fn process_item<Resp>(
     item: Result<Py<PyAny>, PyErr>,
 ) -> Result<Annotated<Resp>, ResponseProcessingError>
@@ -109,6 +109,8 @@ fn register_llm<'p>(
         ModelType::Completion => llm_rs::model_type::ModelType::Completion,
         ModelType::Backend => llm_rs::model_type::ModelType::Backend,
         ModelType::Embedding => llm_rs::model_type::ModelType::Embedding,
+        ModelType::Prefill => llm_rs::model_type::ModelType::Prefill,
+        ModelType::Draft => llm_rs::model_type::ModelType::Draft,
     };
 
     let inner_path = model_path.to_string();
@@ -196,6 +198,8 @@ enum ModelType {
     Completion = 2,
     Backend = 3,
     Embedding = 4,
+    Prefill = 5,
+    Draft = 6,
 }
 
 #[pymethods]
@@ -434,18 +438,28 @@ impl Component {
 
 #[pymethods]
 impl Endpoint {
-    #[pyo3(signature = (generator))]
+    #[pyo3(signature = (generator, max_concurrent_requests=None, description=None))]
     fn serve_endpoint<'p>(
         &self,
         py: Python<'p>,
         generator: PyObject,
+        max_concurrent_requests: Option<u32>,
+        description: Option<PyObject>,
     ) -> PyResult<Bound<'p, PyAny>> {
+        let description: Option<rs::component::WorkerDescription> = description
+            .map(|description| pythonize::depythonize(&description.into_bound(py)))
+            .transpose()?;
         let engine = Arc::new(engine::PythonAsyncEngine::new(
             generator,
             self.event_loop.clone(),
         )?);
         let ingress = JsonServerStreamingIngress::for_engine(engine).map_err(to_pyerr)?;
-        let builder = self.inner.endpoint_builder().handler(ingress);
+        let builder = self
+            .inner
+            .endpoint_builder()
+            .max_concurrent_requests(max_concurrent_requests)
+            .description(description)
+            .handler(ingress);
         pyo3_async_runtimes::tokio::future_into_py(py, async move {
             builder.start().await.map_err(to_pyerr)?;
             Ok(())
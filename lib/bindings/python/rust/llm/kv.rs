@@ -35,10 +35,17 @@ impl KvRouter {
     fn new(component: Component, kv_block_size: usize) -> PyResult<Self> {
         let runtime = pyo3_async_runtimes::tokio::get_runtime();
         runtime.block_on(async {
-            let inner =
-                llm_rs::kv_router::KvRouter::new(component.inner.clone(), kv_block_size, None)
-                    .await
-                    .map_err(to_pyerr)?;
+            let inner = llm_rs::kv_router::KvRouter::new(
+                component.inner.clone(),
+                kv_block_size,
+                None,
+                Default::default(),
+                Default::default(),
+                None,
+                Default::default(),
+            )
+            .await
+            .map_err(to_pyerr)?;
             Ok(Self {
                 inner: Arc::new(inner),
             })
@@ -60,6 +67,26 @@ impl KvRouter {
             Ok(worker_id)
         })
     }
+
+    fn explain<'p>(
+        &self,
+        py: Python<'p>,
+        token_ids: Vec<u32>,
+        lora_id: u64,
+    ) -> PyResult<Bound<'p, PyAny>> {
+        let router = self.inner.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let explanation = router
+                .explain(&token_ids, lora_id)
+                .await
+                .map_err(to_pyerr)?;
+            Python::with_gil(|py| {
+                pythonize::pythonize(py, &explanation)
+                    .map(|obj| obj.unbind())
+                    .map_err(to_pyerr)
+            })
+        })
+    }
 }
 
 #[pyclass]
@@ -96,7 +123,7 @@ impl WorkerMetricsPublisher {
     }
 
     #[allow(clippy::too_many_arguments)]
-    #[pyo3(signature = (request_active_slots, request_total_slots, kv_active_blocks, kv_total_blocks, num_requests_waiting, gpu_cache_usage_perc, gpu_prefix_cache_hit_rate, data_parallel_rank = 0))]
+    #[pyo3(signature = (request_active_slots, request_total_slots, kv_active_blocks, kv_total_blocks, num_requests_waiting, gpu_cache_usage_perc, gpu_prefix_cache_hit_rate, data_parallel_rank = 0, decode_tokens_per_sec = None, lora_ids = None, kv_block_size = None))]
     fn publish(
         &self,
         _py: Python,
@@ -108,6 +135,9 @@ impl WorkerMetricsPublisher {
         gpu_cache_usage_perc: f32,
         gpu_prefix_cache_hit_rate: f32,
         data_parallel_rank: u32,
+        decode_tokens_per_sec: Option<f32>,
+        lora_ids: Option<Vec<u64>>,
+        kv_block_size: Option<u32>,
     ) -> PyResult<()> {
         self.inner
             .publish(
@@ -120,6 +150,9 @@ impl WorkerMetricsPublisher {
                     num_requests_waiting,
                     gpu_cache_usage_perc,
                     gpu_prefix_cache_hit_rate,
+                    decode_tokens_per_sec,
+                    lora_ids,
+                    kv_block_size,
                 }
                 .into(),
             )
@@ -310,14 +343,16 @@ impl KvIndexer {
             // should have been made to a trait and implemented here? i.e. AsyncEngine style
             tokio::spawn(async move {
                 while let Some(event) = kv_events_rx.next().await {
-                    let event: llm_rs::kv_router::indexer::RouterEvent =
-                        serde_json::from_slice(&event.payload).unwrap();
-                    tracing::debug!("received kv event: {:?}", event);
-                    if let Err(e) = kv_events_tx.send(event).await {
-                        tracing::trace!(
-                            "failed to send kv event to indexer; shutting down: {:?}",
-                            e
-                        );
+                    let events =
+                        llm_rs::kv_router::indexer::decode_router_events(&event.payload).unwrap();
+                    for event in events {
+                        tracing::debug!("received kv event: {:?}", event);
+                        if let Err(e) = kv_events_tx.send(event).await {
+                            tracing::trace!(
+                                "failed to send kv event to indexer; shutting down: {:?}",
+                                e
+                            );
+                        }
                     }
                 }
             });
@@ -333,14 +368,16 @@ impl KvIndexer {
         &self,
         py: Python<'p>,
         token_ids: Vec<u32>,
-        _lora_id: u64,
+        lora_id: u64,
     ) -> PyResult<Bound<'p, PyAny>> {
         let indexer = self.inner.clone();
         pyo3_async_runtimes::tokio::future_into_py(py, async move {
-            let rs_overlap_scores = indexer
-                .find_matches_for_request(token_ids.as_slice())
-                .await
-                .map_err(to_pyerr)?;
+            let sequence = llm_rs::kv_router::indexer::compute_block_hash_for_seq_with_lora(
+                &token_ids,
+                indexer.block_size(),
+                lora_id,
+            );
+            let rs_overlap_scores = indexer.find_matches(sequence).await.map_err(to_pyerr)?;
             Ok(OverlapScores {
                 inner: rs_overlap_scores,
             })
@@ -367,6 +404,9 @@ pub(crate) struct EndpointKvMetrics {
     pub gpu_cache_usage_perc: f32,
     #[pyo3(get, set)]
     pub gpu_prefix_cache_hit_rate: f32,
+    /// Share of the prompt blocks routed to the worker it had cached, None before any request
+    #[pyo3(get, set)]
+    pub kv_hit_rate: Option<f64>,
 }
 
 #[pyclass]
@@ -378,6 +418,9 @@ pub(crate) struct AggregatedMetrics {
     pub load_avg: f64,
     #[pyo3(get, set)]
     pub load_std: f64,
+    /// Of all the requests routed to the workers
+    #[pyo3(get, set)]
+    pub kv_hit_rate: Option<f64>,
 }
 
 #[pyclass]
@@ -404,6 +447,7 @@ impl KvMetricsAggregator {
 
     fn get_metrics<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyAny>> {
         let endpoints = self.inner.get_endpoints();
+        let hit_rates = self.inner.hit_rates();
         let endpoint_kv_metrics = endpoints
             .endpoints
             .iter()
@@ -416,6 +460,10 @@ impl KvMetricsAggregator {
                 num_requests_waiting: x.data.num_requests_waiting,
                 gpu_cache_usage_perc: x.data.gpu_cache_usage_perc,
                 gpu_prefix_cache_hit_rate: x.data.gpu_prefix_cache_hit_rate,
+                kv_hit_rate: hit_rates
+                    .workers
+                    .get(worker_id)
+                    .and_then(|hit_rate| hit_rate.hit_rate()),
             })
             .collect();
         pyo3_async_runtimes::tokio::future_into_py(py, async move {
@@ -423,6 +471,7 @@ impl KvMetricsAggregator {
                 endpoints: endpoint_kv_metrics,
                 load_avg: endpoints.load_avg,
                 load_std: endpoints.load_std,
+                kv_hit_rate: hit_rates.total.hit_rate(),
             })
         })
     }
@@ -481,14 +530,16 @@ impl KvRecorder {
             // Spawn a task to forward events to the recorder
             tokio::spawn(async move {
                 while let Some(event) = kv_events_rx.next().await {
-                    let event: llm_rs::kv_router::indexer::RouterEvent =
-                        serde_json::from_slice(&event.payload).unwrap();
-                    tracing::debug!("KvRecorder received kv event: {:?}", event);
-                    if let Err(e) = event_tx.send(event).await {
-                        tracing::trace!(
-                            "KvRecorder failed to send kv event; shutting down: {:?}",
-                            e
-                        );
+                    let events =
+                        llm_rs::kv_router::indexer::decode_router_events(&event.payload).unwrap();
+                    for event in events {
+                        tracing::debug!("KvRecorder received kv event: {:?}", event);
+                        if let Err(e) = event_tx.send(event).await {
+                            tracing::trace!(
+                                "KvRecorder failed to send kv event; shutting down: {:?}",
+                                e
+                            );
+                        }
                     }
                 }
             });
@@ -228,10 +228,21 @@ class Endpoint:
 
     ...
 
-    async def serve_endpoint(self, handler: RequestHandler) -> None:
+    async def serve_endpoint(
+        self,
+        handler: RequestHandler,
+        max_concurrent_requests: Optional[int] = None,
+        description: Optional[Dict[str, str]] = None,
+    ) -> None:
         """
         Serve an endpoint discoverable by all connected clients at
         `{{ namespace }}/components/{{ component_name }}/endpoints/{{ endpoint_name }}`
+
+        Routers send it at most `max_concurrent_requests` requests at once, if given.
+
+        `description` tells the routers what the worker runs, with the keys `engine`,
+        `engine_version`, `build`, `cuda_version` and `driver_version`, all optional. The
+        ingress's worker admission policy uses it to keep outdated workers out of routing.
         """
         ...
 
@@ -291,6 +302,14 @@ class KvRouter:
         """
         ...
 
+    def explain(self, token_ids: List[int], lora_id: int) -> Dict[str, Any]:
+        """
+        Return which worker `schedule` would pick for the given token ids and why:
+        `worker_id`, `reason`, `isl_blocks`, and `workers` with the cached blocks,
+        load and score of every worker, best first. Nothing is scheduled.
+        """
+        ...
+
 class DisaggregatedRouter:
     """
     A router that determines whether to perform prefill locally or remotely based on
@@ -372,9 +391,16 @@ class WorkerMetricsPublisher:
         gpu_cache_usage_perc: float,
         gpu_prefix_cache_hit_rate: float,
         data_parallel_rank: int = 0,
+        decode_tokens_per_sec: Optional[float] = None,
+        lora_ids: Optional[List[int]] = None,
+        kv_block_size: Optional[int] = None,
     ) -> None:
         """
-        Update the KV metrics being reported.
+        Update the KV metrics being reported. `decode_tokens_per_sec`, the tokens generated per
+        second recently, is used by the balanced KV router. `lora_ids`, the LoRA adapters
+        loaded, lets the KV router send requests for an adapter to workers that have it.
+        `kv_block_size`, the tokens per KV block, lets the KV router match the KV events of
+        workers whose block size differs from its own.
         """
         ...
 
@@ -540,7 +566,9 @@ class KvMetricsAggregator:
 
     def get_metrics(self) -> AggregatedMetrics:
         """
-        Return the aggregated metrics of the endpoints.
+        Return the aggregated metrics of the endpoints. `kv_hit_rate`, of each endpoint and of
+        all of them, is the share of the prompt blocks the KV routers of the namespace sent to a
+        worker that had them cached, since the aggregator was created.
         """
         ...
 
@@ -638,7 +666,7 @@ class HttpAsyncEngine:
     ...
 
 class ModelType:
-    """What type of request this model needs: Chat, Component or Backend (pre-processed)"""
+    """What type of request this model needs: Chat, Component, Backend (pre-processed) Prefill (pre-processed, prefill only for disaggregated serving) or Draft (pre-processed, the draft model of speculative decoding)"""
     ...
 
 async def register_llm(model_type: ModelType, endpoint: Endpoint, model_path: str, model_name: Optional[str] = None, context_length: Optional[int] = None, kv_cache_block_size: Optional[int] = None) -> None:
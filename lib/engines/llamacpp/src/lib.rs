@@ -12,7 +12,7 @@ use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseSt
 use dynamo_runtime::pipeline::error as pipeline_error;
 use dynamo_runtime::pipeline::{async_trait, Error, ManyOut, SingleIn};
 use dynamo_runtime::protocols::annotated::Annotated;
-use dynamo_runtime::{CancellationToken, ErrorContext, Result};
+use dynamo_runtime::{raise, CancellationToken, ErrorContext, Result};
 use llama_cpp_2::{
     context::{params::LlamaContextParams, LlamaContext},
     llama_backend::LlamaBackend,
@@ -39,11 +39,100 @@ static LLAMA_CONTEXTS: [OnceLock<Mutex<ContextWrapper>>; NUM_CONTEXTS] =
 
 static LLAMA_CPP_LOG_REDIRECT: Once = Once::new();
 
-// Newtype to simplify LlamaContext lifetime
-#[derive(Debug)]
-struct ContextWrapper(LlamaContext<'static>);
-unsafe impl Send for ContextWrapper {} // LlamaContext has a NonNull which is !Send
-unsafe impl Sync for ContextWrapper {} // LlamaContext has a NonNull which is !Sync
+// Newtype to simplify LlamaContext lifetime, with what the context holds between requests
+struct ContextWrapper {
+    ctx: LlamaContext<'static>,
+    /// The tokens in the KV cache, by position. A request that starts with them only decodes
+    /// the rest, e.g. the next round of speculative decoding.
+    cached: Vec<LlamaToken>,
+    /// The sampler of the request that left `cached`, for the request that continues it
+    sampler: Option<LlamaSampler>,
+}
+// LlamaContext and LlamaSampler have NonNulls which are !Send and !Sync. A context is only used
+// under its Mutex, by one request at a time.
+unsafe impl Send for ContextWrapper {}
+unsafe impl Sync for ContextWrapper {}
+
+impl ContextWrapper {
+    fn new(ctx: LlamaContext<'static>) -> Self {
+        ContextWrapper {
+            ctx,
+            cached: Vec::new(),
+            sampler: None,
+        }
+    }
+
+    /// How many of the first `tokens` are in the KV cache
+    fn common_prefix(&self, tokens: &[LlamaToken]) -> usize {
+        self.cached
+            .iter()
+            .zip(tokens)
+            .take_while(|(cached, token)| cached == token)
+            .count()
+    }
+
+    /// Keep the first `len` tokens in the KV cache, drop the rest
+    fn truncate(&mut self, len: usize) {
+        if len == 0 {
+            self.reset();
+            return;
+        }
+        if len >= self.cached.len() {
+            return;
+        }
+        match self.ctx.clear_kv_cache_seq(Some(0), Some(len as u32), None) {
+            Ok(true) => self.cached.truncate(len),
+            // A partial removal the cache can't do, start over
+            Ok(false) | Err(_) => self.reset(),
+        }
+    }
+
+    /// Empty the KV cache, for an unrelated request or after a failure
+    fn reset(&mut self) {
+        self.ctx.clear_kv_cache();
+        self.cached.clear();
+        self.sampler = None;
+    }
+}
+
+/// The contexts not running a request
+struct FreeContexts {
+    slots: tokio::sync::Semaphore,
+    free: Mutex<Vec<usize>>,
+}
+
+impl FreeContexts {
+    fn new() -> Self {
+        FreeContexts {
+            slots: tokio::sync::Semaphore::new(0),
+            free: Mutex::new(Vec::with_capacity(NUM_CONTEXTS)),
+        }
+    }
+
+    /// Wait for a free context, the one whose KV cache holds most of `tokens`
+    async fn take(&self, tokens: &[LlamaToken]) -> usize {
+        // safety: The semaphore is never closed
+        self.slots.acquire().await.unwrap().forget();
+        let mut free = self.free.lock().unwrap();
+        let best = (0..free.len())
+            .max_by_key(|i| {
+                // Free contexts are not locked by anyone else
+                LLAMA_CONTEXTS[free[*i]]
+                    .get()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .common_prefix(tokens)
+            })
+            .unwrap(); // safety: There was a permit, so there is a free context
+        free.swap_remove(best)
+    }
+
+    fn give_back(&self, ctx_pos: usize) {
+        self.free.lock().unwrap().push(ctx_pos);
+        self.slots.add_permits(1);
+    }
+}
 
 pub async fn make_engine(
     cancel_token: CancellationToken,
@@ -76,7 +165,7 @@ impl LlamacppEngine {
         let model = load_model(&backend, model_config.path())?;
         LLAMA_MODEL.set(model)?;
 
-        let (ctx_set, ctx_get) = tokio::sync::mpsc::channel(NUM_CONTEXTS);
+        let free_contexts = Arc::new(FreeContexts::new());
         let llama_ctx_params = if model_config.card().context_length > 0 {
             let n_ctx = NonZeroU32::new(model_config.card().context_length as u32);
             LlamaContextParams::default().with_n_ctx(n_ctx)
@@ -90,14 +179,14 @@ impl LlamacppEngine {
                 .unwrap() // Safety: We put it in a few lines up
                 .new_context(&backend, llama_ctx_params.clone())
                 .with_context(|| "unable to create the llama_context")?;
-            let _ = ctx_holder.set(Mutex::new(ContextWrapper(llama_ctx)));
-            let _ = ctx_set.send(i).await;
+            let _ = ctx_holder.set(Mutex::new(ContextWrapper::new(llama_ctx)));
+            free_contexts.give_back(i);
         }
         LLAMA_BACKEND.set(backend)?;
 
         let (req_tx, req_rx) = tokio::sync::mpsc::channel(2);
         let ct = cancel_token.clone();
-        tokio::task::spawn(worker(ct, req_rx, ctx_get, ctx_set));
+        tokio::task::spawn(worker(ct, req_rx, free_contexts));
 
         Ok(LlamacppEngine {
             cancel_token,
@@ -168,8 +257,7 @@ impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutpu
 async fn worker(
     cancel_token: CancellationToken,
     mut req_rx: tokio::sync::mpsc::Receiver<WorkRequest>,
-    mut ctx_get: tokio::sync::mpsc::Receiver<usize>,
-    ctx_set: tokio::sync::mpsc::Sender<usize>,
+    free_contexts: Arc<FreeContexts>,
 ) {
     loop {
         let maybe_work_request = tokio::select! {
@@ -185,33 +273,41 @@ async fn worker(
             break;
         };
         // will block if there are already NUM_CONTEXTS requests in flight
-        let Some(ctx_pos) = ctx_get.recv().await else {
-            unreachable!("We don't close ctx_set");
-        };
+        let tokens = to_llama_tokens(&work_request.request.token_ids);
+        let ctx_pos = free_contexts.take(&tokens).await;
         let ct = cancel_token.clone();
-        let inner_ctx_set = ctx_set.clone();
+        let free_contexts = free_contexts.clone();
 
         tokio::task::spawn_blocking(move || {
             let mut ctx = LLAMA_CONTEXTS[ctx_pos].get().unwrap().lock().unwrap();
             if let Err(err) = run_request(ct, work_request, &mut ctx) {
                 tracing::error!("run_request error: {err:#}");
+                ctx.reset();
             }
-            let _ = inner_ctx_set.blocking_send(ctx_pos);
+            ctx.ctx.reset_timings();
+            drop(ctx);
+            free_contexts.give_back(ctx_pos);
         });
     }
 }
 
+fn to_llama_tokens(token_ids: &[u32]) -> Vec<LlamaToken> {
+    token_ids
+        .iter()
+        .map(|u| LlamaToken::new(*u as i32))
+        .collect()
+}
+
 fn run_request(
     cancel_token: CancellationToken,
     work_request: WorkRequest,
     llama_context: &mut ContextWrapper,
 ) -> Result<()> {
-    let tokens_list: Vec<LlamaToken> = work_request
-        .request
-        .token_ids
-        .into_iter()
-        .map(|u| LlamaToken::new(u as i32))
-        .collect();
+    let tokens_list = to_llama_tokens(&work_request.request.token_ids);
+    let draft_tokens = to_llama_tokens(&work_request.request.draft_token_ids);
+    if tokens_list.is_empty() {
+        raise!("Request has no prompt tokens");
+    }
 
     let limit = DEFAULT_MAX_TOKENS; // - prompt_tokens;
     let max_output_tokens = std::cmp::min(
@@ -223,31 +319,70 @@ fn run_request(
         limit,
     );
 
+    // Keep the KV cache of the prompt's start, the last prompt token is decoded again for its
+    // logits. A request that continues all of the cache, like the next round of speculative
+    // decoding, continues its sampler too.
+    let common = llama_context
+        .common_prefix(&tokens_list)
+        .min(tokens_list.len() - 1);
+    let continues = common > 0 && common == llama_context.cached.len();
+    llama_context.truncate(common);
+    let mut sampler = match llama_context.sampler.take() {
+        Some(sampler) if continues => sampler,
+        _ => LlamaSampler::greedy(),
+    };
+
     // we use this object to submit token data for decoding
-    let mut batch = LlamaBatch::new(std::cmp::max(512, max_output_tokens as usize), 1);
+    let batch_size = std::cmp::max(512, max_output_tokens as usize)
+        .max(tokens_list.len() - common + draft_tokens.len());
+    let mut batch = LlamaBatch::new(batch_size, 1);
     let last_index: i32 = (tokens_list.len() - 1) as i32;
-    for (i, token) in (0_i32..).zip(tokens_list.into_iter()) {
+    for (i, token) in (0_i32..).zip(tokens_list.iter()).skip(common) {
         // llama_decode will output logits only for the last token of the prompt
         let is_last = i == last_index;
         batch
-            .add(token, i, &[0], is_last)
+            .add(*token, i, &[0], is_last)
             .with_context(|| format!("Failed adding token pos {i} to batch"))?;
     }
+    // And for each draft token, to check them all in this one pass
+    for (i, token) in (last_index + 1..).zip(draft_tokens.iter()) {
+        batch
+            .add(*token, i, &[0], true)
+            .with_context(|| format!("Failed adding draft token pos {i} to batch"))?;
+    }
 
     // "decode" means "run forward pass"
     llama_context
-        .0
+        .ctx
         .decode(&mut batch)
         .with_context(|| "llama_decode failed on first pass")?;
-
-    let mut sampler = LlamaSampler::greedy();
-    let mut n_cur = batch.n_tokens() as u32;
+    llama_context.cached.truncate(common);
+    llama_context
+        .cached
+        .extend(tokens_list[common..].iter().chain(&draft_tokens));
+
+    if !draft_tokens.is_empty() {
+        // The logits of the last prompt token and of each draft token, in order
+        let first_logits = batch.n_tokens() - 1 - draft_tokens.len() as i32;
+        let accepted = verify_drafts(
+            &work_request,
+            llama_context,
+            &mut sampler,
+            first_logits,
+            &draft_tokens,
+        )?;
+        // Only the rejected drafts leave the cache, the next round continues from the rest
+        llama_context.truncate(tokens_list.len() + accepted);
+        llama_context.sampler = Some(sampler);
+        return Ok(());
+    }
+    let mut n_cur = last_index as u32 + 1;
 
     let mut used_output_tokens = 0;
     while !cancel_token.is_cancelled() {
         // sample the next token
         let n_tokens = batch.n_tokens();
-        let token = sampler.sample(&llama_context.0, n_tokens - 1);
+        let token = sampler.sample(&llama_context.ctx, n_tokens - 1);
         sampler.accept(token);
 
         // is it an end of stream?
@@ -269,6 +404,7 @@ fn run_request(
             cum_log_probs: None, // TODO output.cumulative_logprob.map(|v| v as f64),
             log_probs: None,     // TODO  output.logprobs
             finish_reason: None,
+            kv_transfer_params: None,
         };
         work_request
             .response_channel
@@ -297,9 +433,10 @@ fn run_request(
         }
 
         llama_context
-            .0
+            .ctx
             .decode(&mut batch)
             .with_context(|| "llama_decode failed during loop")?;
+        llama_context.cached.push(token);
     }
     if cancel_token.is_cancelled() {
         let _ = work_request
@@ -307,9 +444,51 @@ fn run_request(
             .blocking_send(Annotated::from_data(LLMEngineOutput::stop()));
     }
 
-    // Clean context for next use
-    llama_context.0.clear_kv_cache();
-    llama_context.0.reset_timings();
+    // The KV cache stays for a request that starts the same way
+    llama_context.sampler = Some(sampler);
 
     Ok(())
 }
+
+/// Speculative decoding: send the draft tokens the model generates too, then one token of its
+/// own, see `dynamo_llm::speculative`. The batch at `first_logits` and after has the logits of
+/// the last prompt token and of each draft token. Returns how many drafts were accepted.
+fn verify_drafts(
+    work_request: &WorkRequest,
+    llama_context: &ContextWrapper,
+    sampler: &mut LlamaSampler,
+    first_logits: i32,
+    draft_tokens: &[LlamaToken],
+) -> Result<usize> {
+    let mut accepted = 0;
+    for (i, draft) in (first_logits..).zip(draft_tokens.iter().map(Some).chain([None])) {
+        let token = sampler.sample(&llama_context.ctx, i);
+        sampler.accept(token);
+        if LLAMA_MODEL.get().unwrap().is_eog_token(token) {
+            work_request
+                .response_channel
+                .blocking_send(Annotated::from_data(LLMEngineOutput::stop()))
+                .with_context(|| "Failed sending stop to response_channel")?;
+            return Ok(accepted);
+        }
+        let engine_out = LLMEngineOutput {
+            token_ids: vec![token.0 as u32],
+            finish_reason: None,
+            ..LLMEngineOutput::stop()
+        };
+        work_request
+            .response_channel
+            .blocking_send(Annotated::from_data(engine_out))
+            .with_context(|| "Failed forwarding engine output to response_channel")?;
+        if draft != Some(&token) {
+            break;
+        }
+        accepted += 1;
+    }
+    // The next round starts from the tokens sent so far
+    work_request
+        .response_channel
+        .blocking_send(Annotated::from_data(LLMEngineOutput::length()))
+        .with_context(|| "Failed sending length to response_channel")?;
+    Ok(accepted)
+}
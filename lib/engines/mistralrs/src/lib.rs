@@ -79,18 +79,21 @@ impl MistralRsEngine {
         let display_name = model.display_name();
         let loader = if model_path.is_file() {
             // Load from a GGUF
-            let Some(model_filename) = model_path.file_name() else {
-                pipeline_error::bail!("Missing filename in model path");
-            };
             let Some(model_dir) = model_path.parent() else {
                 pipeline_error::bail!("Invalid model path");
             };
+            // All the files of a split GGUF
+            let model_filenames = dynamo_llm::gguf::gguf_files(model_path)?
+                .iter()
+                .filter_map(|path| path.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect();
 
             GGUFLoaderBuilder::new(
                 chat_template,
                 None,
                 model_dir.display().to_string(),
-                vec![model_filename.to_string_lossy().into_owned()],
+                model_filenames,
                 GGUFSpecificConfig {
                     prompt_chunksize: None,
                     topology: None,
@@ -413,7 +416,7 @@ impl
                             system_fingerprint: Some(c.system_fingerprint),
                             service_tier: None,
                         };
-                        let delta = NvCreateChatCompletionStreamResponse{inner};
+                        let delta = NvCreateChatCompletionStreamResponse{inner, nvext: None};
                         let ann = Annotated{
                             id: None,
                             data: Some(delta),
@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! How fast the KV indexer applies the events of many workers, by number of shards.
+//!
+//! Run with `cargo bench -p dynamo-llm --bench kv_indexer`.
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dynamo_llm::kv_router::{
+    indexer::{KvIndexerInterface, KvIndexerSharded, RouterEvent, WorkerId},
+    protocols::{
+        ExternalSequenceBlockHash, KvCacheEvent, KvCacheEventData, KvCacheRemoveData,
+        KvCacheStoreData, KvCacheStoredBlockData, LocalBlockHash,
+    },
+};
+use tokio_util::sync::CancellationToken;
+
+const WORKERS: i64 = 256;
+/// Sequences each worker stores, then removes
+const SEQUENCES: u64 = 32;
+const BLOCKS_PER_SEQUENCE: u64 = 16;
+const KV_BLOCK_SIZE: usize = 32;
+
+/// The events of all workers, interleaved as they'd arrive. Each worker stores a sequence and
+/// removes it, so that the index stays small. Workers share the prefixes of their sequences.
+fn events() -> Vec<RouterEvent> {
+    let mut events = Vec::new();
+    for sequence in 0..SEQUENCES {
+        for worker_id in 0..WORKERS {
+            let block_hash = |block: u64| {
+                ExternalSequenceBlockHash((worker_id as u64) << 32 | sequence << 16 | block)
+            };
+            let stored = KvCacheEventData::Stored(KvCacheStoreData {
+                parent_hash: None,
+                blocks: (0..BLOCKS_PER_SEQUENCE)
+                    .map(|block| KvCacheStoredBlockData {
+                        block_hash: block_hash(block),
+                        tokens_hash: LocalBlockHash(sequence << 16 | block),
+                    })
+                    .collect(),
+            });
+            let removed = KvCacheEventData::Removed(KvCacheRemoveData {
+                block_hashes: (0..BLOCKS_PER_SEQUENCE).map(block_hash).collect(),
+            });
+            events.push(event(worker_id, 2 * sequence, stored));
+            events.push(event(worker_id, 2 * sequence + 1, removed));
+        }
+    }
+    events
+}
+
+fn event(worker_id: WorkerId, event_id: u64, data: KvCacheEventData) -> RouterEvent {
+    RouterEvent::new(worker_id, KvCacheEvent { event_id, data })
+}
+
+/// Time to send `events` to an indexer of `shards` shards and for it to apply them
+async fn apply(shards: usize, events: Vec<RouterEvent>) -> Duration {
+    let token = CancellationToken::new();
+    let mut indexer = KvIndexerSharded::new(token, shards, KV_BLOCK_SIZE);
+    let sender = indexer.event_sender();
+    let start = Instant::now();
+    for event in events {
+        sender.send(event).await.unwrap();
+    }
+    // Each shard answers after it applied the events before the request
+    indexer.snapshot().await.unwrap();
+    let elapsed = start.elapsed();
+    indexer.shutdown();
+    elapsed
+}
+
+fn bench_sharded_events(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let events = events();
+    let mut group = c.benchmark_group("kv_indexer_events");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    for shards in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(shards),
+            &shards,
+            |b, &shards| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| runtime.block_on(apply(shards, events.clone())))
+                        .sum()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sharded_events);
+criterion_main!(benches);
@@ -13,8 +13,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-fn main() {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:warning=Building with CUDA KV off");
+
+    // The gRPC frontend's API. protox parses the proto, so protoc doesn't need to be installed.
+    println!("cargo:rerun-if-changed=proto");
+    let file_descriptors = protox::compile(["proto/openai.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(file_descriptors)?;
+    Ok(())
 }
 
 // NOTE: Preserving this build.rs for reference. We may want to re-enable
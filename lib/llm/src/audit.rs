@@ -0,0 +1,628 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Opt-in audit log of full requests and responses, for compliance reviews and abuse
+//! investigations. Unlike the [`crate::request_log`] this keeps the prompts and the generated
+//! text, so it is off unless configured, and a [`Redaction`] removes the fields that must not be
+//! kept before a record leaves the process.
+//!
+//! The HTTP service audits the OpenAI requests as the clients sent them, and a worker audits the
+//! requests it receives on its endpoint, preprocessed or not, with [`AuditEngine`]. A sample rate
+//! below 1 audits that share of the requests, picked at random.
+//!
+//! Records go to [`AuditSink`]s in the background:
+//! - [`RotatingFileSink`] writes JSON lines to files in a directory, starting a new file when one
+//!   is full and deleting the oldest,
+//! - [`NatsSink`] publishes them on a NATS subject.
+//!
+//! When the sinks can't keep up, records are dropped with a warning rather than slowing down
+//! requests.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, Data, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, ServiceEngine, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::protocols::common::llm_backend::BackendOutput;
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionStreamResponse;
+use crate::protocols::openai::completions::CompletionResponse;
+use crate::protocols::TokenIdType;
+use crate::token_timing::{TokenTimestamps, ANNOTATION_TOKEN_TIMESTAMPS};
+
+/// How long a sink gets to take a record
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Records waiting for the sinks. Further records are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// Replaces the value of a redacted field
+pub const REDACTED: &str = "[redacted]";
+
+/// Fields to remove from audit records, as dotted paths from the root of the record, where `*`
+/// matches every key of an object or element of an array. For example
+/// `request.messages.*.content` redacts the messages of a chat request, `response.text` the
+/// generated text and `principal` the API key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Redaction {
+    paths: Vec<Vec<String>>,
+}
+
+impl FromStr for Redaction {
+    type Err = anyhow::Error;
+
+    /// Comma separated paths
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut paths = Vec::new();
+        for path in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let parts: Vec<String> = path.split('.').map(str::to_string).collect();
+            if parts.iter().any(String::is_empty) {
+                anyhow::bail!("Invalid redaction path '{path}'");
+            }
+            paths.push(parts);
+        }
+        Ok(Redaction { paths })
+    }
+}
+
+impl Redaction {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Replace the fields in `value` that match a path with [`REDACTED`]. Paths that don't exist
+    /// are ignored.
+    pub fn apply(&self, value: &mut Value) {
+        for path in &self.paths {
+            redact(value, path);
+        }
+    }
+}
+
+fn redact(value: &mut Value, path: &[String]) {
+    let Some((first, rest)) = path.split_first() else {
+        *value = Value::String(REDACTED.to_string());
+        return;
+    };
+    match (value, first.as_str()) {
+        (Value::Object(map), "*") => map.values_mut().for_each(|v| redact(v, rest)),
+        (Value::Array(items), "*") => items.iter_mut().for_each(|v| redact(v, rest)),
+        (Value::Object(map), key) => {
+            if let Some(v) = map.get_mut(key) {
+                redact(v, rest);
+            }
+        }
+        (Value::Array(items), index) => {
+            if let Some(v) = index.parse().ok().and_then(|i: usize| items.get_mut(i)) {
+                redact(v, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    Success,
+    Error,
+    /// The caller went away before the response was complete
+    Cancelled,
+}
+
+/// The response as audited: the text and, from workers, the token ids
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditedResponse {
+    pub text: String,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub token_ids: Vec<TokenIdType>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+
+    /// When each token was generated on the worker, if the request asked for
+    /// [`ANNOTATION_TOKEN_TIMESTAMPS`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_timestamps: Option<TokenTimestamps>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub request_id: String,
+
+    /// When the request ended
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+
+    /// Where the request was audited: the HTTP endpoint, e.g. `chat_completions`, or the worker's
+    /// endpoint path
+    pub source: String,
+
+    /// The request's `model`, if it has one
+    pub model: Option<String>,
+
+    /// From `nvext.principal`
+    pub principal: Option<String>,
+
+    /// The whole request
+    pub request: Value,
+
+    pub response: AuditedResponse,
+    pub status: AuditStatus,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    pub latency_ms: u64,
+}
+
+/// A response chunk that adds to the audited response
+pub trait AuditResponse {
+    fn audit(&self, response: &mut AuditedResponse);
+}
+
+impl AuditResponse for NvCreateChatCompletionStreamResponse {
+    /// Only the first choice
+    fn audit(&self, response: &mut AuditedResponse) {
+        for choice in self.inner.choices.iter().filter(|c| c.index == 0) {
+            if let Some(content) = &choice.delta.content {
+                response.text.push_str(content);
+            }
+            if let Some(reason) = choice.finish_reason {
+                response.finish_reason = serde_json::to_value(reason)
+                    .ok()
+                    .and_then(|reason| reason.as_str().map(str::to_string));
+            }
+        }
+    }
+}
+
+impl AuditResponse for CompletionResponse {
+    /// Only the first choice
+    fn audit(&self, response: &mut AuditedResponse) {
+        for choice in self.choices.iter().filter(|c| c.index == 0) {
+            response.text.push_str(&choice.text);
+            if let Some(reason) = &choice.finish_reason {
+                response.finish_reason = Some(reason.clone());
+            }
+        }
+    }
+}
+
+impl AuditResponse for BackendOutput {
+    fn audit(&self, response: &mut AuditedResponse) {
+        if let Some(text) = &self.text {
+            response.text.push_str(text);
+        }
+        response.token_ids.extend_from_slice(&self.token_ids);
+        if let Some(reason) = &self.finish_reason {
+            response.finish_reason = Some(reason.to_string());
+        }
+    }
+}
+
+/// Somewhere to send audit records, after redaction
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn write(&self, record: &Value) -> anyhow::Result<()>;
+}
+
+/// Samples requests for auditing and sends their records to the sinks in the background
+pub struct AuditLogger {
+    sample_rate: f64,
+    tx: mpsc::Sender<AuditRecord>,
+}
+
+impl AuditLogger {
+    /// Audit `sample_rate` of the requests, from 0 to 1. Must be called on a Tokio runtime,
+    /// which delivers the records.
+    pub fn new(
+        sample_rate: f64,
+        redaction: Redaction,
+        sinks: Vec<Arc<dyn AuditSink>>,
+    ) -> anyhow::Result<Arc<Self>> {
+        if !(0.0..=1.0).contains(&sample_rate) {
+            anyhow::bail!("Audit sample rate must be between 0 and 1, got {sample_rate}");
+        }
+        let (tx, mut rx) = mpsc::channel::<AuditRecord>(QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some(record) = rx.recv().await {
+                let request_id = record.request_id.clone();
+                let mut value = match serde_json::to_value(record) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        tracing::error!(%err, request_id, "Failed serializing audit record");
+                        continue;
+                    }
+                };
+                redaction.apply(&mut value);
+                for sink in &sinks {
+                    match tokio::time::timeout(SEND_TIMEOUT, sink.write(&value)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => {
+                            tracing::error!(%err, request_id, "Failed writing audit record")
+                        }
+                        Err(_) => tracing::error!(request_id, "Timeout writing audit record"),
+                    }
+                }
+            }
+        });
+        Ok(Arc::new(AuditLogger { sample_rate, tx }))
+    }
+
+    fn sampled(&self) -> bool {
+        self.sample_rate >= 1.0 || rand::random::<f64>() < self.sample_rate
+    }
+
+    fn record(&self, record: AuditRecord) {
+        if let Err(err) = self.tx.try_send(record) {
+            tracing::warn!(
+                request_id = err.into_inner().request_id,
+                "Audit sinks are behind, dropping audit record"
+            );
+        }
+    }
+}
+
+/// Audits one request, sends its record when dropped
+pub struct AuditTrail {
+    logger: Arc<AuditLogger>,
+    record: AuditRecord,
+    start: Instant,
+    /// The engine accepted the request
+    started: bool,
+    finished: bool,
+}
+
+impl AuditTrail {
+    /// Start auditing a request if there is a logger and the request is sampled. The model and
+    /// principal are read from the request's `model` and `nvext.principal`.
+    pub fn start<Req: Serialize>(
+        logger: Option<&Arc<AuditLogger>>,
+        request_id: &str,
+        source: &str,
+        request: &Req,
+    ) -> Option<AuditTrail> {
+        let logger = logger.filter(|logger| logger.sampled())?.clone();
+        let request = match serde_json::to_value(request) {
+            Ok(request) => request,
+            Err(err) => {
+                tracing::error!(%err, request_id, "Failed serializing request for the audit log");
+                return None;
+            }
+        };
+        let string = |pointer: &str| request.pointer(pointer).and_then(Value::as_str);
+        Some(AuditTrail {
+            record: AuditRecord {
+                request_id: request_id.to_string(),
+                timestamp: chrono::Utc::now(),
+                source: source.to_string(),
+                model: string("/model").map(str::to_string),
+                principal: string("/nvext/principal").map(str::to_string),
+                response: AuditedResponse::default(),
+                status: AuditStatus::Success,
+                error: None,
+                latency_ms: 0,
+                request,
+            },
+            logger,
+            start: Instant::now(),
+            started: false,
+            finished: false,
+        })
+    }
+
+    /// The engine failed to start generating
+    pub fn fail(mut self, err: &Error) {
+        self.record.error = Some(format!("{err:#}"));
+    }
+
+    fn observe<T: AuditResponse>(&mut self, annotated: &Annotated<T>) {
+        if let Some(data) = &annotated.data {
+            data.audit(&mut self.record.response);
+        }
+        if annotated.event.as_deref() == Some(ANNOTATION_TOKEN_TIMESTAMPS) {
+            self.record.response.token_timestamps = annotated
+                .comment
+                .as_ref()
+                .and_then(|c| c.first())
+                .and_then(|json| serde_json::from_str(json).ok());
+        }
+        if annotated.is_error() {
+            self.record.status = AuditStatus::Error;
+            self.record.error = annotated.comment.as_ref().map(|c| c.join(", "));
+        }
+    }
+}
+
+impl Drop for AuditTrail {
+    fn drop(&mut self) {
+        let mut record = self.record.clone();
+        if !self.started {
+            record.status = AuditStatus::Error;
+        } else if !self.finished && record.status == AuditStatus::Success {
+            record.status = AuditStatus::Cancelled;
+        }
+        record.timestamp = chrono::Utc::now();
+        record.latency_ms = self.start.elapsed().as_millis() as u64;
+        self.logger.record(record);
+    }
+}
+
+/// Audit the responses of `stream` with `trail`, if there is one. The record is sent when the
+/// stream ends or is dropped.
+pub fn track<T: AuditResponse + Send + 'static>(
+    trail: Option<AuditTrail>,
+    stream: ManyOut<Annotated<T>>,
+) -> ManyOut<Annotated<T>> {
+    let Some(mut trail) = trail else {
+        return stream;
+    };
+    trail.started = true;
+    let ctx = stream.context();
+    let tracked = async_stream::stream! {
+        let mut stream = stream;
+        while let Some(annotated) = stream.next().await {
+            trail.observe(&annotated);
+            yield annotated;
+        }
+        trail.finished = true;
+    };
+    ResponseStream::new(Box::pin(tracked), ctx)
+}
+
+/// Wraps a worker's engine and audits the requests it handles
+pub struct AuditEngine<Req, Resp> {
+    inner: ServiceEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>>,
+    logger: Arc<AuditLogger>,
+    source: String,
+}
+
+impl<Req, Resp> AuditEngine<Req, Resp>
+where
+    Req: Data + Serialize,
+    Resp: Data + AuditResponse,
+{
+    /// `source` names the endpoint in the records
+    pub fn new(
+        inner: ServiceEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>>,
+        logger: Arc<AuditLogger>,
+        source: String,
+    ) -> ServiceEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>> {
+        Arc::new(AuditEngine {
+            inner,
+            logger,
+            source,
+        })
+    }
+}
+
+#[async_trait]
+impl<Req, Resp> AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error>
+    for AuditEngine<Req, Resp>
+where
+    Req: Data + Serialize,
+    Resp: Data + AuditResponse,
+{
+    async fn generate(&self, request: SingleIn<Req>) -> Result<ManyOut<Annotated<Resp>>, Error> {
+        let trail = AuditTrail::start(Some(&self.logger), request.id(), &self.source, &*request);
+        match self.inner.generate(request).await {
+            Ok(stream) => Ok(track(trail, stream)),
+            Err(err) => {
+                if let Some(trail) = trail {
+                    trail.fail(&err);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Writes the records as JSON lines to files in a directory. Starts a new file when the current
+/// one would grow over `max_bytes`, and then deletes the oldest audit files beyond `max_files`.
+pub struct RotatingFileSink {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    current: Mutex<Option<(tokio::fs::File, u64)>>,
+}
+
+impl RotatingFileSink {
+    pub async fn new(dir: &Path, max_bytes: u64, max_files: usize) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(dir).await.map_err(|err| {
+            anyhow::anyhow!(
+                "Failed creating audit log directory {}: {err}",
+                dir.display()
+            )
+        })?;
+        Ok(RotatingFileSink {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_files: max_files.max(1),
+            current: Mutex::new(None),
+        })
+    }
+
+    /// Start a new file and delete the oldest ones. The names sort by creation time.
+    async fn rotate(&self) -> anyhow::Result<tokio::fs::File> {
+        let path = self.dir.join(format!(
+            "audit-{}-{}.jsonl",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            std::process::id()
+        ));
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("audit-") && name.ends_with(".jsonl") {
+                names.push(name);
+            }
+        }
+        names.sort();
+        let excess = names.len().saturating_sub(self.max_files);
+        for name in &names[..excess] {
+            if let Err(err) = tokio::fs::remove_file(self.dir.join(name)).await {
+                tracing::warn!(%err, name, "Failed deleting old audit log");
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[async_trait]
+impl AuditSink for RotatingFileSink {
+    async fn write(&self, record: &Value) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut current = self.current.lock().await;
+        let full = match current.as_ref() {
+            Some((_, written)) => *written > 0 && written + line.len() as u64 > self.max_bytes,
+            None => true,
+        };
+        if full {
+            *current = Some((self.rotate().await?, 0));
+        }
+        // safety: Set above if there was none
+        let (file, written) = current.as_mut().unwrap();
+        file.write_all(&line).await?;
+        file.flush().await?;
+        *written += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Publishes the records as JSON on a NATS subject
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsSink {
+    pub fn new(client: async_nats::Client, subject: String) -> Self {
+        NatsSink { client, subject }
+    }
+}
+
+#[async_trait]
+impl AuditSink for NatsSink {
+    async fn write(&self, record: &Value) -> anyhow::Result<()> {
+        let payload = serde_json::to_vec(record)?;
+        self.client
+            .publish(self.subject.clone(), payload.into())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Passes the records on as they are written
+    struct Collect(mpsc::UnboundedSender<Value>);
+
+    #[async_trait]
+    impl AuditSink for Collect {
+        async fn write(&self, record: &Value) -> anyhow::Result<()> {
+            self.0.send(record.clone())?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_redaction() {
+        let redaction: Redaction = "request.messages.*.content, principal, request.missing.x"
+            .parse()
+            .unwrap();
+        let mut record = json!({
+            "principal": "key-1234",
+            "request": {"model": "m", "messages": [
+                {"role": "system", "content": "secret"},
+                {"role": "user", "content": "also secret"}
+            ]}
+        });
+        redaction.apply(&mut record);
+        assert_eq!(
+            record,
+            json!({
+                "principal": REDACTED,
+                "request": {"model": "m", "messages": [
+                    {"role": "system", "content": REDACTED},
+                    {"role": "user", "content": REDACTED}
+                ]}
+            })
+        );
+        assert!("request..content".parse::<Redaction>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_audit_engine_stream() {
+        let (tx, mut written) = mpsc::unbounded_channel();
+        let sink = Arc::new(Collect(tx));
+        let logger =
+            AuditLogger::new(1.0, "response.text".parse().unwrap(), vec![sink.clone()]).unwrap();
+        let request = json!({"model": "m", "nvext": {"principal": "key-1"}, "prompt": "hi"});
+        let trail = AuditTrail::start(Some(&logger), "req-1", "completions", &request);
+
+        let output = BackendOutput {
+            token_ids: vec![7, 8],
+            tokens: vec![],
+            text: Some("hello".to_string()),
+            cum_log_probs: None,
+            log_probs: None,
+            finish_reason: None,
+        };
+        let stream = futures::stream::iter(vec![Annotated::from_data(output)]);
+        let ctx = Arc::new(dynamo_runtime::pipeline::context::Controller::default());
+        let stream = track(trail, ResponseStream::new(Box::pin(stream), ctx));
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 1);
+
+        let value = tokio::time::timeout(Duration::from_secs(5), written.recv())
+            .await
+            .expect("audit record not written")
+            .unwrap();
+        assert!(written.try_recv().is_err());
+        let record: AuditRecord = serde_json::from_value(value).unwrap();
+        assert_eq!(record.principal.as_deref(), Some("key-1"));
+        assert_eq!(record.model.as_deref(), Some("m"));
+        assert_eq!(record.status, AuditStatus::Success);
+        assert_eq!(record.response.text, REDACTED);
+        assert_eq!(record.response.token_ids, vec![7, 8]);
+        assert_eq!(record.request["prompt"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = RotatingFileSink::new(dir.path(), 10, 2).await.unwrap();
+        for i in 0..4 {
+            sink.write(&json!({"i": i})).await.unwrap();
+            // File names have millisecond resolution
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let mut names: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        let last = std::fs::read_to_string(dir.path().join(&names[1])).unwrap();
+        assert_eq!(last, "{\"i\":3}\n");
+    }
+}
@@ -24,16 +24,20 @@
 //! manager of all resources and concurrent tasks surrounding the LLM execution context / forward pass.
 //!
 //! For almost every known scenario, detokenization and initial post processing must happen in the Backend.
-//! Further post-processing can happen in the response stream. One example is the jailing mechanism for partial
-//! hidden stop condition matches, which can be handled in the response stream rather than the backend.
+//! Further post-processing can happen in the response stream.
+//!
+//! The Backend applies the hidden stop conditions of the request, so they hold for engines that don't
+//! check them. Text that could be the start of a stop sequence is jailed until the following tokens
+//! tell whether it is one; on a match the stream is truncated before the stop sequence and ends with
+//! `finish_reason: stop`.
 
 use std::{collections::HashSet, sync::Arc};
 
-use anyhow::{Error, Result};
+use anyhow::Result;
 use futures::stream::{self, StreamExt};
 use tracing as log;
 
-use crate::model_card::model::{ModelDeploymentCard, TokenizerKind};
+use crate::model_card::model::ModelDeploymentCard;
 use dynamo_runtime::{
     pipeline::{
         async_trait, AsyncEngineContextProvider, ManyOut, Operator, ResponseStream,
@@ -49,6 +53,7 @@ use crate::protocols::{
     },
     TokenIdType,
 };
+use crate::tokenizers::registry::{self, SharedTokenizer};
 use crate::tokenizers::{DecodeStream, HuggingFaceTokenizer, Tokenizer};
 use tokenizers::Tokenizer as HfTokenizer;
 
@@ -63,6 +68,7 @@ pub type ExecutionContext = ServerStreamingEngine<PreprocessedRequest, Execution
 pub struct Backend {
     pub tokenizer: Option<Tokenizer>, // Handles token encoding/decoding
     validate_engine_decode: bool,     // Enable validation of engine decoding
+    loading: Option<Arc<SharedTokenizer>>, // Wait for this before decoding
 }
 
 /// Internal state for managing token decoding and stream processing
@@ -71,6 +77,12 @@ struct DecoderUnfoldState {
     stream: ManyOut<ExecutionOutputStream>,
     decoder: Decoder,
     validate_engine_decode: bool,
+
+    // a stop condition matched, further text from the engine is dropped
+    finished: bool,
+
+    // the engine's stream ended or failed
+    ended: bool,
 }
 
 impl Backend {
@@ -81,23 +93,25 @@ impl Backend {
         Ok(Arc::new(Self {
             tokenizer: Some(tokenizer),
             validate_engine_decode: false,
+            loading: None,
         }))
     }
 
     pub async fn from_mdc(mdc: ModelDeploymentCard) -> Result<Arc<Self>> {
-        let tokenizer = match &mdc.tokenizer {
-            Some(TokenizerKind::HfTokenizerJson(file)) => {
-                HfTokenizer::from_file(file).map_err(Error::msg)?
-            }
-            Some(TokenizerKind::GGUF(t)) => *t.clone(),
-            None => {
-                return Ok(Arc::new(Self {
-                    tokenizer: None,
-                    validate_engine_decode: false,
-                }));
-            }
-        };
-        Self::from_tokenizer(tokenizer).await
+        if !mdc.has_tokenizer() {
+            return Ok(Arc::new(Self {
+                tokenizer: None,
+                validate_engine_decode: false,
+                loading: None,
+            }));
+        }
+        // Shared with the preprocessor when both are in this process
+        let tokenizer = registry::load(&mdc)?;
+        Ok(Arc::new(Self {
+            tokenizer: Some(Tokenizer::from(tokenizer.clone())),
+            validate_engine_decode: false,
+            loading: Some(tokenizer),
+        }))
     }
 
     fn decoder(
@@ -114,10 +128,88 @@ impl Backend {
             stream,
             decoder,
             validate_engine_decode: self.validate_engine_decode,
+            finished: false,
+            ended: false,
         })
     }
 }
 
+impl DecoderUnfoldState {
+    /// Decode the token ids of `output`, if the engine didn't, and apply the stop conditions
+    fn process_output(
+        &mut self,
+        mut output: ExecutionOutputStream,
+    ) -> Result<ExecutionOutputStream> {
+        let Some(mut data) = output.data.take() else {
+            return Ok(output);
+        };
+
+        let engine_decoded = data.text.is_some() && !self.validate_engine_decode;
+        if engine_decoded && !self.decoder.has_stop_sequences() {
+            output.data = Some(data);
+            return Ok(output);
+        }
+
+        let result = if engine_decoded {
+            let text = data.text.take().unwrap_or_default();
+            self.decoder.process_text(&text, data.token_ids.len())
+        } else {
+            self.decoder.process_token_ids(&data.token_ids)?
+        };
+
+        // todo - propagate finish reason details - possibly an annotation
+        let finish_reason = match &result.stop_trigger {
+            Some(StopTrigger::MaxTokensLimit) => Some(FinishReason::Length),
+            Some(StopTrigger::HiddenStopTokenDetected(_)) => Some(FinishReason::Stop),
+            Some(StopTrigger::HiddenStopSequenceDetected(_)) => Some(FinishReason::Stop),
+            None => None,
+        };
+
+        if finish_reason.is_some() {
+            if data.finish_reason.is_none() {
+                tracing::debug!(
+                    ?result.stop_trigger,
+                    "upstream did not provide a finish reason; issuing a stop_generation request to free resources",
+                );
+                self.stream.context().stop_generating();
+            }
+            self.finished = true;
+        }
+
+        let mut text = result.text;
+
+        if self.validate_engine_decode {
+            if data.finish_reason != finish_reason {
+                log::warn!(
+                    "finish reason mismatch: expected {:?}, got {:?}",
+                    data.finish_reason,
+                    finish_reason
+                );
+            }
+
+            if data.text.is_some() && data.text != text {
+                log::warn!("text mismatch: expected {:?}, got {:?}", data.text, text);
+            }
+        }
+
+        // the engine is done, release what the decoder held back
+        if result.stop_trigger.is_none() && data.finish_reason.is_some() {
+            if let Some(jailed) = self.decoder.flush() {
+                text.get_or_insert_with(String::new).push_str(&jailed);
+            }
+        }
+
+        data.finish_reason = finish_reason.or(data.finish_reason);
+        data.text = text;
+        if !engine_decoded {
+            data.tokens = Some(result.tokens);
+        }
+
+        output.data = Some(data);
+        Ok(output)
+    }
+}
+
 #[async_trait]
 impl
     Operator<
@@ -132,6 +224,9 @@ impl
         request: SingleIn<PreprocessedRequest>,
         next: ServerStreamingEngine<PreprocessedRequest, Annotated<LLMEngineOutput>>,
     ) -> Result<ManyOut<Annotated<BackendOutput>>> {
+        if let Some(tokenizer) = self.loading.as_ref() {
+            tokenizer.wait().await?;
+        }
         let stop_conditions = request.stop_conditions.clone();
         let next_stream = next.generate(request).await?;
 
@@ -139,76 +234,45 @@ impl
         let state = self.decoder(next_stream, stop_conditions)?;
 
         let processed_stream = stream::unfold(state, |mut state| async move {
-            match state.stream.next().await {
-                Some(output) => {
-                    // move to state.process_output
-                    // handle any error conditions / unwraps here
-
-                    // events are pass thru
-                    if output.is_event() || output.data.is_none() {
+            loop {
+                if state.ended {
+                    return None;
+                }
+                match state.stream.next().await {
+                    // events are pass thru, also after a stop condition matched: annotations like
+                    // the token timestamps follow the last token
+                    Some(output) if output.is_event() || output.data.is_none() => {
                         return Some((output, state));
                     }
-
-                    // if we have a data field without an event, then we might need to update the data
-                    if let Some(data) = &output.data {
-                        if data.text.is_some() && !state.validate_engine_decode {
-                            return Some((output, state));
-                        }
-                    }
-
-                    let data = output.data.as_ref().unwrap();
-
-                    let result = state.decoder.process_token_ids(&data.token_ids).unwrap();
-
-                    // todo - propagate finish reason details - possibly an annotation
-                    let finish_reason = match &result.stop_trigger {
-                        Some(StopTrigger::MaxTokensLimit) => Some(FinishReason::Length),
-                        Some(StopTrigger::HiddenStopTokenDetected(_)) => Some(FinishReason::Stop),
-                        Some(StopTrigger::HiddenStopSequenceDetected(_)) => {
-                            Some(FinishReason::Stop)
-                        }
-                        None => None,
-                    };
-
-                    if data.finish_reason.is_none() && finish_reason.is_some() {
-                        tracing::debug!(
-                            ?result.stop_trigger,
-                            "upstream did not provide a finish reason; issuing a stop_generation request to free resources",
-                        );
-                        state.stream.context().stop_generating();
+                    // a stop condition matched, anything else the engine produces is dropped
+                    Some(_) if state.finished => continue,
+                    Some(output) => {
+                        return match state.process_output(output) {
+                            Ok(output) => Some((output, state)),
+                            Err(err) => {
+                                state.ended = true;
+                                Some((Annotated::from_error(err.to_string()), state))
+                            }
+                        };
                     }
-
-                    let text = result.text;
-                    let tokens = result.tokens;
-
-                    if state.validate_engine_decode {
-                        if data.finish_reason != finish_reason {
-                            log::warn!(
-                                "finish reason mismatch: expected {:?}, got {:?}",
-                                data.finish_reason,
-                                finish_reason
-                            );
-                        }
-
-                        if data.text.is_some() && data.text != text {
-                            log::warn!("text mismatch: expected {:?}, got {:?}", data.text, text);
-                        }
+                    None if state.finished => return None,
+                    // release text held back as a possible start of a stop sequence
+                    None => {
+                        state.ended = true;
+                        let text = state.decoder.flush()?;
+                        state.finished = true;
+                        let output = LLMEngineOutput {
+                            token_ids: vec![],
+                            tokens: None,
+                            text: Some(text),
+                            cum_log_probs: None,
+                            log_probs: None,
+                            finish_reason: None,
+                            kv_transfer_params: None,
+                        };
+                        return Some((Annotated::from_data(output), state));
                     }
-
-                    // update output in-place
-                    let mut output = output;
-                    let mut data = output.data.take().unwrap();
-
-                    data.finish_reason = finish_reason;
-                    data.text = text;
-                    data.tokens = Some(tokens);
-
-                    output.data = Some(data);
-
-                    Some((output, state))
                 }
-
-                None => None,
             }
         });
 
@@ -257,14 +321,11 @@ pub struct Decoder {
     // number of generated tokens
     generated_tokens: u32,
 
-    // content jailed by partial hidden stop matches
+    // decoded text held back because it could be the start of a hidden stop sequence
     jail: String,
 
     // maximum number of bytes for the largest stop sequence
     jail_max_bytes: usize,
-
-    // the number of bytes currently jailed
-    jailed_bytes: usize,
     // mdcsum
     //mdcsum: String,
 }
@@ -288,6 +349,8 @@ impl StopTrigger {
 }
 
 pub struct StepResult {
+    /// The text to show for the step. Hidden stop conditions and text held back as a possible
+    /// start of a stop sequence are not part of it.
     pub token: Option<String>,
     pub stop_trigger: Option<StopTrigger>,
 }
@@ -332,8 +395,8 @@ impl Decoder {
         let hidden_stop_sequences: Vec<String> = stop_condition
             .stop
             .unwrap_or_default()
-            .iter()
-            .map(|x| x.to_string())
+            .into_iter()
+            .filter(|x| !x.is_empty())
             .collect();
 
         let jail_max_bytes = hidden_stop_sequences
@@ -352,7 +415,6 @@ impl Decoder {
             generated_tokens: 0,
             jail: String::new(),
             jail_max_bytes,
-            jailed_bytes: 0,
         }
     }
 
@@ -375,58 +437,85 @@ impl Decoder {
         }
 
         // check for hidden stop tokens - eos takes precedence
+        // the token is hidden, what the jail held back was not a stop sequence
         if self.hidden_stop_ids.contains(&token_id) {
             return Ok(StepResult::with_stop_trigger(
-                token,
+                self.flush(),
                 StopTrigger::HiddenStopTokenDetected(token_id),
             ));
         }
 
-        // check stop sequences - the jail will always hold at least the largest stop sequence
-        // if jail_max_bytes is 0, then there are no stop sequences
-        if self.jail_max_bytes > 0 {
-            if let Some(token) = &token {
-                let pre_append = self.jail.len();
-                log::debug!("pre_append: {}", pre_append);
-                log::debug!("jail: {}", self.jail);
-                self.jail.push_str(token);
-                log::debug!("post_append: {}", self.jail.len());
-                log::debug!("jail: {}", self.jail);
-
-                for seq in &self.hidden_stop_sequences {
-                    log::debug!("stop seq: {}", seq);
-                    if let Some(offset) =
-                        galil_seiferas::gs_find(self.jail.as_bytes(), seq.as_bytes())
-                    {
-                        log::debug!("offset: {}", offset);
-                        // return only new bytes after pre_append .. offset+seq.len()
-                        // example: seq = "ox", token = "boxes", return "b"
-                        // note: this changes when we start jailing tokens for partial matches
-                        // on the suffix of the jail with prefixes of the stop sequences
-                        //
-                        // we might have returned a partial match, if so, then offset < pre_append
-                        // in that case, we return the empty string
-                        let partial_token = if offset >= pre_append {
-                            self.jail[pre_append..offset].to_string()
-                        } else {
-                            "".to_string()
-                        };
-                        return Ok(StepResult::with_stop_trigger(
-                            Some(partial_token),
-                            StopTrigger::HiddenStopSequenceDetected(seq.to_string()),
-                        ));
-                    }
-                }
+        // text sequences are checked on what the jail held back plus this token
+        let Some(token) = token else {
+            return Ok(StepResult::ok(None));
+        };
+        let (text, stop_sequence) = self.scan(&token);
+        match stop_sequence {
+            Some(seq) => Ok(StepResult::with_stop_trigger(
+                Some(text),
+                StopTrigger::HiddenStopSequenceDetected(seq),
+            )),
+            None => Ok(StepResult::ok(Some(text))),
+        }
+    }
 
-                if self.jail.len() > self.jail_max_bytes {
-                    // truncate the jail
-                    let drain_len = self.jail.len() - self.jail_max_bytes;
-                    self.jail.drain(0..drain_len);
-                }
-            }
+    /// Append `text` to the jail and look for the stop sequences in it.
+    ///
+    /// Returns the text that can be released, and the stop sequence that matched, if one did. On a
+    /// match, the text is everything before the stop sequence and the rest is dropped. Otherwise,
+    /// the end of the text that could be the start of a stop sequence stays in the jail until the
+    /// next call tells whether it is one.
+    fn scan(&mut self, text: &str) -> (String, Option<String>) {
+        if !self.has_stop_sequences() {
+            return (text.to_string(), None);
         }
+        self.jail.push_str(text);
 
-        Ok(StepResult::ok(token))
+        // the earliest match wins, a stop sequence can span any number of tokens
+        let matched = self
+            .hidden_stop_sequences
+            .iter()
+            .filter_map(|seq| {
+                galil_seiferas::gs_find(self.jail.as_bytes(), seq.as_bytes())
+                    .map(|offset| (offset, seq))
+            })
+            .min_by_key(|(offset, _)| *offset);
+        if let Some((offset, seq)) = matched {
+            log::debug!(offset, stop = %seq, "stop sequence detected");
+            let seq = seq.clone();
+            let mut released = std::mem::take(&mut self.jail);
+            released.truncate(offset);
+            return (released, Some(seq));
+        }
+
+        // keep the longest suffix that is a prefix of a stop sequence
+        let held = self
+            .jail
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .find(|idx| {
+                let suffix = &self.jail[*idx..];
+                self.hidden_stop_sequences
+                    .iter()
+                    .any(|seq| seq.starts_with(suffix))
+            })
+            .unwrap_or(self.jail.len());
+        let jailed = self.jail.split_off(held);
+        (std::mem::replace(&mut self.jail, jailed), None)
+    }
+
+    pub fn has_stop_sequences(&self) -> bool {
+        self.jail_max_bytes > 0
+    }
+
+    /// Release the text held back as a possible start of a stop sequence, when no more text will
+    /// follow it
+    pub fn flush(&mut self) -> Option<String> {
+        if self.jail.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.jail))
+        }
     }
 
     pub fn process_token_ids(&mut self, token_ids: &[TokenIdType]) -> Result<SeqResult> {
@@ -439,13 +528,8 @@ impl Decoder {
                 stop_trigger,
             } = self.step(*token_id)?;
 
-            let hide_text = stop_trigger
-                .as_ref()
-                .map(|x| x.should_hide_text())
-                .unwrap_or(false);
-
-            if !hide_text {
-                if let Some(token) = &token {
+            if let Some(token) = &token {
+                if !token.is_empty() {
                     text.get_or_insert_with(String::new).push_str(token);
                 }
             }
@@ -467,6 +551,25 @@ impl Decoder {
         })
     }
 
+    /// Like [`Decoder::process_token_ids`], for engines that decode themselves. Only stop
+    /// sequences are checked, `num_tokens` counts towards the minimum number of tokens.
+    pub fn process_text(&mut self, text: &str, num_tokens: usize) -> SeqResult {
+        self.generated_tokens += num_tokens as u32;
+        if self.generated_tokens < self.min_tokens {
+            return SeqResult {
+                tokens: vec![],
+                text: Some(text.to_string()),
+                stop_trigger: None,
+            };
+        }
+        let (text, stop_sequence) = self.scan(text);
+        SeqResult {
+            tokens: vec![],
+            text: (!text.is_empty()).then_some(text),
+            stop_trigger: stop_sequence.map(StopTrigger::HiddenStopSequenceDetected),
+        }
+    }
+
     fn return_token(&self, token: Option<String>) -> StepResult {
         StepResult {
             token,
@@ -484,13 +587,4 @@ impl Decoder {
             stop_trigger: Some(stop_trigger),
         }
     }
-
-    fn jailed_string(&self) -> Option<String> {
-        if self.jailed_bytes > 0 {
-            // get the last jailed_bytes from the jail
-            Some(self.jail[self.jail.len() - self.jailed_bytes..].to_string())
-        } else {
-            None
-        }
-    }
 }
@@ -0,0 +1,278 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Route over pools of workers that cost differently, e.g. spot and on-demand capacity.
+//!
+//! Workers advertise what an hour of their GPU costs with their instance, `cost_per_gpu_hour`.
+//! A [`BudgetPolicy`] sets for each request class, from `nvext.routing.request_class`, how much
+//! queueing it takes for a lower cost. A request goes to the cheapest worker that has fewer than
+//! `max_inflight` of this router's requests in flight, the least loaded one of that price. When
+//! no worker has that headroom, it goes to the least loaded worker, the cheapest one between
+//! equals: once the cheap pools are full, latency wins over cost. Workers that don't tell their
+//! cost, or advertise one that isn't a finite number of at least 0, are taken for the most
+//! expensive.
+//!
+//! Requests pinned to a worker with `nvext.routing.backend_instance_id` go there. The budget
+//! router doesn't reschedule requests that miss the first token deadline.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use dynamo_runtime::{
+    component::{Instance, InstanceSource},
+    pipeline::{async_trait, AsyncEngine, Error, ManyOut, PushRouter, SingleIn},
+    protocols::annotated::Annotated,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kv_router::{with_worker_annotation, ANNOTATION_WORKER_INSTANCE_ID},
+    preprocessor::PreprocessedRequest,
+    protocols::common::llm_backend::LLMEngineOutput,
+};
+
+/// How a request class trades latency for cost
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetClass {
+    /// Most requests of this router a worker may have in flight and still be preferred for being
+    /// cheaper. Low for latency sensitive classes, high for batch classes.
+    pub max_inflight: usize,
+
+    /// Workers that cost more per GPU-hour only get the class's requests when there is no other
+    /// worker
+    pub max_cost_per_gpu_hour: Option<f64>,
+}
+
+impl Default for BudgetClass {
+    fn default() -> Self {
+        BudgetClass {
+            max_inflight: 4,
+            max_cost_per_gpu_hour: None,
+        }
+    }
+}
+
+/// The [`BudgetClass`] of each request class
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetPolicy {
+    /// For the requests without a class or with a class not in `classes`
+    pub default: BudgetClass,
+
+    pub classes: HashMap<String, BudgetClass>,
+}
+
+impl BudgetPolicy {
+    /// Read the policy from a JSON file, e.g.
+    /// `{"classes": {"batch": {"max_inflight": 32, "max_cost_per_gpu_hour": 1.5}}}`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed reading budget policy from {}: {err}",
+                path.display()
+            )
+        })?;
+        let policy: BudgetPolicy = serde_json::from_str(&contents)
+            .map_err(|err| anyhow::anyhow!("Invalid budget policy in {}: {err}", path.display()))?;
+        policy
+            .validate()
+            .map_err(|err| anyhow::anyhow!("Invalid budget policy in {}: {err}", path.display()))?;
+        Ok(policy)
+    }
+
+    /// Check the costs of the classes
+    pub fn validate(&self) -> Result<(), String> {
+        let classes = std::iter::once(("default", &self.default)).chain(
+            self.classes
+                .iter()
+                .map(|(name, class)| (name.as_str(), class)),
+        );
+        for (name, class) in classes {
+            if let Some(cost) = class.max_cost_per_gpu_hour {
+                check_cost(cost).map_err(|err| format!("class {name}: {err}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn class(&self, request_class: Option<&str>) -> &BudgetClass {
+        request_class
+            .and_then(|class| self.classes.get(class))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Check a cost per GPU-hour: a finite number, at least 0. The error is a message for the user.
+pub fn check_cost(cost: f64) -> Result<f64, String> {
+    if cost.is_finite() && cost >= 0.0 {
+        Ok(cost)
+    } else {
+        Err(format!(
+            "cost per GPU-hour must be a finite number of at least 0, got {cost}"
+        ))
+    }
+}
+
+/// The worker for a request of `class`, from the instances with the requests this router has in
+/// flight to each. See the [module docs](self).
+pub fn choose(class: &BudgetClass, loads: &[(Instance, usize)]) -> Option<i64> {
+    let cost = |instance: &Instance| {
+        instance
+            .cost_per_gpu_hour
+            .and_then(|cost| check_cost(cost).ok())
+            .unwrap_or(f64::INFINITY)
+    };
+    let affordable: Vec<&(Instance, usize)> = loads
+        .iter()
+        .filter(|(instance, _)| {
+            class
+                .max_cost_per_gpu_hour
+                .is_none_or(|max| cost(instance) <= max)
+        })
+        .collect();
+    let pool = if affordable.is_empty() {
+        loads.iter().collect()
+    } else {
+        affordable
+    };
+    let cheapest = pool
+        .iter()
+        .filter(|(_, inflight)| *inflight < class.max_inflight)
+        .min_by(|(a, a_inflight), (b, b_inflight)| {
+            cost(a).total_cmp(&cost(b)).then(a_inflight.cmp(b_inflight))
+        });
+    let chosen = cheapest.or_else(|| {
+        pool.iter().min_by(|(a, a_inflight), (b, b_inflight)| {
+            a_inflight.cmp(b_inflight).then(cost(a).total_cmp(&cost(b)))
+        })
+    });
+    chosen.map(|(instance, _)| instance.id())
+}
+
+/// Routes requests to the backend workers by their cost, see the [module docs](self)
+pub struct BudgetRouter {
+    inner: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+    policy: Arc<BudgetPolicy>,
+}
+
+impl BudgetRouter {
+    pub fn new(
+        inner: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+        policy: Arc<BudgetPolicy>,
+    ) -> Self {
+        BudgetRouter { inner, policy }
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for BudgetRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        if let InstanceSource::Static = self.inner.client.instance_source.as_ref() {
+            return self.inner.r#static(request).await;
+        }
+        let annotate = request.has_annotation(ANNOTATION_WORKER_INSTANCE_ID);
+        let instance_id = match request.backend_instance_id() {
+            // The client pinned the request to a worker
+            Some(instance_id) => instance_id,
+            None => {
+                let class = self.policy.class(request.request_class());
+                let loads = self.inner.loads()?;
+                let instance_id = choose(class, &loads).context("No instance to choose from")?;
+                tracing::trace!(
+                    request_class = request.request_class(),
+                    instance_id,
+                    "budget router selected"
+                );
+                instance_id
+            }
+        };
+        let responses = self.inner.direct(request, instance_id).await?;
+        Ok(with_worker_annotation(annotate, instance_id, responses))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::component::TransportType;
+
+    fn instance(id: i64, cost_per_gpu_hour: Option<f64>) -> Instance {
+        Instance {
+            component: "backend".to_string(),
+            endpoint: "generate".to_string(),
+            namespace: "test".to_string(),
+            instance_id: id,
+            transport: TransportType::NatsTcp(format!("test.backend.generate-{id}")),
+            region: None,
+            zone: None,
+            max_concurrent_requests: None,
+            description: None,
+            cost_per_gpu_hour,
+        }
+    }
+
+    #[test]
+    fn test_choose() {
+        let interactive = BudgetClass {
+            max_inflight: 1,
+            max_cost_per_gpu_hour: None,
+        };
+        let batch = BudgetClass {
+            max_inflight: 8,
+            max_cost_per_gpu_hour: Some(1.0),
+        };
+        let spot = |id, inflight| (instance(id, Some(0.8)), inflight);
+        let on_demand = |id, inflight| (instance(id, Some(2.5)), inflight);
+
+        // The cheap pool while it has headroom, its least loaded worker
+        let loads = vec![on_demand(1, 0), spot(2, 1), spot(3, 0)];
+        assert_eq!(choose(&interactive, &loads), Some(3));
+        assert_eq!(choose(&batch, &loads), Some(3));
+
+        // Latency sensitive requests move on to the expensive pool sooner
+        let loads = vec![on_demand(1, 0), spot(2, 3), spot(3, 2)];
+        assert_eq!(choose(&interactive, &loads), Some(1));
+        assert_eq!(choose(&batch, &loads), Some(3));
+
+        // Without headroom anywhere, the least loaded worker, the cheaper between equals
+        let loads = vec![on_demand(1, 9), spot(2, 9), spot(3, 12)];
+        assert_eq!(choose(&interactive, &loads), Some(2));
+
+        // Batch requests stay within their budget while it has workers
+        let loads = vec![on_demand(1, 0), spot(2, 20)];
+        assert_eq!(choose(&batch, &loads), Some(2));
+        assert_eq!(choose(&batch, &[on_demand(1, 20)]), Some(1));
+
+        // Workers without a cost, or with a bad one, are taken for the most expensive
+        let loads = vec![(instance(4, None), 0), on_demand(1, 0)];
+        assert_eq!(choose(&interactive, &loads), Some(1));
+        let loads = vec![(instance(5, Some(f64::NAN)), 0), on_demand(1, 0)];
+        assert_eq!(choose(&interactive, &loads), Some(1));
+        let loads = vec![(instance(6, Some(-1.0)), 0), on_demand(1, 0)];
+        assert_eq!(choose(&interactive, &loads), Some(1));
+        assert_eq!(choose(&interactive, &[]), None);
+
+        let policy: BudgetPolicy = serde_json::from_str(
+            r#"{"classes": {"batch": {"max_inflight": 8, "max_cost_per_gpu_hour": 1.0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(policy.class(Some("batch")), &batch);
+        assert_eq!(policy.class(Some("other")), &BudgetClass::default());
+        assert_eq!(policy.class(None), &BudgetClass::default());
+        assert!(policy.validate().is_ok());
+
+        let policy: BudgetPolicy =
+            serde_json::from_str(r#"{"default": {"max_cost_per_gpu_hour": -1.0}}"#).unwrap();
+        assert!(policy.validate().is_err());
+        assert!(check_cost(f64::INFINITY).is_err());
+        assert_eq!(check_cost(0.0), Ok(0.0));
+    }
+}
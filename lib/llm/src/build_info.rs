@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! What a build is: its version and git commit, the cargo features it was built with, the hashes
+//! of the engine scripts it runs in sub-processes and the versions of the protocols it speaks.
+//!
+//! Served on `/admin/build-info` and printed by `dynamo-run --version --verbose`, so that the
+//! processes of a fleet can be compared without looking inside their containers.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::kv_router::indexer::KV_EVENTS_PROTOCOL_VERSION;
+use crate::kv_router::protocols::ROUTER_PROTOCOL_VERSION;
+use crate::kv_router::snapshot::SNAPSHOT_VERSION;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+
+    /// The commit the binary was built from, if it was built in a git checkout
+    pub git_sha: Option<String>,
+
+    /// The cargo features that were enabled
+    pub features: Vec<String>,
+
+    /// Name of each engine script to the start of its blake3 hash
+    pub engine_scripts: BTreeMap<String, String>,
+
+    /// Name of each versioned protocol to its version
+    pub protocols: BTreeMap<String, u32>,
+}
+
+impl BuildInfo {
+    /// Build info with the protocol versions of this library, and no features or scripts yet
+    pub fn new(version: &str, git_sha: Option<&str>) -> Self {
+        BuildInfo {
+            version: version.to_string(),
+            git_sha: git_sha.filter(|sha| !sha.is_empty()).map(str::to_string),
+            features: vec![],
+            engine_scripts: BTreeMap::new(),
+            protocols: BTreeMap::from([
+                ("kv_router".to_string(), ROUTER_PROTOCOL_VERSION),
+                ("kv_index_snapshot".to_string(), SNAPSHOT_VERSION),
+                ("kv_events".to_string(), KV_EVENTS_PROTOCOL_VERSION),
+            ]),
+        }
+    }
+
+    /// Add the features that are enabled, given as name and whether it is enabled, in order
+    pub fn with_features(mut self, features: &[(&str, bool)]) -> Self {
+        self.features.extend(
+            features
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| name.to_string()),
+        );
+        self
+    }
+
+    /// Add the script `name` with source code `source`
+    pub fn with_engine_script(mut self, name: &str, source: &str) -> Self {
+        let hash = blake3::hash(source.as_bytes()).to_hex();
+        self.engine_scripts
+            .insert(name.to_string(), hash[..16].to_string());
+        self
+    }
+}
+
+impl Default for BuildInfo {
+    /// The build info of this library
+    fn default() -> Self {
+        BuildInfo::new(env!("CARGO_PKG_VERSION"), None)
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        writeln!(
+            f,
+            "git sha: {}",
+            self.git_sha.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(f, "features: {}", self.features.join(", "))?;
+        for (name, hash) in &self.engine_scripts {
+            writeln!(f, "engine script {name}: {hash}")?;
+        }
+        for (name, version) in &self.protocols {
+            writeln!(f, "protocol {name}: {version}")?;
+        }
+        Ok(())
+    }
+}
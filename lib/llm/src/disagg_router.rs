@@ -88,7 +88,7 @@ impl DisaggRouterConf {
         let (key, _watcher, mut kv_event_rx) = prefix_watcher.dissolve();
 
         // Spawn background task to watch for config changes
-        drt.runtime().secondary().spawn(async move {
+        let config_watcher = async move {
             tracing::info!("Starting config watcher for disagg router key: {}", key);
 
             loop {
@@ -121,8 +121,7 @@ impl DisaggRouterConf {
                                 break;
                             }
                         } else {
-                            tracing::error!("Unable to parse router config for key {}", key);
-                            break;
+                            anyhow::bail!("Unable to parse router config for key {}", key);
                         }
                     }
                     WatchEvent::Delete(_) => {
@@ -137,7 +136,14 @@ impl DisaggRouterConf {
             }
 
             tracing::debug!("Completed config watcher for key: {}", key);
-        });
+            Ok(())
+        };
+        let runtime = drt.runtime();
+        runtime.tasks().spawn_on(
+            format!("disagg router config {etcd_key}"),
+            config_watcher,
+            &runtime.secondary(),
+        );
 
         Ok((initial_config, watch_rx))
     }
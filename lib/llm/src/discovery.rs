@@ -7,6 +7,11 @@ pub use model_manager::{ModelManager, ModelManagerError};
 mod model_entry;
 pub use model_entry::ModelEntry;
 
+pub mod model_control;
+pub mod routing;
+pub mod topology;
+pub mod worker_admission;
+
 mod watcher;
 pub use watcher::ModelWatcher;
 
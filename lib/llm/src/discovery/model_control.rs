@@ -0,0 +1,134 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Load and unload models on a running worker.
+//!
+//! A [`ModelControl`] message is sent to the worker's component, see
+//! [`Component::control_request`]. The worker starts or stops serving the model and adds or
+//! removes its [`ModelEntry`](super::ModelEntry) in etcd, which is how every ingress finds out.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use dynamo_runtime::component::Component;
+use dynamo_runtime::{protocols, DistributedRuntime};
+use serde::{Deserialize, Serialize};
+
+/// How long to wait for a worker to answer. Loading a large model can take minutes.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ModelControl {
+    /// Start serving the model at `model_path`, a local path or Hugging Face repo on the worker
+    Load {
+        model_path: String,
+        model_name: Option<String>,
+    },
+
+    /// Stop serving a model the worker loaded
+    Unload { model_name: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelControlReply {
+    /// The models the worker loaded with model control, after the change
+    pub models: Vec<String>,
+}
+
+/// Implemented by workers that can change the models they serve
+#[async_trait]
+pub trait ModelLoader: Send + Sync {
+    /// Start serving the model and register it. Returns its name.
+    async fn load(&self, model_path: &str, model_name: Option<String>) -> anyhow::Result<String>;
+
+    /// Unregister the model and stop serving it
+    async fn unload(&self, model_name: &str) -> anyhow::Result<()>;
+
+    fn models(&self) -> Vec<String>;
+}
+
+/// Handle the [`ModelControl`] messages sent to `component` with `loader`, until the runtime
+/// shuts down
+pub async fn serve(component: &Component, loader: Arc<dyn ModelLoader>) -> anyhow::Result<()> {
+    component
+        .serve_control(move |request: ModelControl| {
+            let loader = loader.clone();
+            async move {
+                match request {
+                    ModelControl::Load {
+                        model_path,
+                        model_name,
+                    } => {
+                        let name = loader.load(&model_path, model_name).await?;
+                        tracing::info!(name, model_path, "Loaded model");
+                    }
+                    ModelControl::Unload { model_name } => {
+                        loader.unload(&model_name).await?;
+                        tracing::info!(name = model_name, "Unloaded model");
+                    }
+                }
+                Ok(ModelControlReply {
+                    models: loader.models(),
+                })
+            }
+        })
+        .await
+}
+
+/// Sends [`ModelControl`] messages to workers
+#[derive(Clone)]
+pub struct ModelControlClient {
+    drt: DistributedRuntime,
+}
+
+impl ModelControlClient {
+    pub fn new(drt: DistributedRuntime) -> Self {
+        ModelControlClient { drt }
+    }
+
+    /// Send `request` to the component serving `endpoint`, to instance `instance_id` or to any
+    /// instance, and wait for it to be done
+    pub async fn send(
+        &self,
+        endpoint: &protocols::Endpoint,
+        instance_id: Option<i64>,
+        request: &ModelControl,
+    ) -> anyhow::Result<ModelControlReply> {
+        let component = self
+            .drt
+            .namespace(&endpoint.namespace)?
+            .component(&endpoint.component)?;
+        component
+            .control_request(instance_id, request, CONTROL_TIMEOUT)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_control_json() {
+        let load: ModelControl = serde_json::from_str(
+            r#"{"action": "load", "model_path": "Qwen/Qwen3-0.6B", "model_name": null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            load,
+            ModelControl::Load {
+                model_path: "Qwen/Qwen3-0.6B".to_string(),
+                model_name: None,
+            }
+        );
+        let unload = ModelControl::Unload {
+            model_name: "qwen".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&unload).unwrap(),
+            r#"{"action":"unload","model_name":"qwen"}"#
+        );
+    }
+}
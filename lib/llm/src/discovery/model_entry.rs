@@ -34,7 +34,10 @@ impl ModelEntry {
     }
 
     pub fn requires_preprocessing(&self) -> bool {
-        matches!(self.model_type, ModelType::Backend)
+        matches!(
+            self.model_type,
+            ModelType::Backend | ModelType::Prefill | ModelType::Draft
+        )
     }
 
     /// Fetch the ModelDeploymentCard from NATS.
@@ -2,10 +2,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use dynamo_runtime::component::Component;
+use dynamo_runtime::traits::DistributedRuntimeProvider;
 
-use crate::discovery::ModelEntry;
+use crate::discovery::{routing::RoutingPolicy, ModelEntry};
 
-use crate::kv_router::{scheduler::DefaultWorkerSelector, KvRouterConfig};
+use crate::kv_router::{
+    indexer::IndexerConfig,
+    scheduler::{BalancedWorkerSelector, DefaultWorkerSelector},
+    KvRouterConfig, WorkerSelector, WorkerSelectorKind, KV_ROUTER_WEIGHTS_ROOT_PATH,
+};
+use crate::lora::LoraRegistry;
+use crate::prefill_router::PrefillWorkers;
+use crate::speculative::DraftWorkers;
+use crate::tokenizers::lazy::LazyTokenizer;
 use crate::{
     kv_router::KvRouter,
     types::openai::{
@@ -31,14 +40,22 @@ pub enum ModelManagerError {
 
 // Don't implement Clone for this, put it in an Arc instead.
 pub struct ModelManager {
-    // We read a lot and write rarely, so these three are RwLock
+    // We read a lot and write rarely, so these four are RwLock
     completion_engines: RwLock<ModelEngines<OpenAICompletionsStreamingEngine>>,
     chat_completion_engines: RwLock<ModelEngines<OpenAIChatCompletionsStreamingEngine>>,
     embeddings_engines: RwLock<ModelEngines<OpenAIEmbeddingsStreamingEngine>>,
+    routing: RwLock<RoutingPolicy>,
 
-    // These two are Mutex because we read and write rarely and equally
+    // These are Mutex because we read and write rarely and equally
     entries: Mutex<HashMap<String, ModelEntry>>,
     kv_choosers: Mutex<HashMap<String, Arc<KvRouter>>>,
+    tokenizers: Mutex<HashMap<String, Arc<LazyTokenizer>>>,
+    prefill_workers: Mutex<HashMap<String, Arc<PrefillWorkers>>>,
+    draft_workers: Mutex<HashMap<String, Arc<DraftWorkers>>>,
+    /// Models whose engines the watcher is still building
+    pending: Mutex<HashSet<String>>,
+
+    loras: Arc<LoraRegistry>,
 }
 
 impl Default for ModelManager {
@@ -53,8 +70,14 @@ impl ModelManager {
             completion_engines: RwLock::new(ModelEngines::default()),
             chat_completion_engines: RwLock::new(ModelEngines::default()),
             embeddings_engines: RwLock::new(ModelEngines::default()),
+            routing: RwLock::new(RoutingPolicy::default()),
             entries: Mutex::new(HashMap::new()),
             kv_choosers: Mutex::new(HashMap::new()),
+            tokenizers: Mutex::new(HashMap::new()),
+            prefill_workers: Mutex::new(HashMap::new()),
+            draft_workers: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashSet::new()),
+            loras: Arc::new(LoraRegistry::default()),
         }
     }
 
@@ -67,12 +90,74 @@ impl ModelManager {
             || self.completion_engines.read().unwrap().contains(model)
     }
 
+    /// The registered models, the aliases and splits of the routing policy that lead to one, and
+    /// the LoRA adapters of the registered models
     pub fn model_display_names(&self) -> HashSet<String> {
-        self.list_chat_completions_models()
+        let mut names: HashSet<String> = self
+            .list_chat_completions_models()
             .into_iter()
             .chain(self.list_completions_models())
             .chain(self.list_embeddings_models())
-            .collect()
+            .collect();
+        let routing = self.routing.read().unwrap();
+        let routed: Vec<String> = routing
+            .names()
+            .filter(|(_, targets)| targets.iter().any(|model| names.contains(*model)))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        names.extend(routed);
+        let loras: Vec<String> = self
+            .loras
+            .names()
+            .into_iter()
+            .filter(|(_, base_model)| names.contains(base_model))
+            .map(|(name, _)| name)
+            .collect();
+        names.extend(loras);
+        names
+    }
+
+    /// The LoRA adapters the workers loaded, see [`crate::lora`]
+    pub fn loras(&self) -> Arc<LoraRegistry> {
+        self.loras.clone()
+    }
+
+    /// Replace the aliases, splits and default model of requests, see [`super::routing`]
+    pub fn set_routing_policy(&self, policy: RoutingPolicy) {
+        *self.routing.write().unwrap() = policy;
+    }
+
+    pub fn routing_policy(&self) -> RoutingPolicy {
+        self.routing.read().unwrap().clone()
+    }
+
+    /// The model serving requests for `model`, among those `is_registered`
+    fn resolve(&self, model: &str, is_registered: impl Fn(&str) -> bool) -> String {
+        // LoRA adapters are served by the pipeline of their base model
+        if let Some(base_model) = self.loras.base_model(model) {
+            tracing::trace!(requested = model, base_model, "LoRA adapter");
+            return base_model;
+        }
+        let routing = self.routing.read().unwrap();
+        if routing.is_empty() {
+            return model.to_string();
+        }
+        let resolved = routing.resolve(model, is_registered);
+        if resolved != model {
+            tracing::trace!(requested = model, resolved, "Routed model");
+        }
+        resolved
+    }
+
+    /// The registered model serving requests for `model`, after LoRA adapters and the routing
+    /// policy. A split picks one of its models each time.
+    pub fn resolve_model(&self, model: &str) -> String {
+        let chat = self.chat_completion_engines.read().unwrap();
+        let completions = self.completion_engines.read().unwrap();
+        let embeddings = self.embeddings_engines.read().unwrap();
+        self.resolve(model, |name| {
+            chat.contains(name) || completions.contains(name) || embeddings.contains(name)
+        })
     }
 
     pub fn list_chat_completions_models(&self) -> Vec<String> {
@@ -133,10 +218,10 @@ impl ModelManager {
         &self,
         model: &str,
     ) -> Result<OpenAIEmbeddingsStreamingEngine, ModelManagerError> {
-        self.embeddings_engines
-            .read()
-            .unwrap()
-            .get(model)
+        let engines = self.embeddings_engines.read().unwrap();
+        let resolved = self.resolve(model, |name| engines.contains(name));
+        engines
+            .get(&resolved)
             .cloned()
             .ok_or(ModelManagerError::ModelNotFound(model.to_string()))
     }
@@ -145,10 +230,10 @@ impl ModelManager {
         &self,
         model: &str,
     ) -> Result<OpenAICompletionsStreamingEngine, ModelManagerError> {
-        self.completion_engines
-            .read()
-            .unwrap()
-            .get(model)
+        let engines = self.completion_engines.read().unwrap();
+        let resolved = self.resolve(model, |name| engines.contains(name));
+        engines
+            .get(&resolved)
             .cloned()
             .ok_or(ModelManagerError::ModelNotFound(model.to_string()))
     }
@@ -157,14 +242,57 @@ impl ModelManager {
         &self,
         model: &str,
     ) -> Result<OpenAIChatCompletionsStreamingEngine, ModelManagerError> {
-        self.chat_completion_engines
-            .read()
+        let engines = self.chat_completion_engines.read().unwrap();
+        let resolved = self.resolve(model, |name| engines.contains(name));
+        engines
+            .get(&resolved)
+            .cloned()
+            .ok_or(ModelManagerError::ModelNotFound(model.to_string()))
+    }
+
+    /// Make the model's tokenizer available to `/v1/tokenize`. It loads on first use.
+    pub fn add_tokenizer(&self, model: &str, tokenizer: Arc<LazyTokenizer>) {
+        self.tokenizers
+            .lock()
             .unwrap()
-            .get(model)
+            .insert(model.to_string(), tokenizer);
+    }
+
+    pub fn remove_tokenizer(&self, model: &str) {
+        self.tokenizers.lock().unwrap().remove(model);
+    }
+
+    pub fn get_tokenizer(&self, model: &str) -> Result<Arc<LazyTokenizer>, ModelManagerError> {
+        let tokenizers = self.tokenizers.lock().unwrap();
+        let resolved = self.resolve(model, |name| tokenizers.contains_key(name));
+        tokenizers
+            .get(&resolved)
             .cloned()
             .ok_or(ModelManagerError::ModelNotFound(model.to_string()))
     }
 
+    /// The prefill workers of `model`, for disaggregated serving. Shared by the decode pipelines
+    /// of the model, which see the prefill workers come and go.
+    pub fn prefill_workers(&self, model: &str) -> Arc<PrefillWorkers> {
+        self.prefill_workers
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// The draft workers of `model`, for speculative decoding. Shared by the pipelines of the
+    /// model, which see the draft workers come and go.
+    pub fn draft_workers(&self, model: &str) -> Arc<DraftWorkers> {
+        self.draft_workers
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default()
+            .clone()
+    }
+
     /// Save a ModelEntry under an instance's etcd `models/` key so we can fetch it later when the key is
     /// deleted from etcd.
     pub fn save_model_entry(&self, key: &str, entry: ModelEntry) {
@@ -176,6 +304,21 @@ impl ModelManager {
         self.entries.lock().unwrap().remove(key)
     }
 
+    /// The model was discovered, it can't serve requests until its engines are added
+    pub fn add_pending(&self, model: &str) {
+        self.pending.lock().unwrap().insert(model.to_string());
+    }
+
+    /// The model's engines were added, or adding them failed
+    pub fn remove_pending(&self, model: &str) {
+        self.pending.lock().unwrap().remove(model);
+    }
+
+    /// The models discovered that can't serve requests yet
+    pub fn pending_models(&self) -> Vec<String> {
+        self.pending.lock().unwrap().iter().cloned().collect()
+    }
+
     pub async fn kv_chooser_for(
         &self,
         model_name: &str,
@@ -212,8 +355,54 @@ impl ModelManager {
         kv_cache_block_size: usize,
         kv_router_config: Option<KvRouterConfig>,
     ) -> anyhow::Result<Arc<KvRouter>> {
-        let selector = Box::new(DefaultWorkerSelector::new(kv_router_config));
-        let chooser = KvRouter::new(component.clone(), kv_cache_block_size, Some(selector)).await?;
+        let indexer = kv_router_config
+            .as_ref()
+            .map(|config| IndexerConfig {
+                limits: config.indexer_limits,
+                shards: config.index_shards,
+            })
+            .unwrap_or_default();
+        let index_snapshot = kv_router_config
+            .as_ref()
+            .map(|config| config.index_snapshot.clone())
+            .unwrap_or_default();
+        let record_path = kv_router_config
+            .as_ref()
+            .and_then(|config| config.record_path.clone());
+        let circuit_breaker = kv_router_config
+            .as_ref()
+            .map(|config| config.circuit_breaker)
+            .unwrap_or_default();
+        let session_affinity = kv_router_config
+            .as_ref()
+            .map(|config| config.session_affinity)
+            .unwrap_or_default();
+        let selector: Box<dyn WorkerSelector + Send + Sync> = match kv_router_config
+            .as_ref()
+            .map(|config| config.selector)
+            .unwrap_or_default()
+        {
+            WorkerSelectorKind::Default => Box::new(DefaultWorkerSelector::new(kv_router_config)),
+            WorkerSelectorKind::Balanced => {
+                let kv_router_config = kv_router_config.unwrap_or_default();
+                let selector = BalancedWorkerSelector::new(kv_router_config.balanced_weights())
+                    .with_temperature(kv_router_config.temperature);
+                let key = format!("{KV_ROUTER_WEIGHTS_ROOT_PATH}{model_name}");
+                selector.watch_weights(component.drt(), key).await?;
+                Box::new(selector)
+            }
+        };
+        let chooser = KvRouter::new(
+            component.clone(),
+            kv_cache_block_size,
+            Some(selector),
+            indexer,
+            index_snapshot,
+            record_path,
+            circuit_breaker,
+        )
+        .await?
+        .with_session_affinity(session_affinity);
         let new_kv_chooser = Arc::new(chooser);
         self.kv_choosers
             .lock()
@@ -0,0 +1,245 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A [`RoutingPolicy`] maps the model name of a request to one of the registered models, when
+//! several are registered:
+//! - `aliases` are other names for a model,
+//! - `splits` share the requests for a name between models by weight, e.g. a 90/10 canary,
+//! - `default_model` serves requests for models that aren't registered.
+//!
+//! A name is resolved in that order: the alias, then the split, then the default model if the
+//! result isn't registered. Splits only pick from models that are registered. Responses keep the
+//! model name the client asked for.
+//!
+//! The policy is JSON. The ingress loads it from a file at startup and follows
+//! [`MODEL_ROUTING_KEY`] in etcd, which replaces the file's policy while it exists:
+//!
+//! ```text
+//! etcdctl put /dynamo/model_routing '{"aliases": {"gpt-4o": "llama-3.1-70b"},
+//!   "splits": {"llama": [{"model": "llama-3.1-70b", "weight": 90}, {"model": "llama-3.3-70b", "weight": 10}]},
+//!   "default_model": "llama-3.1-70b"}'
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use dynamo_runtime::transports::etcd::{self, WatchEvent};
+use serde::{Deserialize, Serialize};
+
+use super::ModelManager;
+
+/// etcd key holding the [`RoutingPolicy`] as JSON
+pub const MODEL_ROUTING_KEY: &str = "/dynamo/model_routing";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingPolicy {
+    /// Alias to model name. The model can be a split.
+    pub aliases: HashMap<String, String>,
+
+    /// Name to the models sharing its requests
+    pub splits: HashMap<String, Vec<WeightedModel>>,
+
+    /// Serves requests for models that aren't registered
+    pub default_model: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedModel {
+    pub model: String,
+    /// Relative to the other models of the split
+    pub weight: u32,
+}
+
+impl RoutingPolicy {
+    /// Read and validate a policy file
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed reading model routing policy {}", path.display()))?;
+        let policy: RoutingPolicy = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid model routing policy {}", path.display()))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, models) in &self.splits {
+            if models.iter().all(|m| m.weight == 0) {
+                anyhow::bail!("Split '{name}' needs a model with a weight above 0");
+            }
+            if self.aliases.contains_key(name) {
+                anyhow::bail!("'{name}' is both an alias and a split");
+            }
+        }
+        for (alias, model) in &self.aliases {
+            if self.aliases.contains_key(model) {
+                anyhow::bail!("Alias '{alias}' points to alias '{model}', aliases can't chain");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &RoutingPolicy::default()
+    }
+
+    /// The model to serve a request for `requested`. `is_registered` tells which models can
+    /// serve it.
+    pub fn resolve(&self, requested: &str, is_registered: impl Fn(&str) -> bool) -> String {
+        let name = self
+            .aliases
+            .get(requested)
+            .map(String::as_str)
+            .unwrap_or(requested);
+        let name = match self.splits.get(name) {
+            Some(models) => pick(models, &is_registered).unwrap_or(name),
+            None => name,
+        };
+        match &self.default_model {
+            Some(default) if !is_registered(name) => default.clone(),
+            _ => name.to_string(),
+        }
+    }
+
+    /// The aliases and split names, and the models they can resolve to, without the default
+    /// model
+    pub fn names(&self) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        let aliases = self.aliases.iter().map(|(alias, model)| {
+            let targets = match self.splits.get(model) {
+                Some(models) => models.iter().map(|m| m.model.as_str()).collect(),
+                None => vec![model.as_str()],
+            };
+            (alias.as_str(), targets)
+        });
+        let splits = self.splits.iter().map(|(name, models)| {
+            let targets = models.iter().map(|m| m.model.as_str()).collect();
+            (name.as_str(), targets)
+        });
+        aliases.chain(splits)
+    }
+}
+
+/// A weighted random choice among the registered models
+fn pick<'a>(models: &'a [WeightedModel], is_registered: impl Fn(&str) -> bool) -> Option<&'a str> {
+    let available: Vec<&WeightedModel> = models
+        .iter()
+        .filter(|m| m.weight > 0 && is_registered(&m.model))
+        .collect();
+    let total: u64 = available.iter().map(|m| m.weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = rand::random_range(0..total);
+    for model in available {
+        if roll < model.weight as u64 {
+            return Some(&model.model);
+        }
+        roll -= model.weight as u64;
+    }
+    None
+}
+
+/// Apply the policy in [`MODEL_ROUTING_KEY`] to `manager` while the key exists, and `fallback`
+/// otherwise. Runs until the etcd watch ends.
+pub async fn follow(
+    manager: Arc<ModelManager>,
+    etcd_client: etcd::Client,
+    fallback: RoutingPolicy,
+) -> anyhow::Result<()> {
+    let watcher = etcd_client
+        .kv_get_and_watch_prefix(MODEL_ROUTING_KEY)
+        .await?;
+    let (_prefix, _watcher, mut receiver) = watcher.dissolve();
+    while let Some(event) = receiver.recv().await {
+        match event {
+            WatchEvent::Put(kv) => {
+                let policy = serde_json::from_slice::<RoutingPolicy>(kv.value())
+                    .map_err(anyhow::Error::from)
+                    .and_then(|policy| policy.validate().map(|_| policy));
+                match policy {
+                    Ok(policy) => {
+                        tracing::info!(?policy, "Model routing policy from etcd");
+                        manager.set_routing_policy(policy);
+                    }
+                    Err(err) => {
+                        tracing::error!(%err, "Invalid model routing policy in etcd, not applied");
+                    }
+                }
+            }
+            WatchEvent::Delete(_) => {
+                tracing::info!("Model routing policy removed from etcd");
+                manager.set_routing_policy(fallback.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy() -> RoutingPolicy {
+        serde_json::from_value(json!({
+            "aliases": {"gpt-4o": "llama", "small": "qwen"},
+            "splits": {"llama": [
+                {"model": "llama-3.1", "weight": 90},
+                {"model": "llama-3.3", "weight": 10}
+            ]},
+            "default_model": "qwen"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let policy = policy();
+        policy.validate().unwrap();
+        let registered = |name: &str| ["llama-3.1", "llama-3.3", "qwen"].contains(&name);
+
+        assert_eq!(policy.resolve("small", registered), "qwen");
+        assert_eq!(policy.resolve("llama-3.3", registered), "llama-3.3");
+        assert_eq!(policy.resolve("unknown", registered), "qwen");
+        for _ in 0..20 {
+            let model = policy.resolve("gpt-4o", registered);
+            assert!(model == "llama-3.1" || model == "llama-3.3");
+        }
+
+        // Only registered models get a share
+        let registered = |name: &str| ["llama-3.3", "qwen"].contains(&name);
+        for _ in 0..20 {
+            assert_eq!(policy.resolve("llama", registered), "llama-3.3");
+        }
+    }
+
+    #[test]
+    fn test_split_weights() {
+        let policy = policy();
+        let registered = |_: &str| true;
+        let canary = (0..10_000)
+            .filter(|_| policy.resolve("llama", registered) == "llama-3.3")
+            .count();
+        assert!((700..1300).contains(&canary), "{canary}");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut chained = policy();
+        chained
+            .aliases
+            .insert("gpt-4".to_string(), "gpt-4o".to_string());
+        assert!(chained.validate().is_err());
+
+        let mut zero = policy();
+        for model in zero.splits.get_mut("llama").unwrap() {
+            model.weight = 0;
+        }
+        assert!(zero.validate().is_err());
+
+        assert!(serde_json::from_value::<RoutingPolicy>(json!({"alias": {}})).is_err());
+    }
+}
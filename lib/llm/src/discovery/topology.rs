@@ -0,0 +1,359 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The deployment as a graph, for visualization: which frontend routes which model to which
+//! endpoint, and the worker instances serving each endpoint, grouped by namespace and component.
+//!
+//! Components, endpoints, instances and models come from etcd, so any process with an etcd client
+//! can build it. Frontends aren't registered anywhere, a frontend adds itself with
+//! [`Topology::add_frontend`].
+//!
+//! [`Topology::to_dot`] renders it for Graphviz, e.g. `dot -Tsvg topology.dot > topology.svg`. It
+//! serializes to JSON for other tools.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use dynamo_runtime::component::{Instance, TransportType, INSTANCE_ROOT_PATH};
+use dynamo_runtime::pipeline::RouterMode;
+use dynamo_runtime::DistributedRuntime;
+use serde::{Deserialize, Serialize};
+
+use super::{ModelEntry, MODEL_ROOT_PATH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    Frontend,
+    Router,
+    Model,
+    Namespace,
+    Component,
+    Endpoint,
+    Instance,
+}
+
+impl NodeKind {
+    fn dot_shape(&self) -> &'static str {
+        match self {
+            NodeKind::Frontend => "house",
+            NodeKind::Router => "diamond",
+            NodeKind::Model => "ellipse",
+            NodeKind::Namespace => "folder",
+            NodeKind::Component => "component",
+            NodeKind::Endpoint => "box",
+            NodeKind::Instance => "box3d",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Node {
+    /// Unique in the graph, e.g. `endpoint:dynamo.backend.generate`
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topology {
+    /// Sorted by id
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Topology {
+    /// Read the instances and models registered in etcd, in `namespace` or in all of them
+    pub async fn discover(
+        drt: &DistributedRuntime,
+        namespace: Option<&str>,
+    ) -> anyhow::Result<Topology> {
+        let Some(etcd_client) = drt.etcd_client() else {
+            anyhow::bail!("Discovering the topology requires etcd");
+        };
+        let mut instances = Vec::new();
+        for kv in etcd_client.kv_get_prefix(INSTANCE_ROOT_PATH).await? {
+            match serde_json::from_slice::<Instance>(kv.value()) {
+                Ok(instance) => instances.push(instance),
+                Err(err) => {
+                    let key = kv.key_str().unwrap_or_default();
+                    tracing::warn!(%err, key, "Invalid instance in etcd");
+                }
+            }
+        }
+        let mut models = Vec::new();
+        for kv in etcd_client.kv_get_prefix(MODEL_ROOT_PATH).await? {
+            match serde_json::from_slice::<ModelEntry>(kv.value()) {
+                Ok(model) => models.push(model),
+                Err(err) => {
+                    let key = kv.key_str().unwrap_or_default();
+                    tracing::warn!(%err, key, "Invalid model entry in etcd");
+                }
+            }
+        }
+        if let Some(namespace) = namespace {
+            instances.retain(|instance| instance.namespace == namespace);
+            models.retain(|model| model.endpoint.namespace == namespace);
+        }
+        Ok(Topology::build(&instances, &models))
+    }
+
+    /// The graph of these instances and models
+    pub fn build(instances: &[Instance], models: &[ModelEntry]) -> Topology {
+        let mut graph = Builder::default();
+        for instance in instances {
+            let endpoint =
+                graph.endpoint(&instance.namespace, &instance.component, &instance.endpoint);
+            let mut attributes = BTreeMap::new();
+            let TransportType::NatsTcp(subject) = &instance.transport;
+            attributes.insert("transport".to_string(), "nats_tcp".to_string());
+            attributes.insert("subject".to_string(), subject.clone());
+            if let Some(region) = &instance.region {
+                attributes.insert("region".to_string(), region.clone());
+            }
+            if let Some(zone) = &instance.zone {
+                attributes.insert("zone".to_string(), zone.clone());
+            }
+            if let Some(cost) = instance.cost_per_gpu_hour {
+                attributes.insert("cost_per_gpu_hour".to_string(), cost.to_string());
+            }
+            let id = format!("instance:{}", instance.instance_id);
+            graph.node(
+                &id,
+                NodeKind::Instance,
+                &format!("{:x}", instance.instance_id),
+                attributes,
+            );
+            graph.edge(&endpoint, &id, Some("nats_tcp"));
+        }
+        for model in models {
+            let endpoint = graph.endpoint(
+                &model.endpoint.namespace,
+                &model.endpoint.component,
+                &model.endpoint.name,
+            );
+            let id = format!("model:{}", model.name);
+            graph.node(&id, NodeKind::Model, &model.name, BTreeMap::new());
+            graph.edge(&id, &endpoint, Some(model.model_type.as_str()));
+        }
+        graph.finish()
+    }
+
+    /// Add a frontend serving every model in the graph, routing requests with `router_mode`
+    pub fn add_frontend(&mut self, name: &str, router_mode: RouterMode) {
+        let mut graph = Builder::from(std::mem::take(self));
+        let frontend = format!("frontend:{name}");
+        graph.node(&frontend, NodeKind::Frontend, name, BTreeMap::new());
+        let router = format!("router:{name}");
+        let mode = match router_mode {
+            RouterMode::RoundRobin => "round-robin".to_string(),
+            RouterMode::Random => "random".to_string(),
+            RouterMode::LeastLoaded => "least-loaded".to_string(),
+            RouterMode::Pull => "pull".to_string(),
+            RouterMode::Budget => "budget".to_string(),
+            RouterMode::Direct(instance_id) => format!("direct {instance_id:x}"),
+            RouterMode::KV => "kv".to_string(),
+        };
+        graph.node(&router, NodeKind::Router, &mode, BTreeMap::new());
+        graph.edge(&frontend, &router, None);
+        let models: Vec<String> = graph
+            .nodes
+            .values()
+            .filter(|node| node.kind == NodeKind::Model)
+            .map(|node| node.id.clone())
+            .collect();
+        for model in models {
+            graph.edge(&router, &model, None);
+        }
+        *self = graph.finish();
+    }
+
+    /// Render for Graphviz
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph dynamo {\n    rankdir=LR;\n");
+        for node in &self.nodes {
+            let mut label = node.label.clone();
+            for (key, value) in &node.attributes {
+                label.push_str(&format!("\n{key}: {value}"));
+            }
+            out.push_str(&format!(
+                "    {} [shape={}, label={}];\n",
+                dot_string(&node.id),
+                node.kind.dot_shape(),
+                dot_string(&label)
+            ));
+        }
+        for edge in &self.edges {
+            out.push_str(&format!(
+                "    {} -> {}",
+                dot_string(&edge.from),
+                dot_string(&edge.to)
+            ));
+            if let Some(label) = &edge.label {
+                out.push_str(&format!(" [label={}]", dot_string(label)));
+            }
+            out.push_str(";\n");
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// A quoted DOT string
+fn dot_string(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+/// Collects nodes and edges without duplicates
+#[derive(Default)]
+struct Builder {
+    nodes: BTreeMap<String, Node>,
+    edges: BTreeSet<Edge>,
+}
+
+impl From<Topology> for Builder {
+    fn from(topology: Topology) -> Self {
+        Builder {
+            nodes: topology
+                .nodes
+                .into_iter()
+                .map(|node| (node.id.clone(), node))
+                .collect(),
+            edges: topology.edges.into_iter().collect(),
+        }
+    }
+}
+
+impl Builder {
+    fn node(
+        &mut self,
+        id: &str,
+        kind: NodeKind,
+        label: &str,
+        attributes: BTreeMap<String, String>,
+    ) {
+        self.nodes.entry(id.to_string()).or_insert_with(|| Node {
+            id: id.to_string(),
+            kind,
+            label: label.to_string(),
+            attributes,
+        });
+    }
+
+    fn edge(&mut self, from: &str, to: &str, label: Option<&str>) {
+        self.edges.insert(Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+            label: label.map(str::to_string),
+        });
+    }
+
+    /// Add the endpoint with its component and namespace, returns its id
+    fn endpoint(&mut self, namespace: &str, component: &str, endpoint: &str) -> String {
+        let namespace_id = format!("namespace:{namespace}");
+        let component_id = format!("component:{namespace}.{component}");
+        let endpoint_id = format!("endpoint:{namespace}.{component}.{endpoint}");
+        self.node(
+            &namespace_id,
+            NodeKind::Namespace,
+            namespace,
+            BTreeMap::new(),
+        );
+        self.node(
+            &component_id,
+            NodeKind::Component,
+            component,
+            BTreeMap::new(),
+        );
+        self.node(&endpoint_id, NodeKind::Endpoint, endpoint, BTreeMap::new());
+        self.edge(&namespace_id, &component_id, None);
+        self.edge(&component_id, &endpoint_id, None);
+        endpoint_id
+    }
+
+    fn finish(self) -> Topology {
+        Topology {
+            nodes: self.nodes.into_values().collect(),
+            edges: self.edges.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_type::ModelType;
+
+    fn instance(component: &str, instance_id: i64) -> Instance {
+        Instance {
+            component: component.to_string(),
+            endpoint: "generate".to_string(),
+            namespace: "dynamo".to_string(),
+            instance_id,
+            transport: TransportType::NatsTcp(format!(
+                "dynamo_{component}.generate-{instance_id:x}"
+            )),
+            region: None,
+            zone: Some("us-east-1a".to_string()),
+            max_concurrent_requests: None,
+            description: None,
+            cost_per_gpu_hour: None,
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let instances = [instance("backend", 1), instance("backend", 2)];
+        let models = [ModelEntry {
+            name: "llama".to_string(),
+            endpoint: "dyn://dynamo.backend.generate".parse().unwrap(),
+            model_type: ModelType::Chat,
+        }];
+        let mut topology = Topology::build(&instances, &models);
+        topology.add_frontend("http", RouterMode::KV);
+
+        let kinds: Vec<NodeKind> = topology.nodes.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds.len(), 8);
+        for kind in [NodeKind::Namespace, NodeKind::Component, NodeKind::Endpoint] {
+            assert_eq!(kinds.iter().filter(|k| **k == kind).count(), 1);
+        }
+        assert_eq!(
+            kinds.iter().filter(|k| **k == NodeKind::Instance).count(),
+            2
+        );
+        assert!(topology.edges.contains(&Edge {
+            from: "model:llama".to_string(),
+            to: "endpoint:dynamo.backend.generate".to_string(),
+            label: Some(ModelType::Chat.as_str().to_string()),
+        }));
+        assert!(topology.edges.contains(&Edge {
+            from: "router:http".to_string(),
+            to: "model:llama".to_string(),
+            label: None,
+        }));
+
+        let dot = topology.to_dot();
+        assert!(dot.starts_with("digraph dynamo {"));
+        assert!(dot.contains("\"router:http\" [shape=diamond, label=\"kv\"];"));
+        assert!(dot.contains("zone: us-east-1a"));
+    }
+
+    #[test]
+    fn test_dot_string() {
+        assert_eq!(dot_string("a \"b\"\nc"), "\"a \\\"b\\\"\\nc\"");
+    }
+}
@@ -7,9 +7,10 @@ use anyhow::Context as _;
 use tokio::sync::{mpsc::Receiver, Notify};
 
 use dynamo_runtime::{
+    component::{Client, Component},
     pipeline::{
         network::egress::push_router::PushRouter, ManyOut, Operator, RouterMode, SegmentSource,
-        ServiceBackend, SingleIn, Source,
+        ServerStreamingEngine, ServiceBackend, SingleIn, Source,
     },
     protocols::annotated::Annotated,
     transports::etcd::{KeyValue, WatchEvent},
@@ -18,8 +19,15 @@ use dynamo_runtime::{
 
 use crate::{
     backend::Backend,
-    kv_router::{KvPushRouter, KvRouterConfig},
+    budget_router::{BudgetPolicy, BudgetRouter},
+    disagg_router::DisaggregatedRouter,
+    embedding_router::{EmbeddingBatchConfig, EmbeddingBatcher},
+    kv_router::{KvPushRouter, KvRouter, KvRouterConfig},
+    lora::LoraRouter,
+    model_card::model::{GenerationLimits, PromptCompression, SamplingPresets, SpecialTokens},
     model_type::ModelType,
+    prefill_queue::PrefillQueue,
+    prefill_router::{DisaggConfig, PrefillRouter},
     preprocessor::{OpenAIPreprocessor, PreprocessedRequest},
     protocols::common::llm_backend::LLMEngineOutput,
     protocols::openai::chat_completions::{
@@ -27,9 +35,15 @@ use crate::{
     },
     protocols::openai::completions::{CompletionResponse, NvCreateCompletionRequest},
     protocols::openai::embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse},
+    reschedule::{RescheduleConfig, ReschedulingRouter},
+    speculative::{SpeculativeConfig, SpeculativeRouter},
+    tokenizers::{lazy::LazyTokenizer, registry},
 };
 
-use super::{ModelEntry, ModelManager, MODEL_ROOT_PATH};
+use super::{
+    worker_admission::{self, WorkerAdmissionPolicy},
+    ModelEntry, ModelManager, MODEL_ROOT_PATH,
+};
 
 pub struct ModelWatcher {
     manager: Arc<ModelManager>,
@@ -37,6 +51,16 @@ pub struct ModelWatcher {
     router_mode: RouterMode,
     notify_on_model: Notify,
     kv_router_config: Option<KvRouterConfig>,
+    embedding_batch_config: Option<EmbeddingBatchConfig>,
+    generation_limits: GenerationLimits,
+    special_tokens: SpecialTokens,
+    sampling_presets: SamplingPresets,
+    prompt_compression: PromptCompression,
+    reschedule_config: Option<RescheduleConfig>,
+    worker_admission: Option<Arc<WorkerAdmissionPolicy>>,
+    budget_policy: Option<Arc<BudgetPolicy>>,
+    disagg_config: DisaggConfig,
+    speculative_config: SpeculativeConfig,
 }
 
 impl ModelWatcher {
@@ -52,9 +76,85 @@ impl ModelWatcher {
             router_mode,
             notify_on_model: Notify::new(),
             kv_router_config,
+            embedding_batch_config: None,
+            generation_limits: GenerationLimits::default(),
+            special_tokens: SpecialTokens::default(),
+            sampling_presets: SamplingPresets::default(),
+            prompt_compression: PromptCompression::default(),
+            reschedule_config: None,
+            worker_admission: None,
+            budget_policy: None,
+            disagg_config: DisaggConfig::default(),
+            speculative_config: SpeculativeConfig::default(),
         }
     }
 
+    /// Coalesce requests to embedding models into batches before sending them to the workers.
+    pub fn with_embedding_batching(mut self, config: Option<EmbeddingBatchConfig>) -> Self {
+        self.embedding_batch_config = config;
+        self
+    }
+
+    /// Operator limits on output tokens and generation time. Limits that are set here win over
+    /// those in the worker's model deployment card.
+    pub fn with_generation_limits(mut self, overrides: GenerationLimits) -> Self {
+        self.generation_limits = overrides;
+        self
+    }
+
+    /// Operator overrides for the special token handling in the worker's model deployment card
+    pub fn with_special_tokens(mut self, overrides: SpecialTokens) -> Self {
+        self.special_tokens = overrides;
+        self
+    }
+
+    /// Operator sampling presets, which replace those of the same name in the worker's model
+    /// deployment card
+    pub fn with_sampling_presets(mut self, overrides: SamplingPresets) -> Self {
+        self.sampling_presets = overrides;
+        self
+    }
+
+    /// Operator prompt compression settings, which win over the worker's model deployment card
+    pub fn with_prompt_compression(mut self, overrides: PromptCompression) -> Self {
+        self.prompt_compression = overrides;
+        self
+    }
+
+    /// Reschedule requests to backend models whose first token doesn't arrive in time on another
+    /// worker, see [`crate::reschedule`].
+    pub fn with_rescheduling(mut self, config: Option<RescheduleConfig>) -> Self {
+        self.reschedule_config = config;
+        self
+    }
+
+    /// Only route to the workers that run what the model needs, see
+    /// [`crate::discovery::worker_admission`].
+    pub fn with_worker_admission(mut self, policy: Option<Arc<WorkerAdmissionPolicy>>) -> Self {
+        self.worker_admission = policy;
+        self
+    }
+
+    /// How each request class trades latency for cost in budget router mode, see
+    /// [`crate::budget_router`].
+    pub fn with_budget_policy(mut self, policy: Option<Arc<BudgetPolicy>>) -> Self {
+        self.budget_policy = policy;
+        self
+    }
+
+    /// When to prefill the requests of models with prefill workers on one of them, see
+    /// [`crate::prefill_router`].
+    pub fn with_disaggregation(mut self, config: DisaggConfig) -> Self {
+        self.disagg_config = config;
+        self
+    }
+
+    /// How many tokens the draft workers of a model guess at once, see [`crate::speculative`].
+    pub fn with_speculative(mut self, config: SpeculativeConfig) -> Self {
+        self.speculative_config = config;
+        self
+    }
+
     /// Wait until we have at least one chat completions model and return it's name.
     pub async fn wait_for_chat_model(&self) -> String {
         // Loop in case it gets added and immediately deleted
@@ -95,13 +195,25 @@ impl ModelWatcher {
                     };
                     self.manager.save_model_entry(key, model_entry.clone());
 
-                    if self.manager.has_model_any(&model_entry.name) {
+                    let existing = match model_entry.model_type {
+                        // The decode workers may have registered the model already
+                        ModelType::Prefill => {
+                            self.manager.prefill_workers(&model_entry.name).is_set()
+                        }
+                        ModelType::Draft => self.manager.draft_workers(&model_entry.name).is_set(),
+                        _ => self.manager.has_model_any(&model_entry.name),
+                    };
+                    if existing {
                         tracing::trace!(name = model_entry.name, "New endpoint for existing model");
                         self.notify_on_model.notify_waiters();
                         continue;
                     }
 
-                    match self.handle_put(&model_entry).await {
+                    // Not ready for /health until added
+                    self.manager.add_pending(&model_entry.name);
+                    let added = self.handle_put(&model_entry).await;
+                    self.manager.remove_pending(&model_entry.name);
+                    match added {
                         Ok(()) => {
                             tracing::info!(model_name = model_entry.name, "added model");
                             self.notify_on_model.notify_waiters();
@@ -145,7 +257,34 @@ impl ModelWatcher {
             .entries_for_model(&model_name)
             .await
             .with_context(|| model_name.clone())?;
-        if !active_instances.is_empty() {
+        let has_type = |model_type: ModelType| {
+            active_instances
+                .iter()
+                .any(|entry| entry.model_type == model_type)
+        };
+        match model_entry.model_type {
+            ModelType::Prefill => {
+                // The model stays, its requests are prefilled by the decode workers
+                if !has_type(ModelType::Prefill) {
+                    self.manager.prefill_workers(&model_name).clear();
+                    tracing::info!(model_name, "removed the prefill workers");
+                }
+                return Ok(None);
+            }
+            ModelType::Draft => {
+                // The model stays, its requests are generated without drafts
+                if !has_type(ModelType::Draft) {
+                    self.manager.draft_workers(&model_name).clear();
+                    tracing::info!(model_name, "removed the draft workers");
+                }
+                return Ok(None);
+            }
+            _ => {}
+        }
+        if active_instances
+            .iter()
+            .any(|entry| !matches!(entry.model_type, ModelType::Prefill | ModelType::Draft))
+        {
             return Ok(None);
         }
 
@@ -153,10 +292,85 @@ impl ModelWatcher {
         let _ = self.manager.remove_chat_completions_model(&model_name);
         let _ = self.manager.remove_completions_model(&model_name);
         let _ = self.manager.remove_embeddings_model(&model_name);
+        self.manager.remove_tokenizer(&model_name);
 
         Ok(Some(model_name))
     }
 
+    /// The engine that sends pre-processed requests to the workers, as configured by the router
+    /// mode and rescheduling.
+    async fn backend_router(
+        &self,
+        model_entry: &ModelEntry,
+        component: &Component,
+        client: Client,
+        kv_cache_block_size: usize,
+    ) -> anyhow::Result<ServerStreamingEngine<PreprocessedRequest, Annotated<LLMEngineOutput>>>
+    {
+        let router = PushRouter::<PreprocessedRequest, Annotated<LLMEngineOutput>>::from_client(
+            client,
+            self.router_mode,
+        )
+        .await?;
+        let chooser = match self.router_mode {
+            RouterMode::Random
+            | RouterMode::RoundRobin
+            | RouterMode::LeastLoaded
+            | RouterMode::Pull
+            | RouterMode::Budget
+            | RouterMode::Direct(_) => None,
+            RouterMode::KV => Some(
+                self.kv_chooser(model_entry, component, kv_cache_block_size)
+                    .await?,
+            ),
+        };
+        let loras = self.manager.loras();
+        if self.router_mode == RouterMode::Budget {
+            if self.reschedule_config.is_some() {
+                anyhow::bail!("The budget router does not reschedule requests");
+            }
+            let policy = self.budget_policy.clone().unwrap_or_default();
+            let engine = Arc::new(BudgetRouter::new(router.clone(), policy));
+            return Ok(Arc::new(LoraRouter::new(engine, router, loras)));
+        }
+        let engine: ServerStreamingEngine<_, _> = match (self.reschedule_config, chooser) {
+            (Some(config), Some(chooser)) => {
+                Arc::new(ReschedulingRouter::new(router, Some(chooser), config).with_loras(loras))
+            }
+            (None, Some(chooser)) => Arc::new(KvPushRouter::new(router, chooser).with_loras(loras)),
+            // The other modes don't know about adapters
+            (Some(config), None) => {
+                let engine = Arc::new(ReschedulingRouter::new(router.clone(), None, config));
+                Arc::new(LoraRouter::new(engine, router, loras))
+            }
+            (None, None) => Arc::new(LoraRouter::new(Arc::new(router.clone()), router, loras)),
+        };
+        Ok(engine)
+    }
+
+    /// The KV router of the workers of `model_entry`. Prefill and decode workers of a model have
+    /// one each.
+    async fn kv_chooser(
+        &self,
+        model_entry: &ModelEntry,
+        component: &Component,
+        kv_cache_block_size: usize,
+    ) -> anyhow::Result<Arc<KvRouter>> {
+        let key = match model_entry.model_type {
+            ModelType::Prefill => format!("{}.prefill", model_entry.name),
+            ModelType::Draft => format!("{}.draft", model_entry.name),
+            _ => model_entry.name.clone(),
+        };
+        self.manager
+            .kv_chooser_for(
+                &key,
+                component,
+                kv_cache_block_size,
+                self.kv_router_config.clone(),
+            )
+            .await
+    }
+
     // Handles a PUT event from etcd, this usually means adding a new model to the list of served
     // models.
     async fn handle_put(&self, model_entry: &ModelEntry) -> anyhow::Result<()> {
@@ -166,14 +380,33 @@ impl ModelWatcher {
             .namespace(&endpoint_id.namespace)?
             .component(&endpoint_id.component)?;
         let client = component.endpoint(&endpoint_id.name).client().await?;
+        let requirements = self
+            .worker_admission
+            .as_ref()
+            .and_then(|policy| policy.for_model(&model_entry.name));
+        let client = match requirements.clone() {
+            Some(requirements) => {
+                worker_admission::admitted(&client, &model_entry.name, requirements)
+            }
+            None => client,
+        };
 
         let Some(etcd_client) = self.drt.etcd_client() else {
             // Should be impossible because we only get here on an etcd event
             anyhow::bail!("Missing etcd_client");
         };
         let card = match model_entry.load_mdc(&etcd_client).await {
-            Ok(card) => {
+            Ok(mut card) => {
                 tracing::debug!(card.display_name, "adding model");
+                card.generation_limits = card
+                    .generation_limits
+                    .with_overrides(&self.generation_limits);
+                card.special_tokens = card.special_tokens.with_overrides(&self.special_tokens);
+                card.sampling_presets =
+                    card.sampling_presets.with_overrides(&self.sampling_presets);
+                card.prompt_compression = card
+                    .prompt_compression
+                    .with_overrides(&self.prompt_compression);
                 Some(card)
             }
             Err(err) => {
@@ -183,6 +416,12 @@ impl ModelWatcher {
             }
         };
 
+        if let Some(card) = card.as_ref().filter(|card| card.has_tokenizer()) {
+            let tokenizer = LazyTokenizer::new(card.clone(), Some(self.drt.nats_client()));
+            self.manager
+                .add_tokenizer(&model_entry.name, Arc::new(tokenizer));
+        }
+
         match model_entry.model_type {
             ModelType::Backend => {
                 // A Backend model expects pre-processed requests meaning it's up to us whether we
@@ -192,41 +431,58 @@ impl ModelWatcher {
                     anyhow::bail!("Missing model deployment card");
                 };
                 // Download tokenizer.json etc to local disk
-                // This cache_dir is a tempfile::TempDir will be deleted on drop. I _think_
-                // OpenAIPreprocessor::new loads the files, so we can delete them after this
-                // function. Needs checking carefully, possibly we need to store it in state.
-                let _cache_dir = Some(card.move_from_nats(self.drt.nats_client()).await?);
+                // This cache_dir is a tempfile::TempDir will be deleted on drop.
+                // OpenAIPreprocessor::new loads the prompt templates, the tokenizer is loaded
+                // in the background.
+                let cache_dir = card.move_from_nats(self.drt.nats_client()).await?;
+
+                // The tokenizer loads in the background, keep its files until it's done
+                let tokenizer = registry::load(&card)?;
+                tokio::spawn(async move {
+                    let _ = tokenizer.wait().await;
+                    drop(cache_dir);
+                });
+
+                // Shared by the chat and completions pipelines. Remote prefill starts when prefill
+                // workers register the model.
+                let prefill_policy = DisaggregatedRouter::new_with_etcd_and_default(
+                    Arc::new(self.drt.clone()),
+                    model_entry.name.clone(),
+                    self.disagg_config.max_local_prefill_length,
+                )
+                .await?;
+                let prefill_queue = Arc::new(PrefillQueue::new(
+                    &model_entry.name,
+                    self.disagg_config.queue,
+                ));
 
                 let frontend = SegmentSource::<
                     SingleIn<NvCreateChatCompletionRequest>,
                     ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
                 >::new();
-                let preprocessor = OpenAIPreprocessor::new(card.clone()).await?.into_operator();
+                let preprocessor =
+                    OpenAIPreprocessor::new_with_loras(card.clone(), Some(self.manager.loras()))
+                        .await?
+                        .into_operator();
                 let backend = Backend::from_mdc(card.clone()).await?.into_operator();
-                let router =
-                    PushRouter::<PreprocessedRequest, Annotated<LLMEngineOutput>>::from_client(
-                        client.clone(),
-                        self.router_mode,
-                    )
-                    .await?;
-                let service_backend = match self.router_mode {
-                    RouterMode::Random | RouterMode::RoundRobin | RouterMode::Direct(_) => {
-                        ServiceBackend::from_engine(Arc::new(router))
-                    }
-                    RouterMode::KV => {
-                        let chooser = self
-                            .manager
-                            .kv_chooser_for(
-                                &model_entry.name,
-                                &component,
-                                card.kv_cache_block_size,
-                                self.kv_router_config.clone(),
-                            )
-                            .await?;
-                        let kv_push_router = KvPushRouter::new(router, chooser);
-                        ServiceBackend::from_engine(Arc::new(kv_push_router))
-                    }
-                };
+                let service_backend = ServiceBackend::from_engine(Arc::new(PrefillRouter::new(
+                    &model_entry.name,
+                    Arc::new(SpeculativeRouter::new(
+                        &model_entry.name,
+                        self.backend_router(
+                            model_entry,
+                            &component,
+                            client.clone(),
+                            card.kv_cache_block_size,
+                        )
+                        .await?,
+                        self.manager.draft_workers(&model_entry.name),
+                        self.speculative_config,
+                    )),
+                    self.manager.prefill_workers(&model_entry.name),
+                    prefill_policy.clone(),
+                    prefill_queue.clone(),
+                )));
 
                 let chat_engine = frontend
                     .link(preprocessor.forward_edge())?
@@ -242,32 +498,29 @@ impl ModelWatcher {
                     SingleIn<NvCreateCompletionRequest>,
                     ManyOut<Annotated<CompletionResponse>>,
                 >::new();
-                let preprocessor = OpenAIPreprocessor::new(card.clone()).await?.into_operator();
+                let preprocessor =
+                    OpenAIPreprocessor::new_with_loras(card.clone(), Some(self.manager.loras()))
+                        .await?
+                        .into_operator();
                 let backend = Backend::from_mdc(card.clone()).await?.into_operator();
-                let router =
-                    PushRouter::<PreprocessedRequest, Annotated<LLMEngineOutput>>::from_client(
-                        client,
-                        self.router_mode,
-                    )
-                    .await?;
-                let service_backend = match self.router_mode {
-                    RouterMode::Random | RouterMode::RoundRobin | RouterMode::Direct(_) => {
-                        ServiceBackend::from_engine(Arc::new(router))
-                    }
-                    RouterMode::KV => {
-                        let chooser = self
-                            .manager
-                            .kv_chooser_for(
-                                &model_entry.name,
-                                &component,
-                                card.kv_cache_block_size,
-                                self.kv_router_config.clone(),
-                            )
-                            .await?;
-                        let kv_push_router = KvPushRouter::new(router, chooser);
-                        ServiceBackend::from_engine(Arc::new(kv_push_router))
-                    }
-                };
+                let service_backend = ServiceBackend::from_engine(Arc::new(PrefillRouter::new(
+                    &model_entry.name,
+                    Arc::new(SpeculativeRouter::new(
+                        &model_entry.name,
+                        self.backend_router(
+                            model_entry,
+                            &component,
+                            client.clone(),
+                            card.kv_cache_block_size,
+                        )
+                        .await?,
+                        self.manager.draft_workers(&model_entry.name),
+                        self.speculative_config,
+                    )),
+                    self.manager.prefill_workers(&model_entry.name),
+                    prefill_policy.clone(),
+                    prefill_queue.clone(),
+                )));
 
                 let completions_engine = frontend
                     .link(preprocessor.forward_edge())?
@@ -278,6 +531,41 @@ impl ModelWatcher {
                     .link(frontend)?;
                 self.manager
                     .add_completions_model(&model_entry.name, completions_engine)?;
+
+                // The KV router picks workers by their load metrics, not the instances
+                if requirements.is_some() && self.router_mode.is_kv_routing() {
+                    let chooser = self
+                        .kv_chooser(model_entry, &component, card.kv_cache_block_size)
+                        .await?;
+                    worker_admission::follow_admitted(&client, chooser.worker_health());
+                }
+            }
+            ModelType::Prefill | ModelType::Draft => {
+                // The decode pipelines of the model send their prompts here, see
+                // `crate::prefill_router`, or ask here for draft tokens, see `crate::speculative`
+                let Some(card) = card else {
+                    anyhow::bail!("Missing model deployment card");
+                };
+                let engine = self
+                    .backend_router(
+                        model_entry,
+                        &component,
+                        client.clone(),
+                        card.kv_cache_block_size,
+                    )
+                    .await?;
+                if model_entry.model_type == ModelType::Prefill {
+                    self.manager.prefill_workers(&model_entry.name).set(engine);
+                } else {
+                    self.manager.draft_workers(&model_entry.name).set(engine);
+                }
+
+                if requirements.is_some() && self.router_mode.is_kv_routing() {
+                    let chooser = self
+                        .kv_chooser(model_entry, &component, card.kv_cache_block_size)
+                        .await?;
+                    worker_admission::follow_admitted(&client, chooser.worker_health());
+                }
             }
             ModelType::Chat => {
                 let push_router = PushRouter::<
@@ -306,8 +594,17 @@ impl ModelWatcher {
                 >::from_client(client, Default::default())
                 .await?;
                 let engine = Arc::new(push_router);
-                self.manager
-                    .add_embeddings_model(&model_entry.name, engine)?;
+                match self.embedding_batch_config {
+                    Some(batch_config) => {
+                        let batcher = EmbeddingBatcher::new(engine, batch_config);
+                        self.manager
+                            .add_embeddings_model(&model_entry.name, Arc::new(batcher))?;
+                    }
+                    None => {
+                        self.manager
+                            .add_embeddings_model(&model_entry.name, engine)?;
+                    }
+                }
             }
         }
 
@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Which workers may serve a model, by what they run.
+//!
+//! Workers publish a [`WorkerDescription`] with their instances: the engine and its version, the
+//! build, the CUDA and driver versions. A [`WorkerAdmissionPolicy`] sets per model the engine and
+//! the oldest engine version that may serve it. The ingress leaves the other workers out of
+//! routing, requests pinned to them included, so that an outdated worker that would produce wrong
+//! results never gets traffic. A worker that doesn't tell its engine version can't serve a model
+//! with a minimum version.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use dynamo_runtime::component::{Client, Instance, InstanceSource, WorkerDescription};
+use serde::{Deserialize, Serialize};
+
+use crate::kv_router::circuit_breaker::WorkerHealth;
+
+/// What a worker must run to serve a model
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerRequirements {
+    /// The engine, e.g. `vllm`
+    pub engine: Option<String>,
+
+    /// The oldest engine version, e.g. `0.9.1`
+    pub min_engine_version: Option<String>,
+}
+
+impl WorkerRequirements {
+    pub fn is_empty(&self) -> bool {
+        self.engine.is_none() && self.min_engine_version.is_none()
+    }
+
+    /// Why a worker described by `description` may not serve the model, None if it may
+    pub fn rejection(&self, description: Option<&WorkerDescription>) -> Option<String> {
+        let description = description.cloned().unwrap_or_default();
+        if let Some(engine) = &self.engine {
+            match &description.engine {
+                Some(worker_engine) if worker_engine == engine => {}
+                Some(worker_engine) => return Some(format!("runs {worker_engine}, not {engine}")),
+                None => return Some(format!("doesn't say it runs {engine}")),
+            }
+        }
+        if let Some(min) = &self.min_engine_version {
+            match &description.engine_version {
+                Some(version) if version_at_least(version, min) => {}
+                Some(version) => {
+                    return Some(format!("engine version {version} is older than {min}"))
+                }
+                None => return Some("doesn't say its engine version".to_string()),
+            }
+        }
+        None
+    }
+}
+
+/// The [`WorkerRequirements`] of each model
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerAdmissionPolicy {
+    /// For the models not in `per_model`
+    pub default: WorkerRequirements,
+
+    pub per_model: HashMap<String, WorkerRequirements>,
+}
+
+impl WorkerAdmissionPolicy {
+    /// Read the policy from a JSON file, e.g.
+    /// `{"per_model": {"llama": {"engine": "vllm", "min_engine_version": "0.9.1"}}}`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed reading worker admission policy from {}: {err}",
+                path.display()
+            )
+        })?;
+        serde_json::from_str(&contents).map_err(|err| {
+            anyhow::anyhow!(
+                "Invalid worker admission policy in {}: {err}",
+                path.display()
+            )
+        })
+    }
+
+    /// The requirements of `model`, None if it takes any worker
+    pub fn for_model(&self, model: &str) -> Option<WorkerRequirements> {
+        let requirements = self.per_model.get(model).unwrap_or(&self.default);
+        (!requirements.is_empty()).then(|| requirements.clone())
+    }
+}
+
+/// Whether `version` is at least `min`, comparing the numbers of their dot separated parts. A
+/// part ends at its first non-digit, so `0.9.1rc1` and `0.9.1+cu124` are both `0.9.1`.
+fn version_at_least(version: &str, min: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split('.')
+            .map_while(|part| {
+                let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse().ok()
+            })
+            .collect()
+    }
+    let (mut version, mut min) = (parts(version), parts(min));
+    let len = version.len().max(min.len());
+    version.resize(len, 0);
+    min.resize(len, 0);
+    version >= min
+}
+
+/// The instances of `client` that meet `requirements` of `model`. Logs each worker turned away
+/// once.
+pub(crate) fn admitted(client: &Client, model: &str, requirements: WorkerRequirements) -> Client {
+    let model = model.to_string();
+    let logged = Mutex::new(HashSet::new());
+    client.filtered(move |instance: &Instance| {
+        let Some(reason) = requirements.rejection(instance.description.as_ref()) else {
+            return true;
+        };
+        if logged.lock().unwrap().insert(instance.id()) {
+            tracing::warn!(
+                model,
+                instance_id = instance.id(),
+                reason,
+                "Worker turned away by the worker admission policy"
+            );
+        }
+        false
+    })
+}
+
+/// Keep the KV router with `health` to the instances of the `admitted` client, until the router
+/// is gone
+pub(crate) fn follow_admitted(admitted: &Client, health: &Arc<WorkerHealth>) {
+    let InstanceSource::Dynamic(instances_rx) = admitted.instance_source.as_ref() else {
+        return;
+    };
+    let mut instances_rx = instances_rx.clone();
+    let health = Arc::downgrade(health);
+    tokio::spawn(async move {
+        loop {
+            let admitted: HashSet<i64> = instances_rx
+                .borrow_and_update()
+                .iter()
+                .map(Instance::id)
+                .collect();
+            let Some(health) = health.upgrade() else {
+                break;
+            };
+            health.set_admitted(Some(admitted));
+            drop(health);
+            if instances_rx.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_at_least() {
+        assert!(version_at_least("0.9.1", "0.9.1"));
+        assert!(version_at_least("0.10.0", "0.9.1"));
+        assert!(version_at_least("v0.9.2rc1", "0.9.1"));
+        assert!(version_at_least("0.9.1+cu124", "0.9"));
+        assert!(!version_at_least("0.9", "0.9.1"));
+        assert!(!version_at_least("0.8.5", "0.9.1"));
+    }
+
+    #[test]
+    fn test_requirements() {
+        let policy: WorkerAdmissionPolicy = serde_json::from_str(
+            r#"{"per_model": {"llama": {"engine": "vllm", "min_engine_version": "0.9.1"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(policy.for_model("qwen"), None);
+        let requirements = policy.for_model("llama").unwrap();
+
+        let description = |engine: &str, version: Option<&str>| WorkerDescription {
+            engine: Some(engine.to_string()),
+            engine_version: version.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(
+            requirements.rejection(Some(&description("vllm", Some("0.9.2")))),
+            None
+        );
+        assert!(requirements
+            .rejection(Some(&description("vllm", Some("0.9.0"))))
+            .is_some());
+        assert!(requirements
+            .rejection(Some(&description("sglang", Some("0.9.2"))))
+            .is_some());
+        assert!(requirements
+            .rejection(Some(&description("vllm", None)))
+            .is_some());
+        assert!(requirements.rejection(None).is_some());
+    }
+}
@@ -0,0 +1,394 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Embedding requests are usually tiny (one or a few sentences) and engines are much more
+//! efficient when they embed many inputs at once. The [`EmbeddingBatcher`] sits in front of an
+//! embeddings engine, collects requests for up to `max_latency` or until `max_batch_size` inputs
+//! are pending, sends them downstream as a single request, and splits the result back out.
+//!
+//! Only requests that would produce the same output if sent alone are combined: same model,
+//! encoding format, dimensions and input kind (text or tokens).
+//!
+//! A request whose client went away before its batch was sent is left out of it, and a batch is
+//! stopped once all of its clients went away.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_openai::types::{CreateEmbeddingRequest, Embedding, EmbeddingInput, EmbeddingUsage};
+use async_trait::async_trait;
+use dynamo_runtime::engine::{
+    AsyncEngine, AsyncEngineContext, AsyncEngineContextProvider, ResponseStream,
+};
+use dynamo_runtime::pipeline::{Context, Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::protocols::openai::embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse};
+use crate::types::openai::embeddings::OpenAIEmbeddingsStreamingEngine;
+
+/// How many requests can wait to join a batch before callers are back-pressured
+const QUEUE_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddingBatchConfig {
+    /// Max number of inputs (not requests) in a single downstream call
+    pub max_batch_size: usize,
+
+    /// Longest time the first request in a batch waits for others to join
+    pub max_latency: Duration,
+}
+
+impl Default for EmbeddingBatchConfig {
+    fn default() -> Self {
+        EmbeddingBatchConfig {
+            max_batch_size: 64,
+            max_latency: Duration::from_millis(5),
+        }
+    }
+}
+
+/// An embeddings engine that coalesces concurrent requests into batches for the inner engine.
+pub struct EmbeddingBatcher {
+    tx: mpsc::Sender<Pending>,
+}
+
+struct Pending {
+    request: NvCreateEmbeddingRequest,
+    /// The context of the caller, to stop the batch when all its callers stopped
+    context: Arc<dyn AsyncEngineContext>,
+    inputs: BatchInputs,
+    reply: oneshot::Sender<anyhow::Result<NvCreateEmbeddingResponse>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum BatchInputs {
+    Text(Vec<String>),
+    Tokens(Vec<Vec<u32>>),
+}
+
+impl BatchInputs {
+    fn from_input(input: &EmbeddingInput) -> Self {
+        match input {
+            EmbeddingInput::String(s) => BatchInputs::Text(vec![s.clone()]),
+            EmbeddingInput::StringArray(v) => BatchInputs::Text(v.clone()),
+            EmbeddingInput::IntegerArray(v) => BatchInputs::Tokens(vec![v.clone()]),
+            EmbeddingInput::ArrayOfIntegerArray(v) => BatchInputs::Tokens(v.clone()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            BatchInputs::Text(v) => v.len(),
+            BatchInputs::Tokens(v) => v.len(),
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self, BatchInputs::Text(_))
+    }
+}
+
+/// Requests with equal keys can share a downstream call
+#[derive(Debug, PartialEq)]
+struct BatchKey {
+    model: String,
+    encoding_format: Option<String>,
+    dimensions: Option<u32>,
+    is_text: bool,
+}
+
+impl BatchKey {
+    fn of(p: &Pending) -> Self {
+        BatchKey {
+            model: p.request.inner.model.clone(),
+            encoding_format: p
+                .request
+                .inner
+                .encoding_format
+                .as_ref()
+                .and_then(|f| serde_json::to_string(f).ok()),
+            dimensions: p.request.inner.dimensions,
+            is_text: p.inputs.is_text(),
+        }
+    }
+}
+
+impl EmbeddingBatcher {
+    /// Wrap `inner`. Spawns the batching task on the current tokio runtime, which lives as long
+    /// as the returned engine.
+    pub fn new(inner: OpenAIEmbeddingsStreamingEngine, config: EmbeddingBatchConfig) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(batch_loop(inner, config, rx));
+        EmbeddingBatcher { tx }
+    }
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateEmbeddingRequest>,
+        ManyOut<Annotated<NvCreateEmbeddingResponse>>,
+        Error,
+    > for EmbeddingBatcher
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateEmbeddingRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateEmbeddingResponse>>, Error> {
+        let (request, context) = request.into_parts();
+        let context = context.context();
+        let inputs = BatchInputs::from_input(&request.inner.input);
+        let (reply, reply_rx) = oneshot::channel();
+        self.tx
+            .send(Pending {
+                request,
+                context: context.clone(),
+                inputs,
+                reply,
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("Embedding batcher has stopped"))?;
+        let response = tokio::select! {
+            reply = reply_rx => {
+                reply.map_err(|_| anyhow::anyhow!("Embedding batcher dropped the request"))??
+            }
+            _ = context.stopped() => anyhow::bail!("Embedding request {} cancelled", context.id()),
+        };
+        let stream = futures::stream::once(futures::future::ready(Annotated::from_data(response)));
+        Ok(ResponseStream::new(Box::pin(stream), context))
+    }
+}
+
+async fn batch_loop(
+    inner: OpenAIEmbeddingsStreamingEngine,
+    config: EmbeddingBatchConfig,
+    mut rx: mpsc::Receiver<Pending>,
+) {
+    while let Some(first) = rx.recv().await {
+        let deadline = Instant::now() + config.max_latency;
+        let mut num_inputs = first.inputs.len();
+        let mut batch = vec![first];
+        while num_inputs < config.max_batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(pending)) => {
+                    num_inputs += pending.inputs.len();
+                    batch.push(pending);
+                }
+                // Channel closed or latency budget spent
+                Ok(None) | Err(_) => break,
+            }
+        }
+        for group in group_compatible(batch) {
+            tokio::spawn(dispatch(inner.clone(), group));
+        }
+    }
+    tracing::debug!("Embedding batcher stopped");
+}
+
+/// Split into groups that can share a downstream call, preserving arrival order
+fn group_compatible(batch: Vec<Pending>) -> Vec<Vec<Pending>> {
+    let mut groups: Vec<(BatchKey, Vec<Pending>)> = Vec::new();
+    for pending in batch {
+        let key = BatchKey::of(&pending);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(pending),
+            None => groups.push((key, vec![pending])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+async fn dispatch(inner: OpenAIEmbeddingsStreamingEngine, group: Vec<Pending>) {
+    // Nobody waits for these any more
+    let group: Vec<Pending> = group
+        .into_iter()
+        .filter(|p| !p.context.is_stopped())
+        .collect();
+    if group.is_empty() {
+        return;
+    }
+    let counts: Vec<usize> = group.iter().map(|p| p.inputs.len()).collect();
+    let contexts: Vec<_> = group.iter().map(|p| p.context.clone()).collect();
+    let (requests, replies): (Vec<_>, Vec<_>) = group
+        .into_iter()
+        .map(|p| ((p.request, p.inputs), p.reply))
+        .unzip();
+
+    match call_inner(&inner, requests, contexts)
+        .await
+        .and_then(|response| split_response(response, &counts))
+    {
+        Ok(parts) => {
+            for (reply, part) in replies.into_iter().zip(parts) {
+                let _ = reply.send(Ok(part));
+            }
+        }
+        Err(err) => {
+            let msg = format!("{err:#}");
+            for reply in replies {
+                let _ = reply.send(Err(anyhow::anyhow!(msg.clone())));
+            }
+        }
+    }
+}
+
+/// Send the combined request, under the id of the first caller. It is stopped once every caller
+/// in `contexts` stopped.
+async fn call_inner(
+    inner: &OpenAIEmbeddingsStreamingEngine,
+    requests: Vec<(NvCreateEmbeddingRequest, BatchInputs)>,
+    contexts: Vec<Arc<dyn AsyncEngineContext>>,
+) -> anyhow::Result<NvCreateEmbeddingResponse> {
+    let mut requests = requests.into_iter();
+    let Some((first, first_inputs)) = requests.next() else {
+        anyhow::bail!("Empty embedding batch");
+    };
+    let input = requests.fold(first_inputs, |acc, (_, inputs)| match (acc, inputs) {
+        (BatchInputs::Text(mut a), BatchInputs::Text(b)) => {
+            a.extend(b);
+            BatchInputs::Text(a)
+        }
+        (BatchInputs::Tokens(mut a), BatchInputs::Tokens(b)) => {
+            a.extend(b);
+            BatchInputs::Tokens(a)
+        }
+        // group_compatible keys on the input kind
+        _ => unreachable!("Mixed input kinds in an embedding batch"),
+    });
+    let combined = NvCreateEmbeddingRequest {
+        inner: CreateEmbeddingRequest {
+            input: match input {
+                BatchInputs::Text(v) => EmbeddingInput::StringArray(v),
+                BatchInputs::Tokens(v) => EmbeddingInput::ArrayOfIntegerArray(v),
+            },
+            ..first.inner
+        },
+        nvext: first.nvext,
+    };
+    let request_id = contexts
+        .first()
+        .map(|context| context.id().to_string())
+        .unwrap_or_default();
+    let request = Context::with_id(combined, request_id);
+    let batch_context = request.context();
+    let linked = tokio::spawn(async move {
+        futures::future::join_all(contexts.iter().map(|context| context.stopped())).await;
+        batch_context.stop_generating();
+    });
+    let stream = inner.generate(request).await;
+    let response = match stream {
+        Ok(stream) => NvCreateEmbeddingResponse::from_annotated_stream(stream.into())
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to fold batched embeddings stream: {err}")),
+        Err(err) => Err(err),
+    };
+    linked.abort();
+    response
+}
+
+/// Split a batched response into one response per original request. `counts` is the number of
+/// inputs each request had, in order. Usage is shared out in proportion to the number of inputs.
+/// Fails if the engine didn't return one embedding per input.
+fn split_response(
+    response: NvCreateEmbeddingResponse,
+    counts: &[usize],
+) -> anyhow::Result<Vec<NvCreateEmbeddingResponse>> {
+    let total: usize = counts.iter().sum();
+    if response.inner.data.len() != total {
+        anyhow::bail!(
+            "Engine returned {} embeddings for {total} inputs",
+            response.inner.data.len()
+        );
+    }
+    let mut data: Vec<Embedding> = response.inner.data;
+    data.sort_by_key(|e| e.index);
+    let mut data = data.into_iter();
+
+    Ok(counts
+        .iter()
+        .map(|&count| {
+            let part: Vec<Embedding> = data
+                .by_ref()
+                .take(count)
+                .enumerate()
+                .map(|(i, mut e)| {
+                    e.index = i as u32;
+                    e
+                })
+                .collect();
+            let share = |n: u32| {
+                if total == 0 {
+                    0
+                } else {
+                    (n as u64 * count as u64 / total as u64) as u32
+                }
+            };
+            NvCreateEmbeddingResponse {
+                inner: async_openai::types::CreateEmbeddingResponse {
+                    object: response.inner.object.clone(),
+                    model: response.inner.model.clone(),
+                    data: part,
+                    usage: EmbeddingUsage {
+                        prompt_tokens: share(response.inner.usage.prompt_tokens),
+                        total_tokens: share(response.inner.usage.total_tokens),
+                    },
+                },
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(index: u32, value: f32) -> Embedding {
+        Embedding {
+            index,
+            object: "embedding".to_string(),
+            embedding: vec![value],
+        }
+    }
+
+    #[test]
+    fn test_split_response() {
+        let response = NvCreateEmbeddingResponse {
+            inner: async_openai::types::CreateEmbeddingResponse {
+                object: "list".to_string(),
+                model: "m".to_string(),
+                // Out of order on purpose
+                data: vec![embedding(2, 2.0), embedding(0, 0.0), embedding(1, 1.0)],
+                usage: EmbeddingUsage {
+                    prompt_tokens: 30,
+                    total_tokens: 30,
+                },
+            },
+        };
+        let parts = split_response(response.clone(), &[1, 2]).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].inner.data, vec![embedding(0, 0.0)]);
+        assert_eq!(
+            parts[1].inner.data,
+            vec![embedding(0, 1.0), embedding(1, 2.0)]
+        );
+        assert_eq!(parts[0].inner.usage.prompt_tokens, 10);
+        assert_eq!(parts[1].inner.usage.prompt_tokens, 20);
+
+        // One embedding missing
+        assert!(split_response(response, &[2, 2]).is_err());
+    }
+
+    #[test]
+    fn test_batch_inputs() {
+        let single = BatchInputs::from_input(&EmbeddingInput::String("a".to_string()));
+        assert_eq!(single, BatchInputs::Text(vec!["a".to_string()]));
+        let tokens = BatchInputs::from_input(&EmbeddingInput::ArrayOfIntegerArray(vec![
+            vec![1],
+            vec![2, 3],
+        ]));
+        assert_eq!(tokens.len(), 2);
+        assert!(!tokens.is_text());
+    }
+}
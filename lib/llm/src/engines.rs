@@ -78,7 +78,8 @@ pub static TOKEN_ECHO_DELAY: LazyLock<Duration> = LazyLock::new(|| {
 });
 
 /// Engine that accepts pre-processed requests and echos the tokens back as the response
-/// The response will include the full prompt template.
+/// The response will include the full prompt template, up to the first EOS token, where a real
+/// engine would stop too.
 /// Useful for testing pre-processing.
 struct EchoEngineCore {}
 pub fn make_engine_core() -> ExecutionContext {
@@ -100,6 +101,9 @@ impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutpu
             for tok in request.token_ids {
                 tokio::time::sleep(*TOKEN_ECHO_DELAY).await;
                 yield delta_core(tok);
+                if request.eos_token_ids.contains(&tok) {
+                    break;
+                }
             }
             yield Annotated::from_data(LLMEngineOutput::stop());
         };
@@ -115,6 +119,7 @@ fn delta_core(tok: u32) -> Annotated<LLMEngineOutput> {
         cum_log_probs: None,
         log_probs: None,
         finish_reason: None,
+        kv_transfer_params: None,
     };
     Annotated::from_data(delta)
 }
@@ -201,6 +206,7 @@ impl
                 let inner = deltas.create_choice(0, Some(c.to_string()), None, None);
                 let response = NvCreateChatCompletionStreamResponse {
                     inner,
+                    nvext: deltas.nvext(),
                 };
                 yield Annotated{ id: Some(id.to_string()), data: Some(response), event: None, chunk_tokens: None, input_tokens: None, output_tokens: None, comment: None };
                 id += 1;
@@ -209,6 +215,7 @@ impl
             let inner = deltas.create_choice(0, None, Some(async_openai::types::FinishReason::Stop), None);
             let response = NvCreateChatCompletionStreamResponse {
                 inner,
+                nvext: deltas.nvext(),
             };
             yield Annotated { id: Some(id.to_string()), data: Some(response), event: None, chunk_tokens: None, input_tokens: None, output_tokens: None, comment: None };
         };
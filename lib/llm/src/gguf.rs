@@ -36,6 +36,7 @@ pub(crate) use gguf_metadata::ContentConfig;
 pub use gguf_metadata::ModelConfigLike;
 pub(crate) use gguf_tokenizer::convert_gguf_to_hf_tokenizer;
 
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 pub const GGUF_MULTI_FILE_DELIMITER: &str = " ";
@@ -71,3 +72,118 @@ impl GGUFArchitecture {
             .map_err(anyhow::Error::msg)
     }
 }
+
+/// A file of a GGUF model split across several files, named like
+/// `Model-Q4_K_M-00001-of-00003.gguf`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Split<'a> {
+    /// `Model-Q4_K_M`
+    prefix: &'a str,
+    /// 1-based
+    index: u32,
+    count: u32,
+}
+
+impl<'a> Split<'a> {
+    fn parse(file_name: &'a str) -> Option<Self> {
+        let stem = file_name.strip_suffix(".gguf")?;
+        let (rest, count) = stem.rsplit_once("-of-")?;
+        let (prefix, index) = rest.rsplit_once('-')?;
+        let is_number = |s: &str| s.len() == 5 && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_number(index) || !is_number(count) {
+            return None;
+        }
+        let split = Split {
+            prefix,
+            index: index.parse().ok()?,
+            count: count.parse().ok()?,
+        };
+        (split.index >= 1 && split.index <= split.count).then_some(split)
+    }
+
+    fn file_name(&self, index: u32) -> String {
+        format!("{}-{index:05}-of-{:05}.gguf", self.prefix, self.count)
+    }
+}
+
+/// The files of the GGUF model at `path`, in order. That's only `path` itself, unless it is one
+/// file of a split model. Then it is all of them, whichever one `path` names, and an error lists
+/// those missing.
+pub fn gguf_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let Some(split) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(Split::parse)
+    else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let files: Vec<PathBuf> = (1..=split.count)
+        .map(|index| path.with_file_name(split.file_name(index)))
+        .collect();
+    let missing: Vec<String> = files
+        .iter()
+        .filter(|file| !file.is_file())
+        .map(|file| file.display().to_string())
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "GGUF model '{}' is split into {} files, {} missing: {}",
+            split.prefix,
+            split.count,
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    Ok(files)
+}
+
+/// Name of the GGUF model in `file_name`, without the split suffix if it is one file of a split
+/// model: `Model-Q4_K_M-00001-of-00003.gguf` -> `Model-Q4_K_M.gguf`
+pub fn gguf_model_name(file_name: &str) -> String {
+    match Split::parse(file_name) {
+        Some(split) => format!("{}.gguf", split.prefix),
+        None => file_name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_parse() {
+        let split = Split::parse("Model-Q4_K_M-00002-of-00003.gguf").unwrap();
+        assert_eq!(split.prefix, "Model-Q4_K_M");
+        assert_eq!((split.index, split.count), (2, 3));
+        assert_eq!(split.file_name(1), "Model-Q4_K_M-00001-of-00003.gguf");
+
+        assert!(Split::parse("Model-Q4_K_M.gguf").is_none());
+        assert!(Split::parse("Model-00004-of-00003.gguf").is_none());
+        assert!(Split::parse("Model-1-of-3.gguf").is_none());
+        assert_eq!(
+            gguf_model_name("Model-Q4_K_M-00001-of-00003.gguf"),
+            "Model-Q4_K_M.gguf"
+        );
+    }
+
+    #[test]
+    fn test_gguf_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard = |index: u32| dir.path().join(format!("m-{index:05}-of-00003.gguf"));
+        std::fs::write(shard(1), b"").unwrap();
+        std::fs::write(shard(3), b"").unwrap();
+
+        let err = gguf_files(&shard(3)).unwrap_err().to_string();
+        assert!(err.contains("split into 3 files, 1 missing"));
+        assert!(err.contains("m-00002-of-00003.gguf"));
+
+        std::fs::write(shard(2), b"").unwrap();
+        assert_eq!(
+            gguf_files(&shard(3)).unwrap(),
+            vec![shard(1), shard(2), shard(3)]
+        );
+
+        let single = dir.path().join("single.gguf");
+        assert_eq!(gguf_files(&single).unwrap(), vec![single.clone()]);
+    }
+}
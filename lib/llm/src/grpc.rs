@@ -0,0 +1,9 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod service;
+
+/// Messages and stubs generated from `proto/openai.proto`
+pub mod proto {
+    tonic::include_proto!("dynamo.openai.v1");
+}
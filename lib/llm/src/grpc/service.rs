@@ -0,0 +1,570 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A gRPC frontend serving the OpenAI chat completions and completions APIs of
+//! `proto/openai.proto`, for clients that want HTTP/2 multiplexing and generated stubs rather than
+//! REST and SSE. It serves the same engines as the HTTP service, registered in its own
+//! [`ModelManager`].
+//!
+//! Responses always stream. A request is turned into the OpenAI JSON request and deserialized like
+//! the HTTP service does, so both frontends accept the same parameters. Like the HTTP service it
+//! can require an API key, sent as `authorization: Bearer <key>` metadata, applies the
+//! [`NvExtPolicy`] and the principal to `nvext`, and fills in chat requests from the
+//! [`RequestTemplate`].
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use derive_builder::Builder;
+use dynamo_runtime::engine::Data;
+use dynamo_runtime::pipeline::{AsyncEngine, AsyncEngineContextProvider, Context, ManyOut};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status};
+
+use super::proto::{
+    self,
+    inference_server::{Inference, InferenceServer},
+};
+use crate::discovery::ModelManager;
+use crate::http::service::auth::{AuthKeys, Principal};
+use crate::http::service::continue_trace_from;
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+};
+use crate::protocols::openai::completions::{CompletionResponse, NvCreateCompletionRequest};
+use crate::protocols::openai::nvext::{NvExt, NvExtPolicy};
+use crate::request_template::RequestTemplate;
+
+#[derive(Clone)]
+pub struct GrpcService {
+    manager: Arc<ModelManager>,
+    port: u16,
+    host: String,
+    request_template: Option<RequestTemplate>,
+    nvext_policy: NvExtPolicy,
+    auth_keys: Option<Arc<AuthKeys>>,
+}
+
+#[derive(Clone, Builder)]
+#[builder(pattern = "owned", build_fn(private, name = "build_internal"))]
+pub struct GrpcServiceConfig {
+    #[builder(default = "50051")]
+    port: u16,
+
+    #[builder(setter(into), default = "String::from(\"0.0.0.0\")")]
+    host: String,
+
+    #[builder(default = "None")]
+    request_template: Option<RequestTemplate>,
+
+    #[builder(default)]
+    nvext_policy: NvExtPolicy,
+
+    /// When set, every call must carry `authorization: Bearer <key>` metadata with one of these
+    /// keys
+    #[builder(default = "None")]
+    auth_keys: Option<Arc<AuthKeys>>,
+}
+
+impl GrpcService {
+    pub fn builder() -> GrpcServiceConfigBuilder {
+        GrpcServiceConfigBuilder::default()
+    }
+
+    pub fn model_manager(&self) -> &ModelManager {
+        Arc::as_ref(&self.manager)
+    }
+
+    pub fn manager_clone(&self) -> Arc<ModelManager> {
+        self.manager.clone()
+    }
+
+    pub async fn spawn(&self, cancel_token: CancellationToken) -> JoinHandle<Result<()>> {
+        let this = self.clone();
+        tokio::spawn(async move { this.run(cancel_token).await })
+    }
+
+    pub async fn run(&self, cancel_token: CancellationToken) -> Result<()> {
+        let address = format!("{}:{}", self.host, self.port);
+        tracing::info!(address, "Starting gRPC service on: {address}");
+
+        let Some(socket_addr) = tokio::net::lookup_host(address.as_str()).await?.next() else {
+            anyhow::bail!("could not resolve address: {address}");
+        };
+        let frontend = Frontend {
+            manager: self.manager.clone(),
+            request_template: self.request_template.clone(),
+            nvext_policy: self.nvext_policy.clone(),
+            auth_keys: self.auth_keys.clone(),
+        };
+        let observer = cancel_token.child_token();
+        tonic::transport::Server::builder()
+            .add_service(InferenceServer::new(frontend))
+            .serve_with_shutdown(socket_addr, observer.cancelled_owned())
+            .await
+            .inspect_err(|_| cancel_token.cancel())?;
+        Ok(())
+    }
+}
+
+impl GrpcServiceConfigBuilder {
+    pub fn build(self) -> Result<GrpcService, anyhow::Error> {
+        let config: GrpcServiceConfig = self.build_internal()?;
+        Ok(GrpcService {
+            manager: Arc::new(ModelManager::new()),
+            port: config.port,
+            host: config.host,
+            request_template: config.request_template,
+            nvext_policy: config.nvext_policy,
+            auth_keys: config.auth_keys,
+        })
+    }
+
+    pub fn with_request_template(mut self, request_template: Option<RequestTemplate>) -> Self {
+        self.request_template = Some(request_template);
+        self
+    }
+
+    pub fn with_nvext_policy(mut self, nvext_policy: NvExtPolicy) -> Self {
+        self.nvext_policy = Some(nvext_policy);
+        self
+    }
+
+    pub fn with_auth_keys(mut self, auth_keys: Option<Arc<AuthKeys>>) -> Self {
+        self.auth_keys = Some(auth_keys);
+        self
+    }
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Handles the RPCs
+struct Frontend {
+    manager: Arc<ModelManager>,
+    request_template: Option<RequestTemplate>,
+    nvext_policy: NvExtPolicy,
+    auth_keys: Option<Arc<AuthKeys>>,
+}
+
+impl Frontend {
+    /// Check the call's API key if keys are required, and return whose it is
+    fn authorize<T>(&self, request: &Request<T>) -> Result<Option<Principal>, Status> {
+        let Some(keys) = &self.auth_keys else {
+            return Ok(None);
+        };
+        let Some(value) = request.metadata().get("authorization") else {
+            return Err(Status::unauthenticated(
+                "You didn't provide an API key. Provide it in the authorization metadata as 'Bearer <key>'.",
+            ));
+        };
+        let key = value
+            .to_str()
+            .ok()
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        match key {
+            Some(key) if keys.is_valid(key) => Ok(Some(Principal::from_key(key))),
+            Some(_) => Err(Status::unauthenticated("Incorrect API key provided.")),
+            None => Err(Status::unauthenticated(
+                "Malformed authorization metadata, expected 'Bearer <key>'.",
+            )),
+        }
+    }
+
+    /// Validate `nvext` and set its principal, as the HTTP service does. Only the caller's API key
+    /// decides the principal, whatever the request says.
+    fn apply_nvext(
+        &self,
+        principal: Option<Principal>,
+        nvext: &mut Option<NvExt>,
+    ) -> Result<(), Status> {
+        if let Some(nvext) = nvext.as_mut() {
+            self.nvext_policy
+                .apply(nvext)
+                .map_err(Status::invalid_argument)?;
+        }
+        match principal {
+            Some(Principal(principal)) => {
+                nvext.get_or_insert_with(NvExt::default).principal = Some(principal);
+            }
+            None => {
+                if let Some(nvext) = nvext.as_mut() {
+                    nvext.principal = None;
+                }
+            }
+        }
+        if let Some(nvext) = nvext.as_mut() {
+            self.nvext_policy.apply_pinning(nvext);
+            self.nvext_policy.apply_priority(nvext);
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Inference for Frontend {
+    async fn list_models(
+        &self,
+        request: Request<proto::ListModelsRequest>,
+    ) -> Result<Response<proto::ListModelsResponse>, Status> {
+        self.authorize(&request)?;
+        let mut names: Vec<String> = self.manager.model_display_names().into_iter().collect();
+        names.sort();
+        let models = names
+            .into_iter()
+            .map(|id| proto::Model {
+                id,
+                owned_by: "nvidia".to_string(),
+            })
+            .collect();
+        Ok(Response::new(proto::ListModelsResponse { models }))
+    }
+
+    type ChatCompletionStream = ResponseStream<proto::ChatCompletionChunk>;
+
+    #[tracing::instrument(skip_all, fields(request_id))]
+    async fn chat_completion(
+        &self,
+        request: Request<proto::ChatCompletionRequest>,
+    ) -> Result<Response<Self::ChatCompletionStream>, Status> {
+        let principal = self.authorize(&request)?;
+        let metadata = request.metadata().clone();
+        let mut request = chat_completion_request(request.into_inner())?;
+        self.apply_nvext(principal, &mut request.nvext)?;
+        continue_trace(&metadata, request.nvext.as_ref());
+        if let Some(template) = &self.request_template {
+            template.apply(&mut request);
+        }
+        check_single_choice(request.inner.n)?;
+        let engine = self
+            .manager
+            .get_chat_completions_engine(&request.inner.model)
+            .map_err(|_| model_not_found(&request.inner.model))?;
+        let request_id = uuid::Uuid::new_v4().to_string();
+        tracing::Span::current().record("request_id", request_id.as_str());
+        let stream = engine
+            .generate(Context::with_id(request, request_id))
+            .await
+            .map_err(|err| Status::internal(format!("Failed to generate completions: {err}")))?;
+        Ok(Response::new(forward(stream, chat_completion_chunk)))
+    }
+
+    type CompletionStream = ResponseStream<proto::CompletionChunk>;
+
+    #[tracing::instrument(skip_all, fields(request_id))]
+    async fn completion(
+        &self,
+        request: Request<proto::CompletionRequest>,
+    ) -> Result<Response<Self::CompletionStream>, Status> {
+        let principal = self.authorize(&request)?;
+        let metadata = request.metadata().clone();
+        let mut request = completion_request(request.into_inner())?;
+        self.apply_nvext(principal, &mut request.nvext)?;
+        continue_trace(&metadata, request.nvext.as_ref());
+        check_single_choice(request.inner.n)?;
+        let engine = self
+            .manager
+            .get_completions_engine(&request.inner.model)
+            .map_err(|_| model_not_found(&request.inner.model))?;
+        let request_id = uuid::Uuid::new_v4().to_string();
+        tracing::Span::current().record("request_id", request_id.as_str());
+        let stream = engine
+            .generate(Context::with_id(request, request_id))
+            .await
+            .map_err(|err| Status::internal(format!("Failed to generate completions: {err}")))?;
+        Ok(Response::new(forward(stream, completion_chunk)))
+    }
+}
+
+/// Make the call's span continue the client's trace, from the `traceparent` and `tracestate`
+/// metadata or else from `nvext.trace`, like the HTTP service
+fn continue_trace(metadata: &tonic::metadata::MetadataMap, nvext: Option<&NvExt>) {
+    continue_trace_from(
+        |name| metadata.get(name).and_then(|v| v.to_str().ok()),
+        nvext,
+    );
+}
+
+fn model_not_found(model: &str) -> Status {
+    Status::not_found(format!("Model not found: {model}"))
+}
+
+/// The gRPC frontend generates one choice per request
+fn check_single_choice(n: Option<u8>) -> Result<(), Status> {
+    match n {
+        None | Some(1) => Ok(()),
+        Some(n) => Err(Status::invalid_argument(format!(
+            "n must be 1 over gRPC, got {n}"
+        ))),
+    }
+}
+
+/// Send the engine's responses to the client as they arrive. The engine is told to stop
+/// generating as soon as the client goes away.
+fn forward<T, U>(mut stream: ManyOut<Annotated<T>>, convert: fn(T) -> U) -> ResponseStream<U>
+where
+    T: Data,
+    U: Send + 'static,
+{
+    let ctx = stream.context();
+    let (tx, rx) = tokio::sync::mpsc::channel(8);
+    tokio::spawn(async move {
+        loop {
+            let response = tokio::select! {
+                _ = tx.closed() => {
+                    tracing::trace!(request_id = ctx.id(), "gRPC client went away");
+                    ctx.stop_generating();
+                    return;
+                }
+                response = stream.next() => response,
+            };
+            let Some(response) = response else {
+                return;
+            };
+            let message = match response.into_result() {
+                Ok(Some(data)) => Ok(convert(data)),
+                Ok(None) => continue,
+                Err(err) => Err(Status::internal(err.to_string())),
+            };
+            let failed = message.is_err();
+            if tx.send(message).await.is_err() {
+                ctx.stop_generating();
+                return;
+            }
+            if failed {
+                return;
+            }
+        }
+    });
+    Box::pin(ReceiverStream::new(rx))
+}
+
+/// The OpenAI JSON request: the object in `extra_json`, overridden by the typed fields
+fn request_body(extra_json: &str) -> Result<Map<String, Value>, Status> {
+    if extra_json.trim().is_empty() {
+        return Ok(Map::new());
+    }
+    match serde_json::from_str(extra_json) {
+        Ok(Value::Object(body)) => Ok(body),
+        Ok(_) => Err(Status::invalid_argument("extra_json must be a JSON object")),
+        Err(err) => Err(Status::invalid_argument(format!(
+            "extra_json is not valid JSON: {err}"
+        ))),
+    }
+}
+
+fn insert<T: Serialize>(body: &mut Map<String, Value>, key: &str, value: Option<T>) {
+    if let Some(value) = value {
+        body.insert(key.to_string(), json!(value));
+    }
+}
+
+fn chat_completion_request(
+    request: proto::ChatCompletionRequest,
+) -> Result<NvCreateChatCompletionRequest, Status> {
+    let mut body = request_body(&request.extra_json)?;
+    let messages: Vec<Value> = request
+        .messages
+        .into_iter()
+        .map(|message| {
+            let mut out = json!({"role": message.role, "content": message.content});
+            if let Some(name) = message.name {
+                out["name"] = Value::String(name);
+            }
+            out
+        })
+        .collect();
+    body.insert("model".to_string(), Value::String(request.model));
+    body.insert("messages".to_string(), Value::Array(messages));
+    insert(
+        &mut body,
+        "max_completion_tokens",
+        request.max_completion_tokens,
+    );
+    insert(&mut body, "temperature", request.temperature);
+    insert(&mut body, "top_p", request.top_p);
+    insert(&mut body, "seed", request.seed);
+    insert(&mut body, "presence_penalty", request.presence_penalty);
+    insert(&mut body, "frequency_penalty", request.frequency_penalty);
+    insert(&mut body, "user", request.user);
+    insert(
+        &mut body,
+        "stop",
+        Some(request.stop).filter(|s| !s.is_empty()),
+    );
+    body.insert("stream".to_string(), Value::Bool(true));
+    serde_json::from_value(Value::Object(body))
+        .map_err(|err| Status::invalid_argument(format!("Invalid request: {err}")))
+}
+
+fn completion_request(
+    request: proto::CompletionRequest,
+) -> Result<NvCreateCompletionRequest, Status> {
+    let mut body = request_body(&request.extra_json)?;
+    body.insert("model".to_string(), Value::String(request.model));
+    body.insert("prompt".to_string(), Value::String(request.prompt));
+    insert(&mut body, "max_tokens", request.max_tokens);
+    insert(&mut body, "temperature", request.temperature);
+    insert(&mut body, "top_p", request.top_p);
+    insert(&mut body, "seed", request.seed);
+    insert(&mut body, "presence_penalty", request.presence_penalty);
+    insert(&mut body, "frequency_penalty", request.frequency_penalty);
+    insert(&mut body, "user", request.user);
+    insert(
+        &mut body,
+        "stop",
+        Some(request.stop).filter(|s| !s.is_empty()),
+    );
+    body.insert("stream".to_string(), Value::Bool(true));
+    serde_json::from_value(Value::Object(body))
+        .map_err(|err| Status::invalid_argument(format!("Invalid request: {err}")))
+}
+
+/// The name an enum value has in the OpenAI JSON, e.g. `stop` for a finish reason
+fn json_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+fn chat_completion_chunk(
+    response: NvCreateChatCompletionStreamResponse,
+) -> proto::ChatCompletionChunk {
+    let response = response.inner;
+    proto::ChatCompletionChunk {
+        id: response.id,
+        model: response.model,
+        created: response.created.into(),
+        choices: response
+            .choices
+            .into_iter()
+            .map(|choice| proto::ChatChoice {
+                index: choice.index,
+                delta: Some(proto::Delta {
+                    role: choice.delta.role.as_ref().map(json_name),
+                    content: choice.delta.content,
+                }),
+                finish_reason: choice.finish_reason.as_ref().map(json_name),
+            })
+            .collect(),
+        usage: response.usage.map(|usage| proto::Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+        }),
+    }
+}
+
+fn completion_chunk(response: CompletionResponse) -> proto::CompletionChunk {
+    proto::CompletionChunk {
+        id: response.id,
+        model: response.model,
+        created: response.created,
+        choices: response
+            .choices
+            .into_iter()
+            .map(|choice| proto::CompletionChoice {
+                index: choice.index as u32,
+                text: choice.text,
+                finish_reason: choice.finish_reason,
+            })
+            .collect(),
+        usage: response.usage.map(|usage| proto::Usage {
+            prompt_tokens: usage.prompt_tokens as u32,
+            completion_tokens: usage.completion_tokens as u32,
+            total_tokens: usage.total_tokens as u32,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_completion_request() {
+        let request = proto::ChatCompletionRequest {
+            model: "llama".to_string(),
+            messages: vec![proto::ChatMessage {
+                role: "user".to_string(),
+                content: "Hello".to_string(),
+                name: None,
+            }],
+            max_completion_tokens: Some(16),
+            temperature: Some(0.5),
+            stop: vec!["\n".to_string()],
+            extra_json: r#"{"temperature": 1.0, "nvext": {"top_k": 40}}"#.to_string(),
+            ..Default::default()
+        };
+        let request = chat_completion_request(request).unwrap();
+        assert_eq!(request.inner.model, "llama");
+        assert_eq!(request.inner.messages.len(), 1);
+        assert_eq!(request.inner.max_completion_tokens, Some(16));
+        // The typed field wins over extra_json
+        assert_eq!(request.inner.temperature, Some(0.5));
+        assert_eq!(request.inner.stream, Some(true));
+        assert_eq!(request.nvext.unwrap().top_k, Some(40));
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let request = proto::ChatCompletionRequest {
+            model: "llama".to_string(),
+            extra_json: "[1, 2]".to_string(),
+            ..Default::default()
+        };
+        let err = chat_completion_request(request).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        let request = proto::ChatCompletionRequest {
+            model: "llama".to_string(),
+            messages: vec![proto::ChatMessage {
+                role: "narrator".to_string(),
+                content: "Hello".to_string(),
+                name: None,
+            }],
+            ..Default::default()
+        };
+        let err = chat_completion_request(request).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        assert!(check_single_choice(Some(2)).is_err());
+        assert!(check_single_choice(None).is_ok());
+    }
+
+    #[test]
+    fn test_authorize() {
+        let frontend = Frontend {
+            manager: Arc::new(ModelManager::new()),
+            request_template: None,
+            nvext_policy: NvExtPolicy::default(),
+            auth_keys: Some(AuthKeys::from_keys(["sk-one"])),
+        };
+        let mut request = Request::new(());
+        let err = frontend.authorize(&request).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let metadata = request.metadata_mut();
+        metadata.insert("authorization", "Bearer sk-two".parse().unwrap());
+        let err = frontend.authorize(&request).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let metadata = request.metadata_mut();
+        metadata.insert("authorization", "Bearer sk-one".parse().unwrap());
+        let principal = frontend.authorize(&request).unwrap();
+        assert_eq!(principal, Some(Principal::from_key("sk-one")));
+
+        // The request can't claim somebody else's principal
+        let mut nvext = Some(NvExt::default());
+        nvext.as_mut().unwrap().principal = Some("key-000000000000".to_string());
+        frontend.apply_nvext(principal, &mut nvext).unwrap();
+        let expected = Principal::from_key("sk-one").0;
+        assert_eq!(nvext.unwrap().principal, Some(expected));
+    }
+}
@@ -18,15 +18,35 @@
 //!
 //! The [`service_v2::HttpService`] can be further extended to host any [`axum::Router`] using the [`service_v2::HttpServiceConfigBuilder`].
 
+mod choices;
+mod ollama;
 mod openai;
+mod responses;
+mod tokenize;
 
+pub mod admin;
+pub mod admission;
+pub mod auth;
+pub mod cors;
 pub mod error;
+pub mod framing;
 pub mod health;
+pub mod idempotency;
+pub mod interceptor;
+pub mod limits;
 pub mod metrics;
+pub mod output_rate;
+pub mod playground;
+pub mod rate_limit;
+pub mod response_cache;
 pub mod service_v2;
+pub mod spillover;
+pub mod tls;
+pub mod usage;
 
 pub use axum;
 pub use metrics::Metrics;
+pub(crate) use openai::continue_trace_from;
 
 /// Documentation for a route
 #[derive(Debug, Clone)]
@@ -0,0 +1,692 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Admin API to load and unload models on running workers, to drain them, and to inspect this
+//! process.
+//!
+//! Requests need `Authorization: Bearer <key>` with one of the admin keys, which are separate
+//! from the API keys of the OpenAI endpoints. A worker is addressed by the endpoint it serves, and
+//! optionally its instance id. Without an instance id any one worker of that component is picked.
+//!
+//! - `POST /admin/models` with `{"endpoint": "dyn://ns.backend.generate", "model_path": "...",
+//!   "model_name": "..."}` loads a model. `model_name` is optional.
+//! - `DELETE /admin/models/{model_name}` with `{"endpoint": "dyn://ns.backend.generate"}` unloads
+//!   it.
+//!
+//! - `POST /admin/drain` with `{"instance_id": 123}` drains the worker: it unregisters from
+//!   etcd, finishes the requests in flight and exits.
+//! - `GET /admin/standby` lists the endpoints of the standby workers, see
+//!   [`dynamo_runtime::standby`]. `POST /admin/activate` with `{"instance_id": 123}` activates
+//!   one, its endpoints become routable.
+//! - `GET /admin/tasks` lists the long-lived tasks of this process and how they are doing, see
+//!   [`dynamo_runtime::tasks`].
+//! - `GET /admin/topology` returns the frontends, models, components and worker instances as a
+//!   graph, see [`crate::discovery::topology`]. JSON by default, Graphviz with `?format=dot`.
+//! - `GET /admin/build-info` returns the version, git commit, cargo features, engine script
+//!   hashes and protocol versions of this process, see [`crate::build_info`].
+//! - `GET /admin/blacklist` lists the workers the KV routers send no requests to.
+//!   `POST /admin/blacklist` with `{"instance_id": 123, "reason": "..."}` adds one, `reason` is
+//!   optional, and `DELETE /admin/blacklist/{instance_id}` takes it back. The blacklist is kept
+//!   in etcd, every KV router follows it, see [`crate::kv_router::circuit_breaker`].
+//!
+//! - `GET /admin/card-revisions/{model_name}` lists the revisions of the model's deployment card
+//!   we can roll back to, and the one it is pinned to. `POST` with `{"revision": 3}` rolls the
+//!   card back to that revision and pins it there: workers that register afterwards keep it
+//!   instead of publishing their own. `DELETE` unpins it. See [`crate::model_card::rollback`].
+//!
+//! - `POST /admin/loras` with `{"endpoint": "dyn://ns.backend.generate", "lora_name": "...",
+//!   "lora_path": "..."}` loads a LoRA adapter on the workers of the endpoint, or only on
+//!   `instance_id`. Requests for `lora_name` then go to the model of the endpoint, with the
+//!   adapter, see [`crate::lora`].
+//! - `DELETE /admin/loras/{lora_name}` unloads it, from every worker or from `?instance_id=123`.
+//!   `GET /admin/loras` lists the adapters and the workers that loaded them.
+//!
+//! Loading and unloading answer once the worker is done, with the models it loaded this way. The
+//! worker updates its registration in etcd, so every ingress picks up the change. Draining and
+//! activating answer once the worker started.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use dynamo_runtime::{
+    component::Instance, pipeline::RouterMode, protocols, tasks::TaskInfo, transports::etcd,
+    DistributedRuntime,
+};
+use serde::{Deserialize, Serialize};
+
+use super::auth::{self, AuthKeys};
+use super::error::openai_error_response;
+use super::RouteDoc;
+use crate::build_info::BuildInfo;
+use crate::discovery::model_control::{ModelControl, ModelControlClient};
+use crate::discovery::topology::Topology;
+use crate::kv_router::circuit_breaker::{self, BlacklistEntry};
+use crate::lora::{LoadedLora, LoraClient, LoraControlReply};
+use crate::model_card;
+
+/// Every admin route starts with this
+pub const ADMIN_PATH_PREFIX: &str = "/admin/";
+
+/// Who may use the admin API, and how it reaches the workers
+#[derive(Clone)]
+pub struct AdminConfig {
+    keys: Arc<AuthKeys>,
+    drt: DistributedRuntime,
+    control: ModelControlClient,
+    loras: LoraClient,
+    /// How this frontend routes requests, for the topology
+    router_mode: RouterMode,
+    build_info: Arc<BuildInfo>,
+}
+
+impl AdminConfig {
+    pub fn new(keys: Arc<AuthKeys>, drt: DistributedRuntime) -> Self {
+        let control = ModelControlClient::new(drt.clone());
+        let loras = LoraClient::new(drt.clone());
+        AdminConfig {
+            keys,
+            drt,
+            control,
+            loras,
+            router_mode: RouterMode::default(),
+            build_info: Arc::new(BuildInfo::default()),
+        }
+    }
+
+    pub fn with_router_mode(mut self, router_mode: RouterMode) -> Self {
+        self.router_mode = router_mode;
+        self
+    }
+
+    /// What to answer on `/admin/build-info`. Defaults to this library's.
+    pub fn with_build_info(mut self, build_info: BuildInfo) -> Self {
+        self.build_info = Arc::new(build_info);
+        self
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LoadRequest {
+    endpoint: String,
+    instance_id: Option<i64>,
+    model_path: String,
+    model_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnloadRequest {
+    endpoint: String,
+    instance_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkerRequest {
+    instance_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct WorkerReply {
+    instance_id: i64,
+    status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct StandbyReply {
+    instances: Vec<Instance>,
+}
+
+#[derive(Debug, Serialize)]
+struct TasksReply {
+    /// No task failed or panicked
+    healthy: bool,
+    tasks: Vec<TaskInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BlacklistedWorker {
+    instance_id: i64,
+    #[serde(flatten)]
+    entry: BlacklistEntry,
+}
+
+#[derive(Debug, Serialize)]
+struct BlacklistReply {
+    instances: Vec<BlacklistedWorker>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RollbackRequest {
+    revision: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct RollbackReply {
+    model_name: String,
+    /// The new revision of the card, with the content of `pinned`
+    revision: u64,
+    pinned: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoraLoadRequest {
+    endpoint: String,
+    instance_id: Option<i64>,
+    lora_name: String,
+    lora_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoraUnloadQuery {
+    instance_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LoraWorker {
+    instance_id: i64,
+    #[serde(flatten)]
+    reply: LoraControlReply,
+}
+
+#[derive(Debug, Serialize)]
+struct LoraReply {
+    instances: Vec<LoraWorker>,
+}
+
+#[derive(Debug, Serialize)]
+struct LoraListReply {
+    loras: Vec<LoadedLora>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TopologyFormat {
+    #[default]
+    Json,
+    Dot,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopologyQuery {
+    #[serde(default)]
+    format: TopologyFormat,
+}
+
+async fn load_model(
+    State(config): State<AdminConfig>,
+    Json(request): Json<LoadRequest>,
+) -> Response {
+    let message = ModelControl::Load {
+        model_path: request.model_path,
+        model_name: request.model_name,
+    };
+    send(
+        &config.control,
+        &request.endpoint,
+        request.instance_id,
+        message,
+    )
+    .await
+}
+
+async fn unload_model(
+    State(config): State<AdminConfig>,
+    Path(model_name): Path<String>,
+    Json(request): Json<UnloadRequest>,
+) -> Response {
+    let message = ModelControl::Unload { model_name };
+    send(
+        &config.control,
+        &request.endpoint,
+        request.instance_id,
+        message,
+    )
+    .await
+}
+
+async fn drain_worker(
+    State(config): State<AdminConfig>,
+    Json(request): Json<WorkerRequest>,
+) -> Response {
+    let instance_id = request.instance_id;
+    match config.drt.request_drain(instance_id).await {
+        Ok(()) => (
+            StatusCode::ACCEPTED,
+            Json(WorkerReply {
+                instance_id,
+                status: "draining",
+            }),
+        )
+            .into_response(),
+        Err(err) => {
+            tracing::error!(instance_id, "Drain failed: {err:#}");
+            openai_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("{err:#}"),
+                "server_error",
+                "drain_failed",
+            )
+        }
+    }
+}
+
+async fn list_standby(State(config): State<AdminConfig>) -> Response {
+    match config.drt.standby_instances().await {
+        Ok(instances) => Json(StandbyReply { instances }).into_response(),
+        Err(err) => {
+            tracing::error!("Listing standby workers failed: {err:#}");
+            openai_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("{err:#}"),
+                "server_error",
+                "standby_failed",
+            )
+        }
+    }
+}
+
+async fn activate_worker(
+    State(config): State<AdminConfig>,
+    Json(request): Json<WorkerRequest>,
+) -> Response {
+    let instance_id = request.instance_id;
+    match config.drt.request_activation(instance_id).await {
+        Ok(()) => Json(WorkerReply {
+            instance_id,
+            status: "active",
+        })
+        .into_response(),
+        Err(err) => {
+            tracing::error!(instance_id, "Activation failed: {err:#}");
+            openai_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("{err:#}"),
+                "server_error",
+                "activation_failed",
+            )
+        }
+    }
+}
+
+async fn list_tasks(State(config): State<AdminConfig>) -> Json<TasksReply> {
+    let tasks = config.drt.runtime().tasks();
+    Json(TasksReply {
+        healthy: tasks.is_healthy(),
+        tasks: tasks.list(),
+    })
+}
+
+async fn build_info(State(config): State<AdminConfig>) -> Json<BuildInfo> {
+    Json(config.build_info.as_ref().clone())
+}
+
+async fn topology(
+    State(config): State<AdminConfig>,
+    Query(query): Query<TopologyQuery>,
+) -> Response {
+    let mut topology = match Topology::discover(&config.drt, None).await {
+        Ok(topology) => topology,
+        Err(err) => {
+            tracing::error!("Topology discovery failed: {err:#}");
+            return openai_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("{err:#}"),
+                "server_error",
+                "topology_failed",
+            );
+        }
+    };
+    topology.add_frontend("http", config.router_mode);
+    match query.format {
+        TopologyFormat::Json => Json(topology).into_response(),
+        TopologyFormat::Dot => (
+            [(header::CONTENT_TYPE, "text/vnd.graphviz")],
+            topology.to_dot(),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_blacklist(State(config): State<AdminConfig>) -> Response {
+    let etcd_client = match etcd_client(&config, "Static workers have no blacklist") {
+        Ok(etcd_client) => etcd_client,
+        Err(response) => return response,
+    };
+    match circuit_breaker::list_blacklist(&etcd_client).await {
+        Ok(blacklist) => Json(BlacklistReply {
+            instances: blacklist
+                .into_iter()
+                .map(|(instance_id, entry)| BlacklistedWorker { instance_id, entry })
+                .collect(),
+        })
+        .into_response(),
+        Err(err) => blacklist_failed(err),
+    }
+}
+
+async fn blacklist_worker(
+    State(config): State<AdminConfig>,
+    Json(request): Json<BlacklistedWorker>,
+) -> Response {
+    let etcd_client = match etcd_client(&config, "Static workers have no blacklist") {
+        Ok(etcd_client) => etcd_client,
+        Err(response) => return response,
+    };
+    let instance_id = request.instance_id;
+    match circuit_breaker::blacklist(&etcd_client, instance_id, &request.entry).await {
+        Ok(()) => {
+            tracing::info!(instance_id, reason = ?request.entry.reason, "Worker blacklisted by admin");
+            Json(WorkerReply {
+                instance_id,
+                status: "blacklisted",
+            })
+            .into_response()
+        }
+        Err(err) => blacklist_failed(err),
+    }
+}
+
+async fn unblacklist_worker(
+    State(config): State<AdminConfig>,
+    Path(instance_id): Path<i64>,
+) -> Response {
+    let etcd_client = match etcd_client(&config, "Static workers have no blacklist") {
+        Ok(etcd_client) => etcd_client,
+        Err(response) => return response,
+    };
+    match circuit_breaker::unblacklist(&etcd_client, instance_id).await {
+        Ok(true) => Json(WorkerReply {
+            instance_id,
+            status: "active",
+        })
+        .into_response(),
+        Ok(false) => openai_error_response(
+            StatusCode::NOT_FOUND,
+            &format!("Worker {instance_id} is not blacklisted"),
+            "invalid_request_error",
+            "not_blacklisted",
+        ),
+        Err(err) => blacklist_failed(err),
+    }
+}
+
+fn etcd_client(config: &AdminConfig, missing: &str) -> Result<etcd::Client, Response> {
+    config.drt.etcd_client().ok_or_else(|| {
+        openai_error_response(
+            StatusCode::NOT_IMPLEMENTED,
+            missing,
+            "invalid_request_error",
+            "no_etcd",
+        )
+    })
+}
+
+fn blacklist_failed(err: anyhow::Error) -> Response {
+    tracing::error!("Blacklist update failed: {err:#}");
+    openai_error_response(
+        StatusCode::BAD_GATEWAY,
+        &format!("{err:#}"),
+        "server_error",
+        "blacklist_failed",
+    )
+}
+
+async fn card_revisions(
+    State(config): State<AdminConfig>,
+    Path(model_name): Path<String>,
+) -> Response {
+    let etcd_client = match etcd_client(&config, "Static models have no card revisions") {
+        Ok(etcd_client) => etcd_client,
+        Err(response) => return response,
+    };
+    match model_card::revisions(&etcd_client, &model_name).await {
+        Ok(revisions) => Json(revisions).into_response(),
+        Err(err) => card_failed(&model_name, err),
+    }
+}
+
+async fn rollback_card(
+    State(config): State<AdminConfig>,
+    Path(model_name): Path<String>,
+    Json(request): Json<RollbackRequest>,
+) -> Response {
+    let etcd_client = match etcd_client(&config, "Static models have no card revisions") {
+        Ok(etcd_client) => etcd_client,
+        Err(response) => return response,
+    };
+    match model_card::rollback(&etcd_client, &model_name, request.revision).await {
+        Ok(card) => {
+            tracing::info!(
+                model_name,
+                to = request.revision,
+                "Card rolled back by admin"
+            );
+            Json(RollbackReply {
+                model_name,
+                revision: card.revision,
+                pinned: request.revision,
+            })
+            .into_response()
+        }
+        Err(err) => card_failed(&model_name, err),
+    }
+}
+
+async fn unpin_card(State(config): State<AdminConfig>, Path(model_name): Path<String>) -> Response {
+    let etcd_client = match etcd_client(&config, "Static models have no card revisions") {
+        Ok(etcd_client) => etcd_client,
+        Err(response) => return response,
+    };
+    match model_card::unpin(&etcd_client, &model_name).await {
+        Ok(true) => match model_card::revisions(&etcd_client, &model_name).await {
+            Ok(revisions) => Json(revisions).into_response(),
+            Err(err) => card_failed(&model_name, err),
+        },
+        Ok(false) => openai_error_response(
+            StatusCode::NOT_FOUND,
+            &format!("The card of {model_name} is not pinned"),
+            "invalid_request_error",
+            "card_not_pinned",
+        ),
+        Err(err) => card_failed(&model_name, err),
+    }
+}
+
+fn card_failed(model_name: &str, err: anyhow::Error) -> Response {
+    tracing::error!(model_name, "Card revision control failed: {err:#}");
+    openai_error_response(
+        StatusCode::BAD_GATEWAY,
+        &format!("{err:#}"),
+        "server_error",
+        "card_revision_failed",
+    )
+}
+
+async fn send(
+    control: &ModelControlClient,
+    endpoint: &str,
+    instance_id: Option<i64>,
+    message: ModelControl,
+) -> Response {
+    let endpoint: protocols::Endpoint = match endpoint.parse() {
+        Ok(endpoint) => endpoint,
+        Err(err) => {
+            return openai_error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid endpoint '{endpoint}': {err}"),
+                "invalid_request_error",
+                "invalid_endpoint",
+            );
+        }
+    };
+    match control.send(&endpoint, instance_id, &message).await {
+        Ok(reply) => Json(reply).into_response(),
+        Err(err) => {
+            tracing::error!(?message, "Model control failed: {err:#}");
+            openai_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("{err:#}"),
+                "server_error",
+                "model_control_failed",
+            )
+        }
+    }
+}
+
+async fn load_lora(
+    State(config): State<AdminConfig>,
+    Json(request): Json<LoraLoadRequest>,
+) -> Response {
+    let endpoint: protocols::Endpoint = match request.endpoint.parse() {
+        Ok(endpoint) => endpoint,
+        Err(err) => {
+            return openai_error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid endpoint '{}': {err}", request.endpoint),
+                "invalid_request_error",
+                "invalid_endpoint",
+            );
+        }
+    };
+    let loaded = config
+        .loras
+        .load(
+            &endpoint,
+            request.instance_id,
+            &request.lora_name,
+            &request.lora_path,
+        )
+        .await;
+    match loaded {
+        Ok(replies) => lora_reply(replies),
+        Err(err) => lora_failed(&request.lora_name, err),
+    }
+}
+
+async fn unload_lora(
+    State(config): State<AdminConfig>,
+    Path(lora_name): Path<String>,
+    Query(query): Query<LoraUnloadQuery>,
+) -> Response {
+    match config.loras.unload(&lora_name, query.instance_id).await {
+        Ok(replies) if replies.is_empty() => openai_error_response(
+            StatusCode::NOT_FOUND,
+            &format!("LoRA adapter {lora_name} is not loaded"),
+            "invalid_request_error",
+            "lora_not_loaded",
+        ),
+        Ok(replies) => lora_reply(replies),
+        Err(err) => lora_failed(&lora_name, err),
+    }
+}
+
+async fn list_loras(State(config): State<AdminConfig>) -> Response {
+    match config.loras.list().await {
+        Ok(loras) => Json(LoraListReply { loras }).into_response(),
+        Err(err) => {
+            tracing::error!("Listing LoRA adapters failed: {err:#}");
+            openai_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("{err:#}"),
+                "server_error",
+                "lora_failed",
+            )
+        }
+    }
+}
+
+fn lora_reply(replies: BTreeMap<i64, LoraControlReply>) -> Response {
+    Json(LoraReply {
+        instances: replies
+            .into_iter()
+            .map(|(instance_id, reply)| LoraWorker { instance_id, reply })
+            .collect(),
+    })
+    .into_response()
+}
+
+fn lora_failed(lora_name: &str, err: anyhow::Error) -> Response {
+    tracing::error!(lora_name, "LoRA adapter control failed: {err:#}");
+    openai_error_response(
+        StatusCode::BAD_GATEWAY,
+        &format!("{err:#}"),
+        "server_error",
+        "lora_failed",
+    )
+}
+
+async fn admin_auth_middleware(
+    State(keys): State<Arc<AuthKeys>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match auth::authorize(&keys, &request) {
+        Ok(_) => next.run(request).await,
+        Err(response) => response,
+    }
+}
+
+pub fn admin_router(config: AdminConfig) -> (Vec<RouteDoc>, Router) {
+    let path = format!("{ADMIN_PATH_PREFIX}models");
+    // Model names can contain slashes
+    let model_path = format!("{path}/{{*model_name}}");
+    let drain_path = format!("{ADMIN_PATH_PREFIX}drain");
+    let standby_path = format!("{ADMIN_PATH_PREFIX}standby");
+    let activate_path = format!("{ADMIN_PATH_PREFIX}activate");
+    let tasks_path = format!("{ADMIN_PATH_PREFIX}tasks");
+    let topology_path = format!("{ADMIN_PATH_PREFIX}topology");
+    let build_info_path = format!("{ADMIN_PATH_PREFIX}build-info");
+    let blacklist_path = format!("{ADMIN_PATH_PREFIX}blacklist");
+    let blacklisted_path = format!("{blacklist_path}/{{instance_id}}");
+    // Model names can contain slashes
+    let card_path = format!("{ADMIN_PATH_PREFIX}card-revisions/{{*model_name}}");
+    let loras_path = format!("{ADMIN_PATH_PREFIX}loras");
+    // Adapter names can contain slashes too
+    let lora_path = format!("{loras_path}/{{*lora_name}}");
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, &path),
+        RouteDoc::new(axum::http::Method::DELETE, &model_path),
+        RouteDoc::new(axum::http::Method::POST, &drain_path),
+        RouteDoc::new(axum::http::Method::GET, &standby_path),
+        RouteDoc::new(axum::http::Method::POST, &activate_path),
+        RouteDoc::new(axum::http::Method::GET, &tasks_path),
+        RouteDoc::new(axum::http::Method::GET, &topology_path),
+        RouteDoc::new(axum::http::Method::GET, &build_info_path),
+        RouteDoc::new(axum::http::Method::GET, &blacklist_path),
+        RouteDoc::new(axum::http::Method::POST, &blacklist_path),
+        RouteDoc::new(axum::http::Method::DELETE, &blacklisted_path),
+        RouteDoc::new(axum::http::Method::GET, &card_path),
+        RouteDoc::new(axum::http::Method::POST, &card_path),
+        RouteDoc::new(axum::http::Method::DELETE, &card_path),
+        RouteDoc::new(axum::http::Method::GET, &loras_path),
+        RouteDoc::new(axum::http::Method::POST, &loras_path),
+        RouteDoc::new(axum::http::Method::DELETE, &lora_path),
+    ];
+    let keys = config.keys.clone();
+    let router = Router::new()
+        .route(&path, post(load_model))
+        .route(&model_path, delete(unload_model))
+        .route(&drain_path, post(drain_worker))
+        .route(&standby_path, get(list_standby))
+        .route(&activate_path, post(activate_worker))
+        .route(&tasks_path, get(list_tasks))
+        .route(&topology_path, get(topology))
+        .route(&build_info_path, get(build_info))
+        .route(&blacklist_path, get(list_blacklist).post(blacklist_worker))
+        .route(&blacklisted_path, delete(unblacklist_worker))
+        .route(
+            &card_path,
+            get(card_revisions).post(rollback_card).delete(unpin_card),
+        )
+        .route(&loras_path, get(list_loras).post(load_lora))
+        .route(&lora_path, delete(unload_lora))
+        .with_state(config)
+        .route_layer(middleware::from_fn_with_state(keys, admin_auth_middleware));
+    (docs, router)
+}
@@ -0,0 +1,510 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Admission control with a bounded queue.
+//!
+//! Without it every request goes straight to the workers and waits there, so under load latency
+//! grows without bound. With it at most `max_concurrent` requests are in progress at once,
+//! globally and for each configured model (the `model` field of the request body, or the model
+//! the routing policy sends it to if that has no queue of its own). Further
+//! requests wait for a slot in order of arrival, in a queue of at most `max_queue` requests. A
+//! request that finds the queue full, or waits longer than `max_queue_wait_ms`, is rejected with a
+//! `503 Service Unavailable`, an OpenAI style error body and a `Retry-After` header, so that
+//! clients back off or try another frontend.
+//!
+//! Where the concurrency limits of [`super::rate_limit`] protect the service from clients and
+//! reject at once, this absorbs bursts up to the queue length. A slot is held until the response,
+//! including a streamed one, ends.
+//!
+//! Queue depths are exported as Prometheus metrics, and by [`serve_stats`] as the stats of a
+//! component endpoint, so they show up in `scrape_stats`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use dynamo_runtime::{
+    component::Component,
+    pipeline::{
+        network::Ingress, AsyncEngine, AsyncEngineContextProvider, ManyOut, ResponseStream,
+        SingleIn,
+    },
+    protocols::annotated::Annotated,
+    Error, Result,
+};
+use prometheus::{IntCounterVec, IntGaugeVec, Opts};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::admin::ADMIN_PATH_PREFIX;
+use super::auth::OPEN_PATHS;
+use super::error::openai_error_response;
+use super::rate_limit::{hold_until_done, limited_model, read_model};
+use super::service_v2;
+
+/// Endpoint on which [`serve_stats`] answers with the [`AdmissionStats`]
+pub const ADMISSION_STATS_ENDPOINT: &str = "admission";
+
+/// Value of the `scope` metric label for the global queue, the per model ones use the model name
+const GLOBAL_SCOPE: &str = "global";
+
+/// What we tell rejected clients to wait, we can't know when the queue drains
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+static QUEUED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "dynamo_http_admission_queued_requests",
+            "Requests waiting in the HTTP service for a slot, by scope (global or model)",
+        ),
+        &["scope"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static RUNNING: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "dynamo_http_admission_running_requests",
+            "Requests holding a slot in the HTTP service, by scope (global or model)",
+        ),
+        &["scope"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static REJECTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_http_admission_rejected_total",
+            "Requests rejected by admission control, by scope and reason (queue_full or queue_timeout)",
+        ),
+        &["scope", "reason"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the admission control metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(QUEUED.clone()))?;
+    registry.register(Box::new(RUNNING.clone()))?;
+    registry.register(Box::new(REJECTED.clone()))
+}
+
+/// Admission limits of one scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdmissionLimit {
+    /// Requests in progress at once
+    pub max_concurrent: usize,
+
+    /// Requests waiting for a slot. With 0 requests are rejected as soon as all slots are busy.
+    #[serde(default)]
+    pub max_queue: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdmissionConfig {
+    /// Shared by all requests. None means unlimited.
+    pub global: Option<AdmissionLimit>,
+
+    /// Applies to each model separately, by model name. Models not listed are only limited
+    /// globally.
+    pub per_model: HashMap<String, AdmissionLimit>,
+
+    /// Reject requests that waited this long for a slot. None waits until a slot frees up or the
+    /// client goes away.
+    pub max_queue_wait_ms: Option<u64>,
+}
+
+impl AdmissionConfig {
+    /// Read the configuration from a JSON file, e.g.
+    /// `{"global": {"max_concurrent": 256, "max_queue": 1024}, "per_model": {"llama": {"max_concurrent": 32, "max_queue": 64}}, "max_queue_wait_ms": 30000}`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed reading admission control from {}: {err}",
+                path.display()
+            )
+        })?;
+        serde_json::from_str(&contents).map_err(|err| {
+            anyhow::anyhow!("Invalid admission control in {}: {err}", path.display())
+        })
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.global.is_none() && self.per_model.is_empty()
+    }
+}
+
+/// The state of one queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub running: usize,
+    pub queued: usize,
+    pub max_concurrent: usize,
+    pub max_queue: usize,
+}
+
+/// What [`serve_stats`] reports
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdmissionStats {
+    pub global: Option<QueueStats>,
+    pub per_model: HashMap<String, QueueStats>,
+}
+
+/// Why a request was not admitted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    QueueFull { scope: String, max_queue: usize },
+    QueueTimeout { scope: String, waited: Duration },
+}
+
+impl Rejection {
+    fn reason(&self) -> &'static str {
+        match self {
+            Rejection::QueueFull { .. } => "queue_full",
+            Rejection::QueueTimeout { .. } => "queue_timeout",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Rejection::QueueFull { scope, max_queue } => format!(
+                "Service overloaded: the queue for {} is full ({max_queue} requests), try again later.",
+                describe(scope)
+            ),
+            Rejection::QueueTimeout { scope, waited } => format!(
+                "Service overloaded: waited {}s in the queue for {}, try again later.",
+                waited.as_secs_f32(),
+                describe(scope)
+            ),
+        }
+    }
+}
+
+fn describe(scope: &str) -> String {
+    if scope == GLOBAL_SCOPE {
+        "this service".to_string()
+    } else {
+        format!("model {scope}")
+    }
+}
+
+struct Queue {
+    /// Metric label
+    scope: String,
+    limit: AdmissionLimit,
+    slots: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// A slot held by an admitted request, released on drop
+struct Slot {
+    scope: String,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        RUNNING.with_label_values(&[&self.scope]).dec();
+    }
+}
+
+/// Counts a request as queued until dropped, also when the client goes away while waiting
+struct Waiting<'a>(&'a Queue);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::SeqCst);
+        QUEUED.with_label_values(&[&self.0.scope]).dec();
+    }
+}
+
+impl Queue {
+    fn new(scope: &str, limit: AdmissionLimit) -> Self {
+        Queue {
+            scope: scope.to_string(),
+            limit,
+            slots: Arc::new(Semaphore::new(limit.max_concurrent)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take a slot, waiting in the queue if there is room
+    async fn enter(&self, max_wait: Option<Duration>) -> Result<Slot, Rejection> {
+        let permit = match self.slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let max_queue = self.limit.max_queue;
+                if self
+                    .queued
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                        (queued < max_queue).then_some(queued + 1)
+                    })
+                    .is_err()
+                {
+                    return Err(self.reject(Rejection::QueueFull {
+                        scope: self.scope.clone(),
+                        max_queue,
+                    }));
+                }
+                QUEUED.with_label_values(&[&self.scope]).inc();
+                let _waiting = Waiting(self);
+
+                let acquire = self.slots.clone().acquire_owned();
+                let acquired = match max_wait {
+                    Some(max_wait) => {
+                        tokio::time::timeout(max_wait, acquire).await.map_err(|_| {
+                            self.reject(Rejection::QueueTimeout {
+                                scope: self.scope.clone(),
+                                waited: max_wait,
+                            })
+                        })?
+                    }
+                    None => acquire.await,
+                };
+                acquired.expect("admission semaphores are never closed")
+            }
+        };
+        RUNNING.with_label_values(&[&self.scope]).inc();
+        Ok(Slot {
+            scope: self.scope.clone(),
+            _permit: permit,
+        })
+    }
+
+    fn reject(&self, rejection: Rejection) -> Rejection {
+        REJECTED
+            .with_label_values(&[&self.scope, rejection.reason()])
+            .inc();
+        rejection
+    }
+
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            running: self
+                .limit
+                .max_concurrent
+                .saturating_sub(self.slots.available_permits()),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_concurrent: self.limit.max_concurrent,
+            max_queue: self.limit.max_queue,
+        }
+    }
+}
+
+/// The slots of an admitted request, released on drop
+pub struct Admitted {
+    _slots: Vec<Slot>,
+}
+
+/// Admits requests according to an [`AdmissionConfig`]
+pub struct AdmissionController {
+    global: Option<Queue>,
+    per_model: HashMap<String, Queue>,
+    max_queue_wait: Option<Duration>,
+}
+
+impl AdmissionController {
+    pub fn new(config: AdmissionConfig) -> Arc<Self> {
+        Arc::new(AdmissionController {
+            global: config.global.map(|limit| Queue::new(GLOBAL_SCOPE, limit)),
+            per_model: config
+                .per_model
+                .into_iter()
+                .map(|(model, limit)| {
+                    let queue = Queue::new(&model, limit);
+                    (model, queue)
+                })
+                .collect(),
+            max_queue_wait: config.max_queue_wait_ms.map(Duration::from_millis),
+        })
+    }
+
+    /// Wait for a slot in the queue of `model`, then in the global one. A request waiting for its
+    /// model doesn't hold a global slot, so one busy model can't starve the others.
+    pub async fn admit(&self, model: Option<&str>) -> Result<Admitted, Rejection> {
+        let mut slots = Vec::with_capacity(2);
+        if let Some(queue) = model.and_then(|model| self.per_model.get(model)) {
+            slots.push(queue.enter(self.max_queue_wait).await?);
+        }
+        if let Some(queue) = &self.global {
+            slots.push(queue.enter(self.max_queue_wait).await?);
+        }
+        Ok(Admitted { _slots: slots })
+    }
+
+    pub fn stats(&self) -> AdmissionStats {
+        AdmissionStats {
+            global: self.global.as_ref().map(Queue::stats),
+            per_model: self
+                .per_model
+                .iter()
+                .map(|(model, queue)| (model.clone(), queue.stats()))
+                .collect(),
+        }
+    }
+}
+
+fn service_unavailable(rejection: &Rejection) -> Response {
+    let mut response = openai_error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        &rejection.message(),
+        "server_error",
+        "overloaded",
+    );
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(RETRY_AFTER.as_secs()),
+    );
+    response
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state((Arc<AdmissionController>, Arc<service_v2::State>), admission_middleware)`.
+///
+/// Applies to POST requests, except for the admin API.
+pub async fn admission_middleware(
+    State((controller, state)): State<(Arc<AdmissionController>, Arc<service_v2::State>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if request.method() != Method::POST
+        || OPEN_PATHS.contains(&path)
+        || path.starts_with(ADMIN_PATH_PREFIX)
+    {
+        return next.run(request).await;
+    }
+
+    // Only read the body if there are per model queues
+    let (request, model) = if controller.per_model.is_empty() {
+        (request, None)
+    } else {
+        match read_model(request).await {
+            Ok((request, model)) => {
+                let model = model.map(|model| {
+                    limited_model(state.manager(), model, |name| {
+                        controller.per_model.contains_key(name)
+                    })
+                });
+                (request, model)
+            }
+            Err(response) => return response,
+        }
+    };
+
+    let admitted = match controller.admit(model.as_deref()).await {
+        Ok(admitted) => admitted,
+        Err(rejection) => {
+            tracing::debug!(
+                message = rejection.message(),
+                "Request rejected by admission control"
+            );
+            return service_unavailable(&rejection);
+        }
+    };
+
+    // Keep the slots until the body, which may be an SSE stream, is done
+    hold_until_done(next.run(request).await, admitted)
+}
+
+/// Serve the [`AdmissionStats`] of `controller` on the [`ADMISSION_STATS_ENDPOINT`] of
+/// `component`, both as the endpoint's stats and as its response. Runs until the component's
+/// runtime shuts down.
+pub async fn serve_stats(component: Component, controller: Arc<AdmissionController>) -> Result<()> {
+    let stats = controller.clone();
+    component
+        .service_builder()
+        .create()
+        .await?
+        .endpoint(ADMISSION_STATS_ENDPOINT)
+        .endpoint_builder()
+        .stats_handler(move |_| serde_json::to_value(stats.stats()).unwrap())
+        .handler(Ingress::for_engine(Arc::new(StatsHandler { controller }))?)
+        .start()
+        .await
+}
+
+struct StatsHandler {
+    controller: Arc<AdmissionController>,
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<()>, ManyOut<Annotated<AdmissionStats>>, Error> for StatsHandler {
+    async fn generate(&self, request: SingleIn<()>) -> Result<ManyOut<Annotated<AdmissionStats>>> {
+        let stream = futures::stream::iter(vec![Annotated::from_data(self.controller.stats())]);
+        Ok(ResponseStream::new(Box::pin(stream), request.context()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(max_concurrent: usize, max_queue: usize) -> AdmissionLimit {
+        AdmissionLimit {
+            max_concurrent,
+            max_queue,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bounded_queue() {
+        let controller = AdmissionController::new(AdmissionConfig {
+            global: Some(limit(1, 1)),
+            ..Default::default()
+        });
+        let running = controller.admit(None).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.admit(None).await.map(|_| ()) }
+        });
+        while controller.stats().global.unwrap().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            controller.stats().global,
+            Some(QueueStats {
+                running: 1,
+                queued: 1,
+                max_concurrent: 1,
+                max_queue: 1,
+            })
+        );
+
+        let rejection = controller.admit(None).await.err().unwrap();
+        assert_eq!(rejection.reason(), "queue_full");
+
+        drop(running);
+        waiting.await.unwrap().unwrap();
+        assert_eq!(controller.stats().global.unwrap().queued, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_model_and_timeout() {
+        let controller = AdmissionController::new(AdmissionConfig {
+            per_model: HashMap::from([("llama".to_string(), limit(1, 4))]),
+            max_queue_wait_ms: Some(100),
+            ..Default::default()
+        });
+        let _running = controller.admit(Some("llama")).await.unwrap();
+        // Other models aren't limited
+        assert!(controller.admit(Some("other")).await.is_ok());
+        assert!(controller.admit(None).await.is_ok());
+
+        let rejection = controller.admit(Some("llama")).await.err().unwrap();
+        assert_eq!(rejection.reason(), "queue_timeout");
+        assert_eq!(controller.stats().per_model["llama"].queued, 0);
+    }
+}
@@ -0,0 +1,300 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Optional API key authentication.
+//!
+//! When enabled every request must carry `Authorization: Bearer <key>` with a key from the
+//! [`AuthKeys`] store, otherwise it is rejected with a `401` and an OpenAI style error body.
+//! Health and metrics endpoints stay open so probes and scrapers don't need a key.
+//! The admin API has its own keys, see [`super::admin`].
+//!
+//! Keys can come from:
+//! - the builder, [`AuthKeys::from_keys`],
+//! - a file with one key per line, [`AuthKeys::load_file`], re-read by [`AuthKeys::watch_file`],
+//! - etcd, one key per value under a prefix, [`AuthKeys::watch_etcd`]. Keys are added and revoked
+//!   as the values are put and deleted.
+//!
+//! Only a hash of each key is kept in memory.
+//!
+//! An authenticated request carries its [`Principal`] in its extensions. The KV router shares busy
+//! workers fairly between principals.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use dynamo_runtime::transports::etcd::{self, WatchEvent};
+use tokio_util::sync::CancellationToken;
+
+use super::admin::ADMIN_PATH_PREFIX;
+use super::error::openai_error_response;
+use super::playground::PLAYGROUND_PATH;
+
+/// Requests to these paths don't need a key
+pub(crate) const OPEN_PATHS: &[&str] = &["/health", "/metrics", PLAYGROUND_PATH];
+
+/// How often [`AuthKeys::watch_file`] checks whether the file changed
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Who sent a request: `key-` and the start of the hash of its API key, so that it can be logged
+/// and used as a metric label without revealing the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+impl Principal {
+    pub fn from_key(key: &str) -> Self {
+        let hash = blake3::hash(key.as_bytes()).to_hex();
+        Principal(format!("key-{}", &hash[..12]))
+    }
+}
+
+/// The accepted API keys. Cheap to share, keys can be changed while the service runs.
+#[derive(Default)]
+pub struct AuthKeys {
+    /// Key source ("static", "file", "etcd:<key>") and index, to the hash of the key
+    keys: RwLock<HashMap<String, blake3::Hash>>,
+}
+
+impl std::fmt::Debug for AuthKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthKeys")
+            .field("count", &self.len())
+            .finish()
+    }
+}
+
+impl AuthKeys {
+    /// An empty store. Until keys are added every request is rejected.
+    pub fn new() -> Arc<Self> {
+        Arc::new(AuthKeys::default())
+    }
+
+    pub fn from_keys<I, S>(keys: I) -> Arc<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let this = AuthKeys::default();
+        this.replace("static", keys);
+        Arc::new(this)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Is `key` one of the accepted keys
+    pub fn is_valid(&self, key: &str) -> bool {
+        let hash = blake3::hash(key.as_bytes());
+        // blake3::Hash equality is constant time
+        self.keys.read().unwrap().values().any(|h| *h == hash)
+    }
+
+    /// Replace the keys previously loaded from `source`
+    fn replace<I, S>(&self, source: &str, keys: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let prefix = format!("{source}:");
+        let mut all = self.keys.write().unwrap();
+        all.retain(|id, _| !id.starts_with(&prefix));
+        for (i, key) in keys.into_iter().enumerate() {
+            all.insert(
+                format!("{prefix}{i}"),
+                blake3::hash(key.as_ref().as_bytes()),
+            );
+        }
+    }
+
+    /// Replace the keys from the file with the contents of `path`. One key per line, blank lines
+    /// and lines starting with `#` are ignored.
+    pub fn load_file(&self, path: &Path) -> anyhow::Result<usize> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("Failed reading API keys from {}: {err}", path.display())
+        })?;
+        let keys = parse_keys_file(&contents);
+        let count = keys.len();
+        self.replace("file", keys);
+        Ok(count)
+    }
+
+    /// Load `path` now, then reload it whenever it changes, until `cancel_token` is cancelled.
+    /// Lets keys be rotated without restarting the service.
+    pub fn watch_file(
+        self: &Arc<Self>,
+        path: PathBuf,
+        cancel_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.load_file(&path)?;
+        let mut last_modified = modified(&path);
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = tokio::time::sleep(FILE_POLL_INTERVAL) => {}
+                }
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                match this.load_file(&path) {
+                    Ok(count) => tracing::info!(count, "Reloaded API keys from {}", path.display()),
+                    // Keep the previous keys
+                    Err(err) => tracing::error!(%err, "Failed reloading API keys"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Accept the keys stored as values under `prefix` in etcd, following puts and deletes.
+    pub async fn watch_etcd(
+        self: &Arc<Self>,
+        etcd_client: &etcd::Client,
+        prefix: &str,
+    ) -> anyhow::Result<()> {
+        let watcher = etcd_client.kv_get_and_watch_prefix(prefix).await?;
+        let (_prefix, _watcher, mut receiver) = watcher.dissolve();
+        let this = self.clone();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                match event {
+                    WatchEvent::Put(kv) => match (kv.key_str(), kv.value_str()) {
+                        (Ok(id), Ok(key)) => this.replace(&format!("etcd:{id}"), [key.trim()]),
+                        _ => tracing::error!("API key in etcd is not valid UTF-8"),
+                    },
+                    WatchEvent::Delete(kv) => {
+                        if let Ok(id) = kv.key_str() {
+                            this.replace(&format!("etcd:{id}"), [] as [&str; 0]);
+                        }
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+fn parse_keys_file(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut response = openai_error_response(
+        StatusCode::UNAUTHORIZED,
+        message,
+        "invalid_request_error",
+        "invalid_api_key",
+    );
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// The key from an `Authorization: Bearer <key>` header value
+pub(crate) fn bearer_token(value: &HeaderValue) -> Option<&str> {
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state(Arc<AuthKeys>, auth_middleware)`.
+pub async fn auth_middleware(
+    State(keys): State<Arc<AuthKeys>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    // The admin API checks its own keys
+    if OPEN_PATHS.contains(&path) || path.starts_with(ADMIN_PATH_PREFIX) {
+        return next.run(request).await;
+    }
+    match authorize(&keys, &request) {
+        Ok(principal) => {
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Err(response) => response,
+    }
+}
+
+/// Check the request's `Authorization` header against `keys`
+pub(crate) fn authorize(keys: &AuthKeys, request: &Request) -> Result<Principal, Response> {
+    let Some(value) = request.headers().get(header::AUTHORIZATION) else {
+        return Err(unauthorized(
+            "You didn't provide an API key. Provide it in the Authorization header as 'Bearer <key>'.",
+        ));
+    };
+    match bearer_token(value) {
+        Some(key) if keys.is_valid(key) => Ok(Principal::from_key(key)),
+        Some(_) => Err(unauthorized("Incorrect API key provided.")),
+        None => Err(unauthorized(
+            "Malformed Authorization header, expected 'Bearer <key>'.",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let keys = AuthKeys::from_keys(["sk-one", "sk-two"]);
+        assert!(keys.is_valid("sk-one"));
+        assert!(keys.is_valid("sk-two"));
+        assert!(!keys.is_valid("sk-three"));
+        assert!(!keys.is_valid(""));
+        assert_eq!(Principal::from_key("sk-one"), Principal::from_key("sk-one"));
+        assert_ne!(Principal::from_key("sk-one"), Principal::from_key("sk-two"));
+
+        keys.replace("etcd:/keys/a", ["sk-three"]);
+        assert!(keys.is_valid("sk-three"));
+        keys.replace("etcd:/keys/a", [] as [&str; 0]);
+        assert!(!keys.is_valid("sk-three"));
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn test_load_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys");
+        std::fs::write(&path, "# team a\nsk-a\n\n  sk-b  \n").unwrap();
+
+        let keys = AuthKeys::from_keys(["sk-static"]);
+        assert_eq!(keys.load_file(&path).unwrap(), 2);
+        assert!(keys.is_valid("sk-b"));
+
+        // Reloading replaces the file keys but keeps the others
+        std::fs::write(&path, "sk-c\n").unwrap();
+        keys.load_file(&path).unwrap();
+        assert!(!keys.is_valid("sk-a"));
+        assert!(keys.is_valid("sk-c"));
+        assert!(keys.is_valid("sk-static"));
+    }
+}
@@ -0,0 +1,392 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Several choices per request: OpenAI's `n`, and `best_of` for completions.
+//!
+//! Engines generate a single sequence per request, so a request for `n` choices is fanned out
+//! as `n` requests. Their streams are merged into one, each choice with its own `index`, sharing
+//! one response id, and with the usage of all choices added up. The prompt is counted once.
+//! With `stream_options.include_usage` each choice's stream ends with a usage chunk, those are
+//! held back and sent as one once all choices are done.
+//!
+//! `best_of` generates `best_of` candidates and returns the `n` with the highest average token
+//! log probability. Engines that don't report log probabilities can't be ranked, the first `n`
+//! candidates are returned.
+
+use std::sync::Arc;
+
+use dynamo_runtime::pipeline::{
+    async_trait, AsyncEngineContext, AsyncEngineContextProvider, Context, Error, ManyOut,
+    ResponseStream, ServerStreamingEngine,
+};
+use futures::{Stream, StreamExt};
+
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionStreamResponse;
+use crate::protocols::openai::completions::{CompletionResponse, LogprobResult};
+use crate::types::Annotated;
+
+/// Most choices or candidates one request can ask for
+pub const MAX_CHOICES: u8 = 20;
+
+/// Check `n` and `best_of` of a request, both defaulting to 1
+pub(crate) fn validate(n: u8, best_of: u8, streaming: bool) -> Result<(), String> {
+    if n == 0 || n > MAX_CHOICES {
+        return Err(format!("n must be between 1 and {MAX_CHOICES}, got {n}"));
+    }
+    if best_of < n || best_of > MAX_CHOICES {
+        return Err(format!(
+            "best_of must be between n ({n}) and {MAX_CHOICES}, got {best_of}"
+        ));
+    }
+    if best_of > n && streaming {
+        return Err("best_of greater than n can't be streamed".to_string());
+    }
+    Ok(())
+}
+
+/// A streamed response that can be one of several choices
+pub(crate) trait MultiChoice: Send + Sync + 'static {
+    fn id(&self) -> &str;
+    fn set_id(&mut self, id: String);
+
+    /// Engines always answer with choice 0, move it to `index`
+    fn set_choice_index(&mut self, index: u32);
+
+    /// Prompt and completion tokens, if this response carries usage
+    fn usage(&self) -> Option<(u32, u32)>;
+
+    /// Whether this is the usage chunk ending a stream, for `stream_options.include_usage`
+    fn is_usage_chunk(&self) -> bool;
+    fn set_usage(&mut self, prompt_tokens: u32, completion_tokens: u32);
+}
+
+impl MultiChoice for CompletionResponse {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+
+    fn set_choice_index(&mut self, index: u32) {
+        for choice in &mut self.choices {
+            choice.index = index as u64;
+        }
+    }
+
+    fn usage(&self) -> Option<(u32, u32)> {
+        self.usage
+            .as_ref()
+            .map(|u| (u.prompt_tokens as u32, u.completion_tokens as u32))
+    }
+
+    fn is_usage_chunk(&self) -> bool {
+        self.choices.is_empty() && self.usage.is_some()
+    }
+
+    fn set_usage(&mut self, prompt_tokens: u32, completion_tokens: u32) {
+        if let Some(usage) = self.usage.as_mut() {
+            usage.prompt_tokens = prompt_tokens as i32;
+            usage.completion_tokens = completion_tokens as i32;
+            usage.total_tokens = (prompt_tokens + completion_tokens) as i32;
+        }
+    }
+}
+
+impl MultiChoice for NvCreateChatCompletionStreamResponse {
+    fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.inner.id = id;
+    }
+
+    fn set_choice_index(&mut self, index: u32) {
+        for choice in &mut self.inner.choices {
+            choice.index = index;
+        }
+    }
+
+    fn usage(&self) -> Option<(u32, u32)> {
+        self.inner
+            .usage
+            .as_ref()
+            .map(|u| (u.prompt_tokens, u.completion_tokens))
+    }
+
+    fn is_usage_chunk(&self) -> bool {
+        self.inner.choices.is_empty() && self.inner.usage.is_some()
+    }
+
+    fn set_usage(&mut self, prompt_tokens: u32, completion_tokens: u32) {
+        if let Some(usage) = self.inner.usage.as_mut() {
+            usage.prompt_tokens = prompt_tokens;
+            usage.completion_tokens = completion_tokens;
+            usage.total_tokens = prompt_tokens + completion_tokens;
+        }
+    }
+}
+
+/// Usage of all choices so far. Engines report running totals per choice.
+#[derive(Default)]
+struct UsageTotals {
+    prompt_tokens: u32,
+    completion_tokens: Vec<u32>,
+}
+
+impl UsageTotals {
+    fn update(&mut self, choice: usize, (prompt_tokens, completion_tokens): (u32, u32)) {
+        self.prompt_tokens = self.prompt_tokens.max(prompt_tokens);
+        if self.completion_tokens.len() <= choice {
+            self.completion_tokens.resize(choice + 1, 0);
+        }
+        self.completion_tokens[choice] = completion_tokens;
+    }
+
+    fn completion_tokens(&self) -> u32 {
+        self.completion_tokens.iter().sum()
+    }
+}
+
+/// Send each request to `engine` and merge the response streams, the response to request `i`
+/// being choice `i`.
+pub(crate) async fn fan_out<Req, Resp>(
+    engine: &ServerStreamingEngine<Req, Annotated<Resp>>,
+    request_id: &str,
+    requests: Vec<Req>,
+) -> Result<ManyOut<Annotated<Resp>>, Error>
+where
+    Req: Send + Sync + 'static,
+    Resp: MultiChoice,
+{
+    let streams =
+        futures::future::try_join_all(requests.into_iter().enumerate().map(|(i, request)| {
+            engine.generate(Context::with_id(request, format!("{request_id}-{i}")))
+        }))
+        .await?;
+
+    let context = Arc::new(FanOutContext {
+        id: request_id.to_string(),
+        contexts: streams.iter().map(|s| s.context()).collect(),
+    });
+
+    let merged = merge(streams);
+    let stream: ManyOut<Annotated<Resp>> = ResponseStream::new(Box::pin(merged), context);
+    Ok(stream)
+}
+
+/// Merge the response streams of the choices, the response in `streams[i]` being choice `i`
+fn merge<Resp, S>(streams: Vec<S>) -> impl Stream<Item = Annotated<Resp>> + Send
+where
+    Resp: MultiChoice,
+    S: Stream<Item = Annotated<Resp>> + Send + Unpin + 'static,
+{
+    let mut merged = futures::stream::select_all(
+        streams
+            .into_iter()
+            .enumerate()
+            .map(|(i, stream)| stream.map(move |response| (i, response))),
+    );
+    async_stream::stream! {
+        let mut id = None;
+        let mut totals = UsageTotals::default();
+        // The last of the choices' usage chunks, sent with the totals once they all ended
+        let mut usage_chunk = None;
+        while let Some((i, mut response)) = merged.next().await {
+            if let Some(data) = response.data.as_mut() {
+                data.set_choice_index(i as u32);
+                let id = id.get_or_insert_with(|| data.id().to_string());
+                data.set_id(id.clone());
+                if let Some(usage) = data.usage() {
+                    totals.update(i, usage);
+                    data.set_usage(totals.prompt_tokens, totals.completion_tokens());
+                }
+                if data.is_usage_chunk() {
+                    usage_chunk = Some(response);
+                    continue;
+                }
+            }
+            yield response;
+        }
+        if let Some(mut response) = usage_chunk {
+            if let Some(data) = response.data.as_mut() {
+                data.set_usage(totals.prompt_tokens, totals.completion_tokens());
+            }
+            response.output_tokens = Some(totals.completion_tokens() as usize);
+            yield response;
+        }
+    }
+}
+
+/// The `n` best of `candidates`, by average token log probability, re-indexed from 0.
+/// `usage` stays the usage of all candidates, they were all generated.
+pub(crate) fn best_of(mut candidates: CompletionResponse, n: usize) -> CompletionResponse {
+    let score = |logprobs: &Option<LogprobResult>| {
+        logprobs
+            .as_ref()
+            .filter(|l| !l.token_logprobs.is_empty())
+            .map(|l| l.token_logprobs.iter().sum::<f32>() / l.token_logprobs.len() as f32)
+            .unwrap_or(f32::NEG_INFINITY)
+    };
+    // Stable, so without log probabilities the order is unchanged
+    candidates
+        .choices
+        .sort_by(|a, b| score(&b.logprobs).total_cmp(&score(&a.logprobs)));
+    candidates.choices.truncate(n);
+    for (index, choice) in candidates.choices.iter_mut().enumerate() {
+        choice.index = index as u64;
+    }
+    candidates
+}
+
+/// Controls all the streams of a fanned out request together
+#[derive(Debug)]
+struct FanOutContext {
+    id: String,
+    contexts: Vec<Arc<dyn AsyncEngineContext>>,
+}
+
+#[async_trait]
+impl AsyncEngineContext for FanOutContext {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.contexts.iter().all(|c| c.is_stopped())
+    }
+
+    fn is_killed(&self) -> bool {
+        self.contexts.iter().all(|c| c.is_killed())
+    }
+
+    async fn stopped(&self) {
+        futures::future::join_all(self.contexts.iter().map(|c| c.stopped())).await;
+    }
+
+    async fn killed(&self) {
+        futures::future::join_all(self.contexts.iter().map(|c| c.killed())).await;
+    }
+
+    fn stop_generating(&self) {
+        self.contexts.iter().for_each(|c| c.stop_generating());
+    }
+
+    fn stop(&self) {
+        self.contexts.iter().for_each(|c| c.stop());
+    }
+
+    fn kill(&self) {
+        self.contexts.iter().for_each(|c| c.kill());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::openai::completions::CompletionChoice;
+
+    fn choice(index: u64, text: &str, token_logprobs: Option<Vec<f32>>) -> CompletionChoice {
+        CompletionChoice {
+            text: text.to_string(),
+            index,
+            finish_reason: Some("stop".to_string()),
+            logprobs: token_logprobs.map(|token_logprobs| LogprobResult {
+                tokens: vec![],
+                token_logprobs,
+                top_logprobs: vec![],
+                text_offset: vec![],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_best_of() {
+        let candidates = CompletionResponse {
+            id: "cmpl-1".to_string(),
+            choices: vec![
+                choice(0, "meh", Some(vec![-1.0, -2.0])),
+                choice(1, "unranked", None),
+                choice(2, "best", Some(vec![-0.1, -0.3])),
+            ],
+            created: 0,
+            model: "test".to_string(),
+            object: "text_completion".to_string(),
+            usage: None,
+            system_fingerprint: None,
+            nvext: None,
+        };
+        let best = best_of(candidates, 2);
+        let texts: Vec<_> = best.choices.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["best", "meh"]);
+        let indexes: Vec<_> = best.choices.iter().map(|c| c.index).collect();
+        assert_eq!(indexes, [0, 1]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(1, 1, true).is_ok());
+        assert!(validate(2, 5, false).is_ok());
+        assert!(validate(0, 1, false).is_err());
+        assert!(validate(3, 2, false).is_err());
+        assert!(validate(2, 5, true).is_err());
+        assert!(validate(MAX_CHOICES + 1, MAX_CHOICES + 1, false).is_err());
+    }
+
+    fn response(choices: Vec<CompletionChoice>, completion_tokens: i32) -> CompletionResponse {
+        CompletionResponse {
+            id: "cmpl-1".to_string(),
+            choices,
+            created: 0,
+            model: "test".to_string(),
+            object: "text_completion".to_string(),
+            usage: Some(crate::protocols::openai::CompletionUsage {
+                prompt_tokens: 10,
+                completion_tokens,
+                total_tokens: 10 + completion_tokens,
+                ..Default::default()
+            }),
+            system_fingerprint: None,
+            nvext: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_usage_chunks() {
+        // As with `stream_options.include_usage`: no usage in the chunks, one at the end
+        let choice_stream = |text: &str, completion_tokens| {
+            let mut chunk = response(vec![choice(0, text, None)], 0);
+            chunk.usage = None;
+            futures::stream::iter([
+                Annotated::from_data(chunk),
+                Annotated::from_data(response(vec![], completion_tokens)),
+            ])
+        };
+        let merged: Vec<_> = merge(vec![choice_stream("a", 3), choice_stream("b", 4)])
+            .collect()
+            .await;
+        assert_eq!(merged.len(), 3);
+        let usage_chunks: Vec<_> = merged
+            .iter()
+            .filter_map(|r| r.data.as_ref())
+            .filter(|r| r.is_usage_chunk())
+            .collect();
+        assert_eq!(usage_chunks.len(), 1);
+        assert_eq!(usage_chunks[0].usage(), Some((10, 7)));
+        let last = merged.last().and_then(|r| r.data.as_ref()).unwrap();
+        assert!(last.is_usage_chunk());
+    }
+
+    #[test]
+    fn test_usage_totals() {
+        let mut totals = UsageTotals::default();
+        totals.update(1, (10, 3));
+        totals.update(0, (10, 5));
+        // Running totals replace the previous ones
+        totals.update(1, (10, 4));
+        assert_eq!(totals.prompt_tokens, 10);
+        assert_eq!(totals.completion_tokens(), 9);
+    }
+}
@@ -0,0 +1,257 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cross-Origin Resource Sharing (CORS) support for the HTTP service.
+//!
+//! Browser based clients (playgrounds, notebooks) can only call the OpenAI endpoints directly if
+//! the responses, including the SSE streams, carry the right `Access-Control-*` headers. This
+//! module provides a small middleware that answers preflight `OPTIONS` requests and decorates
+//! every other response, configured with a [`CorsConfig`].
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use derive_builder::Builder;
+
+/// Which origins are allowed to make cross-origin requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    /// `*`. Not compatible with `allow_credentials`.
+    Any,
+
+    /// Exact match on the `Origin` header, e.g. `https://playground.example.com`
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    /// From a list of origins, as given on the command line. A single `*` means any origin.
+    pub fn from_origins<S: AsRef<str>>(origins: &[S]) -> Self {
+        if origins.iter().any(|o| o.as_ref() == "*") {
+            AllowedOrigins::Any
+        } else {
+            AllowedOrigins::List(origins.iter().map(|o| o.as_ref().to_string()).collect())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Builder)]
+#[builder(pattern = "owned")]
+pub struct CorsConfig {
+    #[builder(default = "AllowedOrigins::Any")]
+    allowed_origins: AllowedOrigins,
+
+    #[builder(default = "vec![Method::GET, Method::POST, Method::OPTIONS]")]
+    allowed_methods: Vec<Method>,
+
+    /// Request headers the browser may send. Empty means reflect whatever the preflight asked for.
+    #[builder(default)]
+    allowed_headers: Vec<String>,
+
+    /// Response headers the browser may read
+    #[builder(default)]
+    exposed_headers: Vec<String>,
+
+    #[builder(default = "false")]
+    allow_credentials: bool,
+
+    /// How long the browser may cache the preflight response
+    #[builder(default = "Some(Duration::from_secs(600))")]
+    max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig::builder().build().unwrap()
+    }
+}
+
+impl CorsConfig {
+    pub fn builder() -> CorsConfigBuilder {
+        CorsConfigBuilder::default()
+    }
+
+    /// Build a config from a list of origins, as given on the command line.
+    /// A single `*` means any origin.
+    pub fn from_origins<S: AsRef<str>>(origins: &[S]) -> Self {
+        CorsConfig {
+            allowed_origins: AllowedOrigins::from_origins(origins),
+            ..Default::default()
+        }
+    }
+
+    /// HTTP methods by name, e.g. `GET` or `post`
+    pub fn parse_methods<S: AsRef<str>>(methods: &[S]) -> anyhow::Result<Vec<Method>> {
+        methods
+            .iter()
+            .map(|method| {
+                let method = method.as_ref();
+                method
+                    .to_uppercase()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid HTTP method '{method}'"))
+            })
+            .collect()
+    }
+
+    /// The value to use for `Access-Control-Allow-Origin`, or None if this origin is not allowed.
+    fn allow_origin(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        match &self.allowed_origins {
+            // The spec forbids `*` with credentials, so reflect the origin instead
+            AllowedOrigins::Any if self.allow_credentials => origin.cloned(),
+            AllowedOrigins::Any => Some(HeaderValue::from_static("*")),
+            AllowedOrigins::List(allowed) => {
+                let origin = origin?;
+                let origin_str = origin.to_str().ok()?;
+                allowed
+                    .iter()
+                    .any(|a| a == origin_str)
+                    .then(|| origin.clone())
+            }
+        }
+    }
+
+    fn apply_common(&self, allow_origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if self.allowed_origins != AllowedOrigins::Any || self.allow_credentials {
+            headers.append(header::VARY, HeaderValue::from_static("origin"));
+        }
+    }
+
+    fn apply_preflight(&self, request_headers: &HeaderMap, headers: &mut HeaderMap) {
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(v) = HeaderValue::from_str(&methods) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, v);
+        }
+
+        let allow_headers = if self.allowed_headers.is_empty() {
+            request_headers
+                .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned()
+        } else {
+            HeaderValue::from_str(&self.allowed_headers.join(", ")).ok()
+        };
+        if let Some(v) = allow_headers {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, v);
+        }
+
+        if let Some(max_age) = self.max_age {
+            headers.insert(
+                header::ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from(max_age.as_secs()),
+            );
+        }
+    }
+
+    fn apply_exposed(&self, headers: &mut HeaderMap) {
+        if self.exposed_headers.is_empty() {
+            return;
+        }
+        if let Ok(v) = HeaderValue::from_str(&self.exposed_headers.join(", ")) {
+            headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, v);
+        }
+    }
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state(Arc<CorsConfig>, cors_middleware)`.
+pub async fn cors_middleware(
+    State(config): State<Arc<CorsConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let origin = request.headers().get(header::ORIGIN).cloned();
+    let is_preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+    // Not a cross-origin request, nothing to do
+    if origin.is_none() && !is_preflight {
+        return next.run(request).await;
+    }
+    let allow_origin = config.allow_origin(origin.as_ref());
+
+    if is_preflight {
+        let Some(allow_origin) = allow_origin else {
+            tracing::debug!(?origin, "CORS preflight from disallowed origin");
+            return StatusCode::FORBIDDEN.into_response();
+        };
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        config.apply_common(allow_origin, response.headers_mut());
+        config.apply_preflight(request.headers(), response.headers_mut());
+        return response;
+    }
+
+    let mut response = next.run(request).await;
+    if let Some(allow_origin) = allow_origin {
+        config.apply_common(allow_origin, response.headers_mut());
+        config.apply_exposed(response.headers_mut());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_any_origin() {
+        let config = CorsConfig::default();
+        let origin = HeaderValue::from_static("http://localhost:3000");
+        assert_eq!(
+            config.allow_origin(Some(&origin)),
+            Some(HeaderValue::from_static("*"))
+        );
+    }
+
+    #[test]
+    fn test_any_origin_with_credentials_reflects() {
+        let config = CorsConfig::builder()
+            .allow_credentials(true)
+            .build()
+            .unwrap();
+        let origin = HeaderValue::from_static("http://localhost:3000");
+        assert_eq!(config.allow_origin(Some(&origin)), Some(origin));
+    }
+
+    #[test]
+    fn test_origin_list() {
+        let config = CorsConfig::from_origins(&["https://a.example.com"]);
+        let allowed = HeaderValue::from_static("https://a.example.com");
+        let denied = HeaderValue::from_static("https://b.example.com");
+        assert_eq!(config.allow_origin(Some(&allowed)), Some(allowed));
+        assert_eq!(config.allow_origin(Some(&denied)), None);
+        assert_eq!(config.allow_origin(None), None);
+    }
+
+    #[test]
+    fn test_wildcard_in_list() {
+        let config = CorsConfig::from_origins(&["https://a.example.com", "*"]);
+        assert_eq!(config.allowed_origins, AllowedOrigins::Any);
+    }
+
+    #[test]
+    fn test_parse_methods() {
+        assert_eq!(
+            CorsConfig::parse_methods(&["get", "DELETE"]).unwrap(),
+            vec![Method::GET, Method::DELETE]
+        );
+        assert!(CorsConfig::parse_methods(&["not a method"]).is_err());
+    }
+}
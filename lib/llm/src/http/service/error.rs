@@ -12,3 +12,24 @@ pub struct HttpError {
     pub code: u16,
     pub message: String,
 }
+
+/// An error response with the body OpenAI uses, for errors clients handle programmatically
+/// such as authentication and rate limits: `{"error": {"message", "type", "param", "code"}}`
+pub(crate) fn openai_error_response(
+    status: axum::http::StatusCode,
+    message: &str,
+    error_type: &'static str,
+    code: &'static str,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let body = serde_json::json!({
+        "error": {
+            "message": message,
+            "type": error_type,
+            "param": null,
+            "code": code,
+        }
+    });
+    (status, axum::Json(body)).into_response()
+}
@@ -0,0 +1,152 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! How streamed responses are framed on the wire.
+//!
+//! The streaming endpoints answer with server-sent events, unless the request's `Accept` header
+//! prefers `application/x-ndjson`: then each chunk is a line of JSON. The engines stream the same
+//! chunks either way, only the last step of the handler differs.
+//!
+//! NDJSON streams have no `[DONE]`, they end with the body. An error is a last line
+//! `{"error": "..."}`. There are no keep-alives.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::{stream::BoxStream, StreamExt};
+use serde::Serialize;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{error::HttpError, metrics::InflightGuard, openai::ErrorResponse};
+use dynamo_runtime::pipeline::AsyncEngineContext;
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+const SSE_CONTENT_TYPE: &str = "text/event-stream";
+
+/// The framing of a streamed response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamFraming {
+    /// Server-sent events
+    #[default]
+    Sse,
+
+    /// Newline delimited JSON
+    Ndjson,
+}
+
+impl StreamFraming {
+    /// NDJSON if `Accept` ranks it above server-sent events, else server-sent events. Only exact
+    /// media types count, wildcards like `*/*` accept either.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let mut ndjson = 0.0;
+        let mut sse = 0.0;
+        let accepted = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for media_range in accepted {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if media_type.eq_ignore_ascii_case(NDJSON_CONTENT_TYPE) {
+                ndjson = quality;
+            } else if media_type.eq_ignore_ascii_case(SSE_CONTENT_TYPE) {
+                sse = quality;
+            }
+        }
+        if ndjson > 0.0 && ndjson > sse {
+            StreamFraming::Ndjson
+        } else {
+            StreamFraming::Sse
+        }
+    }
+}
+
+/// One line of newline delimited JSON
+pub(super) fn json_line<T: Serialize>(value: &T) -> String {
+    // safety: Our own response types always serialize
+    serde_json::to_string(value).unwrap()
+}
+
+/// Stream the JSON `lines` as newline delimited JSON. An `Err` line is the engine's error, it
+/// ends the stream. Generation stops if the client disconnects.
+pub(super) fn ndjson(
+    lines: BoxStream<'static, Result<String, String>>,
+    context: Arc<dyn AsyncEngineContext>,
+    mut inflight_guard: InflightGuard,
+) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::convert::Infallible>>(8);
+    tokio::spawn(async move {
+        let mut lines = lines;
+        loop {
+            let line = tokio::select! {
+                line = lines.next() => line,
+                _ = tx.closed() => {
+                    tracing::trace!("Client disconnected while waiting for the next line");
+                    context.stop_generating();
+                    return;
+                }
+            };
+            let (line, ok) = match line {
+                Some(Ok(line)) => (line, true),
+                Some(Err(message)) => (
+                    json_line(&ErrorResponse::from(HttpError {
+                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                        message,
+                    })),
+                    false,
+                ),
+                None => break,
+            };
+            if tx.send(Ok(line + "\n")).await.is_err() {
+                context.stop_generating();
+                return;
+            }
+            if !ok {
+                return;
+            }
+        }
+        inflight_guard.mark_ok();
+    });
+    let body = Body::from_stream(ReceiverStream::new(rx));
+    ([(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &str) -> StreamFraming {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, accept.parse().unwrap());
+        StreamFraming::negotiate(&headers)
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(
+            StreamFraming::negotiate(&HeaderMap::new()),
+            StreamFraming::Sse
+        );
+        assert_eq!(negotiate("*/*"), StreamFraming::Sse);
+        assert_eq!(negotiate("application/x-ndjson"), StreamFraming::Ndjson);
+        assert_eq!(
+            negotiate("text/event-stream, application/x-ndjson"),
+            StreamFraming::Sse
+        );
+        assert_eq!(
+            negotiate("text/event-stream;q=0.5, Application/X-NDJSON"),
+            StreamFraming::Ndjson
+        );
+        assert_eq!(negotiate("application/x-ndjson;q=0"), StreamFraming::Sse);
+    }
+}
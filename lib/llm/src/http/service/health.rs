@@ -29,6 +29,7 @@
 // limitations under the License.
 
 use super::{service_v2, RouteDoc};
+use crate::tokenizers::registry::{self, TokenizerStatus};
 use axum::{http::Method, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
 use serde_json::json;
 use std::sync::Arc;
@@ -53,6 +54,19 @@ async fn health_handler(
 ) -> impl IntoResponse {
     let model_entries = state.manager().get_model_entries();
 
+    // Models are only usable once their engines are added and their tokenizer has loaded
+    let mut loading = state.manager().pending_models();
+    let mut failed = vec![];
+    for (model, status) in registry::statuses() {
+        match status {
+            TokenizerStatus::Ready => {}
+            TokenizerStatus::Loading => loading.push(model),
+            TokenizerStatus::Failed(err) => failed.push(format!("{model}: {err}")),
+        }
+    }
+    loading.sort_unstable();
+    loading.dedup();
+
     if model_entries.is_empty() {
         (
             StatusCode::SERVICE_UNAVAILABLE,
@@ -61,17 +75,27 @@ async fn health_handler(
                 "message": "No endpoints available"
             })),
         )
+    } else if !loading.is_empty() {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "status": "loading",
+                "message": "Models are still loading",
+                "loading": loading,
+            })),
+        )
     } else {
         let endpoints: Vec<String> = model_entries
             .iter()
             .map(|entry| entry.endpoint.as_url())
             .collect();
-        (
-            StatusCode::OK,
-            Json(json!({
-                "status": "healthy",
-                "endpoints": endpoints
-            })),
-        )
+        let mut body = json!({
+            "status": "healthy",
+            "endpoints": endpoints
+        });
+        if !failed.is_empty() {
+            body["failed"] = json!(failed);
+        }
+        (StatusCode::OK, Json(body))
     }
 }
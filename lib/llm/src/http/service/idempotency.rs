@@ -0,0 +1,433 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for the `Idempotency-Key` request header.
+//!
+//! Clients that retry a POST after a network error would otherwise start a second, expensive,
+//! generation. When a request carries an `Idempotency-Key` we remember it for a TTL:
+//! - While the first request is still running, a retry with the same key gets a `409 Conflict`.
+//! - Once it completed successfully, a retry gets the stored response replayed, with the
+//!   `Idempotent-Replayed: true` header. Streaming (SSE) responses are replayed in full.
+//! - Re-using a key with a different request body is a `422 Unprocessable Entity`.
+//!
+//! Failed requests, and streams the client disconnected from, are forgotten so they can be retried.
+//!
+//! Keys are per principal (API key), so that clients can't replay each other's responses. The
+//! store is bounded by the bytes it keeps: when full, the responses that expire first make room.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+
+use super::auth::Principal;
+use super::error::HttpError;
+use super::openai::ErrorResponse;
+
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Largest request body we will read to fingerprint it
+const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+
+/// Don't store responses larger than this, the retry will run again
+const MAX_STORED_RESPONSE_BYTES: usize = 8 * 1024 * 1024;
+
+/// Limit memory use if clients send many distinct keys or large responses
+const MAX_STORE_BYTES: usize = 256 * 1024 * 1024;
+
+/// What an entry takes besides its key and response, roughly
+const ENTRY_OVERHEAD_BYTES: usize = 256;
+
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Debug, Clone)]
+enum EntryState {
+    InFlight,
+    Done(StoredResponse),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    fingerprint: blake3::Hash,
+    expires_at: Instant,
+    state: EntryState,
+}
+
+impl Entry {
+    /// Memory used by the entry of `key`, roughly
+    fn size(&self, key: &str) -> usize {
+        let response = match &self.state {
+            EntryState::InFlight => 0,
+            EntryState::Done(stored) => {
+                stored.body.len()
+                    + stored
+                        .headers
+                        .iter()
+                        .map(|(name, value)| name.as_str().len() + value.len())
+                        .sum::<usize>()
+            }
+        };
+        key.len() + ENTRY_OVERHEAD_BYTES + response
+    }
+}
+
+/// The entries and the bytes they take
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    bytes: usize,
+}
+
+impl Entries {
+    fn insert(&mut self, key: &str, entry: Entry) {
+        self.bytes += entry.size(key);
+        if let Some(old) = self.map.insert(key.to_string(), entry) {
+            self.bytes -= old.size(key);
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.map.remove(key)?;
+        self.bytes -= entry.size(key);
+        Some(entry)
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .map
+            .iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+
+    /// Make room for `size` more bytes, removing the stored responses that expire first. False
+    /// if the requests in flight take up the room.
+    fn make_room(&mut self, size: usize) -> bool {
+        if self.bytes + size <= MAX_STORE_BYTES {
+            return true;
+        }
+        let mut done: Vec<(Instant, String)> = self
+            .map
+            .iter()
+            .filter(|(_, entry)| matches!(entry.state, EntryState::Done(_)))
+            .map(|(key, entry)| (entry.expires_at, key.clone()))
+            .collect();
+        done.sort_unstable();
+        for (_, key) in done {
+            self.remove(&key);
+            if self.bytes + size <= MAX_STORE_BYTES {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+enum Lookup {
+    /// First time we see this key. We own it until complete or the marker is dropped.
+    Fresh,
+    InFlight,
+    Done(StoredResponse),
+    Mismatch,
+}
+
+/// Remembers recent idempotency keys and their responses
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyStore {
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    fn begin(&self, key: &str, fingerprint: blake3::Hash) -> Lookup {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove_expired(now);
+
+        if let Some(entry) = entries.map.get(key) {
+            if entry.fingerprint != fingerprint {
+                return Lookup::Mismatch;
+            }
+            return match &entry.state {
+                EntryState::InFlight => Lookup::InFlight,
+                EntryState::Done(stored) => Lookup::Done(stored.clone()),
+            };
+        }
+        let entry = Entry {
+            fingerprint,
+            expires_at: now + self.ttl,
+            state: EntryState::InFlight,
+        };
+        if !entries.make_room(entry.size(key)) {
+            // Don't fail the request, just don't protect it
+            tracing::warn!(
+                max_bytes = MAX_STORE_BYTES,
+                "Idempotency store is full, not tracking key"
+            );
+            return Lookup::Fresh;
+        }
+        entries.insert(key, entry);
+        Lookup::Fresh
+    }
+
+    fn complete(&self, key: &str, stored: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(mut entry) = entries.remove(key) else {
+            return;
+        };
+        entry.state = EntryState::Done(stored);
+        entry.expires_at = Instant::now() + self.ttl;
+        if entries.make_room(entry.size(key)) {
+            entries.insert(key, entry);
+        } else {
+            tracing::warn!(
+                max_bytes = MAX_STORE_BYTES,
+                "Idempotency store is full, not storing the response"
+            );
+        }
+    }
+
+    fn forget(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// Removes the in-flight marker if the request did not complete, so the client can retry.
+struct InFlightMarker {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    completed: bool,
+}
+
+impl InFlightMarker {
+    fn complete(mut self, stored: StoredResponse) {
+        self.store.complete(&self.key, stored);
+        self.completed = true;
+    }
+}
+
+impl Drop for InFlightMarker {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.forget(&self.key);
+        }
+    }
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state(Arc<IdempotencyStore>, idempotency_middleware)`.
+pub async fn idempotency_middleware(
+    State(store): State<Arc<IdempotencyStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    // The auth layer runs first and sets the principal, if API keys are required
+    let principal = request
+        .extensions()
+        .get::<Principal>()
+        .map(|principal| principal.0.as_str())
+        .unwrap_or_default();
+    let Some(key) = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|k| format!("{principal} {} {k}", request.uri().path()))
+    else {
+        return next.run(request).await;
+    };
+
+    // Fingerprint the body so a key re-used for a different request is caught
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_REQUEST_BYTES).await {
+        Ok(body) => body,
+        Err(err) => {
+            return error_response(StatusCode::PAYLOAD_TOO_LARGE, &err.to_string());
+        }
+    };
+    let fingerprint = blake3::hash(&body);
+    let request = Request::from_parts(parts, Body::from(body));
+
+    match store.begin(&key, fingerprint) {
+        Lookup::Mismatch => error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Idempotency-Key was already used with a different request body",
+        ),
+        Lookup::InFlight => error_response(
+            StatusCode::CONFLICT,
+            "A request with this Idempotency-Key is already in progress",
+        ),
+        Lookup::Done(stored) => {
+            tracing::debug!(key, "Replaying idempotent response");
+            let mut response = (stored.status, stored.headers, stored.body).into_response();
+            response
+                .headers_mut()
+                .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+            response
+        }
+        Lookup::Fresh => {
+            let marker = InFlightMarker {
+                store: store.clone(),
+                key,
+                completed: false,
+            };
+            let response = next.run(request).await;
+            if !response.status().is_success() {
+                // marker drops, key is forgotten
+                return response;
+            }
+            record_response(response, marker)
+        }
+    }
+}
+
+fn error_response(code: StatusCode, message: &str) -> Response {
+    ErrorResponse::from_http_error(HttpError {
+        code: code.as_u16(),
+        message: message.to_string(),
+    })
+    .into_response()
+}
+
+/// Pass the response through to the client, keeping a copy. The copy is stored once the body
+/// has been fully sent, so an SSE stream is only stored if it ran to the end.
+fn record_response(response: Response, marker: InFlightMarker) -> Response {
+    let (parts, body) = response.into_parts();
+    let status = parts.status;
+    let headers = parts.headers.clone();
+    let mut upstream = body.into_data_stream();
+
+    let stream = async_stream::stream! {
+        let mut marker = Some(marker);
+        let mut copy: Vec<u8> = Vec::new();
+        let mut too_large = false;
+        while let Some(chunk) = upstream.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    if !too_large {
+                        if copy.len() + bytes.len() > MAX_STORED_RESPONSE_BYTES {
+                            too_large = true;
+                            copy = Vec::new();
+                        } else {
+                            copy.extend_from_slice(&bytes);
+                        }
+                    }
+                    yield Ok(bytes);
+                }
+                Err(err) => {
+                    // marker drops, key is forgotten
+                    yield Err(err);
+                    return;
+                }
+            }
+        }
+        if let Some(marker) = marker.take() {
+            if !too_large {
+                marker.complete(StoredResponse {
+                    status,
+                    headers,
+                    body: Bytes::from(copy),
+                });
+            }
+        }
+    };
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored() -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(b"{}"),
+        }
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+        let fp = blake3::hash(b"body");
+
+        assert!(matches!(store.begin("k", fp), Lookup::Fresh));
+        assert!(matches!(store.begin("k", fp), Lookup::InFlight));
+        assert!(matches!(
+            store.begin("k", blake3::hash(b"other")),
+            Lookup::Mismatch
+        ));
+
+        store.complete("k", stored());
+        assert!(matches!(store.begin("k", fp), Lookup::Done(_)));
+    }
+
+    #[test]
+    fn test_marker_drop_forgets() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+        let fp = blake3::hash(b"body");
+        assert!(matches!(store.begin("k", fp), Lookup::Fresh));
+        drop(InFlightMarker {
+            store: store.clone(),
+            key: "k".to_string(),
+            completed: false,
+        });
+        assert!(matches!(store.begin("k", fp), Lookup::Fresh));
+    }
+
+    #[test]
+    fn test_bounded_by_bytes() {
+        let store = Arc::new(IdempotencyStore::new(Duration::from_secs(60)));
+        let fp = blake3::hash(b"body");
+        let large = || StoredResponse {
+            body: Bytes::from(vec![0; MAX_STORE_BYTES / 2]),
+            ..stored()
+        };
+
+        assert!(matches!(store.begin("a", fp), Lookup::Fresh));
+        store.complete("a", large());
+        assert!(matches!(store.begin("b", fp), Lookup::Fresh));
+        store.complete("b", large());
+        // No room for both, the response that expires first goes
+        assert!(matches!(store.begin("b", fp), Lookup::Done(_)));
+        assert!(matches!(store.begin("a", fp), Lookup::Fresh));
+        assert!(store.entries.lock().unwrap().bytes <= MAX_STORE_BYTES);
+
+        store.forget("a");
+        store.forget("b");
+        assert_eq!(store.entries.lock().unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let store = Arc::new(IdempotencyStore::new(Duration::ZERO));
+        let fp = blake3::hash(b"body");
+        assert!(matches!(store.begin("k", fp), Lookup::Fresh));
+        assert!(matches!(store.begin("k", fp), Lookup::Fresh));
+    }
+}
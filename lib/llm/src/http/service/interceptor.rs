@@ -0,0 +1,294 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hooks to plug content moderation into the HTTP service, like PII scrubbing or safety filters,
+//! without changing the handlers. A [`RequestInterceptor`] sees every chat completion and
+//! completion request before it goes to the engine, and every response chunk before it goes to
+//! the client. It can change them in place or reject them. The Responses and Ollama APIs are
+//! served by the same engines, so their requests are intercepted as chat completions or
+//! completions.
+//!
+//! Register interceptors with
+//! [`HttpServiceConfigBuilder::with_interceptor`](super::service_v2::HttpServiceConfigBuilder::with_interceptor),
+//! they run in that order. Requests are intercepted before their model is looked up, and before
+//! the usage and audit records, so those see what the interceptors let through. Responses served
+//! by the [`super::response_cache`] go through `on_request` too, but not `on_response`: they went
+//! through it when they were generated.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{http::StatusCode, Json};
+use dynamo_runtime::engine::{AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+
+use super::error::HttpError;
+use super::metrics::Endpoint;
+use super::openai::ErrorResponse;
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+};
+use crate::protocols::openai::completions::{CompletionResponse, NvCreateCompletionRequest};
+use crate::protocols::openai::nvext::NvExt;
+
+/// The request an interceptor is called with
+#[derive(Debug, Clone)]
+pub struct InterceptContext {
+    pub request_id: String,
+
+    /// The model the client asked for
+    pub model: String,
+
+    /// `chat_completions`, `completions`, `responses` or `ollama`
+    pub endpoint: &'static str,
+
+    /// The API key that sent it, see [`super::auth::Principal`]. None without authentication.
+    pub principal: Option<String>,
+}
+
+impl InterceptContext {
+    pub(super) fn new(
+        request_id: &str,
+        model: &str,
+        endpoint: Endpoint,
+        nvext: Option<&NvExt>,
+    ) -> Self {
+        InterceptContext {
+            request_id: request_id.to_string(),
+            model: model.to_string(),
+            endpoint: endpoint.as_str(),
+            principal: nvext.and_then(|nvext| nvext.principal.clone()),
+        }
+    }
+}
+
+pub enum InterceptedRequest<'a> {
+    Chat(&'a mut NvCreateChatCompletionRequest),
+    Completion(&'a mut NvCreateCompletionRequest),
+}
+
+/// One streamed chunk of a response
+pub enum InterceptedResponse<'a> {
+    Chat(&'a mut NvCreateChatCompletionStreamResponse),
+    Completion(&'a mut CompletionResponse),
+}
+
+/// Inspects, changes or rejects requests and their responses. Both hooks do nothing by default.
+#[async_trait]
+pub trait RequestInterceptor: Send + Sync {
+    /// Called before the request goes to the engine. An error rejects it with the error's status
+    /// code, which must be a 4xx.
+    async fn on_request(
+        &self,
+        _ctx: &InterceptContext,
+        _request: InterceptedRequest<'_>,
+    ) -> Result<(), HttpError> {
+        Ok(())
+    }
+
+    /// Called for every chunk of the response, streamed or not. An error stops the generation
+    /// and ends the response with the error's message. A streamed response was already sent with
+    /// status 200, so the error is its last event.
+    async fn on_response(
+        &self,
+        _ctx: &InterceptContext,
+        _response: InterceptedResponse<'_>,
+    ) -> Result<(), HttpError> {
+        Ok(())
+    }
+}
+
+/// A request type interceptors understand
+pub(super) trait Intercept {
+    fn intercepted(&mut self) -> InterceptedRequest<'_>;
+}
+
+impl Intercept for NvCreateChatCompletionRequest {
+    fn intercepted(&mut self) -> InterceptedRequest<'_> {
+        InterceptedRequest::Chat(self)
+    }
+}
+
+impl Intercept for NvCreateCompletionRequest {
+    fn intercepted(&mut self) -> InterceptedRequest<'_> {
+        InterceptedRequest::Completion(self)
+    }
+}
+
+/// A response type interceptors understand
+pub(super) trait InterceptResponse {
+    fn intercepted(&mut self) -> InterceptedResponse<'_>;
+}
+
+impl InterceptResponse for NvCreateChatCompletionStreamResponse {
+    fn intercepted(&mut self) -> InterceptedResponse<'_> {
+        InterceptedResponse::Chat(self)
+    }
+}
+
+impl InterceptResponse for CompletionResponse {
+    fn intercepted(&mut self) -> InterceptedResponse<'_> {
+        InterceptedResponse::Completion(self)
+    }
+}
+
+/// Pass `request` through the interceptors in order. The first one to reject it decides the
+/// error.
+pub(super) async fn intercept<R: Intercept>(
+    interceptors: &[Arc<dyn RequestInterceptor>],
+    ctx: &InterceptContext,
+    request: &mut R,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    for interceptor in interceptors {
+        if let Err(err) = interceptor.on_request(ctx, request.intercepted()).await {
+            tracing::debug!(
+                request_id = ctx.request_id,
+                %err,
+                "Request rejected by interceptor"
+            );
+            return Err(ErrorResponse::from_http_error(err));
+        }
+    }
+    Ok(())
+}
+
+/// Pass each response of `stream` through the interceptors in order
+pub(super) fn track<T: InterceptResponse + Send + 'static>(
+    interceptors: &[Arc<dyn RequestInterceptor>],
+    ctx: InterceptContext,
+    stream: ManyOut<Annotated<T>>,
+) -> ManyOut<Annotated<T>> {
+    if interceptors.is_empty() {
+        return stream;
+    }
+    let interceptors = interceptors.to_vec();
+    let engine_ctx = stream.context();
+    let stop = engine_ctx.clone();
+    let intercepted = async_stream::stream! {
+        let mut stream = stream;
+        'responses: while let Some(mut annotated) = stream.next().await {
+            if let Some(data) = annotated.data.as_mut() {
+                for interceptor in &interceptors {
+                    if let Err(err) = interceptor.on_response(&ctx, data.intercepted()).await {
+                        tracing::debug!(
+                            request_id = ctx.request_id,
+                            %err,
+                            "Response rejected by interceptor"
+                        );
+                        stop.stop_generating();
+                        yield Annotated::from_error(err.message);
+                        break 'responses;
+                    }
+                }
+            }
+            yield annotated;
+        }
+    };
+    ResponseStream::new(Box::pin(intercepted), engine_ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::pipeline::context::Controller;
+
+    /// Replaces digits in prompts and blocks responses that mention a secret
+    struct Moderation;
+
+    #[async_trait]
+    impl RequestInterceptor for Moderation {
+        async fn on_request(
+            &self,
+            ctx: &InterceptContext,
+            request: InterceptedRequest<'_>,
+        ) -> Result<(), HttpError> {
+            if ctx.model == "blocked" {
+                return Err(HttpError {
+                    code: 403,
+                    message: "Model not allowed".to_string(),
+                });
+            }
+            if let InterceptedRequest::Completion(request) = request {
+                let prompt = serde_json::to_string(&request.inner.prompt).unwrap();
+                let scrubbed = prompt.replace(|c: char| c.is_ascii_digit(), "#");
+                request.inner.prompt = serde_json::from_str(&scrubbed).unwrap();
+            }
+            Ok(())
+        }
+
+        async fn on_response(
+            &self,
+            _ctx: &InterceptContext,
+            response: InterceptedResponse<'_>,
+        ) -> Result<(), HttpError> {
+            if let InterceptedResponse::Completion(response) = response {
+                if response.choices.iter().any(|c| c.text.contains("secret")) {
+                    return Err(HttpError {
+                        code: 400,
+                        message: "Blocked by safety filter".to_string(),
+                    });
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn ctx(model: &str) -> InterceptContext {
+        InterceptContext::new("req-1", model, Endpoint::Completions, None)
+    }
+
+    fn chunk(text: &str) -> Annotated<CompletionResponse> {
+        let response = serde_json::from_value(serde_json::json!({
+            "id": "cmpl-1",
+            "choices": [{"text": text, "index": 0}],
+            "created": 0,
+            "model": "llama",
+            "object": "text_completion",
+        }))
+        .unwrap();
+        Annotated::from_data(response)
+    }
+
+    #[tokio::test]
+    async fn test_intercept_request() {
+        let interceptors: Vec<Arc<dyn RequestInterceptor>> = vec![Arc::new(Moderation)];
+        let mut request: NvCreateCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "llama",
+            "prompt": "My card is 4111 1111",
+        }))
+        .unwrap();
+
+        intercept(&interceptors, &ctx("llama"), &mut request)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request.inner.prompt).unwrap(),
+            "My card is #### ####"
+        );
+
+        let (status, _) = intercept(&interceptors, &ctx("blocked"), &mut request)
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_intercept_response() {
+        let interceptors: Vec<Arc<dyn RequestInterceptor>> = vec![Arc::new(Moderation)];
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(vec![
+                chunk("The password is"),
+                chunk(" secret"),
+                chunk(" and more"),
+            ])),
+            Arc::new(Controller::default()),
+        );
+
+        let responses: Vec<_> = track(&interceptors, ctx("llama"), stream).collect().await;
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].data.is_some());
+        assert!(responses[1].is_error());
+    }
+}
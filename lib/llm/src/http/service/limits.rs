@@ -0,0 +1,360 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timeouts and the maximum body size of requests.
+//!
+//! Each endpoint can have its own [`RequestTimeouts`], all counted from when the request arrived:
+//! - `connect`, until the engine accepted the request, i.e. a worker was found and reached,
+//! - `first_token`, until the first response,
+//! - `total`, until the response is complete.
+//!
+//! `stream_idle` limits the wait for each response after the first.
+//!
+//! A request that runs out of time is cancelled all the way to the worker. If nothing was sent to
+//! the client yet it gets a `504 Gateway Timeout`, a streamed response ends with an error event.
+//!
+//! A request with a larger body than allowed gets a `413 Payload Too Large` with an OpenAI style
+//! error body, before it is read.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use dynamo_runtime::engine::{AsyncEngineContextProvider, Data, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use tokio::time::Instant;
+
+use super::error::openai_error_response;
+use super::metrics::Endpoint;
+use super::openai::ErrorResponse;
+
+/// The most a request body may have by default, like axum
+pub const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Timeouts of one endpoint. None means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    pub connect: Option<Duration>,
+    pub first_token: Option<Duration>,
+    pub total: Option<Duration>,
+    pub stream_idle: Option<Duration>,
+}
+
+impl RequestTimeouts {
+    /// These timeouts, with the ones that aren't set taken from `other`
+    pub fn or(self, other: RequestTimeouts) -> Self {
+        RequestTimeouts {
+            connect: self.connect.or(other.connect),
+            first_token: self.first_token.or(other.first_token),
+            total: self.total.or(other.total),
+            stream_idle: self.stream_idle.or(other.stream_idle),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.connect.is_none()
+            && self.first_token.is_none()
+            && self.total.is_none()
+            && self.stream_idle.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// For every endpoint
+    pub default: RequestTimeouts,
+
+    /// Override the default of some endpoints, timeout by timeout
+    pub per_endpoint: HashMap<Endpoint, RequestTimeouts>,
+}
+
+impl TimeoutConfig {
+    pub fn new(default: RequestTimeouts) -> Self {
+        TimeoutConfig {
+            default,
+            per_endpoint: HashMap::new(),
+        }
+    }
+
+    pub fn with_endpoint(mut self, endpoint: Endpoint, timeouts: RequestTimeouts) -> Self {
+        self.per_endpoint.insert(endpoint, timeouts);
+        self
+    }
+
+    /// The timeouts that apply to `endpoint`
+    pub fn for_endpoint(&self, endpoint: Endpoint) -> RequestTimeouts {
+        match self.per_endpoint.get(&endpoint) {
+            Some(timeouts) => timeouts.or(self.default),
+            None => self.default,
+        }
+    }
+}
+
+/// The timeouts of one request, counting from when it arrived
+pub(super) struct Deadline {
+    timeouts: RequestTimeouts,
+    start: Instant,
+
+    /// The timeout that fired, if one did
+    expired: Arc<OnceLock<&'static str>>,
+}
+
+impl Deadline {
+    pub(super) fn start(config: &TimeoutConfig, endpoint: Endpoint) -> Self {
+        Deadline {
+            timeouts: config.for_endpoint(endpoint),
+            start: Instant::now(),
+            expired: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// The earliest of `timeouts` that is set, with its name
+    fn earliest(
+        &self,
+        timeouts: &[(&'static str, Option<Duration>)],
+    ) -> Option<(&'static str, Instant)> {
+        timeouts
+            .iter()
+            .filter_map(|(name, timeout)| timeout.map(|t| (*name, self.start + t)))
+            .min_by_key(|(_, at)| *at)
+    }
+
+    /// Wait for the engine to accept the request. Dropping `generate` abandons the request.
+    pub(super) async fn connect<T>(
+        &self,
+        generate: impl Future<Output = T>,
+    ) -> Result<T, (StatusCode, Json<ErrorResponse>)> {
+        // No token can come before the engine accepted the request
+        let Some((name, at)) = self.earliest(&[
+            ("connect", self.timeouts.connect),
+            ("first_token", self.timeouts.first_token),
+            ("total", self.timeouts.total),
+        ]) else {
+            return Ok(generate.await);
+        };
+        tokio::time::timeout_at(at, generate).await.map_err(|_| {
+            let _ = self.expired.set(name);
+            tracing::debug!(
+                timeout = name,
+                "Request timed out before the engine accepted it"
+            );
+            ErrorResponse::gateway_timeout(&timeout_message(name))
+        })
+    }
+
+    /// End `stream` with an error and cancel the request if it runs out of time
+    pub(super) fn track<T: Data>(&self, stream: ManyOut<Annotated<T>>) -> ManyOut<Annotated<T>> {
+        if self.timeouts.first_token.is_none()
+            && self.timeouts.total.is_none()
+            && self.timeouts.stream_idle.is_none()
+        {
+            return stream;
+        }
+        let first_token = self.earliest(&[
+            ("first_token", self.timeouts.first_token),
+            ("total", self.timeouts.total),
+        ]);
+        let total = self.earliest(&[("total", self.timeouts.total)]);
+        let stream_idle = self.timeouts.stream_idle;
+        let expired = self.expired.clone();
+        let engine_ctx = stream.context();
+        let cancel = engine_ctx.clone();
+        let tracked = async_stream::stream! {
+            let mut stream = stream;
+            let mut deadline = first_token;
+            loop {
+                let next = match deadline {
+                    Some((name, at)) => match tokio::time::timeout_at(at, stream.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            let _ = expired.set(name);
+                            tracing::debug!(request_id = cancel.id(), timeout = name, "Request timed out");
+                            cancel.kill();
+                            yield Annotated::from_error(timeout_message(name));
+                            break;
+                        }
+                    },
+                    None => stream.next().await,
+                };
+                let Some(annotated) = next else {
+                    break;
+                };
+                if annotated.data.is_some() {
+                    let idle = stream_idle.map(|idle| ("stream_idle", Instant::now() + idle));
+                    deadline = [total, idle].into_iter().flatten().min_by_key(|(_, at)| *at);
+                }
+                yield annotated;
+            }
+        };
+        ResponseStream::new(Box::pin(tracked), engine_ctx)
+    }
+
+    /// The error to answer with if the request ran out of time, for a response that wasn't sent
+    /// yet
+    pub(super) fn expired(&self) -> Option<(StatusCode, Json<ErrorResponse>)> {
+        self.expired
+            .get()
+            .map(|name| ErrorResponse::gateway_timeout(&timeout_message(name)))
+    }
+}
+
+fn timeout_message(name: &str) -> String {
+    format!("Request timed out: {name} timeout exceeded")
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state(max_bytes, body_limit_middleware)`.
+pub async fn body_limit_middleware(
+    State(max_bytes): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let too_large = || {
+        openai_error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("Request body is larger than {max_bytes} bytes"),
+            "invalid_request_error",
+            "request_too_large",
+        )
+    };
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    match content_length {
+        Some(length) if length > max_bytes as u64 => too_large(),
+        Some(_) => next.run(request).await,
+        // A chunked body, read it to know its size
+        None => {
+            let (parts, body) = request.into_parts();
+            match axum::body::to_bytes(body, max_bytes).await {
+                Ok(body) => next.run(Request::from_parts(parts, Body::from(body))).await,
+                Err(_) => too_large(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::pipeline::context::Controller;
+    use dynamo_runtime::pipeline::AsyncEngineContext;
+
+    fn slow_stream(delays_ms: Vec<u64>, ctx: Arc<Controller>) -> ManyOut<Annotated<String>> {
+        let stream = futures::stream::iter(delays_ms).then(|ms| async move {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            Annotated::from_data("token".to_string())
+        });
+        ResponseStream::new(Box::pin(stream), ctx)
+    }
+
+    #[test]
+    fn test_per_endpoint() {
+        let config = TimeoutConfig::new(RequestTimeouts {
+            connect: Some(Duration::from_secs(5)),
+            total: Some(Duration::from_secs(600)),
+            ..Default::default()
+        })
+        .with_endpoint(
+            Endpoint::Embeddings,
+            RequestTimeouts {
+                total: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+        );
+        let embeddings = config.for_endpoint(Endpoint::Embeddings);
+        assert_eq!(embeddings.connect, Some(Duration::from_secs(5)));
+        assert_eq!(embeddings.total, Some(Duration::from_secs(30)));
+        assert_eq!(
+            config.for_endpoint(Endpoint::ChatCompletions),
+            config.default
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_first_token_timeout() {
+        let config = TimeoutConfig::new(RequestTimeouts {
+            first_token: Some(Duration::from_millis(100)),
+            total: Some(Duration::from_millis(1000)),
+            ..Default::default()
+        });
+
+        // The first token is in time, later tokens can take longer up to the total
+        let deadline = Deadline::start(&config, Endpoint::ChatCompletions);
+        let ctx = Arc::new(Controller::default());
+        let responses: Vec<_> = deadline
+            .track(slow_stream(vec![50, 300, 300], ctx.clone()))
+            .collect()
+            .await;
+        assert!(responses.iter().all(|r| r.data.is_some()));
+        assert!(deadline.expired().is_none());
+        assert!(!ctx.is_killed());
+
+        let deadline = Deadline::start(&config, Endpoint::ChatCompletions);
+        let ctx = Arc::new(Controller::default());
+        let responses: Vec<_> = deadline
+            .track(slow_stream(vec![200], ctx.clone()))
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].is_error());
+        assert!(ctx.is_killed());
+        let (status, _) = deadline.expired().unwrap();
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_total_timeout() {
+        let config = TimeoutConfig::new(RequestTimeouts {
+            total: Some(Duration::from_millis(500)),
+            ..Default::default()
+        });
+        let deadline = Deadline::start(&config, Endpoint::Completions);
+        let ctx = Arc::new(Controller::default());
+        let responses: Vec<_> = deadline
+            .track(slow_stream(vec![200, 200, 200], ctx.clone()))
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 3);
+        assert!(responses[2].is_error());
+        assert!(ctx.is_killed());
+
+        let deadline = Deadline::start(&config, Endpoint::Completions);
+        let connected = deadline
+            .connect(tokio::time::sleep(Duration::from_secs(1)))
+            .await;
+        assert!(connected.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_idle_timeout() {
+        let config = TimeoutConfig::new(RequestTimeouts {
+            stream_idle: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+
+        // The first token may take longer, the ones after it not
+        let deadline = Deadline::start(&config, Endpoint::ChatCompletions);
+        let ctx = Arc::new(Controller::default());
+        let responses: Vec<_> = deadline
+            .track(slow_stream(vec![500, 50, 50, 200], ctx.clone()))
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 4);
+        assert!(responses[..3].iter().all(|r| r.data.is_some()));
+        assert!(responses[3].is_error());
+        assert!(ctx.is_killed());
+    }
+}
@@ -49,6 +49,7 @@ pub struct InflightGuard {
 
 /// Requests will be logged by the type of endpoint hit
 /// This will include llamastack in the future
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Endpoint {
     /// OAI Completions
     Completions,
@@ -58,6 +59,12 @@ pub enum Endpoint {
 
     /// OAI Embeddings
     Embeddings,
+
+    /// OAI Responses
+    Responses,
+
+    /// Ollama chat and generate
+    Ollama,
 }
 
 /// Metrics for the HTTP service
@@ -354,6 +361,8 @@ impl std::fmt::Display for Endpoint {
             Endpoint::Completions => write!(f, "completions"),
             Endpoint::ChatCompletions => write!(f, "chat_completions"),
             Endpoint::Embeddings => write!(f, "embeddings"),
+            Endpoint::Responses => write!(f, "responses"),
+            Endpoint::Ollama => write!(f, "ollama"),
         }
     }
 }
@@ -364,6 +373,8 @@ impl Endpoint {
             Endpoint::Completions => "completions",
             Endpoint::ChatCompletions => "chat_completions",
             Endpoint::Embeddings => "embeddings",
+            Endpoint::Responses => "responses",
+            Endpoint::Ollama => "ollama",
         }
     }
 }
@@ -467,6 +478,25 @@ pub fn router(registry: Registry, path: Option<String>) -> (Vec<RouteDoc>, Route
     (vec![doc], route)
 }
 
+/// Serve `registry` on `/metrics` of `host:port` until `cancel`, for processes without the HTTP
+/// service such as workers
+pub async fn serve(
+    registry: Registry,
+    host: &str,
+    port: u16,
+    cancel: tokio_util::sync::CancellationToken,
+) -> anyhow::Result<()> {
+    let (_, router) = router(registry, None);
+    let listener = tokio::net::TcpListener::bind((host, port))
+        .await
+        .map_err(|err| anyhow::anyhow!("Failed binding metrics to {host}:{port}: {err}"))?;
+    tracing::info!("Serving metrics on http://{host}:{port}/metrics");
+    axum::serve(listener, router)
+        .with_graceful_shutdown(cancel.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
 /// Metrics Handler
 async fn handler_metrics(State(registry): State<Arc<Registry>>) -> impl IntoResponse {
     let encoder = prometheus::TextEncoder::new();
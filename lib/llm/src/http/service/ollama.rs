@@ -0,0 +1,387 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The Ollama API, for desktop tools that only speak it. See [`crate::protocols::ollama`].
+//!
+//! - `POST /api/chat` and `POST /api/generate` generate, streamed as newline delimited JSON
+//!   unless the request has `"stream": false`.
+//! - `GET /api/tags` lists the models, `GET /api/version` answers with a version Ollama clients
+//!   accept.
+//!
+//! Errors are `{"error": "..."}`, also as the last line of a stream.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{stream::BoxStream, Stream, StreamExt};
+use serde::Serialize;
+
+use super::{
+    auth::Principal,
+    error::HttpError,
+    framing::{self, json_line},
+    interceptor::{self, InterceptContext},
+    limits::Deadline,
+    metrics::{Endpoint, InflightGuard},
+    openai::{
+        apply_principal, apply_priority_header, apply_session_header, check_nvext, check_ready,
+        continue_trace, ErrorResponse,
+    },
+    service_v2,
+    usage::{self, UsageTracker},
+    RouteDoc,
+};
+use crate::audit::{self, AuditTrail};
+use crate::protocols::ollama::{
+    created_at, ChatRequest, ChatResponse, DoneStats, GenerateRequest, GenerateResponse, Message,
+    ResponseTracker, TextChunk,
+};
+use crate::protocols::openai::nvext::NvExt;
+use crate::protocols::Annotated;
+use dynamo_runtime::pipeline::{AsyncEngineContext, Context};
+
+/// The Ollama version reported to clients, some check it before using the API
+const OLLAMA_VERSION: &str = "0.6.0";
+
+type ErrorReply = (StatusCode, Json<ErrorResponse>);
+
+fn bad_request(message: String) -> ErrorReply {
+    ErrorResponse::from_http_error(HttpError {
+        code: StatusCode::BAD_REQUEST.as_u16(),
+        message,
+    })
+}
+
+/// Validate `nvext` and set the priority, session and principal on it, and continue the client's
+/// trace, like the OpenAI endpoints
+fn apply_nvext(
+    state: &Arc<service_v2::State>,
+    headers: &HeaderMap,
+    principal: Option<Extension<Principal>>,
+    nvext: &mut Option<NvExt>,
+) -> Result<(), ErrorReply> {
+    check_nvext(state, nvext.as_mut())?;
+    apply_priority_header(headers, nvext)?;
+    apply_session_header(headers, nvext)?;
+    apply_principal(state, principal, nvext);
+    continue_trace(headers, nvext.as_ref());
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(request_id))]
+async fn chat(
+    State(state): State<Arc<service_v2::State>>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<ChatRequest>,
+) -> Result<Response, ErrorReply> {
+    check_ready(&state)?;
+    let deadline = Deadline::start(state.timeouts(), Endpoint::Ollama);
+    let mut chat_request = request.chat_request().map_err(bad_request)?;
+    apply_nvext(&state, &headers, principal, &mut chat_request.nvext)?;
+    let request_id = uuid::Uuid::new_v4().to_string();
+    tracing::Span::current().record("request_id", request_id.as_str());
+    let intercept_ctx = InterceptContext::new(
+        &request_id,
+        &request.model,
+        Endpoint::Ollama,
+        chat_request.nvext.as_ref(),
+    );
+    interceptor::intercept(state.interceptors(), &intercept_ctx, &mut chat_request).await?;
+
+    let model = request.model;
+    let streaming = request.stream.unwrap_or(true);
+    let engine = state
+        .manager()
+        .get_chat_completions_engine(&model)
+        .map_err(|_| ErrorResponse::model_not_found())?;
+    let inflight_guard =
+        state
+            .metrics_clone()
+            .create_inflight_guard(&model, Endpoint::Ollama, streaming);
+
+    let audit = AuditTrail::start(
+        state.audit_logger(),
+        &request_id,
+        Endpoint::Ollama.as_str(),
+        &chat_request,
+    );
+    let usage = UsageTracker::start(
+        state.usage_accounting(),
+        &request_id,
+        &model,
+        Endpoint::Ollama,
+        streaming,
+        &mut chat_request.nvext,
+    );
+    let stream = deadline
+        .connect(engine.generate(Context::with_id(chat_request, request_id)))
+        .await?
+        .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    let stream = deadline.track(stream);
+    let stream = interceptor::track(state.interceptors(), intercept_ctx, stream);
+    let stream = usage::track(usage, stream);
+    let stream = audit::track(audit, stream);
+    let ctx = stream.context();
+    let lines = lines(stream, streaming, move |content, stats| {
+        json_line(&ChatResponse {
+            model: model.clone(),
+            created_at: created_at(),
+            message: Message {
+                role: "assistant".to_string(),
+                content,
+                images: Vec::new(),
+            },
+            done: stats.is_some(),
+            stats,
+        })
+    });
+    respond(lines, streaming, ctx, inflight_guard, deadline).await
+}
+
+#[tracing::instrument(skip_all, fields(request_id))]
+async fn generate(
+    State(state): State<Arc<service_v2::State>>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<GenerateRequest>,
+) -> Result<Response, ErrorReply> {
+    check_ready(&state)?;
+    let deadline = Deadline::start(state.timeouts(), Endpoint::Ollama);
+    let model = request.model.clone();
+    let streaming = request.stream.unwrap_or(true);
+    let request_id = uuid::Uuid::new_v4().to_string();
+    tracing::Span::current().record("request_id", request_id.as_str());
+    let line = move |response, stats: Option<DoneStats>| {
+        json_line(&GenerateResponse {
+            model: model.clone(),
+            created_at: created_at(),
+            response,
+            done: stats.is_some(),
+            stats,
+        })
+    };
+
+    let inflight_guard =
+        state
+            .metrics_clone()
+            .create_inflight_guard(&request.model, Endpoint::Ollama, streaming);
+
+    // Without the chat template the prompt goes to the completions engine as is
+    let (lines, ctx) = if request.is_raw() {
+        let mut completion_request = request.completion_request().map_err(bad_request)?;
+        apply_nvext(&state, &headers, principal, &mut completion_request.nvext)?;
+        let intercept_ctx = InterceptContext::new(
+            &request_id,
+            &request.model,
+            Endpoint::Ollama,
+            completion_request.nvext.as_ref(),
+        );
+        interceptor::intercept(
+            state.interceptors(),
+            &intercept_ctx,
+            &mut completion_request,
+        )
+        .await?;
+        let engine = state
+            .manager()
+            .get_completions_engine(&request.model)
+            .map_err(|_| ErrorResponse::model_not_found())?;
+        let audit = AuditTrail::start(
+            state.audit_logger(),
+            &request_id,
+            Endpoint::Ollama.as_str(),
+            &completion_request,
+        );
+        let usage = UsageTracker::start(
+            state.usage_accounting(),
+            &request_id,
+            &request.model,
+            Endpoint::Ollama,
+            streaming,
+            &mut completion_request.nvext,
+        );
+        let stream = deadline
+            .connect(engine.generate(Context::with_id(completion_request, request_id)))
+            .await?
+            .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+        let stream = deadline.track(stream);
+        let stream = interceptor::track(state.interceptors(), intercept_ctx, stream);
+        let stream = usage::track(usage, stream);
+        let stream = audit::track(audit, stream);
+        let ctx = stream.context();
+        (lines(stream, streaming, line), ctx)
+    } else {
+        let mut chat_request = request.chat_request().map_err(bad_request)?;
+        apply_nvext(&state, &headers, principal, &mut chat_request.nvext)?;
+        let intercept_ctx = InterceptContext::new(
+            &request_id,
+            &request.model,
+            Endpoint::Ollama,
+            chat_request.nvext.as_ref(),
+        );
+        interceptor::intercept(state.interceptors(), &intercept_ctx, &mut chat_request).await?;
+        let engine = state
+            .manager()
+            .get_chat_completions_engine(&request.model)
+            .map_err(|_| ErrorResponse::model_not_found())?;
+        let audit = AuditTrail::start(
+            state.audit_logger(),
+            &request_id,
+            Endpoint::Ollama.as_str(),
+            &chat_request,
+        );
+        let usage = UsageTracker::start(
+            state.usage_accounting(),
+            &request_id,
+            &request.model,
+            Endpoint::Ollama,
+            streaming,
+            &mut chat_request.nvext,
+        );
+        let stream = deadline
+            .connect(engine.generate(Context::with_id(chat_request, request_id)))
+            .await?
+            .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+        let stream = deadline.track(stream);
+        let stream = interceptor::track(state.interceptors(), intercept_ctx, stream);
+        let stream = usage::track(usage, stream);
+        let stream = audit::track(audit, stream);
+        let ctx = stream.context();
+        (lines(stream, streaming, line), ctx)
+    };
+    respond(lines, streaming, ctx, inflight_guard, deadline).await
+}
+
+/// The JSON lines of a response, made by `line` from the generated text and, for the last one,
+/// the stats. Only the last line if not `streaming`, with all the text. An `Err` is the engine's
+/// error.
+fn lines<T, S, F>(stream: S, streaming: bool, line: F) -> BoxStream<'static, Result<String, String>>
+where
+    T: TextChunk + Send + 'static,
+    S: Stream<Item = Annotated<T>> + Send + 'static,
+    F: Fn(String, Option<DoneStats>) -> String + Send + 'static,
+{
+    let lines = async_stream::stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut tracker = ResponseTracker::default();
+        let mut text = String::new();
+        while let Some(annotated) = stream.next().await {
+            let content = match tracker.add(annotated) {
+                Ok(content) => content,
+                Err(message) => {
+                    yield Err(message);
+                    return;
+                }
+            };
+            if !streaming {
+                text.push_str(&content);
+            } else if !content.is_empty() {
+                yield Ok(line(content, None));
+            }
+        }
+        yield Ok(line(text, Some(tracker.finish())));
+    };
+    lines.boxed()
+}
+
+/// Send the JSON `lines` as newline delimited JSON, or only the last one if not `streaming`. An
+/// `Err` line is the engine's error, it ends the stream. Generation stops if the client
+/// disconnects.
+async fn respond(
+    lines: BoxStream<'static, Result<String, String>>,
+    streaming: bool,
+    context: Arc<dyn AsyncEngineContext>,
+    mut inflight_guard: InflightGuard,
+    deadline: Deadline,
+) -> Result<Response, ErrorReply> {
+    if !streaming {
+        let mut lines = lines;
+        let mut last = None;
+        while let Some(line) = lines.next().await {
+            last = Some(line.map_err(|message| {
+                if let Some(timeout) = deadline.expired() {
+                    return timeout;
+                }
+                ErrorResponse::internal_server_error(&format!(
+                    "Failed to generate completions: {message}"
+                ))
+            })?);
+        }
+        inflight_guard.mark_ok();
+        let body = last.unwrap_or_default();
+        return Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response());
+    }
+
+    Ok(framing::ndjson(lines, context, inflight_guard))
+}
+
+#[derive(Serialize)]
+struct ModelTag {
+    name: String,
+    model: String,
+    modified_at: String,
+    size: u64,
+    digest: String,
+}
+
+#[derive(Serialize)]
+struct Tags {
+    models: Vec<ModelTag>,
+}
+
+async fn tags(State(state): State<Arc<service_v2::State>>) -> Result<Json<Tags>, ErrorReply> {
+    check_ready(&state)?;
+    let modified_at = created_at();
+    let mut names: Vec<String> = state.manager().model_display_names().into_iter().collect();
+    names.sort();
+    let models = names
+        .into_iter()
+        .map(|name| ModelTag {
+            model: name.clone(),
+            digest: blake3::hash(name.as_bytes()).to_hex().to_string(),
+            name,
+            modified_at: modified_at.clone(),
+            size: 0,
+        })
+        .collect();
+    Ok(Json(Tags { models }))
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+}
+
+async fn version() -> Json<Version> {
+    Json(Version {
+        version: OLLAMA_VERSION,
+    })
+}
+
+/// Create an Axum [`Router`] for the Ollama API under `/api`
+pub fn ollama_router(state: Arc<service_v2::State>) -> (Vec<RouteDoc>, Router) {
+    let chat_path = "/api/chat";
+    let generate_path = "/api/generate";
+    let tags_path = "/api/tags";
+    let version_path = "/api/version";
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, chat_path),
+        RouteDoc::new(axum::http::Method::POST, generate_path),
+        RouteDoc::new(axum::http::Method::GET, tags_path),
+        RouteDoc::new(axum::http::Method::GET, version_path),
+    ];
+    let router = Router::new()
+        .route(chat_path, post(chat))
+        .route(generate_path, post(generate))
+        .route(tags_path, get(tags))
+        .route(version_path, get(version))
+        .with_state(state);
+    (docs, router)
+}
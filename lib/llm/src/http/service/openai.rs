@@ -2,8 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Extension, State},
+    http::{HeaderMap, StatusCode},
     response::{
         sse::{Event, KeepAlive, Sse},
         IntoResponse, Response,
@@ -11,7 +11,7 @@ use axum::{
     routing::{get, post},
     Json, Router,
 };
-use futures::{Stream, StreamExt};
+use futures::{stream::BoxStream, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
@@ -22,14 +22,24 @@ use std::{
 use tokio_stream::wrappers::ReceiverStream;
 
 use super::{
+    auth::Principal,
+    choices,
     error::HttpError,
+    framing::{self, json_line, StreamFraming},
+    interceptor::{self, InterceptContext},
+    limits::Deadline,
     metrics::{Endpoint, InflightGuard, ResponseMetricCollector},
-    service_v2, RouteDoc,
+    output_rate, service_v2,
+    usage::{self, UsageTracker},
+    RouteDoc,
 };
 
+use crate::audit::{self, AuditTrail};
 use crate::protocols::openai::embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse};
 use crate::protocols::openai::{
-    chat_completions::NvCreateChatCompletionResponse, completions::CompletionResponse,
+    chat_completions::NvCreateChatCompletionResponse,
+    completions::CompletionResponse,
+    nvext::{parse_priority, NvExt, MAX_SESSION_ID_LEN},
 };
 use crate::request_template::RequestTemplate;
 use crate::types::{
@@ -39,8 +49,16 @@ use crate::types::{
     Annotated,
 };
 
+use dynamo_runtime::otel::{self, TraceCarrier};
 use dynamo_runtime::pipeline::{AsyncEngineContext, Context};
 
+/// Scheduling priority of the request: a number from -100 to 100, higher first, or one of the
+/// classes `interactive`, `default` and `batch`
+pub const PRIORITY_HEADER: &str = "x-dynamo-priority";
+
+/// The conversation the request is part of, sets `nvext.routing.session_id`
+pub const SESSION_HEADER: &str = "x-dynamo-session-id";
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct ErrorResponse {
     error: String,
@@ -68,6 +86,17 @@ impl ErrorResponse {
         )
     }
 
+    /// Gateway Timeout
+    /// The request ran out of time waiting for the engine, see [`super::limits`].
+    pub fn gateway_timeout(msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        (
+            StatusCode::GATEWAY_TIMEOUT,
+            Json(ErrorResponse {
+                error: msg.to_string(),
+            }),
+        )
+    }
+
     /// Internal Service Error
     /// Return this error when the service encounters an internal error.
     /// We should return a generic message to the client instead of the real error.
@@ -119,19 +148,40 @@ impl From<HttpError> for ErrorResponse {
 ///
 /// Note: For all requests, streaming or non-streaming, we always call the engine with streaming enabled. For
 /// non-streaming requests, we will fold the stream into a single response as part of this handler.
-#[tracing::instrument(skip_all)]
-async fn completions(
+#[tracing::instrument(skip_all, fields(request_id))]
+pub(super) async fn completions(
     State(state): State<Arc<service_v2::State>>,
-    Json(request): Json<NvCreateCompletionRequest>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    Json(mut request): Json<NvCreateCompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     // return a 503 if the service is not ready
     check_ready(&state)?;
+    let deadline = Deadline::start(state.timeouts(), Endpoint::Completions);
+
+    check_nvext(&state, request.nvext.as_mut())?;
+    apply_priority_header(&headers, &mut request.nvext)?;
+    apply_session_header(&headers, &mut request.nvext)?;
+    apply_principal(&state, principal, &mut request.nvext);
+    continue_trace(&headers, request.nvext.as_ref());
 
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
+    tracing::Span::current().record("request_id", request_id.as_str());
 
     // todo - decide on default
     let streaming = request.inner.stream.unwrap_or(false);
+    let framing = StreamFraming::negotiate(&headers);
+
+    let n = request.inner.n.unwrap_or(1);
+    let best_of = request.inner.best_of.unwrap_or(n);
+    check_choices(n, best_of, streaming)?;
+
+    // ranking the candidates needs their log probabilities
+    let logprobs = request.inner.logprobs.is_some();
+    if best_of > n && !logprobs {
+        request.inner.logprobs = Some(0);
+    }
 
     // update the request to always stream
     let inner = async_openai::types::CreateCompletionRequest {
@@ -139,11 +189,20 @@ async fn completions(
         ..request.inner
     };
 
-    let request = NvCreateCompletionRequest {
+    let mut request = NvCreateCompletionRequest {
         inner,
         nvext: request.nvext,
+        prompt_token_ids: request.prompt_token_ids,
     };
 
+    let intercept_ctx = InterceptContext::new(
+        &request_id,
+        &request.inner.model,
+        Endpoint::Completions,
+        request.nvext.as_ref(),
+    );
+    interceptor::intercept(state.interceptors(), &intercept_ctx, &mut request).await?;
+
     // todo - make the protocols be optional for model name
     // todo - when optional, if none, apply a default
     let model = &request.inner.model;
@@ -161,15 +220,51 @@ async fn completions(
 
     let mut response_collector = state.metrics_clone().create_response_collector(model);
 
-    // setup context
-    // todo - inherit request_id from distributed trace details
-    let request = Context::with_id(request, request_id.clone());
-
-    // issue the generate call on the engine
-    let stream = engine
-        .generate(request)
-        .await
+    let audit = AuditTrail::start(
+        state.audit_logger(),
+        &request_id,
+        Endpoint::Completions.as_str(),
+        &request,
+    );
+    let usage = UsageTracker::start(
+        state.usage_accounting(),
+        &request_id,
+        model,
+        Endpoint::Completions,
+        streaming,
+        &mut request.nvext,
+    );
+    let tokens_per_sec = output_rate_cap(&state, streaming, request.nvext.as_ref());
+
+    // issue the generate call on the engine, once per candidate
+    let stream = deadline
+        .connect(async {
+            if best_of > 1 {
+                let requests = (0..best_of)
+                    .map(|i| {
+                        let mut request = request.clone();
+                        request.inner.n = None;
+                        request.inner.best_of = None;
+                        request.inner.seed =
+                            request.inner.seed.map(|seed| seed.wrapping_add(i as i64));
+                        request
+                    })
+                    .collect();
+                choices::fan_out(&engine, &request_id, requests).await
+            } else {
+                // setup context
+                // todo - inherit request_id from distributed trace details
+                let request = Context::with_id(request, request_id.clone());
+                engine.generate(request).await
+            }
+        })
+        .await?
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    let stream = deadline.track(stream);
+    let stream = output_rate::track(tokens_per_sec, stream);
+    let stream = interceptor::track(state.interceptors(), intercept_ctx, stream);
+    let stream = usage::track(usage, stream);
+    let stream = audit::track(audit, stream);
 
     // capture the context to cancel the stream if the client disconnects
     let ctx = stream.context();
@@ -177,7 +272,10 @@ async fn completions(
     // todo - tap the stream and propagate request level metrics
     // note - we might do this as part of the post processing set to make it more generic
 
-    if streaming {
+    if streaming && framing == StreamFraming::Ndjson {
+        let lines = ndjson_lines(stream, response_collector);
+        Ok(framing::ndjson(lines, ctx, inflight_guard))
+    } else if streaming {
         let stream = stream.map(move |response| {
             process_event_converter(EventConverter::from(response), &mut response_collector)
         });
@@ -192,9 +290,12 @@ async fn completions(
         Ok(sse_stream.into_response())
     } else {
         // TODO: report ISL/OSL for non-streaming requests
-        let response = CompletionResponse::from_annotated_stream(stream.into())
+        let mut response = CompletionResponse::from_annotated_stream(stream.into())
             .await
             .map_err(|e| {
+                if let Some(timeout) = deadline.expired() {
+                    return timeout;
+                }
                 tracing::error!(
                     "Failed to fold completions stream for {}: {:?}",
                     request_id,
@@ -202,22 +303,32 @@ async fn completions(
                 );
                 ErrorResponse::internal_server_error("Failed to fold completions stream")
             })?;
+        if best_of > n {
+            response = choices::best_of(response, n as usize);
+            if !logprobs {
+                response.choices.iter_mut().for_each(|c| c.logprobs = None);
+            }
+        }
 
         inflight_guard.mark_ok();
         Ok(Json(response).into_response())
     }
 }
 
-#[tracing::instrument(skip_all)]
+#[tracing::instrument(skip_all, fields(request_id))]
 async fn embeddings(
     State(state): State<Arc<service_v2::State>>,
+    headers: HeaderMap,
     Json(request): Json<NvCreateEmbeddingRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     // return a 503 if the service is not ready
     check_ready(&state)?;
+    let deadline = Deadline::start(state.timeouts(), Endpoint::Embeddings);
+    continue_trace(&headers, None);
 
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
+    tracing::Span::current().record("request_id", request_id.as_str());
 
     // Embeddings are typically not streamed, so we default to non-streaming
     let streaming = false;
@@ -243,16 +354,20 @@ async fn embeddings(
     let request = Context::with_id(request, request_id.clone());
 
     // issue the generate call on the engine
-    let stream = engine
-        .generate(request)
-        .await
+    let stream = deadline
+        .connect(engine.generate(request))
+        .await?
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate embeddings"))?;
+    let stream = deadline.track(stream);
 
     // Embeddings are typically returned as a single response (non-streaming)
     // so we fold the stream into a single response
     let response = NvCreateEmbeddingResponse::from_annotated_stream(stream.into())
         .await
         .map_err(|e| {
+            if let Some(timeout) = deadline.expired() {
+                return timeout;
+            }
             tracing::error!(
                 "Failed to fold embeddings stream for {}: {:?}",
                 request_id,
@@ -273,33 +388,39 @@ async fn embeddings(
 ///
 /// Note: For all requests, streaming or non-streaming, we always call the engine with streaming enabled. For
 /// non-streaming requests, we will fold the stream into a single response as part of this handler.
-#[tracing::instrument(skip_all)]
-async fn chat_completions(
+#[tracing::instrument(skip_all, fields(request_id))]
+pub(super) async fn chat_completions(
     State((state, template)): State<(Arc<service_v2::State>, Option<RequestTemplate>)>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
     Json(mut request): Json<NvCreateChatCompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     // return a 503 if the service is not ready
     check_ready(&state)?;
+    let deadline = Deadline::start(state.timeouts(), Endpoint::ChatCompletions);
+
+    check_nvext(&state, request.nvext.as_mut())?;
+    apply_priority_header(&headers, &mut request.nvext)?;
+    apply_session_header(&headers, &mut request.nvext)?;
+    apply_principal(&state, principal, &mut request.nvext);
+    continue_trace(&headers, request.nvext.as_ref());
 
     // Apply template values if present
     if let Some(template) = template {
-        if request.inner.model.is_empty() {
-            request.inner.model = template.model.clone();
-        }
-        if request.inner.temperature.unwrap_or(0.0) == 0.0 {
-            request.inner.temperature = Some(template.temperature);
-        }
-        if request.inner.max_completion_tokens.unwrap_or(0) == 0 {
-            request.inner.max_completion_tokens = Some(template.max_completion_tokens);
-        }
+        template.apply(&mut request);
     }
     tracing::trace!("Received chat completions request: {:?}", request.inner);
 
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
+    tracing::Span::current().record("request_id", request_id.as_str());
 
     // todo - decide on default
     let streaming = request.inner.stream.unwrap_or(false);
+    let framing = StreamFraming::negotiate(&headers);
+
+    let n = request.inner.n.unwrap_or(1);
+    check_choices(n, n, streaming)?;
 
     // update the request to always stream
     let inner_request = async_openai::types::CreateChatCompletionRequest {
@@ -307,11 +428,19 @@ async fn chat_completions(
         ..request.inner
     };
 
-    let request = NvCreateChatCompletionRequest {
+    let mut request = NvCreateChatCompletionRequest {
         inner: inner_request,
         nvext: request.nvext,
     };
 
+    let intercept_ctx = InterceptContext::new(
+        &request_id,
+        &request.inner.model,
+        Endpoint::ChatCompletions,
+        request.nvext.as_ref(),
+    );
+    interceptor::intercept(state.interceptors(), &intercept_ctx, &mut request).await?;
+
     // todo - make the protocols be optional for model name
     // todo - when optional, if none, apply a default
     let model = &request.inner.model;
@@ -331,17 +460,52 @@ async fn chat_completions(
 
     let mut response_collector = state.metrics_clone().create_response_collector(model);
 
-    // setup context
-    // todo - inherit request_id from distributed trace details
-    let request = Context::with_id(request, request_id.clone());
+    let audit = AuditTrail::start(
+        state.audit_logger(),
+        &request_id,
+        Endpoint::ChatCompletions.as_str(),
+        &request,
+    );
+    let usage = UsageTracker::start(
+        state.usage_accounting(),
+        &request_id,
+        model,
+        Endpoint::ChatCompletions,
+        streaming,
+        &mut request.nvext,
+    );
+    let tokens_per_sec = output_rate_cap(&state, streaming, request.nvext.as_ref());
 
     tracing::trace!("Issuing generate call for chat completions");
 
-    // issue the generate call on the engine
-    let stream = engine
-        .generate(request)
-        .await
+    // issue the generate call on the engine, once per choice
+    let stream = deadline
+        .connect(async {
+            if n > 1 {
+                let requests = (0..n)
+                    .map(|i| {
+                        let mut request = request.clone();
+                        request.inner.n = None;
+                        request.inner.seed =
+                            request.inner.seed.map(|seed| seed.wrapping_add(i as i64));
+                        request
+                    })
+                    .collect();
+                choices::fan_out(&engine, &request_id, requests).await
+            } else {
+                // setup context
+                // todo - inherit request_id from distributed trace details
+                let request = Context::with_id(request, request_id.clone());
+                engine.generate(request).await
+            }
+        })
+        .await?
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    let stream = deadline.track(stream);
+    let stream = output_rate::track(tokens_per_sec, stream);
+    let stream = interceptor::track(state.interceptors(), intercept_ctx, stream);
+    let stream = usage::track(usage, stream);
+    let stream = audit::track(audit, stream);
 
     // capture the context to cancel the stream if the client disconnects
     let ctx = stream.context();
@@ -349,7 +513,10 @@ async fn chat_completions(
     // todo - tap the stream and propagate request level metrics
     // note - we might do this as part of the post processing set to make it more generic
 
-    if streaming {
+    if streaming && framing == StreamFraming::Ndjson {
+        let lines = ndjson_lines(stream, response_collector);
+        Ok(framing::ndjson(lines, ctx, inflight_guard))
+    } else if streaming {
         let stream = stream.map(move |response| {
             process_event_converter(EventConverter::from(response), &mut response_collector)
         });
@@ -367,6 +534,9 @@ async fn chat_completions(
         let response = NvCreateChatCompletionResponse::from_annotated_stream(stream.into())
             .await
             .map_err(|e| {
+                if let Some(timeout) = deadline.expired() {
+                    return timeout;
+                }
                 tracing::error!(
                     request_id,
                     "Failed to fold chat completions stream for: {:?}",
@@ -385,13 +555,172 @@ async fn chat_completions(
 
 // todo - abstract this to the top level lib.rs to be reused
 // todo - move the service_observer to its own state/arc
-fn check_ready(_state: &Arc<service_v2::State>) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+pub(super) fn check_ready(
+    _state: &Arc<service_v2::State>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
     // if state.service_observer.stage() != ServiceStage::Ready {
     //     return Err(ErrorResponse::service_unavailable());
     // }
     Ok(())
 }
 
+/// Validate the number of choices and candidates requested
+fn check_choices(
+    n: u8,
+    best_of: u8,
+    streaming: bool,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    choices::validate(n, best_of, streaming).map_err(|message| {
+        ErrorResponse::from_http_error(HttpError {
+            code: StatusCode::BAD_REQUEST.as_u16(),
+            message,
+        })
+    })
+}
+
+/// Validate the `nvext` field and apply the service's policy for unknown keys to it
+pub(super) fn check_nvext(
+    state: &Arc<service_v2::State>,
+    nvext: Option<&mut NvExt>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(nvext) = nvext else {
+        return Ok(());
+    };
+    state.nvext_policy().apply(nvext).map_err(|message| {
+        ErrorResponse::from_http_error(HttpError {
+            code: StatusCode::BAD_REQUEST.as_u16(),
+            message,
+        })
+    })
+}
+
+/// How fast the tokens of a response may be sent, see [`output_rate`]. Call it after the
+/// principal was applied.
+pub(super) fn output_rate_cap(
+    state: &service_v2::State,
+    streaming: bool,
+    nvext: Option<&NvExt>,
+) -> Option<f64> {
+    if !streaming {
+        return None;
+    }
+    state.output_rate().tokens_per_sec(
+        nvext.and_then(|nvext| nvext.principal.as_deref()),
+        nvext.and_then(|nvext| nvext.max_tokens_per_sec),
+    )
+}
+
+/// Set `nvext.priority` from the [`PRIORITY_HEADER`], if the request has one. The header wins
+/// over the body, so that a gateway can assign the priority of its clients. [`apply_principal`]
+/// caps it afterwards.
+pub(super) fn apply_priority_header(
+    headers: &HeaderMap,
+    nvext: &mut Option<NvExt>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(value) = headers.get(PRIORITY_HEADER) else {
+        return Ok(());
+    };
+    let priority = value
+        .to_str()
+        .map_err(|_| format!("{PRIORITY_HEADER} is not valid ASCII"))
+        .and_then(parse_priority)
+        .map_err(|message| {
+            ErrorResponse::from_http_error(HttpError {
+                code: StatusCode::BAD_REQUEST.as_u16(),
+                message,
+            })
+        })?;
+    nvext.get_or_insert_with(NvExt::default).priority = Some(priority);
+    Ok(())
+}
+
+/// Set `nvext.routing.session_id` from the [`SESSION_HEADER`], if the request has one. The
+/// header wins over the body.
+pub(super) fn apply_session_header(
+    headers: &HeaderMap,
+    nvext: &mut Option<NvExt>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(value) = headers.get(SESSION_HEADER) else {
+        return Ok(());
+    };
+    let session_id = match value.to_str() {
+        Ok(session_id) if !session_id.is_empty() && session_id.len() <= MAX_SESSION_ID_LEN => {
+            session_id
+        }
+        _ => {
+            return Err(ErrorResponse::from_http_error(HttpError {
+                code: StatusCode::BAD_REQUEST.as_u16(),
+                message: format!(
+                    "{SESSION_HEADER} must be 1 to {MAX_SESSION_ID_LEN} bytes of visible ASCII"
+                ),
+            }));
+        }
+    };
+    nvext
+        .get_or_insert_with(NvExt::default)
+        .routing
+        .get_or_insert_with(Default::default)
+        .session_id = Some(session_id.to_string());
+    Ok(())
+}
+
+/// Make the handler's span continue the client's trace, from the W3C `traceparent` and
+/// `tracestate` headers or else from `nvext.trace`. See [`dynamo_runtime::otel`].
+pub(super) fn continue_trace(headers: &HeaderMap, nvext: Option<&NvExt>) {
+    continue_trace_from(
+        |name| headers.get(name).and_then(|v| v.to_str().ok()),
+        nvext,
+    );
+}
+
+/// [`continue_trace`] for a frontend whose headers aren't a [`HeaderMap`], like gRPC metadata
+pub(crate) fn continue_trace_from<'a>(
+    header: impl Fn(&str) -> Option<&'a str>,
+    nvext: Option<&NvExt>,
+) {
+    let mut carrier = TraceCarrier::new();
+    for name in ["traceparent", "tracestate"] {
+        if let Some(value) = header(name) {
+            carrier.insert(name.to_string(), value.to_string());
+        }
+    }
+    if !carrier.contains_key("traceparent") {
+        carrier.clear();
+        if let Some(trace) = nvext.and_then(|nvext| nvext.trace.as_ref()) {
+            carrier.insert("traceparent".to_string(), trace.traceparent.clone());
+            if let Some(tracestate) = &trace.tracestate {
+                carrier.insert("tracestate".to_string(), tracestate.clone());
+            }
+        }
+    }
+    otel::set_parent(&tracing::Span::current(), &carrier);
+}
+
+/// Set `nvext.principal` to the API key the request was authenticated with, replacing whatever
+/// the client sent there. Then drop the worker the request is pinned to, unless the
+/// [`NvExtPolicy`](crate::protocols::openai::nvext::NvExtPolicy) lets the principal pin, and
+/// cap the priority at the principal's maximum.
+pub(super) fn apply_principal(
+    state: &service_v2::State,
+    principal: Option<Extension<Principal>>,
+    nvext: &mut Option<NvExt>,
+) {
+    match principal {
+        Some(Extension(Principal(principal))) => {
+            nvext.get_or_insert_with(NvExt::default).principal = Some(principal);
+        }
+        None => {
+            if let Some(nvext) = nvext {
+                nvext.principal = None;
+            }
+        }
+    }
+    if let Some(nvext) = nvext {
+        state.nvext_policy().apply_pinning(nvext);
+        state.nvext_policy().apply_priority(nvext);
+    }
+}
+
 /// openai compatible format
 /// Example:
 /// {
@@ -452,8 +781,12 @@ struct ModelListing {
 /// how we can monitor for disconnects and stop the generation of completions.
 ///
 /// If a disconnect is detected, then the context will issue a `stop_generating` call to the context which will
-/// propagate the cancellation signal to the backend.
-async fn monitor_for_disconnects(
+/// propagate the cancellation signal to the backend. The disconnect is noticed as soon as axum drops the
+/// stream, also while waiting for the next event, so a worker still preparing the first token stops too.
+///
+/// Events are numbered from 0 in their SSE `id`. The transport delivers the responses of a worker exactly
+/// once and in order, so a client can tell from the ids whether it missed or repeated an event.
+pub(super) async fn monitor_for_disconnects(
     stream: Pin<
         Box<dyn Stream<Item = Result<axum::response::sse::Event, axum::Error>> + std::marker::Send>,
     >,
@@ -464,11 +797,25 @@ async fn monitor_for_disconnects(
 
     tokio::spawn(async move {
         let mut stream = stream;
-        while let Some(event) = stream.next().await {
+        let mut seq: u64 = 0;
+        loop {
+            let event = tokio::select! {
+                event = stream.next() => event,
+                _ = tx.closed() => {
+                    tracing::trace!("Client disconnected while waiting for the next event");
+                    context.stop_generating();
+                    return;
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
             let event = match event {
-                Ok(event) => Ok(event),
-                Err(err) => Ok(Event::default().event("error").comment(err.to_string())),
+                Ok(event) => event,
+                Err(err) => Event::default().event("error").comment(err.to_string()),
             };
+            let event = Ok(event.id(seq.to_string()));
+            seq += 1;
 
             if (tx.send(event).await).is_err() {
                 tracing::trace!("Forwarding SSE stream was dropped; breaking loop");
@@ -478,7 +825,8 @@ async fn monitor_for_disconnects(
         }
 
         // Stream completed successfully - mark as ok
-        if tx.send(Ok(Event::default().data("[DONE]"))).await.is_ok() {
+        let done = Event::default().id(seq.to_string()).data("[DONE]");
+        if tx.send(Ok(done)).await.is_ok() {
             inflight_guard.mark_ok();
         }
     });
@@ -499,6 +847,7 @@ fn process_event_converter<T: Serialize>(
     response_collector: &mut ResponseMetricCollector,
 ) -> Result<Event, axum::Error> {
     let annotated = annotated.0;
+    observe_tokens(&annotated, response_collector);
 
     let mut event = Event::default();
 
@@ -516,6 +865,16 @@ fn process_event_converter<T: Serialize>(
         event = event.event(msg);
     }
 
+    if let Some(comments) = annotated.comment {
+        for comment in comments {
+            event = event.comment(comment);
+        }
+    }
+
+    Ok(event)
+}
+
+fn observe_tokens<T>(annotated: &Annotated<T>, response_collector: &mut ResponseMetricCollector) {
     if let Some(osl) = annotated.output_tokens {
         response_collector.observe_current_osl(osl);
     }
@@ -525,14 +884,28 @@ fn process_event_converter<T: Serialize>(
             response_collector.observe_response(isl, chunk_tokens);
         }
     }
+}
 
-    if let Some(comments) = annotated.comment {
-        for comment in comments {
-            event = event.comment(comment);
-        }
-    }
-
-    Ok(event)
+/// The chunks of `stream` as lines of JSON, for [`framing::ndjson`]. Annotations without data,
+/// like comments, have no line. An error event is an `Err` line.
+fn ndjson_lines<T: Serialize + Send + 'static>(
+    stream: impl Stream<Item = Annotated<T>> + Send + 'static,
+    mut response_collector: ResponseMetricCollector,
+) -> BoxStream<'static, Result<String, String>> {
+    stream
+        .filter_map(move |annotated| {
+            observe_tokens(&annotated, &mut response_collector);
+            let line = if annotated.event.as_deref() == Some("error") {
+                let msgs = annotated
+                    .comment
+                    .unwrap_or_else(|| vec!["unspecified error".to_string()]);
+                Some(Err(msgs.join(" -- ")))
+            } else {
+                annotated.data.map(|data| Ok(json_line(&data)))
+            };
+            futures::future::ready(line)
+        })
+        .boxed()
 }
 
 /// Create an Axum [`Router`] for the OpenAI API Completions endpoint
@@ -0,0 +1,209 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Output rate shaping: cap how fast the tokens of a streamed response reach the client.
+//!
+//! Consumers that play the text as it arrives, such as text to speech, can't buffer the bursts
+//! of a fast worker. A request asks for a cap with `nvext.max_tokens_per_sec`. The
+//! [`OutputRateConfig`] of the service gives each API key a cap for requests that don't ask for
+//! one, and a maximum they can't go above.
+//!
+//! A chunk of `n` tokens is held back until `n / rate` seconds after the one before it was sent,
+//! so the stream never bursts, also after the worker stalled. Events without tokens, such as
+//! annotations, are not held back. Only streamed chat completions and completions are shaped,
+//! and the request timeouts still apply while a response is held back.
+//!
+//! The tokens of a chunk are counted by the preprocessor. Engines that take the OpenAI request
+//! themselves, without it, such as `StaticFull` engines in `dynamo-run`, are not shaped.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use dynamo_runtime::engine::{AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// The lowest cap, in tokens per second. Below it a chunk would be held back for days.
+pub const MIN_TOKENS_PER_SEC: f64 = 0.1;
+
+/// Output rate caps of one API key, in tokens per second. None means uncapped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputRatePolicy {
+    /// The cap of requests that don't set `nvext.max_tokens_per_sec`
+    pub tokens_per_sec: Option<f64>,
+
+    /// The highest cap, also of requests that ask for more
+    pub max_tokens_per_sec: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputRateConfig {
+    /// Applies to requests without an API key, or with one that isn't listed
+    pub default: OutputRatePolicy,
+
+    /// By the principal of the API key, `key-` and the start of its hash, as in the usage records
+    pub per_key: HashMap<String, OutputRatePolicy>,
+}
+
+impl OutputRateConfig {
+    /// Read the configuration from a JSON file, e.g.
+    /// `{"default": {"max_tokens_per_sec": 200}, "per_key": {"key-3b9a5c21f0e4": {"tokens_per_sec": 25}}}`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("Failed reading output rates from {}: {err}", path.display())
+        })?;
+        let config: Self = serde_json::from_str(&contents)
+            .map_err(|err| anyhow::anyhow!("Invalid output rates in {}: {err}", path.display()))?;
+        config
+            .validate()
+            .map_err(|err| anyhow::anyhow!("Invalid output rates in {}: {err}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Check that every cap is at least [`MIN_TOKENS_PER_SEC`]
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let policies = std::iter::once(("default", &self.default)).chain(
+            self.per_key
+                .iter()
+                .map(|(key, policy)| (key.as_str(), policy)),
+        );
+        for (key, policy) in policies {
+            for rate in [policy.tokens_per_sec, policy.max_tokens_per_sec]
+                .into_iter()
+                .flatten()
+            {
+                if !rate.is_finite() || rate < MIN_TOKENS_PER_SEC {
+                    anyhow::bail!(
+                        "The output rate of {key} must be at least {MIN_TOKENS_PER_SEC} tokens per second, got {rate}"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The cap, in tokens per second, of a request from `principal` that asked for `requested`
+    pub fn tokens_per_sec(&self, principal: Option<&str>, requested: Option<f64>) -> Option<f64> {
+        let policy = principal
+            .and_then(|principal| self.per_key.get(principal))
+            .unwrap_or(&self.default);
+        match (
+            requested.or(policy.tokens_per_sec),
+            policy.max_tokens_per_sec,
+        ) {
+            (Some(rate), Some(max)) => Some(rate.min(max)),
+            (rate, max) => rate.or(max),
+        }
+    }
+}
+
+/// Hold back the responses of `stream` so that their tokens go out at most at `tokens_per_sec`
+pub(super) fn track<T: Send + 'static>(
+    tokens_per_sec: Option<f64>,
+    stream: ManyOut<Annotated<T>>,
+) -> ManyOut<Annotated<T>> {
+    let Some(tokens_per_sec) = tokens_per_sec.filter(|rate| *rate > 0.0) else {
+        return stream;
+    };
+    let tokens_per_sec = tokens_per_sec.max(MIN_TOKENS_PER_SEC);
+    let ctx = stream.context();
+    let shaped = async_stream::stream! {
+        let mut stream = stream;
+        // When the next chunk may go out
+        let mut next = Instant::now();
+        while let Some(annotated) = stream.next().await {
+            let tokens = annotated.chunk_tokens.unwrap_or(0);
+            if tokens > 0 {
+                tokio::time::sleep_until(next).await;
+                let start = next.max(Instant::now());
+                // Out of range only for absurd chunks, which then go out as they come
+                next = Duration::try_from_secs_f64(tokens as f64 / tokens_per_sec)
+                    .ok()
+                    .and_then(|interval| start.checked_add(interval))
+                    .unwrap_or(start);
+            }
+            yield annotated;
+        }
+    };
+    ResponseStream::new(Box::pin(shaped), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::pipeline::context::Controller;
+    use std::sync::Arc;
+
+    fn chunk(tokens: usize) -> Annotated<String> {
+        Annotated {
+            chunk_tokens: Some(tokens),
+            ..Annotated::from_data("text".to_string())
+        }
+    }
+
+    #[test]
+    fn test_tokens_per_sec() {
+        let config: OutputRateConfig = serde_json::from_str(
+            r#"{"default": {"max_tokens_per_sec": 100}, "per_key": {"key-a": {"tokens_per_sec": 20, "max_tokens_per_sec": 50}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.tokens_per_sec(None, None), Some(100.0));
+        assert_eq!(config.tokens_per_sec(None, Some(30.0)), Some(30.0));
+        assert_eq!(
+            config.tokens_per_sec(Some("key-b"), Some(300.0)),
+            Some(100.0)
+        );
+        assert_eq!(config.tokens_per_sec(Some("key-a"), None), Some(20.0));
+        assert_eq!(config.tokens_per_sec(Some("key-a"), Some(80.0)), Some(50.0));
+
+        assert!(config.validate().is_ok());
+        let tiny: OutputRateConfig =
+            serde_json::from_str(r#"{"per_key": {"key-a": {"tokens_per_sec": 1e-300}}}"#).unwrap();
+        assert!(tiny.validate().is_err());
+
+        let uncapped = OutputRateConfig::default();
+        assert_eq!(uncapped.tokens_per_sec(Some("key-a"), None), None);
+        assert_eq!(uncapped.tokens_per_sec(None, Some(10.0)), Some(10.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_track() {
+        let responses = vec![
+            chunk(1),
+            chunk(4),
+            Annotated::from_annotation("worker_instance_id", &1).unwrap(),
+            chunk(2),
+        ];
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(responses)),
+            Arc::new(Controller::default()),
+        );
+        let start = Instant::now();
+        let mut shaped = track(Some(10.0), stream);
+        let mut sent_at = vec![];
+        while shaped.next().await.is_some() {
+            sent_at.push((Instant::now() - start).as_millis());
+        }
+        // 1 token, then 4 after 0.1s, the annotation right away and 2 after 0.4s more
+        assert_eq!(sent_at, vec![0, 100, 100, 500]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_track_tiny_rate() {
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(vec![chunk(1), chunk(1)])),
+            Arc::new(Controller::default()),
+        );
+        let start = Instant::now();
+        let mut shaped = track(Some(1e-300), stream);
+        while shaped.next().await.is_some() {}
+        // Held back as with the lowest cap, without overflowing
+        assert_eq!((Instant::now() - start).as_secs(), 10);
+    }
+}
@@ -0,0 +1,39 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal single page chat UI, for demos on a single box.
+//!
+//! It lists the models from `/v1/models` and streams from `/v1/chat/completions`, so it only works
+//! if the chat endpoints are enabled. Disabled by default, enable with
+//! [`super::service_v2::HttpServiceConfigBuilder::enable_playground`].
+//!
+//! The page itself doesn't need an API key. With API keys enabled, enter one in the page, it is
+//! sent with every request it makes.
+
+use axum::{
+    http::{header, Method},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+use super::RouteDoc;
+
+const PLAYGROUND_HTML: &str = include_str!("static/playground.html");
+
+/// Where the page is served by default
+pub(crate) const PLAYGROUND_PATH: &str = "/playground";
+
+pub fn playground_router(path: Option<String>) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or_else(|| PLAYGROUND_PATH.to_string());
+    let doc = RouteDoc::new(Method::GET, &path);
+    let router = Router::new().route(&path, get(playground_handler));
+    (vec![doc], router)
+}
+
+async fn playground_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        PLAYGROUND_HTML,
+    )
+}
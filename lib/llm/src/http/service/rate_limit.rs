@@ -0,0 +1,545 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Request rate and concurrency limits.
+//!
+//! Limits apply globally, to each API key, and to each model (the `model` field of the request
+//! body, or the model the routing policy sends it to if that has no limits of its own). Per key limits need API keys, they use the [`Principal`] the auth middleware found, so
+//! made up keys can't each get a fresh allowance. A request must fit within all of them. One that
+//! doesn't is rejected with a `429 Too Many Requests`, an OpenAI style error body and a
+//! `Retry-After` header, without using up any of the other limits.
+//!
+//! Requests per minute use a token bucket, so clients can burst up to a minute's worth of
+//! requests. A concurrency slot is held until the response, including a streamed one, ends.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::auth::{Principal, OPEN_PATHS};
+use super::error::openai_error_response;
+use super::service_v2;
+use crate::discovery::ModelManager;
+
+/// Largest request body we will read to find the model
+const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+
+/// Forget idle API keys once we track this many
+const PRUNE_BUCKETS_ABOVE: usize = 10_000;
+
+/// Never track more than this many, the least recently used are forgotten first
+const MAX_BUCKETS: usize = 2 * PRUNE_BUCKETS_ABOVE;
+
+/// What we tell clients rejected for concurrency to wait, we can't know when a slot frees up
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Limits for one scope. None means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub max_concurrent: Option<u32>,
+}
+
+impl RateLimit {
+    fn is_unlimited(&self) -> bool {
+        self.requests_per_minute.is_none() && self.max_concurrent.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Shared by all requests
+    pub global: RateLimit,
+
+    /// Applies to each API key separately
+    pub per_key: RateLimit,
+
+    /// Applies to each model separately, by model name. Models not listed are unlimited.
+    pub per_model: HashMap<String, RateLimit>,
+}
+
+impl RateLimitConfig {
+    /// Read the configuration from a JSON file, e.g.
+    /// `{"global": {"max_concurrent": 64}, "per_key": {"requests_per_minute": 600}, "per_model": {"llama": {"max_concurrent": 8}}}`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("Failed reading rate limits from {}: {err}", path.display())
+        })?;
+        serde_json::from_str(&contents)
+            .map_err(|err| anyhow::anyhow!("Invalid rate limits in {}: {err}", path.display()))
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.global.is_unlimited()
+            && self.per_key.is_unlimited()
+            && self.per_model.values().all(RateLimit::is_unlimited)
+    }
+}
+
+/// Refills continuously at `rate` tokens per second up to `capacity`
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(requests: u32, now: Instant) -> Self {
+        TokenBucket {
+            capacity: requests as f64,
+            tokens: requests as f64,
+            rate: requests as f64 / 60.0,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// How long until a token is available. Zero if one is available now.
+    fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else if self.rate <= 0.0 {
+            // A limit of 0 requests per minute
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Scope {
+    Global,
+    /// The [`Principal`] of the API key, so keys are not kept in memory
+    Key(String),
+    Model(String),
+}
+
+impl Scope {
+    fn describe(&self) -> String {
+        match self {
+            Scope::Global => "this service".to_string(),
+            Scope::Key(_) => "your API key".to_string(),
+            Scope::Model(model) => format!("model {model}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    buckets: HashMap<Scope, TokenBucket>,
+    in_flight: HashMap<Scope, u32>,
+}
+
+impl LimiterState {
+    /// The bucket of `scope`, a full one if it has none yet
+    fn bucket(
+        &mut self,
+        scope: &Scope,
+        requests_per_minute: u32,
+        now: Instant,
+    ) -> &mut TokenBucket {
+        if !self.buckets.contains_key(scope) {
+            if self.buckets.len() > PRUNE_BUCKETS_ABOVE {
+                // A full bucket is the same as a new one
+                self.buckets.retain(|_, bucket| {
+                    bucket.refill(now);
+                    bucket.tokens < bucket.capacity
+                });
+            }
+            while self.buckets.len() >= MAX_BUCKETS {
+                let Some(oldest) = self
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(scope, _)| scope.clone())
+                else {
+                    break;
+                };
+                self.buckets.remove(&oldest);
+            }
+        }
+        self.buckets
+            .entry(scope.clone())
+            .or_insert_with(|| TokenBucket::per_minute(requests_per_minute, now))
+    }
+}
+
+/// Tracks usage against a [`RateLimitConfig`]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<LimiterState>,
+}
+
+/// Why a request was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub message: String,
+    pub retry_after: Duration,
+}
+
+/// Holds the concurrency slots of a request, released on drop
+pub struct Permit {
+    limiter: Arc<RateLimiter>,
+    scopes: Vec<Scope>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        for scope in &self.scopes {
+            if let Some(count) = state.in_flight.get_mut(scope) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    state.in_flight.remove(scope);
+                }
+            }
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Arc<Self> {
+        Arc::new(RateLimiter {
+            config,
+            state: Mutex::new(LimiterState::default()),
+        })
+    }
+
+    fn needs_model(&self) -> bool {
+        !self.config.per_model.is_empty()
+    }
+
+    /// Admit a request, or say why not. Nothing is used up unless every limit allows it.
+    fn acquire(
+        self: &Arc<Self>,
+        principal: Option<&Principal>,
+        model: Option<&str>,
+    ) -> Result<Permit, Rejection> {
+        let mut scopes = vec![(Scope::Global, self.config.global)];
+        if let Some(principal) = principal {
+            scopes.push((Scope::Key(principal.0.clone()), self.config.per_key));
+        }
+        if let Some((model, limit)) = model.and_then(|m| self.config.per_model.get_key_value(m)) {
+            scopes.push((Scope::Model(model.clone()), *limit));
+        }
+        scopes.retain(|(_, limit)| !limit.is_unlimited());
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // Check everything first
+        for (scope, limit) in &scopes {
+            if let Some(max) = limit.max_concurrent {
+                if state.in_flight.get(scope).copied().unwrap_or(0) >= max {
+                    return Err(Rejection {
+                        message: format!(
+                            "Too many concurrent requests for {}, limit is {max}.",
+                            scope.describe()
+                        ),
+                        retry_after: CONCURRENCY_RETRY_AFTER,
+                    });
+                }
+            }
+            if let Some(rpm) = limit.requests_per_minute {
+                let wait = state.bucket(scope, rpm, now).wait_time(now);
+                if !wait.is_zero() {
+                    return Err(Rejection {
+                        message: format!(
+                            "Rate limit reached for {}, limit is {rpm} requests per minute.",
+                            scope.describe()
+                        ),
+                        retry_after: wait,
+                    });
+                }
+            }
+        }
+
+        // Then use them up
+        let mut held = vec![];
+        for (scope, limit) in scopes {
+            if limit.requests_per_minute.is_some() {
+                if let Some(bucket) = state.buckets.get_mut(&scope) {
+                    bucket.take();
+                }
+            }
+            if limit.max_concurrent.is_some() {
+                *state.in_flight.entry(scope.clone()).or_default() += 1;
+                held.push(scope);
+            }
+        }
+        Ok(Permit {
+            limiter: self.clone(),
+            scopes: held,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ModelField {
+    model: Option<String>,
+}
+
+fn too_many_requests(rejection: Rejection) -> Response {
+    let mut response = openai_error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        &rejection.message,
+        "requests",
+        "rate_limit_exceeded",
+    );
+    // Whole seconds, rounded up so a client that waits exactly this long gets in
+    let secs = rejection
+        .retry_after
+        .as_secs_f64()
+        .ceil()
+        .min(u32::MAX as f64) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(secs.max(1)));
+    response
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state((Arc<RateLimiter>, Arc<service_v2::State>), rate_limit_middleware)`.
+pub async fn rate_limit_middleware(
+    State((limiter, state)): State<(Arc<RateLimiter>, Arc<service_v2::State>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if OPEN_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    // Set by the auth middleware, which runs first
+    let principal = request.extensions().get::<Principal>().cloned();
+
+    // Only read the body if there are per model limits
+    let (request, model) = if limiter.needs_model() {
+        match read_model(request).await {
+            Ok((request, model)) => {
+                let model = model.map(|model| {
+                    limited_model(state.manager(), model, |name| {
+                        limiter.config.per_model.contains_key(name)
+                    })
+                });
+                (request, model)
+            }
+            Err(response) => return response,
+        }
+    } else {
+        (request, None)
+    };
+
+    let permit = match limiter.acquire(principal.as_ref(), model.as_deref()) {
+        Ok(permit) => permit,
+        Err(rejection) => {
+            tracing::debug!(
+                message = rejection.message,
+                "Request rejected by rate limit"
+            );
+            return too_many_requests(rejection);
+        }
+    };
+
+    let response = next.run(request).await;
+    if permit.scopes.is_empty() {
+        return response;
+    }
+
+    // Keep the concurrency slots until the body, which may be an SSE stream, is done
+    hold_until_done(response, permit)
+}
+
+/// The `model` field of a POST request's JSON body, and the request to pass on. Answers with a
+/// `413 Payload Too Large` if the body is too large to read.
+pub(super) async fn read_model(request: Request) -> Result<(Request, Option<String>), Response> {
+    if request.method() != Method::POST {
+        return Ok((request, None));
+    }
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_REQUEST_BYTES)
+        .await
+        .map_err(|err| {
+            openai_error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &err.to_string(),
+                "invalid_request_error",
+                "request_too_large",
+            )
+        })?;
+    // Invalid JSON is reported by the handler
+    let model = serde_json::from_slice::<ModelField>(&body)
+        .ok()
+        .and_then(|m| m.model);
+    Ok((Request::from_parts(parts, Body::from(body)), model))
+}
+
+/// The name the per model limits of a request for `model` are under: its own if it has limits,
+/// otherwise the model that aliases, splits and the default model of the routing policy send it
+/// to. The handler resolves a split again, so its requests are shared by weight only on average.
+pub(super) fn limited_model(
+    manager: &ModelManager,
+    model: String,
+    is_limited: impl Fn(&str) -> bool,
+) -> String {
+    if is_limited(&model) {
+        return model;
+    }
+    manager.resolve_model(&model)
+}
+
+/// Drop `guard` once the body of `response` was sent, or the client went away
+pub(super) fn hold_until_done<T: Send + 'static>(response: Response, guard: T) -> Response {
+    let (parts, body) = response.into_parts();
+    let mut upstream = body.into_data_stream();
+    let stream = async_stream::stream! {
+        let _guard = guard;
+        while let Some(chunk) = upstream.next().await {
+            yield chunk;
+        }
+    };
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(rpm: Option<u32>, concurrent: Option<u32>) -> RateLimit {
+        RateLimit {
+            requests_per_minute: rpm,
+            max_concurrent: concurrent,
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::per_minute(2, start);
+        assert!(bucket.wait_time(start).is_zero());
+        bucket.take();
+        bucket.take();
+        let wait = bucket.wait_time(start);
+        assert!(wait > Duration::from_secs(29) && wait < Duration::from_secs(31));
+        assert!(bucket.wait_time(start + Duration::from_secs(31)).is_zero());
+
+        let mut never = TokenBucket::per_minute(0, start);
+        assert_eq!(never.wait_time(start), Duration::MAX);
+    }
+
+    #[test]
+    fn test_concurrency() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_key: limit(None, Some(1)),
+            ..Default::default()
+        });
+        let a = Principal::from_key("a");
+        let permit = limiter.acquire(Some(&a), None).unwrap();
+        assert!(limiter.acquire(Some(&a), None).is_err());
+        // Other keys and anonymous requests have their own limits
+        assert!(limiter
+            .acquire(Some(&Principal::from_key("b")), None)
+            .is_ok());
+        assert!(limiter.acquire(None, None).is_ok());
+        drop(permit);
+        assert!(limiter.acquire(Some(&a), None).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_cap() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_key: limit(Some(1), None),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let empty = |updated| TokenBucket {
+            tokens: 0.0,
+            updated,
+            ..TokenBucket::per_minute(1, now)
+        };
+        let oldest = Scope::Key("key-oldest".to_string());
+        {
+            // Keys that used their token, so pruning can't forget them
+            let mut state = limiter.state.lock().unwrap();
+            for n in 1..MAX_BUCKETS {
+                state
+                    .buckets
+                    .insert(Scope::Key(format!("key-{n}")), empty(now));
+            }
+            let earlier = now.checked_sub(Duration::from_secs(10)).unwrap_or(now);
+            state.buckets.insert(oldest.clone(), empty(earlier));
+        }
+        let principal = Principal("key-new".to_string());
+        assert!(limiter.acquire(Some(&principal), None).is_ok());
+        let state = limiter.state.lock().unwrap();
+        assert_eq!(state.buckets.len(), MAX_BUCKETS);
+        assert!(!state.buckets.contains_key(&oldest));
+    }
+
+    #[test]
+    fn test_rejection_uses_nothing() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            global: limit(Some(1), None),
+            per_model: HashMap::from([("m".to_string(), limit(None, Some(0)))]),
+            ..Default::default()
+        });
+        // Rejected by the model limit, so the global token is still there
+        let rejection = limiter.acquire(None, Some("m")).err().unwrap();
+        assert_eq!(rejection.retry_after, CONCURRENCY_RETRY_AFTER);
+        assert!(limiter.acquire(None, Some("other")).is_ok());
+        let rejection = limiter.acquire(None, Some("other")).err().unwrap();
+        assert!(rejection.message.contains("1 requests per minute"));
+    }
+
+    #[test]
+    fn test_limited_model() {
+        let manager = ModelManager::new();
+        manager.set_routing_policy(crate::discovery::routing::RoutingPolicy {
+            aliases: HashMap::from([("gpt".to_string(), "llama".to_string())]),
+            ..Default::default()
+        });
+        let limited = |name: &str| name == "llama";
+        assert_eq!(limited_model(&manager, "gpt".to_string(), limited), "llama");
+        assert_eq!(
+            limited_model(&manager, "other".to_string(), limited),
+            "other"
+        );
+        // Its own limits come first
+        let limited = |name: &str| name == "gpt";
+        assert_eq!(limited_model(&manager, "gpt".to_string(), limited), "gpt");
+    }
+
+    #[test]
+    fn test_config_file() {
+        let config: RateLimitConfig = serde_json::from_str(
+            r#"{"global": {"max_concurrent": 64}, "per_model": {"llama": {"requests_per_minute": 10}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.global, limit(None, Some(64)));
+        assert!(config.per_key.is_unlimited());
+        assert_eq!(config.per_model["llama"], limit(Some(10), None));
+        assert!(!config.is_unlimited());
+    }
+}
@@ -0,0 +1,652 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cache of responses to identical deterministic requests.
+//!
+//! Evaluation harnesses and agents often send the same greedy request many times. A non-streaming
+//! `/v1/chat/completions` or `/v1/completions` request is cached if it is deterministic: it has
+//! `temperature` 0, or a `seed`. The key is the path, the model, and a hash of the caller's
+//! principal and of the request body with its keys sorted, which includes the seed, so that
+//! callers never get each other's responses. Only successful responses are stored.
+//!
+//! Responses served from the cache have the `x-dynamo-cache: hit` header, others `miss`. A request
+//! with `Cache-Control: no-cache` skips the lookup, its response still refreshes the cache. A hit
+//! goes to the usage accounting and the audit log like a generated response, with the token counts
+//! of the cached `usage`.
+//!
+//! A hit still goes through the `on_request` hook of the [`super::interceptor`]s first. If one
+//! rejects the request, so is the hit. If one changes the request, it is not served from the
+//! cache but generated from what the interceptors let through.
+//!
+//! The entries live in a [`CacheBackend`]: [`InMemoryCache`], private to this process, or with the
+//! `redis` feature a `RedisCache` shared by every ingress. A backend that fails is logged and
+//! treated as a miss, requests don't fail because of the cache.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dynamo_runtime::engine::ResponseStream;
+use dynamo_runtime::pipeline::context::Controller;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use super::auth::Principal;
+use super::error::HttpError;
+use super::interceptor::{self, Intercept, InterceptContext};
+use super::metrics::Endpoint;
+use super::openai::{self, ErrorResponse};
+use super::service_v2;
+use super::usage::{self, UsageTracker};
+use crate::audit::{self, AuditResponse, AuditTrail, AuditedResponse};
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
+use crate::protocols::openai::completions::NvCreateCompletionRequest;
+use crate::protocols::openai::nvext::NvExt;
+
+pub const CACHE_STATUS_HEADER: HeaderName = HeaderName::from_static("x-dynamo-cache");
+
+/// Largest request body we will read to compute the key
+const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+
+/// Don't cache responses larger than this
+const MAX_CACHED_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// Request fields that don't change the response
+const IGNORED_FIELDS: &[&str] = &["stream", "user"];
+
+/// Where cached responses are stored
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+
+    /// Store `body` under `key` until `ttl` elapsed. The backend may evict it earlier.
+    async fn put(&self, key: &str, body: Bytes, ttl: Duration) -> anyhow::Result<()>;
+}
+
+/// Response cache shared by the HTTP handlers
+#[derive(Clone)]
+pub struct ResponseCache {
+    backend: Arc<dyn CacheBackend>,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(backend: Arc<dyn CacheBackend>, ttl: Duration) -> Self {
+        ResponseCache { backend, ttl }
+    }
+
+    /// A cache in this process, holding at most `max_entries` responses and `max_bytes` of
+    /// response bodies
+    pub fn in_memory(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        Self::new(Arc::new(InMemoryCache::new(max_entries, max_bytes)), ttl)
+    }
+
+    /// A cache in Redis at `url`, e.g. `redis://127.0.0.1:6379`, shared with other ingresses
+    #[cfg(feature = "redis")]
+    pub async fn redis(url: &str, ttl: Duration) -> anyhow::Result<Self> {
+        Ok(Self::new(Arc::new(RedisCache::connect(url).await?), ttl))
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    body: Bytes,
+    expires_at: Instant,
+    /// Value of [`InMemoryState::clock`] when last read or written
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct InMemoryState {
+    entries: HashMap<String, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl InMemoryState {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.body.len();
+        }
+    }
+
+    /// Drop expired entries, then the least recently used ones until there is room for `needed`
+    /// more bytes
+    fn evict(&mut self, max_entries: usize, max_bytes: usize, needed: usize) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.remove(&key);
+        }
+        while !self.entries.is_empty()
+            && (self.entries.len() >= max_entries || self.bytes + needed > max_bytes)
+        {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                self.remove(&key);
+            }
+        }
+    }
+}
+
+/// [`CacheBackend`] in this process, evicting the least recently used responses when full
+#[derive(Debug)]
+pub struct InMemoryCache {
+    max_entries: usize,
+    max_bytes: usize,
+    state: Mutex<InMemoryState>,
+}
+
+impl InMemoryCache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        InMemoryCache {
+            max_entries,
+            max_bytes,
+            state: Mutex::new(InMemoryState::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for InMemoryCache {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.clock += 1;
+        match state.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.last_used = state.clock;
+                Ok(Some(entry.body.clone()))
+            }
+            Some(_) => {
+                state.remove(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, key: &str, body: Bytes, ttl: Duration) -> anyhow::Result<()> {
+        if self.max_entries == 0 || body.len() > self.max_bytes {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        state.remove(key);
+        state.evict(self.max_entries, self.max_bytes, body.len());
+        state.clock += 1;
+        state.bytes += body.len();
+        let entry = Entry {
+            body,
+            expires_at: Instant::now() + ttl,
+            last_used: state.clock,
+        };
+        state.entries.insert(key.to_string(), entry);
+        Ok(())
+    }
+}
+
+/// [`CacheBackend`] in Redis. Redis expires the entries, configure its `maxmemory-policy` to
+/// evict when full.
+#[cfg(feature = "redis")]
+pub struct RedisCache {
+    connection: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCache {
+    const KEY_PREFIX: &'static str = "dynamo:response_cache:";
+
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_connection_manager().await?;
+        Ok(RedisCache { connection })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl CacheBackend for RedisCache {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        let body: Option<Vec<u8>> = connection.get(format!("{}{key}", Self::KEY_PREFIX)).await?;
+        Ok(body.map(Bytes::from))
+    }
+
+    async fn put(&self, key: &str, body: Bytes, ttl: Duration) -> anyhow::Result<()> {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        let _: () = connection
+            .set_ex(
+                format!("{}{key}", Self::KEY_PREFIX),
+                body.as_ref(),
+                ttl.as_secs().max(1),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// The cache key of a request to `path` from `principal`, or None if its response must not be
+/// cached
+fn cache_key(path: &str, principal: Option<&str>, body: &[u8]) -> Option<String> {
+    if path != "/v1/chat/completions" && path != "/v1/completions" {
+        return None;
+    }
+    let Ok(Value::Object(mut request)) = serde_json::from_slice::<Value>(body) else {
+        // Not ours to reject, the handler reports it
+        return None;
+    };
+    if request.get("stream").and_then(Value::as_bool) == Some(true) {
+        return None;
+    }
+    let greedy = request.get("temperature").and_then(Value::as_f64) == Some(0.0);
+    let seeded = request.get("seed").is_some_and(|seed| !seed.is_null());
+    if !greedy && !seeded {
+        return None;
+    }
+    let model = request.get("model")?.as_str()?.to_string();
+    for field in IGNORED_FIELDS {
+        request.remove(*field);
+    }
+    // Only part of the hash, so that a shared cache doesn't list who asked what
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(principal.unwrap_or_default().as_bytes());
+    hasher.update(b"\n");
+    let mut normalized = Vec::with_capacity(body.len());
+    write_sorted(&Value::Object(request), &mut normalized);
+    hasher.update(&normalized);
+    Some(format!("{path}:{model}:{}", hasher.finalize()))
+}
+
+/// Serialize `value` with the keys of every object sorted, so that the same request always has
+/// the same key
+fn write_sorted(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push(b'{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                // Serializing a string can't fail
+                serde_json::to_writer(&mut *out, key).unwrap();
+                out.push(b':');
+                write_sorted(&map[key], out);
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_sorted(item, out);
+            }
+            out.push(b']');
+        }
+        scalar => serde_json::to_writer(&mut *out, scalar).unwrap(),
+    }
+}
+
+/// Axum middleware. Install with
+/// `axum::middleware::from_fn_with_state((ResponseCache, Arc<State>), response_cache_middleware)`.
+pub async fn response_cache_middleware(
+    State((cache, state)): State<(ResponseCache, Arc<service_v2::State>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let no_cache = request
+        .headers()
+        .get(header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("no-cache"));
+    let path = request.uri().path().to_string();
+    let principal = request.extensions().get::<Principal>().cloned();
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_REQUEST_BYTES).await {
+        Ok(body) => body,
+        Err(err) => {
+            return ErrorResponse::from_http_error(HttpError {
+                code: StatusCode::PAYLOAD_TOO_LARGE.as_u16(),
+                message: err.to_string(),
+            })
+            .into_response();
+        }
+    };
+    let key = cache_key(&path, principal.as_ref().map(|p| p.0.as_str()), &body);
+    let Some(key) = key else {
+        return next.run(Request::from_parts(parts, Body::from(body))).await;
+    };
+
+    // A response generated from a request the interceptors changed doesn't answer this key
+    let mut cacheable = true;
+    if !no_cache {
+        match cache.backend.get(&key).await {
+            Ok(Some(cached)) => {
+                match intercept_hit(&state, &path, principal.clone(), &body).await {
+                    Ok(true) => {
+                        tracing::debug!(key, "Serving response from cache");
+                        track_hit(&state, &path, principal, &body, &cached).await;
+                        return cached_response(cached);
+                    }
+                    Ok(false) => {
+                        tracing::debug!(
+                            key,
+                            "Interceptors changed the request, not using the cache"
+                        );
+                        cacheable = false;
+                    }
+                    Err(response) => return response,
+                }
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!(%err, "Response cache lookup failed"),
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::warn!(%err, "Failed reading response to cache");
+            return ErrorResponse::from_http_error(HttpError {
+                code: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                message: err.to_string(),
+            })
+            .into_response();
+        }
+    };
+    if cacheable && body.len() <= MAX_CACHED_RESPONSE_BYTES {
+        let body = body.clone();
+        tokio::spawn(async move {
+            if let Err(err) = cache.backend.put(&key, body, cache.ttl).await {
+                tracing::warn!(%err, "Failed storing response in cache");
+            }
+        });
+    }
+    parts
+        .headers
+        .insert(CACHE_STATUS_HEADER, HeaderValue::from_static("miss"));
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Pass the request of a cache hit through the interceptors, as the handler would. True if they
+/// let it through unchanged, false if they changed it, and the response if one rejected it.
+async fn intercept_hit(
+    state: &service_v2::State,
+    path: &str,
+    principal: Option<Principal>,
+    body: &[u8],
+) -> Result<bool, Response> {
+    if state.interceptors().is_empty() {
+        return Ok(true);
+    }
+    if path == "/v1/chat/completions" {
+        intercept_copy::<NvCreateChatCompletionRequest>(
+            state,
+            Endpoint::ChatCompletions,
+            principal,
+            body,
+            |request| (&request.inner.model, &mut request.nvext),
+        )
+        .await
+    } else {
+        intercept_copy::<NvCreateCompletionRequest>(
+            state,
+            Endpoint::Completions,
+            principal,
+            body,
+            |request| (&request.inner.model, &mut request.nvext),
+        )
+        .await
+    }
+}
+
+async fn intercept_copy<R: Intercept + Serialize + DeserializeOwned>(
+    state: &service_v2::State,
+    endpoint: Endpoint,
+    principal: Option<Principal>,
+    body: &[u8],
+    fields: impl Fn(&mut R) -> (&String, &mut Option<NvExt>),
+) -> Result<bool, Response> {
+    // Invalid requests are reported by the handler
+    let Ok(mut request) = serde_json::from_slice::<R>(body) else {
+        return Ok(false);
+    };
+    let (model, nvext) = fields(&mut request);
+    let model = model.clone();
+    openai::apply_principal(state, principal.map(axum::Extension), nvext);
+    let ctx = InterceptContext::new(
+        &uuid::Uuid::new_v4().to_string(),
+        &model,
+        endpoint,
+        nvext.as_ref(),
+    );
+    let before = serde_json::to_value(&request).ok();
+    interceptor::intercept(state.interceptors(), &ctx, &mut request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    Ok(before.is_some() && before == serde_json::to_value(&request).ok())
+}
+
+/// The first choice of a cached chat or text completion, for the audit log
+struct CachedResponse(Value);
+
+impl AuditResponse for CachedResponse {
+    fn audit(&self, response: &mut AuditedResponse) {
+        let Some(choice) = self.0.pointer("/choices/0") else {
+            return;
+        };
+        let text = choice
+            .pointer("/message/content")
+            .or_else(|| choice.get("text"))
+            .and_then(Value::as_str);
+        if let Some(text) = text {
+            response.text.push_str(text);
+        }
+        if let Some(reason) = choice.get("finish_reason").and_then(Value::as_str) {
+            response.finish_reason = Some(reason.to_string());
+        }
+    }
+}
+
+/// Account for and audit a response served from the cache, as the handlers do for the responses
+/// they generate
+async fn track_hit(
+    state: &service_v2::State,
+    path: &str,
+    principal: Option<Principal>,
+    request: &[u8],
+    response: &[u8],
+) {
+    if state.usage_accounting().is_none() && state.audit_logger().is_none() {
+        return;
+    }
+    let (Ok(mut request), Ok(response)) = (
+        serde_json::from_slice::<Value>(request),
+        serde_json::from_slice::<Value>(response),
+    ) else {
+        return;
+    };
+    let endpoint = if path == "/v1/chat/completions" {
+        Endpoint::ChatCompletions
+    } else {
+        Endpoint::Completions
+    };
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let mut nvext: Option<NvExt> = request
+        .get("nvext")
+        .and_then(|nvext| serde_json::from_value(nvext.clone()).ok());
+    openai::apply_principal(state, principal.map(axum::Extension), &mut nvext);
+    if let (Some(request), Some(nvext)) = (request.as_object_mut(), &nvext) {
+        if let Ok(nvext) = serde_json::to_value(nvext) {
+            request.insert("nvext".to_string(), nvext);
+        }
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let audit = AuditTrail::start(
+        state.audit_logger(),
+        &request_id,
+        endpoint.as_str(),
+        &request,
+    );
+    let usage = UsageTracker::start(
+        state.usage_accounting(),
+        &request_id,
+        &model,
+        endpoint,
+        false,
+        &mut nvext,
+    );
+    let tokens = |pointer: &str| {
+        response
+            .pointer(pointer)
+            .and_then(Value::as_u64)
+            .map(|n| n as usize)
+    };
+    let input_tokens = tokens("/usage/prompt_tokens");
+    let chunk_tokens = tokens("/usage/completion_tokens");
+    let annotated = Annotated {
+        input_tokens,
+        chunk_tokens,
+        ..Annotated::from_data(CachedResponse(response))
+    };
+    let stream = ResponseStream::new(
+        Box::pin(futures::stream::iter([annotated])),
+        Arc::new(Controller::default()),
+    );
+    let stream = usage::track(usage, stream);
+    let stream = audit::track(audit, stream);
+    stream.for_each(|_| async {}).await;
+}
+
+fn cached_response(body: Bytes) -> Response {
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (CACHE_STATUS_HEADER, HeaderValue::from_static("hit")),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_key() {
+        let path = "/v1/chat/completions";
+        let a =
+            br#"{"model": "m", "temperature": 0, "messages": [{"role": "user", "content": "hi"}]}"#;
+        let b = br#"{"messages": [{"content": "hi", "role": "user"}], "temperature": 0.0, "model": "m", "stream": false}"#;
+        let key = cache_key(path, None, a).unwrap();
+        assert!(key.starts_with("/v1/chat/completions:m:"));
+        assert_eq!(key, cache_key(path, None, b).unwrap());
+
+        let seeded = br#"{"model": "m", "seed": 1, "messages": []}"#;
+        let other_seed = br#"{"model": "m", "seed": 2, "messages": []}"#;
+        assert_ne!(
+            cache_key(path, None, seeded),
+            cache_key(path, None, other_seed)
+        );
+
+        // Callers don't share responses, and their API key isn't in the key
+        let alice = cache_key(path, Some("key-alice"), a).unwrap();
+        assert_ne!(alice, key);
+        assert_ne!(alice, cache_key(path, Some("key-bob"), a).unwrap());
+        assert!(!alice.contains("key-alice"));
+
+        // Not deterministic, streaming, or not a generation endpoint
+        assert!(cache_key(path, None, br#"{"model": "m", "messages": []}"#).is_none());
+        let streaming = br#"{"model": "m", "temperature": 0, "stream": true}"#;
+        assert!(cache_key(path, None, streaming).is_none());
+        assert!(cache_key("/v1/embeddings", None, a).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_expiry() {
+        let cache = InMemoryCache::new(10, 1024);
+        cache
+            .put("k", Bytes::from_static(b"{}"), Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(cache.get("k").await.unwrap().is_none());
+        assert_eq!(cache.state.lock().unwrap().bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_eviction() {
+        let ttl = Duration::from_secs(60);
+        let cache = InMemoryCache::new(2, 8);
+        cache
+            .put("a", Bytes::from_static(b"aaa"), ttl)
+            .await
+            .unwrap();
+        cache
+            .put("b", Bytes::from_static(b"bbb"), ttl)
+            .await
+            .unwrap();
+        // "a" is now more recently used than "b"
+        assert!(cache.get("a").await.unwrap().is_some());
+        cache
+            .put("c", Bytes::from_static(b"ccc"), ttl)
+            .await
+            .unwrap();
+        assert!(cache.get("b").await.unwrap().is_none());
+        assert!(cache.get("a").await.unwrap().is_some());
+
+        // Over the byte limit, both others go
+        cache
+            .put("d", Bytes::from_static(b"dddddd"), ttl)
+            .await
+            .unwrap();
+        assert!(cache.get("a").await.unwrap().is_none());
+        assert!(cache.get("c").await.unwrap().is_none());
+        assert_eq!(cache.state.lock().unwrap().bytes, 6);
+
+        // Larger than the whole cache, not stored
+        cache.put("e", Bytes::from(vec![0; 9]), ttl).await.unwrap();
+        assert!(cache.get("e").await.unwrap().is_none());
+        assert!(cache.get("d").await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,509 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The OpenAI Responses API, on top of the chat completions engines.
+//!
+//! - `POST /v1/responses` creates a response. With `"background": true` it answers right away
+//!   and the response is generated in the background.
+//! - `GET /v1/responses/{id}` retrieves a stored response.
+//! - `DELETE /v1/responses/{id}` deletes it, stopping it if it is still being generated.
+//! - `POST /v1/responses/{id}/cancel` stops a background response.
+//!
+//! Responses are stored, unless the request has `"store": false`, so that they can be retrieved
+//! and continued with `previous_response_id`. The store is in the memory of this process and
+//! forgets responses after [`RESPONSE_TTL`]: with several HTTP services, a client must keep
+//! talking to the one that created the response. When it is full the oldest completed responses
+//! are forgotten first. With API keys, only the key that created a response can use it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_openai::types::ChatCompletionRequestMessage;
+use axum::{
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures::StreamExt;
+use serde::Serialize;
+
+use super::{
+    auth::Principal,
+    error::HttpError,
+    framing::{self, json_line, StreamFraming},
+    interceptor::{self, InterceptContext},
+    limits::Deadline,
+    metrics::Endpoint,
+    openai::{
+        apply_principal, apply_priority_header, apply_session_header, check_nvext, check_ready,
+        continue_trace, monitor_for_disconnects, ErrorResponse,
+    },
+    service_v2,
+    usage::{self, UsageTracker},
+    RouteDoc,
+};
+use crate::audit::{self, AuditTrail};
+use crate::protocols::openai::responses::{
+    self, NvCreateResponseRequest, Response as ModelResponse, ResponseStatus, ResponseStreamer,
+};
+use crate::request_template::RequestTemplate;
+use dynamo_runtime::pipeline::{AsyncEngineContext, Context};
+
+/// How long responses are stored
+const RESPONSE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Limit memory use if clients create many responses
+const MAX_STORED_RESPONSES: usize = 10_000;
+
+type ResponsesState = (
+    Arc<service_v2::State>,
+    Arc<ResponseStore>,
+    Option<RequestTemplate>,
+);
+
+struct StoredResponse {
+    /// The principal that created the response, if API keys are required
+    owner: Option<String>,
+    response: ModelResponse,
+    /// The chat messages of the conversation up to and including this response, once it is done
+    conversation: Option<Vec<ChatCompletionRequestMessage>>,
+    /// Stops a response that is being generated in the background
+    context: Option<Arc<dyn AsyncEngineContext>>,
+    expires_at: Instant,
+}
+
+/// The responses created by this HTTP service
+struct ResponseStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, StoredResponse>>,
+}
+
+impl ResponseStore {
+    fn new(ttl: Duration) -> Self {
+        ResponseStore {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fails if the store is full of responses that are still being generated
+    fn insert(
+        &self,
+        owner: Option<String>,
+        response: ModelResponse,
+        conversation: Option<Vec<ChatCompletionRequestMessage>>,
+        context: Option<Arc<dyn AsyncEngineContext>>,
+    ) -> Result<(), HttpError> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| e.expires_at > now);
+        if entries.len() >= MAX_STORED_RESPONSES {
+            // The oldest completed response makes room
+            let oldest = entries
+                .iter()
+                .filter(|(_, e)| e.context.is_none())
+                .min_by_key(|(_, e)| e.expires_at)
+                .map(|(id, _)| id.clone());
+            let Some(oldest) = oldest else {
+                return Err(HttpError {
+                    code: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+                    message: "Too many responses are being generated, try again later".to_string(),
+                });
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            response.id.clone(),
+            StoredResponse {
+                owner,
+                response,
+                conversation,
+                context,
+                expires_at: now + self.ttl,
+            },
+        );
+        Ok(())
+    }
+
+    /// Like [`ResponseStore::insert`], for a response that was already sent
+    fn insert_done(
+        &self,
+        owner: Option<String>,
+        response: ModelResponse,
+        conversation: Vec<ChatCompletionRequestMessage>,
+    ) {
+        if let Err(err) = self.insert(owner, response, Some(conversation), None) {
+            // Don't fail the request, it just can't be retrieved
+            tracing::warn!(max = MAX_STORED_RESPONSES, %err, "Not storing response");
+        }
+    }
+
+    /// A background response is done. Unless it was cancelled or deleted meanwhile.
+    fn complete(&self, response: ModelResponse, conversation: Vec<ChatCompletionRequestMessage>) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&response.id) else {
+            return;
+        };
+        if entry.response.status.is_final() {
+            return;
+        }
+        entry.response = response;
+        entry.conversation = Some(conversation);
+        entry.context = None;
+    }
+
+    fn get(&self, id: &str, owner: Option<&str>) -> Option<ModelResponse> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(id)
+            .filter(|e| e.expires_at > Instant::now() && e.owner.as_deref() == owner)
+            .map(|e| e.response.clone())
+    }
+
+    /// The conversation to continue from response `id`
+    fn conversation(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<Vec<ChatCompletionRequestMessage>, HttpError> {
+        let entries = self.entries.lock().unwrap();
+        let Some(entry) = entries
+            .get(id)
+            .filter(|e| e.expires_at > Instant::now() && e.owner.as_deref() == owner)
+        else {
+            return Err(not_found(id));
+        };
+        entry.conversation.clone().ok_or_else(|| HttpError {
+            code: StatusCode::BAD_REQUEST.as_u16(),
+            message: format!("Response {id} is not completed"),
+        })
+    }
+
+    fn cancel(&self, id: &str, owner: Option<&str>) -> Result<ModelResponse, HttpError> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(id).filter(|e| e.owner.as_deref() == owner) else {
+            return Err(not_found(id));
+        };
+        if !entry.response.background {
+            return Err(HttpError {
+                code: StatusCode::BAD_REQUEST.as_u16(),
+                message: "Only background responses can be cancelled".to_string(),
+            });
+        }
+        if let Some(context) = entry.context.take() {
+            context.stop_generating();
+            entry.response.status = ResponseStatus::Cancelled;
+        }
+        Ok(entry.response.clone())
+    }
+
+    fn remove(&self, id: &str, owner: Option<&str>) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.get(id).is_some_and(|e| e.owner.as_deref() == owner) {
+            return false;
+        }
+        let Some(entry) = entries.remove(id) else {
+            return false;
+        };
+        if let Some(context) = entry.context {
+            context.stop_generating();
+        }
+        true
+    }
+}
+
+fn not_found(id: &str) -> HttpError {
+    HttpError {
+        code: StatusCode::NOT_FOUND.as_u16(),
+        message: format!("Response {id} not found"),
+    }
+}
+
+fn bad_request(message: String) -> (StatusCode, Json<ErrorResponse>) {
+    ErrorResponse::from_http_error(HttpError {
+        code: StatusCode::BAD_REQUEST.as_u16(),
+        message,
+    })
+}
+
+/// OpenAI Responses Request Handler
+///
+/// Like chat completions, the engine is always called with streaming enabled, and the stream is
+/// folded into a single response for non-streaming and background requests.
+#[tracing::instrument(skip_all)]
+async fn create_response(
+    State((state, store, template)): State<ResponsesState>,
+    headers: HeaderMap,
+    principal: Option<Extension<Principal>>,
+    Json(mut request): Json<NvCreateResponseRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    check_ready(&state)?;
+    let deadline = Deadline::start(state.timeouts(), Endpoint::Responses);
+
+    check_nvext(&state, request.nvext.as_mut())?;
+    apply_priority_header(&headers, &mut request.nvext)?;
+    apply_session_header(&headers, &mut request.nvext)?;
+    let owner = owner(&principal);
+    apply_principal(&state, principal, &mut request.nvext);
+    continue_trace(&headers, request.nvext.as_ref());
+
+    if let Some(template) = template {
+        if request.model.is_empty() {
+            request.model = template.model.clone();
+        }
+        if request.temperature.unwrap_or(0.0) == 0.0 {
+            request.temperature = Some(template.temperature);
+        }
+        if request.max_output_tokens.unwrap_or(0) == 0 {
+            request.max_output_tokens = Some(template.max_completion_tokens);
+        }
+    }
+    tracing::trace!("Received responses request: {:?}", request);
+
+    let streaming = request.stream.unwrap_or(false);
+    let framing = StreamFraming::negotiate(&headers);
+    let background = request.background.unwrap_or(false);
+    let store_response = request.store.unwrap_or(true);
+    if background && streaming {
+        return Err(bad_request(
+            "Background responses can't be streamed".to_string(),
+        ));
+    }
+    if background && !store_response {
+        return Err(bad_request(
+            "Background responses must be stored".to_string(),
+        ));
+    }
+
+    let mut conversation = match &request.previous_response_id {
+        Some(id) => store
+            .conversation(id, owner.as_deref())
+            .map_err(ErrorResponse::from_http_error)?,
+        None => Vec::new(),
+    };
+    conversation.extend(request.input_messages().map_err(bad_request)?);
+    let mut chat_request = request
+        .chat_request(conversation.clone())
+        .map_err(bad_request)?;
+    let response_id = format!("resp_{}", uuid::Uuid::new_v4().simple());
+    let intercept_ctx = InterceptContext::new(
+        &response_id,
+        &request.model,
+        Endpoint::Responses,
+        chat_request.nvext.as_ref(),
+    );
+    interceptor::intercept(state.interceptors(), &intercept_ctx, &mut chat_request).await?;
+
+    let model = &request.model;
+    let engine = state
+        .manager()
+        .get_chat_completions_engine(model)
+        .map_err(|_| ErrorResponse::model_not_found())?;
+
+    let mut inflight_guard =
+        state
+            .metrics_clone()
+            .create_inflight_guard(model, Endpoint::Responses, streaming);
+
+    let audit = AuditTrail::start(
+        state.audit_logger(),
+        &response_id,
+        Endpoint::Responses.as_str(),
+        &chat_request,
+    );
+    let usage = UsageTracker::start(
+        state.usage_accounting(),
+        &response_id,
+        model,
+        Endpoint::Responses,
+        streaming,
+        &mut chat_request.nvext,
+    );
+    let stream = deadline
+        .connect(engine.generate(Context::with_id(chat_request, response_id.clone())))
+        .await?
+        .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate response"))?;
+    let stream = deadline.track(stream);
+    let stream = interceptor::track(state.interceptors(), intercept_ctx, stream);
+    let stream = usage::track(usage, stream);
+    let stream = audit::track(audit, stream);
+    let ctx = stream.context();
+    let mut streamer = ResponseStreamer::new(ModelResponse::new(response_id, &request));
+
+    if background {
+        let response = streamer.response().clone();
+        if let Err(err) = store.insert(owner, response.clone(), None, Some(ctx.clone())) {
+            ctx.stop_generating();
+            return Err(ErrorResponse::from_http_error(err));
+        }
+        tokio::spawn(async move {
+            let mut stream = stream;
+            while let Some(annotated) = stream.next().await {
+                streamer.add_chunk(annotated);
+            }
+            streamer.finish();
+            let response = streamer.into_response();
+            if response.status != ResponseStatus::Failed {
+                inflight_guard.mark_ok();
+            }
+            let conversation = with_output(conversation, &response);
+            store.complete(response, conversation);
+        });
+        return Ok(Json(response).into_response());
+    }
+
+    if streaming {
+        let events = async_stream::stream! {
+            let mut stream = stream;
+            for event in streamer.start() {
+                yield event;
+            }
+            while let Some(annotated) = stream.next().await {
+                for event in streamer.add_chunk(annotated) {
+                    yield event;
+                }
+            }
+            for event in streamer.finish() {
+                yield event;
+            }
+            let response = streamer.into_response();
+            if store_response && response.status != ResponseStatus::Failed {
+                let conversation = with_output(conversation, &response);
+                store.insert_done(owner, response, conversation);
+            }
+        };
+        if framing == StreamFraming::Ndjson {
+            let lines = events.map(|event| Ok(json_line(&event))).boxed();
+            return Ok(framing::ndjson(lines, ctx, inflight_guard));
+        }
+        let events = events.map(|event| {
+            let data = serde_json::to_value(&event).map_err(axum::Error::new)?;
+            let name = data["type"].as_str().unwrap_or_default().to_string();
+            Event::default().event(name).json_data(data)
+        });
+        let events = monitor_for_disconnects(events.boxed(), ctx, inflight_guard).await;
+
+        let mut sse_stream = Sse::new(events);
+        if let Some(keep_alive) = state.sse_keep_alive() {
+            sse_stream = sse_stream.keep_alive(KeepAlive::default().interval(keep_alive));
+        }
+        return Ok(sse_stream.into_response());
+    }
+
+    let mut stream = stream;
+    while let Some(annotated) = stream.next().await {
+        streamer.add_chunk(annotated);
+    }
+    streamer.finish();
+    let response = streamer.into_response();
+    if let Some(timeout) = deadline.expired() {
+        return Err(timeout);
+    }
+    if let Some(error) = &response.error {
+        return Err(ErrorResponse::internal_server_error(&format!(
+            "Failed to generate response: {}",
+            error.message
+        )));
+    }
+    if store_response {
+        let conversation = with_output(conversation, &response);
+        store.insert_done(owner, response.clone(), conversation);
+    }
+    inflight_guard.mark_ok();
+    Ok(Json(response).into_response())
+}
+
+/// Who may use the responses of this request
+fn owner(principal: &Option<Extension<Principal>>) -> Option<String> {
+    principal
+        .as_ref()
+        .map(|Extension(principal)| principal.0.clone())
+}
+
+/// The conversation continued by `response`
+fn with_output(
+    mut conversation: Vec<ChatCompletionRequestMessage>,
+    response: &ModelResponse,
+) -> Vec<ChatCompletionRequestMessage> {
+    match responses::chat_messages(&response.output) {
+        Ok(output) => conversation.extend(output),
+        Err(err) => tracing::warn!(id = response.id, %err, "Response output is not a conversation"),
+    }
+    conversation
+}
+
+async fn get_response(
+    State((_, store, _)): State<ResponsesState>,
+    principal: Option<Extension<Principal>>,
+    Path(response_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    match store.get(&response_id, owner(&principal).as_deref()) {
+        Some(response) => Ok(Json(response).into_response()),
+        None => Err(ErrorResponse::from_http_error(not_found(&response_id))),
+    }
+}
+
+#[derive(Serialize)]
+struct DeletedResponse {
+    id: String,
+    object: &'static str, // always "response.deleted"
+    deleted: bool,
+}
+
+async fn delete_response(
+    State((_, store, _)): State<ResponsesState>,
+    principal: Option<Extension<Principal>>,
+    Path(response_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if !store.remove(&response_id, owner(&principal).as_deref()) {
+        return Err(ErrorResponse::from_http_error(not_found(&response_id)));
+    }
+    Ok(Json(DeletedResponse {
+        id: response_id,
+        object: "response.deleted",
+        deleted: true,
+    })
+    .into_response())
+}
+
+async fn cancel_response(
+    State((_, store, _)): State<ResponsesState>,
+    principal: Option<Extension<Principal>>,
+    Path(response_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let response = store
+        .cancel(&response_id, owner(&principal).as_deref())
+        .map_err(ErrorResponse::from_http_error)?;
+    Ok(Json(response).into_response())
+}
+
+/// Create an Axum [`Router`] for the OpenAI Responses API
+/// If not path is provided, the default path is `/v1/responses`
+pub fn responses_router(
+    state: Arc<service_v2::State>,
+    template: Option<RequestTemplate>,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/v1/responses".to_string());
+    let response_path = format!("{path}/{{response_id}}");
+    let cancel_path = format!("{response_path}/cancel");
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, &path),
+        RouteDoc::new(axum::http::Method::GET, &response_path),
+        RouteDoc::new(axum::http::Method::DELETE, &response_path),
+        RouteDoc::new(axum::http::Method::POST, &cancel_path),
+    ];
+    let store = Arc::new(ResponseStore::new(RESPONSE_TTL));
+    let router = Router::new()
+        .route(&path, post(create_response))
+        .route(&response_path, get(get_response).delete(delete_response))
+        .route(&cancel_path, post(cancel_response))
+        .with_state((state, store, template));
+    (docs, router)
+}
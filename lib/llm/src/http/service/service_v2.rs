@@ -4,10 +4,25 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use super::admin::{self, AdminConfig};
+use super::admission::{self, AdmissionConfig, AdmissionController};
+use super::auth::{self, AuthKeys};
+use super::cors::{self, CorsConfig};
+use super::idempotency::{self, IdempotencyStore};
+use super::interceptor::RequestInterceptor;
+use super::limits::{self, TimeoutConfig, DEFAULT_MAX_BODY_BYTES};
 use super::metrics;
+use super::output_rate::OutputRateConfig;
+use super::rate_limit::{self, RateLimitConfig, RateLimiter};
+use super::response_cache::{self, ResponseCache};
+use super::spillover::{self, Spillover, SpilloverConfig};
+use super::tls::TlsConfig;
+use super::usage::UsageAccounting;
 use super::Metrics;
 use super::RouteDoc;
+use crate::audit::AuditLogger;
 use crate::discovery::ModelManager;
+use crate::protocols::openai::nvext::NvExtPolicy;
 use crate::request_template::RequestTemplate;
 use anyhow::Result;
 use derive_builder::Builder;
@@ -18,6 +33,14 @@ use tokio_util::sync::CancellationToken;
 pub struct State {
     metrics: Arc<Metrics>,
     manager: Arc<ModelManager>,
+    nvext_policy: NvExtPolicy,
+    sse_keep_alive: Option<Duration>,
+    usage: Option<Arc<UsageAccounting>>,
+    audit: Option<Arc<AuditLogger>>,
+    interceptors: Vec<Arc<dyn RequestInterceptor>>,
+    timeouts: TimeoutConfig,
+    output_rate: OutputRateConfig,
+    spillover: Option<Arc<Spillover>>,
 }
 
 impl State {
@@ -25,9 +48,62 @@ impl State {
         Self {
             manager,
             metrics: Arc::new(Metrics::default()),
+            nvext_policy: NvExtPolicy::default(),
+            sse_keep_alive: None,
+            usage: None,
+            audit: None,
+            interceptors: Vec::new(),
+            timeouts: TimeoutConfig::default(),
+            output_rate: OutputRateConfig::default(),
+            spillover: None,
         }
     }
 
+    pub fn with_nvext_policy(mut self, nvext_policy: NvExtPolicy) -> Self {
+        self.nvext_policy = nvext_policy;
+        self
+    }
+
+    pub fn with_sse_keep_alive(mut self, sse_keep_alive: Option<Duration>) -> Self {
+        self.sse_keep_alive = sse_keep_alive;
+        self
+    }
+
+    pub fn with_usage_accounting(mut self, usage: Option<Arc<UsageAccounting>>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn with_audit_logger(mut self, audit: Option<Arc<AuditLogger>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn with_interceptors(mut self, interceptors: Vec<Arc<dyn RequestInterceptor>>) -> Self {
+        self.interceptors = interceptors;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_output_rate(mut self, output_rate: OutputRateConfig) -> Self {
+        self.output_rate = output_rate;
+        self
+    }
+
+    pub fn with_spillover(mut self, spillover: Option<Arc<Spillover>>) -> Self {
+        self.spillover = spillover;
+        self
+    }
+
+    /// How the `nvext` field of requests is validated and filtered
+    pub fn nvext_policy(&self) -> &NvExtPolicy {
+        &self.nvext_policy
+    }
+
     /// Get the Prometheus [`Metrics`] object which tracks request counts and inflight requests
     pub fn metrics_clone(&self) -> Arc<Metrics> {
         self.metrics.clone()
@@ -41,9 +117,40 @@ impl State {
         self.manager.clone()
     }
 
-    // TODO
+    /// How often to send an SSE comment on a stream that has nothing else to send, so that
+    /// proxies don't close it while the model prepares the first token
     pub fn sse_keep_alive(&self) -> Option<Duration> {
-        None
+        self.sse_keep_alive
+    }
+
+    /// Where usage records of generation requests go, if anywhere
+    pub fn usage_accounting(&self) -> Option<&Arc<UsageAccounting>> {
+        self.usage.as_ref()
+    }
+
+    /// Audits generation requests and their responses, if enabled
+    pub fn audit_logger(&self) -> Option<&Arc<AuditLogger>> {
+        self.audit.as_ref()
+    }
+
+    /// Inspect and moderate generation requests and their responses, in order
+    pub fn interceptors(&self) -> &[Arc<dyn RequestInterceptor>] {
+        &self.interceptors
+    }
+
+    /// Timeouts of generation requests, per endpoint
+    pub fn timeouts(&self) -> &TimeoutConfig {
+        &self.timeouts
+    }
+
+    /// Caps on how fast streamed responses are sent, per API key
+    pub fn output_rate(&self) -> &OutputRateConfig {
+        &self.output_rate
+    }
+
+    /// Where deferrable requests wait while their model has no workers, if enabled
+    pub fn spillover(&self) -> Option<&Arc<Spillover>> {
+        self.spillover.as_ref()
     }
 }
 
@@ -55,7 +162,10 @@ pub struct HttpService {
     router: axum::Router,
     port: u16,
     host: String,
+    tls: Option<TlsConfig>,
     route_docs: Vec<RouteDoc>,
+    admission: Option<Arc<AdmissionController>>,
+    registry: metrics::Registry,
 }
 
 #[derive(Clone, Builder)]
@@ -67,6 +177,10 @@ pub struct HttpServiceConfig {
     #[builder(setter(into), default = "String::from(\"0.0.0.0\")")]
     host: String,
 
+    /// Serve HTTPS with this certificate and key. None serves plain HTTP.
+    #[builder(default = "None")]
+    tls: Option<TlsConfig>,
+
     // #[builder(default)]
     // custom: Vec<axum::Router>
     #[builder(default = "true")]
@@ -78,8 +192,89 @@ pub struct HttpServiceConfig {
     #[builder(default = "true")]
     enable_embeddings_endpoints: bool,
 
+    /// Serve the OpenAI Responses API on top of the chat completions engines
+    #[builder(default = "true")]
+    enable_responses_endpoints: bool,
+
+    /// Serve `/v1/tokenize` and `/v1/detokenize` with the models' tokenizers
+    #[builder(default = "true")]
+    enable_tokenize_endpoints: bool,
+
+    /// Serve the Ollama API on `/api`, for clients that only speak it
+    #[builder(default = "false")]
+    enable_ollama_endpoints: bool,
+
+    /// Serve the web chat UI on `/playground`. Not for production.
+    #[builder(default = "false")]
+    enable_playground: bool,
+
     #[builder(default = "None")]
     request_template: Option<RequestTemplate>,
+
+    /// Add CORS headers to every response. None disables CORS.
+    #[builder(default = "None")]
+    cors: Option<CorsConfig>,
+
+    /// How long to remember `Idempotency-Key` headers and their responses. None disables it.
+    #[builder(default = "None")]
+    idempotency_ttl: Option<Duration>,
+
+    /// What to do with `nvext` keys Dynamo doesn't know about
+    #[builder(default)]
+    nvext_policy: NvExtPolicy,
+
+    /// Interval of keep-alive comments on idle SSE streams. None disables them.
+    #[builder(default = "None")]
+    sse_keep_alive: Option<Duration>,
+
+    /// Require `Authorization: Bearer <key>` with one of these keys. None disables authentication.
+    #[builder(default = "None")]
+    auth_keys: Option<Arc<AuthKeys>>,
+
+    /// Request rate and concurrency limits. None disables them.
+    #[builder(default = "None")]
+    rate_limits: Option<RateLimitConfig>,
+
+    /// Queue requests beyond a concurrency limit, up to a bound. None disables it.
+    #[builder(default = "None")]
+    admission: Option<AdmissionConfig>,
+
+    /// Cache responses to identical deterministic requests. None disables it.
+    #[builder(default = "None")]
+    response_cache: Option<ResponseCache>,
+
+    /// Serve the admin API to load and unload models on workers. None disables it.
+    #[builder(default = "None")]
+    admin: Option<AdminConfig>,
+
+    /// Send a usage record for every generation request. None disables it.
+    #[builder(default = "None")]
+    usage_accounting: Option<Arc<UsageAccounting>>,
+
+    /// Audit generation requests and their responses. None disables it.
+    #[builder(default = "None")]
+    audit_logger: Option<Arc<AuditLogger>>,
+
+    /// Inspect and moderate generation requests and their responses, see [`super::interceptor`]
+    #[builder(default)]
+    interceptors: Vec<Arc<dyn RequestInterceptor>>,
+
+    /// Connect, time to first token and total timeouts of generation requests, per endpoint
+    #[builder(default)]
+    timeouts: TimeoutConfig,
+
+    /// Caps on how fast streamed responses are sent, per API key. Requests can still ask for one.
+    #[builder(default)]
+    output_rate: OutputRateConfig,
+
+    /// Defer requests that can wait while their model has no workers, see [`spillover`]. None
+    /// disables it.
+    #[builder(default = "None")]
+    spillover: Option<SpilloverConfig>,
+
+    /// Reject requests with a larger body with a `413 Payload Too Large`
+    #[builder(default = "DEFAULT_MAX_BODY_BYTES")]
+    max_body_bytes: usize,
 }
 
 impl HttpService {
@@ -115,8 +310,38 @@ impl HttpService {
         let router = self.router.clone();
         let observer = cancel_token.child_token();
 
-        axum::serve(listener, router)
-            .with_graceful_shutdown(observer.cancelled_owned())
+        if self.state.spillover().is_some() {
+            tokio::spawn(spillover::dispatch(
+                self.state.clone(),
+                cancel_token.child_token(),
+            ));
+        }
+
+        let Some(tls) = &self.tls else {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(observer.cancelled_owned())
+                .await
+                .inspect_err(|_| cancel_token.cancel())?;
+            return Ok(());
+        };
+
+        let rustls_config = tls
+            .rustls_config()
+            .await
+            .inspect_err(|_| cancel_token.cancel())?;
+        tracing::info!(cert = %tls.cert_path().display(), "HTTP service is using TLS");
+
+        let handle = axum_server::Handle::new();
+        let shutdown = handle.clone();
+        tokio::spawn(async move {
+            observer.cancelled().await;
+            // Like axum::serve, wait for in-flight requests
+            shutdown.graceful_shutdown(None);
+        });
+
+        axum_server::from_tcp_rustls(listener.into_std()?, rustls_config)
+            .handle(handle)
+            .serve(router.into_make_service())
             .await
             .inspect_err(|_| cancel_token.cancel())?;
 
@@ -127,6 +352,16 @@ impl HttpService {
     pub fn route_docs(&self) -> &[RouteDoc] {
         &self.route_docs
     }
+
+    /// The metrics served on `/metrics`, e.g. to record them with [`crate::metrics_recorder`]
+    pub fn metrics_registry(&self) -> &metrics::Registry {
+        &self.registry
+    }
+
+    /// The admission controller, to serve its stats with [`admission::serve_stats`]
+    pub fn admission(&self) -> Option<Arc<AdmissionController>> {
+        self.admission.clone()
+    }
 }
 
 impl HttpServiceConfigBuilder {
@@ -134,24 +369,57 @@ impl HttpServiceConfigBuilder {
         let config: HttpServiceConfig = self.build_internal()?;
 
         let model_manager = Arc::new(ModelManager::new());
-        let state = Arc::new(State::new(model_manager));
+        let spillover = config
+            .spillover
+            .map(Spillover::open)
+            .transpose()?
+            .map(Arc::new);
+        let state = Arc::new(
+            State::new(model_manager)
+                .with_nvext_policy(config.nvext_policy)
+                .with_sse_keep_alive(config.sse_keep_alive)
+                .with_usage_accounting(config.usage_accounting)
+                .with_audit_logger(config.audit_logger)
+                .with_interceptors(config.interceptors)
+                .with_timeouts(config.timeouts)
+                .with_output_rate(config.output_rate)
+                .with_spillover(spillover),
+        );
 
         // enable prometheus metrics
         let registry = metrics::Registry::new();
         state.metrics_clone().register(&registry)?;
+        dynamo_runtime::locality::register_metrics(&registry)?;
+        dynamo_runtime::observability::register_metrics(&registry)?;
+        crate::kv_router::scheduler::register_metrics(&registry)?;
+        crate::kv_router::indexer::register_metrics(&registry)?;
+        admission::register_metrics(&registry)?;
+        crate::preprocessor::compression::register_metrics(&registry)?;
+        crate::prefill_queue::register_metrics(&registry)?;
+        crate::prefill_router::register_metrics(&registry)?;
+        crate::speculative::register_metrics(&registry)?;
+        spillover::register_metrics(&registry)?;
 
         let mut router = axum::Router::new();
 
         let mut all_docs = Vec::new();
 
         let mut routes = vec![
-            metrics::router(registry, None),
+            metrics::router(registry.clone(), None),
             super::openai::list_models_router(state.clone(), None),
             super::health::health_check_router(state.clone(), None),
         ];
 
         if config.enable_chat_endpoints {
             routes.push(super::openai::chat_completions_router(
+                state.clone(),
+                config.request_template.clone(),
+                None,
+            ));
+        }
+
+        if config.enable_responses_endpoints {
+            routes.push(super::responses::responses_router(
                 state.clone(),
                 config.request_template,
                 None,
@@ -166,6 +434,26 @@ impl HttpServiceConfigBuilder {
             routes.push(super::openai::embeddings_router(state.clone(), None));
         }
 
+        if config.enable_tokenize_endpoints {
+            routes.push(super::tokenize::tokenize_router(state.clone()));
+        }
+
+        if config.enable_ollama_endpoints {
+            routes.push(super::ollama::ollama_router(state.clone()));
+        }
+
+        if config.enable_playground {
+            routes.push(super::playground::playground_router(None));
+        }
+
+        if state.spillover().is_some() {
+            routes.push(spillover::deferred_router(state.clone()));
+        }
+
+        if let Some(admin_config) = config.admin {
+            routes.push(admin::admin_router(admin_config));
+        }
+
         // for (route_docs, route) in routes.into_iter().chain(self.routes.into_iter()) {
         //     router = router.merge(route);
         //     all_docs.extend(route_docs);
@@ -176,12 +464,78 @@ impl HttpServiceConfigBuilder {
             all_docs.extend(route_docs);
         }
 
+        // Innermost, so that cache hits and requests rejected by rate limits don't queue
+        let admission = config.admission.map(AdmissionController::new);
+        if let Some(controller) = &admission {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                (controller.clone(), state.clone()),
+                admission::admission_middleware,
+            ));
+        }
+
+        // Inside the response cache, which can still answer while the model has no workers
+        if state.spillover().is_some() {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                spillover::spillover_middleware,
+            ));
+        }
+
+        // Inside rate limits so that cache hits count against them
+        if let Some(response_cache) = config.response_cache {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                (response_cache, state.clone()),
+                response_cache::response_cache_middleware,
+            ));
+        }
+
+        if let Some(ttl) = config.idempotency_ttl {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(IdempotencyStore::new(ttl)),
+                idempotency::idempotency_middleware,
+            ));
+        }
+
+        if let Some(rate_limits) = config.rate_limits {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                (RateLimiter::new(rate_limits), state.clone()),
+                rate_limit::rate_limit_middleware,
+            ));
+        }
+
+        // Outside the layers that read the body, so that they don't read more than allowed
+        router = router
+            .layer(axum::middleware::from_fn_with_state(
+                config.max_body_bytes,
+                limits::body_limit_middleware,
+            ))
+            .layer(axum::extract::DefaultBodyLimit::max(config.max_body_bytes));
+
+        // Outside idempotency and rate limits so unauthenticated requests can't use them up
+        if let Some(auth_keys) = config.auth_keys {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                auth_keys,
+                auth::auth_middleware,
+            ));
+        }
+
+        // Must be the outermost layer so that it also answers preflight requests
+        if let Some(cors_config) = config.cors {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(cors_config),
+                cors::cors_middleware,
+            ));
+        }
+
         Ok(HttpService {
             state,
             router,
             port: config.port,
             host: config.host,
+            tls: config.tls,
             route_docs: all_docs,
+            admission,
+            registry,
         })
     }
 
@@ -189,4 +543,87 @@ impl HttpServiceConfigBuilder {
         self.request_template = Some(request_template);
         self
     }
+
+    pub fn with_tls(mut self, tls: Option<TlsConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_cors(mut self, cors: Option<CorsConfig>) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    pub fn with_idempotency_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.idempotency_ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_nvext_policy(mut self, nvext_policy: NvExtPolicy) -> Self {
+        self.nvext_policy = Some(nvext_policy);
+        self
+    }
+
+    pub fn with_sse_keep_alive(mut self, sse_keep_alive: Option<Duration>) -> Self {
+        self.sse_keep_alive = Some(sse_keep_alive);
+        self
+    }
+
+    pub fn with_auth_keys(mut self, auth_keys: Option<Arc<AuthKeys>>) -> Self {
+        self.auth_keys = Some(auth_keys);
+        self
+    }
+
+    pub fn with_rate_limits(mut self, rate_limits: Option<RateLimitConfig>) -> Self {
+        self.rate_limits = Some(rate_limits);
+        self
+    }
+
+    pub fn with_admission(mut self, admission: Option<AdmissionConfig>) -> Self {
+        self.admission = Some(admission);
+        self
+    }
+
+    pub fn with_response_cache(mut self, response_cache: Option<ResponseCache>) -> Self {
+        self.response_cache = Some(response_cache);
+        self
+    }
+
+    pub fn with_spillover(mut self, spillover: Option<SpilloverConfig>) -> Self {
+        self.spillover = Some(spillover);
+        self
+    }
+
+    pub fn with_admin(mut self, admin: Option<AdminConfig>) -> Self {
+        self.admin = Some(admin);
+        self
+    }
+
+    pub fn with_usage_accounting(mut self, usage: Option<Arc<UsageAccounting>>) -> Self {
+        self.usage_accounting = Some(usage);
+        self
+    }
+
+    pub fn with_audit_logger(mut self, audit: Option<Arc<AuditLogger>>) -> Self {
+        self.audit_logger = Some(audit);
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    pub fn with_output_rate(mut self, output_rate: OutputRateConfig) -> Self {
+        self.output_rate = Some(output_rate);
+        self
+    }
+
+    /// Add an interceptor, after the ones already added
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn RequestInterceptor>) -> Self {
+        self.interceptors
+            .get_or_insert_with(Vec::new)
+            .push(interceptor);
+        self
+    }
 }
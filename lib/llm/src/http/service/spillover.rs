@@ -0,0 +1,761 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Spill requests that can wait to disk while their model has no workers.
+//!
+//! When the last worker of a model goes away, e.g. during a rollout or an outage, its requests
+//! fail with `404 Model not found`. Batch jobs and other callers that don't need an answer right
+//! away can set `nvext.deferrable: true` on a non-streaming `/v1/chat/completions` or
+//! `/v1/completions` request. If its model had workers since the service started but has none
+//! now, the request is written to a directory and answered with `202 Accepted`:
+//!
+//! `{"id": "<id>", "object": "deferred_request", "status": "queued"}`, and a `Location` header.
+//!
+//! Once workers of the model are back, the requests are sent to them in order, at most
+//! `max_dispatching` at once. `GET /v1/deferred/{id}` answers `202` while the request waits, then
+//! the status and body the request would have had, for `result_ttl`. A request still waiting after
+//! `max_wait` gets a `503`. With authentication only the API key that sent a request can see it.
+//!
+//! The queue holds at most `max_requests` requests. Past that, and for requests that can't wait,
+//! nothing changes. The directory survives restarts, waiting requests are sent once the service is
+//! back. The service must be the only one using the directory.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use axum::{
+    body::Body,
+    extract::{Extension, Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use prometheus::{IntCounterVec, IntGauge, Opts};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+use super::auth::Principal;
+use super::error::HttpError;
+use super::openai::{self, ErrorResponse, PRIORITY_HEADER, SESSION_HEADER};
+use super::{service_v2, RouteDoc};
+use crate::discovery::ModelManager;
+use crate::types::openai::{
+    chat_completions::NvCreateChatCompletionRequest, completions::NvCreateCompletionRequest,
+};
+
+/// Where the results of deferred requests are served
+pub const DEFERRED_PATH: &str = "/v1/deferred";
+
+/// Largest request body we will read to find out if it can wait
+const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+
+/// How often the dispatcher looks for models that have workers again
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Request headers that are replayed with a deferred request
+const REPLAYED_HEADERS: &[&str] = &[PRIORITY_HEADER, SESSION_HEADER, "traceparent", "tracestate"];
+
+static QUEUED: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_http_spillover_queued_requests",
+        "Deferred requests waiting on disk for workers of their model",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_http_spillover_requests_total",
+            "Deferrable requests of models without workers, by model and outcome (queued, full, dispatched or expired)",
+        ),
+        &["model", "outcome"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the spillover metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(QUEUED.clone()))?;
+    registry.register(Box::new(REQUESTS.clone()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpilloverConfig {
+    /// Where the deferred requests and their results are stored
+    pub dir: PathBuf,
+
+    /// Deferred requests waiting or in progress at once
+    pub max_requests: usize,
+
+    /// Deferred requests sent to the workers at once
+    pub max_dispatching: usize,
+
+    /// How long a request waits for workers before it fails
+    pub max_wait: Duration,
+
+    /// How long the result of a request is kept
+    pub result_ttl: Duration,
+}
+
+impl SpilloverConfig {
+    pub fn new(dir: PathBuf) -> Self {
+        SpilloverConfig {
+            dir,
+            max_requests: 10_000,
+            max_dispatching: 16,
+            max_wait: Duration::from_secs(3600),
+            result_ttl: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// The endpoint of a deferred request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    ChatCompletions,
+    Completions,
+}
+
+impl Kind {
+    fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/v1/chat/completions" => Some(Kind::ChatCompletions),
+            "/v1/completions" => Some(Kind::Completions),
+            _ => None,
+        }
+    }
+
+    fn has_workers(&self, manager: &ModelManager, model: &str) -> bool {
+        match self {
+            Kind::ChatCompletions => manager.get_chat_completions_engine(model).is_ok(),
+            Kind::Completions => manager.get_completions_engine(model).is_ok(),
+        }
+    }
+
+    /// The body parses as a request of this endpoint
+    fn parses(&self, body: &Value) -> bool {
+        match self {
+            Kind::ChatCompletions => NvCreateChatCompletionRequest::deserialize(body).is_ok(),
+            Kind::Completions => NvCreateCompletionRequest::deserialize(body).is_ok(),
+        }
+    }
+}
+
+/// A request waiting for workers, in `<id>.request.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Deferred {
+    id: String,
+    kind: Kind,
+    model: String,
+    principal: Option<String>,
+    headers: Vec<(String, String)>,
+    body: Value,
+    /// Unix time in seconds
+    enqueued_at: u64,
+}
+
+/// The response to a deferred request, in `<id>.response.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Outcome {
+    principal: Option<String>,
+    status: u16,
+    body: String,
+}
+
+/// What the service knows of a deferred request
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Lookup {
+    Pending,
+    Done,
+    Unknown,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Not sent yet, oldest first
+    waiting: VecDeque<Deferred>,
+    /// The principal of the waiting and dispatching requests
+    pending: HashMap<String, Option<String>>,
+    /// The principal of the requests with a result on disk, and when it expires
+    results: HashMap<String, (Option<String>, u64)>,
+    /// Models that had workers, see [`Spillover::remember`]
+    known: HashSet<String>,
+}
+
+/// The on-disk queue of deferred requests, see the [module docs](self)
+pub struct Spillover {
+    config: SpilloverConfig,
+    queue: Mutex<Queue>,
+    dispatching: Arc<Semaphore>,
+}
+
+impl Spillover {
+    /// Open the queue in `config.dir`, with the requests and results left by an earlier run
+    pub fn open(config: SpilloverConfig) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&config.dir)
+            .with_context(|| format!("Failed creating spillover dir {}", config.dir.display()))?;
+        let mut queue = Queue::default();
+        let mut requests = Vec::new();
+        let entries = std::fs::read_dir(&config.dir)
+            .with_context(|| format!("Failed reading spillover dir {}", config.dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if let Some(id) = name.strip_suffix(".response.json") {
+                let Some(outcome) = read_file::<Outcome>(&path) else {
+                    continue;
+                };
+                let expires_at = std::fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .map(unix_secs)
+                    .unwrap_or_else(|_| now_secs())
+                    + config.result_ttl.as_secs();
+                queue
+                    .results
+                    .insert(id.to_string(), (outcome.principal, expires_at));
+            } else if name.ends_with(".request.json") {
+                requests.extend(read_file::<Deferred>(&path));
+            } else if name.ends_with(".tmp") {
+                // A write that didn't finish
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        requests.sort_by_key(|deferred| deferred.enqueued_at);
+        for deferred in requests {
+            if queue.results.contains_key(&deferred.id) {
+                // Stopped between writing the result and removing the request
+                let _ = std::fs::remove_file(request_path(&config.dir, &deferred.id));
+                continue;
+            }
+            queue.known.insert(deferred.model.clone());
+            queue
+                .pending
+                .insert(deferred.id.clone(), deferred.principal.clone());
+            queue.waiting.push_back(deferred);
+        }
+        if !queue.waiting.is_empty() {
+            tracing::info!(
+                waiting = queue.waiting.len(),
+                dir = %config.dir.display(),
+                "Loaded deferred requests"
+            );
+        }
+        QUEUED.set(queue.pending.len() as i64);
+        Ok(Spillover {
+            dispatching: Arc::new(Semaphore::new(config.max_dispatching)),
+            queue: Mutex::new(queue),
+            config,
+        })
+    }
+
+    /// Models with workers now. Requests are only deferred for models that had workers, so that a
+    /// typo in the model name still fails right away.
+    fn remember(&self, models: HashSet<String>) {
+        self.queue.lock().unwrap().known.extend(models);
+    }
+
+    fn is_known(&self, model: &str) -> bool {
+        self.queue.lock().unwrap().known.contains(model)
+    }
+
+    /// Store `deferred` until its model has workers. False if the queue is full or the request
+    /// could not be written.
+    async fn defer(&self, deferred: Deferred) -> bool {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.pending.len() >= self.config.max_requests {
+                return false;
+            }
+            queue
+                .pending
+                .insert(deferred.id.clone(), deferred.principal.clone());
+        }
+        let written = match serde_json::to_vec(&deferred) {
+            Ok(bytes) => write_atomic(&request_path(&self.config.dir, &deferred.id), &bytes).await,
+            Err(err) => Err(err.into()),
+        };
+        let mut queue = self.queue.lock().unwrap();
+        if let Err(err) = written {
+            tracing::warn!(id = deferred.id, %err, "Failed storing deferred request");
+            queue.pending.remove(&deferred.id);
+            return false;
+        }
+        queue.waiting.push_back(deferred);
+        QUEUED.set(queue.pending.len() as i64);
+        true
+    }
+
+    /// Take the oldest waiting request whose model has workers
+    fn take_ready(&self, has_workers: impl Fn(Kind, &str) -> bool) -> Option<Deferred> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = queue
+            .waiting
+            .iter()
+            .position(|deferred| has_workers(deferred.kind, &deferred.model))?;
+        queue.waiting.remove(index)
+    }
+
+    /// Take the requests that waited longer than `max_wait`
+    fn take_expired(&self, now: u64) -> Vec<Deferred> {
+        let max_wait = self.config.max_wait.as_secs();
+        let mut queue = self.queue.lock().unwrap();
+        let (expired, waiting): (Vec<_>, VecDeque<_>) = std::mem::take(&mut queue.waiting)
+            .into_iter()
+            .partition(|deferred| deferred.enqueued_at + max_wait <= now);
+        queue.waiting = waiting;
+        expired
+    }
+
+    /// Put back a request whose model lost its workers while it was sent
+    fn requeue(&self, deferred: Deferred) {
+        self.queue.lock().unwrap().waiting.push_front(deferred);
+    }
+
+    /// Store the result of `deferred`, it is done
+    async fn finish(&self, deferred: &Deferred, outcome: Outcome) {
+        let written = match serde_json::to_vec(&outcome) {
+            Ok(bytes) => write_atomic(&response_path(&self.config.dir, &deferred.id), &bytes).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = &written {
+            tracing::warn!(id = deferred.id, %err, "Failed storing result of deferred request");
+        }
+        let _ = tokio::fs::remove_file(request_path(&self.config.dir, &deferred.id)).await;
+        let mut queue = self.queue.lock().unwrap();
+        queue.pending.remove(&deferred.id);
+        if written.is_ok() {
+            let expires_at = now_secs() + self.config.result_ttl.as_secs();
+            queue.results.insert(
+                deferred.id.clone(),
+                (deferred.principal.clone(), expires_at),
+            );
+        }
+        QUEUED.set(queue.pending.len() as i64);
+    }
+
+    /// Remove the results older than `result_ttl`
+    async fn expire_results(&self, now: u64) {
+        let expired: Vec<String> = {
+            let mut queue = self.queue.lock().unwrap();
+            let expired = queue
+                .results
+                .iter()
+                .filter(|(_, (_, expires_at))| *expires_at <= now)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in &expired {
+                queue.results.remove(id);
+            }
+            expired
+        };
+        for id in expired {
+            let _ = tokio::fs::remove_file(response_path(&self.config.dir, &id)).await;
+        }
+    }
+
+    /// Where request `id` of `principal` is. Requests of other principals are unknown.
+    fn lookup(&self, id: &str, principal: Option<&str>) -> Lookup {
+        let queue = self.queue.lock().unwrap();
+        match (queue.pending.get(id), queue.results.get(id)) {
+            (Some(owner), _) if owner.as_deref() == principal => Lookup::Pending,
+            (None, Some((owner, _))) if owner.as_deref() == principal => Lookup::Done,
+            _ => Lookup::Unknown,
+        }
+    }
+
+    async fn read_outcome(&self, id: &str) -> anyhow::Result<Outcome> {
+        let bytes = tokio::fs::read(response_path(&self.config.dir, id)).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// Read a file of the queue, removing it if it's unreadable
+fn read_file<T: serde::de::DeserializeOwned>(path: &FsPath) -> Option<T> {
+    let read = std::fs::read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?));
+    match read {
+        Ok(value) => Some(value),
+        Err(err) => {
+            tracing::warn!(path = %path.display(), %err, "Removing unreadable spillover file");
+            let _ = std::fs::remove_file(path);
+            None
+        }
+    }
+}
+
+fn request_path(dir: &FsPath, id: &str) -> PathBuf {
+    dir.join(format!("{id}.request.json"))
+}
+
+fn response_path(dir: &FsPath, id: &str) -> PathBuf {
+    dir.join(format!("{id}.response.json"))
+}
+
+/// Write `bytes` to `path` so that readers never see a partial file
+async fn write_atomic(path: &FsPath, bytes: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, bytes).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn now_secs() -> u64 {
+    unix_secs(SystemTime::now())
+}
+
+/// The model of a request that may be deferred, None if it must be answered now
+fn deferrable_model(kind: Kind, body: &[u8]) -> Option<String> {
+    let Ok(request) = serde_json::from_slice::<Value>(body) else {
+        // Not ours to reject, the handler reports it
+        return None;
+    };
+    let deferrable = request
+        .pointer("/nvext/deferrable")
+        .and_then(Value::as_bool)
+        == Some(true);
+    let streaming = request.get("stream").and_then(Value::as_bool) == Some(true);
+    if !deferrable || streaming || !kind.parses(&request) {
+        return None;
+    }
+    match request.get("model").and_then(Value::as_str) {
+        Some(model) if !model.is_empty() => Some(model.to_string()),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+struct DeferredStatus<'a> {
+    id: &'a str,
+    object: &'static str, // always "deferred_request"
+    status: &'static str, // always "queued"
+}
+
+fn queued_response(id: &str) -> Response {
+    let location = format!("{DEFERRED_PATH}/{id}");
+    let mut response = (
+        StatusCode::ACCEPTED,
+        Json(DeferredStatus {
+            id,
+            object: "deferred_request",
+            status: "queued",
+        }),
+    )
+        .into_response();
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    response
+}
+
+/// Axum middleware. Install with `axum::middleware::from_fn_with_state(Arc<service_v2::State>, spillover_middleware)`.
+pub async fn spillover_middleware(
+    State(state): State<Arc<service_v2::State>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(spillover) = state.spillover() else {
+        return next.run(request).await;
+    };
+    let kind = match Kind::from_path(request.uri().path()) {
+        Some(kind) if request.method() == Method::POST => kind,
+        _ => return next.run(request).await,
+    };
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_REQUEST_BYTES).await {
+        Ok(body) => body,
+        Err(err) => {
+            return ErrorResponse::from_http_error(HttpError {
+                code: StatusCode::PAYLOAD_TOO_LARGE.as_u16(),
+                message: err.to_string(),
+            })
+            .into_response();
+        }
+    };
+    let model = deferrable_model(kind, &body);
+    let Some(model) = model
+        .filter(|model| !kind.has_workers(state.manager(), model) && spillover.is_known(model))
+    else {
+        return next.run(Request::from_parts(parts, Body::from(body))).await;
+    };
+
+    let headers = REPLAYED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = parts.headers.get(*name)?.to_str().ok()?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect();
+    let deferred = Deferred {
+        id: uuid::Uuid::new_v4().to_string(),
+        kind,
+        model: model.clone(),
+        principal: parts
+            .extensions
+            .get::<Principal>()
+            .map(|Principal(principal)| principal.clone()),
+        headers,
+        // Parsed above
+        body: serde_json::from_slice(&body).unwrap_or_default(),
+        enqueued_at: now_secs(),
+    };
+    let id = deferred.id.clone();
+    if !spillover.defer(deferred).await {
+        REQUESTS.with_label_values(&[&model, "full"]).inc();
+        return next.run(Request::from_parts(parts, Body::from(body))).await;
+    }
+    REQUESTS.with_label_values(&[&model, "queued"]).inc();
+    tracing::info!(id, model, "Model has no workers, deferred request");
+    queued_response(&id)
+}
+
+/// Send `deferred` through the handler of its endpoint
+async fn replay(state: Arc<service_v2::State>, deferred: &Deferred) -> Response {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &deferred.headers {
+        if let (Ok(name), Ok(value)) = (
+            header::HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+    let principal = deferred.principal.clone().map(|p| Extension(Principal(p)));
+    let body = deferred.body.clone();
+    match deferred.kind {
+        Kind::ChatCompletions => match serde_json::from_value(body) {
+            Ok(request) => {
+                openai::chat_completions(State((state, None)), headers, principal, Json(request))
+                    .await
+                    .into_response()
+            }
+            Err(err) => ErrorResponse::internal_server_error(&err.to_string()).into_response(),
+        },
+        Kind::Completions => match serde_json::from_value(body) {
+            Ok(request) => openai::completions(State(state), headers, principal, Json(request))
+                .await
+                .into_response(),
+            Err(err) => ErrorResponse::internal_server_error(&err.to_string()).into_response(),
+        },
+    }
+}
+
+/// Send a deferred request, and store its result unless the model lost its workers again
+async fn dispatch_one(state: Arc<service_v2::State>, spillover: &Spillover, deferred: Deferred) {
+    let response = replay(state, &deferred).await;
+    if response.status() == StatusCode::NOT_FOUND {
+        spillover.requeue(deferred);
+        return;
+    }
+    let status = response.status().as_u16();
+    let body = match axum::body::to_bytes(response.into_body(), usize::MAX).await {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(err) => {
+            tracing::warn!(id = deferred.id, %err, "Failed reading response of deferred request");
+            spillover.requeue(deferred);
+            return;
+        }
+    };
+    REQUESTS
+        .with_label_values(&[&deferred.model, "dispatched"])
+        .inc();
+    let outcome = Outcome {
+        principal: deferred.principal.clone(),
+        status,
+        body,
+    };
+    spillover.finish(&deferred, outcome).await;
+}
+
+/// Send the deferred requests of the models that have workers again, until `cancel_token`. Runs
+/// with the HTTP service.
+pub async fn dispatch(state: Arc<service_v2::State>, cancel_token: CancellationToken) {
+    let Some(spillover) = state.spillover().cloned() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(DISPATCH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+        let now = now_secs();
+        spillover.remember(state.manager().model_display_names());
+        spillover.expire_results(now).await;
+
+        for deferred in spillover.take_expired(now) {
+            REQUESTS
+                .with_label_values(&[&deferred.model, "expired"])
+                .inc();
+            let message = format!(
+                "Model {} had no workers for {}s",
+                deferred.model,
+                spillover.config.max_wait.as_secs()
+            );
+            let outcome = Outcome {
+                principal: deferred.principal.clone(),
+                status: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+                body: serde_json::json!({ "error": message }).to_string(),
+            };
+            spillover.finish(&deferred, outcome).await;
+        }
+
+        while let Ok(permit) = spillover.dispatching.clone().try_acquire_owned() {
+            let Some(deferred) =
+                spillover.take_ready(|kind, model| kind.has_workers(state.manager(), model))
+            else {
+                break;
+            };
+            tracing::debug!(
+                id = deferred.id,
+                model = deferred.model,
+                "Sending deferred request"
+            );
+            let state = state.clone();
+            let spillover = spillover.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                dispatch_one(state, &spillover, deferred).await;
+            });
+        }
+    }
+}
+
+async fn deferred_result(
+    State(state): State<Arc<service_v2::State>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let not_found = || {
+        ErrorResponse::from_http_error(HttpError {
+            code: StatusCode::NOT_FOUND.as_u16(),
+            message: format!("Deferred request {id} not found"),
+        })
+    };
+    let Some(spillover) = state.spillover() else {
+        return Err(not_found());
+    };
+    let principal = principal.as_ref().map(|Extension(Principal(p))| p.as_str());
+    match spillover.lookup(&id, principal) {
+        Lookup::Pending => Ok(queued_response(&id)),
+        Lookup::Done => {
+            let outcome = spillover.read_outcome(&id).await.map_err(|err| {
+                ErrorResponse::internal_server_error(&format!(
+                    "Failed reading result of deferred request {id}: {err}"
+                ))
+            })?;
+            let status =
+                StatusCode::from_u16(outcome.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok((
+                status,
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                )],
+                outcome.body,
+            )
+                .into_response())
+        }
+        Lookup::Unknown => Err(not_found()),
+    }
+}
+
+/// Create an Axum [`Router`] serving the results of deferred requests on [`DEFERRED_PATH`]
+pub fn deferred_router(state: Arc<service_v2::State>) -> (Vec<RouteDoc>, Router) {
+    let path = format!("{DEFERRED_PATH}/{{id}}");
+    let doc = RouteDoc::new(Method::GET, &path);
+    let router = Router::new()
+        .route(&path, get(deferred_result))
+        .with_state(state);
+    (vec![doc], router)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deferred(id: &str, model: &str, enqueued_at: u64) -> Deferred {
+        Deferred {
+            id: id.to_string(),
+            kind: Kind::Completions,
+            model: model.to_string(),
+            principal: Some("key-a".to_string()),
+            headers: vec![],
+            body: serde_json::json!({"model": model, "prompt": "hi"}),
+            enqueued_at,
+        }
+    }
+
+    #[test]
+    fn test_deferrable_model() {
+        let kind = Kind::Completions;
+        let body = br#"{"model": "m", "prompt": "hi", "nvext": {"deferrable": true}}"#;
+        assert_eq!(deferrable_model(kind, body).as_deref(), Some("m"));
+
+        // Not asked for, streaming, or not a valid request
+        assert!(deferrable_model(kind, br#"{"model": "m", "prompt": "hi"}"#).is_none());
+        let streaming =
+            br#"{"model": "m", "prompt": "hi", "stream": true, "nvext": {"deferrable": true}}"#;
+        assert!(deferrable_model(kind, streaming).is_none());
+        let invalid = br#"{"model": "m", "nvext": {"deferrable": true}}"#;
+        assert!(deferrable_model(kind, invalid).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_spillover_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SpilloverConfig {
+            max_requests: 2,
+            ..SpilloverConfig::new(dir.path().to_path_buf())
+        };
+        let spillover = Spillover::open(config.clone()).unwrap();
+        let now = now_secs();
+        assert!(spillover.defer(deferred("a", "m1", now)).await);
+        assert!(spillover.defer(deferred("b", "m2", now)).await);
+        assert!(!spillover.defer(deferred("c", "m1", now)).await);
+        assert_eq!(spillover.lookup("a", Some("key-a")), Lookup::Pending);
+        assert_eq!(spillover.lookup("a", Some("key-b")), Lookup::Unknown);
+
+        // A restart keeps the waiting requests, and their models are known
+        drop(spillover);
+        let spillover = Spillover::open(config).unwrap();
+        assert!(spillover.is_known("m2"));
+        let ready = spillover.take_ready(|_, model| model == "m2").unwrap();
+        assert_eq!(ready.id, "b");
+        assert!(spillover.take_ready(|_, model| model == "m2").is_none());
+
+        let outcome = Outcome {
+            principal: ready.principal.clone(),
+            status: 200,
+            body: "{}".to_string(),
+        };
+        spillover.finish(&ready, outcome).await;
+        assert_eq!(spillover.lookup("b", Some("key-a")), Lookup::Done);
+        assert_eq!(spillover.read_outcome("b").await.unwrap().status, 200);
+        assert!(!request_path(dir.path(), "b").exists());
+
+        // "a" waits too long, results expire
+        assert!(spillover.take_expired(now).is_empty());
+        assert_eq!(spillover.take_expired(now + 3600).len(), 1);
+        spillover.expire_results(now + 2 * 3600).await;
+        assert_eq!(spillover.lookup("b", Some("key-a")), Lookup::Unknown);
+        assert!(!response_path(dir.path(), "b").exists());
+    }
+}
@@ -0,0 +1,62 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TLS termination for the HTTP service, so encrypted ingress doesn't need a reverse proxy in
+//! front of it. Certificates and keys are read from PEM files with rustls.
+
+use std::path::{Path, PathBuf};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, leaf certificate first
+    cert_path: PathBuf,
+
+    /// PEM file with the private key of the leaf certificate
+    key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        TlsConfig {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    pub fn cert_path(&self) -> &Path {
+        &self.cert_path
+    }
+
+    pub fn key_path(&self) -> &Path {
+        &self.key_path
+    }
+
+    /// Read and check the certificate and key
+    pub(crate) async fn rustls_config(&self) -> anyhow::Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert_path, &self.key_path)
+            .await
+            .map_err(|err| {
+                anyhow::anyhow!(
+                    "Invalid TLS certificate {} or key {}: {err}",
+                    self.cert_path.display(),
+                    self.key_path.display()
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("cert.pem"), "not a certificate").unwrap();
+        let config = TlsConfig::new(dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        let err = config.rustls_config().await.unwrap_err().to_string();
+        assert!(err.contains("cert.pem"), "{err}");
+    }
+}
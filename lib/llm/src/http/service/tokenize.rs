@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `/v1/tokenize` and `/v1/detokenize`, with the tokenizer of the model's deployment card. Lets
+//! clients count the tokens of a prompt or a conversation before sending it, to keep within the
+//! model's context length.
+//!
+//! Tokenizers are loaded on the first request for a model, downloaded from NATS if the model
+//! runs on remote workers.
+
+use std::sync::Arc;
+
+use async_openai::types::ChatCompletionRequestMessage;
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use minijinja::value::Value;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::HttpError,
+    openai::{check_ready, ErrorResponse},
+    service_v2, RouteDoc,
+};
+use crate::preprocessor::prompt::OAIChatLikeRequest;
+use crate::protocols::TokenIdType;
+use crate::tokenizers::{
+    lazy::{LazyTokenizer, LoadedTokenizer},
+    traits::Decoder,
+    traits::Encoder,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct TokenizeRequest {
+    pub model: String,
+
+    /// Text to tokenize as is. Either this or `messages`.
+    #[serde(default)]
+    pub prompt: Option<String>,
+
+    /// A conversation, tokenized after applying the model's chat template
+    #[serde(default)]
+    pub messages: Option<Vec<ChatCompletionRequestMessage>>,
+
+    /// With `messages`, end the prompt with the start of an assistant message, like a chat
+    /// completions request does. Defaults to the model's setting, or true.
+    #[serde(default)]
+    pub add_generation_prompt: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenizeResponse {
+    pub model: String,
+    pub count: usize,
+    pub tokens: Vec<TokenIdType>,
+
+    /// The model's context length, prompt and completion tokens together
+    pub max_model_len: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DetokenizeRequest {
+    pub model: String,
+    pub tokens: Vec<TokenIdType>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetokenizeResponse {
+    pub model: String,
+    pub prompt: String,
+}
+
+/// The conversation of a tokenize request, for the chat template
+struct Conversation<'a> {
+    messages: &'a [ChatCompletionRequestMessage],
+    add_generation_prompt: bool,
+}
+
+impl OAIChatLikeRequest for Conversation<'_> {
+    fn messages(&self) -> Value {
+        Value::from_serialize(self.messages)
+    }
+
+    fn should_add_generation_prompt(&self) -> bool {
+        self.add_generation_prompt
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> (StatusCode, Json<ErrorResponse>) {
+    ErrorResponse::from_http_error(HttpError {
+        code: StatusCode::BAD_REQUEST.as_u16(),
+        message: message.into(),
+    })
+}
+
+/// The model's tokenizer, loading it if needed
+async fn loaded_tokenizer(
+    state: &service_v2::State,
+    model: &str,
+) -> Result<(Arc<LoadedTokenizer>, Arc<LazyTokenizer>), (StatusCode, Json<ErrorResponse>)> {
+    let lazy = state
+        .manager()
+        .get_tokenizer(model)
+        .map_err(|_| ErrorResponse::model_not_found())?;
+    let loaded = lazy
+        .get()
+        .await
+        .map_err(|err| ErrorResponse::from_anyhow(err, "Failed to load the tokenizer"))?;
+    Ok((loaded, lazy))
+}
+
+async fn tokenize(
+    State(state): State<Arc<service_v2::State>>,
+    Json(request): Json<TokenizeRequest>,
+) -> Result<Json<TokenizeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    check_ready(&state)?;
+    let (loaded, lazy) = loaded_tokenizer(&state, &request.model).await?;
+    let special_tokens = lazy.special_tokens();
+    let text = match (request.prompt, request.messages) {
+        (Some(prompt), None) => prompt,
+        (None, Some(messages)) => {
+            let Some(formatter) = &loaded.formatter else {
+                return Err(bad_request(format!(
+                    "Model {} has no chat template, send a prompt instead of messages",
+                    request.model
+                )));
+            };
+            let conversation = Conversation {
+                messages: &messages,
+                add_generation_prompt: request
+                    .add_generation_prompt
+                    .or(special_tokens.add_generation_prompt)
+                    .unwrap_or(true),
+            };
+            formatter
+                .render(&conversation)
+                .map_err(|err| bad_request(format!("Failed to apply the chat template: {err}")))?
+        }
+        _ => return Err(bad_request("Provide exactly one of prompt and messages")),
+    };
+    let mut tokens = loaded
+        .tokenizer
+        .encode(&text)
+        .map_err(|err| ErrorResponse::from_anyhow(err, "Failed to tokenize"))?
+        .token_ids;
+    // The same tokens as the preprocessor
+    if let Some(bos_token_id) = loaded.bos_token_id {
+        special_tokens.apply_bos(&mut tokens, bos_token_id);
+    }
+    Ok(Json(TokenizeResponse {
+        model: request.model,
+        count: tokens.len(),
+        tokens,
+        max_model_len: lazy.context_length(),
+    }))
+}
+
+async fn detokenize(
+    State(state): State<Arc<service_v2::State>>,
+    Json(request): Json<DetokenizeRequest>,
+) -> Result<Json<DetokenizeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    check_ready(&state)?;
+    let (loaded, _) = loaded_tokenizer(&state, &request.model).await?;
+    let prompt = loaded
+        .tokenizer
+        .decode(&request.tokens, false)
+        .map_err(|err| bad_request(format!("Failed to detokenize: {err}")))?;
+    Ok(Json(DetokenizeResponse {
+        model: request.model,
+        prompt,
+    }))
+}
+
+/// Create an Axum [`Router`] for `/v1/tokenize` and `/v1/detokenize`
+pub fn tokenize_router(state: Arc<service_v2::State>) -> (Vec<RouteDoc>, Router) {
+    let tokenize_path = "/v1/tokenize";
+    let detokenize_path = "/v1/detokenize";
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, tokenize_path),
+        RouteDoc::new(axum::http::Method::POST, detokenize_path),
+    ];
+    let router = Router::new()
+        .route(tokenize_path, post(tokenize))
+        .route(detokenize_path, post(detokenize))
+        .with_state(state);
+    (docs, router)
+}
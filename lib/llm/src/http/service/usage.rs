@@ -0,0 +1,465 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Usage accounting, for billing and chargeback. Every generation request for a model that exists
+//! produces one [`UsageRecord`] when it ends: who sent it, the model, its prompt and completion
+//! tokens, how long it took and the worker that served it. Records go to [`UsageSink`]s in the
+//! order requests end:
+//! - [`JsonlSink`] appends them to a file, one JSON object per line,
+//! - [`NatsSink`] publishes them on a NATS subject,
+//! - [`WebhookSink`] POSTs them as JSON to a URL.
+//!
+//! When the sinks can't keep up, records are dropped with a warning rather than piling up in
+//! memory, and counted by [`UsageAccounting::dropped`].
+//!
+//! Token counts come from the preprocessor, so they are 0 for models whose workers take OpenAI
+//! requests directly. The worker is known when the ingress picks it, with KV routing or
+//! rescheduling, or when the request was pinned to one. Responses served from the response cache
+//! are accounted with the token counts they were generated with, and no worker.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use dynamo_runtime::engine::{AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Mutex};
+
+use super::metrics::Endpoint;
+use crate::kv_router::ANNOTATION_WORKER_INSTANCE_ID;
+use crate::protocols::openai::nvext::NvExt;
+
+/// How long a sink gets to deliver a record
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Records waiting for the sinks. Further records are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageStatus {
+    Success,
+    Error,
+    /// The client went away before the response was complete
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub request_id: String,
+
+    /// When the request ended
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+
+    /// The API key that sent it, see [`super::auth::Principal`]. None without authentication.
+    pub principal: Option<String>,
+
+    /// From `nvext.tenant`
+    pub tenant: Option<String>,
+
+    /// The model the client asked for
+    pub model: String,
+
+    /// `chat_completions`, `completions`, `responses` or `ollama`
+    pub endpoint: String,
+
+    pub streaming: bool,
+    pub status: UsageStatus,
+    pub prompt_tokens: usize,
+
+    /// Of all the choices together
+    pub completion_tokens: usize,
+
+    /// From sending the request to the engine to the last response
+    pub latency_ms: u64,
+
+    /// Instance id of the worker that served the request, if the ingress knows it
+    pub worker_id: Option<i64>,
+}
+
+/// Somewhere to send usage records
+#[async_trait]
+pub trait UsageSink: Send + Sync {
+    async fn record(&self, record: &UsageRecord) -> anyhow::Result<()>;
+}
+
+/// Sends usage records to the sinks in the background
+pub struct UsageAccounting {
+    tx: mpsc::Sender<UsageRecord>,
+
+    /// Records dropped because the sinks were behind
+    dropped: AtomicU64,
+}
+
+impl UsageAccounting {
+    /// Must be called on a Tokio runtime, which delivers the records
+    pub fn new(sinks: Vec<Arc<dyn UsageSink>>) -> Arc<Self> {
+        let (tx, mut rx) = mpsc::channel::<UsageRecord>(QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some(record) = rx.recv().await {
+                for sink in &sinks {
+                    match tokio::time::timeout(SEND_TIMEOUT, sink.record(&record)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => {
+                            tracing::error!(%err, request_id = record.request_id, "Failed sending usage record")
+                        }
+                        Err(_) => {
+                            tracing::error!(
+                                request_id = record.request_id,
+                                "Timeout sending usage record"
+                            )
+                        }
+                    }
+                }
+            }
+        });
+        Arc::new(UsageAccounting {
+            tx,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queue a record for the sinks
+    pub fn record(&self, record: UsageRecord) {
+        let (record, reason) = match self.tx.try_send(record) {
+            Ok(()) => return,
+            Err(TrySendError::Full(record)) => (record, "Usage sinks are behind"),
+            Err(TrySendError::Closed(record)) => (record, "Usage accounting stopped"),
+        };
+        let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::warn!(
+            request_id = record.request_id,
+            dropped,
+            "{reason}, dropping usage record"
+        );
+    }
+
+    /// How many records were dropped so far because the sinks couldn't keep up
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Accounts for one request, sends its record when dropped
+pub(super) struct UsageTracker {
+    accounting: Arc<UsageAccounting>,
+    record: UsageRecord,
+    start: Instant,
+    /// The client asked for the worker annotation itself, so it is passed on
+    show_worker: bool,
+    /// The engine accepted the request
+    started: bool,
+    finished: bool,
+}
+
+impl UsageTracker {
+    /// Start accounting for a request if accounting is on. Asks the router for the worker it
+    /// picks, by adding its annotation to `nvext`. Call it after the principal was applied.
+    pub(super) fn start(
+        accounting: Option<&Arc<UsageAccounting>>,
+        request_id: &str,
+        model: &str,
+        endpoint: Endpoint,
+        streaming: bool,
+        nvext: &mut Option<NvExt>,
+    ) -> Option<UsageTracker> {
+        let accounting = accounting?.clone();
+        let nvext = nvext.get_or_insert_with(NvExt::default);
+        let annotations = nvext.annotations.get_or_insert_with(Vec::new);
+        let show_worker = annotations
+            .iter()
+            .any(|a| a == ANNOTATION_WORKER_INSTANCE_ID);
+        if !show_worker {
+            annotations.push(ANNOTATION_WORKER_INSTANCE_ID.to_string());
+        }
+        Some(UsageTracker {
+            accounting,
+            record: UsageRecord {
+                request_id: request_id.to_string(),
+                timestamp: chrono::Utc::now(),
+                principal: nvext.principal.clone(),
+                tenant: nvext.tenant.clone(),
+                model: model.to_string(),
+                endpoint: endpoint.as_str().to_string(),
+                streaming,
+                status: UsageStatus::Success,
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                latency_ms: 0,
+                worker_id: None,
+            },
+            start: Instant::now(),
+            show_worker,
+            started: false,
+            finished: false,
+        })
+    }
+
+    /// Count the tokens of a response. False if it is the worker annotation and the client
+    /// didn't ask for it.
+    fn observe<T>(&mut self, annotated: &Annotated<T>) -> bool {
+        match annotated.event.as_deref() {
+            Some("error") => self.record.status = UsageStatus::Error,
+            Some(ANNOTATION_WORKER_INSTANCE_ID) => {
+                if self.record.worker_id.is_none() {
+                    self.record.worker_id = annotated
+                        .comment
+                        .as_ref()
+                        .and_then(|comment| comment.first())
+                        .and_then(|id| id.parse().ok());
+                }
+                return self.show_worker;
+            }
+            _ => {}
+        }
+        if let Some(input_tokens) = annotated.input_tokens {
+            self.record.prompt_tokens = self.record.prompt_tokens.max(input_tokens);
+        }
+        self.record.completion_tokens += annotated.chunk_tokens.unwrap_or(0);
+        true
+    }
+}
+
+impl Drop for UsageTracker {
+    fn drop(&mut self) {
+        let mut record = self.record.clone();
+        if !self.started {
+            record.status = UsageStatus::Error;
+        } else if !self.finished && record.status == UsageStatus::Success {
+            record.status = UsageStatus::Cancelled;
+        }
+        record.timestamp = chrono::Utc::now();
+        record.latency_ms = self.start.elapsed().as_millis() as u64;
+        self.accounting.record(record);
+    }
+}
+
+/// Account for the responses of `stream` with `tracker`, if there is one. The record is sent
+/// when the stream ends or is dropped.
+pub(super) fn track<T: Send + 'static>(
+    tracker: Option<UsageTracker>,
+    stream: ManyOut<Annotated<T>>,
+) -> ManyOut<Annotated<T>> {
+    let Some(mut tracker) = tracker else {
+        return stream;
+    };
+    tracker.started = true;
+    let ctx = stream.context();
+    let tracked = async_stream::stream! {
+        let mut stream = stream;
+        while let Some(annotated) = stream.next().await {
+            if tracker.observe(&annotated) {
+                yield annotated;
+            }
+        }
+        tracker.finished = true;
+    };
+    ResponseStream::new(Box::pin(tracked), ctx)
+}
+
+/// Append each [`UsageRecord`] to a file as a line of JSON
+pub struct JsonlSink {
+    file: Mutex<tokio::fs::File>,
+}
+
+impl JsonlSink {
+    pub async fn new(path: &Path) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed opening usage log {}: {err}", path.display()))?;
+        Ok(JsonlSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl UsageSink for JsonlSink {
+    async fn record(&self, record: &UsageRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Publish each [`UsageRecord`] as JSON on a NATS subject
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsSink {
+    pub fn new(client: async_nats::Client, subject: String) -> Self {
+        NatsSink { client, subject }
+    }
+}
+
+#[async_trait]
+impl UsageSink for NatsSink {
+    async fn record(&self, record: &UsageRecord) -> anyhow::Result<()> {
+        let payload = serde_json::to_vec(record)?;
+        self.client
+            .publish(self.subject.clone(), payload.into())
+            .await?;
+        Ok(())
+    }
+}
+
+/// POST each [`UsageRecord`] as JSON
+pub struct WebhookSink {
+    url: url::Url,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(WebhookSink {
+            url: url.parse()?,
+            client: reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl UsageSink for WebhookSink {
+    async fn record(&self, record: &UsageRecord) -> anyhow::Result<()> {
+        self.client
+            .post(self.url.clone())
+            .json(record)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::pipeline::context::Controller;
+
+    struct Collect(tokio::sync::mpsc::UnboundedSender<UsageRecord>);
+
+    #[async_trait]
+    impl UsageSink for Collect {
+        async fn record(&self, record: &UsageRecord) -> anyhow::Result<()> {
+            self.0.send(record.clone())?;
+            Ok(())
+        }
+    }
+
+    fn chunk(input_tokens: usize, chunk_tokens: usize) -> Annotated<String> {
+        Annotated {
+            input_tokens: Some(input_tokens),
+            chunk_tokens: Some(chunk_tokens),
+            ..Annotated::from_data("text".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let accounting = UsageAccounting::new(vec![Arc::new(Collect(tx))]);
+        let mut nvext = Some(NvExt {
+            principal: Some("key-abc".to_string()),
+            ..Default::default()
+        });
+        let tracker = UsageTracker::start(
+            Some(&accounting),
+            "req-1",
+            "llama",
+            Endpoint::ChatCompletions,
+            true,
+            &mut nvext,
+        );
+        assert_eq!(
+            nvext.unwrap().annotations,
+            Some(vec![ANNOTATION_WORKER_INSTANCE_ID.to_string()])
+        );
+
+        let responses = vec![
+            Annotated::from_annotation(ANNOTATION_WORKER_INSTANCE_ID, &42i64).unwrap(),
+            chunk(10, 1),
+            chunk(10, 2),
+        ];
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(responses)),
+            Arc::new(Controller::default()),
+        );
+        let seen: Vec<_> = track(tracker, stream).collect().await;
+        // The client didn't ask for the worker
+        assert_eq!(seen.len(), 2);
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.principal.as_deref(), Some("key-abc"));
+        assert_eq!(record.endpoint, "chat_completions");
+        assert_eq!(record.status, UsageStatus::Success);
+        assert_eq!(record.prompt_tokens, 10);
+        assert_eq!(record.completion_tokens, 3);
+        assert_eq!(record.worker_id, Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let accounting = UsageAccounting::new(vec![Arc::new(Collect(tx))]);
+        let tracker = UsageTracker::start(
+            Some(&accounting),
+            "req-2",
+            "llama",
+            Endpoint::Completions,
+            true,
+            &mut None,
+        );
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(vec![chunk(5, 1), chunk(5, 1)])),
+            Arc::new(Controller::default()),
+        );
+        let mut tracked = track(tracker, stream);
+        tracked.next().await;
+        drop(tracked);
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.status, UsageStatus::Cancelled);
+        assert_eq!(record.completion_tokens, 1);
+    }
+
+    struct Stuck;
+
+    #[async_trait]
+    impl UsageSink for Stuck {
+        async fn record(&self, _record: &UsageRecord) -> anyhow::Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dropped_when_behind() {
+        let accounting = UsageAccounting::new(vec![Arc::new(Stuck)]);
+        // The delivery task doesn't run until we yield, so the queue fills up
+        for i in 0..QUEUE_SIZE + 2 {
+            drop(UsageTracker::start(
+                Some(&accounting),
+                &format!("req-{i}"),
+                "llama",
+                Endpoint::Completions,
+                false,
+                &mut None,
+            ));
+        }
+        assert_eq!(accounting.dropped(), 2);
+    }
+}
@@ -13,8 +13,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use hf_hub::api::tokio::ApiBuilder;
+use hf_hub::api::tokio::{ApiBuilder, ApiError};
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::path::{Path, PathBuf};
 
 const IGNORED: [&str; 5] = [
@@ -27,26 +29,145 @@ const IGNORED: [&str; 5] = [
 
 const HF_TOKEN_ENV_VAR: &str = "HF_TOKEN";
 
+/// Path to a JSON file of per-repository tokens, for when one token can't access every model.
+/// The keys are a repo name ("org/model"), all of an org's repos ("org/*"), or everything ("*"):
+/// `{"meta-llama/*": "hf_abc", "my-org/private-model": "hf_def"}`
+const HF_CREDENTIALS_FILE_ENV_VAR: &str = "DYN_HF_CREDENTIALS_FILE";
+
+/// Where the token used for a download came from, to explain auth failures
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenSource {
+    /// The entry with this key in the credentials file
+    CredentialsFile(String),
+    EnvVar,
+    /// `huggingface-cli login`, or no token at all
+    Default,
+}
+
+impl fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSource::CredentialsFile(key) => {
+                write!(f, "the token for '{key}' in ${HF_CREDENTIALS_FILE_ENV_VAR}")
+            }
+            TokenSource::EnvVar => write!(f, "the token in ${HF_TOKEN_ENV_VAR}"),
+            TokenSource::Default => {
+                write!(f, "the default token from `huggingface-cli login`, if any")
+            }
+        }
+    }
+}
+
+/// Per-repository tokens from the file in `DYN_HF_CREDENTIALS_FILE`
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(transparent)]
+struct Credentials {
+    tokens: HashMap<String, String>,
+}
+
+impl Credentials {
+    fn load() -> anyhow::Result<Self> {
+        let Ok(path) = env::var(HF_CREDENTIALS_FILE_ENV_VAR) else {
+            return Ok(Credentials::default());
+        };
+        let contents = std::fs::read_to_string(&path).map_err(|err| {
+            anyhow::anyhow!("Failed reading ${HF_CREDENTIALS_FILE_ENV_VAR} file '{path}': {err}")
+        })?;
+        serde_json::from_str(&contents).map_err(|err| {
+            anyhow::anyhow!(
+                "${HF_CREDENTIALS_FILE_ENV_VAR} file '{path}' must be a JSON object of repo name to token: {err}"
+            )
+        })
+    }
+
+    /// The most specific matching entry: the exact repo, then its org, then the catch-all
+    fn token_for(&self, repo: &str) -> Option<(&str, &str)> {
+        let org_key = repo.split_once('/').map(|(org, _)| format!("{org}/*"));
+        [Some(repo.to_string()), org_key, Some("*".to_string())]
+            .into_iter()
+            .flatten()
+            .find_map(|key| self.tokens.get_key_value(key.as_str()))
+            .map(|(key, token)| (key.as_str(), token.as_str()))
+    }
+}
+
+fn resolve_token(repo: &str) -> anyhow::Result<(Option<String>, TokenSource)> {
+    let credentials = Credentials::load()?;
+    if let Some((key, token)) = credentials.token_for(repo) {
+        return Ok((
+            Some(token.to_string()),
+            TokenSource::CredentialsFile(key.to_string()),
+        ));
+    }
+    match env::var(HF_TOKEN_ENV_VAR) {
+        Ok(token) if !token.is_empty() => Ok((Some(token), TokenSource::EnvVar)),
+        _ => Ok((None, TokenSource::Default)),
+    }
+}
+
+/// HTTP status of a failed Hub request, if it got that far
+fn http_status(err: &ApiError) -> Option<u16> {
+    match err {
+        ApiError::RequestError(err) => err.status().map(|s| s.as_u16()),
+        ApiError::TooManyRetries(err) => http_status(err),
+        _ => None,
+    }
+}
+
+/// Turn authentication failures into an error that says how to fix them
+fn download_error(
+    model_name: &str,
+    token_source: &TokenSource,
+    err: ApiError,
+    context: String,
+) -> anyhow::Error {
+    let license_page = format!("https://huggingface.co/{model_name}");
+    match http_status(&err) {
+        Some(401) => anyhow::anyhow!(
+            "{context}: Hugging Face requires authentication for '{model_name}' (401). \
+             It is probably a gated model. Accept its license at {license_page}, then set \
+             ${HF_TOKEN_ENV_VAR} to a token from https://huggingface.co/settings/tokens, or add \
+             one for this repo to ${HF_CREDENTIALS_FILE_ENV_VAR}. Used {token_source}."
+        ),
+        Some(403) => anyhow::anyhow!(
+            "{context}: the Hugging Face token does not have access to '{model_name}' (403). \
+             Request access / accept the license at {license_page} with the account that owns \
+             the token. Fine-grained tokens also need 'Read access to contents of all public \
+             gated repos you can access'. Used {token_source}."
+        ),
+        _ => anyhow::anyhow!("{context}: {err}"),
+    }
+}
+
 /// Attempt to download a model from Hugging Face
 /// Returns the directory it is in
 pub async fn from_hf(name: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
     let name = name.as_ref();
-    let token = env::var(HF_TOKEN_ENV_VAR).ok();
-    let api = ApiBuilder::new()
-        .with_progress(true)
-        .with_token(token)
-        .build()?;
     let model_name = name.display().to_string();
+    let (token, token_source) = resolve_token(&model_name)?;
+    let mut builder = ApiBuilder::new().with_progress(true);
+    if token.is_some() {
+        // Otherwise keep the token hf_hub found in its cache
+        builder = builder.with_token(token);
+    }
+    let api = builder.build()?;
 
     let repo = api.model(model_name.clone());
 
     let info = match repo.info().await {
         Ok(info) => info,
-        Err(e) => {
+        Err(e) if http_status(&e) == Some(404) => {
             return Err(anyhow::anyhow!(
-                "Failed to fetch model '{}' from HuggingFace: {}. Is this a valid HuggingFace ID?",
-                model_name,
-                e
+                "Model '{model_name}' not found on HuggingFace. Is this a valid HuggingFace ID? \
+                 Private repos also return this without a token, used {token_source}."
+            ));
+        }
+        Err(e) => {
+            return Err(download_error(
+                &model_name,
+                &token_source,
+                e,
+                format!("Failed to fetch model '{model_name}' from HuggingFace"),
             ));
         }
     };
@@ -72,11 +193,14 @@ pub async fn from_hf(name: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
                 files_downloaded = true;
             }
             Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "Failed to download file '{}' from model '{}': {}",
-                    sib.rfilename,
-                    model_name,
-                    e
+                return Err(download_error(
+                    &model_name,
+                    &token_source,
+                    e,
+                    format!(
+                        "Failed to download file '{}' from model '{model_name}'",
+                        sib.rfilename
+                    ),
                 ));
             }
         }
@@ -103,3 +227,26 @@ fn is_image(s: &str) -> bool {
         || s.ends_with(".jpeg")
         || s.ends_with("JPEG")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_token_for() {
+        let credentials: Credentials = serde_json::from_str(
+            r#"{"meta-llama/*": "org", "meta-llama/Llama-3.2-1B": "repo", "*": "any"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            credentials.token_for("meta-llama/Llama-3.2-1B"),
+            Some(("meta-llama/Llama-3.2-1B", "repo"))
+        );
+        assert_eq!(
+            credentials.token_for("meta-llama/Llama-3.2-3B"),
+            Some(("meta-llama/*", "org"))
+        );
+        assert_eq!(credentials.token_for("Qwen/Qwen3-0.6B"), Some(("*", "any")));
+        assert_eq!(Credentials::default().token_for("Qwen/Qwen3-0.6B"), None);
+    }
+}
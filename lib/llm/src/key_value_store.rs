@@ -35,6 +35,9 @@ pub use nats::NATSStorage;
 mod etcd;
 pub use etcd::EtcdStorage;
 
+/// How many past revisions of each published object we keep, so that we can roll back to them.
+pub const REVISION_HISTORY_LEN: usize = 5;
+
 #[async_trait]
 pub trait KeyValueStore: Send + Sync {
     async fn get_or_create_bucket(
@@ -117,28 +120,169 @@ impl KeyValueStoreManager {
         (watch_task, rx)
     }
 
+    /// Publish `obj` as a new revision of `key`. A key pinned by
+    /// [`KeyValueStoreManager::rollback`] is left as it is, and so is one with the same content
+    /// unless the bucket has a TTL.
     pub async fn publish<T: Serialize + Versioned + Send + Sync>(
         &self,
         bucket_name: &str,
         bucket_ttl: Option<Duration>,
         key: &str,
         obj: &mut T,
+    ) -> anyhow::Result<StorageOutcome> {
+        self.put(bucket_name, bucket_ttl, key, obj, false).await
+    }
+
+    async fn put<T: Serialize + Versioned + Send + Sync>(
+        &self,
+        bucket_name: &str,
+        bucket_ttl: Option<Duration>,
+        key: &str,
+        obj: &mut T,
+        replace_pinned: bool,
     ) -> anyhow::Result<StorageOutcome> {
         let obj_json = serde_json::to_string(obj)?;
         let bucket = self.0.get_or_create_bucket(bucket_name, bucket_ttl).await?;
 
-        let outcome = bucket
-            .insert(key.to_string(), obj_json, obj.revision())
+        let keep = match bucket.get(key).await? {
+            None => false,
+            Some(_) if !replace_pinned && self.pinned(bucket_name, key).await?.is_some() => {
+                tracing::debug!(bucket_name, key, "Pinned by a rollback, not replacing it");
+                true
+            }
+            // With a TTL the write keeps it alive
+            Some(current) => bucket_ttl.is_none() && same_content::<T>(&current, &obj_json),
+        };
+        if keep {
+            return Ok(StorageOutcome::Exists(obj.revision()));
+        }
+        let mut outcome = bucket
+            .insert(key.to_string(), obj_json.clone(), obj.revision())
             .await?;
+        if let StorageOutcome::Exists(revision) = outcome {
+            if obj.revision() == 0 {
+                // Published before, by another worker or an earlier run of this one. Replace it,
+                // so that every publish is a new revision.
+                outcome = bucket
+                    .insert(key.to_string(), obj_json.clone(), revision)
+                    .await?;
+            }
+        }
 
         match outcome {
-            StorageOutcome::Created(revision) | StorageOutcome::Exists(revision) => {
+            StorageOutcome::Created(revision) => {
+                obj.set_revision(revision);
+                if let Err(err) = self
+                    .record_revision(bucket_name, key, revision, &obj_json)
+                    .await
+                {
+                    // The object is published, only rollback is affected
+                    tracing::warn!(bucket_name, key, revision, %err, "Failed recording revision history");
+                }
+            }
+            StorageOutcome::Exists(revision) => {
                 obj.set_revision(revision);
             }
         }
         Ok(outcome)
     }
 
+    /// The revisions of `key` we can roll back to, oldest first
+    pub async fn revisions(&self, bucket_name: &str, key: &str) -> Result<Vec<u64>, StorageError> {
+        let Some(history) = self.0.get_bucket(&history_bucket_name(bucket_name)).await? else {
+            return Ok(vec![]);
+        };
+        history_revisions(history.as_ref(), key).await
+    }
+
+    /// The revision `key` was rolled back to, if it is pinned there
+    pub async fn pinned(&self, bucket_name: &str, key: &str) -> Result<Option<u64>, StorageError> {
+        let Some(history) = self.0.get_bucket(&history_bucket_name(bucket_name)).await? else {
+            return Ok(None);
+        };
+        let Some(pin) = history.get(&pin_key(key)).await? else {
+            return Ok(None);
+        };
+        Ok(serde_json::from_slice(&pin)?)
+    }
+
+    /// Let publishers replace `key` again. False if it was not pinned.
+    pub async fn unpin(&self, bucket_name: &str, key: &str) -> Result<bool, StorageError> {
+        if self.pinned(bucket_name, key).await?.is_none() {
+            return Ok(false);
+        }
+        if let Some(history) = self.0.get_bucket(&history_bucket_name(bucket_name)).await? {
+            history.delete(&pin_key(key)).await?;
+        }
+        Ok(true)
+    }
+
+    /// Make an earlier revision of `key` the current one again.
+    ///
+    /// The old value is published as a new revision, in a single write, so every consumer that
+    /// loads the object afterwards gets the rolled back value. The history is kept, so a rollback
+    /// can itself be rolled back. Returns the object with it's new revision.
+    ///
+    /// The key stays pinned to the old value until [`KeyValueStoreManager::unpin`], so that
+    /// publishers, e.g. a worker that restarts with the newer value, don't undo the rollback.
+    pub async fn rollback<T: for<'a> Deserialize<'a> + Serialize + Versioned + Send + Sync>(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        to_revision: u64,
+    ) -> anyhow::Result<T> {
+        let Some(history) = self.0.get_bucket(&history_bucket_name(bucket_name)).await? else {
+            anyhow::bail!("No revision history for '{key}' in bucket '{bucket_name}'");
+        };
+        let available = history_revisions(history.as_ref(), key).await?;
+        let Some(old_json) = history.get(&history_key(key, to_revision)).await? else {
+            anyhow::bail!(
+                "Revision {to_revision} of '{key}' is not in the history. Available: {available:?}"
+            );
+        };
+        let mut obj: T = serde_json::from_slice(&old_json)?;
+        // Pin first, a publisher that comes in between must not replace it. It may be pinned to
+        // another revision already, and insert doesn't replace.
+        history.delete(&pin_key(key)).await?;
+        history
+            .insert(pin_key(key), to_revision.to_string(), 0)
+            .await?;
+        // Replace the newest revision we know of
+        obj.set_revision(available.last().copied().unwrap_or(0));
+        let outcome = self.put(bucket_name, None, key, &mut obj, true).await?;
+        tracing::info!(
+            bucket_name,
+            key,
+            to_revision,
+            %outcome,
+            "Rolled back to earlier revision"
+        );
+        Ok(obj)
+    }
+
+    /// Store a copy of this revision and drop those older than [`REVISION_HISTORY_LEN`]
+    pub(crate) async fn record_revision(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        revision: u64,
+        value: &str,
+    ) -> Result<(), StorageError> {
+        let history = self
+            .0
+            .get_or_create_bucket(&history_bucket_name(bucket_name), None)
+            .await?;
+        history
+            .insert(history_key(key, revision), value.to_string(), 0)
+            .await?;
+        let revisions = history_revisions(history.as_ref(), key).await?;
+        let excess = revisions.len().saturating_sub(REVISION_HISTORY_LEN);
+        for old in &revisions[..excess] {
+            history.delete(&history_key(key, *old)).await?;
+        }
+        Ok(())
+    }
+
     /// Re-publish the model card to the store regularly. Spawns a task and returns.
     /// Takes most arguments by value because it will hold on to them in the publish task.
     /// Deletes the card on cancellation.
@@ -193,6 +337,64 @@ impl KeyValueStoreManager {
     }
 }
 
+/// Past revisions of the objects in a bucket live in a sibling bucket
+fn history_bucket_name(bucket_name: &str) -> String {
+    format!("{bucket_name}-history")
+}
+
+fn history_key(key: &str, revision: u64) -> String {
+    format!("{key}_r{revision}")
+}
+
+/// Holds the revision `key` was rolled back to, while it is pinned
+fn pin_key(key: &str) -> String {
+    format!("{key}_pinned")
+}
+
+/// Whether the `stored` value of an object says the same as `obj_json`. The fields that change
+/// on every publish don't count.
+pub(crate) fn same_content<T: Versioned>(stored: &[u8], obj_json: &str) -> bool {
+    let (Ok(mut stored), Ok(mut ours)) = (
+        serde_json::from_slice::<serde_json::Value>(stored),
+        serde_json::from_str::<serde_json::Value>(obj_json),
+    ) else {
+        return false;
+    };
+    for value in [&mut stored, &mut ours] {
+        if let Some(fields) = value.as_object_mut() {
+            for field in T::UNVERSIONED_FIELDS {
+                fields.remove(*field);
+            }
+        }
+    }
+    stored == ours
+}
+
+/// The revision in a history bucket key, if that key belongs to `key`. Some stores return keys
+/// with the bucket as a path prefix, some without.
+fn parse_history_key(stored_key: &str, key: &str) -> Option<u64> {
+    let name = stored_key.rsplit('/').next()?;
+    let (name, revision) = name.rsplit_once("_r")?;
+    if Slug::slugify(name).to_string() != Slug::slugify(key).to_string() {
+        return None;
+    }
+    revision.parse().ok()
+}
+
+async fn history_revisions(
+    history: &dyn KeyValueBucket,
+    key: &str,
+) -> Result<Vec<u64>, StorageError> {
+    let mut revisions: Vec<u64> = history
+        .entries()
+        .await?
+        .keys()
+        .filter_map(|k| parse_history_key(k, key))
+        .collect();
+    revisions.sort_unstable();
+    Ok(revisions)
+}
+
 /// An online storage for key-value config values.
 /// Usually backed by `nats-server`.
 #[async_trait]
@@ -269,6 +471,9 @@ pub enum StorageError {
 /// A trait allowing to get/set a revision on an object.
 /// NATS uses this to ensure atomic updates.
 pub trait Versioned {
+    /// Top level fields that change on every publish, without changing what the object says
+    const UNVERSIONED_FIELDS: &'static [&'static str] = &[];
+
     fn revision(&self) -> u64;
     fn set_revision(&mut self, r: u64);
 }
@@ -315,6 +520,119 @@ mod tests {
         dynamo_runtime::logging::init();
     }
 
+    #[test]
+    fn test_parse_history_key() {
+        assert_eq!(parse_history_key("llama_r3", "llama"), Some(3));
+        assert_eq!(
+            parse_history_key("mdc-history/llama_r12", "llama"),
+            Some(12)
+        );
+        // Key names may contain the separator
+        assert_eq!(parse_history_key("my_rig_r2", "my_rig"), Some(2));
+        assert_eq!(parse_history_key("other_r3", "llama"), None);
+        assert_eq!(parse_history_key("llama", "llama"), None);
+    }
+
+    #[tokio::test]
+    async fn test_revision_history() -> anyhow::Result<()> {
+        init();
+
+        let manager = KeyValueStoreManager::new(Box::new(MemoryStorage::new()));
+        let newest = REVISION_HISTORY_LEN as u64 + 2;
+        for revision in 1..=newest {
+            manager
+                .record_revision(BUCKET_NAME, "llama", revision, &revision.to_string())
+                .await?;
+        }
+        manager.record_revision(BUCKET_NAME, "qwen", 1, "1").await?;
+
+        // Only the newest are kept, other keys are not affected
+        let revisions = manager.revisions(BUCKET_NAME, "llama").await?;
+        assert_eq!(revisions, (3..=newest).collect::<Vec<_>>());
+        assert_eq!(manager.revisions(BUCKET_NAME, "qwen").await?, vec![1]);
+        assert!(manager.revisions("other", "llama").await?.is_empty());
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Card {
+        name: String,
+        #[serde(default, skip_serializing)]
+        revision: u64,
+    }
+
+    impl Versioned for Card {
+        fn revision(&self) -> u64 {
+            self.revision
+        }
+
+        fn set_revision(&mut self, revision: u64) {
+            self.revision = revision;
+        }
+    }
+
+    #[test]
+    fn test_same_content() {
+        struct Published;
+        impl Versioned for Published {
+            const UNVERSIONED_FIELDS: &'static [&'static str] = &["published"];
+            fn revision(&self) -> u64 {
+                0
+            }
+            fn set_revision(&mut self, _: u64) {}
+        }
+        let stored = br#"{"name": "llama", "published": 1}"#;
+        assert!(same_content::<Published>(
+            stored,
+            r#"{"name":"llama","published":2}"#
+        ));
+        assert!(!same_content::<Published>(
+            stored,
+            r#"{"name":"qwen","published":1}"#
+        ));
+        assert!(!same_content::<Card>(
+            stored,
+            r#"{"name":"llama","published":2}"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_pins() -> anyhow::Result<()> {
+        init();
+
+        let manager = KeyValueStoreManager::new(Box::new(MemoryStorage::new()));
+        let card = |name: &str| Card {
+            name: name.to_string(),
+            revision: 0,
+        };
+        // The memory store takes the revisions it is given, it doesn't count them
+        manager
+            .publish(BUCKET_NAME, None, "llama", &mut card("v1"))
+            .await?;
+        let bucket = manager.0.get_or_create_bucket(BUCKET_NAME, None).await?;
+        bucket
+            .insert("llama".to_string(), serde_json::to_string(&card("v2"))?, 7)
+            .await?;
+
+        let rolled_back: Card = manager.rollback(BUCKET_NAME, "llama", 0).await?;
+        assert_eq!(rolled_back.name, "v1");
+        assert_eq!(manager.pinned(BUCKET_NAME, "llama").await?, Some(0));
+
+        // A publisher that comes back with the newer card doesn't undo the rollback
+        let outcome = manager
+            .publish(BUCKET_NAME, None, "llama", &mut card("v2"))
+            .await?;
+        assert!(matches!(outcome, StorageOutcome::Exists(_)));
+        let current: Option<Card> = manager
+            .load(BUCKET_NAME, &Slug::from_string("llama"))
+            .await?;
+        assert_eq!(current.map(|c| c.name).as_deref(), Some("v1"));
+
+        assert!(manager.unpin(BUCKET_NAME, "llama").await?);
+        assert!(!manager.unpin(BUCKET_NAME, "llama").await?);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_memory_storage() -> anyhow::Result<()> {
         init();
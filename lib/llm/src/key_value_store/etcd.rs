@@ -33,6 +33,11 @@ impl EtcdStorage {
     pub fn new(client: Client) -> Self {
         Self { client }
     }
+
+    /// The etcd key `key` of bucket `bucket_name` is stored under
+    pub fn key(bucket_name: &str, key: &str) -> String {
+        make_key(bucket_name, key)
+    }
 }
 
 #[async_trait]
@@ -98,9 +103,12 @@ impl KeyValueBucket for EtcdBucket {
     }
 
     async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let k = make_key(&self.bucket_name, key);
+        tracing::trace!("etcd delete: {k}");
+
         let _ = self
             .client
-            .kv_delete(key, None)
+            .kv_delete(k, None)
             .await
             .map_err(|e| StorageError::EtcdError(e.to_string()))?;
         Ok(())
@@ -204,7 +212,7 @@ impl EtcdBucket {
             return Err(StorageError::MissingKey(key.to_string()));
         }
         let current_version = kvs.first().unwrap().version() as u64;
-        if current_version != version + 1 {
+        if current_version != version {
             tracing::warn!(
                 current_version,
                 attempted_next_version = version,
@@ -226,9 +234,8 @@ impl EtcdBucket {
             // Version of new key is always 1.
             // <https://etcd.io/docs/v3.5/learning/data_model/>
             None => StorageOutcome::Created(1),
-            // Expected case, success
-            Some(kv) if kv.version() as u64 == version + 1 => StorageOutcome::Created(version),
-            // Should this be an error? Something updated the version between our get and put
+            // Expected case is kv.version() == version. If not something updated the key between
+            // our get and put, we overwrote it anyway. Either way ours is the next version.
             Some(kv) => StorageOutcome::Created(kv.version() as u64 + 1),
         })
     }
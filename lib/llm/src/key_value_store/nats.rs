@@ -241,11 +241,10 @@ impl NATSBucket {
     ) -> Result<StorageOutcome, StorageError> {
         match self.nats_store.entry(&key).await {
             Ok(Some(entry)) => {
-                // Re-try the update with new version number
-                let next_rev = entry.revision + 1;
+                // Re-try the update against the current revision
                 match self
                     .nats_store
-                    .update(key.clone(), value.into(), next_rev)
+                    .update(key.clone(), value.into(), entry.revision)
                     .await
                 {
                     Ok(correct_revision) => Ok(StorageOutcome::Created(correct_revision)),
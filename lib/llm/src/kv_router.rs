@@ -1,20 +1,27 @@
 // SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use dynamo_runtime::{
     component::{Component, InstanceSource},
     pipeline::{
-        async_trait, AsyncEngine, AsyncEngineContextProvider, Error, ManyOut, PushRouter,
-        ResponseStream, SingleIn,
+        async_trait, network::Ingress, AsyncEngine, AsyncEngineContextProvider, Error, ManyOut,
+        PushRouter, ResponseStream, SingleIn,
     },
     prelude::*,
     protocols::annotated::Annotated,
 };
 use futures::stream::{self, StreamExt};
+use tokio::sync::mpsc;
 
+pub mod affinity;
+pub mod block_sizes;
+pub mod circuit_breaker;
+pub mod cluster;
 pub mod indexer;
 pub mod metrics_aggregator;
 pub mod protocols;
@@ -22,15 +29,29 @@ pub mod publisher;
 pub mod recorder;
 pub mod scheduler;
 pub mod scoring;
+pub mod snapshot;
 
 use crate::{
     kv_router::{
-        indexer::{KvIndexer, KvIndexerInterface, RouterEvent},
-        metrics_aggregator::KvMetricsAggregator,
-        protocols::{LocalBlockHash, RouterRequest, RouterResponse, WorkerSelectionResult},
-        scheduler::{KvScheduler, KvSchedulerError, SchedulingRequest},
-        scoring::ProcessedEndpoints,
+        affinity::{SessionAffinity, SessionAffinityConfig, SessionKey},
+        block_sizes::BlockSizeIndexers,
+        circuit_breaker::{CircuitBreakerConfig, WorkerHealth},
+        cluster::ClusterCandidate,
+        indexer::{
+            compute_block_hash_for_seq_with_lora, decode_router_events, IndexerConfig,
+            IndexerLimits,
+        },
+        metrics_aggregator::{ClusterMetrics, KvHitRates, KvMetricsAggregator},
+        protocols::{
+            LocalBlockHash, RouterRequest, RouterResponse, RoutingExplanation, WorkerScore,
+            WorkerSelectionResult, ROUTER_PROTOCOL_VERSION,
+        },
+        recorder::{KvRoutingRecorder, RoutingRecord},
+        scheduler::{KvScheduler, KvSchedulerError, Placement, SchedulingRequest},
+        scoring::{BalancedWeights, ProcessedEndpoints},
+        snapshot::IndexSnapshotConfig,
     },
+    lora::LoraRegistry,
     preprocessor::PreprocessedRequest,
     protocols::common::llm_backend::LLMEngineOutput,
     tokens::TokenBlockSequence,
@@ -44,6 +65,51 @@ pub const KV_EVENT_SUBJECT: &str = "kv_events";
 pub const KV_HIT_RATE_SUBJECT: &str = "kv-hit-rate";
 pub const KV_METRICS_ENDPOINT: &str = "load_metrics";
 
+/// The balanced selector of a model takes weights from the etcd key with this prefix and the
+/// model name, see [`scheduler::BalancedWorkerSelector::watch_weights`]
+pub const KV_ROUTER_WEIGHTS_ROOT_PATH: &str = "public/components/kv_router/weights/";
+
+/// Endpoint on which [`serve_scheduler`] answers [`RouterRequest`]s
+pub const KV_SCHEDULER_ENDPOINT: &str = "generate";
+
+/// Requests with this annotation get the instance id of the worker the router sent them to, as
+/// the first response
+pub const ANNOTATION_WORKER_INSTANCE_ID: &str = "worker_instance_id";
+
+/// Requests with this annotation get a [`RoutingExplanation`] of why the KV router chose their
+/// worker, as the first response
+pub const ANNOTATION_ROUTING_EXPLANATION: &str = "routing_explanation";
+
+/// Start `responses` with the [`ANNOTATION_WORKER_INSTANCE_ID`] annotation if `annotate`
+pub(crate) fn with_worker_annotation(
+    annotate: bool,
+    instance_id: i64,
+    responses: ManyOut<Annotated<LLMEngineOutput>>,
+) -> ManyOut<Annotated<LLMEngineOutput>> {
+    if !annotate {
+        return responses;
+    }
+    let ctx = responses.context();
+    // safety: An i64 always serializes
+    let annotation =
+        Annotated::from_annotation(ANNOTATION_WORKER_INSTANCE_ID, &instance_id).unwrap();
+    ResponseStream::new(Box::pin(stream::iter([annotation]).chain(responses)), ctx)
+}
+
+/// The hashes of the complete blocks of `block_size` tokens of `tokens`, for the LoRA adapter
+/// `lora_id`
+pub(crate) fn block_hashes(tokens: &[u32], block_size: usize, lora_id: u64) -> Vec<LocalBlockHash> {
+    if lora_id != 0 {
+        return compute_block_hash_for_seq_with_lora(tokens, block_size, lora_id);
+    }
+    let (complete_blocks, _partial_block) =
+        TokenBlockSequence::split_tokens(tokens, block_size, 1337_u64);
+    complete_blocks
+        .into_iter()
+        .map(|block| LocalBlockHash(block.block_hash()))
+        .collect()
+}
+
 /// A trait that users can implement to define custom selection logic
 pub trait WorkerSelector {
     fn select_worker(
@@ -52,6 +118,28 @@ pub trait WorkerSelector {
         request: &SchedulingRequest,
         block_size: usize,
     ) -> Result<WorkerSelectionResult, KvSchedulerError>;
+
+    /// The score of every worker for `request` and how it was computed, for
+    /// [`KvRouter::explain`]. Selectors that don't implement it explain nothing.
+    fn score_workers(
+        &self,
+        _workers: &ProcessedEndpoints,
+        _request: &SchedulingRequest,
+        _block_size: usize,
+    ) -> Vec<WorkerScore> {
+        Vec::new()
+    }
+}
+
+/// A trait to define how a router over several clusters picks one, the way a [`WorkerSelector`]
+/// picks a worker of a cluster. See [`cluster::HierarchicalRouter`].
+pub trait ClusterSelector {
+    /// The index in `clusters` of the cluster for a prompt of `isl_blocks` complete blocks
+    fn select_cluster(
+        &self,
+        clusters: &[ClusterCandidate],
+        isl_blocks: usize,
+    ) -> Result<usize, KvSchedulerError>;
 }
 
 /// KV Router configuration parameters
@@ -68,6 +156,61 @@ pub struct KvRouterConfig {
     /// Weight for waiting requests in worker selection.
     /// Higher values avoid workers with queued requests. Default: 1.0
     pub waiting_requests_weight: f64,
+
+    /// Bounds on the index of the workers' KV blocks. Default: unbounded
+    pub indexer_limits: IndexerLimits,
+
+    /// Shards of the index, each applying the events of its workers on its own thread.
+    /// More keep up with the events of more workers. Default: 1
+    pub index_shards: usize,
+
+    /// How the index is kept across restarts. Default: it isn't
+    pub index_snapshot: IndexSnapshotConfig,
+
+    /// How workers are scored. Default: [`WorkerSelectorKind::Default`]
+    pub selector: WorkerSelectorKind,
+
+    /// Weight for the share of KV blocks in use, with the balanced selector.
+    /// Higher values spread the load over the workers. Default: 1.0
+    pub active_blocks_weight: f64,
+
+    /// Weight for decode throughput, with the balanced selector.
+    /// Higher values prefer workers that generate faster. Default: 0.5
+    pub decode_throughput_weight: f64,
+
+    /// Weight for not having the request's LoRA adapter loaded in worker selection.
+    /// Higher values keep the requests of an adapter on the workers that have it. Default: 1.0
+    pub lora_miss_weight: f64,
+
+    /// Record the KV events, worker load and routing decisions to this JSONL file, for
+    /// [`recorder::replay`]. Default: not recorded
+    pub record_path: Option<PathBuf>,
+
+    /// When to stop sending requests to a failing worker. Default: after 5 failures in a row,
+    /// for 30 seconds
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// How long and how many sessions keep their worker. Default: 100000 sessions, for 10 minutes
+    /// after their last request
+    pub session_affinity: SessionAffinityConfig,
+
+    /// Above 0, workers are sampled with probability proportional to `exp(score / temperature)`
+    /// instead of picking the best one, so that several routers don't all send their requests
+    /// to the same worker. Higher values spread the load more. Default: 0.0, the best worker
+    pub temperature: f64,
+}
+
+/// The built-in ways of scoring workers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkerSelectorKind {
+    /// Overlap with the prompt, GPU cache usage and waiting requests, see
+    /// [`scheduler::DefaultWorkerSelector`]
+    #[default]
+    Default,
+
+    /// Overlap, queue depth, KV blocks in use and decode throughput, with weights that can be
+    /// changed in etcd, see [`scheduler::BalancedWorkerSelector`]
+    Balanced,
 }
 
 impl Default for KvRouterConfig {
@@ -76,6 +219,17 @@ impl Default for KvRouterConfig {
             overlap_score_weight: 2.0,
             gpu_cache_usage_weight: 1.0,
             waiting_requests_weight: 1.0,
+            indexer_limits: IndexerLimits::default(),
+            index_shards: 1,
+            index_snapshot: IndexSnapshotConfig::default(),
+            selector: WorkerSelectorKind::default(),
+            active_blocks_weight: 1.0,
+            decode_throughput_weight: 0.5,
+            lora_miss_weight: 1.0,
+            record_path: None,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            session_affinity: SessionAffinityConfig::default(),
+            temperature: 0.0,
         }
     }
 }
@@ -95,6 +249,88 @@ impl KvRouterConfig {
                 .unwrap_or(default.gpu_cache_usage_weight),
             waiting_requests_weight: waiting_requests_weight
                 .unwrap_or(default.waiting_requests_weight),
+            indexer_limits: default.indexer_limits,
+            index_snapshot: default.index_snapshot,
+            ..default
+        }
+    }
+
+    /// Weight for not having the request's LoRA adapter loaded. If None, the default is used.
+    pub fn with_lora_miss_weight(mut self, lora_miss_weight: Option<f64>) -> Self {
+        if let Some(weight) = lora_miss_weight {
+            self.lora_miss_weight = weight;
+        }
+        self
+    }
+
+    /// Bound the index of the workers' KV blocks
+    pub fn with_indexer_limits(mut self, indexer_limits: IndexerLimits) -> Self {
+        self.indexer_limits = indexer_limits;
+        self
+    }
+
+    /// Split the index of the workers' KV blocks into `index_shards`, at least 1
+    pub fn with_index_shards(mut self, index_shards: usize) -> Self {
+        self.index_shards = index_shards.max(1);
+        self
+    }
+
+    /// Keep the index of the workers' KV blocks across restarts
+    pub fn with_index_snapshot(mut self, index_snapshot: IndexSnapshotConfig) -> Self {
+        self.index_snapshot = index_snapshot;
+        self
+    }
+
+    /// Record the routing to `record_path`, if set
+    pub fn with_record_path(mut self, record_path: Option<PathBuf>) -> Self {
+        self.record_path = record_path;
+        self
+    }
+
+    /// Stop sending requests to failing workers as `circuit_breaker` says
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    /// Keep the workers of sessions as `session_affinity` says
+    pub fn with_session_affinity(mut self, session_affinity: SessionAffinityConfig) -> Self {
+        self.session_affinity = session_affinity;
+        self
+    }
+
+    /// Sample workers by their score at `temperature`, see [`KvRouterConfig::temperature`]
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Score workers with `selector`. If a weight is None, the default value will be used.
+    pub fn with_selector(
+        mut self,
+        selector: WorkerSelectorKind,
+        active_blocks_weight: Option<f64>,
+        decode_throughput_weight: Option<f64>,
+    ) -> Self {
+        self.selector = selector;
+        if let Some(weight) = active_blocks_weight {
+            self.active_blocks_weight = weight;
+        }
+        if let Some(weight) = decode_throughput_weight {
+            self.decode_throughput_weight = weight;
+        }
+        self
+    }
+
+    /// The weights of the balanced selector. It shares the overlap and waiting requests weights
+    /// with the default one.
+    pub fn balanced_weights(&self) -> BalancedWeights {
+        BalancedWeights {
+            overlap: self.overlap_score_weight,
+            queue_depth: self.waiting_requests_weight,
+            active_blocks: self.active_blocks_weight,
+            decode_throughput: self.decode_throughput_weight,
+            lora_miss: self.lora_miss_weight,
         }
     }
 }
@@ -102,9 +338,13 @@ impl KvRouterConfig {
 /// A KvRouter only decides which worker you should use. It doesn't send you there.
 /// TODO: Rename this to indicate it only selects a worker, it does not route.
 pub struct KvRouter {
-    indexer: KvIndexer,
+    indexers: Arc<BlockSizeIndexers>,
     scheduler: KvScheduler,
     block_size: usize,
+    record_tx: Option<mpsc::Sender<RoutingRecord>>,
+    health: Arc<WorkerHealth>,
+    sessions: SessionAffinity,
+    metrics_aggregator: KvMetricsAggregator,
 }
 
 impl KvRouter {
@@ -112,6 +352,10 @@ impl KvRouter {
         component: Component,
         block_size: usize,
         selector: Option<Box<dyn WorkerSelector + Send + Sync>>,
+        indexer: IndexerConfig,
+        index_snapshot: IndexSnapshotConfig,
+        record_path: Option<PathBuf>,
+        circuit_breaker: CircuitBreakerConfig,
     ) -> Result<Self> {
         let cancellation_token = component
             .drt()
@@ -121,24 +365,86 @@ impl KvRouter {
         tracing::info!("KV Routing initialized");
         let metrics_aggregator =
             KvMetricsAggregator::new(component.clone(), cancellation_token.clone()).await;
-        let indexer = KvIndexer::new(cancellation_token.clone(), block_size);
+        let indexers = Arc::new(BlockSizeIndexers::new(
+            cancellation_token.clone(),
+            block_size,
+            indexer,
+        ));
+        let block_sizes = indexers.clone();
+        let mut block_sizes_rx = metrics_aggregator.endpoints_watcher();
+        component.drt().runtime().tasks().spawn(
+            format!("kv block sizes {component}"),
+            async move {
+                while block_sizes_rx.changed().await.is_ok() {
+                    let endpoints = block_sizes_rx.borrow_and_update().clone();
+                    block_sizes.update_workers(&endpoints).await;
+                }
+            },
+        );
+        let health = WorkerHealth::new(circuit_breaker);
+        health.watch_blacklist(component.drt()).await?;
         let scheduler = KvScheduler::start(
             component.namespace().clone(),
             block_size,
             metrics_aggregator.endpoints_watcher(),
             selector,
+            health.clone(),
         )
         .await?;
 
+        let record_tx = match &record_path {
+            Some(path) => {
+                let recorder =
+                    KvRoutingRecorder::new(cancellation_token.clone(), path, None, None, None)
+                        .await
+                        .with_context(|| format!("Failed creating {}", path.display()))?;
+                tracing::info!(path = %path.display(), "Recording KV routing");
+                Some(recorder.event_sender())
+            }
+            None => None,
+        };
+        if record_tx.is_some() {
+            let load_record_tx = record_tx.clone();
+            let mut endpoints_rx = metrics_aggregator.endpoints_watcher();
+            component.drt().runtime().tasks().spawn(
+                format!("kv routing load recorder {component}"),
+                async move {
+                    while endpoints_rx.changed().await.is_ok() {
+                        let endpoints = endpoints_rx
+                            .borrow_and_update()
+                            .endpoints
+                            .values()
+                            .cloned()
+                            .collect();
+                        recorder::record(&load_record_tx, RoutingRecord::Load { endpoints });
+                    }
+                },
+            );
+        }
+
         // [gluo TODO] try subscribe_with_type::<RouterEvent>,
         // error checking below will be different.
         let mut kv_events_rx = component.subscribe(KV_EVENT_SUBJECT).await?;
-        let kv_events_tx = indexer.event_sender();
+        let kv_events_tx = indexers.clone();
+        let events_record_tx = record_tx.clone();
 
-        tokio::spawn(async move {
-            while let Some(event) = kv_events_rx.next().await {
-                let event: RouterEvent = match serde_json::from_slice(&event.payload) {
-                    Ok(event) => event,
+        // The subscription holds on to the events meanwhile, they are applied after the snapshot
+        snapshot::restore(&component, &index_snapshot, indexers.indexer()).await;
+
+        let events_token = cancellation_token.clone();
+        let events_task = async move {
+            loop {
+                let event = tokio::select! {
+                    _ = events_token.cancelled() => return Ok(()),
+                    event = kv_events_rx.next() => match event {
+                        Some(event) => event,
+                        // Without events the indexer goes stale, routing gets worse without any error
+                        None => anyhow::bail!("KV events subscription closed"),
+                    },
+                };
+                // A single RouterEvent from older workers, or a batch of them
+                let events = match decode_router_events(&event.payload) {
+                    Ok(events) => events,
                     Err(e) => {
                         tracing::warn!("Failed to deserialize RouterEvent: {:?}", e);
                         // Choosing warn and continue to process other events from other workers
@@ -146,55 +452,171 @@ impl KvRouter {
                         continue;
                     }
                 };
-                if let Err(e) = kv_events_tx.send(event).await {
-                    tracing::debug!("failed to send kv event to indexer; shutting down: {:?}", e);
+                for event in events {
+                    if events_record_tx.is_some() {
+                        let record = RoutingRecord::KvEvent {
+                            event: event.clone(),
+                        };
+                        recorder::record(&events_record_tx, record);
+                    }
+                    if let Err(e) = kv_events_tx.apply_event(event).await {
+                        tracing::debug!(
+                            "failed to send kv event to indexer; shutting down: {:?}",
+                            e
+                        );
+                    }
                 }
             }
-        });
+        };
+        component
+            .drt()
+            .runtime()
+            .tasks()
+            .spawn(format!("kv events {component}"), events_task);
+
+        let tasks = component.drt().runtime().tasks();
+        tasks.spawn(
+            format!("kv index bootstrap {component}"),
+            snapshot::serve_bootstrap(
+                component.clone(),
+                indexers.indexer().snapshot_sender(),
+                cancellation_token.clone(),
+            ),
+        );
+        if let Some(store) = index_snapshot.store {
+            tasks.spawn(
+                format!("kv index snapshots {component}"),
+                snapshot::save_periodically(
+                    component.clone(),
+                    store,
+                    index_snapshot.interval,
+                    indexers.indexer().snapshot_sender(),
+                    cancellation_token.clone(),
+                ),
+            );
+        }
 
         Ok(Self {
             scheduler,
-            indexer,
+            indexers,
             block_size,
+            record_tx,
+            health,
+            sessions: SessionAffinity::default(),
+            metrics_aggregator,
         })
     }
 
-    // [TODO] indexer needs to take 'lora_id' as parameter
-    pub async fn schedule(&self, token_ids: &Vec<u32>, _lora_id: u64) -> Result<i64> {
+    /// Keep the worker of each session as `config` says, see [`affinity`]
+    pub fn with_session_affinity(mut self, config: SessionAffinityConfig) -> Self {
+        self.sessions = SessionAffinity::new(config);
+        self
+    }
+
+    /// The worker of the session `key`, if it still serves and is healthy
+    pub(crate) fn session_worker(&self, key: &SessionKey) -> Option<i64> {
+        let worker_id = self.sessions.get(key)?;
+        if self.scheduler.is_available(worker_id) {
+            return Some(worker_id);
+        }
+        tracing::debug!(
+            worker_id,
+            "Session worker unavailable, placing the request again"
+        );
+        None
+    }
+
+    /// The circuit breakers of the workers and the blacklist
+    pub fn worker_health(&self) -> &Arc<WorkerHealth> {
+        &self.health
+    }
+
+    /// Pick a worker for `token_ids` under the LoRA adapter `lora_id`, 0 for the base model.
+    /// Blocks are matched only against the blocks of the same adapter.
+    pub async fn schedule(&self, token_ids: &Vec<u32>, lora_id: u64) -> Result<i64> {
         // Extracting part of the code in KvRouter::generate() for only
         // the decision making part, routing is done by the caller
-        let isl_tokens = token_ids.len();
-        let overlap_scores = self
-            .indexer
-            .find_matches_for_request(token_ids.as_slice())
-            .await?;
-        tracing::debug!("KV router overlap_scores: {:?}", overlap_scores);
-        let worker_id = self.scheduler.schedule(overlap_scores, isl_tokens).await?;
+        let placement = Placement {
+            lora_id,
+            ..Default::default()
+        };
+        let (worker_id, _overlap_amount) = self.find_best_match(token_ids, placement).await?;
         Ok(worker_id)
     }
 
     /// Give these tokens, find the worker with the best match in it's KV cache.
     /// Returned overlap amount is in number of blocks.
-    async fn find_best_match(&self, tokens: &[u32]) -> anyhow::Result<(i64, u32)> {
+    pub(crate) async fn find_best_match(
+        &self,
+        tokens: &[u32],
+        placement: Placement,
+    ) -> anyhow::Result<(i64, u32)> {
         let isl_tokens = tokens.len();
-        let block_size = self.block_size;
-
-        let (complete_blocks, _partial_block) =
-            TokenBlockSequence::split_tokens(tokens, block_size, 1337_u64);
-
-        let local_block_hashes = complete_blocks
-            .into_iter()
-            .map(|block| LocalBlockHash(block.block_hash()))
-            .collect();
-        let overlap_scores = self.indexer.find_matches(local_block_hashes).await?;
+        let lora_id = placement.lora_id;
+        let recorded_hashes = self
+            .record_tx
+            .as_ref()
+            .map(|_| block_hashes(tokens, self.block_size, lora_id));
+        let overlap_scores = self.indexers.find_matches(tokens, lora_id).await?;
         let worker_id = self
             .scheduler
-            .schedule(overlap_scores.clone(), isl_tokens)
+            .schedule(overlap_scores.clone(), isl_tokens, placement)
             .await?;
         let overlap_amount = overlap_scores.scores.get(&worker_id).copied().unwrap_or(0);
+        if let Some(block_hashes) = recorded_hashes {
+            let record = RoutingRecord::Decision {
+                block_hashes,
+                isl_tokens,
+                lora_id,
+                worker_id,
+                overlap_blocks: overlap_amount,
+            };
+            recorder::record(&self.record_tx, record);
+        }
         Ok((worker_id, overlap_amount))
     }
 
+    /// Which worker [`KvRouter::schedule`] would pick for `token_ids`, and why: the cached
+    /// blocks, load and score of every worker. Nothing is scheduled. For debugging routing.
+    pub async fn explain(&self, token_ids: &[u32], lora_id: u64) -> Result<RoutingExplanation> {
+        self.explain_choice(token_ids, lora_id, None).await
+    }
+
+    /// [`KvRouter::explain`] of a request that was sent to `chosen`
+    pub(crate) async fn explain_choice(
+        &self,
+        tokens: &[u32],
+        lora_id: u64,
+        chosen: Option<i64>,
+    ) -> Result<RoutingExplanation> {
+        let overlap_scores = self.indexers.find_matches(tokens, lora_id).await?;
+        Ok(self
+            .scheduler
+            .explain(overlap_scores, tokens.len(), lora_id, chosen)?)
+    }
+
+    /// The most blocks of `tokens` cached by one available worker
+    pub async fn overlap_blocks(&self, tokens: &[u32], lora_id: u64) -> Result<u32> {
+        let overlap_scores = self.indexers.find_matches(tokens, lora_id).await?;
+        Ok(overlap_scores
+            .scores
+            .into_iter()
+            .filter(|(worker_id, _)| self.scheduler.is_available(*worker_id))
+            .map(|(_, blocks)| blocks)
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// The summed load of the healthy workers
+    pub fn cluster_metrics(&self) -> ClusterMetrics {
+        self.scheduler.cluster_metrics()
+    }
+
+    /// The KV cache hits of the requests routed to the workers, by the routers of the namespace
+    pub fn hit_rates(&self) -> KvHitRates {
+        self.metrics_aggregator.hit_rates()
+    }
+
     /// Get the block size this router was configured with
     pub fn block_size(&self) -> usize {
         self.block_size
@@ -208,18 +630,99 @@ impl AsyncEngine<SingleIn<RouterRequest>, ManyOut<Annotated<RouterResponse>>, Er
         request: SingleIn<RouterRequest>,
     ) -> Result<ManyOut<Annotated<RouterResponse>>> {
         let (request, ctx) = request.into_parts();
-        let (worker_id, _) = self.find_best_match(&request.tokens).await?;
-
-        let response = RouterResponse { worker_id };
+        let response = if request.dry_run {
+            let explanation = self.explain(&request.tokens, request.lora_id).await?;
+            let overlap_blocks = explanation
+                .workers
+                .iter()
+                .find(|worker| worker.worker_id == explanation.worker_id)
+                .map(|worker| worker.overlap_blocks)
+                .unwrap_or(0);
+            RouterResponse {
+                worker_id: explanation.worker_id,
+                overlap_blocks,
+                block_size: self.block_size,
+                protocol_version: ROUTER_PROTOCOL_VERSION,
+                explanation: Some(explanation),
+                namespace: None,
+            }
+        } else {
+            let (worker_id, overlap_blocks) = self
+                .find_best_match(
+                    &request.tokens,
+                    Placement {
+                        priority: request.priority,
+                        principal: request.principal,
+                        lora_id: request.lora_id,
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            let explanation = if request.explain {
+                Some(
+                    self.explain_choice(&request.tokens, request.lora_id, Some(worker_id))
+                        .await?,
+                )
+            } else {
+                None
+            };
+            RouterResponse {
+                worker_id,
+                overlap_blocks,
+                block_size: self.block_size,
+                protocol_version: ROUTER_PROTOCOL_VERSION,
+                explanation,
+                namespace: None,
+            }
+        };
         let response = Annotated::from_data(response);
         let stream = stream::iter(vec![response]);
         Ok(ResponseStream::new(Box::pin(stream), ctx.context()))
     }
 }
 
+/// Register `router` as the [`KV_SCHEDULER_ENDPOINT`] of `component`, so that ingresses which
+/// keep their own data plane (Envoy, Go gateways, ...) can ask the KV-aware scheduler where to
+/// send a request: `dyn://{namespace}.{component}.generate`, [`RouterRequest`] in,
+/// one [`RouterResponse`] out. `router` is a [`KvRouter`], or a [`cluster::HierarchicalRouter`]
+/// to route over several clusters. The endpoint's stats are the [`SchedulerStats`] of `router`,
+/// so they show up in `scrape_stats`. Runs until the endpoint is shut down.
+pub async fn serve_scheduler<R>(component: Component, router: Arc<R>) -> Result<()>
+where
+    R: AsyncEngine<SingleIn<RouterRequest>, ManyOut<Annotated<RouterResponse>>, Error>
+        + SchedulerStats
+        + 'static,
+{
+    let stats = router.clone();
+    let ingress = Ingress::for_engine(router)?;
+    component
+        .service_builder()
+        .create()
+        .await?
+        .endpoint(KV_SCHEDULER_ENDPOINT)
+        .endpoint_builder()
+        .stats_handler(move |_| serde_json::json!({ "kv_hit_rates": stats.hit_rates() }))
+        .handler(ingress)
+        .start()
+        .await
+}
+
+/// What a router served by [`serve_scheduler`] reports in `scrape_stats`
+pub trait SchedulerStats: Send + Sync {
+    /// The KV cache hits of the requests routed to the workers
+    fn hit_rates(&self) -> KvHitRates;
+}
+
+impl SchedulerStats for KvRouter {
+    fn hit_rates(&self) -> KvHitRates {
+        KvRouter::hit_rates(self)
+    }
+}
+
 pub struct KvPushRouter {
     inner: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
     chooser: Arc<KvRouter>,
+    loras: Option<Arc<LoraRegistry>>,
 }
 
 impl KvPushRouter {
@@ -227,7 +730,139 @@ impl KvPushRouter {
         inner: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
         chooser: Arc<KvRouter>,
     ) -> Self {
-        KvPushRouter { inner, chooser }
+        KvPushRouter {
+            inner,
+            chooser,
+            loras: None,
+        }
+    }
+
+    /// Send the requests for a LoRA adapter only to the workers that loaded it
+    pub fn with_loras(mut self, loras: Arc<LoraRegistry>) -> Self {
+        self.loras = Some(loras);
+        self
+    }
+
+    /// The workers that loaded the adapter of `request`, None for any worker
+    fn lora_workers(&self, request: &PreprocessedRequest) -> Option<HashSet<i64>> {
+        let (loras, lora) = self.loras.as_ref().zip(request.lora.as_ref())?;
+        Some(loras.instance_ids(&lora.name))
+    }
+
+    /// The workers that can't serve `request`, because they didn't load its adapter
+    pub(crate) fn lacking_lora(&self, request: &PreprocessedRequest) -> Vec<i64> {
+        let Some(workers) = self.lora_workers(request) else {
+            return vec![];
+        };
+        self.inner
+            .loads()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(instance, _)| instance.id())
+            .filter(|worker_id| !workers.contains(worker_id))
+            .collect()
+    }
+
+    /// Place `request` on the worker with the best match, or on the worker it is pinned to or
+    /// its session is on, and send it there. Returns the worker, for callers that wrap the stream.
+    pub(crate) async fn route(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<(i64, ManyOut<Annotated<LLMEngineOutput>>)> {
+        let explain = request.has_annotation(ANNOTATION_ROUTING_EXPLANATION);
+        let tokens = explain.then(|| request.token_ids.clone());
+        let lora_id = request.lora_id();
+        let session = request
+            .session_id()
+            .map(|session_id| SessionKey::new(request.principal(), session_id));
+        let session_worker = session
+            .as_ref()
+            .and_then(|session| self.chooser.session_worker(session));
+        // The zone policy applies to the best match too
+        let mut workers: HashSet<i64> = self
+            .inner
+            .loads()?
+            .into_iter()
+            .map(|(instance, _)| instance.id())
+            .collect();
+        if let Some(lora_workers) = self.lora_workers(&request) {
+            if lora_workers.is_empty() && request.backend_instance_id().is_none() {
+                let name = request.lora.as_ref().map_or("", |lora| lora.name.as_str());
+                anyhow::bail!("No worker has the LoRA adapter {name} loaded");
+            }
+            workers.retain(|worker_id| lora_workers.contains(worker_id));
+            if workers.is_empty() {
+                // Only workers in other zones loaded it
+                workers = lora_workers;
+            }
+        }
+        let placement = Placement {
+            priority: request.priority.unwrap_or_default(),
+            principal: request.principal().map(str::to_string),
+            lora_id,
+            // The client pinned the request to a worker, or its session has one. It still goes
+            // through the scheduler, for its load to count.
+            worker_id: request.backend_instance_id().or(session_worker),
+            workers: Some(workers),
+        };
+        let placed = placement.worker_id.is_none();
+        let (instance_id, overlap_amount) = self
+            .chooser
+            .find_best_match(&request.token_ids, placement)
+            .await?;
+        if let Some(session) = session.filter(|_| placed) {
+            self.chooser.sessions.set(session, instance_id);
+        }
+        // Update the request with the estimated prefix hit blocks
+        let (mut backend_input, context) = request.into_parts();
+        backend_input.estimated_prefix_hit_num_blocks = Some(overlap_amount);
+        let request = context.map(|_| backend_input);
+        let responses = self.send(request, instance_id).await?;
+        let Some(tokens) = tokens else {
+            return Ok((instance_id, responses));
+        };
+        let explanation = self
+            .chooser
+            .explain_choice(&tokens, lora_id, Some(instance_id))
+            .await?;
+        let ctx = responses.context();
+        let annotation = Annotated::from_annotation(ANNOTATION_ROUTING_EXPLANATION, &explanation)?;
+        let responses =
+            ResponseStream::new(Box::pin(stream::iter([annotation]).chain(responses)), ctx);
+        Ok((instance_id, responses))
+    }
+
+    /// Send `request` to `instance_id`, counting how it went for the circuit breaker of the worker
+    pub(crate) async fn send(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+        instance_id: i64,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>> {
+        let health = self.chooser.worker_health().clone();
+        match self.inner.direct(request, instance_id).await {
+            Ok(responses) => Ok(circuit_breaker::track(health, instance_id, responses)),
+            Err(err) => {
+                health.record_failure(instance_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// Count a request that `worker_id` failed to serve, for its circuit breaker
+    pub(crate) fn record_failure(&self, worker_id: i64) {
+        self.chooser.worker_health().record_failure(worker_id);
+    }
+
+    /// The workers the circuit breakers or the blacklist keep requests away from
+    pub(crate) fn unhealthy(&self) -> Vec<i64> {
+        let health = self.chooser.worker_health();
+        self.inner
+            .loads()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(instance, _)| instance.id())
+            .filter(|worker_id| !health.is_healthy(*worker_id))
+            .collect()
     }
 }
 
@@ -235,6 +870,7 @@ impl KvPushRouter {
 impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
     for KvPushRouter
 {
+    #[tracing::instrument(name = "route", skip_all, fields(request_id = request.id()))]
     async fn generate(
         &self,
         request: SingleIn<PreprocessedRequest>,
@@ -242,13 +878,9 @@ impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutpu
         match self.inner.client.instance_source.as_ref() {
             InstanceSource::Static => self.inner.r#static(request).await,
             InstanceSource::Dynamic(_) => {
-                let (instance_id, overlap_amount) =
-                    self.chooser.find_best_match(&request.token_ids).await?;
-                // Update the request with the estimated prefix hit blocks
-                let (mut backend_input, context) = request.into_parts();
-                backend_input.estimated_prefix_hit_num_blocks = Some(overlap_amount);
-                let updated_request = context.map(|_| backend_input);
-                self.inner.direct(updated_request, instance_id).await
+                let annotate = request.has_annotation(ANNOTATION_WORKER_INSTANCE_ID);
+                let (instance_id, responses) = self.route(request).await?;
+                Ok(with_worker_annotation(annotate, instance_id, responses))
             }
         }
     }
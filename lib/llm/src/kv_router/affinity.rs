@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Session affinity: send the requests of a conversation to the same worker.
+//!
+//! A client names its conversation with `nvext.routing.session_id`, or the `x-dynamo-session-id`
+//! header. The first request of a session is placed by KV overlap scoring, the ones after it go
+//! to the same worker as long as it serves the model and its circuit breaker is closed. Otherwise
+//! the request is placed by scoring again, and the session moves to the new worker.
+//!
+//! Sessions are kept per API key, so that one client can't steer the requests of another. A
+//! session is forgotten [`SessionAffinityConfig::ttl`] after its last request, and the least
+//! recently used ones when there are more than [`SessionAffinityConfig::max_sessions`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::indexer::WorkerId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionAffinityConfig {
+    /// How long a session is kept after its last request
+    pub ttl: Duration,
+
+    /// Most sessions kept. 0 disables session affinity.
+    pub max_sessions: usize,
+}
+
+impl Default for SessionAffinityConfig {
+    fn default() -> Self {
+        SessionAffinityConfig {
+            ttl: Duration::from_secs(600),
+            max_sessions: 100_000,
+        }
+    }
+}
+
+/// A conversation of a client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionKey {
+    principal: Option<String>,
+    session_id: String,
+}
+
+impl SessionKey {
+    /// Session `session_id` of the API key `principal`
+    pub fn new(principal: Option<&str>, session_id: &str) -> Self {
+        SessionKey {
+            principal: principal.map(str::to_string),
+            session_id: session_id.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    worker_id: WorkerId,
+    expires_at: Instant,
+    /// Key of the session in [`SessionState::by_use`]
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    sessions: HashMap<SessionKey, Session>,
+    /// The sessions by last use, oldest first. All have the same TTL, so they also expire in
+    /// this order.
+    by_use: BTreeMap<u64, SessionKey>,
+    clock: u64,
+}
+
+impl SessionState {
+    fn remove(&mut self, key: &SessionKey) {
+        if let Some(session) = self.sessions.remove(key) {
+            self.by_use.remove(&session.last_used);
+        }
+    }
+
+    fn touch(&mut self, key: &SessionKey, ttl: Duration) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(session) = self.sessions.get_mut(key) {
+            self.by_use.remove(&session.last_used);
+            session.last_used = clock;
+            session.expires_at = Instant::now() + ttl;
+            self.by_use.insert(clock, key.clone());
+        }
+    }
+
+    /// Drop the expired sessions, then the least recently used ones beyond `max_sessions`
+    fn evict(&mut self, max_sessions: usize) {
+        let now = Instant::now();
+        while let Some((_, key)) = self.by_use.first_key_value() {
+            let expired = self.sessions[key].expires_at <= now;
+            if !expired && self.sessions.len() <= max_sessions {
+                break;
+            }
+            let key = key.clone();
+            self.remove(&key);
+        }
+    }
+}
+
+/// The worker of each session, see the [module docs](self)
+#[derive(Debug)]
+pub struct SessionAffinity {
+    config: SessionAffinityConfig,
+    state: Mutex<SessionState>,
+}
+
+impl SessionAffinity {
+    pub fn new(config: SessionAffinityConfig) -> Self {
+        SessionAffinity {
+            config,
+            state: Mutex::new(SessionState::default()),
+        }
+    }
+
+    /// The worker of the session `key`, if it has one. Keeps the session alive.
+    pub fn get(&self, key: &SessionKey) -> Option<WorkerId> {
+        let mut state = self.state.lock().unwrap();
+        let session = state.sessions.get(key)?;
+        if session.expires_at <= Instant::now() {
+            state.remove(key);
+            return None;
+        }
+        let worker_id = session.worker_id;
+        state.touch(key, self.config.ttl);
+        Some(worker_id)
+    }
+
+    /// Send the next requests of the session `key` to `worker_id`
+    pub fn set(&self, key: SessionKey, worker_id: WorkerId) {
+        if self.config.max_sessions == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(session) = state.sessions.get_mut(&key) {
+            session.worker_id = worker_id;
+        } else {
+            state.sessions.insert(
+                key.clone(),
+                Session {
+                    worker_id,
+                    expires_at: Instant::now(),
+                    last_used: 0,
+                },
+            );
+        }
+        state.touch(&key, self.config.ttl);
+        state.evict(self.config.max_sessions);
+    }
+
+    /// How many sessions are kept
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for SessionAffinity {
+    fn default() -> Self {
+        SessionAffinity::new(SessionAffinityConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_session_affinity() {
+        let affinity = SessionAffinity::new(SessionAffinityConfig {
+            ttl: Duration::from_secs(10),
+            max_sessions: 2,
+        });
+        let a = SessionKey::new(Some("key-a"), "s1");
+        let b = SessionKey::new(Some("key-b"), "s1");
+        let c = SessionKey::new(None, "s2");
+
+        affinity.set(a.clone(), 1);
+        affinity.set(b.clone(), 2);
+        assert_eq!(affinity.get(&a), Some(1));
+        assert_eq!(affinity.get(&b), Some(2));
+
+        // `a` is the least recently used
+        affinity.set(c.clone(), 3);
+        assert_eq!(affinity.len(), 2);
+        assert_eq!(affinity.get(&a), None);
+
+        // A request keeps the session alive
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert_eq!(affinity.get(&c), Some(3));
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert_eq!(affinity.get(&b), None);
+        assert_eq!(affinity.get(&c), Some(3));
+
+        affinity.set(c.clone(), 4);
+        assert_eq!(affinity.get(&c), Some(4));
+        assert_eq!(affinity.len(), 1);
+    }
+}
@@ -0,0 +1,271 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! KV routing over workers with different KV block sizes.
+//!
+//! The hashes of blocks of different sizes never match, so each block size has its own index.
+//! Workers advertise their block size with `kv_block_size` in their load metrics, workers that
+//! don't are taken to have the router's. A request is hashed at every block size and matched in
+//! each index. The overlaps are converted to blocks of the router's size, which the worker
+//! selectors score in.
+//!
+//! The KV events of a worker go to the index of its block size. Events that arrive before its
+//! first metrics go to the index of the router's block size, and are dropped from it when its
+//! metrics show another size.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+
+use tokio_util::sync::CancellationToken;
+
+use super::block_hashes;
+use super::indexer::{
+    IndexerConfig, KvIndexerInterface, KvIndexerSharded, KvRouterError, OverlapScores, RouterEvent,
+    ShardedEventSender, WorkerId,
+};
+use super::protocols::{KvCacheEvent, KvCacheEventData};
+use super::scoring::ProcessedEndpoints;
+
+/// The index of the blocks of one size
+struct BlockSizeIndex {
+    indexer: KvIndexerSharded,
+    events: ShardedEventSender,
+}
+
+impl BlockSizeIndex {
+    fn new(cancel: CancellationToken, block_size: usize, config: IndexerConfig) -> Self {
+        let indexer =
+            KvIndexerSharded::new_with_limits(cancel, config.shards, block_size, config.limits);
+        let events = indexer.event_sender();
+        BlockSizeIndex { indexer, events }
+    }
+}
+
+/// An index per KV block size of the workers, see the [module docs](self)
+pub struct BlockSizeIndexers {
+    cancel: CancellationToken,
+    config: IndexerConfig,
+    /// The router's block size
+    block_size: usize,
+    /// The index of the router's block size, also in `indexes`
+    default: Arc<BlockSizeIndex>,
+    indexes: RwLock<BTreeMap<usize, Arc<BlockSizeIndex>>>,
+    /// The block size of the workers that reported their metrics
+    workers: Mutex<HashMap<WorkerId, usize>>,
+}
+
+impl BlockSizeIndexers {
+    /// Index the blocks of workers of `block_size`, the router's, and of any other size they
+    /// advertise. Each index is set up as `config` says.
+    pub fn new(cancel: CancellationToken, block_size: usize, config: IndexerConfig) -> Self {
+        let default = Arc::new(BlockSizeIndex::new(cancel.clone(), block_size, config));
+        BlockSizeIndexers {
+            cancel,
+            config,
+            block_size,
+            indexes: RwLock::new(BTreeMap::from([(block_size, default.clone())])),
+            default,
+            workers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The index of the blocks of the router's size. Only it is kept in snapshots.
+    pub fn indexer(&self) -> &KvIndexerSharded {
+        &self.default.indexer
+    }
+
+    /// The block sizes indexed, the router's and the ones workers advertised
+    pub fn block_sizes(&self) -> Vec<usize> {
+        self.indexes.read().unwrap().keys().copied().collect()
+    }
+
+    /// The index of `block_size`, created if there is none yet
+    fn index(&self, block_size: usize) -> Arc<BlockSizeIndex> {
+        if let Some(index) = self.indexes.read().unwrap().get(&block_size) {
+            return index.clone();
+        }
+        self.indexes
+            .write()
+            .unwrap()
+            .entry(block_size)
+            .or_insert_with(|| {
+                tracing::info!(block_size, "Indexing KV blocks of another size");
+                Arc::new(BlockSizeIndex::new(
+                    self.cancel.clone(),
+                    block_size,
+                    self.config,
+                ))
+            })
+            .clone()
+    }
+
+    /// The block size of `worker_id`, as far as the router knows
+    fn worker_block_size(&self, worker_id: WorkerId) -> usize {
+        self.workers
+            .lock()
+            .unwrap()
+            .get(&worker_id)
+            .copied()
+            .unwrap_or(self.block_size)
+    }
+
+    /// Learn the block sizes of the workers from their metrics. Workers whose size changed lose
+    /// the blocks indexed at the old one.
+    pub async fn update_workers(&self, endpoints: &ProcessedEndpoints) {
+        let mut moved = Vec::new();
+        {
+            let mut workers = self.workers.lock().unwrap();
+            for (worker_id, endpoint) in &endpoints.endpoints {
+                let block_size = endpoint
+                    .data
+                    .kv_block_size
+                    .map(|block_size| block_size as usize)
+                    .filter(|block_size| *block_size > 0)
+                    .unwrap_or(self.block_size);
+                let previous = workers
+                    .insert(*worker_id, block_size)
+                    .unwrap_or(self.block_size);
+                if previous != block_size {
+                    tracing::debug!(worker_id, block_size, previous, "KV block size of worker");
+                    moved.push((*worker_id, previous));
+                }
+            }
+        }
+        for (worker_id, previous) in moved {
+            let cleared = KvCacheEvent {
+                event_id: 0,
+                data: KvCacheEventData::Cleared,
+            };
+            let event = RouterEvent::new(worker_id, cleared);
+            if let Err(err) = self.index(previous).events.send(event).await {
+                tracing::debug!(%err, worker_id, "Failed dropping blocks of the old size");
+            }
+        }
+    }
+
+    /// Apply `event` to the index of the block size of its worker
+    pub async fn apply_event(&self, event: RouterEvent) -> Result<(), KvRouterError> {
+        let block_size = self.worker_block_size(event.worker_id());
+        self.index(block_size).events.send(event).await
+    }
+
+    /// The blocks of `tokens`, under the LoRA adapter `lora_id`, each worker has cached, in
+    /// blocks of the router's size. The frequencies are those of the router's block size.
+    pub async fn find_matches(
+        &self,
+        tokens: &[u32],
+        lora_id: u64,
+    ) -> Result<OverlapScores, KvRouterError> {
+        let indexes: Vec<(usize, Arc<BlockSizeIndex>)> = self
+            .indexes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(block_size, index)| (*block_size, index.clone()))
+            .collect();
+        if let [(block_size, index)] = indexes.as_slice() {
+            let sequence = block_hashes(tokens, *block_size, lora_id);
+            return index.indexer.find_matches(sequence).await;
+        }
+        let matches = futures::future::try_join_all(indexes.iter().map(|(block_size, index)| {
+            index
+                .indexer
+                .find_matches(block_hashes(tokens, *block_size, lora_id))
+        }))
+        .await?;
+
+        let mut overlap = OverlapScores::new();
+        for ((block_size, _), scores) in indexes.iter().zip(matches) {
+            if *block_size == self.block_size {
+                overlap.frequencies = scores.frequencies;
+            }
+            // A worker is in the index of one block size
+            for (worker_id, blocks) in scores.scores {
+                let blocks = blocks as usize * block_size / self.block_size;
+                overlap.scores.insert(worker_id, blocks as u32);
+            }
+        }
+        Ok(overlap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_router::protocols::{
+        ExternalSequenceBlockHash, ForwardPassMetrics, KvCacheStoreData, KvCacheStoredBlockData,
+    };
+    use crate::kv_router::scheduler::Endpoint;
+
+    fn endpoint(worker_id: WorkerId, kv_block_size: Option<u32>) -> Endpoint {
+        Endpoint {
+            name: format!("worker-{worker_id}"),
+            subject: format!("dynamo.worker.generate-{worker_id:x}"),
+            data: ForwardPassMetrics {
+                kv_block_size,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn store_event(worker_id: WorkerId, tokens: &[u32], block_size: usize) -> RouterEvent {
+        let blocks = block_hashes(tokens, block_size, 0)
+            .into_iter()
+            .enumerate()
+            .map(|(i, tokens_hash)| KvCacheStoredBlockData {
+                block_hash: ExternalSequenceBlockHash(i as u64 + 1),
+                tokens_hash,
+            })
+            .collect();
+        let data = KvCacheEventData::Stored(KvCacheStoreData {
+            parent_hash: None,
+            blocks,
+        });
+        RouterEvent::new(worker_id, KvCacheEvent { event_id: 0, data })
+    }
+
+    /// Wait until every index applied the events sent so far
+    async fn applied(indexers: &BlockSizeIndexers) {
+        for block_size in indexers.block_sizes() {
+            indexers.index(block_size).indexer.snapshot().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_size_indexers() {
+        let token = CancellationToken::new();
+        let indexers = BlockSizeIndexers::new(token, 16, IndexerConfig::default());
+        let tokens: Vec<u32> = (0..128).collect();
+
+        // Before its metrics, a worker is taken to have the router's block size
+        indexers
+            .apply_event(store_event(2, &tokens, 16))
+            .await
+            .unwrap();
+        indexers
+            .update_workers(&ProcessedEndpoints::new(vec![
+                endpoint(1, None),
+                endpoint(2, Some(64)),
+            ]))
+            .await;
+        assert_eq!(indexers.block_sizes(), vec![16, 64]);
+        applied(&indexers).await;
+        let overlap = indexers.find_matches(&tokens, 0).await.unwrap();
+        assert!(overlap.scores.is_empty());
+
+        indexers
+            .apply_event(store_event(1, &tokens[..64], 16))
+            .await
+            .unwrap();
+        indexers
+            .apply_event(store_event(2, &tokens, 64))
+            .await
+            .unwrap();
+        applied(&indexers).await;
+
+        // In blocks of 16 tokens
+        let overlap = indexers.find_matches(&tokens, 0).await.unwrap();
+        assert_eq!(overlap.scores[&1], 4);
+        assert_eq!(overlap.scores[&2], 8);
+    }
+}
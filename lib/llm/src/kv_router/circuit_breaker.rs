@@ -0,0 +1,512 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stop sending requests to workers that fail.
+//!
+//! Each worker has a circuit breaker. It opens after [`CircuitBreakerConfig::failure_threshold`]
+//! failures in a row: a request that couldn't be sent to the worker, whose response stream had an
+//! error, or whose first response took longer than
+//! [`CircuitBreakerConfig::first_response_timeout`]. While it is open the scheduler doesn't pick
+//! the worker. After [`CircuitBreakerConfig::open_duration`] it is half-open: one request goes
+//! to the worker as a probe. If it succeeds the breaker closes, if it fails it opens again.
+//!
+//! Operators can also blacklist a worker by its instance id, with a key under
+//! [`KV_ROUTER_BLACKLIST_ROOT_PATH`] in etcd. Every KV router follows the keys, and takes the
+//! worker back once its key is deleted. Requests pinned to a worker with
+//! `nvext.routing.backend_instance_id` still go to it.
+//!
+//! A model's worker admission policy can keep workers out too, see
+//! [`crate::discovery::worker_admission`].
+//!
+//! When every worker is unavailable, requests wait in the scheduler as if all were busy.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dynamo_runtime::engine::{AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use dynamo_runtime::transports::etcd::{self, WatchEvent};
+use dynamo_runtime::DistributedRuntime;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use super::indexer::WorkerId;
+use super::scoring::ProcessedEndpoints;
+
+/// etcd prefix of the blacklisted workers, one key per instance id in decimal, valued with a
+/// [`BlacklistEntry`]
+pub const KV_ROUTER_BLACKLIST_ROOT_PATH: &str = "public/components/kv_router/blacklist/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Failures in a row that open the breaker. 0 disables the breakers.
+    pub failure_threshold: u32,
+
+    /// How long an open breaker keeps the worker out before a probe
+    pub open_duration: Duration,
+
+    /// A first response slower than this counts as a failure. The request is not cancelled.
+    pub first_response_timeout: Option<Duration>,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            first_response_timeout: None,
+        }
+    }
+}
+
+/// Why an operator blacklisted a worker
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlacklistEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Breaker {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// `probe` is when the probe request was sent, if it was
+    HalfOpen {
+        probe: Option<Instant>,
+    },
+}
+
+#[derive(Default)]
+struct HealthState {
+    /// Workers without an entry are closed with no failures
+    breakers: HashMap<WorkerId, Breaker>,
+    blacklist: HashSet<WorkerId>,
+    /// The workers the worker admission policy lets serve the model, all if None
+    admitted: Option<HashSet<WorkerId>>,
+}
+
+impl HealthState {
+    fn is_admitted(&self, worker_id: WorkerId) -> bool {
+        self.admitted
+            .as_ref()
+            .is_none_or(|admitted| admitted.contains(&worker_id))
+    }
+}
+
+/// The circuit breakers of the workers and the blacklist, shared by a router's scheduler and the
+/// requests it placed
+pub struct WorkerHealth {
+    config: CircuitBreakerConfig,
+    state: Mutex<HealthState>,
+    /// Woken when a worker may have become available
+    changed: Notify,
+}
+
+impl WorkerHealth {
+    pub fn new(config: CircuitBreakerConfig) -> Arc<Self> {
+        Arc::new(WorkerHealth {
+            config,
+            state: Mutex::new(HealthState::default()),
+            changed: Notify::new(),
+        })
+    }
+
+    pub fn record_success(&self, worker_id: WorkerId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(Breaker::HalfOpen { .. }) = state.breakers.remove(&worker_id) {
+            tracing::info!(worker_id, "Worker recovered, circuit breaker closed");
+            self.changed.notify_one();
+        }
+    }
+
+    pub fn record_failure(&self, worker_id: WorkerId) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let breaker = state
+            .breakers
+            .entry(worker_id)
+            .or_insert(Breaker::Closed { failures: 0 });
+        let open = match breaker {
+            Breaker::Closed { failures } => {
+                *failures += 1;
+                *failures >= self.config.failure_threshold
+            }
+            Breaker::HalfOpen { .. } => true,
+            // A request placed before the breaker opened
+            Breaker::Open { .. } => false,
+        };
+        if open {
+            tracing::warn!(
+                worker_id,
+                open_secs = self.config.open_duration.as_secs_f64(),
+                "Worker failing, circuit breaker opened"
+            );
+            *breaker = Breaker::Open {
+                until: now + self.config.open_duration,
+            };
+        }
+    }
+
+    /// Keep `worker_id` from getting requests, or take it back
+    pub fn set_blacklisted(&self, worker_id: WorkerId, blacklisted: bool) {
+        let mut state = self.state.lock().unwrap();
+        if blacklisted {
+            if state.blacklist.insert(worker_id) {
+                tracing::warn!(worker_id, "Worker blacklisted");
+            }
+        } else if state.blacklist.remove(&worker_id) {
+            tracing::info!(worker_id, "Worker no longer blacklisted");
+            self.changed.notify_one();
+        }
+    }
+
+    /// Only let the workers in `admitted` get requests, or all if None
+    pub fn set_admitted(&self, admitted: Option<HashSet<WorkerId>>) {
+        let mut state = self.state.lock().unwrap();
+        if state.admitted != admitted {
+            state.admitted = admitted;
+            self.changed.notify_one();
+        }
+    }
+
+    /// Whether `worker_id` has a closed breaker, isn't blacklisted and is admitted
+    pub fn is_healthy(&self, worker_id: WorkerId) -> bool {
+        let state = self.state.lock().unwrap();
+        state.is_admitted(worker_id)
+            && !state.blacklist.contains(&worker_id)
+            && matches!(
+                state.breakers.get(&worker_id),
+                None | Some(Breaker::Closed { .. })
+            )
+    }
+
+    /// The workers with a breaker that isn't closed, and the blacklisted ones
+    pub fn states(&self) -> (BTreeMap<WorkerId, BreakerState>, Vec<WorkerId>) {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        let breakers = state
+            .breakers
+            .iter()
+            .filter_map(|(worker_id, breaker)| {
+                let state = match breaker {
+                    Breaker::Closed { .. } => return None,
+                    Breaker::Open { until } if *until > now => BreakerState::Open,
+                    Breaker::Open { .. } | Breaker::HalfOpen { .. } => BreakerState::HalfOpen,
+                };
+                Some((*worker_id, state))
+            })
+            .collect();
+        let mut blacklist: Vec<_> = state.blacklist.iter().copied().collect();
+        blacklist.sort_unstable();
+        (breakers, blacklist)
+    }
+
+    /// The workers of `endpoints` that may get a request now
+    pub(crate) fn available<'a>(
+        &self,
+        endpoints: &'a ProcessedEndpoints,
+    ) -> Cow<'a, ProcessedEndpoints> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.breakers.is_empty() && state.blacklist.is_empty() && state.admitted.is_none() {
+            return Cow::Borrowed(endpoints);
+        }
+        let probe_timeout = self.config.open_duration;
+        let mut unavailable = state.blacklist.clone();
+        unavailable.extend(
+            endpoints
+                .endpoints
+                .keys()
+                .filter(|worker_id| !state.is_admitted(**worker_id)),
+        );
+        for (worker_id, breaker) in state.breakers.iter_mut() {
+            match *breaker {
+                Breaker::Closed { .. } => {}
+                Breaker::Open { until } if until > now => {
+                    unavailable.insert(*worker_id);
+                }
+                Breaker::Open { .. } => *breaker = Breaker::HalfOpen { probe: None },
+                // A probe that never finished, e.g. its client went away, doesn't block forever
+                Breaker::HalfOpen { probe: Some(sent) } if now - sent < probe_timeout => {
+                    unavailable.insert(*worker_id);
+                }
+                Breaker::HalfOpen { .. } => {}
+            }
+        }
+        if !endpoints
+            .endpoints
+            .keys()
+            .any(|worker_id| unavailable.contains(worker_id))
+        {
+            return Cow::Borrowed(endpoints);
+        }
+        Cow::Owned(ProcessedEndpoints::new(
+            endpoints
+                .endpoints
+                .iter()
+                .filter(|(worker_id, _)| !unavailable.contains(worker_id))
+                .map(|(_, endpoint)| endpoint.clone())
+                .collect(),
+        ))
+    }
+
+    /// A request was sent to `worker_id`. If its breaker is half-open, it is the probe.
+    pub(crate) fn on_scheduled(&self, worker_id: WorkerId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(Breaker::HalfOpen { probe }) = state.breakers.get_mut(&worker_id) {
+            *probe = Some(Instant::now());
+        }
+    }
+
+    /// Wait until a worker may have become available: a breaker closed or its open time ended,
+    /// a worker was taken off the blacklist or the admitted workers changed
+    pub(crate) async fn changed(&self) {
+        let next_half_open = {
+            let state = self.state.lock().unwrap();
+            state
+                .breakers
+                .values()
+                .filter_map(|breaker| match breaker {
+                    Breaker::Open { until } => Some(*until),
+                    Breaker::HalfOpen { probe: Some(sent) } => {
+                        Some(*sent + self.config.open_duration)
+                    }
+                    _ => None,
+                })
+                .min()
+        };
+        match next_half_open {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = self.changed.notified() => {}
+                    _ = tokio::time::sleep_until(deadline) => {}
+                }
+            }
+            None => self.changed.notified().await,
+        }
+    }
+
+    /// Follow the blacklist in etcd until the runtime shuts down
+    pub async fn watch_blacklist(self: &Arc<Self>, drt: &DistributedRuntime) -> anyhow::Result<()> {
+        let Some(etcd_client) = drt.etcd_client() else {
+            anyhow::bail!("Static components don't have an etcd client");
+        };
+        let (_prefix, watcher, mut receiver) = etcd_client
+            .kv_get_and_watch_prefix(KV_ROUTER_BLACKLIST_ROOT_PATH)
+            .await?
+            .dissolve();
+        let this = self.clone();
+        drt.runtime()
+            .tasks()
+            .spawn("kv router blacklist".to_string(), async move {
+                let _watcher = watcher;
+                while let Some(event) = receiver.recv().await {
+                    let (kv, blacklisted) = match &event {
+                        WatchEvent::Put(kv) => (kv, true),
+                        WatchEvent::Delete(kv) => (kv, false),
+                    };
+                    match blacklisted_worker(kv.key()) {
+                        Some(worker_id) => this.set_blacklisted(worker_id, blacklisted),
+                        None => tracing::warn!(
+                            key = %String::from_utf8_lossy(kv.key()),
+                            "Invalid KV router blacklist key in etcd"
+                        ),
+                    }
+                }
+            });
+        Ok(())
+    }
+}
+
+/// The worker of a blacklist key
+fn blacklisted_worker(key: &[u8]) -> Option<WorkerId> {
+    std::str::from_utf8(key)
+        .ok()?
+        .strip_prefix(KV_ROUTER_BLACKLIST_ROOT_PATH)?
+        .parse()
+        .ok()
+}
+
+/// Blacklist `worker_id` for every KV router, until [`unblacklist`]
+pub async fn blacklist(
+    etcd_client: &etcd::Client,
+    worker_id: WorkerId,
+    entry: &BlacklistEntry,
+) -> anyhow::Result<()> {
+    let key = format!("{KV_ROUTER_BLACKLIST_ROOT_PATH}{worker_id}");
+    // Lease 0 is no lease, the blacklist outlives this process
+    etcd_client
+        .kv_put(key, serde_json::to_vec(entry)?, Some(0))
+        .await
+}
+
+/// Take `worker_id` off the blacklist. Returns whether it was on it.
+pub async fn unblacklist(etcd_client: &etcd::Client, worker_id: WorkerId) -> anyhow::Result<bool> {
+    let key = format!("{KV_ROUTER_BLACKLIST_ROOT_PATH}{worker_id}");
+    Ok(etcd_client.kv_delete(key, None).await? > 0)
+}
+
+/// The blacklisted workers
+pub async fn list_blacklist(
+    etcd_client: &etcd::Client,
+) -> anyhow::Result<BTreeMap<WorkerId, BlacklistEntry>> {
+    let kvs = etcd_client
+        .kv_get_prefix(KV_ROUTER_BLACKLIST_ROOT_PATH)
+        .await?;
+    Ok(kvs
+        .iter()
+        .filter_map(|kv| {
+            let worker_id = blacklisted_worker(kv.key())?;
+            let entry = serde_json::from_slice(kv.value()).unwrap_or_default();
+            Some((worker_id, entry))
+        })
+        .collect())
+}
+
+/// Count the outcome of a request sent to `worker_id` against its circuit breaker. Responses
+/// pass through unchanged. A request that was stopped, or whose client went away, counts as
+/// neither a success nor a failure.
+pub(crate) fn track<T: Send + 'static>(
+    health: Arc<WorkerHealth>,
+    worker_id: WorkerId,
+    stream: ManyOut<Annotated<T>>,
+) -> ManyOut<Annotated<T>> {
+    let ctx = stream.context();
+    let request_ctx = ctx.clone();
+    let tracked = async_stream::stream! {
+        let mut stream = stream;
+        let mut failed = false;
+        let first = match health.config.first_response_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(first) => first,
+                Err(_) => {
+                    tracing::debug!(worker_id, "First response timed out");
+                    health.record_failure(worker_id);
+                    failed = true;
+                    stream.next().await
+                }
+            },
+            None => stream.next().await,
+        };
+        let Some(first) = first else {
+            // The worker ended the stream without a response
+            if !failed && !request_ctx.is_stopped() {
+                health.record_failure(worker_id);
+            }
+            return;
+        };
+        let mut next = Some(first);
+        while let Some(annotated) = next {
+            if annotated.is_error() && !failed {
+                health.record_failure(worker_id);
+                failed = true;
+            }
+            yield annotated;
+            next = stream.next().await;
+        }
+        if !failed && !request_ctx.is_stopped() {
+            health.record_success(worker_id);
+        }
+    };
+    ResponseStream::new(Box::pin(tracked), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv_router::protocols::ForwardPassMetrics;
+    use crate::kv_router::scheduler::Endpoint;
+
+    fn endpoints(worker_ids: &[WorkerId]) -> ProcessedEndpoints {
+        ProcessedEndpoints::new(
+            worker_ids
+                .iter()
+                .map(|worker_id| Endpoint {
+                    name: format!("worker-{worker_id}"),
+                    subject: format!("ns.backend.generate-{worker_id:x}"),
+                    data: ForwardPassMetrics::default(),
+                })
+                .collect(),
+        )
+    }
+
+    fn available(health: &WorkerHealth, all: &ProcessedEndpoints) -> Vec<WorkerId> {
+        let mut worker_ids: Vec<_> = health.available(all).endpoints.keys().copied().collect();
+        worker_ids.sort_unstable();
+        worker_ids
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let health = WorkerHealth::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(10),
+            first_response_timeout: None,
+        });
+        let all = endpoints(&[1, 2]);
+
+        // A success resets the count
+        health.record_failure(1);
+        health.record_success(1);
+        health.record_failure(1);
+        assert_eq!(available(&health, &all), vec![1, 2]);
+        health.record_failure(1);
+        assert_eq!(available(&health, &all), vec![2]);
+        assert_eq!(health.states().0[&1], BreakerState::Open);
+
+        // Half-open after the open time, the probe fails
+        health.changed().await;
+        assert_eq!(available(&health, &all), vec![1, 2]);
+        health.on_scheduled(1);
+        assert_eq!(available(&health, &all), vec![2]);
+        health.record_failure(1);
+        assert_eq!(available(&health, &all), vec![2]);
+
+        // The next probe succeeds
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(available(&health, &all), vec![1, 2]);
+        health.on_scheduled(1);
+        health.record_success(1);
+        assert!(health.states().0.is_empty());
+
+        health.set_blacklisted(2, true);
+        assert_eq!(available(&health, &all), vec![1]);
+        assert_eq!(health.states().1, vec![2]);
+        health.set_blacklisted(2, false);
+        assert_eq!(available(&health, &all), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_blacklisted_worker() {
+        assert_eq!(
+            blacklisted_worker(
+                format!("{KV_ROUTER_BLACKLIST_ROOT_PATH}7587888160958628000").as_bytes()
+            ),
+            Some(7587888160958628000)
+        );
+        assert_eq!(
+            blacklisted_worker(format!("{KV_ROUTER_BLACKLIST_ROOT_PATH}abc").as_bytes()),
+            None
+        );
+    }
+}
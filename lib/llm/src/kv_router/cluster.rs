@@ -0,0 +1,272 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Two-level KV routing over several clusters, each a namespace with its own workers.
+//!
+//! A [`HierarchicalRouter`] keeps a [`KvRouter`] per cluster. For a request it asks each for the
+//! most blocks of the prompt one of its workers has cached and for the summed load of its
+//! workers, lets a [`ClusterSelector`] pick the cluster, then the [`KvRouter`] of that cluster
+//! pick the worker.
+
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use dynamo_runtime::{
+    pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContextProvider, Error, ManyOut, ResponseStream,
+        SingleIn,
+    },
+    protocols::annotated::Annotated,
+};
+use futures::StreamExt;
+
+use super::metrics_aggregator::{ClusterMetrics, KvHitRates};
+use super::protocols::{RouterRequest, RouterResponse};
+use super::scheduler::{KvSchedulerError, Placement};
+use super::{ClusterSelector, KvRouter, SchedulerStats};
+
+/// A cluster a [`ClusterSelector`] can pick for a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterCandidate {
+    pub namespace: String,
+    pub metrics: ClusterMetrics,
+    /// The most blocks of the prompt cached by one worker of the cluster
+    pub overlap_blocks: u32,
+}
+
+/// Picks the cluster with the best cache hit, the emptiest KV caches and the shortest queues.
+/// Clusters without a free request slot are only picked when all are full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DefaultClusterSelector {
+    /// Weight of the share of the prompt that is cached
+    pub overlap_weight: f64,
+    /// Weight of the average KV cache usage
+    pub usage_weight: f64,
+    /// Weight of the requests waiting per worker, relative to the busiest cluster
+    pub waiting_weight: f64,
+}
+
+impl Default for DefaultClusterSelector {
+    fn default() -> Self {
+        DefaultClusterSelector {
+            overlap_weight: 1.0,
+            usage_weight: 1.0,
+            waiting_weight: 1.0,
+        }
+    }
+}
+
+impl DefaultClusterSelector {
+    fn logit(&self, cluster: &ClusterCandidate, isl_blocks: usize, max_waiting: f64) -> f64 {
+        let overlap = if isl_blocks == 0 {
+            0.0
+        } else {
+            cluster.overlap_blocks as f64 / isl_blocks as f64
+        };
+        let waiting = if max_waiting == 0.0 {
+            0.0
+        } else {
+            cluster.metrics.waiting_per_worker() / max_waiting
+        };
+        self.overlap_weight * overlap
+            - self.usage_weight * cluster.metrics.gpu_cache_usage_perc as f64
+            - self.waiting_weight * waiting
+    }
+}
+
+impl ClusterSelector for DefaultClusterSelector {
+    fn select_cluster(
+        &self,
+        clusters: &[ClusterCandidate],
+        isl_blocks: usize,
+    ) -> Result<usize, KvSchedulerError> {
+        let serving: Vec<(usize, &ClusterCandidate)> = clusters
+            .iter()
+            .enumerate()
+            .filter(|(_, cluster)| cluster.metrics.workers > 0)
+            .collect();
+        if serving.is_empty() {
+            return Err(KvSchedulerError::NoEndpoints);
+        }
+        let all_full = serving.iter().all(|(_, cluster)| cluster.metrics.is_full());
+        let max_waiting = serving
+            .iter()
+            .map(|(_, cluster)| cluster.metrics.waiting_per_worker())
+            .fold(0.0, f64::max);
+
+        let mut best: Option<(usize, f64)> = None;
+        for (index, cluster) in serving {
+            if cluster.metrics.is_full() && !all_full {
+                continue;
+            }
+            let logit = self.logit(cluster, isl_blocks, max_waiting);
+            tracing::trace!(namespace = %cluster.namespace, logit, "Cluster score");
+            // The first of equal clusters wins, so that the choice is stable
+            if best.is_none_or(|(_, best_logit)| logit > best_logit) {
+                best = Some((index, logit));
+            }
+        }
+        // safety: There is at least one serving cluster, and all are skipped only if one isn't full
+        Ok(best.unwrap().0)
+    }
+}
+
+/// Routes between clusters, then to a worker of the chosen cluster, see the [module docs](self)
+pub struct HierarchicalRouter {
+    clusters: Vec<(String, Arc<KvRouter>)>,
+    selector: Box<dyn ClusterSelector + Send + Sync>,
+    block_size: usize,
+}
+
+impl HierarchicalRouter {
+    /// Route between `clusters`, the [`KvRouter`] of each namespace. They must have the same
+    /// block size. Uses the [`DefaultClusterSelector`] if `selector` is `None`.
+    pub fn new(
+        clusters: Vec<(String, Arc<KvRouter>)>,
+        selector: Option<Box<dyn ClusterSelector + Send + Sync>>,
+    ) -> Result<Self> {
+        let Some((_, first)) = clusters.first() else {
+            anyhow::bail!("A hierarchical router needs at least one cluster");
+        };
+        let block_size = first.block_size();
+        if let Some((namespace, router)) = clusters
+            .iter()
+            .find(|(_, router)| router.block_size() != block_size)
+        {
+            anyhow::bail!(
+                "Cluster {namespace} has block size {}, the others {block_size}",
+                router.block_size()
+            );
+        }
+        Ok(HierarchicalRouter {
+            clusters,
+            selector: selector.unwrap_or_else(|| Box::new(DefaultClusterSelector::default())),
+            block_size,
+        })
+    }
+
+    /// The clusters as the [`ClusterSelector`] sees them for `tokens`
+    pub async fn candidates(&self, tokens: &[u32], lora_id: u64) -> Result<Vec<ClusterCandidate>> {
+        futures::future::try_join_all(self.clusters.iter().map(|(namespace, router)| async move {
+            Ok::<_, anyhow::Error>(ClusterCandidate {
+                namespace: namespace.clone(),
+                metrics: router.cluster_metrics(),
+                overlap_blocks: router.overlap_blocks(tokens, lora_id).await?,
+            })
+        }))
+        .await
+    }
+
+    /// The namespace and router of the cluster for `tokens`
+    async fn select_cluster(
+        &self,
+        tokens: &[u32],
+        lora_id: u64,
+    ) -> Result<&(String, Arc<KvRouter>)> {
+        let candidates = self.candidates(tokens, lora_id).await?;
+        let index = self
+            .selector
+            .select_cluster(&candidates, tokens.len() / self.block_size)?;
+        self.clusters.get(index).with_context(|| {
+            format!(
+                "Cluster selector picked cluster {index} of {}",
+                self.clusters.len()
+            )
+        })
+    }
+
+    /// Pick a cluster, then a worker of it, for `token_ids` under the LoRA adapter `lora_id`.
+    /// Returns the namespace of the cluster and the worker.
+    pub async fn schedule(&self, token_ids: &[u32], lora_id: u64) -> Result<(String, i64)> {
+        let (namespace, router) = self.select_cluster(token_ids, lora_id).await?;
+        let placement = Placement {
+            lora_id,
+            ..Default::default()
+        };
+        let (worker_id, _overlap_amount) = router.find_best_match(token_ids, placement).await?;
+        Ok((namespace.clone(), worker_id))
+    }
+
+    /// Get the block size of the clusters
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+}
+
+impl SchedulerStats for HierarchicalRouter {
+    /// Of the workers of all the clusters
+    fn hit_rates(&self) -> KvHitRates {
+        let mut hit_rates = KvHitRates::default();
+        for (_, router) in &self.clusters {
+            hit_rates.merge(&router.hit_rates());
+        }
+        hit_rates
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<RouterRequest>, ManyOut<Annotated<RouterResponse>>, Error>
+    for HierarchicalRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<RouterRequest>,
+    ) -> Result<ManyOut<Annotated<RouterResponse>>> {
+        let (namespace, router) = self
+            .select_cluster(&request.tokens, request.lora_id)
+            .await?;
+        let namespace = namespace.clone();
+        let responses = router.generate(request).await?;
+        let ctx = responses.context();
+        let responses = responses.map(move |mut response| {
+            if let Some(data) = response.data.as_mut() {
+                data.namespace = Some(namespace.clone());
+            }
+            response
+        });
+        Ok(ResponseStream::new(Box::pin(responses), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(namespace: &str, workers: usize, usage: f32, waiting: u64) -> ClusterCandidate {
+        ClusterCandidate {
+            namespace: namespace.to_string(),
+            metrics: ClusterMetrics {
+                workers,
+                request_active_slots: 0,
+                request_total_slots: 8 * workers as u64,
+                kv_active_blocks: 0,
+                kv_total_blocks: 100 * workers as u64,
+                num_requests_waiting: waiting,
+                gpu_cache_usage_perc: usage,
+            },
+            overlap_blocks: 0,
+        }
+    }
+
+    #[test]
+    fn test_default_cluster_selector() {
+        let selector = DefaultClusterSelector::default();
+        assert!(matches!(
+            selector.select_cluster(&[cluster("a", 0, 0.0, 0)], 4),
+            Err(KvSchedulerError::NoEndpoints)
+        ));
+
+        // The emptier cluster, then the one with the prompt cached
+        let mut clusters = vec![cluster("a", 2, 0.8, 4), cluster("b", 2, 0.2, 0)];
+        assert_eq!(selector.select_cluster(&clusters, 4).unwrap(), 1);
+        clusters[0].overlap_blocks = 4;
+        clusters[0].metrics.num_requests_waiting = 0;
+        assert_eq!(selector.select_cluster(&clusters, 4).unwrap(), 0);
+
+        // A full cluster is only picked if all are
+        clusters[0].metrics.request_active_slots = 16;
+        assert_eq!(selector.select_cluster(&clusters, 4).unwrap(), 1);
+        clusters[1].metrics.request_active_slots = 16;
+        assert_eq!(selector.select_cluster(&clusters, 4).unwrap(), 0);
+    }
+}
@@ -35,34 +35,145 @@
 //!
 //! - **Concurrency and Asynchronous Operations**:
 //!   - The `KvIndexer` uses a single-threaded Tokio runtime to handle events and match requests concurrently, ensuring efficient processing without blocking.
+//!   - The `KvIndexerSharded` splits the index over several `KvIndexer`s by worker, so that the events of hundreds of workers are applied in parallel.
 //!
 //! - **Match Requests**:
 //!   - The `MatchRequest` struct represents requests to find matches in the Radix Tree, returning overlap scores indicating the best matches.
 //!
+//! - **Bounds**:
+//!   - Workers that die or lose events never remove their blocks. [`IndexerLimits`] caps the entries of the tree, evicting the least recently
+//!     stored or matched blocks, and can expire blocks nobody stored or matched for a while.
+//!
+//! - **Snapshots**:
+//!   - [`RadixTree::snapshot`] lists the blocks of every worker, so that a restarted router can rebuild its index, see
+//!     [`snapshot`](crate::kv_router::snapshot).
+//!
 //! # Purpose
 //!
 //! This module provides a scalable and efficient way to manage and retrieve data blocks for LLM inference, leveraging a global KV cache to optimize performance.
 
-use bytes::Bytes;
-// use prometheus::{IntCounter, IntGauge};
 use async_trait::async_trait;
+use bytes::Bytes;
+use prometheus::{IntCounterVec, IntGauge, Opts};
 use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
+    cmp::Reverse,
     collections::{HashMap, HashSet, VecDeque},
-    iter,
     rc::Rc,
-    sync::OnceLock,
-    thread::JoinHandle,
+    sync::{LazyLock, OnceLock},
     time::{Duration, Instant},
 };
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
 use xxhash_rust::xxh3;
 
 pub const XXH3_SEED: u64 = 1337;
 
+/// Memory one entry of the index takes, estimated: its place in the worker's lookup table and
+/// in the block's set of workers, and a share of the block.
+pub const ESTIMATED_ENTRY_BYTES: usize = 256;
+
+/// Over a limit, entries are evicted down to this share of it, so that eviction doesn't run for
+/// every event.
+const EVICT_TO: f64 = 0.9;
+
+/// Orders entries for eviction: whether the entry is still in the tree, when the worker last used
+/// it or a block below it, and how deep it is, deepest first
+type EvictionKey = (bool, Instant, Reverse<usize>);
+
+/// The longest time between two checks for expired blocks
+const MAX_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
 use crate::kv_router::protocols::*;
+use crate::kv_router::snapshot::{IndexSnapshot, SnapshotBlock};
+
+static INDEX_ENTRIES: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_kv_indexer_entries",
+        "Blocks in the KV router indexes, counted once per worker that has them",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static INDEX_ESTIMATED_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_kv_indexer_estimated_bytes",
+        "Estimated memory of the KV router indexes",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static INDEX_EVICTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_kv_indexer_evicted_total",
+            "Entries the KV router indexes evicted, because they were full (lru) or expired (ttl)",
+        ),
+        &["reason"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the indexer metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(INDEX_ENTRIES.clone()))?;
+    registry.register(Box::new(INDEX_ESTIMATED_BYTES.clone()))?;
+    registry.register(Box::new(INDEX_EVICTED.clone()))?;
+    Ok(())
+}
+
+/// Bounds on a [`RadixTree`]. An entry is a block of one worker. None of them are set by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexerLimits {
+    /// Most entries. Over it, the least recently stored or matched blocks are evicted.
+    pub max_entries: Option<usize>,
+
+    /// Most memory the entries may take, estimated at [`ESTIMATED_ENTRY_BYTES`] each. Evicts like
+    /// `max_entries`.
+    pub max_memory_bytes: Option<usize>,
+
+    /// Blocks not stored or matched for this long are evicted
+    pub block_ttl: Option<Duration>,
+}
+
+impl IndexerLimits {
+    /// The most entries allowed, from both limits
+    fn entry_limit(&self) -> Option<usize> {
+        let from_memory = self
+            .max_memory_bytes
+            .map(|bytes| bytes / ESTIMATED_ENTRY_BYTES);
+        match (self.max_entries, from_memory) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// How often to check for expired blocks, if they expire
+    fn expiry_interval(&self) -> Option<Duration> {
+        self.block_ttl
+            .map(|ttl| ttl.clamp(Duration::from_millis(100), MAX_EXPIRY_INTERVAL))
+    }
+}
+
+/// How the index of a KV router is set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexerConfig {
+    /// Bounds on the index, split evenly over the shards
+    pub limits: IndexerLimits,
+
+    /// Shards of the index, see [`KvIndexerSharded`]. Default: 1
+    pub shards: usize,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            limits: IndexerLimits::default(),
+            shards: 1,
+        }
+    }
+}
 
 /// Errors that can occur in the KV Router.
 #[derive(Debug, thiserror::Error)]
@@ -80,6 +191,9 @@ pub enum KvRouterError {
 /// Identifier of a LLM worker which emits events to the router.
 pub type WorkerId = i64;
 
+/// Asks a [`KvIndexer`] for a snapshot of its index, see [`request_snapshot`].
+pub type SnapshotSender = mpsc::Sender<oneshot::Sender<IndexSnapshot>>;
+
 /// A shared reference to a [`RadixBlock`].
 type SharedRadixBlock = Rc<RefCell<RadixBlock>>;
 
@@ -100,16 +214,6 @@ pub fn compute_block_hash(data: &[u8]) -> LocalBlockHash {
     LocalBlockHash(compute_hash(data))
 }
 
-// /// Updated version of the `compute_block_hash` function that included the lora_id
-// pub fn compute_block_hash_v2(token_id: &[u32], lora_id: u64) {
-//     let mut bytes = Vec::new();
-//     for token in token_id {
-//         bytes.extend_from_slice(&token.to_le_bytes());
-//     }
-//     bytes.extend_from_slice(&lora_id.to_le_bytes());
-//     let hash = xxh3::xxh3_64_with_seed(&bytes, XXH3_SEED);
-// }
-
 /// Compute the hash for a sequence of tokens.
 ///
 /// ### Arguments
@@ -133,6 +237,27 @@ pub fn compute_block_hash_for_seq(tokens: &[u32], kv_block_size: usize) -> Vec<L
         .collect()
 }
 
+/// Compute the hashes for a sequence of tokens under a LoRA adapter, so that the same tokens
+/// under different adapters are different blocks. `lora_id` 0 is the base model, whose hashes
+/// are those of [`compute_block_hash_for_seq`].
+pub fn compute_block_hash_for_seq_with_lora(
+    tokens: &[u32],
+    kv_block_size: usize,
+    lora_id: u64,
+) -> Vec<LocalBlockHash> {
+    if lora_id == 0 {
+        return compute_block_hash_for_seq(tokens, kv_block_size);
+    }
+    tokens
+        .chunks_exact(kv_block_size)
+        .map(|chunk| {
+            let mut bytes: Vec<u8> = chunk.iter().flat_map(|&num| num.to_le_bytes()).collect();
+            bytes.extend_from_slice(&lora_id.to_le_bytes());
+            compute_block_hash(&bytes)
+        })
+        .collect()
+}
+
 /// A [`KvCacheEvent`] on a specific LLM worker denoted by [`WorkerId`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouterEvent {
@@ -156,6 +281,63 @@ impl RouterEvent {
     pub fn new(worker_id: WorkerId, event: KvCacheEvent) -> Self {
         Self { worker_id, event }
     }
+
+    /// The ID of the worker emitting the event.
+    pub fn worker_id(&self) -> WorkerId {
+        self.worker_id
+    }
+}
+
+/// Version of the messages on the KV events subject. Version 1 is one [`RouterEvent`] per
+/// message, version 2 adds the [`RouterEventBatch`]. Routers decode both, so workers can be
+/// upgraded before or after them.
+pub const KV_EVENTS_PROTOCOL_VERSION: u32 = 2;
+
+/// Several [`KvCacheEvent`]s of a worker in one message, in the order they happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouterEventBatch {
+    /// [`KV_EVENTS_PROTOCOL_VERSION`] of the publishing worker
+    pub version: u32,
+    /// The ID of the worker emitting the events.
+    pub worker_id: WorkerId,
+    /// The cache events, oldest first.
+    pub events: Vec<KvCacheEvent>,
+}
+
+impl RouterEventBatch {
+    pub fn new(worker_id: WorkerId, events: Vec<KvCacheEvent>) -> Self {
+        RouterEventBatch {
+            version: KV_EVENTS_PROTOCOL_VERSION,
+            worker_id,
+            events,
+        }
+    }
+
+    /// A [`RouterEvent`] per event, in order
+    pub fn into_router_events(self) -> Vec<RouterEvent> {
+        let worker_id = self.worker_id;
+        self.events
+            .into_iter()
+            .map(|event| RouterEvent::new(worker_id, event))
+            .collect()
+    }
+}
+
+/// A message on the KV events subject, of either version
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KvEventsMessage {
+    Batch(RouterEventBatch),
+    Single(RouterEvent),
+}
+
+/// Decode a message of the KV events subject, a single [`RouterEvent`] or a [`RouterEventBatch`],
+/// into its events.
+pub fn decode_router_events(payload: &[u8]) -> Result<Vec<RouterEvent>, serde_json::Error> {
+    Ok(match serde_json::from_slice(payload)? {
+        KvEventsMessage::Batch(batch) => batch.into_router_events(),
+        KvEventsMessage::Single(event) => vec![event],
+    })
 }
 
 /// A block in the Radix Tree.
@@ -167,6 +349,8 @@ struct RadixBlock {
     workers: HashSet<WorkerId>,
     /// A buffer of times that this block was last traversed
     recent_uses: VecDeque<Instant>,
+    /// When this block was last stored or matched, for eviction
+    last_used: Instant,
 }
 
 impl RadixBlock {
@@ -180,6 +364,7 @@ impl RadixBlock {
             children: HashMap::new(),
             workers: HashSet::new(),
             recent_uses: VecDeque::new(),
+            last_used: Instant::now(),
         }
     }
 }
@@ -200,6 +385,10 @@ pub struct RadixTree {
     lookup: HashMap<WorkerId, HashMap<ExternalSequenceBlockHash, SharedRadixBlock>>,
     /// The time buffer the radix tree should check when considering frequence of block accesses
     expiration_duration: Option<Duration>,
+    /// Bounds on the entries of the tree
+    limits: IndexerLimits,
+    /// The entries last added to the metrics
+    reported_entries: usize,
 }
 
 impl Default for RadixTree {
@@ -219,6 +408,8 @@ impl RadixTree {
             root: Rc::new(RefCell::new(RadixBlock::new())),
             lookup: HashMap::new(),
             expiration_duration,
+            limits: IndexerLimits::default(),
+            reported_entries: 0,
         }
     }
 
@@ -226,6 +417,17 @@ impl RadixTree {
         Self::new_with_frequency(None)
     }
 
+    /// Bound the entries of the tree
+    pub fn with_limits(mut self, limits: IndexerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Blocks of all workers, counting a block once for each worker that has it
+    pub fn num_entries(&self) -> usize {
+        self.lookup.values().map(|blocks| blocks.len()).sum()
+    }
+
     /// Traverse the radix tree to find the best match for a given sequence of [`LocalBlockHash`]es.
     ///
     /// ### Arguments
@@ -247,6 +449,7 @@ impl RadixTree {
             };
             if let Some(block) = next_block {
                 scores.update_scores(&block.borrow().workers);
+                block.borrow_mut().last_used = now;
 
                 if let Some(expiration_duration) = self.expiration_duration {
                     let mut block_mut = block.borrow_mut();
@@ -281,6 +484,16 @@ impl RadixTree {
     ///
     /// * `event` - The `RouterEvent` to apply.
     pub fn apply_event(&mut self, event: RouterEvent) {
+        self.apply_event_inner(event);
+        if let Some(limit) = self.limits.entry_limit() {
+            if self.num_entries() > limit {
+                self.evict_lru(limit);
+            }
+        }
+        self.report_metrics();
+    }
+
+    fn apply_event_inner(&mut self, event: RouterEvent) {
         let (worker_id, event) = (event.worker_id, event.event);
         let (id, op) = (event.event_id, event.data);
         tracing::trace!(id, "Store operation: {:?}", op);
@@ -331,7 +544,11 @@ impl RadixTree {
                     };
 
                     // add our worker_id to the block
-                    block.borrow_mut().workers.insert(worker_id);
+                    {
+                        let mut block = block.borrow_mut();
+                        block.workers.insert(worker_id);
+                        block.last_used = Instant::now();
+                    }
 
                     // add the block to the worker_id lookup table
                     worker_lookup.insert(block_id.block_hash, block.clone());
@@ -363,12 +580,7 @@ impl RadixTree {
                         }
                     };
 
-                    let mut guard = entry.borrow_mut();
-                    guard.workers.remove(&worker_id);
-                    if guard.workers.is_empty() {
-                        // if no worker are using this block, that is true for all children
-                        guard.children.clear();
-                    }
+                    remove_from_block(&entry, worker_id);
                     // remove the block from the lookup table
                     worker_lookup.remove(&block);
                 }
@@ -379,12 +591,210 @@ impl RadixTree {
         }
     }
 
+    /// Evict the least recently used entries, down to below `limit`
+    fn evict_lru(&mut self, limit: usize) {
+        let mut entries = self.eviction_order();
+        let target = (limit as f64 * EVICT_TO) as usize;
+        let excess = entries.len().saturating_sub(target);
+        entries.select_nth_unstable_by_key(excess.saturating_sub(1), |(key, ..)| *key);
+        for (_, worker, hash) in &entries[..excess] {
+            self.evict(*worker, *hash);
+        }
+        let evicted = excess + self.prune();
+        INDEX_EVICTED
+            .with_label_values(&["lru"])
+            .inc_by(evicted as u64);
+        tracing::debug!(
+            evicted,
+            limit,
+            "KV index full; evicted least recently used blocks"
+        );
+    }
+
+    /// Evict the blocks not stored or matched within the TTL, as of `now`
+    pub fn evict_expired(&mut self, now: Instant) {
+        let Some(ttl) = self.limits.block_ttl else {
+            return;
+        };
+        let expired: Vec<(WorkerId, ExternalSequenceBlockHash)> = self
+            .eviction_order()
+            .into_iter()
+            .filter(|((in_tree, last_used, _), ..)| {
+                !in_tree || now.saturating_duration_since(*last_used) > ttl
+            })
+            .map(|(_, worker, hash)| (worker, hash))
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for (worker, hash) in &expired {
+            self.evict(*worker, *hash);
+        }
+        let evicted = expired.len() + self.prune();
+        INDEX_EVICTED
+            .with_label_values(&["ttl"])
+            .inc_by(evicted as u64);
+        self.report_metrics();
+    }
+
+    /// Every entry with its [`EvictionKey`]. A block was used when a block the worker has below it
+    /// was, so an entry sorts before the entries above it, and evicting in this order never leaves
+    /// a worker with blocks whose prefix was evicted.
+    fn eviction_order(&self) -> Vec<(EvictionKey, WorkerId, ExternalSequenceBlockHash)> {
+        // Children before their parents
+        let mut used: HashMap<(*const RefCell<RadixBlock>, WorkerId), (Instant, usize)> =
+            HashMap::new();
+        let mut stack = vec![(self.root.clone(), 0, false)];
+        while let Some((block, depth, children_done)) = stack.pop() {
+            if !children_done {
+                stack.push((block.clone(), depth, true));
+                let children = block
+                    .borrow()
+                    .children
+                    .values()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                stack.extend(children.into_iter().map(|child| (child, depth + 1, false)));
+                continue;
+            }
+            let guard = block.borrow();
+            for worker in guard.workers.iter() {
+                let last_used = guard
+                    .children
+                    .values()
+                    .filter_map(|child| used.get(&(Rc::as_ptr(child), *worker)))
+                    .map(|(last_used, _)| *last_used)
+                    .fold(guard.last_used, Instant::max);
+                used.insert((Rc::as_ptr(&block), *worker), (last_used, depth));
+            }
+        }
+        let used = &used;
+        self.lookup
+            .iter()
+            .flat_map(|(worker, blocks)| {
+                blocks.iter().map(move |(hash, block)| {
+                    let key = match used.get(&(Rc::as_ptr(block), *worker)) {
+                        Some((last_used, depth)) => (true, *last_used, Reverse(*depth)),
+                        // Its parent was dropped, so it can't be matched
+                        None => (false, block.borrow().last_used, Reverse(0)),
+                    };
+                    (key, *worker, *hash)
+                })
+            })
+            .collect()
+    }
+
+    /// Forget that `worker` has the block. [`Self::prune`] drops it from the tree if no worker
+    /// has it any more.
+    fn evict(&mut self, worker: WorkerId, hash: ExternalSequenceBlockHash) {
+        if let Some(block) = self
+            .lookup
+            .get_mut(&worker)
+            .and_then(|blocks| blocks.remove(&hash))
+        {
+            block.borrow_mut().workers.remove(&worker);
+        }
+    }
+
+    /// Drop the blocks no worker has from the tree, so that their memory is freed. Blocks below
+    /// them can't be matched any more, so they are removed from the lookup tables too. Returns
+    /// how many entries those were.
+    fn prune(&mut self) -> usize {
+        let mut detached = HashSet::new();
+        let mut stack = vec![self.root.clone()];
+        while let Some(block) = stack.pop() {
+            let mut block = block.borrow_mut();
+            block.children.retain(|_, child| {
+                if !child.borrow().workers.is_empty() {
+                    return true;
+                }
+                let mut below: Vec<_> = child.borrow().children.values().cloned().collect();
+                while let Some(node) = below.pop() {
+                    detached.insert(Rc::as_ptr(&node));
+                    below.extend(node.borrow().children.values().cloned());
+                }
+                false
+            });
+            stack.extend(block.children.values().cloned());
+        }
+        if detached.is_empty() {
+            return 0;
+        }
+        let mut removed = 0;
+        for blocks in self.lookup.values_mut() {
+            let before = blocks.len();
+            blocks.retain(|_, block| !detached.contains(&Rc::as_ptr(block)));
+            removed += before - blocks.len();
+        }
+        removed
+    }
+
+    /// Add the change in entries since the last report to the metrics, which add up the trees
+    fn report_metrics(&mut self) {
+        let entries = self.num_entries();
+        let change = entries as i64 - self.reported_entries as i64;
+        INDEX_ENTRIES.add(change);
+        INDEX_ESTIMATED_BYTES.add(change * ESTIMATED_ENTRY_BYTES as i64);
+        self.reported_entries = entries;
+    }
+
+    /// The blocks of every worker, every parent before its children
+    pub fn snapshot(&self) -> Vec<(WorkerId, Vec<SnapshotBlock>)> {
+        // The tree has the local hashes, the lookup tables the workers' own
+        let hashes: HashMap<
+            WorkerId,
+            HashMap<*const RefCell<RadixBlock>, ExternalSequenceBlockHash>,
+        > = self
+            .lookup
+            .iter()
+            .map(|(worker, blocks)| {
+                let hashes = blocks
+                    .iter()
+                    .map(|(hash, block)| (Rc::as_ptr(block), *hash))
+                    .collect();
+                (*worker, hashes)
+            })
+            .collect();
+        let mut workers: HashMap<WorkerId, Vec<SnapshotBlock>> = HashMap::new();
+        let mut stack = vec![self.root.clone()];
+        while let Some(parent) = stack.pop() {
+            let parent_ptr = (!Rc::ptr_eq(&parent, &self.root)).then(|| Rc::as_ptr(&parent));
+            for (tokens_hash, child) in parent.borrow().children.iter() {
+                for worker in child.borrow().workers.iter() {
+                    let Some(hashes) = hashes.get(worker) else {
+                        continue;
+                    };
+                    let Some(block_hash) = hashes.get(&Rc::as_ptr(child)) else {
+                        continue;
+                    };
+                    let parent_hash = match parent_ptr {
+                        None => None,
+                        // The worker removed the parent and kept the child, which can't be
+                        // stored again without it
+                        Some(ptr) => match hashes.get(&ptr) {
+                            Some(hash) => Some(*hash),
+                            None => continue,
+                        },
+                    };
+                    workers.entry(*worker).or_default().push(SnapshotBlock {
+                        parent_hash,
+                        block_hash: *block_hash,
+                        tokens_hash: *tokens_hash,
+                    });
+                }
+                stack.push(child.clone());
+            }
+        }
+        workers.into_iter().collect()
+    }
+
     pub fn remove_worker(&mut self, worker: WorkerId) {
         if let Some((_, blocks)) = self.lookup.remove_entry(&worker) {
             blocks.iter().for_each(|(_, block)| {
                 block.borrow_mut().workers.remove(&worker);
             });
         }
+        self.report_metrics();
     }
 
     pub fn clear_all_blocks(&mut self, worker: WorkerId) {
@@ -405,6 +815,23 @@ impl RadixTree {
     }
 }
 
+impl Drop for RadixTree {
+    fn drop(&mut self) {
+        INDEX_ENTRIES.sub(self.reported_entries as i64);
+        INDEX_ESTIMATED_BYTES.sub((self.reported_entries * ESTIMATED_ENTRY_BYTES) as i64);
+    }
+}
+
+/// `worker` no longer has `block`
+fn remove_from_block(block: &SharedRadixBlock, worker: WorkerId) {
+    let mut guard = block.borrow_mut();
+    guard.workers.remove(&worker);
+    if guard.workers.is_empty() {
+        // if no worker are using this block, that is true for all children
+        guard.children.clear();
+    }
+}
+
 /// Scores representing the overlap of workers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OverlapScores {
@@ -524,6 +951,8 @@ pub struct KvIndexer {
     match_tx: mpsc::Sender<MatchRequest>,
     /// A sender for remove worker requests.
     remove_worker_tx: mpsc::Sender<WorkerId>,
+    /// A sender for snapshot requests.
+    snapshot_tx: SnapshotSender,
     /// A handle to the background task managing the KV store.
     task: OnceLock<std::thread::JoinHandle<()>>,
     /// The size of the KV block this indexer can handle.
@@ -545,10 +974,34 @@ impl KvIndexer {
         token: CancellationToken,
         expiration_duration: Option<Duration>,
         kv_block_size: usize,
+    ) -> Self {
+        Self::start(
+            token,
+            expiration_duration,
+            kv_block_size,
+            IndexerLimits::default(),
+        )
+    }
+
+    /// Create a new `KvIndexer` whose index is bounded by `limits`.
+    pub fn new_with_limits(
+        token: CancellationToken,
+        kv_block_size: usize,
+        limits: IndexerLimits,
+    ) -> Self {
+        Self::start(token, None, kv_block_size, limits)
+    }
+
+    fn start(
+        token: CancellationToken,
+        expiration_duration: Option<Duration>,
+        kv_block_size: usize,
+        limits: IndexerLimits,
     ) -> Self {
         let (event_tx, event_rx) = mpsc::channel::<RouterEvent>(2048);
         let (match_tx, match_rx) = mpsc::channel::<MatchRequest>(128);
         let (remove_worker_tx, remove_worker_rx) = mpsc::channel::<WorkerId>(16);
+        let (snapshot_tx, snapshot_rx) = mpsc::channel::<oneshot::Sender<IndexSnapshot>>(4);
         let cancel_clone = token.clone();
         let task = std::thread::spawn(move || {
             // create a new tokio runtime which will only perform work on a single thread
@@ -566,7 +1019,10 @@ impl KvIndexer {
                     let mut match_rx = match_rx;
                     let mut event_rx = event_rx;
                     let mut remove_worker_rx = remove_worker_rx;
-                    let mut trie = RadixTree::new_with_frequency(expiration_duration);
+                    let mut snapshot_rx = snapshot_rx;
+                    let mut trie =
+                        RadixTree::new_with_frequency(expiration_duration).with_limits(limits);
+                    let mut expiry = limits.expiry_interval().map(tokio::time::interval);
                     loop {
                         tokio::select! {
                             biased;
@@ -588,6 +1044,14 @@ impl KvIndexer {
                             Some(event) = event_rx.recv() => {
                                 trie.apply_event(event);
                             }
+
+                            _ = tick(&mut expiry) => {
+                                trie.evict_expired(Instant::now());
+                            }
+
+                            Some(resp) = snapshot_rx.recv() => {
+                                let _ = resp.send(IndexSnapshot::new(kv_block_size, trie.snapshot()));
+                            }
                         }
                     }
                 })
@@ -606,6 +1070,7 @@ impl KvIndexer {
             event_tx,
             match_tx,
             remove_worker_tx,
+            snapshot_tx,
             task: once,
             kv_block_size,
         }
@@ -615,6 +1080,35 @@ impl KvIndexer {
         self.kv_block_size
     }
 
+    /// Get a sender for snapshot requests, see [`request_snapshot`].
+    pub fn snapshot_sender(&self) -> SnapshotSender {
+        self.snapshot_tx.clone()
+    }
+
+    /// A snapshot of the index
+    pub async fn snapshot(&self) -> Result<IndexSnapshot, KvRouterError> {
+        request_snapshot(&self.snapshot_tx).await
+    }
+
+    /// Add the blocks of `snapshot` to the index. Returns how many entries it had.
+    pub async fn restore(&self, snapshot: IndexSnapshot) -> anyhow::Result<usize> {
+        if snapshot.kv_block_size != self.kv_block_size {
+            anyhow::bail!(
+                "KV index snapshot has block size {}, expected {}",
+                snapshot.kv_block_size,
+                self.kv_block_size
+            );
+        }
+        let entries = snapshot.num_entries();
+        for event in snapshot.into_events() {
+            self.event_tx
+                .send(event)
+                .await
+                .map_err(|_| KvRouterError::IndexerOffline)?;
+        }
+        Ok(entries)
+    }
+
     pub fn new(token: CancellationToken, kv_block_size: usize) -> Self {
         Self::new_with_frequency(token, None, kv_block_size)
     }
@@ -685,26 +1179,60 @@ impl KvIndexerInterface for KvIndexer {
     }
 }
 
+/// Ask the indexer behind `sender` for a snapshot of its index
+pub async fn request_snapshot(sender: &SnapshotSender) -> Result<IndexSnapshot, KvRouterError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    sender
+        .send(resp_tx)
+        .await
+        .map_err(|_| KvRouterError::IndexerOffline)?;
+    resp_rx
+        .await
+        .map_err(|_| KvRouterError::IndexerDroppedRequest)
+}
+
+/// The next tick of `interval`, never without one
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The shard of `worker`, out of `num_shards`
+fn shard_of(worker: WorkerId, num_shards: usize) -> usize {
+    (xxh3::xxh3_64_with_seed(&worker.to_le_bytes(), XXH3_SEED) % num_shards as u64) as usize
+}
+
+/// Sends [`RouterEvent`]s to the shard of their worker, see [`KvIndexerSharded::event_sender`].
 #[derive(Debug, Clone)]
-pub struct ShardedMatchRequest {
-    sequence: Vec<LocalBlockHash>,
-    early_exit: bool,
-    resp: mpsc::Sender<OverlapScores>,
+pub struct ShardedEventSender {
+    shards: Vec<mpsc::Sender<RouterEvent>>,
 }
 
-/// The KV Indexer, managing the KV store and handling events and match requests.
+impl ShardedEventSender {
+    /// Send `event` to the shard of its worker
+    pub async fn send(&self, event: RouterEvent) -> Result<(), KvRouterError> {
+        self.shards[shard_of(event.worker_id, self.shards.len())]
+            .send(event)
+            .await
+            .map_err(|_| KvRouterError::IndexerOffline)
+    }
+}
+
+/// The KV Indexer split into shards, each a [`KvIndexer`] on its own thread, so that the events
+/// of many workers are applied in parallel. All the blocks of a worker are in the shard its id
+/// hashes to. Matches are looked up in every shard and merged.
+///
+/// The entry and memory limits are split evenly over the shards, which evict on their own.
 pub struct KvIndexerSharded {
-    /// A `CancellationToken` for managing shutdown.
-    cancel: CancellationToken,
+    shards: Vec<KvIndexer>,
+    /// Asks for a snapshot of all the shards, merged
+    snapshot_tx: SnapshotSender,
     /// The size of the KV block this indexer can handle.
     kv_block_size: usize,
-    worker_assignments: HashMap<WorkerId, usize>,
-    worker_counts: Vec<usize>,
-
-    event_tx: Vec<mpsc::Sender<RouterEvent>>,
-    request_broadcast_tx: broadcast::Sender<ShardedMatchRequest>,
-    remove_worker_tx: Vec<mpsc::Sender<WorkerId>>,
-    tasks: Vec<JoinHandle<()>>,
 }
 
 impl KvIndexerSharded {
@@ -713,92 +1241,72 @@ impl KvIndexerSharded {
     /// ### Arguments
     ///
     /// * `token` - A `CancellationToken` for managing shutdown.
-    /// * `shards` - A list of kvindexer shards.
+    /// * `num_shards` - How many shards to split the index into.
     /// * `expiration_duration` - The amount of time that block usage should be buffered.
     ///
     /// ### Returns
     ///
-    /// A new `KvIndexer`.
+    /// A new `KvIndexerSharded`.
     pub fn new_with_frequency(
         token: CancellationToken,
         num_shards: usize,
         expiration_duration: Option<Duration>,
         kv_block_size: usize,
     ) -> Self {
-        let worker_assignments: HashMap<WorkerId, usize> = HashMap::new();
-        let worker_counts: Vec<usize> = vec![0; num_shards];
-
-        let mut event_tx = Vec::new();
-        let mut remove_worker_tx = Vec::new();
-        let mut tasks = Vec::new();
-
-        let (request_broadcast_tx, _) = broadcast::channel::<ShardedMatchRequest>(1048576);
-
-        for _ in 0..num_shards {
-            let (shard_event_tx, mut shard_event_rx) = mpsc::channel::<RouterEvent>(2048);
-            let (shard_remove_worker_tx, mut shard_remove_worker_rx) =
-                mpsc::channel::<WorkerId>(16);
-            let mut shard_broadcast_rx = request_broadcast_tx.subscribe();
-            let cancel = token.clone();
-
-            event_tx.push(shard_event_tx);
-            remove_worker_tx.push(shard_remove_worker_tx);
-
-            let runtime = tokio::runtime::Builder::new_multi_thread()
-                .worker_threads(1)
-                .enable_all()
-                .build()
-                .unwrap();
-
-            tasks.push(std::thread::spawn(move || {
-                let local_set = tokio::task::LocalSet::new();
-
-                runtime.block_on(local_set.run_until(async move {
-                    tokio::task::spawn_local(async move {
-                        let mut trie = RadixTree::new_with_frequency(expiration_duration);
-                        loop {
-                            tokio::select! {
-                                biased;
-
-                                Some(worker) = shard_remove_worker_rx.recv() => {
-                                    trie.remove_worker(worker);
-                                }
-
-                                Ok(req) = shard_broadcast_rx.recv() => {
-                                    let matches = trie.find_matches(req.sequence, req.early_exit);
-                                    if let Err(e) = req.resp.send(matches).await {
-                                        tracing::trace!("Failed to send match response: {:?}", e);
-                                    }
-                                }
-
-                                _ = cancel.cancelled() => {
-                                    tracing::trace!("KvCacheIndexer progress loop shutting down");
-                                    return;
-                                }
-
-                                Some(event) = shard_event_rx.recv() => {
-                                    trie.apply_event(event);
-                                }
-                            }
-                        }
-                    })
-                    .await
-                    .unwrap()
-                }));
+        Self::start(
+            token,
+            num_shards,
+            expiration_duration,
+            kv_block_size,
+            IndexerLimits::default(),
+        )
+    }
 
-                tracing::debug!("KvCacheIndexer task completed");
-            }));
-        }
+    /// Create a new `KvIndexerSharded` whose index is bounded by `limits`.
+    pub fn new_with_limits(
+        token: CancellationToken,
+        num_shards: usize,
+        kv_block_size: usize,
+        limits: IndexerLimits,
+    ) -> Self {
+        Self::start(token, num_shards, None, kv_block_size, limits)
+    }
 
+    fn start(
+        token: CancellationToken,
+        num_shards: usize,
+        expiration_duration: Option<Duration>,
+        kv_block_size: usize,
+        limits: IndexerLimits,
+    ) -> Self {
+        let num_shards = num_shards.max(1);
+        let shard_limits = IndexerLimits {
+            max_entries: limits.max_entries.map(|n| n.div_ceil(num_shards)),
+            max_memory_bytes: limits.max_memory_bytes.map(|n| n.div_ceil(num_shards)),
+            block_ttl: limits.block_ttl,
+        };
+        let shards: Vec<KvIndexer> = (0..num_shards)
+            .map(|_| {
+                KvIndexer::start(
+                    token.clone(),
+                    expiration_duration,
+                    kv_block_size,
+                    shard_limits,
+                )
+            })
+            .collect();
+        let snapshot_tx = if num_shards == 1 {
+            shards[0].snapshot_sender()
+        } else {
+            merge_snapshots(
+                shards.iter().map(KvIndexer::snapshot_sender).collect(),
+                kv_block_size,
+            )
+        };
         Self {
-            cancel: token,
+            shards,
+            snapshot_tx,
             kv_block_size,
-            worker_assignments,
-            worker_counts,
-            event_tx,
-            request_broadcast_tx,
-            remove_worker_tx,
-            tasks,
         }
     }
 
@@ -806,9 +1314,43 @@ impl KvIndexerSharded {
         self.kv_block_size
     }
 
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get a sender for snapshot requests, see [`request_snapshot`].
+    pub fn snapshot_sender(&self) -> SnapshotSender {
+        self.snapshot_tx.clone()
+    }
+
+    /// A snapshot of the index of all the shards
+    pub async fn snapshot(&self) -> Result<IndexSnapshot, KvRouterError> {
+        request_snapshot(&self.snapshot_tx).await
+    }
+
+    /// Add the blocks of `snapshot` to the index. Returns how many entries it had.
+    pub async fn restore(&self, snapshot: IndexSnapshot) -> anyhow::Result<usize> {
+        let mut workers: Vec<Vec<_>> = self.shards.iter().map(|_| Vec::new()).collect();
+        for (worker, blocks) in snapshot.workers {
+            workers[shard_of(worker, self.shards.len())].push((worker, blocks));
+        }
+        let entries = futures::future::try_join_all(self.shards.iter().zip(workers).map(
+            |(shard, workers)| shard.restore(IndexSnapshot::new(snapshot.kv_block_size, workers)),
+        ))
+        .await?;
+        Ok(entries.into_iter().sum())
+    }
+
     pub fn new(token: CancellationToken, num_shards: usize, kv_block_size: usize) -> Self {
         Self::new_with_frequency(token, num_shards, None, kv_block_size)
     }
+
+    /// Get a sender for `RouterEvent`s, which sends each to the shard of its worker.
+    pub fn event_sender(&self) -> ShardedEventSender {
+        ShardedEventSender {
+            shards: self.shards.iter().map(KvIndexer::event_sender).collect(),
+        }
+    }
 }
 
 #[async_trait]
@@ -817,47 +1359,28 @@ impl KvIndexerInterface for KvIndexerSharded {
         &self,
         sequence: Vec<LocalBlockHash>,
     ) -> Result<OverlapScores, KvRouterError> {
-        'match_loop: loop {
-            let (match_tx, mut match_rx) = mpsc::channel(self.event_tx.len());
-            self.request_broadcast_tx
-                .send(ShardedMatchRequest {
-                    sequence: sequence.clone(),
-                    early_exit: false,
-                    resp: match_tx,
-                })
-                .map_err(|_| KvRouterError::IndexerOffline)?;
-
-            let mut scores = OverlapScores::new();
-
-            for response_num in 0..self.event_tx.len() {
-                match match_rx.recv().await {
-                    Some(response) => {
-                        scores.scores.extend(response.scores);
-
-                        if response_num == 0 {
-                            scores.frequencies = response.frequencies;
-                        } else {
-                            let diff = (response.frequencies.len() as i64)
-                                - (scores.frequencies.len() as i64);
-
-                            if diff > 0 {
-                                scores.frequencies.extend(iter::repeat_n(0, diff as usize));
-                            }
+        if let [shard] = self.shards.as_slice() {
+            return shard.find_matches(sequence).await;
+        }
+        let responses = futures::future::try_join_all(
+            self.shards
+                .iter()
+                .map(|shard| shard.find_matches(sequence.clone())),
+        )
+        .await?;
 
-                            for i in 0..response.frequencies.len() {
-                                scores.frequencies[i] += response.frequencies[i];
-                            }
-                        }
-                    }
-                    None => {
-                        // This can only happen if the broadcast channel overflows.
-                        // In this case, we don't want to recursively call find_matches again. Otherwise, we could overflow the stack.
-                        continue 'match_loop;
-                    }
-                }
+        // A worker is in one shard only, but a block can be in several
+        let mut scores = OverlapScores::new();
+        for response in responses {
+            scores.scores.extend(response.scores);
+            if response.frequencies.len() > scores.frequencies.len() {
+                scores.frequencies.resize(response.frequencies.len(), 0);
+            }
+            for (total, frequency) in scores.frequencies.iter_mut().zip(response.frequencies) {
+                *total += frequency;
             }
-            return Ok(scores);
         }
+        Ok(scores)
     }
 
     async fn find_matches_for_request(
@@ -869,44 +1392,54 @@ impl KvIndexerInterface for KvIndexerSharded {
     }
 
     async fn apply_event(&mut self, event: RouterEvent) {
-        #[allow(clippy::map_entry)]
-        if !self.worker_assignments.contains_key(&event.worker_id) {
-            // Get the shard with the smallest amount of workers.
-            let selected_shard = self
-                .worker_counts
-                .iter()
-                .enumerate()
-                .min_by_key(|&(_, value)| value)
-                .unwrap()
-                .0;
-
-            self.worker_assignments
-                .insert(event.worker_id, selected_shard);
-            self.worker_counts[selected_shard] += 1;
-        }
-
-        self.event_tx[self.worker_assignments[&event.worker_id]]
-            .send(event)
-            .await
-            .unwrap();
+        let shard = shard_of(event.worker_id, self.shards.len());
+        self.shards[shard].apply_event(event).await;
     }
 
     async fn remove_worker(&mut self, worker: WorkerId) {
-        if let Some((_, shard)) = self.worker_assignments.remove_entry(&worker) {
-            self.worker_counts[shard] -= 1;
-            self.remove_worker_tx[shard].send(worker).await.unwrap();
-        }
+        let shard = shard_of(worker, self.shards.len());
+        self.shards[shard].remove_worker(worker).await;
     }
 
     /// Shutdown the KV Indexer.
     fn shutdown(&mut self) {
-        self.cancel.cancel();
-        while !self.tasks.is_empty() {
-            self.tasks.pop().unwrap().join().unwrap();
+        for shard in self.shards.iter_mut() {
+            shard.shutdown();
         }
     }
 }
 
+/// Answer snapshot requests with the snapshots of all `shards` merged, until the senders are
+/// dropped or a shard is offline.
+fn merge_snapshots(shards: Vec<SnapshotSender>, kv_block_size: usize) -> SnapshotSender {
+    let (snapshot_tx, mut snapshot_rx) = mpsc::channel::<oneshot::Sender<IndexSnapshot>>(4);
+    std::thread::spawn(move || {
+        while let Some(resp) = snapshot_rx.blocking_recv() {
+            // Ask every shard before waiting for any, so that they take their snapshots in parallel
+            let pending: Option<Vec<_>> = shards
+                .iter()
+                .map(|shard| {
+                    let (shard_tx, shard_rx) = oneshot::channel();
+                    shard.blocking_send(shard_tx).ok().map(|_| shard_rx)
+                })
+                .collect();
+            let Some(pending) = pending else {
+                tracing::debug!("KV indexer shard offline, not taking snapshots anymore");
+                return;
+            };
+            let mut workers = Vec::new();
+            for shard_rx in pending {
+                let Ok(snapshot) = shard_rx.blocking_recv() else {
+                    return;
+                };
+                workers.extend(snapshot.workers);
+            }
+            let _ = resp.send(IndexSnapshot::new(kv_block_size, workers));
+        }
+    });
+    snapshot_tx
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -1282,6 +1815,119 @@ mod tests {
         assert_eq!(result[&worker_1], 1);
     }
 
+    #[test]
+    fn test_limits() {
+        let limits = IndexerLimits {
+            max_entries: Some(5),
+            max_memory_bytes: Some(3 * ESTIMATED_ENTRY_BYTES),
+            block_ttl: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(limits.entry_limit(), Some(3));
+        let mut trie = RadixTree::new().with_limits(limits);
+        let worker = 0;
+
+        for (event_id, hash) in [1, 2, 3].into_iter().enumerate() {
+            trie.apply_event(create_store_event(
+                worker,
+                event_id as u64,
+                vec![hash],
+                None,
+            ));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // Using block 1 makes 2 the least recently used
+        trie.find_matches(vec![LocalBlockHash(1)], false);
+        std::thread::sleep(Duration::from_millis(1));
+        trie.apply_event(create_store_event(worker, 3, vec![4], None));
+
+        // Evicted down to 90% of the limit
+        assert_eq!(trie.num_entries(), 2);
+        let mut remaining: Vec<_> = trie.root.borrow().children.keys().map(|h| h.0).collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![1, 4]);
+
+        trie.evict_expired(Instant::now());
+        assert_eq!(trie.num_entries(), 2);
+        trie.evict_expired(Instant::now() + Duration::from_secs(61));
+        assert_eq!(trie.num_entries(), 0);
+        assert!(trie.root.borrow().children.is_empty());
+    }
+
+    #[test]
+    fn test_limits_chain() {
+        let mut trie = RadixTree::new().with_limits(IndexerLimits {
+            max_entries: Some(4),
+            ..Default::default()
+        });
+        let worker = 0;
+
+        // A chain, its first blocks stored before the ones that follow
+        trie.apply_event(create_store_event(worker, 0, vec![1, 2, 3], None));
+        std::thread::sleep(Duration::from_millis(1));
+        trie.apply_event(create_store_event(worker, 1, vec![5], None));
+        std::thread::sleep(Duration::from_millis(1));
+        trie.apply_event(create_store_event(
+            worker,
+            2,
+            vec![4],
+            Some(ExternalSequenceBlockHash(300)),
+        ));
+
+        // The chain was used last, through its end, so 5 goes, then the end of the chain. Its
+        // start stays, and nothing is left dangling.
+        assert_eq!(trie.num_entries(), 3);
+        let sequence: Vec<_> = [1, 2, 3, 4].into_iter().map(LocalBlockHash).collect();
+        assert_eq!(trie.find_matches(sequence, false).scores[&worker], 3);
+        assert!(trie
+            .find_matches(vec![LocalBlockHash(5)], false)
+            .scores
+            .is_empty());
+
+        // Over the limit again, the end of the chain goes before its start
+        std::thread::sleep(Duration::from_millis(1));
+        trie.apply_event(create_store_event(worker, 3, vec![6, 7], None));
+        assert_eq!(trie.num_entries(), 3);
+        let sequence: Vec<_> = [1, 2, 3, 4].into_iter().map(LocalBlockHash).collect();
+        assert_eq!(trie.find_matches(sequence, false).scores[&worker], 1);
+        let sequence: Vec<_> = [6, 7].into_iter().map(LocalBlockHash).collect();
+        assert_eq!(trie.find_matches(sequence, false).scores[&worker], 2);
+
+        // Every entry left is in the tree
+        assert_eq!(trie.snapshot()[0].1.len(), trie.num_entries());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut trie = RadixTree::new();
+        trie.apply_event(create_store_event(0, 0, vec![1, 2, 3], None));
+        trie.apply_event(create_store_event(
+            0,
+            1,
+            vec![4],
+            Some(ExternalSequenceBlockHash(100)),
+        ));
+        trie.apply_event(create_store_event(1, 0, vec![1, 2], None));
+        trie.apply_event(create_store_event(2, 0, vec![5], None));
+
+        let snapshot = IndexSnapshot::new(16, trie.snapshot());
+        assert_eq!(snapshot.num_entries(), trie.num_entries());
+        let decoded = IndexSnapshot::decode(&snapshot.encode().unwrap()).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = RadixTree::new();
+        for event in decoded.into_events() {
+            restored.apply_event(event);
+        }
+        assert_eq!(restored.num_entries(), trie.num_entries());
+        for sequence in [vec![1, 2, 3], vec![1, 4], vec![5], vec![2]] {
+            let sequence: Vec<_> = sequence.into_iter().map(LocalBlockHash).collect();
+            assert_eq!(
+                restored.find_matches(sequence.clone(), false).scores,
+                trie.find_matches(sequence, false).scores
+            );
+        }
+    }
+
     #[test]
     fn test_early_stopping() {
         setup();
@@ -1518,6 +2164,37 @@ mod tests {
         assert_eq!(overlap.frequencies, vec![3, 3, 3, 2]);
     }
 
+    #[tokio::test]
+    async fn test_sharded_snapshot() {
+        setup();
+        let token = CancellationToken::new();
+        let mut kv_indexer = KvIndexerSharded::new(token.clone(), 3, 32);
+        for worker_id in 0..8 {
+            let event = create_store_event(worker_id, 0, vec![1, 2, 3], None);
+            kv_indexer.apply_event(event).await;
+        }
+
+        // Events are applied before snapshots, and each worker is in a single shard
+        let snapshot = kv_indexer.snapshot().await.unwrap();
+        assert_eq!(snapshot.workers.len(), 8);
+        assert_eq!(snapshot.num_entries(), 24);
+
+        // Into another number of shards
+        let restored = KvIndexerSharded::new(token.clone(), 2, 32);
+        assert_eq!(restored.restore(snapshot).await.unwrap(), 24);
+        restored.snapshot().await.unwrap();
+        let scores = restored
+            .find_matches(vec![LocalBlockHash(1), LocalBlockHash(2)])
+            .await
+            .unwrap();
+        assert_eq!(scores.scores.len(), 8);
+        assert!(scores.scores.values().all(|&score| score == 2));
+
+        let other_block_size = KvIndexerSharded::new(token, 2, 64);
+        let snapshot = restored.snapshot().await.unwrap();
+        assert!(other_block_size.restore(snapshot).await.is_err());
+    }
+
     #[test]
     fn test_router_event_new() {
         setup();
@@ -1548,6 +2225,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_decode_router_events() {
+        let single = serde_json::to_vec(&create_remove_event(1, 7, vec![1, 2])).unwrap();
+        let events = decode_router_events(&single).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].worker_id, 1);
+        assert_eq!(events[0].event.event_id, 7);
+
+        let batch = RouterEventBatch::new(
+            2,
+            vec![
+                create_remove_event(2, 8, vec![1]).event,
+                create_remove_event(2, 9, vec![2]).event,
+            ],
+        );
+        let events = decode_router_events(&serde_json::to_vec(&batch).unwrap()).unwrap();
+        let ids: Vec<(WorkerId, u64)> = events
+            .iter()
+            .map(|event| (event.worker_id, event.event.event_id))
+            .collect();
+        assert_eq!(ids, vec![(2, 8), (2, 9)]);
+
+        assert!(decode_router_events(b"{\"worker_id\": 1}").is_err());
+    }
+
     #[test]
     fn test_radix_tree_default() {
         setup();
@@ -13,15 +13,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Once;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once};
+
+use futures::StreamExt;
 
 pub use crate::kv_router::protocols::ForwardPassMetrics;
-use crate::kv_router::KV_METRICS_ENDPOINT;
+use crate::kv_router::{KV_HIT_RATE_SUBJECT, KV_METRICS_ENDPOINT};
 
-use crate::kv_router::scheduler::Endpoint;
+use crate::kv_router::indexer::WorkerId;
+use crate::kv_router::scheduler::{Endpoint, KVHitRateEvent};
 use crate::kv_router::ProcessedEndpoints;
 use dynamo_runtime::component::Component;
+use dynamo_runtime::traits::events::EventSubscriber;
+use dynamo_runtime::traits::DistributedRuntimeProvider;
 use dynamo_runtime::{service::EndpointInfo, utils::Duration, Result};
+use serde::{Deserialize, Serialize};
 use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 
@@ -31,21 +38,33 @@ static METRICS_FOUND_MESSAGE: Once = Once::new();
 pub struct KvMetricsAggregator {
     pub service_name: String,
     pub endpoints_rx: watch::Receiver<ProcessedEndpoints>,
+    hit_rates: Arc<Mutex<KvHitRates>>,
 }
 
 impl KvMetricsAggregator {
     pub async fn new(component: Component, cancellation_token: CancellationToken) -> Self {
         let (watch_tx, watch_rx) = watch::channel(ProcessedEndpoints::default());
 
-        tokio::spawn(collect_endpoints_task(
-            component.clone(),
-            watch_tx,
-            cancellation_token.clone(),
-        ));
+        component.drt().runtime().tasks().spawn(
+            format!("kv metrics {component}"),
+            collect_endpoints_task(component.clone(), watch_tx, cancellation_token.clone()),
+        );
+
+        let hit_rates = Arc::new(Mutex::new(KvHitRates::default()));
+        component.drt().runtime().tasks().spawn(
+            format!("kv hit rates {component}"),
+            collect_hit_rates_task(
+                component.clone(),
+                watch_rx.clone(),
+                hit_rates.clone(),
+                cancellation_token,
+            ),
+        );
 
         Self {
             service_name: component.service_name(),
             endpoints_rx: watch_rx,
+            hit_rates,
         }
     }
 
@@ -56,6 +75,108 @@ impl KvMetricsAggregator {
     pub fn endpoints_watcher(&self) -> watch::Receiver<ProcessedEndpoints> {
         self.endpoints_rx.clone()
     }
+
+    /// The load of the component as a whole, for routing between clusters
+    pub fn cluster_metrics(&self) -> ClusterMetrics {
+        ClusterMetrics::from_endpoints(&self.endpoints_rx.borrow())
+    }
+
+    /// The KV cache hits of the requests routed to the workers of the component, by all the
+    /// routers of its namespace, since we started
+    pub fn hit_rates(&self) -> KvHitRates {
+        self.hit_rates.lock().unwrap().clone()
+    }
+}
+
+/// The KV cache hits of the requests routed to a worker, summed from [`KVHitRateEvent`]s
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvHitRate {
+    pub requests: u64,
+    /// Blocks of the prompts
+    pub isl_blocks: u64,
+    /// Blocks of the prompts the worker had cached
+    pub overlap_blocks: u64,
+}
+
+impl KvHitRate {
+    pub fn add(&mut self, event: &KVHitRateEvent) {
+        self.requests += 1;
+        self.isl_blocks += event.isl_blocks as u64;
+        // The overlap can include blocks past the prompt
+        self.overlap_blocks += event.overlap_blocks.min(event.isl_blocks) as u64;
+    }
+
+    /// The share of the prompt blocks that were cached, from 0 to 1. None before any prompt.
+    pub fn hit_rate(&self) -> Option<f64> {
+        (self.isl_blocks > 0).then(|| self.overlap_blocks as f64 / self.isl_blocks as f64)
+    }
+}
+
+/// The KV cache hits of the workers of a component, see [`KvMetricsAggregator::hit_rates`]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KvHitRates {
+    /// Of all the workers, including the ones that are gone
+    pub total: KvHitRate,
+    /// Of the workers we have load metrics of
+    pub workers: HashMap<WorkerId, KvHitRate>,
+}
+
+impl KvHitRates {
+    /// Add the hits of `other`, the hit rates of another component
+    pub fn merge(&mut self, other: &KvHitRates) {
+        self.total.requests += other.total.requests;
+        self.total.isl_blocks += other.total.isl_blocks;
+        self.total.overlap_blocks += other.total.overlap_blocks;
+        self.workers
+            .extend(other.workers.iter().map(|(id, rate)| (*id, *rate)));
+    }
+}
+
+/// The load of all the workers of a cluster, summed from their [`ForwardPassMetrics`]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterMetrics {
+    pub workers: usize,
+    pub request_active_slots: u64,
+    pub request_total_slots: u64,
+    pub kv_active_blocks: u64,
+    pub kv_total_blocks: u64,
+    pub num_requests_waiting: u64,
+    /// Average over the workers, from 0 to 1
+    pub gpu_cache_usage_perc: f32,
+}
+
+impl ClusterMetrics {
+    pub fn from_endpoints(endpoints: &ProcessedEndpoints) -> Self {
+        let mut metrics = ClusterMetrics::default();
+        for endpoint in endpoints.endpoints.values() {
+            let data = &endpoint.data;
+            metrics.workers += 1;
+            metrics.request_active_slots += data.request_active_slots;
+            metrics.request_total_slots += data.request_total_slots;
+            metrics.kv_active_blocks += data.kv_active_blocks;
+            metrics.kv_total_blocks += data.kv_total_blocks;
+            metrics.num_requests_waiting += data.num_requests_waiting;
+            metrics.gpu_cache_usage_perc += data.gpu_cache_usage_perc;
+        }
+        if metrics.workers > 0 {
+            metrics.gpu_cache_usage_perc /= metrics.workers as f32;
+        }
+        metrics
+    }
+
+    /// Requests waiting per worker
+    pub fn waiting_per_worker(&self) -> f64 {
+        if self.workers == 0 {
+            return 0.0;
+        }
+        self.num_requests_waiting as f64 / self.workers as f64
+    }
+
+    /// Whether no worker has a free request slot. Clusters whose workers don't report their
+    /// slots never are.
+    pub fn is_full(&self) -> bool {
+        self.request_total_slots > 0 && self.request_active_slots >= self.request_total_slots
+    }
 }
 
 /// [gluo TODO] 'collect_endpoints' is from component/metrics,
@@ -89,6 +210,54 @@ pub async fn collect_endpoints(
     Ok(endpoints)
 }
 
+/// Sum the [`KVHitRateEvent`]s the routers of the namespace of `component` publish about its
+/// workers into `hit_rates`
+async fn collect_hit_rates_task(
+    component: Component,
+    endpoints_rx: watch::Receiver<ProcessedEndpoints>,
+    hit_rates: Arc<Mutex<KvHitRates>>,
+    cancel: CancellationToken,
+) {
+    let mut events = match component.namespace().subscribe(KV_HIT_RATE_SUBJECT).await {
+        Ok(events) => events,
+        Err(err) => {
+            tracing::warn!(%err, "Not collecting KV hit rates");
+            return;
+        }
+    };
+    loop {
+        let message = tokio::select! {
+            _ = cancel.cancelled() => break,
+            message = events.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+        };
+        let event: KVHitRateEvent = match serde_json::from_slice(&message.payload) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::debug!(%err, "Skipping invalid KV hit rate event");
+                continue;
+            }
+        };
+        // The routers of other components in the namespace publish on the same subject
+        let workers: Vec<WorkerId> = endpoints_rx.borrow().endpoints.keys().copied().collect();
+        if !workers.contains(&event.worker_id) {
+            continue;
+        }
+        let mut hit_rates = hit_rates.lock().unwrap();
+        hit_rates.total.add(&event);
+        hit_rates
+            .workers
+            .retain(|worker_id, _| workers.contains(worker_id));
+        hit_rates
+            .workers
+            .entry(event.worker_id)
+            .or_default()
+            .add(&event);
+    }
+}
+
 pub async fn collect_endpoints_task(
     component: Component,
     watch_tx: watch::Sender<ProcessedEndpoints>,
@@ -144,3 +313,40 @@ pub async fn collect_endpoints_task(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(worker_id: WorkerId, isl_blocks: usize, overlap_blocks: usize) -> KVHitRateEvent {
+        KVHitRateEvent {
+            worker_id,
+            isl_blocks,
+            overlap_blocks,
+        }
+    }
+
+    #[test]
+    fn test_hit_rates() {
+        let mut hit_rate = KvHitRate::default();
+        assert_eq!(hit_rate.hit_rate(), None);
+        hit_rate.add(&event(1, 10, 5));
+        // Overlap past the prompt doesn't count
+        hit_rate.add(&event(1, 10, 12));
+        assert_eq!(hit_rate.requests, 2);
+        assert_eq!(hit_rate.hit_rate(), Some(0.75));
+
+        let mut hit_rates = KvHitRates {
+            total: hit_rate,
+            workers: HashMap::from([(1, hit_rate)]),
+        };
+        let mut other = KvHitRate::default();
+        other.add(&event(2, 20, 0));
+        hit_rates.merge(&KvHitRates {
+            total: other,
+            workers: HashMap::from([(2, other)]),
+        });
+        assert_eq!(hit_rates.total.hit_rate(), Some(15.0 / 40.0));
+        assert_eq!(hit_rates.workers.len(), 2);
+    }
+}
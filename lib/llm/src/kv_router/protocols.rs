@@ -13,17 +13,111 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use crate::tokens::Token;
 use serde::{Deserialize, Serialize};
 
+/// Version of the [`RouterRequest`] / [`RouterResponse`] wire format. The scheduler endpoint is
+/// consumed by ingresses outside this repo, so fields are only ever added, with a serde default,
+/// and this is bumped when a client must know about the change.
+pub const ROUTER_PROTOCOL_VERSION: u32 = 1;
+
+/// Ask the KV scheduler which worker should serve a request.
+///
+/// JSON: `{"tokens": [1, 2, 3], "priority": 50}`
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct RouterRequest {
+    /// The prompt, already tokenized with the model's tokenizer
     pub tokens: Vec<Token>,
+
+    /// From -100 to 100, higher is scheduled first when all workers are busy. Default 0.
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Who sent the request, e.g. a hash of its API key. Requests of the same priority are
+    /// scheduled fairly between principals.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+
+    /// The LoRA adapter the request is for. Default 0, the base model.
+    #[serde(default)]
+    pub lora_id: u64,
+
+    /// Also return why the worker was chosen, see [`RoutingExplanation`]
+    #[serde(default)]
+    pub explain: bool,
+
+    /// Only say which worker would be chosen, and why. The request is not scheduled, so the
+    /// router doesn't count it in the worker's load.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
+/// The scheduler's decision. The caller sends the request to `worker_id` itself.
+///
+/// JSON: `{"worker_id": 7587888160958628000, "overlap_blocks": 3, "block_size": 64, "protocol_version": 1}`
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct RouterResponse {
+    /// The instance id (etcd lease id) of the chosen worker
     pub worker_id: i64,
+
+    /// How many blocks of the prompt the chosen worker is expected to have cached already
+    #[serde(default)]
+    pub overlap_blocks: u32,
+
+    /// The KV block size the scheduler hashes the tokens with. Multiply by `overlap_blocks` for
+    /// the expected number of cached tokens.
+    #[serde(default)]
+    pub block_size: usize,
+
+    /// [`ROUTER_PROTOCOL_VERSION`] of the responding scheduler
+    #[serde(default)]
+    pub protocol_version: u32,
+
+    /// Why the worker was chosen, if the request asked to `explain` or was a `dry_run`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<RoutingExplanation>,
+
+    /// Namespace of the cluster of the worker, from a router over several clusters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+/// How the KV router scored one worker for a request
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct WorkerScore {
+    pub worker_id: i64,
+
+    /// Blocks of the prompt the worker has cached
+    pub overlap_blocks: u32,
+
+    /// The load the worker last reported
+    pub num_requests_waiting: u64,
+    pub kv_active_blocks: u64,
+    pub gpu_cache_usage_perc: f32,
+
+    /// The inputs of the selector's formula by name, before they are weighted
+    pub terms: BTreeMap<String, f64>,
+
+    /// The weighted sum of the terms. The worker with the highest is chosen.
+    pub logit: f64,
+}
+
+/// Why the KV router chose a worker for a request
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct RoutingExplanation {
+    /// The chosen worker
+    pub worker_id: i64,
+
+    /// Why, in words
+    pub reason: String,
+
+    /// Complete blocks in the prompt
+    pub isl_blocks: usize,
+
+    /// Every worker, highest score first. Empty if the selector doesn't explain its scores.
+    pub workers: Vec<WorkerScore>,
 }
 
 #[derive(Debug)]
@@ -54,11 +148,20 @@ pub struct ForwardPassMetrics {
     pub gpu_cache_usage_perc: f32,
     // percentage represented as a float from 0 to 1
     pub gpu_prefix_cache_hit_rate: f32,
+    // tokens generated per second recently, for workers that report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decode_tokens_per_sec: Option<f32>,
+    // LoRA adapters loaded, for workers that report them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lora_ids: Option<Vec<u64>>,
+    // tokens per KV block, for workers whose block size differs from the router's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_block_size: Option<u32>,
 }
 
 /// A [`LocalBlockHash`] is a hash computed from the tokens_ids, extra_token_ids and the optional
 /// lora_id of a block.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct LocalBlockHash(pub u64);
 
 /// A sequence aware hash of a block where the hash is computed from the tokens_ids, extra_token_ids
@@ -200,6 +303,30 @@ mod tests {
         assert_eq!(deserialized, hash);
     }
 
+    #[test]
+    fn test_router_protocol_is_stable() {
+        let request: RouterRequest = serde_json::from_str(r#"{"tokens": [1, 2, 3]}"#).unwrap();
+        assert_eq!(request.tokens, vec![1, 2, 3]);
+
+        let response = RouterResponse {
+            worker_id: 42,
+            overlap_blocks: 3,
+            block_size: 64,
+            protocol_version: ROUTER_PROTOCOL_VERSION,
+            explanation: None,
+            namespace: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"worker_id":42,"overlap_blocks":3,"block_size":64,"protocol_version":1}"#
+        );
+
+        // Responses from older schedulers only had the worker id
+        let old: RouterResponse = serde_json::from_str(r#"{"worker_id": 42}"#).unwrap();
+        assert_eq!(old.worker_id, 42);
+        assert_eq!(old.protocol_version, 0);
+    }
+
     #[test]
     fn test_kv_cache_events_serialization() {
         let event_data = KvCacheEventData::Stored(KvCacheStoreData {
@@ -14,11 +14,12 @@
 // limitations under the License.
 
 use crate::kv_router::{
-    indexer::{compute_block_hash_for_seq, RouterEvent},
+    indexer::{compute_block_hash_for_seq_with_lora, RouterEvent, RouterEventBatch},
     protocols::*,
     KV_EVENT_SUBJECT, KV_METRICS_ENDPOINT,
 };
 use async_trait::async_trait;
+use dynamo_runtime::fault_injection;
 use dynamo_runtime::traits::{events::EventPublisher, DistributedRuntimeProvider};
 use dynamo_runtime::{
     component::Component,
@@ -69,17 +70,18 @@ impl KvEventSource {
     ) -> Result<Self> {
         match source_config {
             KvEventSourceConfig::Zmq { endpoint, topic } => {
-                let zmq_handle = component
-                    .drt()
-                    .runtime()
-                    .secondary()
-                    .spawn(start_zmq_listener(
-                        endpoint,
+                let runtime = component.drt().runtime();
+                let zmq_handle = runtime.tasks().spawn_on(
+                    format!("kv events listener {endpoint}"),
+                    start_zmq_listener(
+                        endpoint.clone(),
                         topic,
                         tx,
                         cancellation_token.clone(),
                         kv_block_size,
-                    ));
+                    ),
+                    &runtime.secondary(),
+                );
 
                 Ok(KvEventSource::Zmq { zmq_handle })
             }
@@ -131,16 +133,18 @@ impl KvEventPublisher {
             )?);
         }
 
-        component
-            .drt()
-            .runtime()
-            .secondary()
-            .spawn(start_event_processor(
+        let runtime = component.drt().runtime().clone();
+        runtime.tasks().spawn_on(
+            format!("kv events publisher {component}"),
+            start_event_processor(
                 component,
                 worker_id,
+                KvEventBatchConfig::from_env(),
                 cancellation_token.clone(),
                 rx,
-            ));
+            ),
+            &runtime.secondary(),
+        );
 
         Ok(Self {
             kv_block_size,
@@ -176,24 +180,127 @@ impl Drop for KvEventPublisher {
     }
 }
 
+/// How a worker batches its KV events. A message per block event floods the events subject at
+/// high request rates, so the events are sent in a [`RouterEventBatch`] once `max_events` are
+/// pending or the oldest waited `max_delay`.
+///
+/// Configured with `DYN_KV_EVENTS_MAX_BATCH`, `DYN_KV_EVENTS_BATCH_DELAY_MS` and
+/// `DYN_KV_EVENTS_MAX_BATCH_BYTES`. A max batch of 1 sends one [`RouterEvent`] per message, as
+/// before batching, for routers that don't decode batches yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KvEventBatchConfig {
+    pub max_events: usize,
+    pub max_delay: Duration,
+    /// Most bytes of one message. Larger batches are split, and so are events that are larger
+    /// on their own, which compaction or a long prompt can make. NATS rejects messages over its
+    /// `max_payload`, 1 MiB by default.
+    pub max_bytes: usize,
+}
+
+impl Default for KvEventBatchConfig {
+    fn default() -> Self {
+        KvEventBatchConfig {
+            max_events: 64,
+            max_delay: Duration::from_millis(5),
+            max_bytes: 512 * 1024,
+        }
+    }
+}
+
+impl KvEventBatchConfig {
+    pub fn from_env() -> Self {
+        let default = KvEventBatchConfig::default();
+        let max_events = std::env::var("DYN_KV_EVENTS_MAX_BATCH")
+            .ok()
+            .and_then(|val| val.parse::<usize>().ok())
+            .unwrap_or(default.max_events)
+            .max(1);
+        let max_delay = std::env::var("DYN_KV_EVENTS_BATCH_DELAY_MS")
+            .ok()
+            .and_then(|val| val.parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default.max_delay);
+        let max_bytes = std::env::var("DYN_KV_EVENTS_MAX_BATCH_BYTES")
+            .ok()
+            .and_then(|val| val.parse::<usize>().ok())
+            .unwrap_or(default.max_bytes);
+        KvEventBatchConfig {
+            max_events,
+            max_delay,
+            max_bytes,
+        }
+    }
+
+    /// Whether events are sent one per message, in the version 1 format
+    fn unbatched(&self) -> bool {
+        self.max_events <= 1
+    }
+}
+
 async fn start_event_processor<P: EventPublisher + Send + Sync + 'static>(
     publisher: P,
     worker_id: i64,
+    batch_config: KvEventBatchConfig,
     cancellation_token: CancellationToken,
     mut rx: mpsc::UnboundedReceiver<KvCacheEvent>,
 ) {
+    let mut batch = Vec::new();
+    // When the oldest pending event must be sent
+    let mut deadline = None;
     loop {
         tokio::select! {
             _ = cancellation_token.cancelled() => {
                 tracing::info!("KV Event source received cancellation signal");
                 break;
             }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if deadline.is_some() =>
+            {
+                deadline = None;
+                publish_events(&publisher, worker_id, batch_config, std::mem::take(&mut batch))
+                    .await;
+            }
             event = rx.recv() => {
                 let Some(event) = event else {
                     tracing::debug!("Event processor channel closed.");
                     break;
                 };
 
+                let event = if fault_injection::corrupt_kv_event() {
+                    corrupt_event(event)
+                } else {
+                    event
+                };
+
+                batch.push(event);
+                if batch.len() >= batch_config.max_events {
+                    deadline = None;
+                    publish_events(&publisher, worker_id, batch_config, std::mem::take(&mut batch))
+                        .await;
+                } else if deadline.is_none() {
+                    deadline = Some(tokio::time::Instant::now() + batch_config.max_delay);
+                }
+            }
+        }
+    }
+    // The events of the last moments still reach the routers
+    publish_events(&publisher, worker_id, batch_config, batch).await;
+}
+
+/// Publish the pending `events` of `worker_id`, compacted into batches or one by one, each
+/// message within `batch_config.max_bytes`
+async fn publish_events<P: EventPublisher>(
+    publisher: &P,
+    worker_id: i64,
+    batch_config: KvEventBatchConfig,
+    events: Vec<KvCacheEvent>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    if batch_config.unbatched() {
+        for event in events {
+            for event in split_event(event, batch_config.max_bytes) {
                 // Encapsulate in a router event and publish.
                 let router_event = RouterEvent::new(worker_id, event);
                 if let Err(e) = publisher.publish(KV_EVENT_SUBJECT, &router_event).await {
@@ -201,7 +308,154 @@ async fn start_event_processor<P: EventPublisher + Send + Sync + 'static>(
                 }
             }
         }
+        return;
+    }
+    for events in split_batch(compact_events(events), batch_config.max_bytes) {
+        let batch = RouterEventBatch::new(worker_id, events);
+        if let Err(e) = publisher.publish(KV_EVENT_SUBJECT, &batch).await {
+            tracing::error!("Failed to publish batch of events: {}", e);
+        }
+    }
+}
+
+/// Room in a message for what wraps the events: worker id, version and field names
+const MESSAGE_OVERHEAD_BYTES: usize = 128;
+
+/// Bytes of `event` in a message. Components publish JSON, the largest encoding.
+fn event_bytes(event: &KvCacheEvent) -> usize {
+    serde_json::to_vec(event).map_or(0, |bytes| bytes.len())
+}
+
+/// `events` in order, in batches of at most `max_bytes`
+fn split_batch(events: Vec<KvCacheEvent>, max_bytes: usize) -> Vec<Vec<KvCacheEvent>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_bytes = MESSAGE_OVERHEAD_BYTES;
+    for event in events {
+        for event in split_event(event, max_bytes) {
+            // The comma between events
+            let bytes = event_bytes(&event) + 1;
+            if !batch.is_empty() && batch_bytes + bytes > max_bytes {
+                batches.push(std::mem::take(&mut batch));
+                batch_bytes = MESSAGE_OVERHEAD_BYTES;
+            }
+            batch_bytes += bytes;
+            batch.push(event);
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// `event` as events that each fit a message of `max_bytes`, applied in order they do the same.
+/// Stores are split into stores that continue each other, removals into removals. The parts keep
+/// the event id. An event of a single block stays whole.
+fn split_event(event: KvCacheEvent, max_bytes: usize) -> Vec<KvCacheEvent> {
+    if event_bytes(&event) + MESSAGE_OVERHEAD_BYTES <= max_bytes {
+        return vec![event];
+    }
+    let event_id = event.event_id;
+    let (first, second) = match event.data {
+        KvCacheEventData::Stored(mut data) if data.blocks.len() > 1 => {
+            let rest = data.blocks.split_off(data.blocks.len() / 2);
+            let parent_hash = data.blocks.last().map(|block| block.block_hash);
+            (
+                KvCacheEventData::Stored(data),
+                KvCacheEventData::Stored(KvCacheStoreData {
+                    parent_hash,
+                    blocks: rest,
+                }),
+            )
+        }
+        KvCacheEventData::Removed(mut data) if data.block_hashes.len() > 1 => {
+            let rest = data.block_hashes.split_off(data.block_hashes.len() / 2);
+            (
+                KvCacheEventData::Removed(data),
+                KvCacheEventData::Removed(KvCacheRemoveData { block_hashes: rest }),
+            )
+        }
+        data => {
+            tracing::warn!(
+                event_id,
+                max_bytes,
+                "KV event can't be split to fit a message"
+            );
+            return vec![KvCacheEvent { event_id, data }];
+        }
+    };
+    let mut events = split_event(
+        KvCacheEvent {
+            event_id,
+            data: first,
+        },
+        max_bytes,
+    );
+    events.extend(split_event(
+        KvCacheEvent {
+            event_id,
+            data: second,
+        },
+        max_bytes,
+    ));
+    events
+}
+
+/// Merge the events of a batch that the router would apply the same way: a store that continues
+/// the blocks of the store before it, removals in a row, and anything before a clear, which is
+/// dropped. A merged event has the id of the last one.
+fn compact_events(events: Vec<KvCacheEvent>) -> Vec<KvCacheEvent> {
+    let mut compacted: Vec<KvCacheEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        if matches!(event.data, KvCacheEventData::Cleared) {
+            compacted.clear();
+            compacted.push(event);
+            continue;
+        }
+        let Some(last) = compacted.last_mut() else {
+            compacted.push(event);
+            continue;
+        };
+        match (&mut last.data, event.data) {
+            (KvCacheEventData::Stored(last_data), KvCacheEventData::Stored(data))
+                if data.parent_hash.is_some()
+                    && data.parent_hash
+                        == last_data.blocks.last().map(|block| block.block_hash) =>
+            {
+                last_data.blocks.extend(data.blocks);
+                last.event_id = event.event_id;
+            }
+            (KvCacheEventData::Removed(last_data), KvCacheEventData::Removed(data)) => {
+                last_data.block_hashes.extend(data.block_hashes);
+                last.event_id = event.event_id;
+            }
+            (_, data) => compacted.push(KvCacheEvent {
+                event_id: event.event_id,
+                data,
+            }),
+        }
     }
+    compacted
+}
+
+/// Replace the block hashes with random ones, for fault injection. The router then holds blocks
+/// the worker doesn't have, or keeps blocks the worker removed.
+fn corrupt_event(mut event: KvCacheEvent) -> KvCacheEvent {
+    match &mut event.data {
+        KvCacheEventData::Stored(data) => {
+            for block in &mut data.blocks {
+                block.block_hash = ExternalSequenceBlockHash(rand::random());
+            }
+        }
+        KvCacheEventData::Removed(data) => {
+            for hash in &mut data.block_hashes {
+                *hash = ExternalSequenceBlockHash(rand::random());
+            }
+        }
+        KvCacheEventData::Cleared => {}
+    }
+    event
 }
 
 // Error handling configuration for ZMQ operations
@@ -385,9 +639,9 @@ pub fn create_stored_block_from_parts(
     kv_block_size: usize,
     block_hash: i64,
     token_ids: &[u32],
-    _lora_id: u64,
+    lora_id: u64,
 ) -> KvCacheStoredBlockData {
-    let tokens_hash = compute_block_hash_for_seq(token_ids, kv_block_size)[0];
+    let tokens_hash = compute_block_hash_for_seq_with_lora(token_ids, kv_block_size, lora_id)[0];
     KvCacheStoredBlockData {
         block_hash: ExternalSequenceBlockHash::from(block_hash),
         tokens_hash,
@@ -546,6 +800,14 @@ mod test_event_processing {
         assert_eq!(stored.block_hash.0, blk_hash as u64);
         let expected_hash = compute_block_hash_for_seq(&token_ids, 4)[0];
         assert_eq!(stored.tokens_hash, expected_hash);
+
+        // The same tokens under an adapter are another block
+        let lora = create_stored_block_from_parts(kv_block_size, blk_hash, &token_ids, 7);
+        assert_ne!(lora.tokens_hash, expected_hash);
+        assert_eq!(
+            lora.tokens_hash,
+            compute_block_hash_for_seq_with_lora(&token_ids, 4, 7)[0]
+        );
     }
 
     // ---------------------------------------------------------------------
@@ -717,7 +979,13 @@ mod tests_startup_helpers {
         tx.send(event).unwrap();
         drop(tx);
 
-        let handle = tokio::spawn(start_event_processor(component, 1, token, rx));
+        let handle = tokio::spawn(start_event_processor(
+            component,
+            1,
+            KvEventBatchConfig::default(),
+            token,
+            rx,
+        ));
 
         tokio::time::timeout(tokio::time::Duration::from_secs(1), handle)
             .await
@@ -730,6 +998,147 @@ mod tests_startup_helpers {
         assert_eq!(subject, &KV_EVENT_SUBJECT.to_string());
     }
 
+    fn stored(event_id: u64, parent: Option<u64>, hashes: &[u64]) -> KvCacheEvent {
+        KvCacheEvent {
+            event_id,
+            data: KvCacheEventData::Stored(KvCacheStoreData {
+                parent_hash: parent.map(ExternalSequenceBlockHash),
+                blocks: hashes
+                    .iter()
+                    .map(|hash| KvCacheStoredBlockData {
+                        block_hash: ExternalSequenceBlockHash(*hash),
+                        tokens_hash: LocalBlockHash(*hash),
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    fn removed(event_id: u64, hashes: &[u64]) -> KvCacheEvent {
+        KvCacheEvent {
+            event_id,
+            data: KvCacheEventData::Removed(KvCacheRemoveData {
+                block_hashes: hashes
+                    .iter()
+                    .copied()
+                    .map(ExternalSequenceBlockHash)
+                    .collect(),
+            }),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_event_batching() {
+        let (component, published) = MockComponent::new();
+        let token = CancellationToken::new();
+        let (tx, rx) = mpsc::unbounded_channel::<KvCacheEvent>();
+        let config = KvEventBatchConfig {
+            max_events: 3,
+            max_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let handle = tokio::spawn(start_event_processor(component, 1, config, token, rx));
+
+        // A full batch goes out at once, compacted
+        tx.send(stored(1, None, &[1, 2])).unwrap();
+        tx.send(stored(2, Some(2), &[3])).unwrap();
+        tx.send(removed(3, &[3])).unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(published.lock().unwrap().len(), 1);
+
+        // Fewer events wait for the delay
+        tx.send(removed(4, &[2])).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(published.lock().unwrap().len(), 1);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(published.lock().unwrap().len(), 2);
+
+        drop(tx);
+        handle.await.unwrap();
+
+        let published = published.lock().unwrap();
+        let batch: RouterEventBatch = rmp_serde::from_slice(&published[0].1).unwrap();
+        assert_eq!(batch.worker_id, 1);
+        assert_eq!(batch.events.len(), 2);
+        assert_eq!(batch.events[0].event_id, 2);
+        let KvCacheEventData::Stored(data) = &batch.events[0].data else {
+            panic!("Expected KvCacheEventData::Stored");
+        };
+        assert_eq!(data.blocks.len(), 3);
+        let batch: RouterEventBatch = rmp_serde::from_slice(&published[1].1).unwrap();
+        assert_eq!(batch.events.len(), 1);
+    }
+
+    #[test]
+    fn test_compact_events() {
+        let compacted = compact_events(vec![
+            removed(1, &[1]),
+            removed(2, &[2]),
+            stored(3, None, &[3]),
+            // Not a continuation of the last store
+            stored(4, Some(7), &[4]),
+        ]);
+        let ids: Vec<u64> = compacted.iter().map(|event| event.event_id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+
+        let compacted = compact_events(vec![
+            stored(1, None, &[1]),
+            KvCacheEvent {
+                event_id: 2,
+                data: KvCacheEventData::Cleared,
+            },
+            removed(3, &[1]),
+        ]);
+        let ids: Vec<u64> = compacted.iter().map(|event| event.event_id).collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_split_batch() {
+        let hashes: Vec<u64> = (1..=1000).collect();
+        let events = vec![
+            stored(1, None, &hashes),
+            removed(2, &hashes),
+            removed(3, &[1]),
+        ];
+        let max_bytes = 4096;
+        let batches = split_batch(events, max_bytes);
+        assert!(batches.len() > 2);
+        for batch in &batches {
+            let bytes = serde_json::to_vec(&RouterEventBatch::new(1, batch.clone()))
+                .unwrap()
+                .len();
+            assert!(bytes <= max_bytes, "{bytes} bytes");
+        }
+
+        // The parts of the store continue each other, and all blocks are there once
+        let events: Vec<KvCacheEvent> = batches.into_iter().flatten().collect();
+        let mut stored_hashes = Vec::new();
+        let mut removed_blocks = 0;
+        for event in &events {
+            match &event.data {
+                KvCacheEventData::Stored(data) => {
+                    assert_eq!(data.parent_hash, stored_hashes.last().copied());
+                    stored_hashes.extend(data.blocks.iter().map(|block| block.block_hash));
+                }
+                KvCacheEventData::Removed(data) => removed_blocks += data.block_hashes.len(),
+                KvCacheEventData::Cleared => unreachable!(),
+            }
+        }
+        let expected: Vec<_> = hashes
+            .iter()
+            .copied()
+            .map(ExternalSequenceBlockHash)
+            .collect();
+        assert_eq!(stored_hashes, expected);
+        assert_eq!(removed_blocks, hashes.len() + 1);
+        assert_eq!(events.last().unwrap().event_id, 3);
+
+        // Small batches stay whole
+        let batches = split_batch(vec![removed(1, &[1]), removed(2, &[2])], max_bytes);
+        assert_eq!(batches.len(), 1);
+    }
+
     //--------------------------------------------------------------------
     // Test start_zmq_listener without a real socket
     //   (feed it frames through a ZMQ PAIR tcp socket)
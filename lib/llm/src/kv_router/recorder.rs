@@ -13,18 +13,213 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::kv_router::indexer::RouterEvent;
+//! Record what the KV router sees and decides, and replay it offline.
+//!
+//! A [`KvRoutingRecorder`] writes to a JSON Lines file the KV cache events of the workers, their
+//! load each time it changes, and every scheduling decision: the block hashes of the prompt, not
+//! its tokens, and the worker it was sent to. [`replay`] feeds a recording through a
+//! [`RadixTree`] and any [`WorkerSelector`], so that an alternative selector can be compared with
+//! the decisions that were made, without a cluster.
+//!
+//! The KV events of a recording follow the recorded decisions: a replayed selector that sends a
+//! request elsewhere is still scored against the caches the recorded decisions built.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::kv_router::indexer::{RadixTree, RouterEvent, WorkerId};
+use crate::kv_router::protocols::LocalBlockHash;
+use crate::kv_router::scheduler::{
+    process_worker_selection, Endpoint, KVHitRateEvent, KvSchedulerError, SchedulingRequest,
+};
+use crate::kv_router::scoring::ProcessedEndpoints;
+use crate::kv_router::WorkerSelector;
 use crate::recorder::Recorder;
 
 // Type alias for backward compatibility
 pub type KvRecorder = Recorder<RouterEvent>;
 
+/// Records [`RoutingRecord`]s, see the module docs
+pub type KvRoutingRecorder = Recorder<RoutingRecord>;
+
+/// One line of a routing recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoutingRecord {
+    /// A KV cache event of a worker
+    KvEvent { event: RouterEvent },
+
+    /// The load of all workers, as they last reported it
+    Load { endpoints: Vec<Endpoint> },
+
+    /// A request was sent to `worker_id`
+    Decision {
+        block_hashes: Vec<LocalBlockHash>,
+        isl_tokens: usize,
+        lora_id: u64,
+        worker_id: WorkerId,
+        overlap_blocks: u32,
+    },
+}
+
+/// Record `record` if recording, without waiting. A full recorder drops it.
+pub(crate) fn record(record_tx: &Option<mpsc::Sender<RoutingRecord>>, record: RoutingRecord) {
+    let Some(record_tx) = record_tx else {
+        return;
+    };
+    if let Err(err) = record_tx.try_send(record) {
+        tracing::trace!(%err, "Routing record dropped");
+    }
+}
+
+/// How a [`WorkerSelector`] would have placed the requests of a recording
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplayReport {
+    /// Recorded decisions
+    pub decisions: usize,
+
+    /// Decisions the selector couldn't make, because there were no workers or all were busy
+    pub unplaced: usize,
+
+    /// Decisions where the selector picked the recorded worker
+    pub same_worker: usize,
+
+    /// Complete blocks in the prompts
+    pub isl_blocks: u64,
+
+    /// Prompt blocks the recorded workers had cached
+    pub recorded_overlap_blocks: u64,
+
+    /// Prompt blocks the workers the selector picked had cached
+    pub replayed_overlap_blocks: u64,
+
+    /// Requests per worker as recorded
+    pub recorded_requests: BTreeMap<WorkerId, usize>,
+
+    /// Requests per worker as the selector placed them
+    pub replayed_requests: BTreeMap<WorkerId, usize>,
+}
+
+impl ReplayReport {
+    /// The share of prompt blocks that were cached on the recorded workers
+    pub fn recorded_hit_rate(&self) -> f64 {
+        self.recorded_overlap_blocks as f64 / self.isl_blocks.max(1) as f64
+    }
+
+    /// The share of prompt blocks cached on the workers the selector picked
+    pub fn replayed_hit_rate(&self) -> f64 {
+        self.replayed_overlap_blocks as f64 / self.isl_blocks.max(1) as f64
+    }
+}
+
+/// Replay the recording at `path` through `selector`, as fast as it can be read
+pub async fn replay(
+    path: &Path,
+    block_size: usize,
+    selector: &(dyn WorkerSelector + Send + Sync),
+) -> anyhow::Result<ReplayReport> {
+    let (records_tx, mut records_rx) = mpsc::channel(2048);
+    let path = path.to_path_buf();
+    let reader = tokio::spawn(async move {
+        KvRoutingRecorder::send_events(&path, &records_tx, false, None, None).await
+    });
+
+    let mut replayer = Replayer::new(block_size);
+    while let Some(record) = records_rx.recv().await {
+        replayer.apply(record, selector);
+    }
+    reader.await??;
+    Ok(replayer.report)
+}
+
+/// The state of a replay
+struct Replayer {
+    block_size: usize,
+    tree: RadixTree,
+    endpoints: ProcessedEndpoints,
+    report: ReplayReport,
+    /// The hit rate events of the placed requests, not needed offline
+    hit_rate_tx: mpsc::UnboundedSender<KVHitRateEvent>,
+    hit_rate_rx: mpsc::UnboundedReceiver<KVHitRateEvent>,
+}
+
+impl Replayer {
+    fn new(block_size: usize) -> Self {
+        let (hit_rate_tx, hit_rate_rx) = mpsc::unbounded_channel();
+        Replayer {
+            block_size,
+            tree: RadixTree::new(),
+            endpoints: ProcessedEndpoints::default(),
+            report: ReplayReport::default(),
+            hit_rate_tx,
+            hit_rate_rx,
+        }
+    }
+
+    fn apply(&mut self, record: RoutingRecord, selector: &(dyn WorkerSelector + Send + Sync)) {
+        match record {
+            RoutingRecord::KvEvent { event } => self.tree.apply_event(event),
+            RoutingRecord::Load { endpoints } => {
+                self.endpoints = ProcessedEndpoints::new(endpoints);
+            }
+            RoutingRecord::Decision {
+                block_hashes,
+                isl_tokens,
+                lora_id,
+                worker_id,
+                overlap_blocks,
+            } => {
+                let report = &mut self.report;
+                report.decisions += 1;
+                report.isl_blocks += block_hashes.len() as u64;
+                report.recorded_overlap_blocks += overlap_blocks as u64;
+                *report.recorded_requests.entry(worker_id).or_default() += 1;
+
+                let overlap = self.tree.find_matches(block_hashes, false);
+                let request = SchedulingRequest::unscheduled(isl_tokens, overlap, lora_id);
+                let selection =
+                    match selector.select_worker(&self.endpoints, &request, self.block_size) {
+                        Ok(selection) => selection,
+                        Err(KvSchedulerError::NoEndpoints | KvSchedulerError::AllWorkersBusy) => {
+                            report.unplaced += 1;
+                            return;
+                        }
+                        Err(err) => {
+                            tracing::warn!(%err, "Replayed selector failed");
+                            report.unplaced += 1;
+                            return;
+                        }
+                    };
+                let replayed = selection.worker_id;
+                if replayed == worker_id {
+                    report.same_worker += 1;
+                }
+                report.replayed_overlap_blocks += selection.overlap_blocks as u64;
+                *report.replayed_requests.entry(replayed).or_default() += 1;
+
+                // The load of the workers grows with the requests placed until they report again
+                process_worker_selection(
+                    &mut self.endpoints,
+                    selection,
+                    lora_id,
+                    &self.hit_rate_tx,
+                );
+                while self.hit_rate_rx.try_recv().is_ok() {}
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::kv_router::indexer::KvIndexer;
     use crate::kv_router::indexer::WorkerId;
     use crate::kv_router::protocols::*;
+    use crate::kv_router::scheduler::DefaultWorkerSelector;
     use std::time::Duration;
     use tempfile::tempdir;
     use tokio::fs;
@@ -137,4 +332,53 @@ mod tests {
             .unwrap();
         assert_eq!(count, 2, "Expected to send 2 events from file to indexer");
     }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("routing.jsonl");
+        let token = CancellationToken::new();
+        let recorder = KvRoutingRecorder::new(token.clone(), &file_path, None, None, None)
+            .await
+            .unwrap();
+        let record_tx = Some(recorder.event_sender());
+
+        let endpoints = (1..=2)
+            .map(|worker_id: i64| Endpoint {
+                name: format!("worker-{worker_id}"),
+                subject: format!("ns.backend.generate-{worker_id:x}"),
+                data: ForwardPassMetrics::default(),
+            })
+            .collect();
+        record(&record_tx, RoutingRecord::Load { endpoints });
+        record(
+            &record_tx,
+            RoutingRecord::KvEvent {
+                event: create_store_event(1, 1, vec![1, 2], None),
+            },
+        );
+        // Recorded on the worker without the prefix
+        record(
+            &record_tx,
+            RoutingRecord::Decision {
+                block_hashes: vec![LocalBlockHash(1), LocalBlockHash(2), LocalBlockHash(3)],
+                isl_tokens: 48,
+                lora_id: 0,
+                worker_id: 2,
+                overlap_blocks: 0,
+            },
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        recorder.shutdown();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let selector = DefaultWorkerSelector::default();
+        let report = replay(&file_path, 16, &selector).await.unwrap();
+        assert_eq!(report.decisions, 1);
+        assert_eq!(report.same_worker, 0);
+        assert_eq!(report.replayed_requests, BTreeMap::from([(1, 1)]));
+        assert_eq!(report.isl_blocks, 3);
+        assert_eq!(report.recorded_hit_rate(), 0.0);
+        assert_eq!(report.replayed_overlap_blocks, 2);
+    }
 }
@@ -15,16 +15,24 @@
 
 use dynamo_runtime::component::Namespace;
 use dynamo_runtime::traits::events::EventPublisher;
+use dynamo_runtime::traits::DistributedRuntimeProvider;
+use dynamo_runtime::transports::etcd::WatchEvent;
+use dynamo_runtime::DistributedRuntime;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::borrow::{BorrowMut, Cow};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Instant;
 
-use super::protocols::WorkerSelectionResult;
+use super::circuit_breaker::WorkerHealth;
+use super::metrics_aggregator::ClusterMetrics;
+use super::protocols::{RoutingExplanation, WorkerScore, WorkerSelectionResult};
 use super::WorkerSelector;
 use crate::kv_router::indexer::OverlapScores;
 pub use crate::kv_router::protocols::ForwardPassMetrics;
-use crate::kv_router::scoring::ProcessedEndpoints;
+use crate::kv_router::scoring::{BalancedWeights, ProcessedEndpoints};
 use crate::kv_router::KvRouterConfig;
 use crate::kv_router::KV_HIT_RATE_SUBJECT;
 
@@ -35,6 +43,16 @@ pub struct KVHitRateEvent {
     pub overlap_blocks: usize,
 }
 
+impl KVHitRateEvent {
+    /// The share of the prompt blocks the worker had cached, from 0 to 1
+    pub fn hit_rate(&self) -> f64 {
+        if self.isl_blocks == 0 {
+            return 0.0;
+        }
+        self.overlap_blocks.min(self.isl_blocks) as f64 / self.isl_blocks as f64
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum KvSchedulerError {
     #[error("no endpoints aviailable to route work")]
@@ -69,15 +87,69 @@ impl Endpoint {
         )
         .expect("invalid worker id")
     }
+
+    /// Whether the worker would have to load the LoRA adapter `lora_id` first. Unknown, so
+    /// false, for workers that don't report their adapters.
+    pub fn lacks_lora(&self, lora_id: u64) -> bool {
+        lora_id != 0
+            && self
+                .data
+                .lora_ids
+                .as_ref()
+                .is_some_and(|lora_ids| !lora_ids.contains(&lora_id))
+    }
+}
+
+/// How a request competes for the workers, and which it may go to
+#[derive(Debug, Clone, Default)]
+pub struct Placement {
+    /// From -100 to 100, higher is scheduled first when workers are busy
+    pub priority: i32,
+    /// Who sent the request, usually the API key
+    pub principal: Option<String>,
+    /// The LoRA adapter of the request, 0 for the base model
+    pub lora_id: u64,
+    /// The worker the request must go to, because the client pinned it there or its session is
+    /// there. Nothing is chosen, but the load of the request is accounted for.
+    pub worker_id: Option<i64>,
+    /// The workers the request may go to, those the zone policy of the router allows. Any
+    /// worker if None.
+    pub workers: Option<HashSet<i64>>,
 }
 
 pub struct SchedulingRequest {
     pub isl_tokens: usize,
     pub overlap: OverlapScores,
+    /// From -100 to 100, higher is scheduled first when workers are busy
+    pub priority: i32,
+    /// Who sent the request, usually the API key. Busy workers are shared fairly between
+    /// principals of the same priority.
+    pub principal: Option<String>,
+    /// The LoRA adapter of the request, 0 for the base model
+    pub lora_id: u64,
+    /// See [`Placement::worker_id`]
+    pub worker_id: Option<i64>,
+    /// See [`Placement::workers`]
+    pub workers: Option<HashSet<i64>>,
     resp_tx: tokio::sync::oneshot::Sender<i64>,
 }
 
 impl SchedulingRequest {
+    /// A request that is only scored, nobody waits for its worker
+    pub(crate) fn unscheduled(isl_tokens: usize, overlap: OverlapScores, lora_id: u64) -> Self {
+        let (resp_tx, _resp_rx) = tokio::sync::oneshot::channel();
+        SchedulingRequest {
+            isl_tokens: isl_tokens.max(1),
+            overlap,
+            priority: 0,
+            principal: None,
+            lora_id,
+            worker_id: None,
+            workers: None,
+            resp_tx,
+        }
+    }
+
     pub fn respond(self, worker_id: i64) {
         if self.resp_tx.send(worker_id).is_err() {
             tracing::trace!("failed to send response to requestor");
@@ -85,8 +157,220 @@ impl SchedulingRequest {
     }
 }
 
+/// Prompt tokens a principal may place per turn when the scheduler shares busy workers between
+/// principals
+const FAIR_SHARE_QUANTUM: usize = 4096;
+
+/// Principal of requests without one, in metrics
+const ANONYMOUS: &str = "anonymous";
+
+static QUEUE_WAIT: LazyLock<HistogramVec> = LazyLock::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "dynamo_kv_scheduler_wait_seconds",
+            "Time requests waited in the KV router for a worker, by principal (API key)",
+        )
+        .buckets(vec![
+            0.001, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+        ]),
+        &["principal"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static HIT_RATE: LazyLock<HistogramVec> = LazyLock::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "dynamo_kv_router_hit_rate",
+            "Share of the prompt blocks of each routed request its worker had cached, by namespace",
+        )
+        .buckets(vec![0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0]),
+        &["namespace"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static BLOCKS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_kv_router_blocks_total",
+            "Prompt blocks of the routed requests, by namespace and kind (isl, or overlap when cached)",
+        ),
+        &["namespace", "kind"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the scheduler metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(QUEUE_WAIT.clone()))?;
+    registry.register(Box::new(HIT_RATE.clone()))?;
+    registry.register(Box::new(BLOCKS.clone()))
+}
+
+fn observe_hit_rate(namespace: &str, event: &KVHitRateEvent) {
+    HIT_RATE
+        .with_label_values(&[namespace])
+        .observe(event.hit_rate());
+    BLOCKS
+        .with_label_values(&[namespace, "isl"])
+        .inc_by(event.isl_blocks as u64);
+    BLOCKS
+        .with_label_values(&[namespace, "overlap"])
+        .inc_by(event.overlap_blocks.min(event.isl_blocks) as u64);
+}
+
+/// A request waiting in the scheduler
+struct Queued {
+    enqueued: Instant,
+    request: SchedulingRequest,
+}
+
+impl Queued {
+    /// What placing the request uses up of its principal's share
+    fn cost(&self) -> usize {
+        self.request.isl_tokens.max(1)
+    }
+
+    fn principal(&self) -> &str {
+        self.request.principal.as_deref().unwrap_or_default()
+    }
+
+    fn respond(self, worker_id: i64) {
+        let principal = match self.principal() {
+            "" => ANONYMOUS,
+            principal => principal,
+        };
+        QUEUE_WAIT
+            .with_label_values(&[principal])
+            .observe(self.enqueued.elapsed().as_secs_f64());
+        self.request.respond(worker_id);
+    }
+}
+
+/// The requests of one priority. Principals take turns with deficit round robin: in its turn a
+/// principal places requests worth up to [`FAIR_SHARE_QUANTUM`] prompt tokens, plus what it
+/// didn't use in earlier turns, so one principal sending many requests can't keep the others
+/// waiting. Each principal's requests are placed in arrival order.
+#[derive(Default)]
+struct FairQueue {
+    /// Waiting requests by principal, oldest first
+    queues: HashMap<String, VecDeque<Queued>>,
+
+    /// The principals in `queues`, the one whose turn it is first
+    turns: VecDeque<String>,
+
+    /// Prompt tokens each principal may still place in its turn
+    deficits: HashMap<String, usize>,
+
+    len: usize,
+}
+
+impl FairQueue {
+    fn push(&mut self, queued: Queued) {
+        let principal = queued.principal().to_string();
+        if !self.queues.contains_key(&principal) {
+            self.turns.push_back(principal.clone());
+            self.deficits.insert(principal.clone(), FAIR_SHARE_QUANTUM);
+        }
+        self.queues.entry(principal).or_default().push_back(queued);
+        self.len += 1;
+    }
+
+    /// Put back a request that could not be placed, ahead of the others and with its share
+    fn requeue(&mut self, queued: Queued) {
+        let principal = queued.principal().to_string();
+        if !self.queues.contains_key(&principal) {
+            self.turns.push_front(principal.clone());
+        }
+        *self.deficits.entry(principal.clone()).or_default() += queued.cost();
+        self.queues.entry(principal).or_default().push_front(queued);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        if self.len == 0 {
+            return None;
+        }
+        loop {
+            let principal = self.turns.front()?.clone();
+            let queue = self.queues.entry(principal.clone()).or_default();
+            let Some(cost) = queue.front().map(Queued::cost) else {
+                // Out of requests, the unused share expires
+                self.turns.pop_front();
+                self.queues.remove(&principal);
+                self.deficits.remove(&principal);
+                continue;
+            };
+            let deficit = self.deficits.entry(principal).or_default();
+            if *deficit >= cost {
+                *deficit -= cost;
+                self.len -= 1;
+                return queue.pop_front();
+            }
+            // End of the turn, the next one comes with a new quantum
+            *deficit += FAIR_SHARE_QUANTUM;
+            self.turns.rotate_left(1);
+        }
+    }
+}
+
+/// The requests the scheduler has not placed yet. Higher priorities first, shared fairly between
+/// principals within a priority.
+#[derive(Default)]
+struct SchedulingQueue {
+    levels: BTreeMap<i32, FairQueue>,
+    len: usize,
+}
+
+impl SchedulingQueue {
+    fn push(&mut self, request: SchedulingRequest) {
+        self.levels
+            .entry(request.priority)
+            .or_default()
+            .push(Queued {
+                enqueued: Instant::now(),
+                request,
+            });
+        self.len += 1;
+    }
+
+    /// Put back a request that could not be placed, keeping its place in line
+    fn requeue(&mut self, queued: Queued) {
+        self.levels
+            .entry(queued.request.priority)
+            .or_default()
+            .requeue(queued);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        let mut level = self.levels.last_entry()?;
+        let queued = level.get_mut().pop();
+        if level.get().len == 0 {
+            level.remove();
+        }
+        if queued.is_some() {
+            self.len -= 1;
+        }
+        queued
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
 pub struct KvScheduler {
     request_tx: tokio::sync::mpsc::Sender<SchedulingRequest>,
+    selector: Arc<dyn WorkerSelector + Send + Sync>,
+    endpoints_rx: tokio::sync::watch::Receiver<ProcessedEndpoints>,
+    block_size: usize,
+    health: Arc<WorkerHealth>,
 }
 
 impl KvScheduler {
@@ -95,15 +379,27 @@ impl KvScheduler {
         block_size: usize,
         endpoints_rx: tokio::sync::watch::Receiver<ProcessedEndpoints>,
         selector: Option<Box<dyn WorkerSelector + Send + Sync>>,
+        health: Arc<WorkerHealth>,
     ) -> Result<Self, KvSchedulerError> {
-        let selector = selector.unwrap_or(Box::new(DefaultWorkerSelector::default()));
+        let selector: Arc<dyn WorkerSelector + Send + Sync> = match selector {
+            Some(selector) => Arc::from(selector),
+            None => Arc::new(DefaultWorkerSelector::default()),
+        };
+        let explain_selector = selector.clone();
+        let explain_endpoints_rx = endpoints_rx.clone();
+        let explain_health = health.clone();
         let mut endpoints_rx = endpoints_rx;
         let mut endpoints: ProcessedEndpoints = endpoints_rx.borrow_and_update().clone();
 
+        let tasks = ns.drt().runtime().tasks().clone();
+        let cancel_token = ns.drt().primary_token();
+        let ns_name = ns.name().to_string();
+
         let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel::<KVHitRateEvent>();
-        tokio::spawn(async move {
+        tasks.spawn(format!("kv hit rate publisher {ns_name}"), async move {
             let mut event_rx = event_rx;
             while let Some(event) = event_rx.recv().await {
+                observe_hit_rate(ns.name(), &event);
                 if let Err(e) = ns.publish(KV_HIT_RATE_SUBJECT, &event).await {
                     tracing::warn!("Failed to publish KV hit rate event: {:?}", e);
                 }
@@ -113,78 +409,165 @@ impl KvScheduler {
         // Channel to accept new scheduling requests
         let (request_tx, request_rx) = tokio::sync::mpsc::channel::<SchedulingRequest>(1024);
         // Background task to handle scheduling requests
-        tokio::spawn(async move {
-            let mut request: SchedulingRequest;
+        tasks.spawn(format!("kv scheduler {ns_name}"), async move {
             let mut request_rx = request_rx;
+            let mut queue = SchedulingQueue::default();
             tracing::trace!("scheduler background task started");
 
             'outer: loop {
-                request = tokio::select! {
-                    biased;
-
-                    new_request = request_rx.recv() => {
-                        match new_request {
-                            Some(new_request) => {
-                                tracing::trace!("received request to be scheduled");
-                                new_request
-                            },
-                            None => {
-                                tracing::trace!("scheduler shutdown");
-                                break 'outer;
-                            }
-                        }
-                    }
+                if queue.is_empty() {
+                    tokio::select! {
+                        biased;
 
-                    _ = endpoints_rx.changed() => {
-                        endpoints = endpoints_rx.borrow_and_update().clone();
-                        continue 'outer;
-                    }
-                };
-                loop {
-                    match selector.select_worker(&endpoints, &request, block_size) {
-                        Ok(selection) => {
-                            let worker_id = process_worker_selection(
-                                endpoints.borrow_mut(),
-                                selection,
-                                &event_tx,
-                            );
-                            request.respond(worker_id);
-                            continue 'outer;
+                        _ = cancel_token.cancelled() => {
+                            break 'outer;
                         }
-                        Err(KvSchedulerError::AllWorkersBusy) => {
-                            tracing::trace!("all workers busy; waiting for more capacity");
-                            match endpoints_rx.changed().await {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    tracing::error!("error waiting for endpoints change: {:?}", e);
+
+                        new_request = request_rx.recv() => {
+                            match new_request {
+                                Some(new_request) => {
+                                    tracing::trace!("received request to be scheduled");
+                                    queue.push(new_request);
+                                },
+                                None => {
+                                    tracing::trace!("scheduler shutdown");
                                     break 'outer;
                                 }
-                            };
+                            }
+                        }
+
+                        _ = endpoints_rx.changed() => {
                             endpoints = endpoints_rx.borrow_and_update().clone();
+                            continue 'outer;
                         }
-                        Err(e) => {
-                            tracing::error!("error scheduling request: {:?}", e);
-                            break 'outer;
+                    };
+                }
+
+                // Requests that arrived in the meantime compete on priority
+                while let Ok(new_request) = request_rx.try_recv() {
+                    queue.push(new_request);
+                }
+                let queued = queue.pop().expect("queue is not empty");
+                if let Some(worker_id) = queued.request.worker_id {
+                    // Nothing to choose, but the next requests see its load
+                    if endpoints.endpoints.contains_key(&worker_id) {
+                        let request = &queued.request;
+                        let selection = WorkerSelectionResult {
+                            worker_id,
+                            required_blocks: (request.isl_tokens / block_size).max(1) as u64,
+                            overlap_blocks: request
+                                .overlap
+                                .scores
+                                .get(&worker_id)
+                                .copied()
+                                .unwrap_or(0) as usize,
+                        };
+                        process_worker_selection(
+                            endpoints.borrow_mut(),
+                            selection,
+                            request.lora_id,
+                            &event_tx,
+                        );
+                    }
+                    health.on_scheduled(worker_id);
+                    queued.respond(worker_id);
+                    continue 'outer;
+                }
+                let available = match (health.available(&endpoints), &queued.request.workers) {
+                    (available, Some(workers))
+                        if available.endpoints.keys().any(|id| !workers.contains(id)) =>
+                    {
+                        Cow::Owned(ProcessedEndpoints::new(
+                            available
+                                .endpoints
+                                .values()
+                                .filter(|endpoint| workers.contains(&endpoint.worker_id()))
+                                .cloned()
+                                .collect(),
+                        ))
+                    }
+                    (available, _) => available,
+                };
+                let selected = match selector.select_worker(&available, &queued.request, block_size)
+                {
+                    // The workers left out by their circuit breaker or the blacklist come back
+                    Err(KvSchedulerError::NoEndpoints) if !endpoints.endpoints.is_empty() => {
+                        Err(KvSchedulerError::AllWorkersBusy)
+                    }
+                    selected => selected,
+                };
+                match selected {
+                    Ok(selection) => {
+                        let worker_id = process_worker_selection(
+                            endpoints.borrow_mut(),
+                            selection,
+                            queued.request.lora_id,
+                            &event_tx,
+                        );
+                        health.on_scheduled(worker_id);
+                        queued.respond(worker_id);
+                    }
+                    Err(KvSchedulerError::AllWorkersBusy) => {
+                        tracing::trace!(
+                            waiting = queue.len() + 1,
+                            "all workers busy; waiting for more capacity"
+                        );
+                        queue.requeue(queued);
+                        tokio::select! {
+                            biased;
+
+                            _ = cancel_token.cancelled() => {
+                                break 'outer;
+                            }
+
+                            changed = endpoints_rx.changed() => {
+                                if let Err(e) = changed {
+                                    anyhow::bail!("error waiting for endpoints change: {e}");
+                                }
+                                endpoints = endpoints_rx.borrow_and_update().clone();
+                            }
+
+                            _ = health.changed() => {}
                         }
                     }
+                    Err(e) => {
+                        anyhow::bail!("error scheduling request: {e}");
+                    }
                 }
             }
 
             tracing::trace!("background endpoint subscriber shutting down");
+            Ok(())
         });
 
-        Ok(KvScheduler { request_tx })
+        Ok(KvScheduler {
+            request_tx,
+            selector: explain_selector,
+            endpoints_rx: explain_endpoints_rx,
+            block_size,
+            health: explain_health,
+        })
     }
 
+    /// Pick a worker for a request. When all workers are busy, requests wait for capacity and
+    /// are served highest `priority` first. Principals with requests of the same priority take
+    /// turns, each principal's requests are served in arrival order. Workers that have the LoRA
+    /// adapter of the request loaded are preferred. See [`Placement`].
     pub async fn schedule(
         &self,
         overlap: OverlapScores,
         isl_tokens: usize,
+        placement: Placement,
     ) -> Result<i64, KvSchedulerError> {
         let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
         let request = SchedulingRequest {
             isl_tokens,
             overlap,
+            priority: placement.priority,
+            principal: placement.principal,
+            lora_id: placement.lora_id,
+            worker_id: placement.worker_id,
+            workers: placement.workers,
             resp_tx,
         };
         self.request_tx
@@ -196,12 +579,115 @@ impl KvScheduler {
             .map_err(|_| KvSchedulerError::SubscriberShutdown)?;
         Ok(res)
     }
+
+    /// Whether `worker_id` reports its load and is healthy
+    pub fn is_available(&self, worker_id: i64) -> bool {
+        self.endpoints_rx
+            .borrow()
+            .endpoints
+            .contains_key(&worker_id)
+            && self.health.is_healthy(worker_id)
+    }
+
+    /// The summed load of the healthy workers
+    pub fn cluster_metrics(&self) -> ClusterMetrics {
+        let endpoints = self.endpoints_rx.borrow();
+        ClusterMetrics::from_endpoints(&self.health.available(&endpoints))
+    }
+
+    /// How the selector scores the workers for a request, on the load they last reported.
+    /// Nothing is scheduled. `chosen` is the worker the request was sent to, if it was.
+    pub fn explain(
+        &self,
+        overlap: OverlapScores,
+        isl_tokens: usize,
+        lora_id: u64,
+        chosen: Option<i64>,
+    ) -> Result<RoutingExplanation, KvSchedulerError> {
+        let endpoints = self.endpoints_rx.borrow().clone();
+        let available = self.health.available(&endpoints);
+        if available.endpoints.is_empty() {
+            return Err(KvSchedulerError::NoEndpoints);
+        }
+        let request = SchedulingRequest::unscheduled(isl_tokens, overlap, lora_id);
+        let mut workers = self
+            .selector
+            .score_workers(&available, &request, self.block_size);
+        workers.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+        Ok(explanation(workers, isl_tokens / self.block_size, chosen))
+    }
+}
+
+/// Why `chosen`, or the best of `workers`, sorted best first, is picked
+fn explanation(
+    workers: Vec<WorkerScore>,
+    isl_blocks: usize,
+    chosen: Option<i64>,
+) -> RoutingExplanation {
+    let Some(best) = workers.first() else {
+        return RoutingExplanation {
+            worker_id: chosen.unwrap_or_default(),
+            reason: "The worker selector doesn't explain its scores".to_string(),
+            isl_blocks,
+            workers,
+        };
+    };
+    let worker_id = chosen.unwrap_or(best.worker_id);
+    let reason = match workers.iter().find(|worker| worker.worker_id == worker_id) {
+        None => format!("Worker {worker_id} was chosen but no longer reports its load"),
+        Some(worker) if worker.logit < best.logit => format!(
+            "Chosen on the load predicted from the requests sent since the workers last reported. On the reported load it scores {:.3} and worker {} scores best, {:.3}",
+            worker.logit, best.worker_id, best.logit
+        ),
+        Some(worker) => {
+            let mut reason = format!(
+                "Highest score {:.3}, with {} of {isl_blocks} prompt blocks cached",
+                worker.logit, worker.overlap_blocks
+            );
+            let tied = workers
+                .iter()
+                .filter(|other| other.logit == worker.logit)
+                .count();
+            if tied > 1 {
+                reason.push_str(&format!(
+                    ". {tied} workers have that score, one of them is picked at random"
+                ));
+            }
+            reason
+        }
+    };
+    RoutingExplanation {
+        worker_id,
+        reason,
+        isl_blocks,
+        workers,
+    }
+}
+
+/// The [`WorkerScore`] of `endpoint` from the selector's `terms` and `logit`
+fn worker_score(
+    worker_id: i64,
+    endpoint: &Endpoint,
+    request: &SchedulingRequest,
+    terms: BTreeMap<String, f64>,
+    logit: f64,
+) -> WorkerScore {
+    WorkerScore {
+        worker_id,
+        overlap_blocks: request.overlap.scores.get(&worker_id).copied().unwrap_or(0),
+        num_requests_waiting: endpoint.data.num_requests_waiting,
+        kv_active_blocks: endpoint.data.kv_active_blocks,
+        gpu_cache_usage_perc: endpoint.data.gpu_cache_usage_perc,
+        terms,
+        logit,
+    }
 }
 
 // This becomes the driver function that handles the selection result
 pub fn process_worker_selection(
     workers: &mut ProcessedEndpoints,
     selection: WorkerSelectionResult,
+    lora_id: u64,
     event_tx: &tokio::sync::mpsc::UnboundedSender<KVHitRateEvent>,
 ) -> i64 {
     let worker = workers
@@ -217,6 +703,12 @@ pub fn process_worker_selection(
     worker.data.kv_active_blocks += selection
         .required_blocks
         .saturating_sub(selection.overlap_blocks as u64);
+    // The worker loads the adapter for the request
+    if worker.lacks_lora(lora_id) {
+        if let Some(lora_ids) = worker.data.lora_ids.as_mut() {
+            lora_ids.push(lora_id);
+        }
+    }
 
     // Emit event
     if let Err(e) = event_tx.send(KVHitRateEvent {
@@ -257,6 +749,25 @@ impl WorkerSelector for DefaultWorkerSelector {
             return Err(KvSchedulerError::NoEndpoints);
         }
 
+        let logits = self
+            .score_workers(workers, request, block_size)
+            .into_iter()
+            .map(|score| (score.worker_id, score.logit))
+            .collect();
+        select_best(
+            logits,
+            request,
+            block_size,
+            self.kv_router_config.temperature,
+        )
+    }
+
+    fn score_workers(
+        &self,
+        workers: &ProcessedEndpoints,
+        request: &SchedulingRequest,
+        block_size: usize,
+    ) -> Vec<WorkerScore> {
         let mut worker_scores = HashMap::new();
         let mut max_waiting = 0.0;
 
@@ -277,8 +788,7 @@ impl WorkerSelector for DefaultWorkerSelector {
         let max_waiting = max_waiting;
 
         // Calculate logits for each worker
-        let mut best_logit = f64::NEG_INFINITY;
-        let mut best_workers = Vec::new();
+        let mut scores = Vec::with_capacity(workers.endpoints.len());
 
         for (worker_id, ep) in workers.endpoints.iter() {
             let worker_id = *worker_id;
@@ -294,58 +804,474 @@ impl WorkerSelector for DefaultWorkerSelector {
                 0.0
             };
 
-            // Calculate logit using same formula as Python
+            let lora_miss = if ep.lacks_lora(request.lora_id) {
+                1.0
+            } else {
+                0.0
+            };
+
+            // Calculate logit using same formula as Python, and the adapter
             let logit = self.kv_router_config.overlap_score_weight * score
                 - self.kv_router_config.gpu_cache_usage_weight * gpu_cache_usage
-                - self.kv_router_config.waiting_requests_weight * normalized_waiting;
+                - self.kv_router_config.waiting_requests_weight * normalized_waiting
+                - self.kv_router_config.lora_miss_weight * lora_miss;
 
             tracing::trace!(
-                "Formula for {worker_id}: {logit:.3} = {:.1} * {score:.3} - {:.1} * {gpu_cache_usage:.3} - {:.1} * {normalized_waiting:.3}",
+                "Formula for {worker_id}: {logit:.3} = {:.1} * {score:.3} - {:.1} * {gpu_cache_usage:.3} - {:.1} * {normalized_waiting:.3} - {:.1} * {lora_miss}",
                 self.kv_router_config.overlap_score_weight,
                 self.kv_router_config.gpu_cache_usage_weight,
                 self.kv_router_config.waiting_requests_weight,
+                self.kv_router_config.lora_miss_weight,
             );
 
-            // Track best workers
-            match logit.partial_cmp(&best_logit) {
-                Some(std::cmp::Ordering::Greater) => {
-                    best_logit = logit;
-                    best_workers.clear();
-                    best_workers.push(worker_id);
-                }
-                Some(std::cmp::Ordering::Equal) => {
-                    best_workers.push(worker_id);
+            let terms = BTreeMap::from([
+                ("overlap_score".to_string(), score),
+                ("gpu_cache_usage".to_string(), gpu_cache_usage),
+                ("waiting_requests".to_string(), normalized_waiting),
+                ("lora_miss".to_string(), lora_miss),
+            ]);
+            scores.push(worker_score(worker_id, ep, request, terms, logit));
+        }
+        scores
+    }
+}
+
+/// Picks the worker with the best [`BalancedWeights`] score of prefix overlap, queue depth, KV
+/// blocks in use and decode throughput. The weights can be changed while it runs, see
+/// [`BalancedWorkerSelector::watch_weights`].
+#[derive(Debug, Clone, Default)]
+pub struct BalancedWorkerSelector {
+    weights: Arc<RwLock<BalancedWeights>>,
+    /// See [`KvRouterConfig::temperature`]
+    temperature: f64,
+}
+
+impl BalancedWorkerSelector {
+    pub fn new(weights: BalancedWeights) -> Self {
+        Self {
+            weights: Arc::new(RwLock::new(weights)),
+            temperature: 0.0,
+        }
+    }
+
+    /// Sample workers by their score instead of picking the best, see
+    /// [`KvRouterConfig::temperature`]
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn weights(&self) -> BalancedWeights {
+        // safety: Only whole values are written
+        *self.weights.read().unwrap()
+    }
+
+    pub fn set_weights(&self, weights: BalancedWeights) {
+        *self.weights.write().unwrap() = weights;
+    }
+
+    /// Follow the etcd key `key`: a JSON object of the weights to change from the current ones,
+    /// e.g. `{"decode_throughput": 1.0}`. Deleting the key restores the weights from before.
+    pub async fn watch_weights(&self, drt: &DistributedRuntime, key: String) -> anyhow::Result<()> {
+        let Some(etcd_client) = drt.etcd_client() else {
+            anyhow::bail!("Static components don't have an etcd client");
+        };
+        let base = self.weights();
+        let (_prefix, watcher, mut receiver) =
+            etcd_client.kv_get_and_watch_prefix(&key).await?.dissolve();
+        let this = self.clone();
+        drt.runtime()
+            .tasks()
+            .spawn(format!("kv router weights {key}"), async move {
+                let _watcher = watcher;
+                while let Some(event) = receiver.recv().await {
+                    match event {
+                        // The watch is on a prefix, it also sees the models whose names start with ours
+                        WatchEvent::Put(kv) if kv.key() == key.as_bytes() => {
+                            match base.merge_json(kv.value()) {
+                                Ok(weights) => {
+                                    tracing::info!(?weights, "KV router weights from etcd");
+                                    this.set_weights(weights);
+                                }
+                                Err(err) => {
+                                    tracing::error!(%err, key, "Invalid KV router weights in etcd")
+                                }
+                            }
+                        }
+                        WatchEvent::Delete(kv) if kv.key() == key.as_bytes() => {
+                            tracing::info!(weights = ?base, "KV router weights removed from etcd");
+                            this.set_weights(base);
+                        }
+                        _ => {}
+                    }
                 }
-                _ => {}
+            });
+        Ok(())
+    }
+}
+
+impl WorkerSelector for BalancedWorkerSelector {
+    fn select_worker(
+        &self,
+        workers: &ProcessedEndpoints,
+        request: &SchedulingRequest,
+        block_size: usize,
+    ) -> Result<WorkerSelectionResult, KvSchedulerError> {
+        assert!(request.isl_tokens > 0);
+
+        if workers.endpoints.is_empty() {
+            return Err(KvSchedulerError::NoEndpoints);
+        }
+
+        let weights = self.weights();
+        let logits = workers
+            .balanced_terms(
+                &request.overlap,
+                request.isl_tokens,
+                block_size,
+                request.lora_id,
+            )
+            .into_iter()
+            .map(|(worker_id, terms)| {
+                let logit = weights.logit(&terms);
+                tracing::trace!(worker_id, logit, ?terms, "Balanced score");
+                (worker_id, logit)
+            })
+            .collect();
+        select_best(logits, request, block_size, self.temperature)
+    }
+
+    fn score_workers(
+        &self,
+        workers: &ProcessedEndpoints,
+        request: &SchedulingRequest,
+        block_size: usize,
+    ) -> Vec<WorkerScore> {
+        let weights = self.weights();
+        workers
+            .balanced_terms(
+                &request.overlap,
+                request.isl_tokens,
+                block_size,
+                request.lora_id,
+            )
+            .into_iter()
+            .filter_map(|(worker_id, terms)| {
+                let endpoint = workers.endpoints.get(&worker_id)?;
+                Some(worker_score(
+                    worker_id,
+                    endpoint,
+                    request,
+                    terms.named(),
+                    weights.logit(&terms),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// The worker with the highest logit, at random between equals. With a `temperature` above 0, a
+/// worker sampled with probability proportional to `exp(logit / temperature)` instead.
+fn select_best(
+    logits: Vec<(i64, f64)>,
+    request: &SchedulingRequest,
+    block_size: usize,
+    temperature: f64,
+) -> Result<WorkerSelectionResult, KvSchedulerError> {
+    let selected = if temperature > 0.0 {
+        sample_softmax(&logits, temperature)
+    } else {
+        argmax(logits)
+    };
+    let Some((worker_id, logit)) = selected else {
+        return Err(KvSchedulerError::NoEndpoints);
+    };
+    if logit == 0.0 {
+        tracing::debug!("selected worker logit is 0");
+    }
+
+    // Lower to trace level eventually. Nice to see KV routing working for now.
+    tracing::debug!("Selected worker: {worker_id}, logit: {logit:.3}");
+
+    // Log selection metrics
+    let total_blocks = std::cmp::max(request.isl_tokens / block_size, 1) as u64;
+    let overlap_blocks = request.overlap.scores.get(&worker_id).copied().unwrap_or(0) as usize;
+
+    Ok(WorkerSelectionResult {
+        worker_id,
+        required_blocks: total_blocks,
+        overlap_blocks,
+    })
+}
+
+/// The worker with the highest logit, at random between equals
+fn argmax(logits: Vec<(i64, f64)>) -> Option<(i64, f64)> {
+    let mut best_logit = f64::NEG_INFINITY;
+    let mut best_workers = Vec::new();
+    for (worker_id, logit) in logits {
+        match logit.partial_cmp(&best_logit) {
+            Some(std::cmp::Ordering::Greater) => {
+                best_logit = logit;
+                best_workers.clear();
+                best_workers.push(worker_id);
+            }
+            Some(std::cmp::Ordering::Equal) => {
+                best_workers.push(worker_id);
             }
+            _ => {}
         }
+    }
+    let worker_id = match best_workers.len() {
+        0 => return None,
+        1 => best_workers[0],
+        // Randomly select from best workers
+        n => best_workers[rand::rng().random_range(0..n)],
+    };
+    Some((worker_id, best_logit))
+}
 
-        // Return early if no valid workers found
-        if best_workers.is_empty() {
-            return Err(KvSchedulerError::NoEndpoints);
-        } else if best_logit == 0.0 {
-            tracing::debug!("best worker logit is 0");
+/// A worker sampled with probability proportional to `exp(logit / temperature)`, so that routers
+/// running side by side spread their requests over workers that score about the same instead of
+/// all picking the best one. Workers with a logit of minus infinity or NaN are never picked.
+fn sample_softmax(logits: &[(i64, f64)], temperature: f64) -> Option<(i64, f64)> {
+    let candidates: Vec<(i64, f64)> = logits
+        .iter()
+        .copied()
+        .filter(|(_, logit)| logit.is_finite())
+        .collect();
+    let max_logit = candidates
+        .iter()
+        .map(|(_, logit)| *logit)
+        .fold(f64::NEG_INFINITY, f64::max);
+    // Shifted by the best logit, so the weights don't overflow
+    let weights: Vec<f64> = candidates
+        .iter()
+        .map(|(_, logit)| ((logit - max_logit) / temperature).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    if candidates.is_empty() || !total.is_finite() {
+        return None;
+    }
+    let mut point = rand::rng().random::<f64>() * total;
+    for (candidate, weight) in candidates.iter().zip(&weights) {
+        if point < *weight {
+            return Some(*candidate);
+        }
+        point -= weight;
+    }
+    // Rounding left the point past the last weight
+    candidates.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(priority: i32, principal: &str, isl_tokens: usize) -> SchedulingRequest {
+        SchedulingRequest {
+            isl_tokens,
+            overlap: OverlapScores::new(),
+            priority,
+            principal: Some(principal.to_string()).filter(|p| !p.is_empty()),
+            lora_id: 0,
+            worker_id: None,
+            workers: None,
+            resp_tx: tokio::sync::oneshot::channel().0,
+        }
+    }
+
+    /// Pop everything, as (priority, isl_tokens)
+    fn drain(queue: &mut SchedulingQueue) -> Vec<(i32, usize)> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|queued| (queued.request.priority, queued.request.isl_tokens))
+            .collect()
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let mut queue = SchedulingQueue::default();
+        for (priority, isl_tokens) in [(0, 1), (50, 2), (-50, 3), (50, 4)] {
+            queue.push(request(priority, "", isl_tokens));
         }
 
-        let worker_id = if best_workers.len() == 1 {
-            best_workers[0]
-        } else {
-            // Randomly select from best workers
-            let mut rng = rand::rng();
-            best_workers[rng.random_range(0..best_workers.len())]
+        let first = queue.pop().unwrap();
+        assert_eq!((first.request.priority, first.request.isl_tokens), (50, 2));
+        // A request that could not be placed stays ahead of later ones
+        queue.requeue(first);
+        assert_eq!(queue.len(), 4);
+        assert_eq!(drain(&mut queue), vec![(50, 2), (50, 4), (0, 1), (-50, 3)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_fair_share() {
+        let mut queue = SchedulingQueue::default();
+        // A heavy user queues many large requests before a light user sends two small ones
+        for _ in 0..6 {
+            queue.push(request(0, "key-heavy", 3000));
+        }
+        queue.push(request(0, "key-light", 100));
+        queue.push(request(0, "key-light", 101));
+        // Higher priority still goes first
+        queue.push(request(10, "key-heavy", 3000));
+
+        let order = drain(&mut queue);
+        assert_eq!(order[0], (10, 3000));
+        // The heavy user places one request per quantum, the light one both of theirs in their
+        // first turn
+        assert_eq!(
+            order[1..5].to_vec(),
+            vec![(0, 3000), (0, 100), (0, 101), (0, 3000)]
+        );
+        assert_eq!(order.len(), 9);
+    }
+
+    #[test]
+    fn test_requeue_keeps_share() {
+        let mut fair = FairQueue::default();
+        for principal in ["a", "b"] {
+            fair.push(Queued {
+                enqueued: Instant::now(),
+                request: request(0, principal, FAIR_SHARE_QUANTUM),
+            });
+        }
+        let first = fair.pop().unwrap();
+        assert_eq!(first.principal(), "a");
+        fair.requeue(first);
+        assert_eq!(fair.pop().unwrap().principal(), "a");
+        assert_eq!(fair.pop().unwrap().principal(), "b");
+        assert!(fair.pop().is_none());
+    }
+
+    fn endpoint(
+        worker_id: i64,
+        kv_active_blocks: u64,
+        decode_tokens_per_sec: Option<f32>,
+    ) -> Endpoint {
+        Endpoint {
+            name: format!("worker-{worker_id}"),
+            subject: format!("dynamo.worker.generate-{worker_id:x}"),
+            data: ForwardPassMetrics {
+                kv_active_blocks,
+                kv_total_blocks: 100,
+                decode_tokens_per_sec,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_balanced_selector() {
+        let workers =
+            ProcessedEndpoints::new(vec![endpoint(1, 90, None), endpoint(2, 10, Some(50.0))]);
+        let mut request = request(0, "", 64);
+        request.overlap.scores.insert(1, 4);
+
+        let terms = workers.balanced_terms(&request.overlap, request.isl_tokens, 16, 0);
+        assert_eq!(terms[&1].overlap, 1.0);
+        assert_eq!(terms[&1].active_blocks, 0.9);
+        assert_eq!(terms[&1].decode_throughput, 0.0);
+        assert_eq!(terms[&2].decode_throughput, 1.0);
+
+        // The cached prompt outweighs the load
+        let selector = BalancedWorkerSelector::default();
+        let selection = selector.select_worker(&workers, &request, 16).unwrap();
+        assert_eq!((selection.worker_id, selection.overlap_blocks), (1, 4));
+
+        let weights = selector
+            .weights()
+            .merge_json(br#"{"overlap": 0.5}"#)
+            .unwrap();
+        assert_eq!(weights.active_blocks, 1.0);
+        selector.set_weights(weights);
+        let selection = selector.select_worker(&workers, &request, 16).unwrap();
+        assert_eq!(selection.worker_id, 2);
+
+        assert!(weights.merge_json(br#"{"overlap_score": 1.0}"#).is_err());
+    }
+
+    #[test]
+    fn test_lora_affinity() {
+        let mut with_lora = endpoint(1, 10, None);
+        with_lora.data.lora_ids = Some(vec![5]);
+        let mut without_lora = endpoint(2, 10, None);
+        without_lora.data.lora_ids = Some(vec![]);
+        // Doesn't report its adapters, might have it
+        let unknown = endpoint(3, 10, None);
+        assert!(!unknown.lacks_lora(5));
+        let mut workers = ProcessedEndpoints::new(vec![with_lora, without_lora, unknown]);
+
+        let mut request = request(0, "", 64);
+        request.lora_id = 5;
+        let selector = DefaultWorkerSelector::default();
+        for _ in 0..10 {
+            let worker_id = selector
+                .select_worker(&workers, &request, 16)
+                .unwrap()
+                .worker_id;
+            assert_ne!(worker_id, 2);
+        }
+
+        // Once sent there, the worker has the adapter
+        let (event_tx, _event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let selection = WorkerSelectionResult {
+            worker_id: 2,
+            required_blocks: 4,
+            overlap_blocks: 0,
         };
+        process_worker_selection(&mut workers, selection, 5, &event_tx);
+        assert!(!workers.endpoints[&2].lacks_lora(5));
+    }
 
-        // Lower to trace level eventually. Nice to see KV routing working for now.
-        tracing::debug!("Selected worker: {worker_id}, logit: {best_logit:.3}");
+    #[test]
+    fn test_explain() {
+        let workers =
+            ProcessedEndpoints::new(vec![endpoint(1, 90, None), endpoint(2, 10, Some(50.0))]);
+        let mut request = request(0, "", 64);
+        request.overlap.scores.insert(1, 4);
 
-        // Log selection metrics
-        let total_blocks = std::cmp::max(request.isl_tokens / block_size, 1) as u64;
-        let overlap_blocks = request.overlap.scores.get(&worker_id).copied().unwrap_or(0) as usize;
+        let mut scores = BalancedWorkerSelector::default().score_workers(&workers, &request, 16);
+        scores.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+        assert_eq!(scores[0].worker_id, 1);
+        assert_eq!(scores[0].overlap_blocks, 4);
+        assert_eq!(scores[0].terms["overlap"], 1.0);
+        assert_eq!(scores[1].terms["decode_throughput"], 1.0);
 
-        Ok(WorkerSelectionResult {
-            worker_id,
-            required_blocks: total_blocks,
-            overlap_blocks,
-        })
+        let explained = explanation(scores.clone(), 4, None);
+        assert_eq!(explained.worker_id, 1);
+        assert_eq!(
+            explained.reason,
+            "Highest score 1.100, with 4 of 4 prompt blocks cached"
+        );
+        let explained = explanation(scores, 4, Some(2));
+        assert_eq!(explained.worker_id, 2);
+        assert!(explained.reason.contains("worker 1 scores best"));
+
+        assert!(DefaultWorkerSelector::default()
+            .score_workers(&workers, &request, 16)
+            .iter()
+            .all(|score| score.terms.contains_key("overlap_score")));
+        assert_eq!(explanation(vec![], 4, Some(7)).worker_id, 7);
+    }
+
+    #[test]
+    fn test_sample_softmax() {
+        let logits = vec![(1, 1.0), (2, 0.0), (3, f64::NEG_INFINITY)];
+        let samples = 10_000;
+        let mut picked = HashMap::new();
+        for _ in 0..samples {
+            let (worker_id, _) = sample_softmax(&logits, 1.0).unwrap();
+            *picked.entry(worker_id).or_insert(0) += 1;
+        }
+        // e / (e + 1) of the samples
+        let share = picked[&1] as f64 / samples as f64;
+        assert!((0.70..0.76).contains(&share), "{share}");
+        assert!(!picked.contains_key(&3));
+
+        // A low temperature is about argmax
+        for _ in 0..100 {
+            assert_eq!(sample_softmax(&logits, 0.01).unwrap().0, 1);
+        }
+        assert!(sample_softmax(&[(1, f64::NAN)], 1.0).is_none());
     }
 }
@@ -16,10 +16,103 @@
 //! Scoring functions for the KV router.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use crate::kv_router::indexer::OverlapScores;
 use crate::kv_router::scheduler::Endpoint;
 
+/// Weights of the balanced worker score. The score of a worker is
+///
+/// `overlap * overlap_term - queue_depth * queue_term - active_blocks * blocks_term
+///  + decode_throughput * throughput_term - lora_miss * lora_miss_term`
+///
+/// where each term is from 0 to 1, see [`BalancedTerms`]. A weight of 0 ignores its term.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalancedWeights {
+    /// Prefer workers that have more of the prompt cached. Default: 2.0
+    pub overlap: f64,
+
+    /// Avoid workers with requests waiting. Default: 1.0
+    pub queue_depth: f64,
+
+    /// Avoid workers with more of their KV blocks in use. Default: 1.0
+    pub active_blocks: f64,
+
+    /// Prefer workers that generate faster. Default: 0.5
+    pub decode_throughput: f64,
+
+    /// Avoid workers that would have to load the request's LoRA adapter. Default: 1.0
+    pub lora_miss: f64,
+}
+
+impl Default for BalancedWeights {
+    fn default() -> Self {
+        BalancedWeights {
+            overlap: 2.0,
+            queue_depth: 1.0,
+            active_blocks: 1.0,
+            decode_throughput: 0.5,
+            lora_miss: 1.0,
+        }
+    }
+}
+
+impl BalancedWeights {
+    /// These weights with the ones in the JSON object `json` replaced
+    pub fn merge_json(&self, json: &[u8]) -> anyhow::Result<Self> {
+        let serde_json::Value::Object(changes) = serde_json::from_slice(json)? else {
+            anyhow::bail!("KV router weights must be a JSON object");
+        };
+        let mut weights = serde_json::to_value(self)?;
+        if let Some(weights) = weights.as_object_mut() {
+            weights.extend(changes);
+        }
+        Ok(serde_json::from_value(weights)?)
+    }
+
+    pub fn logit(&self, terms: &BalancedTerms) -> f64 {
+        self.overlap * terms.overlap
+            - self.queue_depth * terms.queue_depth
+            - self.active_blocks * terms.active_blocks
+            + self.decode_throughput * terms.decode_throughput
+            - self.lora_miss * terms.lora_miss
+    }
+}
+
+/// What the balanced score knows of a worker, each from 0 to 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BalancedTerms {
+    /// The share of the prompt the worker has cached
+    pub overlap: f64,
+
+    /// Requests waiting on the worker, relative to the worker with the most
+    pub queue_depth: f64,
+
+    /// The share of the worker's KV blocks in use
+    pub active_blocks: f64,
+
+    /// Decode tokens per second relative to the fastest worker. 0 for workers that don't report
+    /// it.
+    pub decode_throughput: f64,
+
+    /// 1 if the worker doesn't have the request's LoRA adapter loaded
+    pub lora_miss: f64,
+}
+
+impl BalancedTerms {
+    /// The terms by the name of their weight, to explain a score
+    pub fn named(&self) -> BTreeMap<String, f64> {
+        BTreeMap::from([
+            ("overlap".to_string(), self.overlap),
+            ("queue_depth".to_string(), self.queue_depth),
+            ("active_blocks".to_string(), self.active_blocks),
+            ("decode_throughput".to_string(), self.decode_throughput),
+            ("lora_miss".to_string(), self.lora_miss),
+        ])
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct ProcessedEndpoints {
     pub endpoints: HashMap<i64, Endpoint>,
@@ -50,4 +143,58 @@ impl ProcessedEndpoints {
             load_std,
         }
     }
+
+    /// The [`BalancedTerms`] of each worker for a prompt of `isl_tokens` tokens with `overlap`,
+    /// under the LoRA adapter `lora_id`
+    pub fn balanced_terms(
+        &self,
+        overlap: &OverlapScores,
+        isl_tokens: usize,
+        block_size: usize,
+        lora_id: u64,
+    ) -> HashMap<i64, BalancedTerms> {
+        let max_waiting = self
+            .endpoints
+            .values()
+            .map(|ep| ep.data.num_requests_waiting)
+            .max()
+            .unwrap_or(0);
+        let max_throughput = self
+            .endpoints
+            .values()
+            .filter_map(|ep| ep.data.decode_tokens_per_sec)
+            .fold(0.0, f32::max);
+
+        self.endpoints
+            .iter()
+            .map(|(worker_id, ep)| {
+                let overlap_blocks = overlap.scores.get(worker_id).copied().unwrap_or(0);
+                let active_blocks = if ep.data.kv_total_blocks > 0 {
+                    ep.data.kv_active_blocks as f64 / ep.data.kv_total_blocks as f64
+                } else {
+                    ep.data.gpu_cache_usage_perc as f64
+                };
+                let terms = BalancedTerms {
+                    overlap: (overlap_blocks as f64 * block_size as f64 / isl_tokens.max(1) as f64)
+                        .min(1.0),
+                    queue_depth: if max_waiting > 0 {
+                        ep.data.num_requests_waiting as f64 / max_waiting as f64
+                    } else {
+                        0.0
+                    },
+                    // The scheduler adds the blocks of the requests it places until the worker
+                    // reports again, which may overshoot
+                    active_blocks: active_blocks.min(1.0),
+                    decode_throughput: match ep.data.decode_tokens_per_sec {
+                        Some(throughput) if max_throughput > 0.0 => {
+                            (throughput / max_throughput) as f64
+                        }
+                        _ => 0.0,
+                    },
+                    lora_miss: if ep.lacks_lora(lora_id) { 1.0 } else { 0.0 },
+                };
+                (*worker_id, terms)
+            })
+            .collect()
+    }
 }
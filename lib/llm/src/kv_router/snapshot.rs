@@ -0,0 +1,408 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Snapshots of the KV router's index, so that a restarted router doesn't start with an empty
+//! index and ignore the workers' caches until it has seen their events again.
+//!
+//! A snapshot is the blocks each worker has, every parent before its children, so restoring one
+//! is replaying them as store events. The router can save its index periodically, to a
+//! directory or the NATS object store, and restore it at start. A new router can also ask a
+//! running one for its index: every [`KvRouter`](super::KvRouter) answers on the
+//! [`KV_INDEX_SNAPSHOT_SUBJECT`] of its component, in chunks that fit in a NATS message.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use bytes::Bytes;
+use dynamo_runtime::component::Component;
+use dynamo_runtime::traits::events::EventPublisher;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::kv_router::indexer::{
+    request_snapshot, KvIndexerSharded, RouterEvent, SnapshotSender, WorkerId,
+};
+use crate::kv_router::protocols::{
+    ExternalSequenceBlockHash, KvCacheEvent, KvCacheEventData, KvCacheStoreData,
+    KvCacheStoredBlockData, LocalBlockHash,
+};
+
+/// Subject, under the component's, on which routers send their index to new ones
+pub const KV_INDEX_SNAPSHOT_SUBJECT: &str = "kv_index_snapshot";
+
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+/// Snapshots are sent to new routers in chunks of this size, below the NATS payload limit
+const CHUNK_BYTES: usize = 512 * 1024;
+
+/// Header of every chunk with how many there are
+const CHUNKS_HEADER: &str = "Dynamo-Chunks";
+
+/// How long a new router waits for a running one to send its index
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+const NATS_PREFIX: &str = "nats://";
+
+/// A block of one worker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotBlock {
+    pub parent_hash: Option<ExternalSequenceBlockHash>,
+    pub block_hash: ExternalSequenceBlockHash,
+    pub tokens_hash: LocalBlockHash,
+}
+
+/// The blocks of a KV index
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexSnapshot {
+    pub version: u32,
+    pub kv_block_size: usize,
+
+    /// Milliseconds since the Unix epoch
+    pub taken_at_ms: i64,
+
+    /// The blocks of each worker, every parent before its children
+    pub workers: Vec<(WorkerId, Vec<SnapshotBlock>)>,
+}
+
+impl IndexSnapshot {
+    pub fn new(kv_block_size: usize, workers: Vec<(WorkerId, Vec<SnapshotBlock>)>) -> Self {
+        IndexSnapshot {
+            version: SNAPSHOT_VERSION,
+            kv_block_size,
+            taken_at_ms: chrono::Utc::now().timestamp_millis(),
+            workers,
+        }
+    }
+
+    /// Blocks of all workers, counting a block once for each worker that has it
+    pub fn num_entries(&self) -> usize {
+        self.workers.iter().map(|(_, blocks)| blocks.len()).sum()
+    }
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(self)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let snapshot: IndexSnapshot =
+            rmp_serde::from_slice(bytes).context("Malformed KV index snapshot")?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            anyhow::bail!(
+                "KV index snapshot has version {}, expected {SNAPSHOT_VERSION}",
+                snapshot.version
+            );
+        }
+        Ok(snapshot)
+    }
+
+    /// The store events that rebuild the index. A chain of blocks is one event.
+    pub fn into_events(self) -> Vec<RouterEvent> {
+        let mut events = Vec::new();
+        for (worker, blocks) in self.workers {
+            let mut current: Option<KvCacheStoreData> = None;
+            for block in blocks {
+                let stored = KvCacheStoredBlockData {
+                    block_hash: block.block_hash,
+                    tokens_hash: block.tokens_hash,
+                };
+                match current.as_mut() {
+                    Some(data)
+                        if data.blocks.last().map(|last| last.block_hash) == block.parent_hash =>
+                    {
+                        data.blocks.push(stored);
+                    }
+                    _ => {
+                        let next = KvCacheStoreData {
+                            parent_hash: block.parent_hash,
+                            blocks: vec![stored],
+                        };
+                        if let Some(data) = current.replace(next) {
+                            events.push(stored_event(worker, data));
+                        }
+                    }
+                }
+            }
+            if let Some(data) = current {
+                events.push(stored_event(worker, data));
+            }
+        }
+        events
+    }
+}
+
+fn stored_event(worker: WorkerId, data: KvCacheStoreData) -> RouterEvent {
+    RouterEvent::new(
+        worker,
+        KvCacheEvent {
+            event_id: 0,
+            data: KvCacheEventData::Stored(data),
+        },
+    )
+}
+
+/// Where snapshots are saved, one per component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotStore {
+    /// A directory on local disk
+    Dir(PathBuf),
+
+    /// A bucket of the NATS object store, `nats://<bucket>`
+    Nats { bucket: String },
+}
+
+impl FromStr for SnapshotStore {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.strip_prefix(NATS_PREFIX) {
+            Some(bucket) => {
+                let bucket = bucket.trim_end_matches('/');
+                if bucket.is_empty() || bucket.contains('/') {
+                    anyhow::bail!("Expected nats://<bucket>, got {s}");
+                }
+                Ok(SnapshotStore::Nats {
+                    bucket: bucket.to_string(),
+                })
+            }
+            None => Ok(SnapshotStore::Dir(PathBuf::from(s))),
+        }
+    }
+}
+
+impl SnapshotStore {
+    async fn save(&self, component: &Component, snapshot: &IndexSnapshot) -> anyhow::Result<()> {
+        let bytes = snapshot.encode()?;
+        match self {
+            SnapshotStore::Dir(dir) => {
+                tokio::fs::create_dir_all(dir).await?;
+                // Written next to it and renamed, so that a crash doesn't leave half a snapshot
+                let path = dir.join(snapshot_key(component));
+                let partial = path.with_extension("partial");
+                tokio::fs::write(&partial, &bytes).await?;
+                tokio::fs::rename(&partial, &path).await?;
+            }
+            SnapshotStore::Nats { bucket } => {
+                let file = tempfile::NamedTempFile::new()?;
+                tokio::fs::write(file.path(), &bytes).await?;
+                let nats = component.drt().nats_client();
+                nats.object_store_upload(file.path(), nats_url(&nats, bucket, component)?)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn load(&self, component: &Component) -> anyhow::Result<Option<IndexSnapshot>> {
+        let bytes = match self {
+            SnapshotStore::Dir(dir) => {
+                let path = dir.join(snapshot_key(component));
+                if !path.exists() {
+                    return Ok(None);
+                }
+                tokio::fs::read(&path).await?
+            }
+            SnapshotStore::Nats { bucket } => {
+                let file = tempfile::NamedTempFile::new()?;
+                let nats = component.drt().nats_client();
+                nats.object_store_download(nats_url(&nats, bucket, component)?, file.path())
+                    .await?;
+                tokio::fs::read(file.path()).await?
+            }
+        };
+        IndexSnapshot::decode(&bytes).map(Some)
+    }
+}
+
+/// The name of the snapshot of `component`'s index in a store
+fn snapshot_key(component: &Component) -> String {
+    format!("{}.kv_index", component.service_name())
+}
+
+fn nats_url(
+    nats: &dynamo_runtime::transports::nats::Client,
+    bucket: &str,
+    component: &Component,
+) -> anyhow::Result<url::Url> {
+    let url = format!(
+        "{NATS_PREFIX}{}/{bucket}/{}",
+        nats.addr(),
+        snapshot_key(component)
+    );
+    Ok(url::Url::parse(&url)?)
+}
+
+/// How a [`KvRouter`](super::KvRouter) keeps its index across restarts
+#[derive(Debug, Clone)]
+pub struct IndexSnapshotConfig {
+    /// Where to save the index and restore it from. Not saved if not set.
+    pub store: Option<SnapshotStore>,
+
+    /// How often to save the index. Default: 60s
+    pub interval: Duration,
+
+    /// Ask a running router for its index at start, before trying the store. Default: false
+    pub bootstrap: bool,
+}
+
+impl Default for IndexSnapshotConfig {
+    fn default() -> Self {
+        IndexSnapshotConfig {
+            store: None,
+            interval: Duration::from_secs(60),
+            bootstrap: false,
+        }
+    }
+}
+
+/// Fill `indexer` at start: from a running router if `config.bootstrap`, else, or if none
+/// answers, from the store. Starting with an empty index is not an error.
+pub(crate) async fn restore(
+    component: &Component,
+    config: &IndexSnapshotConfig,
+    indexer: &KvIndexerSharded,
+) {
+    let mut snapshot = None;
+    if config.bootstrap {
+        match bootstrap(component).await {
+            Ok(from_router) => snapshot = Some(("router", from_router)),
+            Err(err) => tracing::info!(%err, "No KV index from a running router"),
+        }
+    }
+    if let Some(store) = config.store.as_ref().filter(|_| snapshot.is_none()) {
+        match store.load(component).await {
+            Ok(from_store) => snapshot = from_store.map(|s| ("store", s)),
+            Err(err) => tracing::warn!(%err, "Failed loading the KV index snapshot"),
+        }
+    }
+    let Some((source, snapshot)) = snapshot else {
+        return;
+    };
+    let age_secs = (chrono::Utc::now().timestamp_millis() - snapshot.taken_at_ms) / 1000;
+    match indexer.restore(snapshot).await {
+        Ok(entries) => tracing::info!(entries, source, age_secs, "Restored the KV index"),
+        Err(err) => tracing::warn!(%err, source, "Failed restoring the KV index"),
+    }
+}
+
+/// Ask a running router for its index
+async fn bootstrap(component: &Component) -> anyhow::Result<IndexSnapshot> {
+    let client = component.drt().nats_client().client().clone();
+    let inbox = client.new_inbox();
+    let mut replies = client.subscribe(inbox.clone()).await?;
+    client
+        .publish_with_reply(snapshot_subject(component), inbox, Bytes::new())
+        .await?;
+    let receive = async {
+        let mut bytes = Vec::new();
+        let mut received = 0;
+        while let Some(chunk) = replies.next().await {
+            let chunks: usize = chunk
+                .headers
+                .as_ref()
+                .and_then(|headers| headers.get(CHUNKS_HEADER))
+                .and_then(|count| count.as_str().parse().ok())
+                .context("KV index snapshot chunk without a count")?;
+            bytes.extend_from_slice(&chunk.payload);
+            received += 1;
+            if received == chunks {
+                return IndexSnapshot::decode(&bytes);
+            }
+        }
+        anyhow::bail!("KV index snapshot subscription closed")
+    };
+    tokio::time::timeout(BOOTSTRAP_TIMEOUT, receive)
+        .await
+        .context("No running router answered in time")?
+}
+
+/// Send the index to new routers that ask for it, until `cancel`. The routers of a component
+/// are a queue group, so one of them answers each request.
+pub(crate) async fn serve_bootstrap(
+    component: Component,
+    snapshots: SnapshotSender,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let client = component.drt().nats_client().client().clone();
+    let subject = snapshot_subject(&component);
+    let mut requests = client.queue_subscribe(subject.clone(), subject).await?;
+    loop {
+        let request = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            request = requests.next() => match request {
+                Some(request) => request,
+                None => anyhow::bail!("KV index snapshot subscription closed"),
+            },
+        };
+        let Some(reply) = request.reply else {
+            continue;
+        };
+        // The asking router gives up after a while and starts empty, this one keeps serving
+        match send_snapshot(&client, reply, &snapshots).await {
+            Ok((entries, bytes)) => {
+                tracing::debug!(entries, bytes, "Sent the KV index to a new router")
+            }
+            Err(err) => tracing::warn!(%err, "Failed sending the KV index to a new router"),
+        }
+    }
+}
+
+/// Send a snapshot of the index to `reply`, in chunks. Returns its entries and bytes.
+async fn send_snapshot(
+    client: &async_nats::Client,
+    reply: async_nats::Subject,
+    snapshots: &SnapshotSender,
+) -> anyhow::Result<(usize, usize)> {
+    let snapshot = request_snapshot(snapshots).await?;
+    let bytes = snapshot.encode()?;
+    let chunks = bytes.chunks(CHUNK_BYTES);
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert(CHUNKS_HEADER, chunks.len().to_string().as_str());
+    for chunk in chunks {
+        client
+            .publish_with_headers(
+                reply.clone(),
+                headers.clone(),
+                Bytes::copy_from_slice(chunk),
+            )
+            .await?;
+    }
+    Ok((snapshot.num_entries(), bytes.len()))
+}
+
+/// Save the index to `store` every `interval`, until `cancel`
+pub(crate) async fn save_periodically(
+    component: Component,
+    store: SnapshotStore,
+    interval: Duration,
+    snapshots: SnapshotSender,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(interval);
+    // The first tick is immediate, and the index is still empty or just restored
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = interval.tick() => {}
+        }
+        let snapshot = match request_snapshot(&snapshots).await {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                // Try again next time, the indexer may be busy or restarting
+                tracing::warn!(%err, "Failed taking a snapshot of the KV index");
+                continue;
+            }
+        };
+        match store.save(&component, &snapshot).await {
+            Ok(()) => tracing::debug!(entries = snapshot.num_entries(), "Saved the KV index"),
+            Err(err) => tracing::warn!(%err, ?store, "Failed saving the KV index"),
+        }
+    }
+}
+
+fn snapshot_subject(component: &Component) -> String {
+    format!("{}.{KV_INDEX_SNAPSHOT_SUBJECT}", component.subject())
+}
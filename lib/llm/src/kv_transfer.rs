@@ -0,0 +1,251 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Move KV cache blocks between workers, e.g. from a prefill worker to a decode worker, or off a
+//! worker that is drained.
+//!
+//! Experimental: nothing in dynamo uses it yet. Disaggregated serving still moves the KV cache
+//! with the engines' own connectors, see [`crate::prefill_router`].
+//!
+//! The worker holding the blocks runs a [`TransferServer`], the worker that wants them connects
+//! with a [`TransferClient`]. Both sides send a [`Hello`] with their block [`LayoutDescriptor`]
+//! and the backends they can use, and [`negotiate`] the backend of the connection:
+//! - [`Backend::Nixl`]: the server writes the blocks straight into the client's memory with NIXL,
+//!   over UCX, see [`nixl`]. Needs the `block-manager` feature on both sides.
+//! - [`Backend::Tcp`]: the server copies the blocks into the connection, and the client writes
+//!   them into its [`BlockSink`]. Slower, but works anywhere.
+//!
+//! The client then sends a [`TransferRequest`] per set of blocks, and the server answers each with
+//! a [`TransferEvent`] once the blocks arrived or the transfer failed. See [`protocol`] for the
+//! wire format.
+//!
+//! Anyone who can reach the server could otherwise read the KV cache, the prompts of its users in
+//! all but name. Give both sides the [`TransferKey`] of the cluster key, the one that signs
+//! control messages (`DYN_CONTROL_SIGNING_KEY_FILE`), and they only talk to each other. A peer
+//! that stalls mid-frame, or doesn't answer, is dropped after [`protocol::FRAME_TIMEOUT`].
+
+pub mod client;
+#[cfg(feature = "block-manager")]
+pub mod nixl;
+pub mod protocol;
+pub mod server;
+
+use std::ops::Range;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+
+pub use client::TransferClient;
+pub use protocol::{
+    negotiate, Backend, Hello, LayoutDescriptor, TransferEvent, TransferKey, TransferRequest,
+    TransferStatus,
+};
+pub use server::TransferServer;
+
+/// KV blocks a [`TransferServer`] sends over [`Backend::Tcp`]
+pub trait BlockSource: Send + Sync {
+    fn layout(&self) -> &LayoutDescriptor;
+
+    /// Copy block `block` into `out`, which is [`LayoutDescriptor::block_bytes`] long
+    fn read_block(&self, block: usize, out: &mut [u8]) -> anyhow::Result<()>;
+}
+
+/// KV blocks a [`TransferClient`] receives over [`Backend::Tcp`]
+pub trait BlockSink: Send + Sync {
+    fn layout(&self) -> &LayoutDescriptor;
+
+    /// Overwrite block `block` with `data`, which is [`LayoutDescriptor::block_bytes`] long
+    fn write_block(&self, block: usize, data: &[u8]) -> anyhow::Result<()>;
+}
+
+/// KV blocks a [`TransferServer`] sends over [`Backend::Nixl`], see [`nixl::NixlBlocks`]
+#[async_trait]
+pub trait NixlSource: Send + Sync {
+    /// The block set the clients import, sent in the server's [`Hello`]
+    fn blockset(&self) -> anyhow::Result<serde_json::Value>;
+
+    /// Import the block set of a client, so that its blocks can be written to
+    fn import_peer(&self, hello: &Hello) -> anyhow::Result<()>;
+
+    /// Write the blocks `src` into the blocks of a client described by `dst`. `notify` is passed
+    /// on to the client's NIXL agent.
+    async fn write_blocks(
+        &self,
+        src: &[usize],
+        dst: &serde_json::Value,
+        notify: &str,
+    ) -> anyhow::Result<()>;
+}
+
+/// Blocks in host memory, e.g. to stage them on a worker without a block manager
+pub struct HostBlocks {
+    layout: LayoutDescriptor,
+    num_blocks: usize,
+    data: RwLock<Vec<u8>>,
+}
+
+impl HostBlocks {
+    pub fn new(layout: LayoutDescriptor, num_blocks: usize) -> Self {
+        HostBlocks {
+            layout,
+            num_blocks,
+            data: RwLock::new(vec![0; layout.block_bytes() * num_blocks]),
+        }
+    }
+
+    fn range(&self, block: usize) -> anyhow::Result<Range<usize>> {
+        anyhow::ensure!(
+            block < self.num_blocks,
+            "Block {block} out of range, there are {}",
+            self.num_blocks
+        );
+        let block_bytes = self.layout.block_bytes();
+        Ok(block * block_bytes..(block + 1) * block_bytes)
+    }
+}
+
+impl BlockSource for HostBlocks {
+    fn layout(&self) -> &LayoutDescriptor {
+        &self.layout
+    }
+
+    fn read_block(&self, block: usize, out: &mut [u8]) -> anyhow::Result<()> {
+        let range = self.range(block)?;
+        anyhow::ensure!(out.len() == range.len(), "Buffer is not one block");
+        out.copy_from_slice(&self.data.read().unwrap()[range]);
+        Ok(())
+    }
+}
+
+impl BlockSink for HostBlocks {
+    fn layout(&self) -> &LayoutDescriptor {
+        &self.layout
+    }
+
+    fn write_block(&self, block: usize, data: &[u8]) -> anyhow::Result<()> {
+        let range = self.range(block)?;
+        anyhow::ensure!(data.len() == range.len(), "Data is not one block");
+        self.data.write().unwrap()[range].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::dtype::DType;
+    use std::sync::Arc;
+    use tokio_util::sync::CancellationToken;
+
+    const LAYOUT: LayoutDescriptor = LayoutDescriptor {
+        num_layers: 2,
+        outer_dim: 2,
+        page_size: 4,
+        inner_dim: 8,
+        dtype: DType::FP16,
+    };
+
+    #[tokio::test]
+    async fn test_tcp_transfer() {
+        let source = Arc::new(HostBlocks::new(LAYOUT, 4));
+        for block in 0..4 {
+            let data = vec![block as u8 + 1; LAYOUT.block_bytes()];
+            source.write_block(block, &data).unwrap();
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let cancel_token = CancellationToken::new();
+        let server = Arc::new(TransferServer::new(1, source));
+        tokio::spawn(server.serve(listener, cancel_token.clone()));
+
+        let sink = HostBlocks::new(LAYOUT, 2);
+        let mut client = TransferClient::connect(address, Hello::new(2, LAYOUT), None)
+            .await
+            .unwrap();
+        assert_eq!(client.backend(), Backend::Tcp);
+
+        // Blocks 3 and 1 of the server into blocks 0 and 1 of the client
+        let event = client.pull(&[3, 1], &[0, 1], &sink).await.unwrap();
+        assert_eq!(
+            event.status,
+            TransferStatus::Completed {
+                blocks: 2,
+                bytes: 2 * LAYOUT.block_bytes() as u64
+            }
+        );
+        let mut block = vec![0; LAYOUT.block_bytes()];
+        sink.read_block(0, &mut block).unwrap();
+        assert!(block.iter().all(|byte| *byte == 4));
+        sink.read_block(1, &mut block).unwrap();
+        assert!(block.iter().all(|byte| *byte == 2));
+
+        // A block the server doesn't have fails the transfer, not the connection
+        let event = client.pull(&[9], &[0], &sink).await.unwrap();
+        assert!(matches!(event.status, TransferStatus::Failed { .. }));
+        client.pull(&[0], &[0], &sink).await.unwrap();
+
+        // Workers with other layouts are turned away
+        let other = LayoutDescriptor {
+            page_size: 16,
+            ..LAYOUT
+        };
+        assert!(TransferClient::connect(address, Hello::new(3, other), None)
+            .await
+            .is_err());
+        cancel_token.cancel();
+    }
+
+    /// Fails writing one block
+    struct FailingSink {
+        blocks: HostBlocks,
+        fail: usize,
+    }
+
+    impl BlockSink for FailingSink {
+        fn layout(&self) -> &LayoutDescriptor {
+            &self.blocks.layout
+        }
+
+        fn write_block(&self, block: usize, data: &[u8]) -> anyhow::Result<()> {
+            anyhow::ensure!(block != self.fail, "Block {block} is broken");
+            self.blocks.write_block(block, data)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyed_transfer() {
+        let source = Arc::new(HostBlocks::new(LAYOUT, 4));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let cancel_token = CancellationToken::new();
+        let key = TransferKey::from_cluster_key(&[1; 32]);
+        let server = Arc::new(TransferServer::new(1, source).with_key(Some(key.clone())));
+        tokio::spawn(server.serve(listener, cancel_token.clone()));
+
+        // Without the key, or with another one, there is no connection
+        assert!(
+            TransferClient::connect(address, Hello::new(2, LAYOUT), None)
+                .await
+                .is_err()
+        );
+        let other = TransferKey::from_cluster_key(&[2; 32]);
+        assert!(
+            TransferClient::connect(address, Hello::new(2, LAYOUT), Some(&other))
+                .await
+                .is_err()
+        );
+
+        let mut client = TransferClient::connect(address, Hello::new(2, LAYOUT), Some(&key))
+            .await
+            .unwrap();
+        // A sink that fails fails the transfer, and the connection can still be used
+        let sink = FailingSink {
+            blocks: HostBlocks::new(LAYOUT, 3),
+            fail: 1,
+        };
+        assert!(client.pull(&[0, 1, 2], &[0, 1, 2], &sink).await.is_err());
+        let event = client.pull(&[3], &[2], &sink).await.unwrap();
+        assert!(matches!(event.status, TransferStatus::Completed { .. }));
+        cancel_token.cancel();
+    }
+}
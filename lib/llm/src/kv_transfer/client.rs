@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The side of a KV block transfer that wants the blocks
+
+use anyhow::Context as _;
+use tokio::io::BufStream;
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::protocol::{
+    self, expect_frame, write_frame, Backend, Hello, LayoutDescriptor, Message, Role,
+    TransferEvent, TransferKey, TransferRequest, TransferStatus,
+};
+use super::BlockSink;
+
+/// A connection to the [`super::TransferServer`] of a worker, see the [module docs](super).
+/// Transfers on a connection run one after the other, open more connections to run them at once.
+pub struct TransferClient {
+    stream: BufStream<TcpStream>,
+    backend: Backend,
+    layout: LayoutDescriptor,
+    /// The server's hello
+    peer: Hello,
+    /// Set when a transfer failed midway, the connection is out of step with the server
+    broken: bool,
+}
+
+impl TransferClient {
+    /// Connect to the server at `address` and negotiate the backend with our `hello`. With a
+    /// `key`, the server must have the same cluster key, see [`TransferKey`].
+    pub async fn connect(
+        address: impl ToSocketAddrs,
+        hello: Hello,
+        key: Option<&TransferKey>,
+    ) -> anyhow::Result<Self> {
+        let stream = tokio::time::timeout(protocol::FRAME_TIMEOUT, TcpStream::connect(address))
+            .await
+            .context("Timed out connecting to KV transfer server")?
+            .context("Failed connecting to KV transfer server")?;
+        stream.set_nodelay(true)?;
+        let mut stream = BufStream::new(stream);
+        let hello = hello.with_nonce_for(key);
+        write_frame(&mut stream, &Message::Hello(hello.clone()), &[]).await?;
+        let peer = match expect_frame(&mut stream, 0).await? {
+            Some((Message::Hello(peer), _)) => peer,
+            Some((Message::Rejected { reason }, _)) => {
+                anyhow::bail!("KV transfer server rejected us: {reason}")
+            }
+            Some((message, _)) => anyhow::bail!("Expected a hello, got {message:?}"),
+            None => anyhow::bail!("KV transfer server closed the connection"),
+        };
+        let backend = protocol::negotiate(&hello, &peer)?;
+        if let Some(key) = key {
+            let message = expect_frame(&mut stream, 0)
+                .await?
+                .map(|(message, _)| message);
+            key.verify(Role::Server, &hello, &peer, message)
+                .context("KV transfer server failed to authenticate")?;
+            let proof = key.prove(Role::Client, &hello, &peer);
+            write_frame(&mut stream, &proof, &[]).await?;
+        }
+        Ok(TransferClient {
+            stream,
+            backend,
+            layout: hello.layout,
+            peer,
+            broken: false,
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The server's hello, with its worker ID and NIXL block set
+    pub fn peer(&self) -> &Hello {
+        &self.peer
+    }
+
+    /// Copy the blocks `src` of the server into the blocks `dst` of `sink`, over
+    /// [`Backend::Tcp`]. The blocks of `dst` are undefined after a failed transfer. If `sink`
+    /// fails, the rest of the transfer is read and dropped, and the connection can be used
+    /// again. Other errors leave the connection broken, connect again.
+    pub async fn pull(
+        &mut self,
+        src: &[usize],
+        dst: &[usize],
+        sink: &dyn BlockSink,
+    ) -> anyhow::Result<TransferEvent> {
+        self.ensure_usable()?;
+        anyhow::ensure!(
+            self.backend == Backend::Tcp,
+            "The connection uses {}, not tcp",
+            self.backend.as_str()
+        );
+        anyhow::ensure!(
+            src.len() == dst.len(),
+            "{} source blocks for {} destination blocks",
+            src.len(),
+            dst.len()
+        );
+        anyhow::ensure!(
+            *sink.layout() == self.layout,
+            "The sink's block layout is not the connection's"
+        );
+        self.broken = true;
+        let transfer_id = self.request(src, None).await?;
+        let block_bytes = self.layout.block_bytes();
+        let mut received = 0;
+        // The first failure of the sink, the blocks after it are dropped
+        let mut sink_error = None;
+        loop {
+            let Some((message, payload)) = expect_frame(&mut self.stream, block_bytes).await?
+            else {
+                anyhow::bail!(
+                    "KV transfer server closed the connection during transfer {transfer_id}"
+                );
+            };
+            match message {
+                Message::Block {
+                    transfer_id: id,
+                    index,
+                } if id == transfer_id && index == received && index < dst.len() => {
+                    anyhow::ensure!(payload.len() == block_bytes, "Block {index} is truncated");
+                    if sink_error.is_none() {
+                        sink_error = sink.write_block(dst[index], &payload).err();
+                    }
+                    received += 1;
+                }
+                Message::Event(event) if event.transfer_id == transfer_id => {
+                    // In step with the server again
+                    self.broken = false;
+                    if let Some(err) = sink_error {
+                        return Err(err.context(format!(
+                            "Failed writing the blocks of transfer {transfer_id}"
+                        )));
+                    }
+                    if let TransferStatus::Completed { blocks, .. } = event.status {
+                        anyhow::ensure!(
+                            blocks == received,
+                            "Transfer {transfer_id} completed with {received} of {blocks} blocks"
+                        );
+                    }
+                    return Ok(event);
+                }
+                message => {
+                    anyhow::bail!("Unexpected message in transfer {transfer_id}: {message:?}")
+                }
+            }
+        }
+    }
+
+    fn ensure_usable(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.broken,
+            "An earlier transfer failed midway, the KV transfer connection must be reopened"
+        );
+        Ok(())
+    }
+
+    /// Have the server write its blocks `src` into our blocks described by `descriptors`, over
+    /// [`Backend::Nixl`]. The blocks are in place once the event completes.
+    pub async fn pull_nixl(
+        &mut self,
+        src: &[usize],
+        descriptors: serde_json::Value,
+    ) -> anyhow::Result<TransferEvent> {
+        self.ensure_usable()?;
+        anyhow::ensure!(
+            self.backend == Backend::Nixl,
+            "The connection uses {}, not nixl",
+            self.backend.as_str()
+        );
+        self.broken = true;
+        let transfer_id = self.request(src, Some(descriptors)).await?;
+        match expect_frame(&mut self.stream, 0).await? {
+            Some((Message::Event(event), _)) if event.transfer_id == transfer_id => {
+                self.broken = false;
+                Ok(event)
+            }
+            Some((message, _)) => {
+                anyhow::bail!("Unexpected message in transfer {transfer_id}: {message:?}")
+            }
+            None => anyhow::bail!(
+                "KV transfer server closed the connection during transfer {transfer_id}"
+            ),
+        }
+    }
+
+    async fn request(
+        &mut self,
+        src: &[usize],
+        nixl_descriptors: Option<serde_json::Value>,
+    ) -> anyhow::Result<String> {
+        let request = TransferRequest {
+            transfer_id: uuid::Uuid::new_v4().to_string(),
+            src_blocks: src.to_vec(),
+            nixl_descriptors,
+        };
+        let transfer_id = request.transfer_id.clone();
+        write_frame(&mut self.stream, &Message::Transfer(request), &[]).await?;
+        Ok(transfer_id)
+    }
+}
@@ -0,0 +1,142 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! [`Backend::Nixl`](super::Backend::Nixl) with the blocks of a [`KvBlockManager`].
+//!
+//! The block managers of both workers exchange their NIXL block sets in the [`Hello`]s. The
+//! server writes the blocks it [exposed](NixlBlocks::expose) into the blocks of the client
+//! described by [`describe`], with the UCX backend of its NIXL agent.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+
+use super::{Hello, LayoutDescriptor, NixlSource};
+use crate::block_manager::block::{
+    transfer::{TransferContext, WriteTo},
+    MutableBlock,
+};
+use crate::block_manager::{
+    BlockDescriptorList, BlockMetadata, IsMutable, KvBlockManager, LayoutConfig, RemoteBlock,
+    Storage,
+};
+
+impl From<&LayoutConfig> for LayoutDescriptor {
+    fn from(config: &LayoutConfig) -> Self {
+        LayoutDescriptor {
+            num_layers: config.num_layers,
+            outer_dim: config.outer_dim,
+            page_size: config.page_size,
+            inner_dim: config.inner_dim,
+            dtype: config.dtype,
+        }
+    }
+}
+
+/// The descriptors of the client's `blocks` the server writes into, for
+/// [`TransferClient::pull_nixl`](super::TransferClient::pull_nixl)
+pub fn describe<S: Storage, M: BlockMetadata>(
+    blocks: &[MutableBlock<S, M>],
+) -> anyhow::Result<serde_json::Value> {
+    let descriptors = BlockDescriptorList::from_mutable_blocks(blocks)?;
+    Ok(serde_json::to_value(descriptors)?)
+}
+
+/// The blocks of a [`KvBlockManager`] that clients may take, `RB` is their block type
+pub struct NixlBlocks<M: BlockMetadata, RB> {
+    manager: Arc<KvBlockManager<M>>,
+    ctx: Arc<TransferContext>,
+    /// By index in the layout
+    exposed: Mutex<HashMap<usize, Arc<RB>>>,
+    /// The workers whose block sets were imported
+    imported: Mutex<HashSet<u64>>,
+}
+
+impl<M: BlockMetadata, RB> NixlBlocks<M, RB> {
+    pub fn new(manager: Arc<KvBlockManager<M>>, ctx: Arc<TransferContext>) -> Self {
+        NixlBlocks {
+            manager,
+            ctx,
+            exposed: Mutex::new(HashMap::new()),
+            imported: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Let clients take `blocks`, by their index in the layout. They are held until
+    /// [withdrawn](Self::withdraw).
+    pub fn expose(&self, blocks: impl IntoIterator<Item = (usize, Arc<RB>)>) {
+        self.exposed.lock().unwrap().extend(blocks);
+    }
+
+    /// Release `blocks`, e.g. once the client's transfer completed
+    pub fn withdraw(&self, blocks: &[usize]) {
+        let mut exposed = self.exposed.lock().unwrap();
+        for block in blocks {
+            exposed.remove(block);
+        }
+    }
+}
+
+#[async_trait]
+impl<M, RB> NixlSource for NixlBlocks<M, RB>
+where
+    M: BlockMetadata,
+    RB: Send + Sync + 'static,
+    Vec<Arc<RB>>: WriteTo<RemoteBlock<IsMutable>>,
+{
+    fn blockset(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::to_value(self.manager.export_local_blockset()?)?)
+    }
+
+    fn import_peer(&self, hello: &Hello) -> anyhow::Result<()> {
+        let mut imported = self.imported.lock().unwrap();
+        // The block manager takes the block set of a worker once
+        if imported.contains(&hello.worker_id) {
+            return Ok(());
+        }
+        let blockset = hello
+            .nixl_blockset
+            .clone()
+            .context("The peer sent no NIXL block set")?;
+        self.manager
+            .import_remote_blockset(serde_json::from_value(blockset)?)
+            .with_context(|| {
+                format!("Failed importing the blocks of worker {}", hello.worker_id)
+            })?;
+        imported.insert(hello.worker_id);
+        Ok(())
+    }
+
+    async fn write_blocks(
+        &self,
+        src: &[usize],
+        dst: &serde_json::Value,
+        notify: &str,
+    ) -> anyhow::Result<()> {
+        let sources: Vec<Arc<RB>> = {
+            let exposed = self.exposed.lock().unwrap();
+            src.iter()
+                .map(|block| {
+                    exposed
+                        .get(block)
+                        .cloned()
+                        .with_context(|| format!("Block {block} is not exposed"))
+                })
+                .collect::<anyhow::Result<_>>()?
+        };
+        let descriptors: BlockDescriptorList = serde_json::from_value(dst.clone())?;
+        let mut targets = self.manager.get_remote_blocks_mutable(&descriptors)?;
+        anyhow::ensure!(
+            targets.len() == sources.len(),
+            "{} source blocks for {} destination blocks",
+            sources.len(),
+            targets.len()
+        );
+        let done =
+            sources.nixl_write_to(&mut targets, Some(notify.to_string()), self.ctx.clone())?;
+        done.await;
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The messages of the KV block transfer protocol and how they are framed on the wire.
+//!
+//! A frame is the length of a JSON [`Message`] as a big endian `u32`, the message, then the length
+//! of its binary payload as a big endian `u32` and the payload. Only [`Message::Block`] has a
+//! payload, the bytes of one block.
+//!
+//! With a cluster key, the [`Hello`]s carry a random nonce each and both sides then send a
+//! [`Message::Auth`] with a MAC of the handshake, see [`TransferKey`]. A side without the key
+//! can't connect to one with it.
+
+use std::time::Duration;
+
+use anyhow::Context as _;
+use dynamo_runtime::control_signing::{ControlSigner, ControlSigningConfig};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::common::dtype::DType;
+
+/// Bumped on incompatible changes of the messages
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// How long a frame may take to arrive once it started, or to be sent, and how long a side waits
+/// for the frames it expects: the handshake, and the blocks and event of a transfer
+pub const FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Context of the [`blake3::derive_key`] of a [`TransferKey`] from the cluster key
+const KEY_CONTEXT: &str = "dynamo 2025 KV transfer handshake";
+
+/// Largest JSON message, the NIXL block set of a worker included
+const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// How the blocks are moved
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The sender writes into the memory of the receiver with NIXL, over UCX (RDMA, NVLink)
+    Nixl,
+    /// The sender copies the blocks into the control connection
+    Tcp,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Nixl => "nixl",
+            Backend::Tcp => "tcp",
+        }
+    }
+}
+
+/// The shape of the KV blocks of a worker. Blocks only move between workers with the same layout.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutDescriptor {
+    pub num_layers: usize,
+    /// 2 with separate K and V, 1 otherwise
+    pub outer_dim: usize,
+    /// Tokens per block
+    pub page_size: usize,
+    /// Elements per token, per layer and outer dimension
+    pub inner_dim: usize,
+    pub dtype: DType,
+}
+
+impl LayoutDescriptor {
+    /// Bytes of one block
+    pub fn block_bytes(&self) -> usize {
+        self.num_layers
+            * self.outer_dim
+            * self.page_size
+            * self.inner_dim
+            * self.dtype.size_in_bytes()
+    }
+}
+
+/// First message of each side. The client sends its own, the server answers with its own or
+/// [`Message::Rejected`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    pub worker_id: u64,
+    pub layout: LayoutDescriptor,
+    /// The backends this worker can use, the preferred one first
+    pub backends: Vec<Backend>,
+    /// The serialized NIXL block set of the worker, for the peer to import. With
+    /// [`Backend::Nixl`] only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixl_blockset: Option<serde_json::Value>,
+    /// Random, if the worker has a [`TransferKey`] and the peer must prove it has it too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+impl Hello {
+    pub fn new(worker_id: u64, layout: LayoutDescriptor) -> Self {
+        Hello {
+            version: PROTOCOL_VERSION,
+            worker_id,
+            layout,
+            backends: vec![Backend::Tcp],
+            nixl_blockset: None,
+            nonce: None,
+        }
+    }
+
+    /// A nonce for the handshake, if there is a key to prove
+    pub(crate) fn with_nonce_for(mut self, key: Option<&TransferKey>) -> Self {
+        self.nonce = key.map(|_| uuid::Uuid::new_v4().to_string());
+        self
+    }
+
+    /// Offer NIXL ahead of TCP, with the worker's block set
+    pub fn with_nixl(mut self, blockset: serde_json::Value) -> Self {
+        self.backends = vec![Backend::Nixl, Backend::Tcp];
+        self.nixl_blockset = Some(blockset);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NegotiationError {
+    #[error("Protocol version {0} is not supported, this worker speaks {PROTOCOL_VERSION}")]
+    Version(u32),
+    #[error("Block layouts differ: {0:?} and {1:?}")]
+    Layout(LayoutDescriptor, LayoutDescriptor),
+    #[error("No common backend between {0:?} and {1:?}")]
+    NoCommonBackend(Vec<Backend>, Vec<Backend>),
+    #[error("Only one side has the cluster key")]
+    Key,
+}
+
+/// The backend of a connection: the first of the client's that the server has too. Both sides
+/// compute it from the two [`Hello`]s.
+pub fn negotiate(client: &Hello, server: &Hello) -> Result<Backend, NegotiationError> {
+    for hello in [client, server] {
+        if hello.version != PROTOCOL_VERSION {
+            return Err(NegotiationError::Version(hello.version));
+        }
+    }
+    if client.nonce.is_some() != server.nonce.is_some() {
+        return Err(NegotiationError::Key);
+    }
+    if client.layout != server.layout {
+        return Err(NegotiationError::Layout(client.layout, server.layout));
+    }
+    client
+        .backends
+        .iter()
+        .copied()
+        .find(|backend| {
+            server.backends.contains(backend)
+                && (*backend != Backend::Nixl
+                    || (client.nixl_blockset.is_some() && server.nixl_blockset.is_some()))
+        })
+        .ok_or_else(|| {
+            NegotiationError::NoCommonBackend(client.backends.clone(), server.backends.clone())
+        })
+}
+
+/// Sent by the client, the receiver of the blocks
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferRequest {
+    pub transfer_id: String,
+    /// The blocks of the server to send, by index in its layout
+    pub src_blocks: Vec<usize>,
+    /// With [`Backend::Nixl`], the descriptors of the client's blocks the server writes into, as
+    /// many as `src_blocks`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixl_descriptors: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TransferStatus {
+    Completed { blocks: usize, bytes: u64 },
+    Failed { error: String },
+}
+
+/// Sent by the server when a transfer is over. With [`Backend::Nixl`] the blocks are in the
+/// client's memory once it arrives.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransferEvent {
+    pub transfer_id: String,
+    #[serde(flatten)]
+    pub status: TransferStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Hello(Hello),
+    /// The server can't serve this client
+    Rejected {
+        reason: String,
+    },
+    Transfer(TransferRequest),
+    /// The `index`th block of a transfer over [`Backend::Tcp`], in the payload
+    Block {
+        transfer_id: String,
+        index: usize,
+    },
+    Event(TransferEvent),
+    /// Proves that the sender has the [`TransferKey`], after the [`Hello`]s
+    Auth {
+        mac: String,
+    },
+}
+
+/// Which side of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Client,
+    Server,
+}
+
+/// Authenticates the handshake of a connection, derived from the cluster key of
+/// [`ControlSigner`]. Each side sends a keyed BLAKE3 MAC of its role and both [`Hello`]s, with
+/// their fresh nonces, so a MAC can't be replayed on another connection or by the other side.
+#[derive(Clone)]
+pub struct TransferKey([u8; 32]);
+
+impl std::fmt::Debug for TransferKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never the key
+        f.debug_struct("TransferKey").finish_non_exhaustive()
+    }
+}
+
+impl TransferKey {
+    pub fn from_cluster_key(cluster_key: &[u8; 32]) -> Self {
+        TransferKey(blake3::derive_key(KEY_CONTEXT, cluster_key))
+    }
+
+    /// The key of the cluster key `config` points to, None if it has no key file
+    pub fn load(config: &ControlSigningConfig) -> anyhow::Result<Option<Self>> {
+        Ok(ControlSigner::load_key(config)?.map(|key| Self::from_cluster_key(&key)))
+    }
+
+    fn mac(&self, role: Role, client: &Hello, server: &Hello) -> blake3::Hash {
+        let role = match role {
+            Role::Client => "client",
+            Role::Server => "server",
+        };
+        let mut hasher = blake3::Hasher::new_keyed(&self.0);
+        for part in [
+            role,
+            client.nonce.as_deref().unwrap_or_default(),
+            server.nonce.as_deref().unwrap_or_default(),
+            &client.worker_id.to_string(),
+            &server.worker_id.to_string(),
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update(b"\0");
+        }
+        hasher.finalize()
+    }
+
+    /// The [`Message::Auth`] of `role` for the handshake of `client` and `server`
+    pub(crate) fn prove(&self, role: Role, client: &Hello, server: &Hello) -> Message {
+        Message::Auth {
+            mac: self.mac(role, client, server).to_hex().to_string(),
+        }
+    }
+
+    /// Check the [`Message::Auth`] of the peer, in `role`
+    pub(crate) fn verify(
+        &self,
+        role: Role,
+        client: &Hello,
+        server: &Hello,
+        message: Option<Message>,
+    ) -> anyhow::Result<()> {
+        let Some(Message::Auth { mac }) = message else {
+            anyhow::bail!("KV transfer peer didn't authenticate");
+        };
+        let mac = blake3::Hash::from_hex(mac).context("Malformed KV transfer MAC")?;
+        // Hash equality is constant time
+        anyhow::ensure!(
+            mac == self.mac(role, client, server),
+            "KV transfer peer has another cluster key"
+        );
+        Ok(())
+    }
+}
+
+/// Read the frame of the next message the peer must send, within [`FRAME_TIMEOUT`]
+pub async fn expect_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_payload: usize,
+) -> anyhow::Result<Option<(Message, Vec<u8>)>> {
+    tokio::time::timeout(FRAME_TIMEOUT, read_frame(reader, max_payload))
+        .await
+        .context("KV transfer peer timed out")?
+}
+
+/// Write a frame of `message` and `payload`, within [`FRAME_TIMEOUT`]
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    let message = serde_json::to_vec(message)?;
+    let write = async {
+        writer.write_u32(message.len().try_into()?).await?;
+        writer.write_all(&message).await?;
+        writer.write_u32(payload.len().try_into()?).await?;
+        writer.write_all(payload).await?;
+        writer.flush().await?;
+        anyhow::Ok(())
+    };
+    tokio::time::timeout(FRAME_TIMEOUT, write)
+        .await
+        .context("KV transfer peer stopped reading")?
+}
+
+/// Read a frame, with a payload of at most `max_payload` bytes. None when the peer closed the
+/// connection between frames. Waits for a frame as long as it takes, but once it started the rest
+/// must arrive within [`FRAME_TIMEOUT`].
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_payload: usize,
+) -> anyhow::Result<Option<(Message, Vec<u8>)>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    anyhow::ensure!(
+        len <= MAX_MESSAGE_BYTES,
+        "KV transfer message of {len} bytes is too large"
+    );
+    let read = async {
+        let mut message = vec![0; len];
+        reader.read_exact(&mut message).await?;
+        let message = serde_json::from_slice(&message)?;
+        let len = reader.read_u32().await? as usize;
+        anyhow::ensure!(
+            len <= max_payload,
+            "KV transfer payload of {len} bytes is larger than {max_payload}"
+        );
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).await?;
+        Ok(Some((message, payload)))
+    };
+    tokio::time::timeout(FRAME_TIMEOUT, read)
+        .await
+        .context("KV transfer frame timed out")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> LayoutDescriptor {
+        LayoutDescriptor {
+            num_layers: 2,
+            outer_dim: 2,
+            page_size: 4,
+            inner_dim: 8,
+            dtype: DType::FP16,
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let tcp = Hello::new(1, layout());
+        let nixl = Hello::new(2, layout()).with_nixl(serde_json::json!({}));
+        assert_eq!(negotiate(&nixl, &nixl), Ok(Backend::Nixl));
+        assert_eq!(negotiate(&nixl, &tcp), Ok(Backend::Tcp));
+        assert_eq!(negotiate(&tcp, &nixl), Ok(Backend::Tcp));
+
+        // NIXL without a block set to import is no use
+        let no_blockset = Hello {
+            backends: vec![Backend::Nixl],
+            ..Hello::new(3, layout())
+        };
+        assert!(matches!(
+            negotiate(&no_blockset, &nixl),
+            Err(NegotiationError::NoCommonBackend(..))
+        ));
+
+        let other = Hello::new(
+            4,
+            LayoutDescriptor {
+                page_size: 16,
+                ..layout()
+            },
+        );
+        assert!(matches!(
+            negotiate(&tcp, &other),
+            Err(NegotiationError::Layout(..))
+        ));
+        let future = Hello {
+            version: PROTOCOL_VERSION + 1,
+            ..Hello::new(5, layout())
+        };
+        assert_eq!(
+            negotiate(&future, &tcp),
+            Err(NegotiationError::Version(PROTOCOL_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let key = TransferKey::from_cluster_key(&[7; 32]);
+        let client = Hello::new(1, layout()).with_nonce_for(Some(&key));
+        let server = Hello::new(2, layout()).with_nonce_for(Some(&key));
+        assert_eq!(negotiate(&client, &server), Ok(Backend::Tcp));
+
+        let proof = key.prove(Role::Client, &client, &server);
+        key.verify(Role::Client, &client, &server, Some(proof.clone()))
+            .unwrap();
+        // Not as the other side, on another connection or with another key
+        assert!(key
+            .verify(Role::Server, &client, &server, Some(proof.clone()))
+            .is_err());
+        let other_server = Hello::new(2, layout()).with_nonce_for(Some(&key));
+        assert!(key
+            .verify(Role::Client, &client, &other_server, Some(proof.clone()))
+            .is_err());
+        let other_key = TransferKey::from_cluster_key(&[8; 32]);
+        assert!(other_key
+            .verify(Role::Client, &client, &server, Some(proof))
+            .is_err());
+        assert!(key.verify(Role::Client, &client, &server, None).is_err());
+
+        let unkeyed = Hello::new(3, layout());
+        assert_eq!(negotiate(&unkeyed, &server), Err(NegotiationError::Key));
+    }
+
+    #[tokio::test]
+    async fn test_frames() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let block = Message::Block {
+            transfer_id: "t".to_string(),
+            index: 3,
+        };
+        write_frame(&mut client, &block, b"abcd").await.unwrap();
+        let event = Message::Event(TransferEvent {
+            transfer_id: "t".to_string(),
+            status: TransferStatus::Completed {
+                blocks: 1,
+                bytes: 4,
+            },
+        });
+        write_frame(&mut client, &event, &[]).await.unwrap();
+
+        assert_eq!(
+            read_frame(&mut server, 4).await.unwrap(),
+            Some((block.clone(), b"abcd".to_vec()))
+        );
+        assert_eq!(
+            read_frame(&mut server, 4).await.unwrap(),
+            Some((event, vec![]))
+        );
+        drop(client);
+        assert_eq!(read_frame(&mut server, 4).await.unwrap(), None);
+
+        // Payloads larger than a block are refused
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        write_frame(&mut client, &block, b"abcdef").await.unwrap();
+        assert!(read_frame(&mut server, 4).await.is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The side of a KV block transfer that has the blocks
+
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use tokio::io::BufStream;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use super::protocol::{
+    self, expect_frame, read_frame, write_frame, Backend, Hello, Message, Role, TransferEvent,
+    TransferKey, TransferRequest, TransferStatus,
+};
+use super::{BlockSource, NixlSource};
+
+/// Serves the blocks of a worker to the workers that connect, see the [module docs](super)
+pub struct TransferServer {
+    worker_id: u64,
+    source: Arc<dyn BlockSource>,
+    nixl: Option<Arc<dyn NixlSource>>,
+    key: Option<TransferKey>,
+}
+
+impl TransferServer {
+    pub fn new(worker_id: u64, source: Arc<dyn BlockSource>) -> Self {
+        TransferServer {
+            worker_id,
+            source,
+            nixl: None,
+            key: None,
+        }
+    }
+
+    /// Only serve the clients that have the same cluster key, see [`TransferKey`]
+    pub fn with_key(mut self, key: Option<TransferKey>) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Write the blocks with NIXL to the clients that can take them that way
+    pub fn with_nixl(mut self, nixl: Arc<dyn NixlSource>) -> Self {
+        self.nixl = Some(nixl);
+        self
+    }
+
+    fn hello(&self) -> anyhow::Result<Hello> {
+        let hello =
+            Hello::new(self.worker_id, *self.source.layout()).with_nonce_for(self.key.as_ref());
+        match &self.nixl {
+            Some(nixl) => Ok(hello.with_nixl(nixl.blockset()?)),
+            None => Ok(hello),
+        }
+    }
+
+    /// Accept connections on `listener` until `cancel_token` is cancelled
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        cancel_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        loop {
+            let (stream, peer) = tokio::select! {
+                _ = cancel_token.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted?,
+            };
+            let this = self.clone();
+            let cancel_token = cancel_token.child_token();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => {}
+                    result = this.handle(stream) => {
+                        if let Err(err) = result {
+                            tracing::warn!(%peer, error = format!("{err:#}"), "KV transfer connection failed");
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut stream = BufStream::new(stream);
+        let Some((Message::Hello(client), _)) = expect_frame(&mut stream, 0).await? else {
+            anyhow::bail!("KV transfer client didn't start with a hello");
+        };
+        let hello = self.hello()?;
+        let backend = match protocol::negotiate(&client, &hello) {
+            Ok(backend) => backend,
+            Err(err) => {
+                let reason = err.to_string();
+                write_frame(&mut stream, &Message::Rejected { reason }, &[]).await?;
+                return Err(err).context(format!("Rejected worker {}", client.worker_id));
+            }
+        };
+        write_frame(&mut stream, &Message::Hello(hello.clone()), &[]).await?;
+        if let Some(key) = &self.key {
+            let proof = key.prove(Role::Server, &client, &hello);
+            write_frame(&mut stream, &proof, &[]).await?;
+            let message = expect_frame(&mut stream, 0)
+                .await?
+                .map(|(message, _)| message);
+            key.verify(Role::Client, &client, &hello, message)
+                .with_context(|| format!("Worker {} failed to authenticate", client.worker_id))?;
+        }
+        if backend == Backend::Nixl {
+            // negotiate only picks NIXL when we have it
+            if let Some(nixl) = &self.nixl {
+                nixl.import_peer(&client)?;
+            }
+        }
+        tracing::debug!(
+            worker_id = client.worker_id,
+            backend = backend.as_str(),
+            "KV transfer connection"
+        );
+
+        while let Some((message, _)) = read_frame(&mut stream, 0).await? {
+            let Message::Transfer(request) = message else {
+                anyhow::bail!("Expected a transfer request, got {message:?}");
+            };
+            let transfer_id = request.transfer_id.clone();
+            let status = match backend {
+                Backend::Tcp => self.send_blocks(&mut stream, &request).await,
+                Backend::Nixl => self.write_blocks(&request).await,
+            }
+            .unwrap_or_else(|err| TransferStatus::Failed {
+                error: format!("{err:#}"),
+            });
+            let event = TransferEvent {
+                transfer_id,
+                status,
+            };
+            write_frame(&mut stream, &Message::Event(event), &[]).await?;
+        }
+        Ok(())
+    }
+
+    /// Copy the blocks of `request` into the connection
+    async fn send_blocks(
+        &self,
+        stream: &mut BufStream<TcpStream>,
+        request: &TransferRequest,
+    ) -> anyhow::Result<TransferStatus> {
+        // On failure the blocks sent so far are of no use to the client, it gets a failed event
+        let mut block = vec![0; self.source.layout().block_bytes()];
+        for (index, src) in request.src_blocks.iter().enumerate() {
+            self.source.read_block(*src, &mut block)?;
+            let message = Message::Block {
+                transfer_id: request.transfer_id.clone(),
+                index,
+            };
+            write_frame(stream, &message, &block).await?;
+        }
+        Ok(TransferStatus::Completed {
+            blocks: request.src_blocks.len(),
+            bytes: (block.len() * request.src_blocks.len()) as u64,
+        })
+    }
+
+    async fn write_blocks(&self, request: &TransferRequest) -> anyhow::Result<TransferStatus> {
+        let nixl = self.nixl.as_ref().context("NIXL is not enabled")?;
+        let dst = request
+            .nixl_descriptors
+            .as_ref()
+            .context("NIXL transfer request without descriptors")?;
+        nixl.write_blocks(&request.src_blocks, dst, &request.transfer_id)
+            .await?;
+        Ok(TransferStatus::Completed {
+            blocks: request.src_blocks.len(),
+            bytes: (self.source.layout().block_bytes() * request.src_blocks.len()) as u64,
+        })
+    }
+}
@@ -10,24 +10,39 @@ use std::{fs::File, io::BufReader, path::Path};
 
 use anyhow::Context as _;
 
+pub mod audit;
 pub mod backend;
+pub mod budget_router;
+pub mod build_info;
 pub mod common;
 pub mod disagg_router;
 pub mod discovery;
+pub mod embedding_router;
 pub mod engines;
 pub mod gguf;
+pub mod grpc;
 pub mod http;
 pub mod hub;
 pub mod key_value_store;
+pub mod kv_transfer;
 pub mod kv_router;
 pub mod local_model;
+pub mod lora;
+pub mod metrics_recorder;
 pub mod mocker;
 pub mod model_card;
 pub mod model_type;
+pub mod prefill_queue;
+pub mod prefill_router;
 pub mod preprocessor;
 pub mod protocols;
 pub mod recorder;
+pub mod request_log;
 pub mod request_template;
+pub mod reschedule;
+pub mod response_tee;
+pub mod speculative;
+pub mod token_timing;
 pub mod tokenizers;
 pub mod tokens;
 pub mod types;
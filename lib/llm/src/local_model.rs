@@ -1,16 +1,28 @@
 // SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::time::Duration;
 
-use dynamo_runtime::component::{Component, Endpoint};
+use anyhow::Context as _;
+use dynamo_runtime::component::{Component, Endpoint, Instance, TransportType};
+use dynamo_runtime::pipeline::network::ingress::push_endpoint::PROBE_HEADER;
 use dynamo_runtime::traits::DistributedRuntimeProvider;
+use dynamo_runtime::transports::etcd;
 
 use crate::discovery::ModelEntry;
-use crate::key_value_store::{EtcdStorage, KeyValueStore, KeyValueStoreManager};
-use crate::model_card::{self, ModelDeploymentCard};
+use crate::key_value_store::{
+    same_content, EtcdStorage, KeyValueStore, KeyValueStoreManager, Versioned,
+};
+use crate::model_card::{
+    self,
+    model::{
+        EngineCapabilities, GenerationLimits, PromptCompression, SamplingPresets, SpecialTokens,
+    },
+    ModelDeploymentCard,
+};
 use crate::model_type::ModelType;
 
 mod network_name;
@@ -23,6 +35,43 @@ const HF_SCHEME: &str = "hf://";
 /// is invisible, for example in a text chat.
 const DEFAULT_NAME: &str = "dynamo";
 
+/// How often to try registering when other workers keep publishing the same model card
+const REGISTER_ATTEMPTS: usize = 3;
+
+/// How often [`LocalModel::verify_routable`] retries
+const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the instances of another model get to answer before [`DuplicateRegistration::TakeOver`]
+/// checks their leases
+const TAKE_OVER_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most versioned components [`DuplicateRegistration::Coexist`] tries
+const MAX_COMPONENT_VERSIONS: u32 = 100;
+
+/// What to do when the component a model is served on already serves another model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateRegistration {
+    /// Refuse to register
+    #[default]
+    Fail,
+    /// Remove the registrations of the other model whose instances are gone, e.g. after an
+    /// unclean shutdown: they don't answer and their etcd lease expired. Fails if one of them
+    /// still answers or holds its lease.
+    TakeOver,
+    /// Serve on the first `{component}-v{n}` component that is free or serves this model, from
+    /// n = 2, next to the other model
+    Coexist,
+}
+
+/// The two halves of a model's registration in etcd
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegistrationPart {
+    /// The model deployment card, shared by the workers of a model
+    Card,
+    /// Our ModelEntry, tied to our lease
+    Entry,
+}
+
 #[derive(Debug, Clone)]
 pub struct LocalModel {
     full_path: PathBuf,
@@ -77,6 +126,36 @@ impl LocalModel {
         self.card.kv_cache_block_size = block_size;
     }
 
+    /// Record engine settings we chose on the user's behalf, so they are visible in the card.
+    pub fn set_inferred_defaults(&mut self, inferred: BTreeMap<String, String>) {
+        self.card.inferred_defaults = inferred;
+    }
+
+    /// Caps on output tokens and generation time, enforced by whichever ingress serves this model
+    pub fn set_generation_limits(&mut self, limits: GenerationLimits) {
+        self.card.generation_limits = limits;
+    }
+
+    /// How whichever ingress serves this model handles its special tokens
+    pub fn set_special_tokens(&mut self, special_tokens: SpecialTokens) {
+        self.card.special_tokens = special_tokens;
+    }
+
+    /// Named sampling parameters requests to this model can pick
+    pub fn set_sampling_presets(&mut self, presets: SamplingPresets) {
+        self.card.sampling_presets = presets;
+    }
+
+    /// How whichever ingress serves this model shortens its prompts
+    pub fn set_prompt_compression(&mut self, compression: PromptCompression) {
+        self.card.prompt_compression = compression;
+    }
+
+    /// What the engine serving this model can't do, so the ingress can remove it from requests
+    pub fn set_engine_capabilities(&mut self, capabilities: EngineCapabilities) {
+        self.card.engine_capabilities = capabilities;
+    }
+
     /// Make an LLM ready for use:
     /// - Download it from Hugging Face (and NGC in future) if necessary
     /// - Resolve the path
@@ -86,6 +165,8 @@ impl LocalModel {
     /// The model name will depend on what "model_path" is:
     /// - A folder: The last part of the folder name: "/data/llms/Qwen2.5-3B-Instruct" -> "Qwen2.5-3B-Instruct"
     /// - A file: The GGUF filename: "/data/llms/Qwen2.5-3B-Instruct-Q6_K.gguf" -> "Qwen2.5-3B-Instruct-Q6_K.gguf"
+    /// - One file of a split GGUF: The filename without the split suffix:
+    ///   "/data/llms/Qwen2.5-72B-Instruct-Q4_K_M-00001-of-00002.gguf" -> "Qwen2.5-72B-Instruct-Q4_K_M.gguf"
     /// - An HF repo: The HF repo name: "Qwen/Qwen2.5-3B-Instruct" stays the same
     pub async fn prepare(
         model_path: &str,
@@ -100,12 +181,16 @@ impl LocalModel {
             model_path.starts_with(HF_SCHEME) || !fs::exists(model_path).unwrap_or(false);
         let relative_path = model_path.trim_start_matches(HF_SCHEME);
 
-        let full_path = if is_hf_repo {
+        let mut full_path = if is_hf_repo {
             // HF download if necessary
             super::hub::from_hf(relative_path).await?
         } else {
             fs::canonicalize(relative_path)?
         };
+        if full_path.is_file() {
+            // Engines load a split GGUF from its first file, and find the others themselves
+            full_path = crate::gguf::gguf_files(&full_path)?.swap_remove(0);
+        }
 
         let model_name = override_name.unwrap_or_else(|| {
             if is_hf_repo {
@@ -115,7 +200,7 @@ impl LocalModel {
                 full_path
                     .iter()
                     .next_back()
-                    .map(|n| n.to_string_lossy().into_owned())
+                    .map(|n| crate::gguf::gguf_model_name(&n.to_string_lossy()))
                     .unwrap_or_else(|| {
                         // Panic because we can't do anything without a model
                         panic!("Invalid model path, too short: '{}'", full_path.display())
@@ -141,6 +226,21 @@ impl LocalModel {
         model_type: ModelType,
     ) -> anyhow::Result<()> {
         // A static component doesn't have an etcd_client because it doesn't need to register
+        let Some(etcd_client) = endpoint.drt().etcd_client() else {
+            anyhow::bail!("Cannot attach to static endpoint");
+        };
+        self.attach_with_lease(endpoint, model_type, etcd_client.lease_id())
+            .await
+    }
+
+    /// [`LocalModel::attach`] with the registration tied to etcd lease `lease_id` instead of the
+    /// primary lease. Revoking the lease unregisters the model, without stopping the process.
+    pub async fn attach_with_lease(
+        &mut self,
+        endpoint: &Endpoint,
+        model_type: ModelType,
+        lease_id: i64,
+    ) -> anyhow::Result<()> {
         let Some(etcd_client) = endpoint.drt().etcd_client() else {
             anyhow::bail!("Cannot attach to static endpoint");
         };
@@ -151,30 +251,286 @@ impl LocalModel {
         let nats_client = endpoint.drt().nats_client();
         self.card.move_to_nats(nats_client.clone()).await?;
 
-        // Publish the Model Deployment Card to etcd
-        let kvstore: Box<dyn KeyValueStore> = Box::new(EtcdStorage::new(etcd_client.clone()));
-        let card_store = Arc::new(KeyValueStoreManager::new(kvstore));
-        let key = self.card.slug().to_string();
-        card_store
-            .publish(model_card::ROOT_PATH, None, &key, &mut self.card)
-            .await?;
-
-        // Publish our ModelEntry to etcd. This allows ingress to find the model card.
+        // Publish the Model Deployment Card and our ModelEntry to etcd. The entry allows ingress
+        // to find the model card, so they are written in one transaction: a failure can't
+        // leave one without the other.
         // (Why don't we put the model card directly under this key?)
-        let network_name = ModelNetworkName::from_local(endpoint, etcd_client.lease_id());
+        let kvstore: Box<dyn KeyValueStore> = Box::new(EtcdStorage::new(etcd_client.clone()));
+        let card_store = KeyValueStoreManager::new(kvstore);
+        let network_name = ModelNetworkName::from_local(endpoint, lease_id);
         tracing::debug!("Registering with etcd as {network_name}");
         let model_registration = ModelEntry {
             name: self.display_name().to_string(),
             endpoint: endpoint.id(),
             model_type,
         };
-        etcd_client
-            .kv_create(
-                network_name.to_string(),
-                serde_json::to_vec_pretty(&model_registration)?,
-                None, // use primary lease
+        self.register(
+            &etcd_client,
+            &card_store,
+            &network_name,
+            &model_registration,
+            lease_id,
+        )
+        .await?;
+
+        // Read both back, so a write that silently went elsewhere fails here. A half that
+        // went missing since, e.g. a card removed by an operator's cleanup, is written again.
+        let missing = self
+            .verify_registration(
+                &etcd_client,
+                &card_store,
+                &network_name,
+                &model_registration,
+            )
+            .await?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        tracing::warn!(%network_name, ?missing, "Registration incomplete, repairing");
+        if missing.contains(&RegistrationPart::Card) {
+            // It doesn't exist, so create it
+            self.card.set_revision(0);
+            card_store
+                .publish(
+                    model_card::ROOT_PATH,
+                    None,
+                    &self.card.slug().to_string(),
+                    &mut self.card,
+                )
+                .await?;
+        }
+        if missing.contains(&RegistrationPart::Entry) {
+            etcd_client
+                .kv_create(
+                    network_name.to_string(),
+                    serde_json::to_vec_pretty(&model_registration)?,
+                    Some(lease_id),
+                )
+                .await?;
+        }
+        let missing = self
+            .verify_registration(
+                &etcd_client,
+                &card_store,
+                &network_name,
+                &model_registration,
             )
+            .await?;
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Model {} is not registered in etcd after repairing it, missing {missing:?}",
+                self.display_name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Write the card and `entry` in one etcd transaction. Retries if another worker of the same
+    /// model publishes the card at the same time. A card with the same content, or one pinned
+    /// by a rollback, is kept and only `entry` is written.
+    async fn register(
+        &mut self,
+        etcd_client: &etcd::Client,
+        card_store: &KeyValueStoreManager,
+        network_name: &ModelNetworkName,
+        entry: &ModelEntry,
+        lease_id: i64,
+    ) -> anyhow::Result<()> {
+        let key = self.card.slug().to_string();
+        let card_key = EtcdStorage::key(model_card::ROOT_PATH, &key);
+        let entry_json = serde_json::to_vec_pretty(entry)?;
+        for _ in 0..REGISTER_ATTEMPTS {
+            let current = etcd_client
+                .kv_get(card_key.as_str(), None)
+                .await?
+                .into_iter()
+                .next();
+            let version = current.as_ref().map(|kv| kv.version()).unwrap_or(0);
+            let card_json = serde_json::to_string(&self.card)?;
+            if let Some(current) = current {
+                let pinned = card_store.pinned(model_card::ROOT_PATH, &key).await?;
+                if let Some(pinned) = pinned {
+                    tracing::info!(
+                        key,
+                        pinned,
+                        "Model card was rolled back and is pinned, not publishing ours"
+                    );
+                }
+                if pinned.is_some()
+                    || same_content::<ModelDeploymentCard>(current.value(), &card_json)
+                {
+                    // Keep the card as it is, a new revision would say nothing new
+                    etcd_client
+                        .kv_create(network_name.to_string(), entry_json, Some(lease_id))
+                        .await
+                        .with_context(|| format!("Failed registering {network_name}"))?;
+                    self.card.set_revision(version as u64);
+                    return Ok(());
+                }
+            }
+            let written = etcd_client
+                .kv_create_with(
+                    network_name.to_string(),
+                    entry_json.clone(),
+                    Some(lease_id),
+                    vec![(card_key.clone(), card_json.clone().into_bytes(), version)],
+                )
+                .await
+                .with_context(|| format!("Failed registering {network_name}"))?;
+            if !written {
+                tracing::debug!(card_key, "Model card changed while registering, retrying");
+                continue;
+            }
+            // Every put increments the version
+            let revision = version as u64 + 1;
+            self.card.set_revision(revision);
+            if let Err(err) = card_store
+                .record_revision(model_card::ROOT_PATH, &key, revision, &card_json)
+                .await
+            {
+                // The card is published, only rollback is affected
+                tracing::warn!(key, revision, %err, "Failed recording revision history");
+            }
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Model card {card_key} kept changing while registering {network_name}, tried {REGISTER_ATTEMPTS} times"
+        );
+    }
+
+    /// Which parts of the registration can't be read back from etcd
+    async fn verify_registration(
+        &self,
+        etcd_client: &etcd::Client,
+        card_store: &KeyValueStoreManager,
+        network_name: &ModelNetworkName,
+        entry: &ModelEntry,
+    ) -> anyhow::Result<Vec<RegistrationPart>> {
+        let mut missing = Vec::new();
+        let card: Option<ModelDeploymentCard> = card_store
+            .load(model_card::ROOT_PATH, &self.card.slug())
+            .await?;
+        if card.is_none() {
+            missing.push(RegistrationPart::Card);
+        }
+        if etcd_client
+            .kv_get(network_name.to_string(), None)
+            .await?
+            .is_empty()
+        {
+            missing.push(RegistrationPart::Entry);
+            return Ok(missing);
+        }
+        let found = network_name.load_entry(etcd_client).await?;
+        if &found != entry {
+            anyhow::bail!("Model entry {network_name} read back from etcd does not match what we wrote. Is another worker registering under the same name? Got {found:?}, expected {entry:?}.");
+        }
+        Ok(missing)
+    }
+
+    /// Wait until `endpoint`, which must already be serving, is routable the way a frontend
+    /// would route to it: this instance is discovered through etcd, and answers a NATS request.
+    ///
+    /// Call after [`LocalModel::attach`] and starting the endpoint. A misconfigured namespace or
+    /// NATS permissions are then caught at startup instead of on the first user request.
+    pub async fn verify_routable(endpoint: &Endpoint, timeout: Duration) -> anyhow::Result<()> {
+        let Some(lease_id) = endpoint.drt().primary_lease().map(|l| l.id()) else {
+            anyhow::bail!("Cannot verify a static endpoint");
+        };
+        Self::verify_routable_with_lease(endpoint, lease_id, timeout).await
+    }
+
+    /// [`LocalModel::verify_routable`] for an endpoint started with its own etcd lease
+    pub async fn verify_routable_with_lease(
+        endpoint: &Endpoint,
+        lease_id: i64,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        tokio::time::timeout(timeout, Self::probe(endpoint, lease_id))
             .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "{} instance {lease_id:x} is not routable after {}s. Check that the frontend and this worker use the same namespace, and that NATS permissions allow requests to {}.",
+                    endpoint.path(),
+                    timeout.as_secs(),
+                    endpoint.subject_to(lease_id)
+                )
+            })?
+    }
+
+    async fn probe(endpoint: &Endpoint, lease_id: i64) -> anyhow::Result<()> {
+        // Discovery: the same etcd watch a frontend's client uses
+        let client = endpoint.client().await?;
+        while !client.instance_ids().contains(&lease_id) {
+            tokio::time::sleep(PROBE_INTERVAL).await;
+        }
+
+        // Transport: a request over NATS to the subject a frontend sends to must be answered
+        let subject = endpoint.subject_to(lease_id);
+        let client = endpoint.drt().nats_client().client().clone();
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert(PROBE_HEADER, "1");
+        loop {
+            let request = async_nats::Request::new()
+                .headers(headers.clone())
+                .timeout(Some(PROBE_INTERVAL));
+            match client.send_request(subject.clone(), request).await {
+                Ok(_) => return Ok(()),
+                Err(err) => tracing::debug!(%err, "Readiness probe failed, retrying"),
+            }
+            tokio::time::sleep(PROBE_INTERVAL).await;
+        }
+    }
+
+    /// The endpoint to serve this model on, `endpoint` unless its component already serves
+    /// another model: then `policy` decides. Call it before serving the endpoint and attaching
+    /// the model to it.
+    pub async fn claim_endpoint(
+        &self,
+        endpoint: &Endpoint,
+        policy: DuplicateRegistration,
+    ) -> anyhow::Result<Endpoint> {
+        let component = endpoint.component();
+        let model_name = self.display_name();
+        let others = other_models(component, model_name).await?;
+        let Some((instance, other_model)) = others.first() else {
+            return Ok(endpoint.clone());
+        };
+        match policy {
+            DuplicateRegistration::Fail => Err(duplicate_error(
+                component,
+                model_name,
+                instance,
+                other_model,
+            )),
+            DuplicateRegistration::TakeOver => {
+                take_over(component, &others).await?;
+                Ok(endpoint.clone())
+            }
+            DuplicateRegistration::Coexist => {
+                for version in 2..=MAX_COMPONENT_VERSIONS {
+                    let versioned = component
+                        .namespace()
+                        .component(format!("{}-v{version}", component.name()))?;
+                    if !other_models(&versioned, model_name).await?.is_empty() {
+                        continue;
+                    }
+                    // The service is already there if this model was served on it before
+                    if let Err(err) = versioned.service_builder().create().await {
+                        tracing::debug!(%err, "Re-using service of {versioned}");
+                    }
+                    tracing::warn!(
+                        model_name,
+                        other_model = %other_model,
+                        "Component {component} serves another model, serving on {versioned}"
+                    );
+                    return Ok(versioned.endpoint(endpoint.name()));
+                }
+                anyhow::bail!(
+                    "No free version of component {component} for model {model_name} up to v{MAX_COMPONENT_VERSIONS}"
+                );
+            }
+        }
     }
 
     /// Ensure that each component serves only one model.
@@ -183,20 +539,229 @@ impl LocalModel {
     /// We cannot have multiple models with the same component name.
     ///
     /// Returns an error if there is already a component by this name serving a different model.
+    /// [`LocalModel::claim_endpoint`] resolves that beforehand.
     async fn ensure_unique(&self, component: &Component, model_name: &str) -> anyhow::Result<()> {
-        let Some(etcd_client) = component.drt().etcd_client() else {
-            // A static component is necessarily unique, it cannot register
-            return Ok(());
-        };
-        for endpoint_info in component.list_instances().await? {
-            let network_name: ModelNetworkName = (&endpoint_info).into();
+        match other_models(component, model_name).await?.first() {
+            Some((instance, other_model)) => Err(duplicate_error(
+                component,
+                model_name,
+                instance,
+                other_model,
+            )),
+            None => Ok(()),
+        }
+    }
+}
 
-            if let Ok(entry) = network_name.load_entry(&etcd_client).await {
-                if entry.name != model_name {
-                    anyhow::bail!("Duplicate component. Attempt to register model {model_name} at {component}, which is already used by {network_name} running model {}.", entry.name);
-                }
+/// The instances of `component` registered with a model other than `model_name`, and that model
+async fn other_models(
+    component: &Component,
+    model_name: &str,
+) -> anyhow::Result<Vec<(Instance, String)>> {
+    let Some(etcd_client) = component.drt().etcd_client() else {
+        // A static component is necessarily unique, it cannot register
+        return Ok(vec![]);
+    };
+    let mut others = vec![];
+    for instance in component.list_instances().await? {
+        let network_name: ModelNetworkName = (&instance).into();
+        if let Ok(entry) = network_name.load_entry(&etcd_client).await {
+            if entry.name != model_name {
+                others.push((instance, entry.name));
             }
         }
-        Ok(())
+    }
+    Ok(others)
+}
+
+fn duplicate_error(
+    component: &Component,
+    model_name: &str,
+    instance: &Instance,
+    other_model: &str,
+) -> anyhow::Error {
+    let network_name: ModelNetworkName = instance.into();
+    anyhow::anyhow!("Duplicate component. Attempt to register model {model_name} at {component}, which is already used by {network_name} running model {other_model}.")
+}
+
+/// Remove the registrations of `others`, the instances of another model on `component`, if all
+/// of them are gone: none answers and their leases expired
+async fn take_over(component: &Component, others: &[(Instance, String)]) -> anyhow::Result<()> {
+    let Some(etcd_client) = component.drt().etcd_client() else {
+        return Ok(());
+    };
+    let answering: Vec<String> = component
+        .scrape_stats(TAKE_OVER_PROBE_TIMEOUT)
+        .await
+        .with_context(|| format!("Cannot take over component {component}"))?
+        .into_endpoints()
+        .map(|e| e.subject)
+        .collect();
+    for (instance, other_model) in others {
+        let TransportType::NatsTcp(subject) = &instance.transport;
+        if answering.contains(subject) {
+            anyhow::bail!(
+                "Cannot take over component {component}: instance {:x} of model {other_model} is still running",
+                instance.id()
+            );
+        }
+        // A busy worker may not answer in time, but keeps its lease alive
+        if etcd_client
+            .lease_time_to_live(instance.id())
+            .await?
+            .is_some()
+        {
+            anyhow::bail!(
+                "Cannot take over component {component}: instance {:x} of model {other_model} still holds its lease. Try again once it expired.",
+                instance.id()
+            );
+        }
+    }
+    for (instance, other_model) in others {
+        // The keys written with the lease went with it, these were written without one
+        let network_name: ModelNetworkName = instance.into();
+        etcd_client
+            .kv_delete(network_name.to_string(), None)
+            .await?;
+        let instance_key = component
+            .endpoint(&instance.endpoint)
+            .etcd_path(instance.id());
+        etcd_client.kv_delete(instance_key, None).await?;
+        tracing::warn!(
+            %other_model,
+            "Took over component {component} from instance {:x}, which stopped without unregistering",
+            instance.id()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::{DistributedRuntime, Runtime};
+
+    const MODEL: &str = "test-model";
+    const OTHER_MODEL: &str = "other-model";
+
+    async fn endpoint() -> Endpoint {
+        let rt = Runtime::from_current().unwrap();
+        let drt = DistributedRuntime::from_settings(rt).await.unwrap();
+        let namespace = drt
+            .namespace(format!("claim-{}", uuid::Uuid::new_v4().simple()))
+            .unwrap();
+        namespace.component("worker").unwrap().endpoint("generate")
+    }
+
+    /// Register `OTHER_MODEL` on `endpoint` as an instance with the lease `lease_id` that doesn't
+    /// serve anything. Without a lease the keys stay, as after an unclean shutdown.
+    async fn register_other(endpoint: &Endpoint, lease_id: i64, with_lease: bool) {
+        let etcd_client = endpoint.drt().etcd_client().unwrap();
+        let instance = Instance {
+            component: endpoint.component().name(),
+            endpoint: endpoint.name().to_string(),
+            namespace: endpoint.component().namespace().name().to_string(),
+            instance_id: lease_id,
+            transport: TransportType::NatsTcp(endpoint.subject_to(lease_id)),
+            region: None,
+            zone: None,
+            max_concurrent_requests: None,
+            description: None,
+            cost_per_gpu_hour: None,
+        };
+        let entry = ModelEntry {
+            name: OTHER_MODEL.to_string(),
+            endpoint: endpoint.id(),
+            model_type: ModelType::Backend,
+        };
+        let keys = [
+            (
+                endpoint.etcd_path(lease_id),
+                serde_json::to_vec(&instance).unwrap(),
+            ),
+            (
+                ModelNetworkName::from_local(endpoint, lease_id).to_string(),
+                serde_json::to_vec(&entry).unwrap(),
+            ),
+        ];
+        for (key, value) in keys {
+            if with_lease {
+                etcd_client
+                    .kv_put(key, value, Some(lease_id))
+                    .await
+                    .unwrap();
+            } else {
+                etcd_client
+                    .etcd_client()
+                    .clone()
+                    .put(key, value, None)
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim_endpoint_fail() {
+        let endpoint = endpoint().await;
+        register_other(&endpoint, 0x7e57, false).await;
+        let model = LocalModel::with_name_only(MODEL);
+        let err = model
+            .claim_endpoint(&endpoint, DuplicateRegistration::Fail)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(OTHER_MODEL), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_claim_endpoint_take_over() {
+        let endpoint = endpoint().await;
+        register_other(&endpoint, 0x7e57, false).await;
+        let model = LocalModel::with_name_only(MODEL);
+        let claimed = model
+            .claim_endpoint(&endpoint, DuplicateRegistration::TakeOver)
+            .await
+            .unwrap();
+        assert_eq!(claimed.id(), endpoint.id());
+        let others = other_models(endpoint.component(), MODEL).await.unwrap();
+        assert!(others.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claim_endpoint_take_over_live_lease() {
+        let endpoint = endpoint().await;
+        let etcd_client = endpoint.drt().etcd_client().unwrap();
+        // Doesn't answer, like a busy worker, but its lease is alive
+        let lease = etcd_client.create_lease(10).await.unwrap();
+        register_other(&endpoint, lease.id(), true).await;
+        let model = LocalModel::with_name_only(MODEL);
+        let err = model
+            .claim_endpoint(&endpoint, DuplicateRegistration::TakeOver)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("lease"), "{err}");
+        let others = other_models(endpoint.component(), MODEL).await.unwrap();
+        assert_eq!(others.len(), 1);
+        lease.revoke();
+    }
+
+    #[tokio::test]
+    async fn test_claim_endpoint_coexist() {
+        let endpoint = endpoint().await;
+        register_other(&endpoint, 0x7e57, false).await;
+        let model = LocalModel::with_name_only(MODEL);
+        let claimed = model
+            .claim_endpoint(&endpoint, DuplicateRegistration::Coexist)
+            .await
+            .unwrap();
+        assert_eq!(claimed.name(), endpoint.name());
+        assert_eq!(
+            claimed.component().name(),
+            format!("{}-v2", endpoint.component().name())
+        );
+        // The other model keeps its registration
+        let others = other_models(endpoint.component(), MODEL).await.unwrap();
+        assert_eq!(others.len(), 1);
     }
 }
@@ -0,0 +1,605 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! LoRA adapters on top of the models the workers serve.
+//!
+//! Workers started with `--enable-lora` load and unload adapters while they run, when they get a
+//! [`LoraControl`] on their [`LORA_ENDPOINT`] endpoint. The admin API sends these with a
+//! [`LoraClient`], and registers each adapter a worker loaded in etcd:
+//! `loras/<adapter>/<instance id>` holds a [`LoraEntry`] with the base model. The key has the
+//! worker's lease, it goes away with the worker.
+//!
+//! The ingress follows the keys in a [`LoraRegistry`]. A request whose `model` is the name of an
+//! adapter goes to the pipeline of the base model, keeping its model name, and the preprocessor
+//! sets [`PreprocessedRequest::lora`] for the engine. The routers send it only to the workers
+//! that loaded the adapter: those are the workers that can serve adapters at all. The KV router
+//! picks the best match among them, the other router modes pick like [`PushRouter`] does with
+//! [`LoraRouter`]. Adapter requests are not rescheduled outside KV mode, nor routed by budget.
+//!
+//! Adapter names are slugified in the etcd keys, two names with the same slug can't be loaded
+//! together.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use dynamo_runtime::{
+    component::InstanceSource,
+    pipeline::{
+        async_trait, network::egress::push_router::PushRouter, AsyncEngine, Error, ManyOut,
+        ServerStreamingEngine, SingleIn,
+    },
+    protocols::{self, annotated::Annotated},
+    slug::Slug,
+    transports::etcd::{self, WatchEvent},
+    DistributedRuntime,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::discovery::{ModelEntry, MODEL_ROOT_PATH};
+use crate::kv_router::{with_worker_annotation, ANNOTATION_WORKER_INSTANCE_ID};
+use crate::model_type::ModelType;
+use crate::preprocessor::PreprocessedRequest;
+use crate::protocols::common::llm_backend::LLMEngineOutput;
+
+/// The etcd root of the [`LoraEntry`] of each adapter and worker
+pub const LORA_ROOT_PATH: &str = "loras/";
+
+/// Workers that can load adapters serve this endpoint on the component of their model
+pub const LORA_ENDPOINT: &str = "lora";
+
+/// How long to wait for a worker to load an adapter. It may have to download it first.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long to wait for the workers of an endpoint to be discovered
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The id of adapter `name`. The same on every worker and ingress, never 0, which is the base
+/// model, and below 2^31 for the engines that keep it in an `int32`.
+pub fn lora_id(name: &str) -> u64 {
+    xxh3_64(name.as_bytes()) % i32::MAX as u64 + 1
+}
+
+/// The adapter a request is served with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoraRequest {
+    /// Name of the adapter, the `model` of its requests
+    pub name: String,
+
+    /// See [`lora_id`]
+    pub id: u64,
+
+    /// Local path or Hugging Face repo of the adapter, on the worker
+    pub path: String,
+}
+
+impl LoraRequest {
+    pub fn new(name: &str, path: &str) -> Self {
+        LoraRequest {
+            name: name.to_string(),
+            id: lora_id(name),
+            path: path.to_string(),
+        }
+    }
+}
+
+/// An adapter a worker loaded, in etcd under [`LORA_ROOT_PATH`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoraEntry {
+    #[serde(flatten)]
+    pub lora: LoraRequest,
+
+    /// The model the adapter applies to
+    pub base_model: String,
+
+    /// Where the worker serves the base model
+    pub endpoint: protocols::Endpoint,
+}
+
+/// Sent to the [`LORA_ENDPOINT`] of a worker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LoraControl {
+    /// Load the adapter, so that its first requests don't wait for it
+    Load { lora: LoraRequest },
+
+    /// Free the adapter's memory
+    Unload { lora_name: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoraControlReply {
+    /// The adapters the worker has loaded, after the change
+    pub loras: Vec<String>,
+}
+
+/// A registered adapter and the workers that loaded it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadedLora {
+    #[serde(flatten)]
+    pub entry: LoraEntry,
+
+    pub instance_ids: Vec<i64>,
+}
+
+/// etcd key of adapter `name` on worker `instance_id`
+fn lora_key(name: &str, instance_id: i64) -> String {
+    format!("{}{instance_id:x}", lora_prefix(name))
+}
+
+/// etcd prefix of the keys of adapter `name`
+fn lora_prefix(name: &str) -> String {
+    format!("{LORA_ROOT_PATH}{}/", Slug::slugify(name))
+}
+
+/// The worker of an adapter's etcd key
+fn lora_instance_id(key: &str) -> Option<i64> {
+    let (_, instance_id) = key.strip_prefix(LORA_ROOT_PATH)?.rsplit_once('/')?;
+    i64::from_str_radix(instance_id, 16).ok()
+}
+
+/// The adapters registered in etcd, for the ingress to resolve model names
+#[derive(Debug, Default)]
+pub struct LoraRegistry {
+    inner: Mutex<Registry>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// By etcd key
+    entries: HashMap<String, LoraEntry>,
+
+    /// By adapter name, with the number of workers that loaded it
+    loras: HashMap<String, (LoraEntry, usize)>,
+}
+
+impl LoraRegistry {
+    /// Add the adapter of etcd key `key`, or replace it
+    pub fn insert(&self, key: &str, entry: LoraEntry) {
+        let mut registry = self.inner.lock().unwrap();
+        registry.remove(key);
+        let name = entry.lora.name.clone();
+        registry.entries.insert(key.to_string(), entry.clone());
+        let (registered, workers) = registry
+            .loras
+            .entry(name)
+            .or_insert_with(|| (entry.clone(), 0));
+        if registered.base_model != entry.base_model || registered.lora.path != entry.lora.path {
+            tracing::warn!(
+                name = entry.lora.name,
+                base_model = entry.base_model,
+                path = entry.lora.path,
+                "Workers registered different LoRA adapters under the same name, using the latest"
+            );
+            *registered = entry;
+        }
+        *workers += 1;
+    }
+
+    /// Remove the adapter of etcd key `key`
+    pub fn remove(&self, key: &str) -> Option<LoraEntry> {
+        self.inner.lock().unwrap().remove(key)
+    }
+
+    /// The model serving the requests for `model`, if it is an adapter
+    pub fn base_model(&self, model: &str) -> Option<String> {
+        let registry = self.inner.lock().unwrap();
+        let (entry, _) = registry.loras.get(model)?;
+        Some(entry.base_model.clone())
+    }
+
+    /// The adapter to serve the requests for `model` with, if it is one
+    pub fn request(&self, model: &str) -> Option<LoraRequest> {
+        let registry = self.inner.lock().unwrap();
+        let (entry, _) = registry.loras.get(model)?;
+        Some(entry.lora.clone())
+    }
+
+    /// The workers that loaded adapter `name`
+    pub fn instance_ids(&self, name: &str) -> HashSet<i64> {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.lora.name == name)
+            .filter_map(|(key, _)| lora_instance_id(key))
+            .collect()
+    }
+
+    /// The adapters and their base model
+    pub fn names(&self) -> Vec<(String, String)> {
+        let registry = self.inner.lock().unwrap();
+        registry
+            .loras
+            .iter()
+            .map(|(name, (entry, _))| (name.clone(), entry.base_model.clone()))
+            .collect()
+    }
+}
+
+impl Registry {
+    fn remove(&mut self, key: &str) -> Option<LoraEntry> {
+        let entry = self.entries.remove(key)?;
+        if let Some((_, workers)) = self.loras.get_mut(&entry.lora.name) {
+            *workers -= 1;
+            if *workers == 0 {
+                self.loras.remove(&entry.lora.name);
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// Keep `registry` in line with the adapters in etcd. Runs until the etcd watch ends.
+pub async fn follow(registry: Arc<LoraRegistry>, etcd_client: etcd::Client) -> anyhow::Result<()> {
+    let watcher = etcd_client.kv_get_and_watch_prefix(LORA_ROOT_PATH).await?;
+    let (_prefix, _watcher, mut receiver) = watcher.dissolve();
+    while let Some(event) = receiver.recv().await {
+        match event {
+            WatchEvent::Put(kv) => {
+                let key = kv.key_str()?;
+                match serde_json::from_slice::<LoraEntry>(kv.value()) {
+                    Ok(entry) => {
+                        tracing::info!(
+                            name = entry.lora.name,
+                            base_model = entry.base_model,
+                            "LoRA adapter registered"
+                        );
+                        registry.insert(key, entry);
+                    }
+                    Err(err) => tracing::error!(%err, key, "Invalid LoRA adapter in etcd"),
+                }
+            }
+            WatchEvent::Delete(kv) => {
+                if let Some(entry) = registry.remove(kv.key_str()?) {
+                    tracing::info!(name = entry.lora.name, "LoRA adapter unregistered");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+type LoraRouter = PushRouter<LoraControl, Annotated<LoraControlReply>>;
+
+/// Loads and unloads adapters on the workers, and registers them in etcd. For the admin API.
+#[derive(Clone)]
+pub struct LoraClient {
+    drt: DistributedRuntime,
+}
+
+impl LoraClient {
+    pub fn new(drt: DistributedRuntime) -> Self {
+        LoraClient { drt }
+    }
+
+    fn etcd_client(&self) -> anyhow::Result<etcd::Client> {
+        self.drt
+            .etcd_client()
+            .context("LoRA adapters need etcd, static workers can't load them")
+    }
+
+    /// Load adapter `name` from `path` on worker `instance_id` of `endpoint`, or on each of its
+    /// workers, and register it. Returns the adapters of the workers.
+    pub async fn load(
+        &self,
+        endpoint: &protocols::Endpoint,
+        instance_id: Option<i64>,
+        name: &str,
+        path: &str,
+    ) -> anyhow::Result<BTreeMap<i64, LoraControlReply>> {
+        let etcd_client = self.etcd_client()?;
+        // The keys only have the slug of the name
+        for kv in etcd_client.kv_get_prefix(lora_prefix(name)).await? {
+            if let Ok(entry) = serde_json::from_slice::<LoraEntry>(kv.value()) {
+                if entry.lora.name != name {
+                    anyhow::bail!(
+                        "LoRA adapter {name} has the same slug as the loaded adapter {}, \
+                         unload it or pick another name",
+                        entry.lora.name
+                    );
+                }
+            }
+        }
+        let base_model = base_model(&etcd_client, endpoint).await?;
+        let router = self.router(endpoint).await?;
+        let instance_ids = match instance_id {
+            Some(instance_id) => vec![instance_id],
+            None => router.client.instance_ids(),
+        };
+        let entry = LoraEntry {
+            lora: LoraRequest::new(name, path),
+            base_model,
+            endpoint: endpoint.clone(),
+        };
+        let message = LoraControl::Load {
+            lora: entry.lora.clone(),
+        };
+        let mut replies = BTreeMap::new();
+        for instance_id in instance_ids {
+            let reply = send(&router, instance_id, message.clone()).await?;
+            // With the worker's lease, the adapter is unregistered when the worker stops
+            etcd_client
+                .kv_put(
+                    lora_key(name, instance_id),
+                    serde_json::to_vec(&entry)?,
+                    Some(instance_id),
+                )
+                .await
+                .with_context(|| format!("Failed registering {name} of worker {instance_id}"))?;
+            replies.insert(instance_id, reply);
+        }
+        Ok(replies)
+    }
+
+    /// Unregister adapter `name` and unload it from worker `instance_id`, or from each worker
+    /// that loaded it. Returns the adapters of the workers, none if no worker had it.
+    pub async fn unload(
+        &self,
+        name: &str,
+        instance_id: Option<i64>,
+    ) -> anyhow::Result<BTreeMap<i64, LoraControlReply>> {
+        let etcd_client = self.etcd_client()?;
+        let mut routers: HashMap<String, LoraRouter> = HashMap::new();
+        let mut replies = BTreeMap::new();
+        for kv in etcd_client.kv_get_prefix(lora_prefix(name)).await? {
+            let key = kv.key_str()?;
+            let Some(worker) = lora_instance_id(key) else {
+                continue;
+            };
+            if instance_id.is_some_and(|instance_id| instance_id != worker) {
+                continue;
+            }
+            let entry: LoraEntry = serde_json::from_slice(kv.value())
+                .with_context(|| format!("Invalid LoRA adapter in etcd at {key}"))?;
+            if entry.lora.name != name {
+                // Another adapter with the same slug, e.g. registered by an older version
+                continue;
+            }
+            // Requests stop coming before the adapter goes
+            etcd_client.kv_delete(key, None).await?;
+            let url = entry.endpoint.as_url();
+            if !routers.contains_key(&url) {
+                let router = self.router(&entry.endpoint).await?;
+                routers.insert(url.clone(), router);
+            }
+            let message = LoraControl::Unload {
+                lora_name: name.to_string(),
+            };
+            replies.insert(worker, send(&routers[&url], worker, message).await?);
+        }
+        Ok(replies)
+    }
+
+    /// The registered adapters, with the workers that loaded them
+    pub async fn list(&self) -> anyhow::Result<Vec<LoadedLora>> {
+        let etcd_client = self.etcd_client()?;
+        let mut loras: BTreeMap<String, LoadedLora> = BTreeMap::new();
+        for kv in etcd_client.kv_get_prefix(LORA_ROOT_PATH).await? {
+            let key = kv.key_str()?;
+            let Some(instance_id) = lora_instance_id(key) else {
+                continue;
+            };
+            let entry: LoraEntry = match serde_json::from_slice(kv.value()) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    tracing::warn!(%err, key, "Invalid LoRA adapter in etcd");
+                    continue;
+                }
+            };
+            loras
+                .entry(entry.lora.name.clone())
+                .or_insert_with(|| LoadedLora {
+                    entry,
+                    instance_ids: vec![],
+                })
+                .instance_ids
+                .push(instance_id);
+        }
+        Ok(loras.into_values().collect())
+    }
+
+    /// Sends to the [`LORA_ENDPOINT`] of the workers of `endpoint`
+    async fn router(&self, endpoint: &protocols::Endpoint) -> anyhow::Result<LoraRouter> {
+        let client = self
+            .drt
+            .namespace(&endpoint.namespace)?
+            .component(&endpoint.component)?
+            .endpoint(LORA_ENDPOINT)
+            .client()
+            .await?;
+        tokio::time::timeout(DISCOVERY_TIMEOUT, client.wait_for_instances())
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "No worker of {} takes LoRA adapters, start them with --enable-lora",
+                    endpoint.as_url()
+                )
+            })??;
+        PushRouter::from_client(client, Default::default()).await
+    }
+}
+
+/// Sends the requests for an adapter to the workers that loaded it, picking among them like
+/// [`PushRouter::select_excluding`]. The other requests, and those pinned to a worker, go to
+/// `inner`. For the router modes other than KV, see the [module docs](self).
+pub struct LoraRouter {
+    inner: ServerStreamingEngine<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+    router: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+    registry: Arc<LoraRegistry>,
+}
+
+impl LoraRouter {
+    pub fn new(
+        inner: ServerStreamingEngine<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+        router: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+        registry: Arc<LoraRegistry>,
+    ) -> Self {
+        LoraRouter {
+            inner,
+            router,
+            registry,
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for LoraRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let Some(lora) = request.lora.as_ref() else {
+            return self.inner.generate(request).await;
+        };
+        if request.backend_instance_id().is_some() {
+            return self.inner.generate(request).await;
+        }
+        if let InstanceSource::Static = self.router.client.instance_source.as_ref() {
+            return self.inner.generate(request).await;
+        }
+        let workers = self.registry.instance_ids(&lora.name);
+        let exclude: Vec<i64> = self
+            .router
+            .loads()?
+            .into_iter()
+            .map(|(instance, _)| instance.id())
+            .filter(|instance_id| !workers.contains(instance_id))
+            .collect();
+        let instance_id = self
+            .router
+            .select_excluding(&exclude)
+            .with_context(|| format!("No worker has the LoRA adapter {} loaded", lora.name))?;
+        let annotate = request.has_annotation(ANNOTATION_WORKER_INSTANCE_ID);
+        let responses = self.router.direct(request, instance_id).await?;
+        Ok(with_worker_annotation(annotate, instance_id, responses))
+    }
+}
+
+/// The model served on `endpoint`
+async fn base_model(
+    etcd_client: &etcd::Client,
+    endpoint: &protocols::Endpoint,
+) -> anyhow::Result<String> {
+    for kv in etcd_client.kv_get_prefix(MODEL_ROOT_PATH).await? {
+        let Ok(entry) = serde_json::from_slice::<ModelEntry>(kv.value()) else {
+            continue;
+        };
+        if entry.model_type == ModelType::Backend && entry.endpoint == *endpoint {
+            return Ok(entry.name);
+        }
+    }
+    anyhow::bail!("No model is served on {}", endpoint.as_url())
+}
+
+/// Send `message` to worker `instance_id` and wait for its reply
+async fn send(
+    router: &LoraRouter,
+    instance_id: i64,
+    message: LoraControl,
+) -> anyhow::Result<LoraControlReply> {
+    let mut stream = router.direct(SingleIn::new(message), instance_id).await?;
+    let reply = tokio::time::timeout(CONTROL_TIMEOUT, stream.next())
+        .await
+        .map_err(|_| anyhow::anyhow!("Worker {instance_id} did not answer in time"))?
+        .with_context(|| format!("Worker {instance_id} closed the stream without answering"))?;
+    reply
+        .into_result()
+        .with_context(|| format!("Worker {instance_id} failed"))?
+        .with_context(|| format!("Worker {instance_id} answered without its adapters"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, base_model: &str) -> LoraEntry {
+        LoraEntry {
+            lora: LoraRequest::new(name, &format!("/adapters/{name}")),
+            base_model: base_model.to_string(),
+            endpoint: "dyn://dynamo.backend.generate".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = LoraRegistry::default();
+        let key_1 = lora_key("sql", 0x1a);
+        let key_2 = lora_key("sql", 0x2b);
+        registry.insert(&key_1, entry("sql", "llama"));
+        registry.insert(&key_2, entry("sql", "llama"));
+        registry.insert(&lora_key("chat", 0x1a), entry("chat", "qwen"));
+
+        assert_eq!(registry.base_model("sql").as_deref(), Some("llama"));
+        assert_eq!(registry.request("sql").unwrap().id, lora_id("sql"));
+        assert_eq!(registry.instance_ids("sql"), HashSet::from([0x1a, 0x2b]));
+        assert_eq!(registry.instance_ids("chat"), HashSet::from([0x1a]));
+        assert!(registry.instance_ids("llama").is_empty());
+        assert_eq!(registry.base_model("llama"), None);
+        let mut names = registry.names();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                ("chat".to_string(), "qwen".to_string()),
+                ("sql".to_string(), "llama".to_string())
+            ]
+        );
+
+        // Registered until the last worker unloads it
+        registry.remove(&key_1);
+        assert_eq!(registry.base_model("sql").as_deref(), Some("llama"));
+        registry.insert(&key_2, entry("sql", "llama"));
+        registry.remove(&key_2);
+        assert_eq!(registry.base_model("sql"), None);
+        assert!(registry.remove(&key_2).is_none());
+    }
+
+    #[test]
+    fn test_lora_id() {
+        assert_eq!(lora_id("sql"), lora_id("sql"));
+        assert_ne!(lora_id("sql"), lora_id("chat"));
+        for name in ["", "a", "sql", "a-much-longer-adapter-name"] {
+            assert!((1..=i32::MAX as u64).contains(&lora_id(name)));
+        }
+    }
+
+    #[test]
+    fn test_lora_key() {
+        let key = lora_key("org/SQL adapter", 0x7f3a);
+        assert!(key.starts_with(LORA_ROOT_PATH));
+        assert_eq!(lora_instance_id(&key), Some(0x7f3a));
+        assert_eq!(lora_instance_id("models/sql/7f3a"), None);
+    }
+
+    #[test]
+    fn test_lora_control_json() {
+        let load = LoraControl::Load {
+            lora: LoraRequest {
+                name: "sql".to_string(),
+                id: 7,
+                path: "/adapters/sql".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&load).unwrap(),
+            serde_json::json!({
+                "action": "load",
+                "lora": {"name": "sql", "id": 7, "path": "/adapters/sql"}
+            })
+        );
+        let unload: LoraControl =
+            serde_json::from_str(r#"{"action": "unload", "lora_name": "sql"}"#).unwrap();
+        assert_eq!(
+            unload,
+            LoraControl::Unload {
+                lora_name: "sql".to_string()
+            }
+        );
+    }
+}
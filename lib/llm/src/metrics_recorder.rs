@@ -0,0 +1,298 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Record the per-model metrics of the ingress in a local SQLite database, for deployments
+//! without a Prometheus stack.
+//!
+//! Every interval the recorder takes the metrics with a `model` label from the registry the
+//! ingress serves on `/metrics`, and appends them to the `samples` table, one row per metric and
+//! label set. Counters and gauges are stored as they are, histograms as their `_count` and
+//! `_sum`: rates and averages are the difference between two snapshots. Old rows are deleted by
+//! age and by count, see [`MetricsRecorderConfig`].
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::Registry;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+/// The label that makes a metric per-model
+const MODEL_LABEL: &str = "model";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    timestamp_ms INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    labels TEXT NOT NULL,
+    value REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp_ms);
+";
+
+#[derive(Debug, Clone)]
+pub struct MetricsRecorderConfig {
+    /// The SQLite database, created if missing
+    pub path: PathBuf,
+
+    /// How often to take a snapshot
+    pub interval: Duration,
+
+    /// Delete samples older than this
+    pub max_age: Option<Duration>,
+
+    /// Keep at most this many samples, deleting the oldest
+    pub max_rows: Option<u64>,
+}
+
+/// The value of one metric for one model at one time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sample {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: i64,
+    pub model: String,
+    pub metric: String,
+
+    /// The labels other than `model`, as a JSON object
+    pub labels: String,
+    pub value: f64,
+}
+
+/// The per-model metrics of `registry` now
+pub fn snapshot(registry: &Registry, timestamp_ms: i64) -> Vec<Sample> {
+    registry
+        .gather()
+        .iter()
+        .flat_map(|family| family_samples(family, timestamp_ms))
+        .collect()
+}
+
+fn family_samples(family: &MetricFamily, timestamp_ms: i64) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for metric in family.get_metric() {
+        let mut model = None;
+        let mut labels = serde_json::Map::new();
+        for label in metric.get_label() {
+            if label.get_name() == MODEL_LABEL {
+                model = Some(label.get_value().to_string());
+            } else {
+                labels.insert(label.get_name().to_string(), label.get_value().into());
+            }
+        }
+        let Some(model) = model else {
+            continue;
+        };
+        let labels = serde_json::Value::Object(labels).to_string();
+        let values = match family.get_field_type() {
+            MetricType::COUNTER => vec![(
+                family.get_name().to_string(),
+                metric.get_counter().get_value(),
+            )],
+            MetricType::GAUGE => vec![(
+                family.get_name().to_string(),
+                metric.get_gauge().get_value(),
+            )],
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                vec![
+                    (
+                        format!("{}_count", family.get_name()),
+                        histogram.get_sample_count() as f64,
+                    ),
+                    (
+                        format!("{}_sum", family.get_name()),
+                        histogram.get_sample_sum(),
+                    ),
+                ]
+            }
+            _ => continue,
+        };
+        samples.extend(values.into_iter().map(|(metric, value)| Sample {
+            timestamp_ms,
+            model: model.clone(),
+            metric,
+            labels: labels.clone(),
+            value,
+        }));
+    }
+    samples
+}
+
+/// The SQLite database of samples
+pub struct MetricsStore {
+    conn: Connection,
+}
+
+impl MetricsStore {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed opening metrics database {}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(MetricsStore { conn })
+    }
+
+    pub fn insert(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO samples (timestamp_ms, model, metric, labels, value) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for sample in samples {
+                insert.execute(params![
+                    sample.timestamp_ms,
+                    sample.model,
+                    sample.metric,
+                    sample.labels,
+                    sample.value
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Delete the samples from before `oldest_ms`, and the oldest ones over `max_rows`. Returns
+    /// how many were deleted.
+    pub fn delete_old(
+        &self,
+        oldest_ms: Option<i64>,
+        max_rows: Option<u64>,
+    ) -> anyhow::Result<usize> {
+        let mut deleted = 0;
+        if let Some(oldest_ms) = oldest_ms {
+            deleted += self
+                .conn
+                .execute("DELETE FROM samples WHERE timestamp_ms < ?1", [oldest_ms])?;
+        }
+        if let Some(max_rows) = max_rows {
+            // Rows are only appended, so the smallest rowids are the oldest
+            deleted += self.conn.execute(
+                "DELETE FROM samples WHERE rowid <= (SELECT MAX(rowid) FROM samples) - ?1",
+                [max_rows as i64],
+            )?;
+        }
+        Ok(deleted)
+    }
+
+    /// The samples from `since_ms` on, of `model` or all models, oldest first
+    pub fn read(&self, since_ms: Option<i64>, model: Option<&str>) -> anyhow::Result<Vec<Sample>> {
+        let mut query = self.conn.prepare(
+            "SELECT timestamp_ms, model, metric, labels, value FROM samples
+             WHERE timestamp_ms >= ?1 AND (?2 IS NULL OR model = ?2)
+             ORDER BY timestamp_ms, rowid",
+        )?;
+        let rows = query.query_map(params![since_ms.unwrap_or(i64::MIN), model], |row| {
+            Ok(Sample {
+                timestamp_ms: row.get(0)?,
+                model: row.get(1)?,
+                metric: row.get(2)?,
+                labels: row.get(3)?,
+                value: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Record the metrics of `registry` until `cancel`. Fails if the database can't be opened.
+pub fn start(
+    registry: Registry,
+    config: MetricsRecorderConfig,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let store = Arc::new(Mutex::new(MetricsStore::open(&config.path)?));
+    tracing::info!(path = %config.path.display(), interval = ?config.interval, "Recording metrics");
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let now = chrono::Utc::now();
+            let samples = snapshot(&registry, now.timestamp_millis());
+            let oldest_ms = config
+                .max_age
+                .and_then(|max_age| chrono::Duration::from_std(max_age).ok())
+                .map(|max_age| (now - max_age).timestamp_millis());
+            let max_rows = config.max_rows;
+            let store = store.clone();
+            let written = tokio::task::spawn_blocking(move || {
+                // safety: Only this task uses the store, one write at a time
+                let mut store = store.lock().unwrap();
+                store.insert(&samples)?;
+                store.delete_old(oldest_ms, max_rows)
+            })
+            .await;
+            match written {
+                Ok(Ok(deleted)) => tracing::trace!(deleted, "Recorded metrics"),
+                Ok(Err(err)) => tracing::error!(%err, "Failed recording metrics"),
+                Err(err) => tracing::error!(%err, "Metrics recorder failed"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts};
+
+    #[test]
+    fn test_record() {
+        let registry = Registry::new();
+        let inflight = IntGaugeVec::new(
+            Opts::new("inflight", "Requests in flight"),
+            &["model", "endpoint"],
+        )
+        .unwrap();
+        let duration =
+            HistogramVec::new(HistogramOpts::new("duration", "Duration"), &["model"]).unwrap();
+        let process = IntGauge::new("threads", "Not per model").unwrap();
+        registry.register(Box::new(inflight.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        registry.register(Box::new(process)).unwrap();
+        inflight.with_label_values(&["llama", "chat"]).set(3);
+        duration.with_label_values(&["llama"]).observe(0.5);
+        duration.with_label_values(&["llama"]).observe(1.5);
+
+        let samples = snapshot(&registry, 1000);
+        let values: Vec<_> = samples
+            .iter()
+            .map(|s| (s.metric.as_str(), s.labels.as_str(), s.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("duration_count", "{}", 2.0),
+                ("duration_sum", "{}", 2.0),
+                ("inflight", r#"{"endpoint":"chat"}"#, 3.0),
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = MetricsStore::open(&dir.path().join("metrics.db")).unwrap();
+        store.insert(&samples).unwrap();
+        let later: Vec<_> = samples
+            .iter()
+            .map(|s| Sample {
+                timestamp_ms: 2000,
+                ..s.clone()
+            })
+            .collect();
+        store.insert(&later).unwrap();
+        assert_eq!(store.read(None, None).unwrap().len(), 6);
+        assert_eq!(store.read(Some(2000), Some("llama")).unwrap(), later);
+        assert!(store.read(None, Some("other")).unwrap().is_empty());
+
+        assert_eq!(store.delete_old(Some(1500), None).unwrap(), 3);
+        assert_eq!(store.delete_old(None, Some(1)).unwrap(), 2);
+        assert_eq!(store.read(None, None).unwrap(), &later[2..]);
+    }
+}
@@ -401,6 +401,9 @@ impl Scheduler {
             num_requests_waiting: state.waiting.len() as u64,
             gpu_cache_usage_perc,
             gpu_prefix_cache_hit_rate: 0.0, // Placeholder value as specified
+            decode_tokens_per_sec: None,
+            lora_ids: None,
+            kv_block_size: Some(kv_manager.block_size() as u32),
         }
     }
 }
@@ -1,9 +1,60 @@
 // SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use dynamo_runtime::{slug::Slug, transports::etcd};
+use serde::Serialize;
+
+use crate::key_value_store::{EtcdStorage, KeyValueStoreManager};
+
 pub mod create;
 pub mod model;
 pub use model::ModelDeploymentCard;
 
 /// Identify model deployment cards in the key-value store
 pub const ROOT_PATH: &str = "mdc";
+
+/// The revisions of a model's card we can roll back to
+#[derive(Debug, Clone, Serialize)]
+pub struct CardRevisions {
+    /// Oldest first
+    pub revisions: Vec<u64>,
+    /// The revision the card was rolled back to, while workers can't replace it
+    pub pinned: Option<u64>,
+}
+
+fn card_store(etcd_client: &etcd::Client) -> KeyValueStoreManager {
+    KeyValueStoreManager::new(Box::new(EtcdStorage::new(etcd_client.clone())))
+}
+
+pub async fn revisions(
+    etcd_client: &etcd::Client,
+    model_name: &str,
+) -> anyhow::Result<CardRevisions> {
+    let card_store = card_store(etcd_client);
+    let key = Slug::from_string(model_name);
+    Ok(CardRevisions {
+        revisions: card_store.revisions(ROOT_PATH, key.as_ref()).await?,
+        pinned: card_store.pinned(ROOT_PATH, key.as_ref()).await?,
+    })
+}
+
+/// Publish an earlier revision of the model's card as the current one, and pin it there until
+/// [`unpin`]. Ingresses that load the card afterwards all get the rolled back card.
+pub async fn rollback(
+    etcd_client: &etcd::Client,
+    model_name: &str,
+    to_revision: u64,
+) -> anyhow::Result<ModelDeploymentCard> {
+    let key = Slug::from_string(model_name);
+    card_store(etcd_client)
+        .rollback(ROOT_PATH, key.as_ref(), to_revision)
+        .await
+}
+
+/// Let workers publish their card for the model again. False if it was not pinned.
+pub async fn unpin(etcd_client: &etcd::Client, model_name: &str) -> anyhow::Result<bool> {
+    let key = Slug::from_string(model_name);
+    Ok(card_store(etcd_client)
+        .unpin(ROOT_PATH, key.as_ref())
+        .await?)
+}
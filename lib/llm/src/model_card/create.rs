@@ -63,7 +63,7 @@ impl ModelDeploymentCard {
         let model_name = gguf_file
             .iter()
             .next_back()
-            .map(|n| n.to_string_lossy().to_string());
+            .map(|n| crate::gguf::gguf_model_name(&n.to_string_lossy()));
         let Some(model_name) = model_name else {
             // I think this would only happy on an empty path
             anyhow::bail!(
@@ -91,6 +91,12 @@ impl ModelDeploymentCard {
             last_published: None,
             context_length,
             kv_cache_block_size: 0,
+            inferred_defaults: Default::default(),
+            generation_limits: Default::default(),
+            special_tokens: Default::default(),
+            sampling_presets: Default::default(),
+            prompt_compression: Default::default(),
+            engine_capabilities: Default::default(),
         })
     }
 
@@ -129,6 +135,12 @@ impl ModelDeploymentCard {
             last_published: None,
             context_length,
             kv_cache_block_size: 0, // set later
+            inferred_defaults: Default::default(),
+            generation_limits: Default::default(),
+            special_tokens: Default::default(),
+            sampling_presets: Default::default(),
+            prompt_compression: Default::default(),
+            engine_capabilities: Default::default(),
         })
     }
 }
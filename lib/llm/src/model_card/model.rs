@@ -13,6 +13,7 @@
 //! - Prompt formatter settings (PromptFormatterArtifact)
 //! - Various metadata like revision, publish time, etc.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::fs::File;
 use std::path::{Path, PathBuf};
@@ -29,6 +30,7 @@ use url::Url;
 
 use crate::gguf::{Content, ContentConfig, ModelConfigLike};
 use crate::key_value_store::Versioned;
+use crate::protocols::common::SamplingOptions;
 use crate::protocols::TokenIdType;
 
 /// If a model deployment card hasn't been refreshed in this much time the worker is likely gone
@@ -84,6 +86,399 @@ pub enum GenerationConfig {
     GGUF(PathBuf),
 }
 
+/// Operator caps on how much a single request may generate. Enforced by the preprocessor at the
+/// ingress whatever the client asks for, so one client can't monopolize a worker.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GenerationLimits {
+    /// Hard limit on `max_tokens`. Requests asking for more are rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+
+    /// Soft limit: the `max_tokens` to use when the request doesn't set one.
+    /// Falls back to `max_tokens` if only the hard limit is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_max_tokens: Option<u32>,
+
+    /// Hard limit on the wall clock time of a single generation, in seconds.
+    /// The response stream is stopped when it runs out, with a `length` finish reason.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_generation_secs: Option<u64>,
+}
+
+impl GenerationLimits {
+    pub fn is_empty(&self) -> bool {
+        self == &GenerationLimits::default()
+    }
+
+    /// Limits set in `overrides` win over ours. This is how operators override the card's limits
+    /// at the ingress.
+    pub fn with_overrides(&self, overrides: &GenerationLimits) -> GenerationLimits {
+        GenerationLimits {
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            default_max_tokens: overrides.default_max_tokens.or(self.default_max_tokens),
+            max_generation_secs: overrides.max_generation_secs.or(self.max_generation_secs),
+        }
+    }
+
+    /// The `max_tokens` to generate with, given what the client asked for.
+    /// Errors with a message for the client if it asked for more than the hard limit.
+    pub fn effective_max_tokens(&self, requested: Option<u32>) -> Result<Option<u32>, String> {
+        match (requested, self.max_tokens) {
+            (Some(requested), Some(limit)) if requested > limit => Err(format!(
+                "max_tokens of {requested} exceeds this model's limit of {limit}"
+            )),
+            (Some(requested), _) => Ok(Some(requested)),
+            (None, limit) => Ok(match (self.default_max_tokens, limit) {
+                (Some(default), Some(limit)) => Some(default.min(limit)),
+                (default, limit) => default.or(limit),
+            }),
+        }
+    }
+
+    pub fn max_generation_time(&self) -> Option<Duration> {
+        self.max_generation_secs.map(Duration::from_secs)
+    }
+}
+
+/// How the ingress handles a model's special tokens, for models whose tokenizer and chat
+/// template get them wrong. Applied in the preprocessor, so every engine sees the same prompt and
+/// stops on the same tokens. Unset fields keep the model's own behavior.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpecialTokens {
+    /// Whether prompts start with the BOS token. `true` adds it if the chat template didn't,
+    /// `false` removes it if the chat template added it. Prompts the client tokenized are
+    /// left alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_bos: Option<bool>,
+
+    /// Whether chat prompts end with the start of an assistant message. By default they do
+    /// unless the last message is from the assistant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_generation_prompt: Option<bool>,
+
+    /// Replaces the EOS token ids from the model's config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eos_token_ids: Option<Vec<TokenIdType>>,
+
+    /// More tokens that end generation, hidden from the output like EOS
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_token_ids: Vec<TokenIdType>,
+}
+
+impl SpecialTokens {
+    pub fn is_empty(&self) -> bool {
+        self == &SpecialTokens::default()
+    }
+
+    /// Settings in `overrides` win over ours, stop tokens are added to ours
+    pub fn with_overrides(&self, overrides: &SpecialTokens) -> SpecialTokens {
+        let mut stop_token_ids = self.stop_token_ids.clone();
+        for token_id in &overrides.stop_token_ids {
+            if !stop_token_ids.contains(token_id) {
+                stop_token_ids.push(*token_id);
+            }
+        }
+        SpecialTokens {
+            add_bos: overrides.add_bos.or(self.add_bos),
+            add_generation_prompt: overrides
+                .add_generation_prompt
+                .or(self.add_generation_prompt),
+            eos_token_ids: overrides
+                .eos_token_ids
+                .clone()
+                .or_else(|| self.eos_token_ids.clone()),
+            stop_token_ids,
+        }
+    }
+
+    /// The EOS tokens to use, given the model's
+    pub fn eos_token_ids(&self, model_eos_token_ids: Vec<TokenIdType>) -> Vec<TokenIdType> {
+        self.eos_token_ids.clone().unwrap_or(model_eos_token_ids)
+    }
+
+    /// Add or remove the BOS token at the start of a tokenized prompt
+    pub fn apply_bos(&self, token_ids: &mut Vec<TokenIdType>, bos_token_id: TokenIdType) {
+        let has_bos = token_ids.first() == Some(&bos_token_id);
+        match self.add_bos {
+            Some(true) if !has_bos => token_ids.insert(0, bos_token_id),
+            Some(false) if has_bos => {
+                token_ids.remove(0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sampling parameters under a name, that requests pick with `nvext.sampling_preset` instead of
+/// setting each of them. Parameters the request sets itself win.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SamplingPreset {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repetition_penalty: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
+impl SamplingPreset {
+    /// Fill in the options the request didn't set
+    pub fn apply(&self, options: &mut SamplingOptions) {
+        options.temperature = options.temperature.or(self.temperature);
+        options.top_p = options.top_p.or(self.top_p);
+        options.top_k = options.top_k.or(self.top_k);
+        options.min_p = options.min_p.or(self.min_p);
+        options.presence_penalty = options.presence_penalty.or(self.presence_penalty);
+        options.frequency_penalty = options.frequency_penalty.or(self.frequency_penalty);
+        options.repetition_penalty = options.repetition_penalty.or(self.repetition_penalty);
+        options.seed = options.seed.or(self.seed);
+    }
+}
+
+/// The presets every deployment has, unless it replaces them
+fn builtin_sampling_preset(name: &str) -> Option<SamplingPreset> {
+    let preset = match name {
+        "creative" => SamplingPreset {
+            temperature: Some(1.0),
+            top_p: Some(0.95),
+            presence_penalty: Some(0.3),
+            ..Default::default()
+        },
+        "precise" => SamplingPreset {
+            temperature: Some(0.2),
+            top_p: Some(0.9),
+            ..Default::default()
+        },
+        // Temperature zero is greedy sampling
+        "deterministic" => SamplingPreset {
+            temperature: Some(0.0),
+            top_p: Some(1.0),
+            ..Default::default()
+        },
+        _ => return None,
+    };
+    Some(preset)
+}
+
+const BUILTIN_SAMPLING_PRESETS: &[&str] = &["creative", "deterministic", "precise"];
+
+/// The sampling presets of a deployment, by name, in addition to the built in `creative`,
+/// `precise` and `deterministic`. A preset with the name of a built in one replaces it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct SamplingPresets(BTreeMap<String, SamplingPreset>);
+
+impl SamplingPresets {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Read presets from a JSON file, an object of preset names to parameters
+    pub fn from_json_file(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed opening sampling presets {}", path.display()))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("Invalid sampling presets in {}", path.display()))
+    }
+
+    /// Presets in `overrides` replace ours of the same name. This is how operators set presets
+    /// at the ingress.
+    pub fn with_overrides(&self, overrides: &SamplingPresets) -> SamplingPresets {
+        let mut presets = self.0.clone();
+        presets.extend(overrides.0.clone());
+        SamplingPresets(presets)
+    }
+
+    pub fn get(&self, name: &str) -> Option<SamplingPreset> {
+        self.0
+            .get(name)
+            .cloned()
+            .or_else(|| builtin_sampling_preset(name))
+    }
+
+    /// The names of all presets, built in ones included, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        names.extend(BUILTIN_SAMPLING_PRESETS);
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// How the ingress shortens prompts before templating and tokenizing them, see
+/// [`crate::preprocessor::compression`]. Off unless set.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PromptCompression {
+    /// Collapse runs of spaces and blank lines, outside of code blocks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collapse_whitespace: Option<bool>,
+
+    /// Drop paragraphs that appeared earlier in the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_chunks: Option<bool>,
+
+    /// An external compressor to send the text of all but the last message to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressor_url: Option<Url>,
+
+    /// The share of tokens the external compressor should keep, from 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressor_rate: Option<f32>,
+}
+
+impl PromptCompression {
+    pub fn is_empty(&self) -> bool {
+        self == &PromptCompression::default()
+    }
+
+    /// Whether any strategy is on
+    pub fn is_enabled(&self) -> bool {
+        self.collapse_whitespace.unwrap_or(false)
+            || self.dedup_chunks.unwrap_or(false)
+            || self.compressor_url.is_some()
+    }
+
+    /// Settings in `overrides` win over ours
+    pub fn with_overrides(&self, overrides: &PromptCompression) -> PromptCompression {
+        PromptCompression {
+            collapse_whitespace: overrides.collapse_whitespace.or(self.collapse_whitespace),
+            dedup_chunks: overrides.dedup_chunks.or(self.dedup_chunks),
+            compressor_url: overrides
+                .compressor_url
+                .clone()
+                .or_else(|| self.compressor_url.clone()),
+            compressor_rate: overrides.compressor_rate.or(self.compressor_rate),
+        }
+    }
+}
+
+/// A sampling parameter of [`SamplingOptions`], named as in the request
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplingParam {
+    N,
+    BestOf,
+    PresencePenalty,
+    FrequencyPenalty,
+    RepetitionPenalty,
+    Temperature,
+    TopP,
+    TopK,
+    MinP,
+    UseBeamSearch,
+    LengthPenalty,
+    Seed,
+}
+
+impl SamplingParam {
+    /// Unset this parameter in `options`. Returns whether it was set.
+    pub fn clear(self, options: &mut SamplingOptions) -> bool {
+        match self {
+            SamplingParam::N => options.n.take().is_some(),
+            SamplingParam::BestOf => options.best_of.take().is_some(),
+            SamplingParam::PresencePenalty => options.presence_penalty.take().is_some(),
+            SamplingParam::FrequencyPenalty => options.frequency_penalty.take().is_some(),
+            SamplingParam::RepetitionPenalty => options.repetition_penalty.take().is_some(),
+            SamplingParam::Temperature => options.temperature.take().is_some(),
+            SamplingParam::TopP => options.top_p.take().is_some(),
+            SamplingParam::TopK => options.top_k.take().is_some(),
+            SamplingParam::MinP => options.min_p.take().is_some(),
+            SamplingParam::UseBeamSearch => options.use_beam_search.take().is_some(),
+            SamplingParam::LengthPenalty => options.length_penalty.take().is_some(),
+            SamplingParam::Seed => options.seed.take().is_some(),
+        }
+    }
+}
+
+impl fmt::Display for SamplingParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // safety: A unit variant serializes to a string
+        let name = serde_json::to_value(self).unwrap();
+        write!(f, "{}", name.as_str().unwrap_or_default())
+    }
+}
+
+/// What the engine behind a model can't do. The ingress removes unsupported parameters from
+/// requests and warns the client in a `warnings` annotation, instead of the engine failing the
+/// whole request. Empty means the engine supports everything.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EngineCapabilities {
+    /// The engine, e.g. "trtllm", for the warnings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+
+    /// Sampling parameters the engine rejects or ignores
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub unsupported_sampling: BTreeSet<SamplingParam>,
+}
+
+impl EngineCapabilities {
+    /// The capabilities of the engines we know to be missing some, by their `out=` name
+    pub fn for_engine(engine: &str) -> Self {
+        use SamplingParam::*;
+        let unsupported: &[SamplingParam] = match engine {
+            // Beam width is fixed when the engine is built, and older releases have no min_p
+            "trtllm" => &[BestOf, UseBeamSearch, LengthPenalty, MinP],
+            // Our sglang worker only passes these on
+            "sglang" => &[
+                N,
+                BestOf,
+                PresencePenalty,
+                FrequencyPenalty,
+                RepetitionPenalty,
+                TopP,
+                TopK,
+                MinP,
+                UseBeamSearch,
+                LengthPenalty,
+            ],
+            _ => &[],
+        };
+        if unsupported.is_empty() {
+            return EngineCapabilities::default();
+        }
+        EngineCapabilities {
+            engine: Some(engine.to_string()),
+            unsupported_sampling: unsupported.iter().copied().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unsupported_sampling.is_empty()
+    }
+
+    /// Remove the parameters the engine doesn't support from `options`. Returns those that were
+    /// set.
+    pub fn strip_unsupported(&self, options: &mut SamplingOptions) -> Vec<SamplingParam> {
+        self.unsupported_sampling
+            .iter()
+            .copied()
+            .filter(|param| param.clear(options))
+            .collect()
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Builder, Default)]
 pub struct ModelDeploymentCard {
     /// Human readable model name, e.g. "Meta Llama 3.1 8B Instruct"
@@ -124,6 +519,37 @@ pub struct ModelDeploymentCard {
     /// Size of a KV cache block - vllm only currently
     /// Passed to the engine and the KV router.
     pub kv_cache_block_size: usize,
+
+    /// Engine settings that were not given by the user and were inferred from the hardware
+    /// at startup, e.g. "tensor_parallel_size" -> "4". For operators to inspect.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[builder(default)]
+    pub inferred_defaults: BTreeMap<String, String>,
+
+    /// Caps on output tokens and generation time, enforced at the ingress
+    #[serde(default, skip_serializing_if = "GenerationLimits::is_empty")]
+    #[builder(default)]
+    pub generation_limits: GenerationLimits,
+
+    /// BOS, generation prompt and stop token handling, applied at the ingress
+    #[serde(default, skip_serializing_if = "SpecialTokens::is_empty")]
+    #[builder(default)]
+    pub special_tokens: SpecialTokens,
+
+    /// Named sampling parameters requests can pick, expanded at the ingress
+    #[serde(default, skip_serializing_if = "SamplingPresets::is_empty")]
+    #[builder(default)]
+    pub sampling_presets: SamplingPresets,
+
+    /// How the ingress shortens prompts, off by default
+    #[serde(default, skip_serializing_if = "PromptCompression::is_empty")]
+    #[builder(default)]
+    pub prompt_compression: PromptCompression,
+
+    /// Sampling parameters the engine doesn't support, removed at the ingress
+    #[serde(default, skip_serializing_if = "EngineCapabilities::is_empty")]
+    #[builder(default)]
+    pub engine_capabilities: EngineCapabilities,
 }
 
 impl ModelDeploymentCard {
@@ -340,6 +766,8 @@ impl ModelDeploymentCard {
 }
 
 impl Versioned for ModelDeploymentCard {
+    const UNVERSIONED_FIELDS: &'static [&'static str] = &["last_published"];
+
     fn revision(&self) -> u64 {
         self.revision
     }
@@ -372,6 +800,11 @@ pub trait ModelInfo: Send + Sync {
     /// Vocabulary size
     /// TODO: This is only used in a single test, no other code. Remove?
     fn vocab_size(&self) -> Option<usize>;
+
+    /// Whether the model takes images in the chat messages
+    fn supports_images(&self) -> bool {
+        false
+    }
 }
 
 impl ModelInfoType {
@@ -399,6 +832,10 @@ struct HFConfig {
 
     // Sometimes it's inside HFTextConfig, sometimes it's here
     eos_token_id: Option<serde_json::Value>,
+
+    /// Only vision models have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vision_config: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -551,6 +988,7 @@ impl HFConfig {
                 vocab_size: Some(vocab_size),
             }),
             eos_token_id: None,
+            vision_config: None,
         }))
     }
 }
@@ -579,6 +1017,10 @@ impl ModelInfo for HFConfig {
     fn vocab_size(&self) -> Option<usize> {
         self.text_config.as_ref().unwrap().vocab_size
     }
+
+    fn supports_images(&self) -> bool {
+        self.vision_config.is_some()
+    }
 }
 
 impl TokenizerKind {
@@ -592,9 +1034,12 @@ impl TokenizerKind {
 
 pub(crate) fn load_gguf(gguf_file: &Path) -> anyhow::Result<Content> {
     let filename = gguf_file.display().to_string();
-    let mut f = File::open(gguf_file).with_context(|| filename.clone())?;
-    // vec because GGUF can be split into multiple files (shards)
-    let mut readers = vec![&mut f];
+    // GGUF can be split into multiple files (shards)
+    let mut files = crate::gguf::gguf_files(gguf_file)?
+        .iter()
+        .map(|path| File::open(path).with_context(|| path.display().to_string()))
+        .collect::<anyhow::Result<Vec<File>>>()?;
+    let mut readers: Vec<&mut File> = files.iter_mut().collect();
     crate::gguf::Content::from_readers(&mut readers).with_context(|| filename.clone())
 }
 
@@ -613,15 +1058,155 @@ fn capitalize(s: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::HFConfig;
+    use super::{
+        EngineCapabilities, GenerationLimits, HFConfig, SamplingParam, SamplingPreset,
+        SamplingPresets, SpecialTokens,
+    };
+    use crate::protocols::common::SamplingOptions;
     use std::path::Path;
 
+    #[test]
+    fn test_generation_limits() {
+        let limits = GenerationLimits {
+            max_tokens: Some(4096),
+            default_max_tokens: Some(512),
+            max_generation_secs: None,
+        };
+        assert_eq!(limits.effective_max_tokens(None), Ok(Some(512)));
+        assert_eq!(limits.effective_max_tokens(Some(100)), Ok(Some(100)));
+        assert_eq!(limits.effective_max_tokens(Some(4096)), Ok(Some(4096)));
+        assert!(limits.effective_max_tokens(Some(32768)).is_err());
+
+        let hard_only = GenerationLimits {
+            max_tokens: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(hard_only.effective_max_tokens(None), Ok(Some(1024)));
+        assert_eq!(
+            GenerationLimits::default().effective_max_tokens(None),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_generation_limits_overrides() {
+        let card = GenerationLimits {
+            max_tokens: Some(4096),
+            default_max_tokens: Some(512),
+            max_generation_secs: Some(60),
+        };
+        let operator = GenerationLimits {
+            max_tokens: Some(1024),
+            ..Default::default()
+        };
+        let merged = card.with_overrides(&operator);
+        assert_eq!(merged.max_tokens, Some(1024));
+        assert_eq!(merged.default_max_tokens, Some(512));
+        assert_eq!(merged.max_generation_secs, Some(60));
+    }
+
+    #[test]
+    fn test_special_tokens() {
+        let card = SpecialTokens {
+            add_bos: Some(true),
+            stop_token_ids: vec![7],
+            ..Default::default()
+        };
+        let operator = SpecialTokens {
+            eos_token_ids: Some(vec![2]),
+            stop_token_ids: vec![7, 9],
+            ..Default::default()
+        };
+        let merged = card.with_overrides(&operator);
+        assert_eq!(merged.add_bos, Some(true));
+        assert_eq!(merged.eos_token_ids(vec![1]), vec![2]);
+        assert_eq!(merged.stop_token_ids, vec![7, 9]);
+        assert_eq!(card.eos_token_ids(vec![1]), vec![1]);
+
+        let mut token_ids = vec![5, 6];
+        merged.apply_bos(&mut token_ids, 1);
+        merged.apply_bos(&mut token_ids, 1);
+        assert_eq!(token_ids, vec![1, 5, 6]);
+
+        let no_bos = SpecialTokens {
+            add_bos: Some(false),
+            ..Default::default()
+        };
+        no_bos.apply_bos(&mut token_ids, 1);
+        assert_eq!(token_ids, vec![5, 6]);
+        SpecialTokens::default().apply_bos(&mut token_ids, 1);
+        assert_eq!(token_ids, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_sampling_presets() {
+        let card: SamplingPresets = serde_json::from_str(
+            r#"{"support": {"temperature": 0.4, "presence_penalty": 0.1}, "precise": {"temperature": 0.1}}"#,
+        )
+        .unwrap();
+        let operator: SamplingPresets =
+            serde_json::from_str(r#"{"support": {"temperature": 0.5}}"#).unwrap();
+        let presets = card.with_overrides(&operator);
+        assert_eq!(
+            presets.names(),
+            vec!["creative", "deterministic", "precise", "support"]
+        );
+        assert_eq!(
+            presets.get("support"),
+            Some(SamplingPreset {
+                temperature: Some(0.5),
+                ..Default::default()
+            })
+        );
+        assert_eq!(presets.get("precise").unwrap().temperature, Some(0.1));
+        assert_eq!(presets.get("creative").unwrap().top_p, Some(0.95));
+        assert_eq!(presets.get("other"), None);
+        assert!(serde_json::from_str::<SamplingPresets>(r#"{"x": {"temprature": 1}}"#).is_err());
+
+        // What the request sets wins
+        let mut options = SamplingOptions {
+            temperature: Some(0.9),
+            ..Default::default()
+        };
+        presets.get("deterministic").unwrap().apply(&mut options);
+        assert_eq!(options.temperature, Some(0.9));
+        assert_eq!(options.top_p, Some(1.0));
+    }
+
+    #[test]
+    fn test_engine_capabilities() {
+        assert!(EngineCapabilities::for_engine("vllm").is_empty());
+        let trtllm = EngineCapabilities::for_engine("trtllm");
+        assert_eq!(trtllm.engine.as_deref(), Some("trtllm"));
+
+        let mut options = SamplingOptions {
+            temperature: Some(0.7),
+            min_p: Some(0.1),
+            use_beam_search: Some(false),
+            ..Default::default()
+        };
+        let stripped = trtllm.strip_unsupported(&mut options);
+        assert_eq!(
+            stripped,
+            vec![SamplingParam::MinP, SamplingParam::UseBeamSearch]
+        );
+        assert_eq!(options.temperature, Some(0.7));
+        assert_eq!(options.min_p, None);
+        assert_eq!(options.use_beam_search, None);
+        assert_eq!(SamplingParam::UseBeamSearch.to_string(), "use_beam_search");
+
+        let card: EngineCapabilities =
+            serde_json::from_str(r#"{"engine": "x", "unsupported_sampling": ["top_k"]}"#).unwrap();
+        assert!(card.unsupported_sampling.contains(&SamplingParam::TopK));
+    }
+
     #[tokio::test]
     pub async fn test_config_json_llama3() -> anyhow::Result<()> {
         let config_file = Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("tests/data/sample-models/mock-llama-3.1-8b-instruct/config.json");
         let config = HFConfig::from_json_file(&config_file.display().to_string()).await?;
         assert_eq!(config.bos_token_id(), 128000);
+        assert!(!config.supports_images());
         Ok(())
     }
 
@@ -631,6 +1216,7 @@ mod tests {
             .join("tests/data/sample-models/Llama-4-Scout-17B-16E-Instruct/config.json");
         let config = HFConfig::from_json_file(&config_file.display().to_string()).await?;
         assert_eq!(config.bos_token_id(), 200000);
+        assert!(config.supports_images());
         Ok(())
     }
 }
@@ -26,6 +26,11 @@ pub enum ModelType {
     Embedding,
     // Pre-processed requests
     Backend,
+    /// Pre-processed requests, prefill only. The prefill workers of disaggregated serving.
+    Prefill,
+    /// Pre-processed requests, from a small model guessing what the bigger model of the same name
+    /// generates next. The draft workers of speculative decoding.
+    Draft,
 }
 
 impl ModelType {
@@ -35,10 +40,19 @@ impl ModelType {
             Self::Completion => "completion",
             Self::Embedding => "embedding",
             Self::Backend => "backend",
+            Self::Prefill => "prefill",
+            Self::Draft => "draft",
         }
     }
 
     pub fn all() -> Vec<Self> {
-        vec![Self::Chat, Self::Completion, Self::Embedding, Self::Backend]
+        vec![
+            Self::Chat,
+            Self::Completion,
+            Self::Embedding,
+            Self::Backend,
+            Self::Prefill,
+            Self::Draft,
+        ]
     }
 }
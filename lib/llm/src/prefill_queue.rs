@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bounds the remote prefills of a model in disaggregated serving.
+//!
+//! The prefill workers take the long prompts of all the decode workers. When they fall behind,
+//! waiting for them costs more time to first token than prefilling on the decode worker. The
+//! [`PrefillQueue`] lets `max_inflight` remote prefills run at once and `max_waiting` more wait up
+//! to `max_wait` for their turn. The others are prefilled by their decode worker.
+
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use prometheus::{IntGauge, IntGaugeVec, Opts};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+static WAITING: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "dynamo_disagg_prefill_waiting_requests",
+            "Requests waiting for a remote prefill slot, by model",
+        ),
+        &["model"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the prefill queue metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(WAITING.clone()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefillQueueConfig {
+    /// Remote prefills in progress at once
+    pub max_inflight: usize,
+
+    /// Requests waiting for a remote prefill. With 0 requests are prefilled locally as soon as
+    /// all slots are busy.
+    pub max_waiting: usize,
+
+    /// How long a request waits before it is prefilled locally
+    pub max_wait: Duration,
+}
+
+impl Default for PrefillQueueConfig {
+    fn default() -> Self {
+        PrefillQueueConfig {
+            max_inflight: 64,
+            max_waiting: 256,
+            max_wait: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Why a request doesn't get a remote prefill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueRejection {
+    Full,
+    Timeout,
+}
+
+impl QueueRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueueRejection::Full => "queue_full",
+            QueueRejection::Timeout => "queue_timeout",
+        }
+    }
+}
+
+/// Counts a request as waiting until dropped, also when the caller gives up while waiting
+struct Waiting<'a> {
+    _permit: SemaphorePermit<'a>,
+    gauge: &'a IntGauge,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(permit: SemaphorePermit<'a>, gauge: &'a IntGauge) -> Self {
+        gauge.inc();
+        Waiting {
+            _permit: permit,
+            gauge,
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// The remote prefill slots of one model, see the [module docs](self)
+pub struct PrefillQueue {
+    config: PrefillQueueConfig,
+    inflight: Arc<Semaphore>,
+    waiting: Semaphore,
+    waiting_gauge: IntGauge,
+}
+
+impl PrefillQueue {
+    pub fn new(model: &str, config: PrefillQueueConfig) -> Self {
+        PrefillQueue {
+            config,
+            inflight: Arc::new(Semaphore::new(config.max_inflight)),
+            waiting: Semaphore::new(config.max_waiting),
+            waiting_gauge: WAITING.with_label_values(&[model]),
+        }
+    }
+
+    /// A remote prefill slot, held until the prefill is done
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, QueueRejection> {
+        if let Ok(permit) = self.inflight.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        let Ok(permit) = self.waiting.try_acquire() else {
+            return Err(QueueRejection::Full);
+        };
+        let _waiting = Waiting::new(permit, &self.waiting_gauge);
+        match tokio::time::timeout(self.config.max_wait, self.inflight.clone().acquire_owned())
+            .await
+        {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed
+            Ok(Err(_)) | Err(_) => Err(QueueRejection::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire() {
+        let queue = PrefillQueue::new(
+            "test_acquire",
+            PrefillQueueConfig {
+                max_inflight: 1,
+                max_waiting: 1,
+                max_wait: Duration::from_millis(100),
+            },
+        );
+        let first = queue.acquire().await.unwrap();
+
+        // One waits, the next one finds the queue full
+        let waiting = queue.acquire();
+        tokio::pin!(waiting);
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        assert_eq!(queue.acquire().await.unwrap_err(), QueueRejection::Full);
+
+        // The waiting one gets the slot when it frees up
+        drop(first);
+        let second = waiting.await.unwrap();
+        assert_eq!(queue.waiting_gauge.get(), 0);
+
+        // Nobody frees it up in time
+        assert_eq!(queue.acquire().await.unwrap_err(), QueueRejection::Timeout);
+        drop(second);
+        queue.acquire().await.unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Disaggregated serving: the prompt is prefilled on one worker and decoded on another.
+//!
+//! Prefill workers register the model as [`ModelType::Prefill`](crate::model_type::ModelType),
+//! next to the decode workers that register it as `Backend`. For the long prompts, the
+//! [`PrefillRouter`] first sends the request to a prefill worker, for one token and with
+//! `kv_transfer_params` asking it to keep the KV cache for a remote decode. The prefill worker
+//! answers with the `kv_transfer_params` of where the decode worker fetches the KV cache, which
+//! the router passes on to a decode worker with the request. The KV connectors of the engines,
+//! e.g. vLLM's NixlConnector, do the transfer, Dynamo only carries the metadata.
+//!
+//! Short prompts are prefilled by the decode worker, as are all prompts while the model has no
+//! prefill workers, when the [`PrefillQueue`] is full, and when the remote prefill fails.
+
+use std::sync::{Arc, LazyLock, RwLock};
+
+use anyhow::Context as _;
+use dynamo_runtime::{
+    pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContextProvider, Context, Error, ManyOut,
+        ResponseStream, SingleIn,
+    },
+    protocols::annotated::Annotated,
+};
+use futures::StreamExt;
+use prometheus::{IntCounterVec, Opts};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    backend::ExecutionContext,
+    disagg_router::{DisaggRouterConf, DisaggregatedRouter},
+    prefill_queue::{PrefillQueue, PrefillQueueConfig},
+    preprocessor::PreprocessedRequest,
+    protocols::common::{llm_backend::LLMEngineOutput, FinishReason},
+    reschedule::forward_cancellation,
+};
+
+static PREFILLS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_disagg_prefill_requests_total",
+            "Requests of models with prefill workers, by model and where they were prefilled (remote, local, queue_full, queue_timeout or failed)",
+        ),
+        &["model", "outcome"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the disaggregated serving metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(PREFILLS.clone()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisaggConfig {
+    /// Longer prompts are prefilled on a prefill worker. Can be changed at runtime per model in
+    /// etcd, see [`DisaggRouterConf`].
+    pub max_local_prefill_length: i32,
+
+    /// Bounds on the remote prefills of each model
+    pub queue: PrefillQueueConfig,
+}
+
+impl Default for DisaggConfig {
+    fn default() -> Self {
+        DisaggConfig {
+            max_local_prefill_length: DisaggRouterConf::default().max_local_prefill_length,
+            queue: PrefillQueueConfig::default(),
+        }
+    }
+}
+
+/// The engine sending requests to the prefill workers of a model, while it has some
+#[derive(Default)]
+pub struct PrefillWorkers {
+    engine: RwLock<Option<ExecutionContext>>,
+}
+
+impl PrefillWorkers {
+    pub fn set(&self, engine: ExecutionContext) {
+        *self.engine.write().unwrap() = Some(engine);
+    }
+
+    pub fn clear(&self) {
+        *self.engine.write().unwrap() = None;
+    }
+
+    pub fn get(&self) -> Option<ExecutionContext> {
+        self.engine.read().unwrap().clone()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.engine.read().unwrap().is_some()
+    }
+}
+
+/// Sends requests to the decode workers through `decode`, after prefilling them on a prefill
+/// worker when worth it. See the [module docs](self).
+pub struct PrefillRouter {
+    model: String,
+    decode: ExecutionContext,
+    workers: Arc<PrefillWorkers>,
+    policy: DisaggregatedRouter,
+    queue: Arc<PrefillQueue>,
+}
+
+impl PrefillRouter {
+    pub fn new(
+        model: &str,
+        decode: ExecutionContext,
+        workers: Arc<PrefillWorkers>,
+        policy: DisaggregatedRouter,
+        queue: Arc<PrefillQueue>,
+    ) -> Self {
+        PrefillRouter {
+            model: model.to_string(),
+            decode,
+            workers,
+            policy,
+            queue,
+        }
+    }
+
+    fn count(&self, outcome: &str) {
+        PREFILLS.with_label_values(&[&self.model, outcome]).inc();
+    }
+
+    /// Prefill the prompt of `request` with `prefill`, returns where the decode worker finds its
+    /// KV cache
+    async fn prefill(
+        &self,
+        prefill: &ExecutionContext,
+        request: &SingleIn<PreprocessedRequest>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let request_ctx = request.context();
+        let mut prefill_request = PreprocessedRequest::clone(request);
+        prefill_request.stop_conditions.max_tokens = Some(1);
+        prefill_request.stop_conditions.min_tokens = Some(1);
+        prefill_request.annotations.clear();
+        prefill_request.kv_transfer_params = Some(serde_json::json!({"do_remote_decode": true}));
+
+        // Its own controller, stop and kill from the caller are forwarded
+        let prefill_request = Context::with_id(prefill_request, request_ctx.id().to_string());
+        let forwarding = CancellationToken::new();
+        tokio::spawn(forward_cancellation(
+            request_ctx,
+            prefill_request.context(),
+            forwarding.clone(),
+        ));
+        let _forwarding = forwarding.drop_guard();
+
+        let mut responses = prefill.generate(prefill_request).await?;
+        let mut kv_transfer_params = None;
+        while let Some(response) = responses.next().await {
+            let Some(output) = response.ok().map_err(anyhow::Error::msg)?.data else {
+                continue;
+            };
+            if let Some(FinishReason::Error(err)) = output.finish_reason {
+                anyhow::bail!(err);
+            }
+            if output.kv_transfer_params.is_some() {
+                kv_transfer_params = output.kv_transfer_params;
+            }
+        }
+        kv_transfer_params.context("The prefill worker sent no kv_transfer_params")
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for PrefillRouter
+{
+    async fn generate(
+        &self,
+        mut request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let Some(prefill) = self.workers.get() else {
+            return self.decode.generate(request).await;
+        };
+        // The caller prefilled the request already
+        if request.kv_transfer_params.is_some() {
+            return self.decode.generate(request).await;
+        }
+        // The decode worker isn't chosen yet, so its prefix cache hit is unknown
+        if !self
+            .policy
+            .prefill_remote(request.token_ids.len() as i32, 0)
+        {
+            self.count("local");
+            return self.decode.generate(request).await;
+        }
+        let permit = match self.queue.acquire().await {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                self.count(rejection.as_str());
+                return self.decode.generate(request).await;
+            }
+        };
+
+        match self.prefill(&prefill, &request).await {
+            Ok(kv_transfer_params) => {
+                self.count("remote");
+                request.kv_transfer_params = Some(kv_transfer_params);
+            }
+            Err(_) if request.context().is_stopped() => {
+                // The caller gave up, no point in decoding
+                return Ok(ResponseStream::new(
+                    Box::pin(futures::stream::empty()),
+                    request.context(),
+                ));
+            }
+            Err(err) => {
+                tracing::warn!(
+                    request_id = request.id(),
+                    model = self.model,
+                    error = format!("{err:#}"),
+                    "Remote prefill failed, prefilling on the decode worker"
+                );
+                self.count("failed");
+            }
+        }
+        drop(permit);
+        self.decode.generate(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::common::{SamplingOptions, StopConditions};
+    use std::sync::Mutex;
+
+    /// Records the requests it gets. As a prefill worker, answers with `kv_transfer_params`.
+    #[derive(Default)]
+    struct Worker {
+        requests: Mutex<Vec<PreprocessedRequest>>,
+        kv_transfer_params: Option<serde_json::Value>,
+    }
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+        for Worker
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<PreprocessedRequest>,
+        ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push(PreprocessedRequest::clone(&request));
+            let output = LLMEngineOutput {
+                token_ids: vec![7],
+                kv_transfer_params: self.kv_transfer_params.clone(),
+                ..LLMEngineOutput::stop()
+            };
+            Ok(ResponseStream::new(
+                Box::pin(futures::stream::iter(vec![Annotated::from_data(output)])),
+                request.context(),
+            ))
+        }
+    }
+
+    fn request(prompt_len: usize) -> SingleIn<PreprocessedRequest> {
+        let request = PreprocessedRequest::builder()
+            .token_ids(vec![1; prompt_len])
+            .stop_conditions(StopConditions {
+                max_tokens: Some(100),
+                ..Default::default()
+            })
+            .sampling_options(SamplingOptions::default())
+            .build()
+            .unwrap();
+        Context::new(request)
+    }
+
+    #[tokio::test]
+    async fn test_prefill_router() {
+        let params = serde_json::json!({"remote_block_ids": [1, 2]});
+        let decode = Arc::new(Worker::default());
+        let prefill = Arc::new(Worker {
+            kv_transfer_params: Some(params.clone()),
+            ..Default::default()
+        });
+        let workers = Arc::new(PrefillWorkers::default());
+        let router = PrefillRouter::new(
+            "test_prefill_router",
+            decode.clone(),
+            workers.clone(),
+            DisaggregatedRouter::new(10, "test_prefill_router".to_string()),
+            Arc::new(PrefillQueue::new(
+                "test_prefill_router",
+                PrefillQueueConfig::default(),
+            )),
+        );
+
+        // No prefill workers yet
+        router.generate(request(100)).await.unwrap();
+        assert!(decode.requests.lock().unwrap()[0]
+            .kv_transfer_params
+            .is_none());
+
+        // Long prompts go through the prefill worker, short ones don't
+        workers.set(prefill.clone());
+        router.generate(request(100)).await.unwrap();
+        router.generate(request(5)).await.unwrap();
+        let prefilled = prefill.requests.lock().unwrap();
+        assert_eq!(prefilled.len(), 1);
+        assert_eq!(prefilled[0].stop_conditions.max_tokens, Some(1));
+        assert_eq!(
+            prefilled[0].kv_transfer_params,
+            Some(serde_json::json!({"do_remote_decode": true}))
+        );
+        let decoded = decode.requests.lock().unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[1].kv_transfer_params, Some(params));
+        assert_eq!(decoded[1].stop_conditions.max_tokens, Some(100));
+        assert!(decoded[2].kv_transfer_params.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_prefill_failure() {
+        // A prefill worker that doesn't say where its KV cache is
+        let decode = Arc::new(Worker::default());
+        let workers = Arc::new(PrefillWorkers::default());
+        workers.set(Arc::new(Worker::default()));
+        let router = PrefillRouter::new(
+            "test_prefill_failure",
+            decode.clone(),
+            workers,
+            DisaggregatedRouter::new(10, "test_prefill_failure".to_string()),
+            Arc::new(PrefillQueue::new(
+                "test_prefill_failure",
+                PrefillQueueConfig::default(),
+            )),
+        );
+
+        router.generate(request(100)).await.unwrap();
+        let decoded = decode.requests.lock().unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert!(decoded[0].kv_transfer_params.is_none());
+    }
+}
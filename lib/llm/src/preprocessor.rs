@@ -23,16 +23,25 @@
 //!
 //! The Preprocessor will accept any IngressRequest and transform it to a BackendRequest.
 
+pub mod compression;
+pub mod images;
 pub mod prompt;
 pub mod tools;
 
 use anyhow::Result;
+use compression::PromptCompressor;
 use futures::stream::{self, StreamExt};
+use images::ImageFetcher;
 use prompt::OAIPromptFormatter;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tracing;
 
-use crate::model_card::model::{ModelDeploymentCard, ModelInfo, TokenizerKind};
+use crate::http::service::error::HttpError;
+use crate::lora::{LoraRegistry, LoraRequest};
+use crate::model_card::model::{
+    EngineCapabilities, GenerationLimits, ModelDeploymentCard, ModelInfo, SamplingPresets,
+    SpecialTokens,
+};
 use crate::preprocessor::prompt::OAIChatLikeRequest;
 use crate::tokenizers::Encoding;
 
@@ -43,48 +52,92 @@ use dynamo_runtime::pipeline::{
 use dynamo_runtime::protocols::annotated::{Annotated, AnnotationsProvider};
 
 use crate::protocols::{
-    common::{SamplingOptionsProvider, StopConditionsProvider},
+    common::{
+        FinishReason, GuidedDecodingProvider, SamplingOptions, SamplingOptionsProvider,
+        StopConditionsProvider,
+    },
     openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
         completions::{CompletionResponse, NvCreateCompletionRequest},
+        continuation::Continuation,
         nvext::NvExtProvider,
         DeltaGeneratorExt,
     },
 };
-use crate::tokenizers::{traits::Tokenizer, HuggingFaceTokenizer};
+use crate::tokenizers::registry::{self, SharedTokenizer};
+use crate::tokenizers::traits::Encoder;
 
 use crate::preprocessor::prompt::PromptFormatter;
 
 pub use crate::protocols::common::llm_backend::{BackendOutput, PreprocessedRequest};
+use crate::protocols::common::preprocessor::EncodedImage;
 
 pub const ANNOTATION_FORMATTED_PROMPT: &str = "formatted_prompt";
 pub const ANNOTATION_TOKEN_IDS: &str = "token_ids";
+/// Always sent when the ingress changed the request, e.g. removed parameters the engine lacks
+pub const ANNOTATION_WARNINGS: &str = "warnings";
 
 pub struct OpenAIPreprocessor {
     mdcsum: String,
+    /// In tokens, 0 if unknown
+    context_length: usize,
     formatter: Arc<dyn OAIPromptFormatter>,
-    tokenizer: Arc<dyn Tokenizer>,
+    tokenizer: Arc<SharedTokenizer>,
     model_info: Arc<dyn ModelInfo>,
+    generation_limits: GenerationLimits,
+    special_tokens: SpecialTokens,
+    sampling_presets: SamplingPresets,
+    compressor: Option<PromptCompressor>,
+    engine_capabilities: EngineCapabilities,
+    images: ImageFetcher,
+    loras: Option<Arc<LoraRegistry>>,
+}
+
+/// A request with the model's `add_generation_prompt` setting instead of its own
+struct GenerationPrompt<'a> {
+    request: &'a dyn OAIChatLikeRequest,
+    add_generation_prompt: bool,
+}
+
+impl OAIChatLikeRequest for GenerationPrompt<'_> {
+    fn messages(&self) -> minijinja::value::Value {
+        self.request.messages()
+    }
+
+    fn tools(&self) -> Option<minijinja::value::Value> {
+        self.request.tools()
+    }
+
+    fn tool_choice(&self) -> Option<minijinja::value::Value> {
+        self.request.tool_choice()
+    }
+
+    fn should_add_generation_prompt(&self) -> bool {
+        self.add_generation_prompt
+    }
 }
 
 impl OpenAIPreprocessor {
     pub async fn new(mdc: ModelDeploymentCard) -> Result<Arc<Self>> {
+        Self::new_with_loras(mdc, None).await
+    }
+
+    /// A preprocessor that serves the requests for the LoRA adapters of `loras` with their adapter
+    pub async fn new_with_loras(
+        mdc: ModelDeploymentCard,
+        loras: Option<Arc<LoraRegistry>>,
+    ) -> Result<Arc<Self>> {
         let mdcsum = mdc.mdcsum();
         let formatter = PromptFormatter::from_mdc(mdc.clone()).await?;
         let PromptFormatter::OAI(formatter) = formatter;
 
-        let tokenizer = match &mdc.tokenizer {
-            Some(TokenizerKind::HfTokenizerJson(file)) => HuggingFaceTokenizer::from_file(file)?,
-            Some(TokenizerKind::GGUF(tokenizer)) => {
-                HuggingFaceTokenizer::from_tokenizer(*tokenizer.clone())
-            }
-            None => {
-                anyhow::bail!(
-                    "Blank ModelDeploymentCard cannot be used for pre-processing, no tokenizer"
-                );
-            }
-        };
-        let tokenizer = Arc::new(tokenizer);
+        if !mdc.has_tokenizer() {
+            anyhow::bail!(
+                "Blank ModelDeploymentCard cannot be used for pre-processing, no tokenizer"
+            );
+        }
+        // Loads in the background, requests wait for it in `generate`
+        let tokenizer = registry::load(&mdc)?;
 
         let Some(model_info) = mdc.model_info else {
             anyhow::bail!(
@@ -92,15 +145,40 @@ impl OpenAIPreprocessor {
             );
         };
         let model_info = model_info.get_model_info().await?;
+        let compressor = PromptCompressor::new(mdc.prompt_compression, &mdc.service_name)?;
 
         Ok(Arc::new(Self {
             formatter,
             tokenizer,
             model_info,
             mdcsum,
+            context_length: mdc.context_length,
+            generation_limits: mdc.generation_limits,
+            special_tokens: mdc.special_tokens,
+            sampling_presets: mdc.sampling_presets,
+            compressor,
+            engine_capabilities: mdc.engine_capabilities,
+            images: ImageFetcher::new()?,
+            loras,
         }))
     }
 
+    /// The adapter of the requests for `model`, if it is a LoRA adapter
+    fn lora(&self, model: &str) -> Option<LoraRequest> {
+        self.loras.as_ref()?.request(model)
+    }
+
+    /// Apply the prompt template, with the model's `add_generation_prompt` setting if it has one
+    fn render(&self, request: &dyn OAIChatLikeRequest) -> Result<String> {
+        match self.special_tokens.add_generation_prompt {
+            Some(add_generation_prompt) => self.formatter.render(&GenerationPrompt {
+                request,
+                add_generation_prompt,
+            }),
+            None => self.formatter.render(request),
+        }
+    }
+
     /// Encode a string to it's tokens
     pub fn tokenize(&self, s: &str) -> anyhow::Result<Encoding> {
         self.tokenizer.encode(s)
@@ -112,11 +190,13 @@ impl OpenAIPreprocessor {
     /// Annotations evaluated by this method include:
     /// - `formatted_prompt`
     /// - `token_ids`
+    /// - `warnings`, without being requested
     pub fn preprocess_request<
         R: OAIChatLikeRequest
             + AnnotationsProvider
             + SamplingOptionsProvider
             + StopConditionsProvider
+            + GuidedDecodingProvider
             + NvExtProvider,
     >(
         &self,
@@ -125,68 +205,231 @@ impl OpenAIPreprocessor {
         let mut annotations = HashMap::new();
         let mut builder = PreprocessedRequest::builder();
 
-        let use_raw_prompt = request
-            .nvext()
-            .is_some_and(|ext| ext.use_raw_prompt.unwrap_or(false));
+        let token_ids = match request.prompt_token_ids() {
+            // Already tokenized by the client, nothing to format
+            Some(token_ids) => token_ids,
+            None => {
+                let use_raw_prompt = request
+                    .nvext()
+                    .is_some_and(|ext| ext.use_raw_prompt.unwrap_or(false));
+
+                let formatted_prompt = if use_raw_prompt {
+                    match request.raw_prompt() {
+                        Some(prompt) => prompt,
+                        None => {
+                            tracing::warn!("Raw prompt requested but not available");
+                            self.render(request)?
+                        }
+                    }
+                } else {
+                    self.render(request)?
+                };
+
+                let encoding =
+                    tokio::task::block_in_place(|| self.tokenizer.encode(&formatted_prompt))?;
 
-        let formatted_prompt = if use_raw_prompt {
-            match request.raw_prompt() {
-                Some(prompt) => prompt,
-                None => {
-                    tracing::warn!("Raw prompt requested but not available");
-                    self.formatter.render(request)?
+                if request.has_annotation(ANNOTATION_FORMATTED_PROMPT) {
+                    annotations.insert(ANNOTATION_FORMATTED_PROMPT.to_string(), formatted_prompt);
                 }
+                let mut token_ids = encoding.token_ids;
+                self.special_tokens
+                    .apply_bos(&mut token_ids, self.model_info.bos_token_id());
+                token_ids
             }
-        } else {
-            self.formatter.render(request)?
         };
 
-        let encoding = tokio::task::block_in_place(|| self.tokenizer.encode(&formatted_prompt))?;
-
-        if request.has_annotation(ANNOTATION_FORMATTED_PROMPT) {
-            annotations.insert(ANNOTATION_FORMATTED_PROMPT.to_string(), formatted_prompt);
-        }
-
         if request.has_annotation(ANNOTATION_TOKEN_IDS) {
             annotations.insert(
                 ANNOTATION_TOKEN_IDS.to_string(),
-                serde_json::to_string(&encoding.token_ids)?,
+                serde_json::to_string(&token_ids)?,
             );
         }
 
         let mut stop_conditions = request.extract_stop_conditions()?;
-        if let Some(stop_tokens) = &mut stop_conditions.stop_token_ids_hidden {
-            for eos_token in self.model_info.eos_token_ids() {
-                if !stop_tokens.contains(&eos_token) {
-                    stop_tokens.push(eos_token);
-                }
+        stop_conditions.max_tokens = self
+            .generation_limits
+            .effective_max_tokens(stop_conditions.max_tokens)
+            .map_err(|message| HttpError { code: 400, message })?;
+        let eos_token_ids = self
+            .special_tokens
+            .eos_token_ids(self.model_info.eos_token_ids());
+        let stop_tokens = stop_conditions
+            .stop_token_ids_hidden
+            .get_or_insert_with(Vec::new);
+        for token_id in eos_token_ids
+            .iter()
+            .chain(&self.special_tokens.stop_token_ids)
+        {
+            if !stop_tokens.contains(token_id) {
+                stop_tokens.push(*token_id);
             }
-        } else {
-            stop_conditions.stop_token_ids_hidden = Some(self.model_info.eos_token_ids());
         }
 
         // apply ignore eos if not already set
         stop_conditions.apply_ignore_eos();
 
         if !stop_conditions.ignore_eos.unwrap_or(false) {
-            builder.eos_token_ids(self.model_info.eos_token_ids());
+            builder.eos_token_ids(eos_token_ids);
+        }
+
+        let mut sampling_options = self.sampling_options(request)?;
+        let stripped = self
+            .engine_capabilities
+            .strip_unsupported(&mut sampling_options);
+        if !stripped.is_empty() {
+            let engine = self
+                .engine_capabilities
+                .engine
+                .as_deref()
+                .unwrap_or("the engine");
+            let warnings: Vec<String> = stripped
+                .iter()
+                .map(|param| format!("'{param}' is not supported by {engine} and was ignored"))
+                .collect();
+            tracing::debug!(?stripped, engine, "Removed unsupported sampling parameters");
+            annotations.insert(
+                ANNOTATION_WARNINGS.to_string(),
+                serde_json::to_string(&warnings)?,
+            );
         }
 
-        builder.token_ids(encoding.token_ids);
-        builder.sampling_options(request.extract_sampling_options()?);
+        builder.token_ids(token_ids);
+        builder.sampling_options(sampling_options);
         builder.stop_conditions(stop_conditions);
         builder.annotations(request.annotations().unwrap_or_default());
         builder.mdc_sum(Some(self.mdcsum.clone()));
         builder.estimated_prefix_hit_num_blocks(None);
+        builder.priority(request.nvext().and_then(|ext| ext.priority));
+        builder.nvext(request.nvext().cloned());
+        let guided_decoding = request.extract_guided_decoding().map_err(|err| HttpError {
+            code: 400,
+            message: err.to_string(),
+        })?;
+        builder.guided_decoding(guided_decoding);
 
         Ok((builder.build()?, annotations))
     }
 
+    /// The request's sampling options, with those it didn't set from its `nvext.sampling_preset`
+    fn sampling_options<R: SamplingOptionsProvider + NvExtProvider>(
+        &self,
+        request: &R,
+    ) -> Result<SamplingOptions> {
+        let mut options = request.extract_sampling_options()?;
+        let Some(nvext) = request.nvext() else {
+            return Ok(options);
+        };
+        let Some(name) = nvext.sampling_preset.as_deref() else {
+            return Ok(options);
+        };
+        let Some(mut preset) = self.sampling_presets.get(name) else {
+            return Err(HttpError {
+                code: 400,
+                message: format!(
+                    "Unknown sampling preset '{name}', this model has: {}",
+                    self.sampling_presets.names().join(", ")
+                ),
+            }
+            .into());
+        };
+        if nvext.greed_sampling.unwrap_or(false) {
+            // Greedy sampling wins over the preset
+            preset.temperature = None;
+            preset.top_p = None;
+        }
+        preset.apply(&mut options);
+        Ok(options)
+    }
+
+    /// Shorten the text of `prompt`, chat messages or a completions prompt as JSON, see
+    /// [`compression`]. Returns whether it changed.
+    async fn compress(&self, prompt: &mut serde_json::Value) -> Result<bool> {
+        let Some(compressor) = &self.compressor else {
+            return Ok(false);
+        };
+        let count_tokens = |text: &str| {
+            let encoding = tokio::task::block_in_place(|| self.tokenizer.encode(text))?;
+            Ok(encoding.token_ids.len())
+        };
+        Ok(compressor.compress(prompt, count_tokens).await? > 0)
+    }
+
+    /// Fetch the images of the chat messages. Models without vision support don't take any.
+    async fn fetch_images(
+        &self,
+        request: &NvCreateChatCompletionRequest,
+    ) -> Result<Vec<EncodedImage>> {
+        let urls = request.image_urls();
+        if urls.is_empty() {
+            return Ok(vec![]);
+        }
+        if !self.model_info.supports_images() {
+            return Err(HttpError {
+                code: 400,
+                message: format!(
+                    "Model {} does not support images, send text content only",
+                    request.inner.model
+                ),
+            }
+            .into());
+        }
+        let images = self
+            .images
+            .fetch_all(&urls)
+            .await
+            .map_err(|err| HttpError {
+                code: 400,
+                message: format!("{err:#}"),
+            })?;
+        Ok(images)
+    }
+
+    /// Resume generating the response from the continuation token in the `nvext` of `request`,
+    /// or start tracking the response for one if the client asked for it. What was generated
+    /// before is appended to the prompt, and the segment is sampled with a seed of its own.
+    fn continuation(&self, request: &mut PreprocessedRequest) -> Result<Option<Continuation>> {
+        let Some(nvext) = request.nvext.as_mut() else {
+            return Ok(None);
+        };
+        // The token can be large, the workers don't need it
+        let continuation = match nvext.continuation.take() {
+            Some(token) => {
+                let continuation = Continuation::decode(&token).map_err(|err| HttpError {
+                    code: 400,
+                    message: err.to_string(),
+                })?;
+                let context_length = (self.context_length > 0).then_some(self.context_length);
+                continuation
+                    .check(
+                        &self.mdcsum,
+                        &request.token_ids,
+                        self.model_info.vocab_size(),
+                        context_length,
+                    )
+                    .map_err(|message| HttpError { code: 400, message })?;
+                continuation
+            }
+            None if nvext.return_continuation.unwrap_or(false) => Continuation::new(
+                &self.mdcsum,
+                &request.token_ids,
+                request.sampling_options.seed,
+            ),
+            None => return Ok(None),
+        };
+        request
+            .token_ids
+            .extend_from_slice(continuation.generated());
+        request.sampling_options.seed = Some(continuation.segment_seed());
+        Ok(Some(continuation))
+    }
+
     pub fn transform_postprocessor_stream<Resp: Send + Sync + 'static + std::fmt::Debug>(
         stream: ManyOut<Annotated<BackendOutput>>,
         generator: Box<dyn DeltaGeneratorExt<Resp>>,
+        max_generation_time: Option<Duration>,
     ) -> ManyOut<Annotated<Resp>> {
         let context = stream.context();
+        let deadline = max_generation_time.map(|d| tokio::time::Instant::now() + d);
 
         struct State<Resp: Send + Sync + 'static + std::fmt::Debug> {
             response_stream: ManyOut<Annotated<BackendOutput>>,
@@ -194,6 +437,12 @@ impl OpenAIPreprocessor {
             context: Arc<dyn AsyncEngineContext>,
             cancelled: bool,
             cumulative_output_tokens: usize,
+            deadline: Option<tokio::time::Instant>,
+            finished: bool,
+            // the stream underneath ended, or we stopped waiting for it
+            ended: bool,
+            flushed: bool,
+            usage_sent: bool,
         }
 
         let state = State {
@@ -202,12 +451,52 @@ impl OpenAIPreprocessor {
             context: context.clone(),
             cancelled: false,
             cumulative_output_tokens: 0,
+            deadline,
+            finished: false,
+            ended: false,
+            flushed: false,
+            usage_sent: false,
         };
 
         // transform the common response stream into a chat response stream
         let stream = stream::unfold(state, |mut inner| {
             async move {
-                if let Some(response) = inner.response_stream.next().await {
+                if inner.usage_sent {
+                    // the usage is last, the stream underneath already ended
+                    return None;
+                }
+                let next = match inner.deadline {
+                    // Don't poll it again, nor wait for what the engine sends after it was told
+                    // to stop
+                    _ if inner.ended => None,
+                    Some(deadline) => {
+                        match tokio::time::timeout_at(deadline, inner.response_stream.next()).await
+                        {
+                            Ok(next) => next,
+                            Err(_) => {
+                                tracing::warn!(
+                                    request_id = inner.context.id(),
+                                    "Generation time limit reached; stopping generation"
+                                );
+                                inner.context.stop_generating();
+                                inner.ended = true;
+                                // Tell the client why the response stopped
+                                (!inner.finished).then(|| {
+                                    Annotated::from_data(BackendOutput {
+                                        token_ids: vec![],
+                                        tokens: vec![],
+                                        text: None,
+                                        cum_log_probs: None,
+                                        log_probs: None,
+                                        finish_reason: Some(FinishReason::Length),
+                                    })
+                                })
+                            }
+                        }
+                    }
+                    None => inner.response_stream.next().await,
+                };
+                if let Some(response) = next {
                     if inner.cancelled {
                         tracing::debug!(
                             request_id = inner.context.id(),
@@ -223,6 +512,7 @@ impl OpenAIPreprocessor {
                     );
 
                     let (chunk_tokens, isl) = if let Some(ref backend_output) = response.data {
+                        inner.finished |= backend_output.finish_reason.is_some();
                         let chunk_tokens = backend_output.token_ids.len();
                         inner.cumulative_output_tokens += chunk_tokens;
 
@@ -261,6 +551,28 @@ impl OpenAIPreprocessor {
                         response
                     );
 
+                    Some((response, inner))
+                } else if !inner.cancelled {
+                    inner.ended = true;
+                    // text the generator held back, if the engine didn't finish the response
+                    if !std::mem::replace(&mut inner.flushed, true) {
+                        if let Some(chunk) = inner.response_generator.flush() {
+                            let mut response = Annotated::from_data(chunk);
+                            response.chunk_tokens = Some(0);
+                            response.input_tokens =
+                                Some(inner.response_generator.get_isl().unwrap_or(0) as usize);
+                            response.output_tokens = Some(inner.cumulative_output_tokens);
+                            return Some((response, inner));
+                        }
+                    }
+                    // the usage of the whole request goes last, if the client asked for it
+                    inner.usage_sent = true;
+                    let chunk = inner.response_generator.usage_chunk()?;
+                    let mut response = Annotated::from_data(chunk);
+                    response.chunk_tokens = Some(0);
+                    response.input_tokens =
+                        Some(inner.response_generator.get_isl().unwrap_or(0) as usize);
+                    response.output_tokens = Some(inner.cumulative_output_tokens);
                     Some((response, inner))
                 } else {
                     // stream closed with out graceful closure
@@ -301,14 +613,32 @@ impl
         >,
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
         // unpack the request
-        let (request, context) = request.into_parts();
+        let (mut request, context) = request.into_parts();
 
-        // create a response generator
-        let response_generator = request.response_generator();
-        let mut response_generator = Box::new(response_generator);
+        // a model that can't take the images fails the request before it's templated
+        let images = self.fetch_images(&request).await?;
 
         // convert the chat completion request to a common completion request
-        let (common_request, annotations) = self.preprocess_request(&request)?;
+        self.tokenizer.wait().await?;
+        if self.compressor.is_some() {
+            let mut messages = serde_json::to_value(&request.inner.messages)?;
+            if self.compress(&mut messages).await? {
+                request.inner.messages = serde_json::from_value(messages)?;
+            }
+        }
+        let (mut common_request, annotations) = self.preprocess_request(&request)?;
+        common_request.images = images;
+        common_request.lora = self.lora(&request.inner.model);
+        let continuation = self.continuation(&mut common_request)?;
+
+        // create a response generator
+        let mut response_generator = request.response_generator();
+        if continuation.is_some() {
+            response_generator = response_generator
+                .with_seed(common_request.sampling_options.seed)
+                .with_continuation(continuation);
+        }
+        let mut response_generator = Box::new(response_generator);
 
         // update isl
         response_generator.update_isl(common_request.token_ids.len() as u32);
@@ -327,7 +657,11 @@ impl
         let response_stream = next.generate(common_request).await?;
 
         // transform the postprocessor stream
-        let stream = Self::transform_postprocessor_stream(response_stream, response_generator);
+        let stream = Self::transform_postprocessor_stream(
+            response_stream,
+            response_generator,
+            self.generation_limits.max_generation_time(),
+        );
         let context = stream.context();
 
         // prepend the annotations to the response stream
@@ -359,13 +693,28 @@ impl
         >,
     ) -> Result<ManyOut<Annotated<CompletionResponse>>, Error> {
         // unpack the request
-        let (request, context) = request.into_parts();
+        let (mut request, context) = request.into_parts();
+
+        // convert the completion request to a common completion request
+        self.tokenizer.wait().await?;
+        if self.compressor.is_some() {
+            let mut prompt = serde_json::to_value(&request.inner.prompt)?;
+            if self.compress(&mut prompt).await? {
+                request.inner.prompt = serde_json::from_value(prompt)?;
+            }
+        }
+        let (mut common_request, annotations) = self.preprocess_request(&request)?;
+        common_request.lora = self.lora(&request.inner.model);
+        let continuation = self.continuation(&mut common_request)?;
 
         // create a response generator
-        let response_generator = request.response_generator();
+        let mut response_generator = request.response_generator();
+        if continuation.is_some() {
+            response_generator = response_generator
+                .with_seed(common_request.sampling_options.seed)
+                .with_continuation(continuation);
+        }
         let mut response_generator = Box::new(response_generator);
-        // convert the chat completion request to a common completion request
-        let (common_request, annotations) = self.preprocess_request(&request)?;
 
         // update isl
         response_generator.update_isl(common_request.token_ids.len() as i32);
@@ -384,7 +733,11 @@ impl
         let response_stream = next.generate(common_request).await?;
 
         // transform the postprocessor stream
-        let stream = Self::transform_postprocessor_stream(response_stream, response_generator);
+        let stream = Self::transform_postprocessor_stream(
+            response_stream,
+            response_generator,
+            self.generation_limits.max_generation_time(),
+        );
         let context = stream.context();
 
         // prepend the annotations to the response stream
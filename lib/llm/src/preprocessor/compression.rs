@@ -0,0 +1,328 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Prompt compression: shorten the text of a request before it is templated and tokenized.
+//!
+//! RAG prompts carry the same retrieved passages again and again, and text pasted from documents
+//! is full of padding. The strategies, each enabled in [`PromptCompression`]:
+//!
+//! - collapse whitespace: runs of spaces and tabs become one space, trailing spaces are removed
+//!   and runs of blank lines become one blank line. Indentation and code blocks are kept.
+//! - dedup chunks: a paragraph, text between blank lines, of at least [`MIN_DEDUP_CHUNK_CHARS`]
+//!   that appeared earlier in the request, in any message, is removed.
+//! - external compressor: the text of all messages but the last, which usually holds the
+//!   question, is sent to an LLMLingua-style service that returns it shorter. If it fails the
+//!   request goes on uncompressed.
+//!
+//! Only text content is changed. The tokens saved are counted per model in the
+//! `dynamo_prompt_compression_*` metrics.
+
+use std::collections::HashSet;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use anyhow::Context;
+use prometheus::{IntCounterVec, Opts};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::model_card::model::PromptCompression;
+
+/// Shorter paragraphs are kept even if repeated, such as list items and headers
+pub const MIN_DEDUP_CHUNK_CHARS: usize = 64;
+
+/// How long the external compressor may take
+pub const COMPRESSOR_TIMEOUT: Duration = Duration::from_secs(10);
+
+static COMPRESSED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_prompt_compression_requests_total",
+            "Requests whose prompt compression shortened",
+        ),
+        &["model"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static SAVED_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_prompt_compression_saved_tokens_total",
+            "Prompt tokens removed by prompt compression",
+        ),
+        &["model"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the prompt compression metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(COMPRESSED_REQUESTS.clone()))?;
+    registry.register(Box::new(SAVED_TOKENS.clone()))
+}
+
+#[derive(Serialize)]
+struct CompressorRequest<'a> {
+    model: &'a str,
+    texts: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct CompressorResponse {
+    texts: Vec<String>,
+}
+
+pub struct PromptCompressor {
+    config: PromptCompression,
+    model: String,
+    client: reqwest::Client,
+}
+
+impl PromptCompressor {
+    /// A compressor for `model`, if `config` enables any strategy
+    pub fn new(config: PromptCompression, model: &str) -> anyhow::Result<Option<Self>> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+        let client = reqwest::Client::builder()
+            .timeout(COMPRESSOR_TIMEOUT)
+            .build()?;
+        Ok(Some(PromptCompressor {
+            config,
+            model: model.to_string(),
+            client,
+        }))
+    }
+
+    /// Compress the text content of `messages`, a JSON array of OpenAI chat messages, or a
+    /// completions prompt, a string or an array of them. `count_tokens` tokenizes for the
+    /// metrics. Returns how many tokens were saved.
+    pub async fn compress(
+        &self,
+        messages: &mut Value,
+        count_tokens: impl Fn(&str) -> anyhow::Result<usize>,
+    ) -> anyhow::Result<usize> {
+        let mut texts = texts_mut(messages);
+        let before: Vec<String> = texts.iter().map(|text| text.to_string()).collect();
+
+        if let Some(url) = &self.config.compressor_url {
+            // The last text is the question, or part of it
+            let context = texts.len().saturating_sub(1);
+            if let Err(err) = self.call_compressor(url, &mut texts[..context]).await {
+                tracing::warn!(%url, "External prompt compressor failed: {err:#}");
+            }
+        }
+        let collapse = self.config.collapse_whitespace.unwrap_or(false);
+        let dedup = self.config.dedup_chunks.unwrap_or(false);
+        let mut seen = HashSet::new();
+        for text in texts.iter_mut() {
+            if collapse {
+                **text = collapse_whitespace(text);
+            }
+            if dedup {
+                **text = dedup_chunks(text, &mut seen);
+            }
+        }
+
+        let mut saved = 0;
+        for (before, after) in before.iter().zip(&texts) {
+            if before.as_str() != after.as_str() {
+                saved += count_tokens(before)?.saturating_sub(count_tokens(after)?);
+            }
+        }
+        if saved > 0 {
+            COMPRESSED_REQUESTS.with_label_values(&[&self.model]).inc();
+            SAVED_TOKENS
+                .with_label_values(&[&self.model])
+                .inc_by(saved as u64);
+        }
+        Ok(saved)
+    }
+
+    async fn call_compressor(
+        &self,
+        url: &url::Url,
+        texts: &mut [&mut String],
+    ) -> anyhow::Result<()> {
+        if texts.is_empty() {
+            return Ok(());
+        }
+        let request = CompressorRequest {
+            model: &self.model,
+            texts: texts.iter().map(|text| text.as_str()).collect(),
+            rate: self.config.compressor_rate,
+        };
+        let response: CompressorResponse = self
+            .client
+            .post(url.clone())
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid response")?;
+        if response.texts.len() != texts.len() {
+            anyhow::bail!(
+                "Sent {} texts but got {} back",
+                texts.len(),
+                response.texts.len()
+            );
+        }
+        for (text, compressed) in texts.iter_mut().zip(response.texts) {
+            **text = compressed;
+        }
+        Ok(())
+    }
+}
+
+/// The text content of chat messages, or the strings of a prompt, in order
+fn texts_mut(value: &mut Value) -> Vec<&mut String> {
+    let mut texts = Vec::new();
+    match value {
+        Value::String(text) => texts.push(text),
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::String(text) => texts.push(text),
+                    Value::Object(message) => match message.get_mut("content") {
+                        Some(Value::String(text)) => texts.push(text),
+                        Some(Value::Array(parts)) => {
+                            for part in parts {
+                                if part.get("type").and_then(Value::as_str) != Some("text") {
+                                    continue;
+                                }
+                                if let Some(Value::String(text)) = part.get_mut("text") {
+                                    texts.push(text);
+                                }
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    texts
+}
+
+/// Collapse runs of spaces and tabs, and of blank lines, outside of code blocks
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_code = false;
+    let mut blank_lines = 0;
+    for line in text.split_inclusive('\n') {
+        let fence = line.trim_start().starts_with("```");
+        if fence {
+            in_code = !in_code;
+        }
+        if fence || in_code {
+            blank_lines = 0;
+            out.push_str(line);
+            continue;
+        }
+        let content = line.trim_end();
+        if content.is_empty() {
+            blank_lines += 1;
+            if blank_lines == 1 && line.ends_with('\n') {
+                out.push('\n');
+            }
+            continue;
+        }
+        blank_lines = 0;
+        let words = content.trim_start();
+        out.push_str(&content[..content.len() - words.len()]);
+        for (i, word) in words
+            .split([' ', '\t'])
+            .filter(|w| !w.is_empty())
+            .enumerate()
+        {
+            if i > 0 {
+                out.push(' ');
+            }
+            out.push_str(word);
+        }
+        if line.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Remove the paragraphs of `text` whose words are in `seen`, and add the others
+fn dedup_chunks(text: &str, seen: &mut HashSet<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    // A paragraph and the blank lines after it
+    let mut chunk = String::new();
+    let mut after_blank = false;
+    let mut flush = |chunk: &mut String| {
+        let words = chunk.split_whitespace().collect::<Vec<_>>().join(" ");
+        if words.len() < MIN_DEDUP_CHUNK_CHARS || seen.insert(words) {
+            out.push_str(chunk);
+        }
+        chunk.clear();
+    };
+    for line in text.split_inclusive('\n') {
+        let blank = line.trim().is_empty();
+        if !blank && after_blank {
+            flush(&mut chunk);
+        }
+        after_blank = blank;
+        chunk.push_str(line);
+    }
+    flush(&mut chunk);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapse_whitespace() {
+        let text = "Title  \n\n\n\n  Some   spaced\twords \n```\nkeep   this\n\n\n```\nend";
+        assert_eq!(
+            collapse_whitespace(text),
+            "Title\n\n  Some spaced words\n```\nkeep   this\n\n\n```\nend"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compress() {
+        let passage = "The quick brown fox jumps over the lazy dog, again and again and again.";
+        let mut messages = serde_json::json!([
+            {"role": "system", "content": format!("Context:\n\n{passage}\n\nShort line\n")},
+            {"role": "user", "content": [
+                {"type": "text", "text": format!("{passage}\n\nShort line\n\nWhat  does the fox do?")},
+                {"type": "image_url", "image_url": {"url": "data:,"}},
+            ]},
+        ]);
+        let config = PromptCompression {
+            collapse_whitespace: Some(true),
+            dedup_chunks: Some(true),
+            ..Default::default()
+        };
+        let compressor = PromptCompressor::new(config, "test").unwrap().unwrap();
+        let words = |text: &str| Ok::<_, anyhow::Error>(text.split_whitespace().count());
+        let saved = compressor.compress(&mut messages, words).await.unwrap();
+
+        assert_eq!(
+            messages[1]["content"][0]["text"],
+            "Short line\n\nWhat does the fox do?"
+        );
+        assert_eq!(
+            messages[0]["content"],
+            format!("Context:\n\n{passage}\n\nShort line\n")
+        );
+        assert_eq!(saved, 14);
+
+        assert!(PromptCompressor::new(PromptCompression::default(), "test")
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,356 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Images of chat requests, from the OpenAI `image_url` content parts.
+//!
+//! The preprocessor takes them from `data:` URLs, checks that they are PNG, JPEG, GIF or WebP
+//! images and passes them to the engine base64 encoded, in
+//! [`PreprocessedRequest::images`](crate::protocols::common::preprocessor::PreprocessedRequest::images).
+//! The prompt template sees each of them as a `{"type": "image"}` content part.
+//!
+//! Fetching images over `http(s)` makes the frontend send requests on behalf of its clients, so it
+//! is off unless `DYN_IMAGE_URL_ALLOWED_HOSTS` lists the hosts to fetch from, comma separated,
+//! where `*.example.com` matches every subdomain. Even then only public addresses are contacted,
+//! redirects are not followed, and errors don't say what the server answered.
+//!
+//! The images travel with the request to the worker, so a request has at most [`MAX_IMAGES`] of
+//! them and [`MAX_TOTAL_IMAGE_BYTES`] together.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::protocols::common::preprocessor::EncodedImage;
+
+/// The most images a request may have
+pub const MAX_IMAGES: usize = 8;
+
+/// The most bytes the images of a request may have together. They are sent base64 encoded, a
+/// third larger, with the request, and NATS takes messages of 1 MiB by default.
+pub const MAX_TOTAL_IMAGE_BYTES: usize = 512 * 1024;
+
+/// How long fetching one image may take
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hosts images may be fetched from, see the [module docs](self)
+const ALLOWED_HOSTS_VAR: &str = "DYN_IMAGE_URL_ALLOWED_HOSTS";
+
+pub struct ImageFetcher {
+    client: reqwest::Client,
+    /// Host names, or `*.` and a domain. Empty if images may not be fetched.
+    allowed_hosts: Vec<String>,
+}
+
+impl ImageFetcher {
+    /// Fetches from the hosts in `DYN_IMAGE_URL_ALLOWED_HOSTS`, if set
+    pub fn new() -> anyhow::Result<Self> {
+        let allowed_hosts = std::env::var(ALLOWED_HOSTS_VAR).unwrap_or_default();
+        Self::with_allowed_hosts(allowed_hosts.split(','))
+    }
+
+    pub fn with_allowed_hosts<'a>(
+        hosts: impl IntoIterator<Item = &'a str>,
+    ) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()?;
+        let allowed_hosts = hosts
+            .into_iter()
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        Ok(ImageFetcher {
+            client,
+            allowed_hosts,
+        })
+    }
+
+    /// Fetch the images at `urls`, in order. The error is a message for the client.
+    pub async fn fetch_all(&self, urls: &[&str]) -> anyhow::Result<Vec<EncodedImage>> {
+        if urls.len() > MAX_IMAGES {
+            anyhow::bail!("A request can have at most {MAX_IMAGES} images");
+        }
+        let images =
+            futures::future::try_join_all(urls.iter().enumerate().map(|(i, url)| async move {
+                self.fetch(url)
+                    .await
+                    .with_context(|| format!("Image {i} could not be used"))
+            }))
+            .await?;
+        let total: usize = images.iter().map(|image| image.len()).sum();
+        if total > MAX_TOTAL_IMAGE_BYTES {
+            anyhow::bail!("The images of a request can have at most {MAX_TOTAL_IMAGE_BYTES} bytes");
+        }
+        Ok(images.into_iter().map(|image| image.encode()).collect())
+    }
+
+    async fn fetch(&self, url: &str) -> anyhow::Result<Image> {
+        let bytes = if url.starts_with("data:") {
+            decode_data_url(url)?
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            self.download(url).await?
+        } else {
+            anyhow::bail!("Only data:, http:// and https:// image URLs are supported");
+        };
+        if bytes.len() > MAX_TOTAL_IMAGE_BYTES {
+            anyhow::bail!("Image is larger than {MAX_TOTAL_IMAGE_BYTES} bytes");
+        }
+        let mime_type = sniff_mime_type(&bytes).context("Not a PNG, JPEG, GIF or WebP image")?;
+        Ok(Image { mime_type, bytes })
+    }
+
+    /// Whether images may be fetched from `host`
+    fn is_allowed(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => *allowed == host,
+            })
+    }
+
+    async fn download(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        if self.allowed_hosts.is_empty() {
+            anyhow::bail!("Fetching images by URL is disabled, send them as data: URLs");
+        }
+        let parsed = reqwest::Url::parse(url).context("Malformed image URL")?;
+        let allowed = match parsed.host() {
+            Some(url::Host::Domain(host)) => self.is_allowed(host),
+            // Literal addresses skip the resolver, and the allowlist names hosts
+            Some(url::Host::Ipv4(_)) | Some(url::Host::Ipv6(_)) | None => false,
+        };
+        if !allowed {
+            anyhow::bail!("Images may not be fetched from this host");
+        }
+        // Neither the URL nor the error go to the client, it could probe the network with them
+        let failed = |err: reqwest::Error| {
+            tracing::debug!(url, %err, "Fetching image failed");
+            anyhow::anyhow!("Fetching the image failed")
+        };
+        let mut response = self.client.get(parsed).send().await.map_err(failed)?;
+        if !response.status().is_success() {
+            tracing::debug!(url, status = %response.status(), "Fetching image failed");
+            anyhow::bail!("Fetching the image failed");
+        }
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_TOTAL_IMAGE_BYTES as u64)
+        {
+            anyhow::bail!("Image is larger than {MAX_TOTAL_IMAGE_BYTES} bytes");
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(failed)? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > MAX_TOTAL_IMAGE_BYTES {
+                anyhow::bail!("Image is larger than {MAX_TOTAL_IMAGE_BYTES} bytes");
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// An image before it is encoded for the engine
+struct Image {
+    mime_type: &'static str,
+    bytes: Vec<u8>,
+}
+
+impl Image {
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn encode(self) -> EncodedImage {
+        EncodedImage {
+            mime_type: self.mime_type.to_string(),
+            data: STANDARD.encode(&self.bytes),
+        }
+    }
+}
+
+/// Resolves host names to their public addresses only, so that an allowed host name can't point
+/// the frontend at its own network. Checked when connecting, so a name can't change in between.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err("host has no public address".into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// False for loopback, private, link-local, shared and other addresses that aren't on the
+/// internet
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // 0.0.0.0/8, carrier-grade NAT 100.64.0.0/10 and the reserved 240.0.0.0/4
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// The bytes of a `data:<mime type>;base64,<data>` URL
+fn decode_data_url(url: &str) -> anyhow::Result<Vec<u8>> {
+    let (header, data) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+        .context("Malformed data URL")?;
+    if !header.ends_with(";base64") {
+        anyhow::bail!("Image data URLs must be base64 encoded");
+    }
+    STANDARD
+        .decode(data.trim())
+        .context("Image data URL is not valid base64")
+}
+
+/// The type of image file from its first bytes. The one the client says is not trusted.
+fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[tokio::test]
+    async fn test_data_url() {
+        let fetcher = ImageFetcher::with_allowed_hosts([]).unwrap();
+        let url = format!("data:image/png;base64,{}", STANDARD.encode(PNG));
+        let images = fetcher.fetch_all(&[url.as_str()]).await.unwrap();
+        assert_eq!(images[0].mime_type, "image/png");
+        assert_eq!(images[0].data_url(), url);
+
+        // the client's type doesn't count
+        let url = format!("data:image/jpeg;base64,{}", STANDARD.encode(PNG));
+        assert_eq!(fetcher.fetch(&url).await.unwrap().mime_type, "image/png");
+
+        let not_an_image = format!("data:image/png;base64,{}", STANDARD.encode(b"hello"));
+        assert!(fetcher.fetch(&not_an_image).await.is_err());
+        assert!(fetcher.fetch("data:image/png,raw").await.is_err());
+        assert!(fetcher.fetch("file:///etc/passwd").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let fetcher = ImageFetcher::with_allowed_hosts([]).unwrap();
+        let url = format!("data:image/png;base64,{}", STANDARD.encode(PNG));
+        let urls = vec![url.as_str(); MAX_IMAGES + 1];
+        assert!(fetcher.fetch_all(&urls).await.is_err());
+
+        let mut large = PNG.to_vec();
+        large.resize(MAX_TOTAL_IMAGE_BYTES / 2 + 1, 0);
+        let large = format!("data:image/png;base64,{}", STANDARD.encode(&large));
+        assert!(fetcher.fetch_all(&[large.as_str()]).await.is_ok());
+        assert!(fetcher
+            .fetch_all(&[large.as_str(), large.as_str()])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_fetch_is_opt_in() {
+        let fetcher = ImageFetcher::with_allowed_hosts([]).unwrap();
+        let err = fetcher
+            .fetch("https://example.com/cat.png")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("disabled"));
+
+        let fetcher =
+            ImageFetcher::with_allowed_hosts(["images.example.com", "*.cdn.net"]).unwrap();
+        assert!(fetcher.is_allowed("images.example.com"));
+        assert!(fetcher.is_allowed("a.b.cdn.net"));
+        assert!(!fetcher.is_allowed("cdn.net"));
+        assert!(!fetcher.is_allowed("evilcdn.net"));
+        assert!(!fetcher.is_allowed("example.com"));
+        // Addresses aren't host names, even allowed ones
+        assert!(fetcher.fetch("http://127.0.0.1/cat.png").await.is_err());
+        assert!(fetcher.fetch("http://[::1]/cat.png").await.is_err());
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_sniff_mime_type() {
+        assert_eq!(
+            sniff_mime_type(&[0xff, 0xd8, 0xff, 0xe0]),
+            Some("image/jpeg")
+        );
+        assert_eq!(sniff_mime_type(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(sniff_mime_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_mime_type(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(sniff_mime_type(b""), None);
+    }
+}
@@ -34,6 +34,8 @@ use anyhow::Result;
 use minijinja::value::Value;
 use std::sync::Arc;
 
+use crate::protocols::TokenIdType;
+
 mod template;
 
 pub use template::ContextMixins;
@@ -49,6 +51,12 @@ pub trait OAIChatLikeRequest {
     }
 
     fn should_add_generation_prompt(&self) -> bool;
+
+    /// Legacy completions clients may send the prompt already tokenized. If so it is used as is,
+    /// skipping the prompt template and the tokenizer.
+    fn prompt_token_ids(&self) -> Option<Vec<TokenIdType>> {
+        None
+    }
 }
 
 pub trait OAIPromptFormatter: Send + Sync + 'static {
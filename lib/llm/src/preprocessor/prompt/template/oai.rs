@@ -20,11 +20,35 @@ use minijinja::{context, value::Value};
 use crate::protocols::openai::{
     chat_completions::NvCreateChatCompletionRequest, completions::NvCreateCompletionRequest,
 };
+use crate::protocols::TokenIdType;
 use tracing;
 
+/// Replace the `image_url` content parts with `{"type": "image"}`, what the templates of vision
+/// models expect where the image goes. The image itself goes to the engine separately.
+fn image_placeholders(messages: &mut serde_json::Value) {
+    let Some(messages) = messages.as_array_mut() else {
+        return;
+    };
+    for message in messages {
+        let Some(parts) = message
+            .get_mut("content")
+            .and_then(|content| content.as_array_mut())
+        else {
+            continue;
+        };
+        for part in parts {
+            if part.get("type").and_then(|t| t.as_str()) == Some("image_url") {
+                *part = serde_json::json!({ "type": "image" });
+            }
+        }
+    }
+}
+
 impl OAIChatLikeRequest for NvCreateChatCompletionRequest {
     fn messages(&self) -> Value {
-        Value::from_serialize(&self.inner.messages)
+        let mut messages = serde_json::to_value(&self.inner.messages).unwrap_or_default();
+        image_placeholders(&mut messages);
+        Value::from_serialize(&messages)
     }
 
     fn tools(&self) -> Option<Value> {
@@ -72,6 +96,10 @@ impl OAIChatLikeRequest for NvCreateCompletionRequest {
     fn should_add_generation_prompt(&self) -> bool {
         true
     }
+
+    fn prompt_token_ids(&self) -> Option<Vec<TokenIdType>> {
+        self.prompt_token_ids.clone()
+    }
 }
 
 impl OAIPromptFormatter for HfTokenizerConfigJsonFormatter {
@@ -22,6 +22,10 @@ use serde_json::Value;
 use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Hermes / Qwen style models wrap each call in these tags
+pub const TOOL_CALL_START: &str = "<tool_call>";
+pub const TOOL_CALL_END: &str = "</tool_call>";
+
 /// Matches and processes tool calling patterns in LLM responses
 ///
 /// Supports multiple formats for tool calls:
@@ -54,6 +58,16 @@ impl ToolCallingMatcher {
         if matches!(self.tool_choice, ToolChoice::None) {
             return Ok(Vec::new());
         }
+        let message = message.trim();
+
+        if message.starts_with(TOOL_CALL_START) {
+            let mut calls = Vec::new();
+            for block in message.split(TOOL_CALL_START).skip(1) {
+                let block = block.split(TOOL_CALL_END).next().unwrap_or_default();
+                calls.extend(self.get_call(block)?);
+            }
+            return Ok(calls);
+        }
 
         if let Ok(deser) = serde_json::from_str::<CalledFunctionParameters>(message) {
             let id = format!("call-{}", Uuid::new_v4());
@@ -113,3 +127,50 @@ impl ToolCallingMatcher {
         }
     }
 }
+
+/// Does the start of the model's output look like a tool call, in one of the formats
+/// [`ToolCallingMatcher`] understands?
+pub fn is_tool_call_prefix(text: &str) -> bool {
+    text.starts_with('{') || text.starts_with('[') || text.starts_with(TOOL_CALL_START)
+}
+
+impl From<ToolCallResponse> for async_openai::types::ChatCompletionMessageToolCall {
+    fn from(call: ToolCallResponse) -> Self {
+        async_openai::types::ChatCompletionMessageToolCall {
+            id: call.id,
+            r#type: async_openai::types::ChatCompletionToolType::Function,
+            function: async_openai::types::FunctionCall {
+                name: call.function.name,
+                arguments: call.function.arguments,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_call_json() {
+        let matcher = ToolCallingMatcher::new(ToolChoice::Auto).unwrap();
+        let calls = matcher
+            .get_call(r#"{"name": "get_weather", "arguments": {"city": "Paris"}}"#)
+            .unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+
+        assert!(matcher.get_call("The weather is nice").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_call_tagged() {
+        let matcher = ToolCallingMatcher::new(ToolChoice::Auto).unwrap();
+        let message = "<tool_call>\n{\"name\": \"a\", \"arguments\": {}}\n</tool_call>\n\
+                       <tool_call>\n{\"name\": \"b\", \"arguments\": {\"x\": 1}}\n</tool_call>";
+        let calls = matcher.get_call(message).unwrap();
+        let names: Vec<_> = calls.iter().map(|c| c.function.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
@@ -23,7 +23,7 @@ pub enum ToolCallType {
 
 // #[cfg_attr(feature = "pyo3_macros", pyo3::pyclass)]
 // #[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct CalledFunction {
     pub name: String,
     pub arguments: String,
@@ -31,7 +31,7 @@ pub struct CalledFunction {
 
 // #[cfg_attr(feature = "pyo3_macros", pyo3::pyclass)]
 // #[cfg_attr(feature = "pyo3_macros", pyo3(get_all))]
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Serialize, PartialEq)]
 pub struct ToolCallResponse {
     pub id: String,
     #[serde(rename = "type")]
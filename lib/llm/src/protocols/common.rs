@@ -48,6 +48,10 @@ pub trait StopConditionsProvider {
     fn extract_stop_conditions(&self) -> Result<StopConditions>;
 }
 
+pub trait GuidedDecodingProvider {
+    fn extract_guided_decoding(&self) -> Result<Option<GuidedDecodingOptions>>;
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum FinishReason {
     #[serde(rename = "eos")]
@@ -315,6 +319,133 @@ impl SamplingOptions {
     }
 }
 
+/// Constrains the output of the engine. Each engine translates this into its own guided decoding
+/// options; engines that don't support it ignore it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GuidedDecodingOptions {
+    /// The output must be JSON matching this JSON schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
+
+    /// The output must be a JSON object, of any shape
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub json_object: bool,
+}
+
+/// Schemas nested deeper than this are rejected
+const MAX_JSON_SCHEMA_DEPTH: usize = 32;
+
+const JSON_SCHEMA_TYPES: &[&str] = &[
+    "object", "array", "string", "number", "integer", "boolean", "null",
+];
+
+impl GuidedDecodingOptions {
+    pub fn json_object() -> Self {
+        GuidedDecodingOptions {
+            json_object: true,
+            ..Default::default()
+        }
+    }
+
+    /// Checks the schema is well formed, so that a mistake is a 400 here rather than an engine
+    /// error. This checks the structure of the schema, it is not a full JSON Schema validator.
+    pub fn json_schema(schema: serde_json::Value) -> Result<Self> {
+        validate_json_schema(&schema, "schema", 0).map_err(|err| anyhow::anyhow!(err))?;
+        Ok(GuidedDecodingOptions {
+            json: Some(schema),
+            ..Default::default()
+        })
+    }
+}
+
+fn validate_json_schema(
+    schema: &serde_json::Value,
+    path: &str,
+    depth: usize,
+) -> std::result::Result<(), String> {
+    use serde_json::Value;
+
+    if depth > MAX_JSON_SCHEMA_DEPTH {
+        return Err(format!(
+            "{path}: schema is nested more than {MAX_JSON_SCHEMA_DEPTH} levels deep"
+        ));
+    }
+    let obj = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(obj) => obj,
+        _ => return Err(format!("{path}: a schema must be an object or a boolean")),
+    };
+
+    let check_type = |t: &Value| match t.as_str() {
+        Some(t) if JSON_SCHEMA_TYPES.contains(&t) => Ok(()),
+        _ => Err(format!("{path}.type: unknown type {t}")),
+    };
+    match obj.get("type") {
+        None => {}
+        Some(Value::Array(types)) => types.iter().try_for_each(check_type)?,
+        Some(t) => check_type(t)?,
+    }
+
+    // Maps of name to schema
+    for key in ["properties", "patternProperties", "$defs", "definitions"] {
+        let Some(value) = obj.get(key) else {
+            continue;
+        };
+        let Some(map) = value.as_object() else {
+            return Err(format!("{path}.{key}: must be an object"));
+        };
+        for (name, sub) in map {
+            validate_json_schema(sub, &format!("{path}.{key}.{name}"), depth + 1)?;
+        }
+    }
+
+    // Single schemas
+    for key in [
+        "items",
+        "additionalProperties",
+        "not",
+        "contains",
+        "propertyNames",
+        "if",
+        "then",
+        "else",
+    ] {
+        if let Some(sub) = obj.get(key) {
+            validate_json_schema(sub, &format!("{path}.{key}"), depth + 1)?;
+        }
+    }
+
+    // Lists of schemas
+    for key in ["anyOf", "oneOf", "allOf", "prefixItems"] {
+        let Some(value) = obj.get(key) else {
+            continue;
+        };
+        match value.as_array() {
+            Some(list) if !list.is_empty() => {
+                for (i, sub) in list.iter().enumerate() {
+                    validate_json_schema(sub, &format!("{path}.{key}[{i}]"), depth + 1)?;
+                }
+            }
+            _ => return Err(format!("{path}.{key}: must be a non-empty array")),
+        }
+    }
+
+    if let Some(required) = obj.get("required") {
+        let all_strings = required
+            .as_array()
+            .is_some_and(|r| r.iter().all(Value::is_string));
+        if !all_strings {
+            return Err(format!("{path}.required: must be an array of strings"));
+        }
+    }
+    if let Some(values) = obj.get("enum") {
+        if !values.is_array() {
+            return Err(format!("{path}.enum: must be an array"));
+        }
+    }
+    Ok(())
+}
+
 /// Collection of options that control what information the inference engine returns in the response.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct OutputOptions {
@@ -1084,4 +1215,45 @@ mod tests {
             serde_json::from_str::<serde_json::Value>(expected_json).unwrap()
         );
     }
+
+    #[test]
+    fn test_guided_decoding_json_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "size": {"anyOf": [{"type": "integer"}, {"type": "null"}]}
+            },
+            "required": ["name"],
+            "additionalProperties": false
+        });
+        let options = GuidedDecodingOptions::json_schema(schema.clone()).unwrap();
+        assert_eq!(options.json, Some(schema));
+        assert!(!options.json_object);
+
+        let err = GuidedDecodingOptions::json_schema(serde_json::json!({
+            "type": "object",
+            "properties": {"tags": {"type": "array", "items": {"type": "list"}}}
+        }))
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("schema.properties.tags.items.type: unknown type"));
+
+        assert!(GuidedDecodingOptions::json_schema(serde_json::json!("object")).is_err());
+        assert!(
+            GuidedDecodingOptions::json_schema(serde_json::json!({"required": "name"})).is_err()
+        );
+        assert!(GuidedDecodingOptions::json_schema(serde_json::json!({"anyOf": []})).is_err());
+    }
+
+    #[test]
+    fn test_guided_decoding_serialization() {
+        let options = GuidedDecodingOptions::json_object();
+        assert_eq!(
+            serde_json::to_value(&options).unwrap(),
+            serde_json::json!({"json_object": true})
+        );
+    }
 }
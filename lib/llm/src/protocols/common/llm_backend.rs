@@ -77,6 +77,11 @@ pub struct LLMEngineOutput {
     // TODO: Enrich this with more information as can apply our first-level postprocessing
     // logic and return more detailed information
     pub finish_reason: Option<FinishReason>,
+
+    /// From a prefill worker of disaggregated serving, where the decode worker finds the KV
+    /// cache of the prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_transfer_params: Option<serde_json::Value>,
 }
 
 impl LLMEngineOutput {
@@ -88,6 +93,7 @@ impl LLMEngineOutput {
             cum_log_probs: None,
             log_probs: None,
             finish_reason: Some(FinishReason::Cancelled),
+            kv_transfer_params: None,
         }
     }
 
@@ -99,6 +105,7 @@ impl LLMEngineOutput {
             cum_log_probs: None,
             log_probs: None,
             finish_reason: Some(FinishReason::Stop),
+            kv_transfer_params: None,
         }
     }
 
@@ -110,6 +117,7 @@ impl LLMEngineOutput {
             cum_log_probs: None,
             log_probs: None,
             finish_reason: Some(FinishReason::Length),
+            kv_transfer_params: None,
         }
     }
 
@@ -121,6 +129,7 @@ impl LLMEngineOutput {
             cum_log_probs: None,
             log_probs: None,
             finish_reason: Some(FinishReason::Error(err_msg)),
+            kv_transfer_params: None,
         }
     }
 }
@@ -16,7 +16,9 @@
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
-use super::{SamplingOptions, StopConditions};
+use super::{GuidedDecodingOptions, SamplingOptions, StopConditions};
+use crate::lora::LoraRequest;
+use crate::protocols::openai::nvext::NvExt;
 use crate::protocols::TokenIdType;
 
 /// [`PreprocessedRequest`] is the internal representation of an LLM request. The [`dynamo.llm-preprocessor`]
@@ -51,12 +53,117 @@ pub struct PreprocessedRequest {
     /// Estimated number of prefix hit tokens (only used in kv aware routing)
     #[builder(default)]
     pub estimated_prefix_hit_num_blocks: Option<u32>,
+
+    /// Scheduling priority from -100 to 100, higher first. From `nvext.priority` or the
+    /// `x-dynamo-priority` header. The KV router and engines that support priorities serve
+    /// higher priority requests first.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+
+    /// The request's `nvext`, after the HTTP service validated it and applied its policy
+    /// for unknown keys. Workers read priority, tenant, trace context etc. from here.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvExt>,
+
+    /// Constraints on the output, e.g. from the OpenAI `response_format`
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guided_decoding: Option<GuidedDecodingOptions>,
+
+    /// The images of the chat messages, in order. The prompt has the model's image placeholder
+    /// tokens where they go.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<EncodedImage>,
+
+    /// Disaggregated serving: for a prefill worker, what it must prepare for the decode worker.
+    /// For a decode worker, where to fetch the KV cache the prefill worker computed. Opaque to
+    /// Dynamo, the engines' KV connectors read and write it.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_transfer_params: Option<serde_json::Value>,
+
+    /// Speculative decoding: how a draft model continues the prompt. The engine checks them in
+    /// one forward pass and answers with those it would have generated itself, then one token of
+    /// its own. Engines that don't check them generate as usual. See [`crate::speculative`].
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub draft_token_ids: Vec<TokenIdType>,
+
+    /// The LoRA adapter to generate with, when the requested model is one. See [`crate::lora`].
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lora: Option<LoraRequest>,
+}
+
+/// An image for the engine
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EncodedImage {
+    /// `image/png`, `image/jpeg`, `image/gif` or `image/webp`
+    pub mime_type: String,
+
+    /// The image file, base64 encoded
+    pub data: String,
+}
+
+impl EncodedImage {
+    /// The image as a `data:` URL
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.data)
+    }
 }
 
 impl PreprocessedRequest {
     pub fn has_annotation(&self, annotation: &str) -> bool {
         self.annotations.contains(&annotation.to_string())
     }
+
+    /// The id of the LoRA adapter of the request, for the KV router. 0 is the base model.
+    pub fn lora_id(&self) -> u64 {
+        self.lora.as_ref().map_or(0, |lora| lora.id)
+    }
+
+    /// The worker instance the client asked for, if any
+    pub fn backend_instance_id(&self) -> Option<i64> {
+        self.nvext
+            .as_ref()
+            .and_then(|nvext| nvext.routing.as_ref())
+            .and_then(|routing| routing.backend_instance_id)
+    }
+
+    /// Send the request to worker `instance_id`, as if the client had pinned it there
+    pub fn set_backend_instance_id(&mut self, instance_id: i64) {
+        self.nvext
+            .get_or_insert_with(NvExt::default)
+            .routing
+            .get_or_insert_with(Default::default)
+            .backend_instance_id = Some(instance_id);
+    }
+
+    /// The conversation the request is part of, see [`crate::kv_router::affinity`]
+    pub fn session_id(&self) -> Option<&str> {
+        self.nvext
+            .as_ref()
+            .and_then(|nvext| nvext.routing.as_ref())
+            .and_then(|routing| routing.session_id.as_deref())
+    }
+
+    /// Who sent the request, from `nvext.principal`
+    pub fn principal(&self) -> Option<&str> {
+        self.nvext
+            .as_ref()
+            .and_then(|nvext| nvext.principal.as_deref())
+    }
+
+    /// The class of the request for budget routing, see [`crate::budget_router`]
+    pub fn request_class(&self) -> Option<&str> {
+        self.nvext
+            .as_ref()
+            .and_then(|nvext| nvext.routing.as_ref())
+            .and_then(|routing| routing.request_class.as_deref())
+    }
 }
 
 impl PreprocessedRequest {
@@ -0,0 +1,511 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The Ollama API, served by the chat completions and completions engines, for clients that only
+//! speak it.
+//!
+//! A [`ChatRequest`] becomes a [`NvCreateChatCompletionRequest`]. A [`GenerateRequest`] does too,
+//! its `system` and `prompt` are a system and a user message, because Ollama applies the chat
+//! template to them. With `raw` the prompt goes to the completions engine as is. `top_k` and
+//! `repeat_penalty` go to `nvext`.
+//!
+//! Ollama streams one JSON object per line. The last one has `done: true`, the reason generation
+//! stopped and the token counts, tracked by a [`ResponseTracker`].
+//!
+//! Images, tools and `format` are not supported.
+
+use std::time::{Duration, Instant};
+
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage, CompletionUsage,
+    CreateChatCompletionRequest, FinishReason, Stop,
+};
+use serde::{Deserialize, Serialize};
+
+use super::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+};
+use super::openai::completions::{CompletionResponse, NvCreateCompletionRequest};
+use super::openai::nvext::NvExt;
+use crate::types::Annotated;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+
+    /// Base64 encoded. Not supported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+/// Sampling options. Ollama's options for loading the model are ignored.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Options {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i64>,
+
+    /// Maximum number of tokens to generate. Negative means no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+}
+
+impl Options {
+    fn max_tokens(&self) -> Option<u32> {
+        self.num_predict.and_then(|n| u32::try_from(n).ok())
+    }
+
+    fn stop(&self) -> Option<Stop> {
+        self.stop
+            .clone()
+            .filter(|stop| !stop.is_empty())
+            .map(Stop::StringArray)
+    }
+
+    fn nvext(&self) -> Option<NvExt> {
+        if self.top_k.is_none() && self.repeat_penalty.is_none() {
+            return None;
+        }
+        Some(NvExt {
+            top_k: self.top_k,
+            repetition_penalty: self.repeat_penalty,
+            ..Default::default()
+        })
+    }
+}
+
+/// A request to `/api/chat`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+
+    #[serde(default)]
+    pub messages: Vec<Message>,
+
+    /// Default true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<Options>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<serde_json::Value>,
+}
+
+impl ChatRequest {
+    pub fn chat_request(&self) -> Result<NvCreateChatCompletionRequest, String> {
+        if self.format.is_some() {
+            return Err("format is not supported".to_string());
+        }
+        if self.tools.is_some() {
+            return Err("tools are not supported".to_string());
+        }
+        let messages = self
+            .messages
+            .iter()
+            .map(chat_message)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(chat_request(
+            &self.model,
+            messages,
+            self.options.as_ref().unwrap_or(&Options::default()),
+        ))
+    }
+}
+
+/// A request to `/api/generate`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerateRequest {
+    pub model: String,
+
+    #[serde(default)]
+    pub prompt: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+
+    /// Send the prompt to the model without applying the chat template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<bool>,
+
+    /// Default true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<Options>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+impl GenerateRequest {
+    pub fn is_raw(&self) -> bool {
+        self.raw.unwrap_or(false)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.format.is_some() {
+            return Err("format is not supported".to_string());
+        }
+        if !self.images.is_empty() {
+            return Err("images are not supported".to_string());
+        }
+        Ok(())
+    }
+
+    /// The chat completion, for requests that aren't [`GenerateRequest::is_raw`]
+    pub fn chat_request(&self) -> Result<NvCreateChatCompletionRequest, String> {
+        self.check()?;
+        let mut messages = Vec::with_capacity(2);
+        if let Some(system) = &self.system {
+            messages.push(ChatCompletionRequestSystemMessage::from(system.as_str()).into());
+        }
+        messages.push(ChatCompletionRequestUserMessage::from(self.prompt.as_str()).into());
+        Ok(chat_request(
+            &self.model,
+            messages,
+            self.options.as_ref().unwrap_or(&Options::default()),
+        ))
+    }
+
+    /// The completion, for [`GenerateRequest::is_raw`] requests
+    pub fn completion_request(&self) -> Result<NvCreateCompletionRequest, String> {
+        self.check()?;
+        let default_options = Options::default();
+        let options = self.options.as_ref().unwrap_or(&default_options);
+        // Engines always stream, responses are assembled from the stream
+        let mut inner: async_openai::types::CreateCompletionRequest =
+            serde_json::from_value(serde_json::json!({
+                "model": self.model,
+                "prompt": self.prompt,
+                "stream": true,
+            }))
+            .map_err(|err| err.to_string())?;
+        inner.temperature = options.temperature;
+        inner.top_p = options.top_p;
+        inner.max_tokens = options.max_tokens();
+        inner.stop = options.stop();
+        inner.seed = options.seed;
+        inner.presence_penalty = options.presence_penalty;
+        inner.frequency_penalty = options.frequency_penalty;
+        Ok(NvCreateCompletionRequest {
+            inner,
+            nvext: options.nvext(),
+            prompt_token_ids: None,
+        })
+    }
+}
+
+fn chat_message(message: &Message) -> Result<ChatCompletionRequestMessage, String> {
+    if !message.images.is_empty() {
+        return Err("images are not supported".to_string());
+    }
+    let content = message.content.as_str();
+    match message.role.as_str() {
+        "system" => Ok(ChatCompletionRequestSystemMessage::from(content).into()),
+        "user" => Ok(ChatCompletionRequestUserMessage::from(content).into()),
+        "assistant" => Ok(ChatCompletionRequestAssistantMessage::from(content).into()),
+        role => Err(format!("Unsupported message role '{role}'")),
+    }
+}
+
+fn chat_request(
+    model: &str,
+    messages: Vec<ChatCompletionRequestMessage>,
+    options: &Options,
+) -> NvCreateChatCompletionRequest {
+    let inner = CreateChatCompletionRequest {
+        model: model.to_string(),
+        messages,
+        temperature: options.temperature,
+        top_p: options.top_p,
+        max_completion_tokens: options.max_tokens(),
+        stop: options.stop(),
+        seed: options.seed,
+        presence_penalty: options.presence_penalty,
+        frequency_penalty: options.frequency_penalty,
+        // Engines always stream, responses are assembled from the stream
+        stream: Some(true),
+        ..Default::default()
+    };
+    NvCreateChatCompletionRequest {
+        inner,
+        nvext: options.nvext(),
+    }
+}
+
+/// Why generation stopped and how long it took, in the last response of a stream. Durations are
+/// in nanoseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DoneStats {
+    pub done_reason: String,
+    pub total_duration: u64,
+    pub prompt_eval_count: u32,
+    /// Until the first token
+    pub prompt_eval_duration: u64,
+    pub eval_count: u32,
+    /// From the first token on
+    pub eval_duration: u64,
+}
+
+/// A response of `/api/chat`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatResponse {
+    pub model: String,
+    pub created_at: String,
+    pub message: Message,
+    pub done: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<DoneStats>,
+}
+
+/// A response of `/api/generate`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GenerateResponse {
+    pub model: String,
+    pub created_at: String,
+    pub response: String,
+    pub done: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<DoneStats>,
+}
+
+/// The time in Ollama's format
+pub fn created_at() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+}
+
+/// Collects the text, token counts and timings of an engine stream
+#[derive(Debug)]
+pub struct ResponseTracker {
+    start: Instant,
+    first_token: Option<Instant>,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    done_reason: Option<String>,
+}
+
+impl Default for ResponseTracker {
+    fn default() -> Self {
+        ResponseTracker {
+            start: Instant::now(),
+            first_token: None,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            done_reason: None,
+        }
+    }
+}
+
+/// The generated text in a response of the chat completions or completions engine
+pub struct ChunkText {
+    /// Of the first choice
+    pub text: String,
+    pub usage: Option<CompletionUsage>,
+    /// `stop` or `length`, once generation stopped
+    pub done_reason: Option<&'static str>,
+}
+
+pub trait TextChunk {
+    fn into_text(self) -> ChunkText;
+}
+
+impl TextChunk for NvCreateChatCompletionStreamResponse {
+    fn into_text(self) -> ChunkText {
+        let mut text = String::new();
+        let mut done_reason = None;
+        for choice in self.inner.choices.into_iter().filter(|c| c.index == 0) {
+            text.push_str(choice.delta.content.as_deref().unwrap_or_default());
+            done_reason = choice.finish_reason.map(|reason| match reason {
+                FinishReason::Length => "length",
+                _ => "stop",
+            });
+        }
+        ChunkText {
+            text,
+            usage: self.inner.usage,
+            done_reason,
+        }
+    }
+}
+
+impl TextChunk for CompletionResponse {
+    fn into_text(self) -> ChunkText {
+        let mut text = String::new();
+        let mut done_reason = None;
+        for choice in self.choices.into_iter().filter(|c| c.index == 0) {
+            text.push_str(&choice.text);
+            done_reason = choice.finish_reason.map(|reason| match reason.as_str() {
+                "length" => "length",
+                _ => "stop",
+            });
+        }
+        ChunkText {
+            text,
+            usage: self.usage,
+            done_reason,
+        }
+    }
+}
+
+impl ResponseTracker {
+    /// The text of an engine response, or the engine's error
+    pub fn add<T: TextChunk>(&mut self, annotated: Annotated<T>) -> Result<String, String> {
+        let Some(chunk) = annotated.ok()?.data else {
+            return Ok(String::new());
+        };
+        let chunk = chunk.into_text();
+        if let Some(usage) = chunk.usage {
+            self.prompt_tokens = usage.prompt_tokens;
+            self.completion_tokens = usage.completion_tokens;
+        }
+        if let Some(reason) = chunk.done_reason {
+            self.done_reason = Some(reason.to_string());
+        }
+        if !chunk.text.is_empty() && self.first_token.is_none() {
+            self.first_token = Some(Instant::now());
+        }
+        Ok(chunk.text)
+    }
+
+    /// The stream ended
+    pub fn finish(&self) -> DoneStats {
+        let total = self.start.elapsed();
+        let prompt = self
+            .first_token
+            .map(|first| first.duration_since(self.start))
+            .unwrap_or(total);
+        DoneStats {
+            done_reason: self
+                .done_reason
+                .clone()
+                .unwrap_or_else(|| "stop".to_string()),
+            total_duration: nanos(total),
+            prompt_eval_count: self.prompt_tokens,
+            prompt_eval_duration: nanos(prompt),
+            eval_count: self.completion_tokens,
+            eval_duration: nanos(total.saturating_sub(prompt)),
+        }
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_chat_request() {
+        let request: ChatRequest = serde_json::from_value(json!({
+            "model": "llama3",
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": "Hi"},
+                {"role": "assistant", "content": "Hello"},
+                {"role": "user", "content": "Why is the sky blue?"}
+            ],
+            "options": {"temperature": 0.5, "num_predict": 64, "top_k": 40, "num_ctx": 4096}
+        }))
+        .unwrap();
+        let chat = request.chat_request().unwrap();
+        assert_eq!(chat.inner.messages.len(), 4);
+        assert_eq!(chat.inner.temperature, Some(0.5));
+        assert_eq!(chat.inner.max_completion_tokens, Some(64));
+        assert_eq!(chat.inner.stream, Some(true));
+        assert_eq!(chat.nvext.unwrap().top_k, Some(40));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let request: ChatRequest = serde_json::from_value(json!({
+            "model": "llama3",
+            "messages": [{"role": "tool", "content": "42"}]
+        }))
+        .unwrap();
+        assert!(request.chat_request().is_err());
+
+        let request: GenerateRequest = serde_json::from_value(json!({
+            "model": "llava",
+            "prompt": "What is in this picture?",
+            "images": ["iVBORw0KGgo="]
+        }))
+        .unwrap();
+        assert!(request.chat_request().is_err());
+    }
+
+    #[test]
+    fn test_generate_request() {
+        let request: GenerateRequest = serde_json::from_value(json!({
+            "model": "llama3",
+            "prompt": "Why is the sky blue?",
+            "system": "Be brief",
+            "options": {"num_predict": -1, "stop": ["\n"]}
+        }))
+        .unwrap();
+        assert!(!request.is_raw());
+        let chat = request.chat_request().unwrap();
+        assert_eq!(chat.inner.messages.len(), 2);
+        assert_eq!(chat.inner.max_completion_tokens, None);
+        assert!(chat.nvext.is_none());
+
+        let completion = request.completion_request().unwrap();
+        assert_eq!(completion.inner.stream, Some(true));
+        assert!(matches!(completion.inner.stop, Some(Stop::StringArray(stop)) if stop == ["\n"]));
+    }
+
+    #[test]
+    fn test_done_response() {
+        let response = GenerateResponse {
+            model: "llama3".to_string(),
+            created_at: created_at(),
+            response: String::new(),
+            done: true,
+            stats: Some(ResponseTracker::default().finish()),
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(value["done_reason"], "stop");
+        assert_eq!(value["eval_count"], 0);
+
+        let response = GenerateResponse {
+            done: false,
+            stats: None,
+            ..response
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        assert!(value.get("done_reason").is_none());
+    }
+}
@@ -15,9 +15,11 @@
 
 pub mod chat_completions;
 pub mod completions;
+pub mod continuation;
 pub mod embeddings;
 pub mod models;
 pub mod nvext;
+pub mod responses;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -141,6 +143,8 @@ trait OpenAISamplingOptionsProvider {
 
     fn get_presence_penalty(&self) -> Option<f32>;
 
+    fn get_seed(&self) -> Option<i64>;
+
     fn nvext(&self) -> Option<&nvext::NvExt>;
 }
 
@@ -189,7 +193,7 @@ impl<T: OpenAISamplingOptionsProvider> SamplingOptionsProvider for T {
             top_p,
             top_k: None,
             min_p: None,
-            seed: None,
+            seed: self.get_seed(),
             use_beam_search: None,
             length_penalty: None,
         })
@@ -310,6 +314,15 @@ pub trait DeltaGeneratorExt<ResponseType: Send + Sync + 'static + std::fmt::Debu
 
     /// Gets the current prompt token count (Input Sequence Length).
     fn get_isl(&self) -> Option<u32>;
+
+    /// The response that ends the stream with the usage of the whole request, if the client asked
+    /// for one with `stream_options.include_usage`. It has no choices.
+    fn usage_chunk(&self) -> Option<ResponseType>;
+
+    /// The text held back so far, for a stream that ended without a finish reason
+    fn flush(&mut self) -> Option<ResponseType> {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -15,8 +15,10 @@
 
 use super::nvext::NvExt;
 use super::nvext::NvExtProvider;
+use super::nvext::NvResponseExt;
 use super::OpenAISamplingOptionsProvider;
 use super::OpenAIStopConditionsProvider;
+use crate::protocols::common::{GuidedDecodingOptions, GuidedDecodingProvider};
 use dynamo_runtime::protocols::annotated::AnnotationsProvider;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
@@ -49,10 +51,15 @@ pub struct NvCreateChatCompletionRequest {
 /// # Fields
 /// - `inner`: The base OpenAI unary chat completion response, embedded
 ///   using `serde(flatten)`.
+/// - `nvext`: The optional NVIDIA extension field, such as the seed used. See
+///   [`NvResponseExt`].
 #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
 pub struct NvCreateChatCompletionResponse {
     #[serde(flatten)]
     pub inner: async_openai::types::CreateChatCompletionResponse,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvResponseExt>,
 }
 
 /// A response structure for streamed chat completions, embedding OpenAI's
@@ -61,10 +68,43 @@ pub struct NvCreateChatCompletionResponse {
 /// # Fields
 /// - `inner`: The base OpenAI streaming chat completion response, embedded
 ///   using `serde(flatten)`.
+/// - `nvext`: The optional NVIDIA extension field, such as the seed used. See
+///   [`NvResponseExt`].
 #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
 pub struct NvCreateChatCompletionStreamResponse {
     #[serde(flatten)]
     pub inner: async_openai::types::CreateChatCompletionStreamResponse,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvResponseExt>,
+}
+
+impl NvCreateChatCompletionRequest {
+    /// The URLs of the `image_url` content parts of the user messages, in order.
+    pub fn image_urls(&self) -> Vec<&str> {
+        use async_openai::types::{
+            ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent,
+            ChatCompletionRequestUserMessageContentPart,
+        };
+        self.inner
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                ChatCompletionRequestMessage::User(user) => match &user.content {
+                    ChatCompletionRequestUserMessageContent::Array(parts) => Some(parts),
+                    ChatCompletionRequestUserMessageContent::Text(_) => None,
+                },
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|part| match part {
+                ChatCompletionRequestUserMessageContentPart::ImageUrl(image) => {
+                    Some(image.image_url.url.as_str())
+                }
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 /// Implements `NvExtProvider` for `NvCreateChatCompletionRequest`,
@@ -130,12 +170,51 @@ impl OpenAISamplingOptionsProvider for NvCreateChatCompletionRequest {
         self.inner.presence_penalty
     }
 
+    /// Retrieves the seed for sampling, if set.
+    fn get_seed(&self) -> Option<i64> {
+        self.inner.seed
+    }
+
     /// Returns a reference to the optional `NvExt` extension, if available.
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
     }
 }
 
+/// Implements `GuidedDecodingProvider` for `NvCreateChatCompletionRequest`,
+/// translating OpenAI's `response_format` into guided decoding options for the engine.
+impl GuidedDecodingProvider for NvCreateChatCompletionRequest {
+    fn extract_guided_decoding(&self) -> anyhow::Result<Option<GuidedDecodingOptions>> {
+        use async_openai::types::ResponseFormat;
+        match &self.inner.response_format {
+            None | Some(ResponseFormat::Text) => Ok(None),
+            Some(ResponseFormat::JsonObject) => Ok(Some(GuidedDecodingOptions::json_object())),
+            Some(ResponseFormat::JsonSchema { json_schema }) => {
+                let name = &json_schema.name;
+                let valid_name = !name.is_empty()
+                    && name.len() <= 64
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                if !valid_name {
+                    anyhow::bail!(
+                        "response_format.json_schema.name must be 1 to 64 letters, digits, '_' or '-'"
+                    );
+                }
+                match json_schema.schema.clone() {
+                    Some(schema) => GuidedDecodingOptions::json_schema(schema)
+                        .map(Some)
+                        .map_err(|err| {
+                            anyhow::anyhow!("Invalid response_format.json_schema: {err}")
+                        }),
+                    // No schema means any JSON object
+                    None => Ok(Some(GuidedDecodingOptions::json_object())),
+                }
+            }
+        }
+    }
+}
+
 /// Implements `OpenAIStopConditionsProvider` for `NvCreateChatCompletionRequest`,
 /// providing access to stop conditions that control chat completion behavior.
 impl OpenAIStopConditionsProvider for NvCreateChatCompletionRequest {
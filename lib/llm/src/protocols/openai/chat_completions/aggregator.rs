@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use super::{NvCreateChatCompletionResponse, NvCreateChatCompletionStreamResponse};
+use crate::protocols::openai::nvext::NvResponseExt;
 use crate::protocols::{
     codec::{Message, SseCodecError},
     convert_sse_stream, Annotated,
@@ -45,6 +46,8 @@ pub struct DeltaAggregator {
     error: Option<String>,
     /// Optional service tier information for the response.
     service_tier: Option<async_openai::types::ServiceTierResponse>,
+    /// NVIDIA extensions of the first choice.
+    nvext: Option<NvResponseExt>,
 }
 
 /// Represents the accumulated state of a single chat choice during streaming aggregation.
@@ -59,6 +62,8 @@ struct DeltaChoice {
     finish_reason: Option<async_openai::types::FinishReason>,
     /// Optional log probabilities for the chat choice.
     logprobs: Option<async_openai::types::ChatChoiceLogprobs>,
+    /// Tool calls, assembled from their streamed chunks.
+    tool_calls: Vec<async_openai::types::ChatCompletionMessageToolCall>,
 }
 
 impl Default for DeltaAggregator {
@@ -80,6 +85,7 @@ impl DeltaAggregator {
             choices: HashMap::new(),
             error: None,
             service_tier: None,
+            nvext: None,
         }
     }
 
@@ -121,6 +127,14 @@ impl DeltaAggregator {
                     if let Some(system_fingerprint) = delta.inner.system_fingerprint {
                         aggregator.system_fingerprint = Some(system_fingerprint);
                     }
+                    // Keep the seed of the first choice, the others were sampled with the seeds
+                    // after it.
+                    if delta.nvext.is_some()
+                        && (aggregator.nvext.is_none()
+                            || delta.inner.choices.iter().any(|choice| choice.index == 0))
+                    {
+                        aggregator.nvext = delta.nvext;
+                    }
 
                     // Aggregate choices incrementally.
                     for choice in delta.inner.choices {
@@ -134,6 +148,7 @@ impl DeltaAggregator {
                                     role: choice.delta.role,
                                     finish_reason: None,
                                     logprobs: choice.logprobs,
+                                    tool_calls: Vec::new(),
                                 });
 
                         // Append content if available.
@@ -141,6 +156,11 @@ impl DeltaAggregator {
                             state_choice.text.push_str(content);
                         }
 
+                        // Tool calls arrive in pieces, keyed by their index.
+                        for chunk in choice.delta.tool_calls.unwrap_or_default() {
+                            state_choice.add_tool_call_chunk(chunk);
+                        }
+
                         // Update finish reason if provided.
                         if let Some(finish_reason) = choice.finish_reason {
                             state_choice.finish_reason = Some(finish_reason);
@@ -179,12 +199,47 @@ impl DeltaAggregator {
             service_tier: aggregator.service_tier,
         };
 
-        let response = NvCreateChatCompletionResponse { inner };
+        let response = NvCreateChatCompletionResponse {
+            inner,
+            nvext: aggregator.nvext,
+        };
 
         Ok(response)
     }
 }
 
+impl DeltaChoice {
+    fn add_tool_call_chunk(
+        &mut self,
+        chunk: async_openai::types::ChatCompletionMessageToolCallChunk,
+    ) {
+        let index = chunk.index as usize;
+        while self.tool_calls.len() <= index {
+            self.tool_calls
+                .push(async_openai::types::ChatCompletionMessageToolCall {
+                    id: String::new(),
+                    r#type: async_openai::types::ChatCompletionToolType::Function,
+                    function: async_openai::types::FunctionCall {
+                        name: String::new(),
+                        arguments: String::new(),
+                    },
+                });
+        }
+        let call = &mut self.tool_calls[index];
+        if let Some(id) = chunk.id {
+            call.id = id;
+        }
+        if let Some(function) = chunk.function {
+            if let Some(name) = function.name {
+                call.function.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+}
+
 #[allow(deprecated)]
 impl From<DeltaChoice> for async_openai::types::ChatChoice {
     /// Converts a [`DeltaChoice`] into an [`async_openai::types::ChatChoice`].
@@ -192,11 +247,16 @@ impl From<DeltaChoice> for async_openai::types::ChatChoice {
     /// # Note
     /// The `function_call` field is deprecated.
     fn from(delta: DeltaChoice) -> Self {
+        let has_tool_calls = !delta.tool_calls.is_empty();
         async_openai::types::ChatChoice {
             message: async_openai::types::ChatCompletionResponseMessage {
                 role: delta.role.expect("delta should have a Role"),
-                content: Some(delta.text),
-                tool_calls: None,
+                content: if has_tool_calls && delta.text.is_empty() {
+                    None
+                } else {
+                    Some(delta.text)
+                },
+                tool_calls: has_tool_calls.then_some(delta.tool_calls),
                 refusal: None,
                 function_call: None,
                 audio: None,
@@ -278,7 +338,7 @@ mod tests {
             object: "chat.completion".to_string(),
         };
 
-        let data = NvCreateChatCompletionStreamResponse { inner };
+        let data = NvCreateChatCompletionStreamResponse { inner, nvext: None };
 
         Annotated {
             data: Some(data),
@@ -423,7 +483,10 @@ mod tests {
             object: "chat.completion".to_string(),
         };
 
-        let data = NvCreateChatCompletionStreamResponse { inner: delta };
+        let data = NvCreateChatCompletionStreamResponse {
+            inner: delta,
+            nvext: None,
+        };
 
         // Wrap it in Annotated and create a stream
         let annotated_delta = Annotated {
@@ -465,4 +528,54 @@ mod tests {
         );
         assert_eq!(choice1.message.role, async_openai::types::Role::Assistant);
     }
+
+    #[tokio::test]
+    async fn test_tool_calls() {
+        // The name and the arguments arrive in separate chunks
+        let chunks = [
+            (Some("call-1"), Some("get_weather"), "{\"city\": ", None),
+            (
+                None,
+                None,
+                "\"Paris\"}",
+                Some(async_openai::types::FinishReason::ToolCalls),
+            ),
+        ];
+        let deltas: Vec<_> = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, (id, name, arguments, finish_reason))| {
+                let role = (i == 0).then_some(async_openai::types::Role::Assistant);
+                let mut delta = create_test_delta(0, "", role, finish_reason);
+                let choice = &mut delta.data.as_mut().unwrap().inner.choices[0];
+                choice.delta.content = None;
+                choice.delta.tool_calls = Some(vec![
+                    async_openai::types::ChatCompletionMessageToolCallChunk {
+                        index: 0,
+                        id: id.map(String::from),
+                        r#type: Some(async_openai::types::ChatCompletionToolType::Function),
+                        function: Some(async_openai::types::FunctionCallStream {
+                            name: name.map(String::from),
+                            arguments: Some(arguments.to_string()),
+                        }),
+                    },
+                ]);
+                delta
+            })
+            .collect();
+        let stream = Box::pin(stream::iter(deltas));
+
+        let response = DeltaAggregator::apply(stream).await.unwrap();
+        let choice = &response.inner.choices[0];
+        assert!(choice.message.content.is_none());
+        assert_eq!(
+            choice.finish_reason,
+            Some(async_openai::types::FinishReason::ToolCalls)
+        );
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "call-1");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, "{\"city\": \"Paris\"}");
+    }
 }
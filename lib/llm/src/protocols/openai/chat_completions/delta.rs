@@ -14,7 +14,12 @@
 // limitations under the License.
 
 use super::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse};
+use crate::preprocessor::tools::{
+    is_tool_call_prefix, ToolCallResponse, ToolCallingMatcher, ToolChoice, TOOL_CALL_START,
+};
 use crate::protocols::common;
+use crate::protocols::openai::continuation::Continuation;
+use crate::protocols::openai::nvext::NvResponseExt;
 
 /// Provides a method for generating a [`DeltaGenerator`] from a chat completion request.
 impl NvCreateChatCompletionRequest {
@@ -23,12 +28,23 @@ impl NvCreateChatCompletionRequest {
     /// # Returns
     /// * [`DeltaGenerator`] configured with model name and response options.
     pub fn response_generator(&self) -> DeltaGenerator {
+        let include_usage = self
+            .inner
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
         let options = DeltaGeneratorOptions {
-            enable_usage: true,
+            enable_usage: !include_usage,
+            enable_usage_chunk: include_usage,
             enable_logprobs: self.inner.logprobs.unwrap_or(false),
+            enable_tool_calls: self.inner.tools.as_ref().is_some_and(|t| !t.is_empty())
+                && !matches!(
+                    self.inner.tool_choice,
+                    Some(async_openai::types::ChatCompletionToolChoiceOption::None)
+                ),
         };
 
-        DeltaGenerator::new(self.inner.model.clone(), options)
+        DeltaGenerator::new(self.inner.model.clone(), options).with_seed(self.inner.seed)
     }
 }
 
@@ -37,8 +53,13 @@ impl NvCreateChatCompletionRequest {
 pub struct DeltaGeneratorOptions {
     /// Determines whether token usage statistics should be included in the response.
     pub enable_usage: bool,
+    /// End the stream with a chunk of its own carrying the usage, as asked for with
+    /// `stream_options.include_usage`, instead of including it in every chunk.
+    pub enable_usage_chunk: bool,
     /// Determines whether log probabilities should be included in the response.
     pub enable_logprobs: bool,
+    /// Parse tool calls out of the generated text and return them as `tool_calls`.
+    pub enable_tool_calls: bool,
 }
 
 /// Generates incremental chat completion responses in a streaming fashion.
@@ -62,6 +83,12 @@ pub struct DeltaGenerator {
     msg_counter: u64,
     /// Configuration options for response generation.
     options: DeltaGeneratorOptions,
+    /// Holds back text that may be a tool call, if tool calls are enabled.
+    tool_call_buffer: Option<ToolCallBuffer>,
+    /// NVIDIA extensions sent with every chunk, such as the seed.
+    nvext: Option<NvResponseExt>,
+    /// Tracks the generated tokens, if the response can be continued.
+    continuation: Option<Continuation>,
 }
 
 impl DeltaGenerator {
@@ -98,10 +125,51 @@ impl DeltaGenerator {
             service_tier: None,
             usage,
             msg_counter: 0,
+            tool_call_buffer: options.enable_tool_calls.then(ToolCallBuffer::default),
             options,
+            nvext: None,
+            continuation: None,
         }
     }
 
+    /// Echoes `seed` back in the `nvext` of the responses, if set.
+    pub fn with_seed(mut self, seed: Option<i64>) -> Self {
+        self.nvext = NvResponseExt::for_seed(seed);
+        self
+    }
+
+    /// Sends a continuation token with the last chunk if the response stops at the token limit.
+    pub fn with_continuation(mut self, continuation: Option<Continuation>) -> Self {
+        self.continuation = continuation;
+        self
+    }
+
+    /// The NVIDIA extensions to send with each chunk.
+    pub fn nvext(&self) -> Option<NvResponseExt> {
+        self.nvext.clone()
+    }
+
+    /// The NVIDIA extensions of a chunk, with the continuation token on the one that stopped
+    /// at the token limit.
+    fn chunk_nvext(&self, finish_reason: Option<&common::FinishReason>) -> Option<NvResponseExt> {
+        let mut nvext = self.nvext.clone();
+        if let (Some(continuation), Some(common::FinishReason::Length)) =
+            (&self.continuation, finish_reason)
+        {
+            nvext
+                .get_or_insert_with(NvResponseExt::default)
+                .continuation = Some(continuation.encode());
+        }
+        nvext
+    }
+
+    /// The usage so far, with its total.
+    fn usage(&self) -> async_openai::types::CompletionUsage {
+        let mut usage = self.usage.clone();
+        usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+        usage
+    }
+
     /// Updates the prompt token usage count.
     ///
     /// # Arguments
@@ -158,7 +226,7 @@ impl DeltaGenerator {
             system_fingerprint: self.system_fingerprint.clone(),
             choices,
             usage: if self.options.enable_usage {
-                Some(self.usage.clone())
+                Some(self.usage())
             } else {
                 None
             },
@@ -184,10 +252,12 @@ impl crate::protocols::openai::DeltaGeneratorExt<NvCreateChatCompletionStreamRes
         &mut self,
         delta: crate::protocols::common::llm_backend::BackendOutput,
     ) -> anyhow::Result<NvCreateChatCompletionStreamResponse> {
-        // Aggregate token usage if enabled.
-        if self.options.enable_usage {
-            self.usage.completion_tokens += delta.token_ids.len() as u32;
+        self.usage.completion_tokens += delta.token_ids.len() as u32;
+
+        if let Some(continuation) = self.continuation.as_mut() {
+            continuation.extend(&delta.token_ids);
         }
+        let nvext = self.chunk_nvext(delta.finish_reason.as_ref());
 
         // TODO: Implement log probabilities aggregation.
         let logprobs = None;
@@ -204,16 +274,290 @@ impl crate::protocols::openai::DeltaGeneratorExt<NvCreateChatCompletionStreamRes
             None => None,
         };
 
+        let (text, tool_calls, finish_reason) = match self.tool_call_buffer.as_mut() {
+            Some(buffer) => buffer.push(delta.text, finish_reason),
+            None => (delta.text, None, finish_reason),
+        };
+
         // Create the streaming response.
         let index = 0;
-        let stream_response = self.create_choice(index, delta.text, finish_reason, logprobs);
+        let mut stream_response = self.create_choice(index, text, finish_reason, logprobs);
+        if let Some(tool_calls) = tool_calls {
+            stream_response.choices[0].delta.tool_calls = Some(
+                tool_calls
+                    .into_iter()
+                    .enumerate()
+                    .map(
+                        |(i, call)| async_openai::types::ChatCompletionMessageToolCallChunk {
+                            index: i as u32,
+                            id: Some(call.id),
+                            r#type: Some(async_openai::types::ChatCompletionToolType::Function),
+                            function: Some(async_openai::types::FunctionCallStream {
+                                name: Some(call.function.name),
+                                arguments: Some(call.function.arguments),
+                            }),
+                        },
+                    )
+                    .collect(),
+            );
+        }
 
         Ok(NvCreateChatCompletionStreamResponse {
             inner: stream_response,
+            nvext,
         })
     }
 
     fn get_isl(&self) -> Option<u32> {
         Some(self.usage.prompt_tokens)
     }
+
+    fn usage_chunk(&self) -> Option<NvCreateChatCompletionStreamResponse> {
+        if !self.options.enable_usage_chunk {
+            return None;
+        }
+        let mut inner = self.create_choice(0, None, None, None);
+        inner.choices.clear();
+        inner.usage = Some(self.usage());
+        Some(NvCreateChatCompletionStreamResponse {
+            inner,
+            nvext: self.nvext(),
+        })
+    }
+
+    fn flush(&mut self) -> Option<NvCreateChatCompletionStreamResponse> {
+        let text = self.tool_call_buffer.as_mut()?.flush()?;
+        Some(NvCreateChatCompletionStreamResponse {
+            inner: self.create_choice(0, Some(text), None, None),
+            nvext: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ToolCallState {
+    /// Haven't seen enough text yet to know
+    #[default]
+    Undecided,
+    /// Ordinary text, stream it
+    Text,
+    /// Looks like a tool call, hold it back until the end
+    ToolCall,
+}
+
+/// Text, tool calls and finish reason to send in the next chunk
+type BufferOutput = (
+    Option<String>,
+    Option<Vec<ToolCallResponse>>,
+    Option<async_openai::types::FinishReason>,
+);
+
+/// Tool calls can only be parsed once complete, so when the output starts like one we hold the
+/// text back until generation finishes. If it then doesn't parse, the text is sent as content.
+#[derive(Debug, Clone, Default)]
+struct ToolCallBuffer {
+    state: ToolCallState,
+    text: String,
+}
+
+impl ToolCallBuffer {
+    /// Returns the text and tool calls to send now, and the finish reason to send with them.
+    fn push(
+        &mut self,
+        text: Option<String>,
+        finish_reason: Option<async_openai::types::FinishReason>,
+    ) -> BufferOutput {
+        if self.state == ToolCallState::Text {
+            return (text, None, finish_reason);
+        }
+        if let Some(text) = text {
+            self.text.push_str(&text);
+        }
+
+        if self.state == ToolCallState::Undecided {
+            let start = self.text.trim_start();
+            if is_tool_call_prefix(start) {
+                self.state = ToolCallState::ToolCall;
+            } else if !start.is_empty() && !TOOL_CALL_START.starts_with(start) {
+                self.state = ToolCallState::Text;
+                return (Some(std::mem::take(&mut self.text)), None, finish_reason);
+            }
+        }
+        if finish_reason.is_none() {
+            return (None, None, None);
+        }
+
+        let text = std::mem::take(&mut self.text);
+        if self.state == ToolCallState::ToolCall {
+            let matcher = ToolCallingMatcher::new(ToolChoice::Auto).expect("infallible");
+            match matcher.get_call(&text) {
+                Ok(calls) if !calls.is_empty() => {
+                    return (
+                        None,
+                        Some(calls),
+                        Some(async_openai::types::FinishReason::ToolCalls),
+                    );
+                }
+                Ok(_) => {}
+                Err(err) => tracing::debug!(%err, "Output looked like a tool call but isn't"),
+            }
+        }
+        ((!text.is_empty()).then_some(text), None, finish_reason)
+    }
+
+    /// The text held back, as content. Without a finish reason we can't know the tool call was
+    /// complete.
+    fn flush(&mut self) -> Option<String> {
+        let text = std::mem::take(&mut self.text);
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::FinishReason;
+
+    #[test]
+    fn test_seed_echo() {
+        use crate::protocols::common::llm_backend::BackendOutput;
+        use crate::protocols::common::SamplingOptionsProvider;
+        use crate::protocols::openai::DeltaGeneratorExt;
+
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "llama",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "temperature": 0.0,
+            "seed": 42,
+        }))
+        .unwrap();
+        let sampling = request.extract_sampling_options().unwrap();
+        assert_eq!(sampling.seed, Some(42));
+        assert_eq!(sampling.temperature, Some(0.0));
+
+        let mut generator = request.response_generator();
+        let response = generator
+            .choice_from_postprocessor(BackendOutput {
+                token_ids: vec![1],
+                tokens: vec![None],
+                text: Some("Hello".to_string()),
+                cum_log_probs: None,
+                log_probs: None,
+                finish_reason: None,
+            })
+            .unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["nvext"]["seed"], 42);
+        assert_eq!(json["choices"][0]["delta"]["content"], "Hello");
+    }
+
+    #[test]
+    fn test_usage_chunk() {
+        use crate::protocols::common::llm_backend::BackendOutput;
+        use crate::protocols::openai::DeltaGeneratorExt;
+
+        let output = || BackendOutput {
+            token_ids: vec![1, 2],
+            tokens: vec![None, None],
+            text: Some("Hi".to_string()),
+            cum_log_probs: None,
+            log_probs: None,
+            finish_reason: None,
+        };
+        let request = |stream_options: serde_json::Value| {
+            let request: NvCreateChatCompletionRequest =
+                serde_json::from_value(serde_json::json!({
+                    "model": "llama",
+                    "messages": [{"role": "user", "content": "Hi"}],
+                    "stream": true,
+                    "stream_options": stream_options,
+                }))
+                .unwrap();
+            request
+        };
+
+        let mut generator =
+            request(serde_json::json!({"include_usage": true})).response_generator();
+        generator.update_isl(5);
+        let response = generator.choice_from_postprocessor(output()).unwrap();
+        assert!(response.inner.usage.is_none());
+        generator.choice_from_postprocessor(output()).unwrap();
+        let chunk = generator.usage_chunk().unwrap();
+        assert!(chunk.inner.choices.is_empty());
+        let usage = chunk.inner.usage.unwrap();
+        assert_eq!(
+            (
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.total_tokens
+            ),
+            (5, 4, 9)
+        );
+
+        // Without it the usage is in every chunk
+        let mut generator = request(serde_json::Value::Null).response_generator();
+        let response = generator.choice_from_postprocessor(output()).unwrap();
+        assert_eq!(response.inner.usage.unwrap().completion_tokens, 2);
+        assert!(generator.usage_chunk().is_none());
+    }
+
+    #[test]
+    fn test_tool_call_buffer_text() {
+        let mut buffer = ToolCallBuffer::default();
+        assert_eq!(buffer.push(Some(" ".to_string()), None), (None, None, None));
+        let (text, calls, _) = buffer.push(Some("Hello".to_string()), None);
+        assert_eq!(text.as_deref(), Some(" Hello"));
+        assert!(calls.is_none());
+        let (text, _, finish) = buffer.push(Some("!".to_string()), Some(FinishReason::Stop));
+        assert_eq!(text.as_deref(), Some("!"));
+        assert_eq!(finish, Some(FinishReason::Stop));
+    }
+
+    #[test]
+    fn test_tool_call_buffer_tool_call() {
+        let mut buffer = ToolCallBuffer::default();
+        for piece in [
+            "<tool",
+            "_call>{\"name\": \"get_weather\", ",
+            "\"arguments\": {}}",
+        ] {
+            assert_eq!(
+                buffer.push(Some(piece.to_string()), None),
+                (None, None, None)
+            );
+        }
+        let (text, calls, finish) =
+            buffer.push(Some("</tool_call>".to_string()), Some(FinishReason::Stop));
+        assert!(text.is_none());
+        assert_eq!(calls.unwrap()[0].function.name, "get_weather");
+        assert_eq!(finish, Some(FinishReason::ToolCalls));
+    }
+
+    #[test]
+    fn test_tool_call_buffer_not_a_call() {
+        let mut buffer = ToolCallBuffer::default();
+        assert_eq!(
+            buffer.push(Some("[1] ".to_string()), None),
+            (None, None, None)
+        );
+        let (text, calls, finish) = buffer.push(
+            Some("is a citation".to_string()),
+            Some(FinishReason::Length),
+        );
+        assert_eq!(text.as_deref(), Some("[1] is a citation"));
+        assert!(calls.is_none());
+        assert_eq!(finish, Some(FinishReason::Length));
+    }
+
+    #[test]
+    fn test_tool_call_buffer_flush() {
+        let mut buffer = ToolCallBuffer::default();
+        assert_eq!(
+            buffer.push(Some("<tool_call>{\"name\"".to_string()), None),
+            (None, None, None)
+        );
+        // The stream ended without a finish reason
+        assert_eq!(buffer.flush().as_deref(), Some("<tool_call>{\"name\""));
+        assert!(buffer.flush().is_none());
+    }
 }
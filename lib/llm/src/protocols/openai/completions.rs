@@ -16,7 +16,7 @@
 use std::collections::HashMap;
 
 use derive_builder::Builder;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use validator::Validate;
 
 mod aggregator;
@@ -27,19 +27,91 @@ pub use delta::DeltaGenerator;
 
 use super::{
     common::{self, SamplingOptionsProvider, StopConditionsProvider},
-    nvext::{NvExt, NvExtProvider},
+    nvext::{NvExt, NvExtProvider, NvResponseExt},
     CompletionUsage, ContentProvider, OpenAISamplingOptionsProvider, OpenAIStopConditionsProvider,
 };
 
+use crate::protocols::TokenIdType;
 use dynamo_runtime::protocols::annotated::AnnotationsProvider;
 
-#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+#[derive(Validate, Debug, Clone)]
 pub struct NvCreateCompletionRequest {
-    #[serde(flatten)]
     pub inner: async_openai::types::CreateCompletionRequest,
 
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub nvext: Option<NvExt>,
+
+    /// The prompt, if the client sent it as token ids. async-openai keeps those as u16, too small
+    /// for most vocabularies, so they are read here instead and `inner.prompt` has them as text.
+    pub prompt_token_ids: Option<Vec<TokenIdType>>,
+}
+
+/// How the request looks on the wire, without the token ids
+#[derive(Serialize, Deserialize)]
+struct CompletionRequestWire<Inner, Ext> {
+    #[serde(flatten)]
+    inner: Inner,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nvext: Option<Ext>,
+}
+
+impl<'de> Deserialize<'de> for NvCreateCompletionRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = serde_json::Value::deserialize(deserializer)?;
+        let prompt_token_ids = match value.get_mut("prompt") {
+            Some(prompt) => take_token_ids(prompt),
+            None => None,
+        };
+        let wire: CompletionRequestWire<async_openai::types::CreateCompletionRequest, NvExt> =
+            serde_json::from_value(value).map_err(D::Error::custom)?;
+        Ok(NvCreateCompletionRequest {
+            inner: wire.inner,
+            nvext: wire.nvext,
+            prompt_token_ids,
+        })
+    }
+}
+
+impl Serialize for NvCreateCompletionRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let wire = CompletionRequestWire {
+            inner: &self.inner,
+            nvext: self.nvext.as_ref(),
+        };
+        let Some(token_ids) = &self.prompt_token_ids else {
+            return wire.serialize(serializer);
+        };
+        let mut value = serde_json::to_value(wire).map_err(S::Error::custom)?;
+        value["prompt"] = serde_json::json!(token_ids);
+        value.serialize(serializer)
+    }
+}
+
+/// The token ids of a prompt of one list of them, which are replaced by their text as
+/// [`prompt_to_string`] would give it
+fn take_token_ids(prompt: &mut serde_json::Value) -> Option<Vec<TokenIdType>> {
+    let token_ids = match serde_json::from_value::<Vec<TokenIdType>>(prompt.clone()) {
+        Ok(token_ids) => token_ids,
+        Err(_) => {
+            let mut prompts =
+                serde_json::from_value::<Vec<Vec<TokenIdType>>>(prompt.clone()).ok()?;
+            if prompts.len() != 1 {
+                return None;
+            }
+            prompts.pop()?
+        }
+    };
+    // An empty list is also an empty list of strings
+    if token_ids.is_empty() {
+        return None;
+    }
+    let text = token_ids
+        .iter()
+        .map(|token| token.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    *prompt = serde_json::Value::String(text);
+    Some(token_ids)
 }
 
 /// Legacy OpenAI CompletionResponse
@@ -75,8 +147,9 @@ pub struct CompletionResponse {
     /// The optional nature of this field will be relaxed when it is supported.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub system_fingerprint: Option<String>,
-    // TODO(ryan)
-    // pub nvext: Option<NimResponseExt>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvResponseExt>,
 }
 
 /// Legacy OpenAI CompletionResponse Choice component
@@ -110,7 +183,7 @@ impl CompletionChoice {
 
 // TODO: validate this is the correct format
 /// Legacy OpenAI LogprobResult component
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct LogprobResult {
     pub tokens: Vec<String>,
     pub token_logprobs: Vec<f32>,
@@ -191,11 +264,22 @@ impl OpenAISamplingOptionsProvider for NvCreateCompletionRequest {
         self.inner.presence_penalty
     }
 
+    fn get_seed(&self) -> Option<i64> {
+        self.inner.seed
+    }
+
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
     }
 }
 
+impl common::GuidedDecodingProvider for NvCreateCompletionRequest {
+    /// The completions API has no `response_format`
+    fn extract_guided_decoding(&self) -> anyhow::Result<Option<common::GuidedDecodingOptions>> {
+        Ok(None)
+    }
+}
+
 impl OpenAIStopConditionsProvider for NvCreateCompletionRequest {
     fn get_max_tokens(&self) -> Option<u32> {
         self.inner.max_tokens
@@ -206,7 +290,10 @@ impl OpenAIStopConditionsProvider for NvCreateCompletionRequest {
     }
 
     fn get_stop(&self) -> Option<Vec<String>> {
-        None
+        self.inner.stop.as_ref().map(|stop| match stop {
+            async_openai::types::Stop::String(s) => vec![s.clone()],
+            async_openai::types::Stop::StringArray(arr) => arr.clone(),
+        })
     }
 
     fn nvext(&self) -> Option<&NvExt> {
@@ -250,6 +337,7 @@ impl ResponseFactory {
             choices: vec![choice],
             system_fingerprint: self.system_fingerprint.clone(),
             usage,
+            nvext: None,
         }
     }
 }
@@ -19,6 +19,7 @@ use anyhow::Result;
 use futures::StreamExt;
 
 use super::{CompletionChoice, CompletionResponse, CompletionUsage, LogprobResult};
+use crate::protocols::openai::nvext::NvResponseExt;
 use crate::protocols::{
     codec::{Message, SseCodecError},
     common::FinishReason,
@@ -34,6 +35,7 @@ pub struct DeltaAggregator {
     system_fingerprint: Option<String>,
     choices: HashMap<u64, DeltaChoice>,
     error: Option<String>,
+    nvext: Option<NvResponseExt>,
 }
 
 struct DeltaChoice {
@@ -59,6 +61,7 @@ impl DeltaAggregator {
             system_fingerprint: None,
             choices: HashMap::new(),
             error: None,
+            nvext: None,
         }
     }
 
@@ -92,6 +95,14 @@ impl DeltaAggregator {
                     if let Some(system_fingerprint) = delta.system_fingerprint {
                         aggregator.system_fingerprint = Some(system_fingerprint);
                     }
+                    // Keep the seed of the first choice, the others were sampled with the seeds
+                    // after it
+                    if delta.nvext.is_some()
+                        && (aggregator.nvext.is_none()
+                            || delta.choices.iter().any(|choice| choice.index == 0))
+                    {
+                        aggregator.nvext = delta.nvext;
+                    }
 
                     // handle the choices
                     for choice in delta.choices {
@@ -103,12 +114,18 @@ impl DeltaAggregator {
                                     index: choice.index,
                                     text: "".to_string(),
                                     finish_reason: None,
-                                    logprobs: choice.logprobs,
+                                    logprobs: None,
                                 });
 
                         state_choice.text.push_str(&choice.text);
 
-                        // todo - handle logprobs
+                        if let Some(logprobs) = choice.logprobs {
+                            let state = state_choice.logprobs.get_or_insert_with(Default::default);
+                            state.tokens.extend(logprobs.tokens);
+                            state.token_logprobs.extend(logprobs.token_logprobs);
+                            state.top_logprobs.extend(logprobs.top_logprobs);
+                            state.text_offset.extend(logprobs.text_offset);
+                        }
 
                         if let Some(finish_reason) = choice.finish_reason {
                             let reason = FinishReason::from_str(&finish_reason).ok();
@@ -144,6 +161,7 @@ impl DeltaAggregator {
             object: "text_completion".to_string(),
             system_fingerprint: aggregator.system_fingerprint,
             choices,
+            nvext: aggregator.nvext,
         })
     }
 }
@@ -195,6 +213,7 @@ mod tests {
                 created: 1234567890,
                 usage: None,
                 system_fingerprint: None,
+                nvext: None,
                 choices: vec![CompletionChoice {
                     index,
                     text: text.to_string(),
@@ -299,6 +318,7 @@ mod tests {
                 created: 1234567890,
                 usage: None,
                 system_fingerprint: None,
+                nvext: None,
                 choices: vec![
                     CompletionChoice {
                         index: 0,
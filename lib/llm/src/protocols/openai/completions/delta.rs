@@ -13,26 +13,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{CompletionChoice, CompletionResponse, NvCreateCompletionRequest};
+use super::{CompletionChoice, CompletionResponse, LogprobResult, NvCreateCompletionRequest};
 use crate::protocols::common;
+use crate::protocols::openai::continuation::Continuation;
+use crate::protocols::openai::nvext::NvResponseExt;
 use crate::protocols::openai::CompletionUsage;
 
 impl NvCreateCompletionRequest {
     // put this method on the request
     // inspect the request to extract options
     pub fn response_generator(&self) -> DeltaGenerator {
+        let include_usage = self
+            .inner
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
         let options = DeltaGeneratorOptions {
-            enable_usage: true,
-            enable_logprobs: false,
+            enable_usage: !include_usage,
+            enable_usage_chunk: include_usage,
+            enable_logprobs: self.inner.logprobs.is_some(),
         };
 
-        DeltaGenerator::new(self.inner.model.clone(), options)
+        DeltaGenerator::new(self.inner.model.clone(), options).with_seed(self.inner.seed)
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct DeltaGeneratorOptions {
     pub enable_usage: bool,
+    /// Send the usage in a last response of its own, for `stream_options.include_usage`
+    pub enable_usage_chunk: bool,
     pub enable_logprobs: bool,
 }
 
@@ -46,6 +56,12 @@ pub struct DeltaGenerator {
     usage: CompletionUsage,
 
     options: DeltaGeneratorOptions,
+
+    /// Sent with every response
+    nvext: Option<NvResponseExt>,
+
+    /// Tracks the generated tokens, if the response can be continued
+    continuation: Option<Continuation>,
 }
 
 impl DeltaGenerator {
@@ -63,9 +79,30 @@ impl DeltaGenerator {
             system_fingerprint: None,
             usage: CompletionUsage::default(),
             options,
+            nvext: None,
+            continuation: None,
         }
     }
 
+    /// Echo `seed` back in the `nvext` of the responses, if set
+    pub fn with_seed(mut self, seed: Option<i64>) -> Self {
+        self.nvext = NvResponseExt::for_seed(seed);
+        self
+    }
+
+    /// Send a continuation token with the last response if it stops at the token limit
+    pub fn with_continuation(mut self, continuation: Option<Continuation>) -> Self {
+        self.continuation = continuation;
+        self
+    }
+
+    /// The usage so far, with its total
+    fn usage(&self) -> CompletionUsage {
+        let mut usage = self.usage.clone();
+        usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+        usage
+    }
+
     pub fn update_isl(&mut self, isl: i32) {
         self.usage.prompt_tokens = isl;
     }
@@ -91,10 +128,11 @@ impl DeltaGenerator {
                 logprobs: None,
             }],
             usage: if self.options.enable_usage {
-                Some(self.usage.clone())
+                Some(self.usage())
             } else {
                 None
             },
+            nvext: self.nvext.clone(),
         }
     }
 }
@@ -105,11 +143,29 @@ impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGe
         delta: common::llm_backend::BackendOutput,
     ) -> anyhow::Result<CompletionResponse> {
         // aggregate usage
-        if self.options.enable_usage {
-            self.usage.completion_tokens += delta.token_ids.len() as i32;
+        self.usage.completion_tokens += delta.token_ids.len() as i32;
+
+        let mut continuation = None;
+        if let Some(state) = self.continuation.as_mut() {
+            state.extend(&delta.token_ids);
+            if matches!(delta.finish_reason, Some(common::FinishReason::Length)) {
+                continuation = Some(state.encode());
+            }
         }
 
-        // todo logprobs
+        let logprobs = match delta.log_probs {
+            Some(log_probs) if self.options.enable_logprobs => Some(LogprobResult {
+                tokens: delta
+                    .tokens
+                    .into_iter()
+                    .map(Option::unwrap_or_default)
+                    .collect(),
+                token_logprobs: log_probs.into_iter().map(|p| p as f32).collect(),
+                top_logprobs: vec![],
+                text_offset: vec![],
+            }),
+            _ => None,
+        };
 
         let finish_reason = match delta.finish_reason {
             Some(common::FinishReason::EoS) => Some("stop".to_string()),
@@ -124,11 +180,29 @@ impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGe
 
         // create choice
         let index = 0;
-        Ok(self.create_choice(index, delta.text, finish_reason))
+        let mut response = self.create_choice(index, delta.text, finish_reason);
+        response.choices[0].logprobs = logprobs;
+        if continuation.is_some() {
+            response
+                .nvext
+                .get_or_insert_with(NvResponseExt::default)
+                .continuation = continuation;
+        }
+        Ok(response)
     }
 
     // TODO: This is a hack. Change `prompt_tokens` to u32
     fn get_isl(&self) -> Option<u32> {
         Some(self.usage.prompt_tokens as u32)
     }
+
+    fn usage_chunk(&self) -> Option<CompletionResponse> {
+        if !self.options.enable_usage_chunk {
+            return None;
+        }
+        let mut response = self.create_choice(0, None, None);
+        response.choices.clear();
+        response.usage = Some(self.usage());
+        Some(response)
+    }
 }
@@ -0,0 +1,247 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Continuation tokens, to generate a very long response in segments.
+//!
+//! A request with `nvext.return_continuation` whose response stops at `max_tokens` gets a token in
+//! the `nvext` of its last chunk. Sending the same request again with that token in
+//! `nvext.continuation` resumes the generation where it stopped, on any worker: the token carries
+//! everything generated so far, which is prefilled again after the prompt, and the seed. Each
+//! segment is a request of its own, so the usual limits on tokens and time apply to it.
+//!
+//! With the same segment sizes, the segments are sampled with the same seeds and the response is
+//! reproducible, engine permitting.
+//!
+//! The tokens are signed with a keyed BLAKE3 MAC, so that a client can't make up the tokens to
+//! prefill. The key is derived from the cluster key, see [`dynamo_runtime::control_signing`], so
+//! that every ingress of the cluster takes the tokens of the others. Without a cluster key each
+//! process signs with a random key of its own, and a token only continues on the ingress that
+//! issued it, until it restarts.
+
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dynamo_runtime::control_signing::{ControlSigner, ControlSigningConfig};
+use serde::{Deserialize, Serialize};
+
+use crate::protocols::TokenIdType;
+
+const VERSION_PREFIX: &str = "2.";
+
+/// Context of the key derived from the cluster key
+const KEY_CONTEXT: &str = "dynamo 2025 continuation token MAC";
+
+static SERVER_KEY: LazyLock<[u8; 32]> =
+    LazyLock::new(
+        || match ControlSigner::load_key(&ControlSigningConfig::from_settings()) {
+            Ok(Some(cluster_key)) => blake3::derive_key(KEY_CONTEXT, &cluster_key),
+            Ok(None) => {
+                tracing::info!(
+                    "No cluster key, continuation tokens are only accepted by this process"
+                );
+                rand::random()
+            }
+            Err(err) => {
+                tracing::error!(%err, "Continuation tokens are only accepted by this process");
+                rand::random()
+            }
+        },
+    );
+
+/// The state of a generation in segments
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Continuation {
+    /// Checksum of the model deployment card the response was generated with
+    model: String,
+
+    /// Hash of the prompt token ids. The follow-up requests must have the same prompt.
+    prompt: String,
+
+    /// The seed of the first segment. Chosen at random if the request had none.
+    seed: i64,
+
+    /// The tokens generated by the segments so far
+    generated: Vec<TokenIdType>,
+}
+
+impl Continuation {
+    /// Start generating the response to `prompt` in segments
+    pub fn new(model: &str, prompt: &[TokenIdType], seed: Option<i64>) -> Self {
+        Continuation {
+            model: model.to_string(),
+            prompt: prompt_hash(prompt),
+            seed: seed.unwrap_or_else(rand::random),
+            generated: Vec::new(),
+        }
+    }
+
+    /// Parse a token from [`Continuation::encode`]
+    pub fn decode(token: &str) -> anyhow::Result<Self> {
+        Self::decode_with(token, &SERVER_KEY)
+    }
+
+    /// Parse a token from [`Continuation::encode_with`] with the same `key`
+    pub fn decode_with(token: &str, key: &[u8; 32]) -> anyhow::Result<Self> {
+        let encoded = token
+            .strip_prefix(VERSION_PREFIX)
+            .context("Unknown continuation token version")?;
+        let (payload, mac) = encoded
+            .split_once('.')
+            .context("Continuation token is not signed")?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .context("Continuation token is not valid base64")?;
+        let mac: [u8; 32] = URL_SAFE_NO_PAD
+            .decode(mac)
+            .ok()
+            .and_then(|mac| mac.try_into().ok())
+            .context("Continuation token signature is not valid")?;
+        // Hash equality is constant time
+        if blake3::keyed_hash(key, &payload) != blake3::Hash::from(mac) {
+            anyhow::bail!("Continuation token was not issued by this service");
+        }
+        rmp_serde::from_slice(&payload).context("Continuation token is corrupt")
+    }
+
+    /// The token, signed with the key of this service
+    pub fn encode(&self) -> String {
+        self.encode_with(&SERVER_KEY)
+    }
+
+    pub fn encode_with(&self, key: &[u8; 32]) -> String {
+        // safety: The struct has only plain fields
+        let payload = rmp_serde::to_vec(self).unwrap();
+        let mac = blake3::keyed_hash(key, &payload);
+        format!(
+            "{VERSION_PREFIX}{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(mac.as_bytes())
+        )
+    }
+
+    /// Check that the token can continue a request to `model` with this prompt, and that what it
+    /// prefills fits the model: token ids below `vocab_size`, and the prompt and the generated
+    /// tokens within `context_length`. The error is a message for the client.
+    pub fn check(
+        &self,
+        model: &str,
+        prompt: &[TokenIdType],
+        vocab_size: Option<usize>,
+        context_length: Option<usize>,
+    ) -> Result<(), String> {
+        if self.model != model {
+            return Err("Continuation token was issued by a different model".to_string());
+        }
+        if self.prompt != prompt_hash(prompt) {
+            return Err("Continuation token was issued for a different prompt".to_string());
+        }
+        if let Some(vocab_size) = vocab_size {
+            if let Some(id) = self.generated.iter().find(|id| **id as usize >= vocab_size) {
+                return Err(format!(
+                    "Continuation token has token id {id}, the vocabulary has {vocab_size}"
+                ));
+            }
+        }
+        if let Some(context_length) = context_length {
+            let total = prompt.len() + self.generated.len();
+            if total > context_length {
+                return Err(format!(
+                    "Continuation token and prompt have {total} tokens, the context length is {context_length}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tokens generated by the earlier segments
+    pub fn generated(&self) -> &[TokenIdType] {
+        &self.generated
+    }
+
+    /// Record tokens of the current segment
+    pub fn extend(&mut self, token_ids: &[TokenIdType]) {
+        self.generated.extend_from_slice(token_ids);
+    }
+
+    /// The seed to sample the next segment with. It depends on where the segment starts, so that
+    /// the segments don't repeat each other's random choices.
+    pub fn segment_seed(&self) -> i64 {
+        self.seed.wrapping_add(self.generated.len() as i64)
+    }
+}
+
+fn prompt_hash(prompt: &[TokenIdType]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for token_id in prompt {
+        hasher.update(&token_id.to_le_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let prompt = [1, 2, 3];
+        let mut continuation = Continuation::new("mdcsum", &prompt, Some(42));
+        assert_eq!(continuation.segment_seed(), 42);
+        continuation.extend(&[100, 200_000]);
+
+        let token = continuation.encode();
+        assert!(token.starts_with("2."));
+        let decoded = Continuation::decode(&token).unwrap();
+        assert_eq!(decoded, continuation);
+        assert_eq!(decoded.generated(), &[100, 200_000]);
+        assert_eq!(decoded.segment_seed(), 44);
+
+        assert!(decoded.check("mdcsum", &prompt, None, None).is_ok());
+        assert!(decoded.check("other", &prompt, None, None).is_err());
+        assert!(decoded.check("mdcsum", &[1, 2], None, None).is_err());
+        assert!(Continuation::decode("2.AAAA").is_err());
+        assert!(Continuation::decode(&token[2..]).is_err());
+    }
+
+    #[test]
+    fn test_signed() {
+        let mut continuation = Continuation::new("mdcsum", &[1, 2, 3], Some(42));
+        continuation.extend(&[100]);
+        let key = [7u8; 32];
+        let token = continuation.encode_with(&key);
+        assert_eq!(
+            Continuation::decode_with(&token, &key).unwrap(),
+            continuation
+        );
+        assert!(Continuation::decode_with(&token, &[8u8; 32]).is_err());
+
+        // A client can't change the tokens to prefill, or drop the signature
+        let (payload, _) = token.rsplit_once('.').unwrap();
+        let mut forged = continuation.clone();
+        forged.extend(&[666]);
+        let forged_token = forged.encode_with(&[8u8; 32]);
+        let (_, forged_mac) = forged_token.rsplit_once('.').unwrap();
+        assert!(Continuation::decode_with(&format!("{payload}.{forged_mac}"), &key).is_err());
+        assert!(Continuation::decode_with(payload, &key).is_err());
+        let (forged_payload, _) = forged_token.rsplit_once('.').unwrap();
+        let (_, mac) = token.rsplit_once('.').unwrap();
+        assert!(Continuation::decode_with(&format!("{forged_payload}.{mac}"), &key).is_err());
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let prompt = [1, 2, 3];
+        let mut continuation = Continuation::new("mdcsum", &prompt, None);
+        continuation.extend(&[10, 20]);
+        assert!(continuation
+            .check("mdcsum", &prompt, Some(21), Some(5))
+            .is_ok());
+        assert!(continuation
+            .check("mdcsum", &prompt, Some(20), None)
+            .is_err());
+        assert!(continuation
+            .check("mdcsum", &prompt, None, Some(4))
+            .is_err());
+    }
+}
@@ -13,10 +13,65 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{HashMap, HashSet};
+
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
+/// The keys of [`NvExt`] that Dynamo understands. What happens to any other key is decided by
+/// the [`NvExtPolicy`] of the HTTP service.
+pub const REGISTERED_KEYS: &[&str] = &[
+    "ignore_eos",
+    "top_k",
+    "repetition_penalty",
+    "greed_sampling",
+    "use_raw_prompt",
+    "annotations",
+    "priority",
+    "routing",
+    "tenant",
+    "principal",
+    "trace",
+    "return_continuation",
+    "continuation",
+    "sampling_preset",
+    "max_tokens_per_sec",
+    "deferrable",
+];
+
+const MAX_TENANT_LEN: usize = 128;
+
+/// Longest `routing.session_id`
+pub const MAX_SESSION_ID_LEN: usize = 256;
+
+/// Priority of latency sensitive traffic, `interactive` in the `x-dynamo-priority` header
+pub const PRIORITY_INTERACTIVE: i32 = 50;
+
+/// Priority of throughput oriented traffic, `batch` in the `x-dynamo-priority` header
+pub const PRIORITY_BATCH: i32 = -50;
+
+/// Parse a priority given as a number from -100 to 100, or as one of the classes `interactive`,
+/// `default` and `batch`. The error is a message for the client.
+pub fn parse_priority(value: &str) -> Result<i32, String> {
+    let priority = match value.trim().to_ascii_lowercase().as_str() {
+        "interactive" => PRIORITY_INTERACTIVE,
+        "default" => 0,
+        "batch" => PRIORITY_BATCH,
+        other => other.parse::<i32>().map_err(|_| {
+            format!(
+                "priority must be a number or one of interactive, default, batch, got '{value}'"
+            )
+        })?,
+    };
+    if !(-100..=100).contains(&priority) {
+        return Err(format!(
+            "priority must be between -100 and 100, got {priority}"
+        ));
+    }
+    Ok(priority)
+}
+
 pub trait NvExtProvider {
     fn nvext(&self) -> Option<&NvExt>;
     fn raw_prompt(&self) -> Option<String>;
@@ -61,6 +116,260 @@ pub struct NvExt {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[builder(default, setter(strip_option))]
     pub annotations: Option<Vec<String>>,
+
+    /// Scheduling priority, from -100 to 100. Higher is more important. Engines that don't
+    /// support priorities ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    #[validate(range(min = -100, max = 100))]
+    pub priority: Option<i32>,
+
+    /// Hints for the router
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub routing: Option<RoutingHints>,
+
+    /// Who the request is for, for accounting and isolation. Letters, digits, `-`, `_` and `.`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option, into))]
+    pub tenant: Option<String>,
+
+    /// Which API key sent the request, set by the HTTP service. Whatever the client puts here is
+    /// replaced. The KV router shares busy workers fairly between principals.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option, into))]
+    pub principal: Option<String>,
+
+    /// W3C trace context of the caller, so the request can be followed through the workers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub trace: Option<TraceContext>,
+
+    /// If true and the response stops at the token limit, its `nvext` has a continuation token
+    /// to generate the rest with another request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub return_continuation: Option<bool>,
+
+    /// Continuation token of an earlier response to this request, to resume generating it. See
+    /// [`super::continuation`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option, into))]
+    pub continuation: Option<String>,
+
+    /// Named set of sampling parameters of the deployment, e.g. `precise`. Parameters the request
+    /// sets itself win over the preset's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option, into))]
+    pub sampling_preset: Option<String>,
+
+    /// Send the tokens of a streamed response at most this fast, for clients that can't buffer
+    /// bursts. The API key's policy may lower it, see [`crate::http::service::output_rate`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    #[validate(range(min = crate::http::service::output_rate::MIN_TOKENS_PER_SEC))]
+    pub max_tokens_per_sec: Option<f64>,
+
+    /// The caller can wait for the response. While the model has no workers, the HTTP service
+    /// may queue the request and answer `202 Accepted`, see
+    /// [`crate::http::service::spillover`]. Only for non-streaming requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub deferrable: Option<bool>,
+
+    /// Keys that are not in [`REGISTERED_KEYS`]. The [`NvExtPolicy`] decides whether they are
+    /// rejected, dropped or passed through to the workers.
+    #[serde(flatten)]
+    #[builder(default)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingHints {
+    /// Send the request to this worker instance instead of letting the router choose.
+    /// The HTTP service drops it for principals the [`NvExtPolicy`] doesn't let pin requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_instance_id: Option<i64>,
+
+    /// The conversation the request is part of. The KV router sends the requests of a session to
+    /// the same worker while it is healthy, see [`crate::kv_router::affinity`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+
+    /// The class of the request in the budget policy of the router, e.g. `interactive` or
+    /// `batch`, see [`crate::budget_router`]. Unknown classes get the policy's default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_class: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// `traceparent` header value: `version-trace_id-parent_id-flags`
+    pub traceparent: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+}
+
+/// NVIDIA extensions of a response
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct NvResponseExt {
+    /// The seed the response was sampled with. Sending it again with the same request reproduces
+    /// the response, engine permitting. With `n` or `best_of` above 1, choice `i` is sampled with
+    /// this seed plus `i`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    /// Token to continue a response that stopped at the token limit, if the request asked for
+    /// one with `nvext.return_continuation`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+}
+
+impl NvResponseExt {
+    /// The extension to send for a request sampled with `seed`, None if there is nothing to send
+    pub fn for_seed(seed: Option<i64>) -> Option<Self> {
+        seed.map(|seed| NvResponseExt {
+            seed: Some(seed),
+            ..Default::default()
+        })
+    }
+}
+
+/// What to do with `nvext` keys that are not registered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownKeyPolicy {
+    /// Remove them before the request goes further
+    #[default]
+    Drop,
+
+    /// Fail the request with a 400
+    Reject,
+
+    /// Forward them to the workers unchanged
+    PassThrough,
+}
+
+/// How the HTTP service treats the `nvext` field of incoming requests
+#[derive(Debug, Clone, Default)]
+pub struct NvExtPolicy {
+    unknown_keys: UnknownKeyPolicy,
+
+    /// Deployment specific keys, always passed through to the workers
+    allowed: HashSet<String>,
+
+    /// The principals that may pin requests to a worker with `routing.backend_instance_id`.
+    /// Pinning bypasses load balancing, the circuit breakers and the zone policy, so nobody may
+    /// by default. `*` lets anybody, for services behind a trusted gateway.
+    pinning: HashSet<String>,
+
+    /// The highest `priority` each principal may ask for, `*` for principals not listed.
+    /// Without an entry it is 0, so that clients can lower the priority of their requests but
+    /// not jump the queue.
+    max_priority: HashMap<String, i32>,
+}
+
+impl NvExtPolicy {
+    pub fn new(unknown_keys: UnknownKeyPolicy) -> Self {
+        NvExtPolicy {
+            unknown_keys,
+            allowed: HashSet::new(),
+            pinning: HashSet::new(),
+            max_priority: HashMap::new(),
+        }
+    }
+
+    /// Register an extra key that workers of this deployment understand
+    pub fn allow(mut self, key: impl Into<String>) -> Self {
+        self.allowed.insert(key.into());
+        self
+    }
+
+    /// Let `principal`, e.g. `key-0123456789ab`, pin requests to a worker. `*` for anybody.
+    pub fn allow_pinning(mut self, principal: impl Into<String>) -> Self {
+        self.pinning.insert(principal.into());
+        self
+    }
+
+    /// Let `principal`, e.g. `key-0123456789ab`, ask for priorities up to `max`. `*` for the
+    /// principals not listed.
+    pub fn allow_priority(mut self, principal: impl Into<String>, max: i32) -> Self {
+        self.max_priority.insert(principal.into(), max);
+        self
+    }
+
+    /// Lower `priority` to the highest the principal of the request may ask for. Call it once
+    /// the principal is set.
+    pub fn apply_priority(&self, nvext: &mut NvExt) {
+        let Some(priority) = nvext.priority else {
+            return;
+        };
+        let max = nvext
+            .principal
+            .as_deref()
+            .and_then(|principal| self.max_priority.get(principal))
+            .or_else(|| self.max_priority.get("*"))
+            .copied()
+            .unwrap_or(0);
+        if priority > max {
+            tracing::debug!(
+                principal = ?nvext.principal,
+                priority,
+                max,
+                "Lowering nvext.priority to the principal's maximum"
+            );
+            nvext.priority = Some(max);
+        }
+    }
+
+    /// Remove `routing.backend_instance_id` unless the principal of the request may pin requests
+    /// to a worker. Call it once the principal is set.
+    pub fn apply_pinning(&self, nvext: &mut NvExt) {
+        let Some(routing) = nvext.routing.as_mut() else {
+            return;
+        };
+        if routing.backend_instance_id.is_none() || self.pinning.contains("*") {
+            return;
+        }
+        if let Some(principal) = nvext.principal.as_deref() {
+            if self.pinning.contains(principal) {
+                return;
+            }
+        }
+        tracing::debug!(
+            principal = ?nvext.principal,
+            "Dropping nvext.routing.backend_instance_id, the principal may not pin requests"
+        );
+        routing.backend_instance_id = None;
+    }
+
+    /// Validate `nvext` and apply the unknown key policy to it.
+    /// The error is a message for the client.
+    pub fn apply(&self, nvext: &mut NvExt) -> Result<(), String> {
+        let mut unknown: Vec<&String> = nvext
+            .extra
+            .keys()
+            .filter(|k| !self.allowed.contains(*k))
+            .collect();
+        if !unknown.is_empty() {
+            match self.unknown_keys {
+                UnknownKeyPolicy::Reject => {
+                    unknown.sort();
+                    let keys: Vec<&str> = unknown.iter().map(|k| k.as_str()).collect();
+                    return Err(format!("Unknown nvext keys: {}", keys.join(", ")));
+                }
+                UnknownKeyPolicy::Drop => {
+                    let unknown: Vec<String> = unknown.into_iter().cloned().collect();
+                    tracing::debug!(?unknown, "Dropping unknown nvext keys");
+                    for key in unknown {
+                        nvext.extra.remove(&key);
+                    }
+                }
+                UnknownKeyPolicy::PassThrough => {}
+            }
+        }
+        nvext.validate().map_err(|err| err.to_string())
+    }
 }
 
 impl Default for NvExt {
@@ -75,10 +384,60 @@ impl NvExt {
     }
 }
 
-fn validate_nv_ext(_nv_ext: &NvExt) -> Result<(), ValidationError> {
+fn validate_nv_ext(nv_ext: &NvExt) -> Result<(), ValidationError> {
+    if let Some(tenant) = nv_ext.tenant.as_deref() {
+        let valid_chars = tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if tenant.is_empty() || tenant.len() > MAX_TENANT_LEN || !valid_chars {
+            let mut error = ValidationError::new("tenant");
+            error.message = Some(
+                format!("tenant must be 1 to {MAX_TENANT_LEN} letters, digits, '-', '_' or '.'")
+                    .into(),
+            );
+            return Err(error);
+        }
+    }
+    if let Some(session_id) = nv_ext
+        .routing
+        .as_ref()
+        .and_then(|routing| routing.session_id.as_deref())
+    {
+        if session_id.is_empty() || session_id.len() > MAX_SESSION_ID_LEN {
+            let mut error = ValidationError::new("routing");
+            error.message =
+                Some(format!("routing.session_id must be 1 to {MAX_SESSION_ID_LEN} bytes").into());
+            return Err(error);
+        }
+    }
+    if let Some(trace) = nv_ext.trace.as_ref() {
+        if !is_valid_traceparent(&trace.traceparent) {
+            let mut error = ValidationError::new("trace");
+            error.message = Some("trace.traceparent is not a valid W3C traceparent".into());
+            return Err(error);
+        }
+    }
     Ok(())
 }
 
+/// `00-<32 hex>-<16 hex>-<2 hex>`, with the all zero ids not allowed
+fn is_valid_traceparent(traceparent: &str) -> bool {
+    let parts: Vec<&str> = traceparent.split('-').collect();
+    let [version, trace_id, parent_id, flags] = parts.as_slice() else {
+        return false;
+    };
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len && s.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+    };
+    is_hex(version, 2)
+        && *version != "ff"
+        && is_hex(trace_id, 32)
+        && trace_id.chars().any(|c| c != '0')
+        && is_hex(parent_id, 16)
+        && parent_id.chars().any(|c| c != '0')
+        && is_hex(flags, 2)
+}
+
 fn validate_top_k(top_k: i64) -> Result<(), ValidationError> {
     if top_k == -1 || (top_k >= 1) {
         return Ok(());
@@ -136,6 +495,15 @@ mod tests {
         assert!(nv_ext.validate().is_ok());
     }
 
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!(parse_priority("interactive"), Ok(PRIORITY_INTERACTIVE));
+        assert_eq!(parse_priority("Batch"), Ok(PRIORITY_BATCH));
+        assert_eq!(parse_priority(" -7 "), Ok(-7));
+        assert!(parse_priority("101").is_err());
+        assert!(parse_priority("urgent").is_err());
+    }
+
     // Test invalid `top_k` validation using proptest
     proptest! {
         #[test]
@@ -150,6 +518,149 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_registered_keys() {
+        let nv_ext: NvExt = serde_json::from_str(
+            r#"{"priority": 5, "tenant": "team-a", "routing": {"backend_instance_id": 7},
+                "trace": {"traceparent": "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"},
+                "my_key": 1}"#,
+        )
+        .unwrap();
+        assert_eq!(nv_ext.priority, Some(5));
+        assert_eq!(nv_ext.tenant.as_deref(), Some("team-a"));
+        assert_eq!(nv_ext.routing.unwrap().backend_instance_id, Some(7));
+        assert_eq!(nv_ext.extra.len(), 1);
+        assert!(REGISTERED_KEYS
+            .iter()
+            .all(|k| !nv_ext.extra.contains_key(*k)));
+    }
+
+    #[test]
+    fn test_unknown_key_policy() {
+        let with_extra = || {
+            let mut nv_ext = NvExt::default();
+            nv_ext.extra.insert("a".to_string(), 1.into());
+            nv_ext.extra.insert("b".to_string(), 2.into());
+            nv_ext
+        };
+
+        let mut nv_ext = with_extra();
+        NvExtPolicy::default().apply(&mut nv_ext).unwrap();
+        assert!(nv_ext.extra.is_empty());
+
+        let mut nv_ext = with_extra();
+        NvExtPolicy::default()
+            .allow("a")
+            .apply(&mut nv_ext)
+            .unwrap();
+        assert_eq!(nv_ext.extra.keys().collect::<Vec<_>>(), ["a"]);
+
+        let mut nv_ext = with_extra();
+        let err = NvExtPolicy::new(UnknownKeyPolicy::Reject)
+            .apply(&mut nv_ext)
+            .unwrap_err();
+        assert_eq!(err, "Unknown nvext keys: a, b");
+
+        let mut nv_ext = with_extra();
+        NvExtPolicy::new(UnknownKeyPolicy::PassThrough)
+            .apply(&mut nv_ext)
+            .unwrap();
+        assert_eq!(nv_ext.extra.len(), 2);
+    }
+
+    #[test]
+    fn test_pinning_policy() {
+        let pinned = |principal: Option<&str>| {
+            let mut nv_ext = NvExt::builder()
+                .routing(RoutingHints {
+                    backend_instance_id: Some(7),
+                    ..Default::default()
+                })
+                .build()
+                .unwrap();
+            nv_ext.principal = principal.map(str::to_string);
+            nv_ext
+        };
+        let instance_id = |nv_ext: NvExt| nv_ext.routing.unwrap().backend_instance_id;
+
+        let mut nv_ext = pinned(Some("key-a"));
+        NvExtPolicy::default().apply_pinning(&mut nv_ext);
+        assert_eq!(instance_id(nv_ext), None);
+
+        let policy = NvExtPolicy::default().allow_pinning("key-a");
+        let mut nv_ext = pinned(Some("key-a"));
+        policy.apply_pinning(&mut nv_ext);
+        assert_eq!(instance_id(nv_ext), Some(7));
+        let mut nv_ext = pinned(Some("key-b"));
+        policy.apply_pinning(&mut nv_ext);
+        assert_eq!(instance_id(nv_ext), None);
+        let mut nv_ext = pinned(None);
+        policy.apply_pinning(&mut nv_ext);
+        assert_eq!(instance_id(nv_ext), None);
+
+        let mut nv_ext = pinned(None);
+        NvExtPolicy::default()
+            .allow_pinning("*")
+            .apply_pinning(&mut nv_ext);
+        assert_eq!(instance_id(nv_ext), Some(7));
+    }
+
+    #[test]
+    fn test_priority_policy() {
+        let priority = |policy: &NvExtPolicy, principal: Option<&str>, priority: i32| {
+            let mut nv_ext = NvExt::builder().priority(priority).build().unwrap();
+            nv_ext.principal = principal.map(str::to_string);
+            policy.apply_priority(&mut nv_ext);
+            nv_ext.priority
+        };
+
+        let policy = NvExtPolicy::default();
+        assert_eq!(priority(&policy, Some("key-a"), 100), Some(0));
+        assert_eq!(
+            priority(&policy, None, PRIORITY_BATCH),
+            Some(PRIORITY_BATCH)
+        );
+
+        let policy = NvExtPolicy::default()
+            .allow_priority("key-a", 100)
+            .allow_priority("*", PRIORITY_INTERACTIVE);
+        assert_eq!(priority(&policy, Some("key-a"), 100), Some(100));
+        assert_eq!(
+            priority(&policy, Some("key-b"), 100),
+            Some(PRIORITY_INTERACTIVE)
+        );
+        assert_eq!(priority(&policy, None, 100), Some(PRIORITY_INTERACTIVE));
+    }
+
+    #[test]
+    fn test_validate_tenant_and_trace() {
+        let policy = NvExtPolicy::default();
+        let mut nv_ext = NvExt::builder().tenant("bad tenant!").build().unwrap();
+        assert!(policy.apply(&mut nv_ext).is_err());
+
+        let mut nv_ext = NvExt::builder().priority(101).build().unwrap();
+        assert!(policy.apply(&mut nv_ext).is_err());
+
+        let trace = |traceparent: &str| TraceContext {
+            traceparent: traceparent.to_string(),
+            tracestate: None,
+        };
+        let mut nv_ext = NvExt::builder()
+            .trace(trace(
+                "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            ))
+            .build()
+            .unwrap();
+        assert!(policy.apply(&mut nv_ext).is_err());
+        let mut nv_ext = NvExt::builder()
+            .trace(trace(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .build()
+            .unwrap();
+        assert!(policy.apply(&mut nv_ext).is_ok());
+    }
+
     // Test valid `top_k` values
     #[test]
     fn test_valid_top_k_values() {
@@ -0,0 +1,560 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The OpenAI Responses API, served by the chat completions engines.
+//!
+//! A [`NvCreateResponseRequest`] becomes a [`NvCreateChatCompletionRequest`]: `instructions` is
+//! the system message, input messages are chat messages, `function_call` items are assistant tool
+//! calls and `function_call_output` items are tool messages. The chat completion stream is turned
+//! back into a [`Response`] and its events by a [`ResponseStreamer`].
+//!
+//! Only text content and function tools are supported.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionNamedToolChoice,
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessage, ChatCompletionRequestToolMessage,
+    ChatCompletionRequestUserMessage, ChatCompletionTool, ChatCompletionToolChoiceOption,
+    ChatCompletionToolType, CreateChatCompletionRequest, FunctionCall, FunctionName,
+    FunctionObject,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::chat_completions::NvCreateChatCompletionRequest;
+use super::nvext::NvExt;
+
+mod stream;
+
+pub use stream::{ResponseEvent, ResponseStreamer, SequencedEvent};
+
+/// A request to `/v1/responses`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NvCreateResponseRequest {
+    pub model: String,
+
+    pub input: ResponseInput,
+
+    /// A system message. Unlike the input, not carried over by `previous_response_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+
+    /// Continue the conversation of a stored response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    /// Return immediately and generate the response in the background
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<bool>,
+
+    /// Keep the response, to retrieve it or continue from it later. Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvExt>,
+}
+
+/// A single user message, or a list of items
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ResponseInput {
+    Text(String),
+    Items(Vec<Item>),
+}
+
+impl<'de> Deserialize<'de> for ResponseInput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(text) => Ok(ResponseInput::Text(text)),
+            serde_json::Value::Array(items) => items
+                .into_iter()
+                .map(|mut item| {
+                    // Messages may leave out their type
+                    if let Some(item) = item.as_object_mut() {
+                        item.entry("type").or_insert_with(|| "message".into());
+                    }
+                    serde_json::from_value(item)
+                })
+                .collect::<Result<_, _>>()
+                .map(ResponseInput::Items)
+                .map_err(D::Error::custom),
+            _ => Err(D::Error::custom(
+                "input must be a string or a list of items",
+            )),
+        }
+    }
+}
+
+/// An input or output item
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Item {
+    Message(MessageItem),
+    FunctionCall(FunctionCallItem),
+    FunctionCallOutput(FunctionCallOutputItem),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MessageItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub role: Role,
+    pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ItemStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+    Developer,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text of all the parts
+    fn text(&self) -> Result<String, String> {
+        let parts = match self {
+            MessageContent::Text(text) => return Ok(text.clone()),
+            MessageContent::Parts(parts) => parts,
+        };
+        parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::InputText { text } | ContentPart::OutputText { text, .. } => {
+                    Ok(text.as_str())
+                }
+                ContentPart::Refusal { refusal } => Ok(refusal.as_str()),
+                ContentPart::Unsupported => Err("Only text content is supported".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|texts| texts.join("\n"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+        #[serde(default)]
+        annotations: Vec<serde_json::Value>,
+    },
+    Refusal {
+        refusal: String,
+    },
+    /// Images, files and audio
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FunctionCallItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub call_id: String,
+    pub name: String,
+    pub arguments: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ItemStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FunctionCallOutputItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub call_id: String,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ItemStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    InProgress,
+    Completed,
+    Incomplete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Tool {
+    Function {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parameters: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        strict: Option<bool>,
+    },
+    /// Built-in tools such as web or file search
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(ToolChoiceMode),
+    /// `{"type": "function", "name": "..."}`
+    Function {
+        r#type: String,
+        name: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolChoiceMode {
+    None,
+    Auto,
+    Required,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Queued,
+    InProgress,
+    Completed,
+    Failed,
+    Cancelled,
+    Incomplete,
+}
+
+impl ResponseStatus {
+    /// Whether the response will not change anymore
+    pub fn is_final(&self) -> bool {
+        !matches!(self, ResponseStatus::Queued | ResponseStatus::InProgress)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ResponseUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResponseError {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IncompleteDetails {
+    /// `max_output_tokens` or `content_filter`
+    pub reason: String,
+}
+
+/// A response, as returned by `/v1/responses`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    pub id: String,
+    /// Always `response`
+    pub object: String,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    pub status: ResponseStatus,
+    pub model: String,
+    pub output: Vec<Item>,
+    pub usage: Option<ResponseUsage>,
+    pub error: Option<ResponseError>,
+    pub incomplete_details: Option<IncompleteDetails>,
+    pub instructions: Option<String>,
+    pub previous_response_id: Option<String>,
+    pub tools: Vec<Tool>,
+    pub tool_choice: ToolChoice,
+    pub parallel_tool_calls: bool,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_output_tokens: Option<u32>,
+    pub background: bool,
+    pub metadata: HashMap<String, String>,
+}
+
+impl Response {
+    /// A response to `request` that is in progress and has no output yet
+    pub fn new(id: String, request: &NvCreateResponseRequest) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Response {
+            id,
+            object: "response".to_string(),
+            created_at,
+            status: ResponseStatus::InProgress,
+            model: request.model.clone(),
+            output: Vec::new(),
+            usage: None,
+            error: None,
+            incomplete_details: None,
+            instructions: request.instructions.clone(),
+            previous_response_id: request.previous_response_id.clone(),
+            tools: request.tools.clone(),
+            tool_choice: request
+                .tool_choice
+                .clone()
+                .unwrap_or(ToolChoice::Mode(ToolChoiceMode::Auto)),
+            parallel_tool_calls: request.parallel_tool_calls.unwrap_or(true),
+            temperature: request.temperature,
+            top_p: request.top_p,
+            max_output_tokens: request.max_output_tokens,
+            background: request.background.unwrap_or(false),
+            metadata: request.metadata.clone(),
+        }
+    }
+}
+
+impl NvCreateResponseRequest {
+    /// The input as chat messages
+    pub fn input_messages(&self) -> Result<Vec<ChatCompletionRequestMessage>, String> {
+        match &self.input {
+            ResponseInput::Text(text) => {
+                Ok(vec![
+                    ChatCompletionRequestUserMessage::from(text.as_str()).into()
+                ])
+            }
+            ResponseInput::Items(items) => chat_messages(items),
+        }
+    }
+
+    /// The chat completion request generating this response, continuing `conversation`: the
+    /// messages of the previous responses and the input of this one.
+    pub fn chat_request(
+        &self,
+        conversation: Vec<ChatCompletionRequestMessage>,
+    ) -> Result<NvCreateChatCompletionRequest, String> {
+        let mut messages = Vec::with_capacity(conversation.len() + 1);
+        if let Some(instructions) = &self.instructions {
+            messages.push(ChatCompletionRequestSystemMessage::from(instructions.as_str()).into());
+        }
+        messages.extend(conversation);
+
+        let tools = self
+            .tools
+            .iter()
+            .map(|tool| match tool {
+                Tool::Function {
+                    name,
+                    description,
+                    parameters,
+                    strict,
+                } => Ok(ChatCompletionTool {
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionObject {
+                        name: name.clone(),
+                        description: description.clone(),
+                        parameters: parameters.clone(),
+                        strict: *strict,
+                    },
+                }),
+                Tool::Unsupported => Err("Only function tools are supported".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let tool_choice = self.tool_choice.as_ref().map(|choice| match choice {
+            ToolChoice::Mode(ToolChoiceMode::None) => ChatCompletionToolChoiceOption::None,
+            ToolChoice::Mode(ToolChoiceMode::Auto) => ChatCompletionToolChoiceOption::Auto,
+            ToolChoice::Mode(ToolChoiceMode::Required) => ChatCompletionToolChoiceOption::Required,
+            ToolChoice::Function { name, .. } => {
+                ChatCompletionToolChoiceOption::Named(ChatCompletionNamedToolChoice {
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionName { name: name.clone() },
+                })
+            }
+        });
+
+        let inner = CreateChatCompletionRequest {
+            model: self.model.clone(),
+            messages,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_completion_tokens: self.max_output_tokens,
+            // Engines always stream, responses are assembled from the stream
+            stream: Some(true),
+            tools: (!tools.is_empty()).then_some(tools),
+            tool_choice,
+            parallel_tool_calls: self.parallel_tool_calls,
+            user: self.user.clone(),
+            ..Default::default()
+        };
+        Ok(NvCreateChatCompletionRequest {
+            inner,
+            nvext: self.nvext.clone(),
+        })
+    }
+}
+
+/// Convert items to chat messages. Consecutive function calls, and the assistant message before
+/// them, are a single assistant message.
+pub fn chat_messages(items: &[Item]) -> Result<Vec<ChatCompletionRequestMessage>, String> {
+    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            Item::Message(message) => {
+                let text = message.content.text()?;
+                messages.push(match message.role {
+                    Role::User => ChatCompletionRequestUserMessage::from(text).into(),
+                    Role::Assistant => ChatCompletionRequestAssistantMessage::from(text).into(),
+                    // Chat templates don't know the developer role
+                    Role::System | Role::Developer => {
+                        ChatCompletionRequestSystemMessage::from(text).into()
+                    }
+                });
+            }
+            Item::FunctionCall(call) => {
+                let tool_call = ChatCompletionMessageToolCall {
+                    id: call.call_id.clone(),
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                };
+                if let Some(ChatCompletionRequestMessage::Assistant(assistant)) =
+                    messages.last_mut()
+                {
+                    assistant
+                        .tool_calls
+                        .get_or_insert_with(Vec::new)
+                        .push(tool_call);
+                } else {
+                    messages.push(
+                        ChatCompletionRequestAssistantMessage {
+                            tool_calls: Some(vec![tool_call]),
+                            ..Default::default()
+                        }
+                        .into(),
+                    );
+                }
+            }
+            Item::FunctionCallOutput(output) => {
+                messages.push(
+                    ChatCompletionRequestToolMessage {
+                        content: output.output.as_str().into(),
+                        tool_call_id: output.call_id.clone(),
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: serde_json::Value) -> NvCreateResponseRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_input_items() {
+        let request = request(serde_json::json!({
+            "model": "test",
+            "instructions": "Be brief",
+            "input": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"type": "function_call", "call_id": "call_1", "name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+                {"type": "function_call", "call_id": "call_2", "name": "get_time", "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "Sunny"},
+                {"type": "message", "role": "user", "content": [{"type": "input_text", "text": "Thanks"}]}
+            ],
+            "tools": [{"type": "function", "name": "get_weather", "parameters": {"type": "object"}}],
+            "tool_choice": {"type": "function", "name": "get_weather"}
+        }));
+        let messages = request.input_messages().unwrap();
+        assert_eq!(messages.len(), 4);
+        let ChatCompletionRequestMessage::Assistant(assistant) = &messages[1] else {
+            panic!("Expected the function calls as one assistant message");
+        };
+        assert_eq!(assistant.tool_calls.as_ref().unwrap().len(), 2);
+        assert!(matches!(
+            &messages[2],
+            ChatCompletionRequestMessage::Tool(tool) if tool.tool_call_id == "call_1"
+        ));
+
+        let chat = request.chat_request(messages).unwrap();
+        assert_eq!(chat.inner.messages.len(), 5);
+        assert!(matches!(
+            chat.inner.messages[0],
+            ChatCompletionRequestMessage::System(_)
+        ));
+        assert_eq!(chat.inner.tools.unwrap()[0].function.name, "get_weather");
+        assert!(matches!(
+            chat.inner.tool_choice,
+            Some(ChatCompletionToolChoiceOption::Named(_))
+        ));
+        assert_eq!(chat.inner.stream, Some(true));
+    }
+
+    #[test]
+    fn test_unsupported_input() {
+        let image = request(serde_json::json!({
+            "model": "test",
+            "input": [{"role": "user", "content": [{"type": "input_image", "image_url": "https://example.com/cat.png"}]}]
+        }));
+        assert!(image.input_messages().is_err());
+
+        let web_search = request(serde_json::json!({
+            "model": "test",
+            "input": "What's new?",
+            "tools": [{"type": "web_search_preview"}]
+        }));
+        assert!(web_search.chat_request(vec![]).is_err());
+
+        let number: Result<NvCreateResponseRequest, _> =
+            serde_json::from_value(serde_json::json!({"model": "test", "input": 42}));
+        assert!(number.is_err());
+    }
+}
@@ -0,0 +1,515 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk, ChatCompletionToolType,
+    CompletionUsage, FinishReason, FunctionCall,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ContentPart, FunctionCallItem, IncompleteDetails, Item, ItemStatus, MessageContent,
+    MessageItem, Response, ResponseError, ResponseStatus, ResponseUsage, Role,
+};
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionStreamResponse;
+use crate::types::Annotated;
+
+/// The events of a streamed response, sent as server-sent events named after their `type`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum ResponseEvent {
+    #[serde(rename = "response.created")]
+    Created { response: Response },
+
+    #[serde(rename = "response.in_progress")]
+    InProgress { response: Response },
+
+    #[serde(rename = "response.completed")]
+    Completed { response: Response },
+
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: Response },
+
+    #[serde(rename = "response.failed")]
+    Failed { response: Response },
+
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded { output_index: usize, item: Item },
+
+    #[serde(rename = "response.output_item.done")]
+    OutputItemDone { output_index: usize, item: Item },
+
+    #[serde(rename = "response.content_part.added")]
+    ContentPartAdded {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: ContentPart,
+    },
+
+    #[serde(rename = "response.content_part.done")]
+    ContentPartDone {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: ContentPart,
+    },
+
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        delta: String,
+    },
+
+    #[serde(rename = "response.output_text.done")]
+    OutputTextDone {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        text: String,
+    },
+}
+
+/// A [`ResponseEvent`] and its position in the stream
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SequencedEvent {
+    pub sequence_number: u64,
+    #[serde(flatten)]
+    pub event: ResponseEvent,
+}
+
+/// The output message, once the engine produced text
+struct OutputMessage {
+    output_index: usize,
+    id: String,
+    text: String,
+}
+
+/// Builds a [`Response`] from a chat completion stream, and the events to stream to the client
+/// along the way. Only the first choice is used.
+pub struct ResponseStreamer {
+    response: Response,
+    sequence_number: u64,
+    message: Option<OutputMessage>,
+    /// Assembled from their streamed chunks, output once the stream ends
+    tool_calls: Vec<ChatCompletionMessageToolCall>,
+    finish_reason: Option<FinishReason>,
+    usage: Option<CompletionUsage>,
+}
+
+impl ResponseStreamer {
+    pub fn new(response: Response) -> Self {
+        ResponseStreamer {
+            response,
+            sequence_number: 0,
+            message: None,
+            tool_calls: Vec::new(),
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    /// The response so far
+    pub fn response(&self) -> &Response {
+        &self.response
+    }
+
+    pub fn into_response(self) -> Response {
+        self.response
+    }
+
+    /// The events announcing the response
+    pub fn start(&mut self) -> Vec<SequencedEvent> {
+        vec![
+            self.event(ResponseEvent::Created {
+                response: self.response.clone(),
+            }),
+            self.event(ResponseEvent::InProgress {
+                response: self.response.clone(),
+            }),
+        ]
+    }
+
+    /// Add a chunk of the chat completion stream
+    pub fn add_chunk(
+        &mut self,
+        annotated: Annotated<NvCreateChatCompletionStreamResponse>,
+    ) -> Vec<SequencedEvent> {
+        if self.response.status.is_final() {
+            return Vec::new();
+        }
+        let chunk = match annotated.ok() {
+            Ok(annotated) => annotated.data,
+            Err(message) => return self.fail(message),
+        };
+        let Some(chunk) = chunk else {
+            return Vec::new();
+        };
+        if let Some(usage) = chunk.inner.usage {
+            self.usage = Some(usage);
+        }
+
+        let mut events = Vec::new();
+        for choice in chunk.inner.choices.into_iter().filter(|c| c.index == 0) {
+            if let Some(delta) = choice.delta.content.filter(|c| !c.is_empty()) {
+                events.extend(self.text_delta(delta));
+            }
+            for tool_call in choice.delta.tool_calls.unwrap_or_default() {
+                self.add_tool_call_chunk(tool_call);
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        events
+    }
+
+    /// The engine failed, the response failed
+    pub fn fail(&mut self, message: String) -> Vec<SequencedEvent> {
+        self.response.status = ResponseStatus::Failed;
+        self.response.error = Some(ResponseError {
+            code: "server_error".to_string(),
+            message,
+        });
+        vec![self.event(ResponseEvent::Failed {
+            response: self.response.clone(),
+        })]
+    }
+
+    /// The chat completion stream ended. Completes the response.
+    pub fn finish(&mut self) -> Vec<SequencedEvent> {
+        if self.response.status.is_final() {
+            return Vec::new();
+        }
+        let incomplete_reason = match self.finish_reason {
+            Some(FinishReason::Length) => Some("max_output_tokens"),
+            Some(FinishReason::ContentFilter) => Some("content_filter"),
+            _ => None,
+        };
+        let item_status = match incomplete_reason {
+            Some(_) => ItemStatus::Incomplete,
+            None => ItemStatus::Completed,
+        };
+
+        let mut events = Vec::new();
+        if let Some(message) = self.message.take() {
+            let part = ContentPart::OutputText {
+                text: message.text.clone(),
+                annotations: Vec::new(),
+            };
+            let item = Item::Message(MessageItem {
+                id: Some(message.id.clone()),
+                role: Role::Assistant,
+                content: MessageContent::Parts(vec![part.clone()]),
+                status: Some(item_status),
+            });
+            self.response.output[message.output_index] = item.clone();
+            events.push(self.event(ResponseEvent::OutputTextDone {
+                item_id: message.id.clone(),
+                output_index: message.output_index,
+                content_index: 0,
+                text: message.text,
+            }));
+            events.push(self.event(ResponseEvent::ContentPartDone {
+                item_id: message.id,
+                output_index: message.output_index,
+                content_index: 0,
+                part,
+            }));
+            events.push(self.event(ResponseEvent::OutputItemDone {
+                output_index: message.output_index,
+                item,
+            }));
+        }
+
+        for tool_call in std::mem::take(&mut self.tool_calls) {
+            let call_id = if tool_call.id.is_empty() {
+                format!("call_{}", uuid::Uuid::new_v4().simple())
+            } else {
+                tool_call.id
+            };
+            let item = Item::FunctionCall(FunctionCallItem {
+                id: Some(format!("fc_{}", uuid::Uuid::new_v4().simple())),
+                call_id,
+                name: tool_call.function.name,
+                arguments: tool_call.function.arguments,
+                status: Some(item_status),
+            });
+            let output_index = self.response.output.len();
+            self.response.output.push(item.clone());
+            events.push(self.event(ResponseEvent::OutputItemAdded {
+                output_index,
+                item: item.clone(),
+            }));
+            events.push(self.event(ResponseEvent::OutputItemDone { output_index, item }));
+        }
+
+        self.response.usage = self.usage.take().map(|usage| ResponseUsage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+            total_tokens: usage.prompt_tokens + usage.completion_tokens,
+        });
+        let response = match incomplete_reason {
+            Some(reason) => {
+                self.response.status = ResponseStatus::Incomplete;
+                self.response.incomplete_details = Some(IncompleteDetails {
+                    reason: reason.to_string(),
+                });
+                ResponseEvent::Incomplete {
+                    response: self.response.clone(),
+                }
+            }
+            None => {
+                self.response.status = ResponseStatus::Completed;
+                ResponseEvent::Completed {
+                    response: self.response.clone(),
+                }
+            }
+        };
+        events.push(self.event(response));
+        events
+    }
+
+    fn text_delta(&mut self, delta: String) -> Vec<SequencedEvent> {
+        let mut events = Vec::new();
+        if self.message.is_none() {
+            let id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+            let output_index = self.response.output.len();
+            let item = Item::Message(MessageItem {
+                id: Some(id.clone()),
+                role: Role::Assistant,
+                content: MessageContent::Parts(Vec::new()),
+                status: Some(ItemStatus::InProgress),
+            });
+            self.response.output.push(item.clone());
+            events.push(self.event(ResponseEvent::OutputItemAdded { output_index, item }));
+            events.push(self.event(ResponseEvent::ContentPartAdded {
+                item_id: id.clone(),
+                output_index,
+                content_index: 0,
+                part: ContentPart::OutputText {
+                    text: String::new(),
+                    annotations: Vec::new(),
+                },
+            }));
+            self.message = Some(OutputMessage {
+                output_index,
+                id,
+                text: String::new(),
+            });
+        }
+        let Some(message) = self.message.as_mut() else {
+            return events;
+        };
+        message.text.push_str(&delta);
+        let item_id = message.id.clone();
+        let output_index = message.output_index;
+        events.push(self.event(ResponseEvent::OutputTextDelta {
+            item_id,
+            output_index,
+            content_index: 0,
+            delta,
+        }));
+        events
+    }
+
+    fn add_tool_call_chunk(&mut self, chunk: ChatCompletionMessageToolCallChunk) {
+        let index = chunk.index as usize;
+        while self.tool_calls.len() <= index {
+            self.tool_calls.push(ChatCompletionMessageToolCall {
+                id: String::new(),
+                r#type: ChatCompletionToolType::Function,
+                function: FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                },
+            });
+        }
+        let call = &mut self.tool_calls[index];
+        if let Some(id) = chunk.id {
+            call.id = id;
+        }
+        if let Some(function) = chunk.function {
+            if let Some(name) = function.name {
+                call.function.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+
+    fn event(&mut self, event: ResponseEvent) -> SequencedEvent {
+        let sequence_number = self.sequence_number;
+        self.sequence_number += 1;
+        SequencedEvent {
+            sequence_number,
+            event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{NvCreateResponseRequest, ResponseInput};
+    use super::*;
+    use async_openai::types::{
+        ChatChoiceStream, ChatCompletionStreamResponseDelta, CreateChatCompletionStreamResponse,
+        FunctionCallStream,
+    };
+
+    fn streamer() -> ResponseStreamer {
+        let request: NvCreateResponseRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "input": "Hi",
+        }))
+        .unwrap();
+        assert_eq!(request.input, ResponseInput::Text("Hi".to_string()));
+        ResponseStreamer::new(Response::new("resp_1".to_string(), &request))
+    }
+
+    #[allow(deprecated)]
+    fn chunk(
+        content: Option<&str>,
+        tool_calls: Option<Vec<ChatCompletionMessageToolCallChunk>>,
+        finish_reason: Option<FinishReason>,
+    ) -> Annotated<NvCreateChatCompletionStreamResponse> {
+        // ALLOW: function_call is deprecated
+        let delta = ChatCompletionStreamResponseDelta {
+            content: content.map(str::to_string),
+            function_call: None,
+            tool_calls,
+            role: None,
+            refusal: None,
+        };
+        let inner = CreateChatCompletionStreamResponse {
+            id: "chat-1".to_string(),
+            choices: vec![ChatChoiceStream {
+                index: 0,
+                delta,
+                finish_reason,
+                logprobs: None,
+            }],
+            created: 0,
+            model: "test".to_string(),
+            service_tier: None,
+            system_fingerprint: None,
+            object: "chat.completion.chunk".to_string(),
+            usage: finish_reason.map(|_| CompletionUsage {
+                prompt_tokens: 5,
+                completion_tokens: 2,
+                total_tokens: 7,
+                prompt_tokens_details: None,
+                completion_tokens_details: None,
+            }),
+        };
+        Annotated::from_data(NvCreateChatCompletionStreamResponse { inner, nvext: None })
+    }
+
+    fn event_types(events: &[SequencedEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|e| {
+                serde_json::to_value(e).unwrap()["type"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_text_response() {
+        let mut streamer = streamer();
+        let mut events = streamer.start();
+        events.extend(streamer.add_chunk(chunk(Some("Hel"), None, None)));
+        events.extend(streamer.add_chunk(chunk(Some("lo"), None, Some(FinishReason::Stop))));
+        events.extend(streamer.finish());
+        assert_eq!(
+            event_types(&events),
+            [
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        let sequence_numbers: Vec<_> = events.iter().map(|e| e.sequence_number).collect();
+        assert_eq!(sequence_numbers, (0..10).collect::<Vec<_>>());
+
+        let response = streamer.into_response();
+        assert_eq!(response.status, ResponseStatus::Completed);
+        let Item::Message(message) = &response.output[0] else {
+            panic!("Expected a message");
+        };
+        assert_eq!(
+            message.content,
+            MessageContent::Parts(vec![ContentPart::OutputText {
+                text: "Hello".to_string(),
+                annotations: vec![],
+            }])
+        );
+        assert_eq!(response.usage.unwrap().total_tokens, 7);
+    }
+
+    #[test]
+    fn test_function_call_response() {
+        let mut streamer = streamer();
+        let tool_call = |id: Option<&str>, name: Option<&str>, arguments: &str| {
+            vec![ChatCompletionMessageToolCallChunk {
+                index: 0,
+                id: id.map(str::to_string),
+                r#type: Some(ChatCompletionToolType::Function),
+                function: Some(FunctionCallStream {
+                    name: name.map(str::to_string),
+                    arguments: Some(arguments.to_string()),
+                }),
+            }]
+        };
+        streamer.add_chunk(chunk(
+            None,
+            Some(tool_call(Some("call_1"), Some("get_weather"), "{\"city\":")),
+            None,
+        ));
+        streamer.add_chunk(chunk(
+            None,
+            Some(tool_call(None, None, "\"Paris\"}")),
+            Some(FinishReason::ToolCalls),
+        ));
+        streamer.finish();
+        let response = streamer.into_response();
+        let Item::FunctionCall(call) = &response.output[0] else {
+            panic!("Expected a function call");
+        };
+        assert_eq!(call.call_id, "call_1");
+        assert_eq!(call.name, "get_weather");
+        assert_eq!(call.arguments, "{\"city\":\"Paris\"}");
+    }
+
+    #[test]
+    fn test_incomplete_and_failed_response() {
+        let mut incomplete = streamer();
+        incomplete.add_chunk(chunk(Some("Hi"), None, Some(FinishReason::Length)));
+        incomplete.finish();
+        let response = incomplete.into_response();
+        assert_eq!(response.status, ResponseStatus::Incomplete);
+        assert_eq!(
+            response.incomplete_details.unwrap().reason,
+            "max_output_tokens"
+        );
+
+        let mut failed = streamer();
+        let events = failed.add_chunk(Annotated::from_error("engine died".to_string()));
+        assert_eq!(event_types(&events), ["response.failed"]);
+        assert!(failed.finish().is_empty());
+        assert_eq!(failed.response().status, ResponseStatus::Failed);
+    }
+}
@@ -0,0 +1,345 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A small in-memory record of the requests a worker handled most recently.
+//!
+//! When an engine crashes the first question is "what was it doing?". Full audit logging is too
+//! expensive to leave on, so instead the worker keeps summaries of the last few requests in a
+//! ring buffer and writes them to disk when it panics or is asked to shut down.
+//!
+//! Prompts are user data. What we keep of them is controlled by a [`RedactionPolicy`].
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_stream::stream;
+use async_trait::async_trait;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::backend::ExecutionContext;
+use crate::preprocessor::PreprocessedRequest;
+use crate::protocols::common::llm_backend::LLMEngineOutput;
+use crate::protocols::TokenIdType;
+
+/// How many prompt tokens [`RedactionPolicy::Tokens`] keeps
+const MAX_PROMPT_TOKENS_KEPT: usize = 64;
+
+/// What to keep of each prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedactionPolicy {
+    /// Only the number of tokens
+    CountOnly,
+
+    /// A hash of the prompt tokens. Enough to tell whether two crashes had the same prompt.
+    #[default]
+    Hash,
+
+    /// The first few prompt token ids. Only for debugging non-sensitive traffic.
+    Tokens,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestStatus {
+    InFlight,
+    Finished(String),
+    Error(String),
+    /// The caller went away before the engine finished
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestSummary {
+    pub request_id: String,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub duration_ms: Option<u64>,
+    pub prompt_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_token_ids: Option<Vec<TokenIdType>>,
+    pub max_tokens: Option<u32>,
+    pub output_tokens: usize,
+    pub status: RequestStatus,
+}
+
+/// Ring buffer of the most recent [`RequestSummary`]s
+pub struct RequestLog {
+    capacity: usize,
+    policy: RedactionPolicy,
+    entries: Mutex<VecDeque<RequestSummary>>,
+}
+
+impl fmt::Debug for RequestLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestLog")
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl RequestLog {
+    pub fn new(capacity: usize, policy: RedactionPolicy) -> Arc<Self> {
+        Arc::new(RequestLog {
+            capacity,
+            policy,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        })
+    }
+
+    fn start(&self, request_id: &str, request: &PreprocessedRequest) {
+        if self.capacity == 0 {
+            return;
+        }
+        let token_ids = &request.token_ids;
+        let summary = RequestSummary {
+            request_id: request_id.to_string(),
+            started_at: chrono::Utc::now(),
+            duration_ms: None,
+            prompt_tokens: token_ids.len(),
+            prompt_hash: (self.policy == RedactionPolicy::Hash).then(|| hash_tokens(token_ids)),
+            prompt_token_ids: (self.policy == RedactionPolicy::Tokens).then(|| {
+                token_ids
+                    .iter()
+                    .take(MAX_PROMPT_TOKENS_KEPT)
+                    .copied()
+                    .collect()
+            }),
+            max_tokens: request.stop_conditions.max_tokens,
+            output_tokens: 0,
+            status: RequestStatus::InFlight,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(summary);
+    }
+
+    fn finish(
+        &self,
+        request_id: &str,
+        elapsed_ms: u64,
+        output_tokens: usize,
+        status: RequestStatus,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        // Requests finish roughly in order, so search from the newest
+        if let Some(entry) = entries
+            .iter_mut()
+            .rev()
+            .find(|e| e.request_id == request_id)
+        {
+            entry.duration_ms = Some(elapsed_ms);
+            entry.output_tokens = output_tokens;
+            entry.status = status;
+        }
+    }
+
+    /// Copy of the current entries, oldest first
+    pub fn snapshot(&self) -> Vec<RequestSummary> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Write the entries as JSON lines to a new file in `dir`. Returns the file's path.
+    pub fn dump(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let entries = self.snapshot();
+        write_dump(dir, &entries)
+    }
+
+    /// Dump the log to `dir` if the process panics, in addition to the existing panic hook.
+    pub fn dump_on_panic(self: &Arc<Self>, dir: PathBuf) {
+        let log = Arc::clone(self);
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // The panic may have happened while the lock was held, don't wait for it
+            if let Ok(entries) = log.entries.try_lock() {
+                let entries: Vec<RequestSummary> = entries.iter().cloned().collect();
+                match write_dump(&dir, &entries) {
+                    Ok(path) => eprintln!("Recent requests written to {}", path.display()),
+                    Err(err) => eprintln!("Failed writing recent requests: {err:#}"),
+                }
+            }
+            previous(info);
+        }));
+    }
+}
+
+fn hash_tokens(token_ids: &[TokenIdType]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for t in token_ids {
+        hasher.update(&t.to_le_bytes());
+    }
+    hasher.finalize().to_hex()[..16].to_string()
+}
+
+fn write_dump(dir: &Path, entries: &[RequestSummary]) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "dynamo-requests-{}-{}.jsonl",
+        std::process::id(),
+        chrono::Utc::now().format("%Y%m%dT%H%M%S")
+    ));
+    let mut f = std::fs::File::create(&path)?;
+    for entry in entries {
+        serde_json::to_writer(&mut f, entry)?;
+        f.write_all(b"\n")?;
+    }
+    Ok(path)
+}
+
+/// Wraps an engine and records each request it handles in a [`RequestLog`]
+pub struct RequestLogEngine {
+    inner: ExecutionContext,
+    log: Arc<RequestLog>,
+}
+
+impl RequestLogEngine {
+    pub fn new(inner: ExecutionContext, log: Arc<RequestLog>) -> ExecutionContext {
+        Arc::new(RequestLogEngine { inner, log })
+    }
+}
+
+/// Records the outcome when the response stream ends or is dropped
+struct Tracker {
+    log: Arc<RequestLog>,
+    request_id: String,
+    start: Instant,
+    output_tokens: usize,
+    status: Option<RequestStatus>,
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        let status = self.status.take().unwrap_or(RequestStatus::Cancelled);
+        self.log.finish(
+            &self.request_id,
+            self.start.elapsed().as_millis() as u64,
+            self.output_tokens,
+            status,
+        );
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for RequestLogEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let request_id = request.id().to_string();
+        self.log.start(&request_id, &request);
+        let mut tracker = Tracker {
+            log: self.log.clone(),
+            request_id,
+            start: Instant::now(),
+            output_tokens: 0,
+            status: None,
+        };
+
+        let mut response = match self.inner.generate(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                tracker.status = Some(RequestStatus::Error(format!("{err:#}")));
+                return Err(err);
+            }
+        };
+        let ctx = response.context();
+
+        let output = stream! {
+            while let Some(item) = response.next().await {
+                if let Some(data) = item.data.as_ref() {
+                    tracker.output_tokens += data.token_ids.len();
+                    if let Some(reason) = data.finish_reason.as_ref() {
+                        tracker.status = Some(RequestStatus::Finished(reason.to_string()));
+                    }
+                }
+                if item.is_error() {
+                    let msg = item.comment.as_ref().map(|c| c.join(", ")).unwrap_or_default();
+                    tracker.status = Some(RequestStatus::Error(msg));
+                }
+                yield item;
+            }
+            if tracker.status.is_none() && !response.context().is_stopped() {
+                tracker.status = Some(RequestStatus::Finished("end of stream".to_string()));
+            }
+        };
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::common::{SamplingOptions, StopConditions};
+
+    fn request(token_ids: Vec<TokenIdType>) -> PreprocessedRequest {
+        PreprocessedRequest::builder()
+            .token_ids(token_ids)
+            .stop_conditions(StopConditions {
+                max_tokens: Some(16),
+                ..Default::default()
+            })
+            .sampling_options(SamplingOptions::default())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let log = RequestLog::new(2, RedactionPolicy::CountOnly);
+        for id in ["a", "b", "c"] {
+            log.start(id, &request(vec![1, 2, 3]));
+        }
+        log.finish("c", 5, 7, RequestStatus::Finished("stop".to_string()));
+
+        let entries = log.snapshot();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].request_id, "b");
+        assert_eq!(entries[0].status, RequestStatus::InFlight);
+        assert_eq!(entries[1].output_tokens, 7);
+        assert_eq!(entries[1].prompt_tokens, 3);
+        assert!(entries[1].prompt_hash.is_none());
+        assert!(entries[1].prompt_token_ids.is_none());
+    }
+
+    #[test]
+    fn test_redaction() {
+        let hashed = RequestLog::new(4, RedactionPolicy::Hash);
+        hashed.start("a", &request(vec![1, 2, 3]));
+        hashed.start("b", &request(vec![1, 2, 3]));
+        let entries = hashed.snapshot();
+        assert!(entries[0].prompt_token_ids.is_none());
+        assert_eq!(entries[0].prompt_hash, entries[1].prompt_hash);
+
+        let tokens = RequestLog::new(4, RedactionPolicy::Tokens);
+        tokens.start("a", &request((0..100).collect()));
+        let entries = tokens.snapshot();
+        assert_eq!(
+            entries[0].prompt_token_ids.as_ref().map(|t| t.len()),
+            Some(MAX_PROMPT_TOKENS_KEPT)
+        );
+    }
+
+    #[test]
+    fn test_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RequestLog::new(4, RedactionPolicy::Hash);
+        log.start("a", &request(vec![1]));
+        let path = log.dump(dir.path()).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("\"request_id\":\"a\""));
+    }
+}
@@ -14,6 +14,8 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RequestTemplate {
     pub model: String,
@@ -27,4 +29,18 @@ impl RequestTemplate {
         let template: Self = serde_json::from_str(&template)?;
         Ok(template)
     }
+
+    /// Fill in the fields a chat completion request left unset or zero. Every frontend calls it,
+    /// so that they serve a request the same way.
+    pub fn apply(&self, request: &mut NvCreateChatCompletionRequest) {
+        if request.inner.model.is_empty() {
+            request.inner.model = self.model.clone();
+        }
+        if request.inner.temperature.unwrap_or(0.0) == 0.0 {
+            request.inner.temperature = Some(self.temperature);
+        }
+        if request.inner.max_completion_tokens.unwrap_or(0) == 0 {
+            request.inner.max_completion_tokens = Some(self.max_completion_tokens);
+        }
+    }
 }
@@ -0,0 +1,269 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A worker whose engine is stuck accepts requests and never answers them. The
+//! [`ReschedulingRouter`] protects against that without hedging every request: if the worker it
+//! picked doesn't send anything within the first token deadline, the request is cancelled there
+//! and sent to a worker it didn't try yet, up to `max_attempts` times.
+//!
+//! The first attempt goes where the router mode places it: round robin, least loaded, pull or the
+//! best KV match. The retries skip the workers already tried, picking among the rest like
+//! [`PushRouter::select_excluding`]. A worker that missed the deadline counts as a failure for its
+//! circuit breaker in KV mode, and the retries are tracked by the breakers like the first
+//! attempt, skipping the workers whose breaker is open.
+//!
+//! Once the first response arrived the request stays where it is, the worker started generating.
+//! Requests pinned to a worker with `nvext.routing.backend_instance_id` are never moved.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use dynamo_runtime::{
+    component::InstanceSource,
+    pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContext, AsyncEngineContextProvider, Context, Error,
+        ManyOut, PushRouter, ResponseStream, SingleIn,
+    },
+    protocols::annotated::Annotated,
+};
+use futures::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    kv_router::{with_worker_annotation, KvPushRouter, KvRouter, ANNOTATION_WORKER_INSTANCE_ID},
+    lora::LoraRegistry,
+    preprocessor::PreprocessedRequest,
+    protocols::common::llm_backend::LLMEngineOutput,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RescheduleConfig {
+    /// How long a worker has to send the first response
+    pub first_token_timeout: Duration,
+
+    /// How many workers to try, including the first one
+    pub max_attempts: u32,
+}
+
+impl Default for RescheduleConfig {
+    fn default() -> Self {
+        RescheduleConfig {
+            first_token_timeout: Duration::from_secs(30),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Routes requests to the backend workers like [`PushRouter`], or like
+/// [`KvPushRouter`](crate::kv_router::KvPushRouter) when given a KV chooser, and reschedules those
+/// that miss the first token deadline. See the [module docs](self).
+pub struct ReschedulingRouter {
+    inner: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+    kv: Option<KvPushRouter>,
+    config: RescheduleConfig,
+}
+
+impl ReschedulingRouter {
+    pub fn new(
+        inner: PushRouter<PreprocessedRequest, Annotated<LLMEngineOutput>>,
+        chooser: Option<Arc<KvRouter>>,
+        config: RescheduleConfig,
+    ) -> Self {
+        let kv = chooser.map(|chooser| KvPushRouter::new(inner.clone(), chooser));
+        ReschedulingRouter { inner, kv, config }
+    }
+
+    /// In KV mode, send the requests for a LoRA adapter only to the workers that loaded it. In
+    /// the other modes wrap the router in a [`LoraRouter`](crate::lora::LoraRouter).
+    pub fn with_loras(mut self, loras: Arc<LoraRegistry>) -> Self {
+        self.kv = self.kv.map(|kv| kv.with_loras(loras));
+        self
+    }
+
+    /// Send one attempt at `request`. The first goes where the router mode places it, the next
+    /// ones to a worker not `tried` yet. Returns the worker, None when pull mode let the workers
+    /// take it.
+    async fn dispatch(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+        tried: &[i64],
+        first: bool,
+    ) -> anyhow::Result<(Option<i64>, ManyOut<Annotated<LLMEngineOutput>>)> {
+        if first {
+            return match &self.kv {
+                Some(kv) => {
+                    let (instance_id, responses) = kv.route(request).await?;
+                    Ok((Some(instance_id), responses))
+                }
+                None => self.inner.route(request).await,
+            };
+        }
+        // The best match is the worker we already tried, retries only look at the load
+        let (mut backend_input, context) = request.into_parts();
+        backend_input.estimated_prefix_hit_num_blocks = None;
+        let request = context.map(|_| backend_input);
+        let Some(kv) = &self.kv else {
+            let instance_id = self.inner.select_excluding(tried)?;
+            let responses = self.inner.direct(request, instance_id).await?;
+            return Ok((Some(instance_id), responses));
+        };
+        // Only the workers that loaded the adapter of the request can serve it
+        let mut exclude = kv.lacking_lora(&request);
+        exclude.extend_from_slice(tried);
+        // Keep away from the workers with an open breaker too, unless that leaves none
+        let mut healthy = kv.unhealthy();
+        healthy.extend_from_slice(&exclude);
+        let instance_id = match self.inner.select_excluding(&healthy) {
+            Ok(instance_id) => instance_id,
+            Err(_) => self.inner.select_excluding(&exclude)?,
+        };
+        Ok((Some(instance_id), kv.send(request, instance_id).await?))
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for ReschedulingRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        if let InstanceSource::Static = self.inner.client.instance_source.as_ref() {
+            return self.inner.r#static(request).await;
+        }
+        let annotate = request.has_annotation(ANNOTATION_WORKER_INSTANCE_ID);
+        if let Some(instance_id) = request.backend_instance_id() {
+            // The client pinned the request to a worker
+            if let Some(kv) = &self.kv {
+                return kv.generate(request).await;
+            }
+            let responses = self.inner.direct(request, instance_id).await?;
+            return Ok(with_worker_annotation(annotate, instance_id, responses));
+        }
+
+        let request_ctx = request.context();
+        let (backend_input, _) = request.into_parts();
+        let max_attempts = self.config.max_attempts.max(1);
+        let mut tried = Vec::new();
+        for attempt in 1..=max_attempts {
+            // Each attempt gets its own controller, so that a stuck worker can be cancelled
+            // without cancelling the request. Stop and kill from the caller are forwarded.
+            let attempt_request =
+                Context::with_id(backend_input.clone(), request_ctx.id().to_string());
+            let attempt_ctx = attempt_request.context();
+            let forwarding = CancellationToken::new();
+            tokio::spawn(forward_cancellation(
+                request_ctx.clone(),
+                attempt_ctx.clone(),
+                forwarding.clone(),
+            ));
+            let forwarding = forwarding.drop_guard();
+
+            let (instance_id, mut responses) =
+                self.dispatch(attempt_request, &tried, attempt == 1).await?;
+            tried.extend(instance_id);
+            match tokio::time::timeout(self.config.first_token_timeout, responses.next()).await {
+                Ok(first) => {
+                    let stream = async_stream::stream! {
+                        let _forwarding = forwarding;
+                        if let Some(first) = first {
+                            yield first;
+                            while let Some(response) = responses.next().await {
+                                yield response;
+                            }
+                        }
+                    };
+                    let responses = ResponseStream::new(Box::pin(stream), request_ctx);
+                    return Ok(match instance_id {
+                        Some(instance_id) => {
+                            with_worker_annotation(annotate, instance_id, responses)
+                        }
+                        None => responses,
+                    });
+                }
+                Err(_) if request_ctx.is_stopped() => {
+                    // The caller gave up, no point in trying elsewhere
+                    attempt_ctx.kill();
+                    return Ok(ResponseStream::new(
+                        Box::pin(futures::stream::empty()),
+                        request_ctx,
+                    ));
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        request_id = request_ctx.id(),
+                        instance_id,
+                        attempt,
+                        "No first token within {:?}, rescheduling",
+                        self.config.first_token_timeout
+                    );
+                    attempt_ctx.kill();
+                    // A killed request is neither a success nor a failure to the breaker
+                    if let (Some(kv), Some(instance_id)) = (&self.kv, instance_id) {
+                        kv.record_failure(instance_id);
+                    }
+                }
+            }
+        }
+        anyhow::bail!(
+            "No first token within {:?} from any of the {} attempts",
+            self.config.first_token_timeout,
+            max_attempts
+        );
+    }
+}
+
+/// Pass stop and kill from the request on to one attempt at serving it, until `done`
+pub(crate) async fn forward_cancellation(
+    from: Arc<dyn AsyncEngineContext>,
+    to: Arc<dyn AsyncEngineContext>,
+    done: CancellationToken,
+) {
+    tokio::select! {
+        _ = done.cancelled() => return,
+        _ = from.stopped() => to.stop_generating(),
+    }
+    tokio::select! {
+        _ = done.cancelled() => {}
+        _ = from.killed() => {
+            if from.is_killed() {
+                to.kill();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::pipeline::context::Controller;
+
+    #[tokio::test]
+    async fn test_forward_cancellation() {
+        let from: Arc<dyn AsyncEngineContext> = Arc::new(Controller::default());
+        let to: Arc<dyn AsyncEngineContext> = Arc::new(Controller::default());
+        let done = CancellationToken::new();
+        let forwarding = tokio::spawn(forward_cancellation(from.clone(), to.clone(), done.clone()));
+
+        from.stop_generating();
+        to.stopped().await;
+        assert!(!to.is_killed());
+        from.kill();
+        forwarding.await.unwrap();
+        assert!(to.is_killed());
+    }
+
+    #[tokio::test]
+    async fn test_forward_cancellation_done() {
+        let from: Arc<dyn AsyncEngineContext> = Arc::new(Controller::default());
+        let to: Arc<dyn AsyncEngineContext> = Arc::new(Controller::default());
+        let done = CancellationToken::new();
+        let forwarding = tokio::spawn(forward_cancellation(from.clone(), to.clone(), done.clone()));
+
+        done.cancel();
+        forwarding.await.unwrap();
+        from.kill();
+        assert!(!to.is_stopped());
+    }
+}
@@ -0,0 +1,507 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tee full responses to a file per request, for long offline generations.
+//!
+//! The batch runner and workers (`in=dyn://...`) write the generated text of each request to the
+//! path of the [`ResponseTeeConfig`] pattern, with `{request_id}` and `{model}` replaced. A local
+//! path is written as the text arrives, so nothing generated is lost if the caller goes away. An
+//! `http://` or `https://` URL, such as an object store bucket, gets the whole response with a
+//! `PUT` once it is complete.
+//!
+//! Each request has a bounded queue to its writer. When the disk or the upload can't keep up,
+//! generating that response waits instead of buffering without bound. A response that fails to
+//! be written still goes to the caller.
+//!
+//! When a response is done a [`ManifestEntry`] is appended to the manifest, JSON lines listing
+//! where each response went and how it ended.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, Data, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, ServiceEngine, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex, Notify};
+
+use crate::audit::{AuditResponse, AuditedResponse};
+
+pub const PLACEHOLDER_REQUEST_ID: &str = "{request_id}";
+pub const PLACEHOLDER_MODEL: &str = "{model}";
+
+/// Text chunks of one response waiting to be written. Generating waits when it is full.
+const CHUNK_QUEUE_SIZE: usize = 64;
+
+/// How long uploading one response may take
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTeeConfig {
+    /// Where each response goes, a local path or an `http(s)://` URL. Must contain
+    /// `{request_id}`, may contain `{model}`.
+    pub pattern: String,
+
+    /// JSON lines file with a [`ManifestEntry`] per response. None writes no manifest.
+    pub manifest: Option<PathBuf>,
+
+    /// Sent as `Authorization: Bearer <token>` with uploads
+    pub upload_token: Option<String>,
+}
+
+impl ResponseTeeConfig {
+    /// For a local `pattern` the manifest is `manifest.jsonl` in the pattern's directory, up to
+    /// the first placeholder. For a URL there is none.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        let manifest = (!is_url(&pattern)).then(|| {
+            let fixed = &pattern[..pattern.find('{').unwrap_or(pattern.len())];
+            let dir = fixed.rfind('/').map_or("", |i| &fixed[..=i]);
+            Path::new(dir).join("manifest.jsonl")
+        });
+        ResponseTeeConfig {
+            pattern,
+            manifest,
+            upload_token: None,
+        }
+    }
+}
+
+fn is_url(pattern: &str) -> bool {
+    pattern.starts_with("http://") || pattern.starts_with("https://")
+}
+
+/// Only keep characters that are safe in a file name or URL path segment, so that a request id
+/// sent by a client can't write elsewhere
+fn sanitize(value: &str) -> String {
+    let safe: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if safe.is_empty() || safe.chars().all(|c| c == '.') {
+        safe.replace('.', "_") + "_"
+    } else {
+        safe
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeeStatus {
+    Success,
+    /// The engine failed, the response is what was generated until then
+    Error,
+    /// The caller went away before the response was complete
+    Cancelled,
+    /// The response could not be written
+    WriteFailed,
+}
+
+/// A response in the manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub request_id: String,
+    pub model: String,
+
+    /// The path or URL the response was written to
+    pub location: String,
+
+    pub status: TeeStatus,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+
+    /// Bytes of text written
+    pub bytes: u64,
+
+    /// Generated tokens, only known on workers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>,
+
+    pub started: chrono::DateTime<chrono::Utc>,
+    pub elapsed_ms: u64,
+}
+
+enum Chunk {
+    Response(AuditedResponse),
+    Error(String),
+    /// The stream ended. A stream dropped after its finish reason is complete too.
+    End,
+}
+
+/// How a response ended
+#[derive(Debug, Default)]
+struct Outcome {
+    error: Option<String>,
+    finish_reason: Option<String>,
+    tokens: usize,
+    ended: bool,
+}
+
+impl Outcome {
+    fn status(&self) -> TeeStatus {
+        if self.error.is_some() {
+            TeeStatus::Error
+        } else if self.ended || self.finish_reason.is_some() {
+            TeeStatus::Success
+        } else {
+            TeeStatus::Cancelled
+        }
+    }
+}
+
+/// The next text to write, None once the response is done
+async fn next_text(rx: &mut mpsc::Receiver<Chunk>, outcome: &mut Outcome) -> Option<String> {
+    while let Some(chunk) = rx.recv().await {
+        match chunk {
+            Chunk::Response(response) => {
+                outcome.tokens += response.token_ids.len();
+                if response.finish_reason.is_some() {
+                    outcome.finish_reason = response.finish_reason;
+                }
+                if !response.text.is_empty() {
+                    return Some(response.text);
+                }
+            }
+            Chunk::Error(err) => outcome.error = Some(err),
+            Chunk::End => {
+                outcome.ended = true;
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Writes responses according to a [`ResponseTeeConfig`]
+pub struct ResponseTee {
+    pattern: String,
+    upload_token: Option<String>,
+    client: reqwest::Client,
+    manifest: Option<Mutex<tokio::fs::File>>,
+
+    /// Responses still being written
+    pending: AtomicUsize,
+    written: Notify,
+}
+
+impl ResponseTee {
+    /// Opens the manifest. Responses are written on the Tokio runtime that tracks them.
+    pub fn new(config: ResponseTeeConfig) -> anyhow::Result<Arc<Self>> {
+        if !config.pattern.contains(PLACEHOLDER_REQUEST_ID) {
+            anyhow::bail!(
+                "Response tee pattern {} must contain {PLACEHOLDER_REQUEST_ID}",
+                config.pattern
+            );
+        }
+        let manifest = match &config.manifest {
+            Some(path) => Some(Mutex::new(open_manifest(path)?)),
+            None => None,
+        };
+        Ok(Arc::new(ResponseTee {
+            pattern: config.pattern,
+            upload_token: config.upload_token,
+            client: reqwest::Client::builder().timeout(UPLOAD_TIMEOUT).build()?,
+            manifest,
+            pending: AtomicUsize::new(0),
+            written: Notify::new(),
+        }))
+    }
+
+    /// Wait until the responses tracked so far are written and in the manifest
+    pub async fn finish(&self) {
+        loop {
+            let written = self.written.notified();
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            written.await;
+        }
+    }
+
+    /// Where the response to `request_id` goes
+    pub fn location(&self, request_id: &str, model: &str) -> String {
+        self.pattern
+            .replace(PLACEHOLDER_REQUEST_ID, &sanitize(request_id))
+            .replace(PLACEHOLDER_MODEL, &sanitize(model))
+    }
+
+    /// Tee the text of the responses in `stream`
+    pub fn track<T: AuditResponse + Data>(
+        self: &Arc<Self>,
+        request_id: &str,
+        model: &str,
+        stream: ManyOut<Annotated<T>>,
+    ) -> ManyOut<Annotated<T>> {
+        let entry = ManifestEntry {
+            request_id: request_id.to_string(),
+            model: model.to_string(),
+            location: self.location(request_id, model),
+            status: TeeStatus::Success,
+            error: None,
+            finish_reason: None,
+            bytes: 0,
+            tokens: None,
+            started: chrono::Utc::now(),
+            elapsed_ms: 0,
+        };
+        let (tx, rx) = mpsc::channel(CHUNK_QUEUE_SIZE);
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(self.clone().write(entry, rx));
+
+        let ctx = stream.context();
+        let teed = async_stream::stream! {
+            let mut stream = stream;
+            // None once the writer gave up
+            let mut tx = Some(tx);
+            while let Some(annotated) = stream.next().await {
+                let mut chunk = None;
+                if let Some(data) = &annotated.data {
+                    let mut response = AuditedResponse::default();
+                    data.audit(&mut response);
+                    chunk = Some(Chunk::Response(response));
+                }
+                if annotated.is_error() {
+                    let err = annotated.comment.as_ref().map(|c| c.join(", ")).unwrap_or_default();
+                    chunk = Some(Chunk::Error(err));
+                }
+                if let (Some(sender), Some(chunk)) = (tx.as_ref(), chunk) {
+                    if sender.send(chunk).await.is_err() {
+                        tx = None;
+                    }
+                }
+                yield annotated;
+            }
+            if let Some(tx) = tx {
+                let _ = tx.send(Chunk::End).await;
+            }
+        };
+        ResponseStream::new(Box::pin(teed), ctx)
+    }
+
+    /// Write one response, then its manifest entry
+    async fn write(self: Arc<Self>, mut entry: ManifestEntry, mut rx: mpsc::Receiver<Chunk>) {
+        let start = Instant::now();
+        let mut outcome = Outcome::default();
+        let written = if is_url(&entry.location) {
+            self.upload(&entry.location, &mut rx, &mut outcome).await
+        } else {
+            write_file(Path::new(&entry.location), &mut rx, &mut outcome).await
+        };
+        match written {
+            Ok(bytes) => {
+                entry.bytes = bytes;
+                entry.status = outcome.status();
+                entry.error = outcome.error;
+                entry.finish_reason = outcome.finish_reason;
+                entry.tokens = (outcome.tokens > 0).then_some(outcome.tokens);
+            }
+            Err(err) => {
+                tracing::warn!(
+                    request_id = entry.request_id,
+                    location = entry.location,
+                    "Failed writing response: {err:#}"
+                );
+                entry.status = TeeStatus::WriteFailed;
+                entry.error = Some(format!("{err:#}"));
+            }
+        }
+        entry.elapsed_ms = start.elapsed().as_millis() as u64;
+        if let Some(manifest) = &self.manifest {
+            if let Err(err) = append_entry(&mut *manifest.lock().await, &entry).await {
+                tracing::error!(
+                    request_id = entry.request_id,
+                    "Failed writing manifest entry: {err:#}"
+                );
+            }
+        }
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.written.notify_waiters();
+        }
+    }
+
+    /// Collect the response and `PUT` it to `url`. What was generated is uploaded also if the
+    /// caller went away.
+    async fn upload(
+        &self,
+        url: &str,
+        rx: &mut mpsc::Receiver<Chunk>,
+        outcome: &mut Outcome,
+    ) -> anyhow::Result<u64> {
+        let mut text = String::new();
+        while let Some(chunk) = next_text(rx, outcome).await {
+            text.push_str(&chunk);
+        }
+        let bytes = text.len() as u64;
+        let mut request = self
+            .client
+            .put(url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(text);
+        if let Some(token) = &self.upload_token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(bytes)
+    }
+}
+
+/// Write the text to `path` as it arrives
+async fn write_file(
+    path: &Path,
+    rx: &mut mpsc::Receiver<Chunk>,
+    outcome: &mut Outcome,
+) -> anyhow::Result<u64> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(path).await?);
+    let mut bytes = 0;
+    while let Some(text) = next_text(rx, outcome).await {
+        file.write_all(text.as_bytes()).await?;
+        bytes += text.len() as u64;
+    }
+    file.flush().await?;
+    Ok(bytes)
+}
+
+/// Open the manifest for appending
+fn open_manifest(path: &Path) -> anyhow::Result<tokio::fs::File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| anyhow::anyhow!("Failed opening manifest {}: {err}", path.display()))?;
+    Ok(tokio::fs::File::from_std(file))
+}
+
+async fn append_entry(file: &mut tokio::fs::File, entry: &ManifestEntry) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    file.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// Wraps a worker's engine and tees the responses it generates
+pub struct ResponseTeeEngine<Req, Resp> {
+    inner: ServiceEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>>,
+    tee: Arc<ResponseTee>,
+    model: String,
+}
+
+impl<Req, Resp> ResponseTeeEngine<Req, Resp>
+where
+    Req: Data,
+    Resp: Data + AuditResponse,
+{
+    /// `model` fills the `{model}` placeholder
+    pub fn new(
+        inner: ServiceEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>>,
+        tee: Arc<ResponseTee>,
+        model: String,
+    ) -> ServiceEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>> {
+        Arc::new(ResponseTeeEngine { inner, tee, model })
+    }
+}
+
+#[async_trait]
+impl<Req, Resp> AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error>
+    for ResponseTeeEngine<Req, Resp>
+where
+    Req: Data,
+    Resp: Data + AuditResponse,
+{
+    async fn generate(&self, request: SingleIn<Req>) -> Result<ManyOut<Annotated<Resp>>, Error> {
+        let request_id = request.id().to_string();
+        let stream = self.inner.generate(request).await?;
+        Ok(self.tee.track(&request_id, &self.model, stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::openai::completions::CompletionResponse;
+    use dynamo_runtime::pipeline::context::Controller;
+
+    fn chunk(text: &str, finish_reason: Option<&str>) -> Annotated<CompletionResponse> {
+        let response = serde_json::from_value(serde_json::json!({
+            "id": "cmpl-1",
+            "choices": [{"text": text, "index": 0, "finish_reason": finish_reason}],
+            "created": 0,
+            "model": "llama",
+            "object": "text_completion",
+        }))
+        .unwrap();
+        Annotated::from_data(response)
+    }
+
+    #[test]
+    fn test_location() {
+        let config = ResponseTeeConfig::new("/data/out/{model}/{request_id}.txt");
+        assert_eq!(
+            config.manifest,
+            Some(PathBuf::from("/data/out/manifest.jsonl"))
+        );
+        assert_eq!(
+            ResponseTeeConfig::new("https://bucket/{request_id}").manifest,
+            None
+        );
+        assert!(ResponseTee::new(ResponseTeeConfig::new("/data/out.txt")).is_err());
+
+        let tee = ResponseTee::new(ResponseTeeConfig {
+            manifest: None,
+            ..config
+        })
+        .unwrap();
+        assert_eq!(
+            tee.location("../../etc/passwd", "Qwen/Qwen3-0.6B"),
+            "/data/out/Qwen_Qwen3-0.6B/.._.._etc_passwd.txt"
+        );
+        assert_eq!(tee.location("..", "m"), "/data/out/m/___.txt");
+    }
+
+    #[tokio::test]
+    async fn test_tee_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let pattern = format!("{}/{{model}}/{{request_id}}.txt", dir.path().display());
+        let tee = ResponseTee::new(ResponseTeeConfig::new(pattern)).unwrap();
+
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(vec![
+                chunk("Once upon", None),
+                chunk(" a time", Some("stop")),
+            ])),
+            Arc::new(Controller::default()),
+        );
+        let responses: Vec<_> = tee.track("req-1", "llama", stream).collect().await;
+        assert_eq!(responses.len(), 2);
+
+        tee.finish().await;
+        let manifest = std::fs::read_to_string(dir.path().join("manifest.jsonl")).unwrap();
+        let entry: ManifestEntry = serde_json::from_str(manifest.lines().next().unwrap()).unwrap();
+        assert_eq!(entry.status, TeeStatus::Success);
+        assert_eq!(entry.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(entry.bytes, 16);
+        let text = std::fs::read_to_string(&entry.location).unwrap();
+        assert_eq!(text, "Once upon a time");
+        assert!(entry.location.ends_with("llama/req-1.txt"));
+    }
+}
@@ -0,0 +1,555 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Speculative decoding across workers: a small draft model guesses the next tokens of the bigger
+//! target model, which checks them all in one forward pass.
+//!
+//! Draft workers register the target's model as [`ModelType::Draft`](crate::model_type::ModelType)
+//! on their own component, next to the target workers that register it as `Backend`. While the
+//! model has draft workers, the [`SpeculativeRouter`] generates in rounds. A draft worker
+//! continues the prompt, and what was generated so far, by `num_speculative_tokens` tokens. A
+//! target worker then gets the request with those as
+//! [`draft_token_ids`](PreprocessedRequest::draft_token_ids), to generate up to one token more
+//! than there are drafts. An engine that checks the drafts answers with those it generates too
+//! and one token of its own, so each round generates between 1 and `num_speculative_tokens + 1`
+//! tokens. An engine that ignores them generates up to as many of its own. Either way the output
+//! is the target model's: a draft token only stays if the target generated it as well.
+//!
+//! The rounds of a request are pinned to the workers of its first round when the router says
+//! which they were, as the KV, budget and rescheduling routers do, so that a worker that keeps
+//! the KV cache of a request between rounds, like llamacpp, only decodes what is new. A request
+//! whose draft worker fails goes on without drafts.
+
+use std::sync::{Arc, LazyLock, RwLock};
+
+use dynamo_runtime::{
+    pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContext, AsyncEngineContextProvider, Context, Error,
+        ManyOut, ResponseStream, SingleIn,
+    },
+    protocols::annotated::Annotated,
+};
+use futures::{Stream, StreamExt};
+use prometheus::{IntCounterVec, Opts};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::{
+    backend::ExecutionContext,
+    kv_router::ANNOTATION_WORKER_INSTANCE_ID,
+    preprocessor::PreprocessedRequest,
+    protocols::{
+        common::{llm_backend::LLMEngineOutput, FinishReason},
+        TokenIdType,
+    },
+    reschedule::forward_cancellation,
+};
+
+static DRAFT_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_speculative_draft_tokens_total",
+            "Tokens guessed by the draft workers of speculative decoding, by model",
+        ),
+        &["model"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static ACCEPTED_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_speculative_accepted_tokens_total",
+            "Draft tokens the target workers generated too, by model",
+        ),
+        &["model"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the speculative decoding metrics to `registry`
+pub fn register_metrics(registry: &prometheus::Registry) -> prometheus::Result<()> {
+    registry.register(Box::new(DRAFT_TOKENS.clone()))?;
+    registry.register(Box::new(ACCEPTED_TOKENS.clone()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeculativeConfig {
+    /// How many tokens the draft worker guesses each round. 0 turns speculative decoding off.
+    pub num_speculative_tokens: u32,
+}
+
+impl Default for SpeculativeConfig {
+    fn default() -> Self {
+        SpeculativeConfig {
+            num_speculative_tokens: 4,
+        }
+    }
+}
+
+/// The engine sending requests to the draft workers of a model, while it has some
+#[derive(Default)]
+pub struct DraftWorkers {
+    engine: RwLock<Option<ExecutionContext>>,
+}
+
+impl DraftWorkers {
+    pub fn set(&self, engine: ExecutionContext) {
+        *self.engine.write().unwrap() = Some(engine);
+    }
+
+    pub fn clear(&self) {
+        *self.engine.write().unwrap() = None;
+    }
+
+    pub fn get(&self) -> Option<ExecutionContext> {
+        self.engine.read().unwrap().clone()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.engine.read().unwrap().is_some()
+    }
+}
+
+/// Sends requests to the target workers through `target`, with the tokens the draft workers
+/// guessed while the model has some. See the [module docs](self).
+pub struct SpeculativeRouter {
+    model: String,
+    target: ExecutionContext,
+    workers: Arc<DraftWorkers>,
+    config: SpeculativeConfig,
+}
+
+impl SpeculativeRouter {
+    pub fn new(
+        model: &str,
+        target: ExecutionContext,
+        workers: Arc<DraftWorkers>,
+        config: SpeculativeConfig,
+    ) -> Self {
+        SpeculativeRouter {
+            model: model.to_string(),
+            target,
+            workers,
+            config,
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for SpeculativeRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let Some(draft) = self.workers.get() else {
+            return self.target.generate(request).await;
+        };
+        // The caller drafted already
+        if self.config.num_speculative_tokens == 0 || !request.draft_token_ids.is_empty() {
+            return self.target.generate(request).await;
+        }
+        let request_ctx = request.context();
+        let (input, _) = request.into_parts();
+        let rounds = Rounds {
+            model: self.model.clone(),
+            target: self.target.clone(),
+            draft: Some(draft),
+            num_speculative_tokens: self.config.num_speculative_tokens as usize,
+            request_ctx: request_ctx.clone(),
+            annotate: input.has_annotation(ANNOTATION_WORKER_INSTANCE_ID),
+            input,
+            generated: Vec::new(),
+            target_instance: None,
+            draft_instance: None,
+        };
+        Ok(ResponseStream::new(Box::pin(rounds.run()), request_ctx))
+    }
+}
+
+/// The state of one request between its rounds
+struct Rounds {
+    model: String,
+    target: ExecutionContext,
+    /// None once the draft worker failed
+    draft: Option<ExecutionContext>,
+    num_speculative_tokens: usize,
+    request_ctx: Arc<dyn AsyncEngineContext>,
+    /// The caller wants to know the target worker
+    annotate: bool,
+    input: PreprocessedRequest,
+    /// The output so far
+    generated: Vec<TokenIdType>,
+    target_instance: Option<i64>,
+    draft_instance: Option<i64>,
+}
+
+impl Rounds {
+    fn run(mut self) -> impl Stream<Item = Annotated<LLMEngineOutput>> + Send {
+        async_stream::stream! {
+            let max_tokens = self.input.stop_conditions.max_tokens.map(|max| max as usize);
+            let reached_max = |generated: usize| max_tokens.is_some_and(|max| generated >= max);
+            let mut first = true;
+            loop {
+                let remaining = max_tokens.map(|max| max.saturating_sub(self.generated.len()));
+                // The target generates one token more than it is given
+                let draft_len = remaining.map_or(self.num_speculative_tokens, |remaining| {
+                    remaining.saturating_sub(1).min(self.num_speculative_tokens)
+                });
+                let drafts = match self.draft.clone() {
+                    Some(draft) if draft_len > 0 => self.draft_round(&draft, draft_len).await,
+                    _ => Ok(Vec::new()),
+                };
+                let drafts = match drafts {
+                    Ok(drafts) => drafts,
+                    Err(_) if self.request_ctx.is_stopped() => return,
+                    Err(err) => {
+                        tracing::warn!(
+                            request_id = self.request_ctx.id(),
+                            model = self.model,
+                            error = format!("{err:#}"),
+                            "Draft worker failed, generating without drafts"
+                        );
+                        self.draft = None;
+                        Vec::new()
+                    }
+                };
+                DRAFT_TOKENS
+                    .with_label_values(&[&self.model])
+                    .inc_by(drafts.len() as u64);
+
+                let (mut responses, _forwarding) = match self.target_round(&drafts, first).await {
+                    Ok(round) => round,
+                    Err(err) => {
+                        yield Annotated::from_data(LLMEngineOutput::error(format!("{err:#}")));
+                        return;
+                    }
+                };
+                let round_start = self.generated.len();
+                let mut finished = false;
+                while let Some(mut response) = responses.next().await {
+                    if let Some(instance_id) = worker_instance_id(&response) {
+                        self.target_instance.get_or_insert(instance_id);
+                        if self.annotate {
+                            self.annotate = false;
+                            yield response;
+                        }
+                        continue;
+                    }
+                    let Some(output) = response.data.as_mut() else {
+                        yield response;
+                        continue;
+                    };
+                    self.generated.extend(&output.token_ids);
+                    match output.finish_reason {
+                        // The limit of the round, not of the request
+                        Some(FinishReason::Length) if !reached_max(self.generated.len()) => {
+                            output.finish_reason = None;
+                            if output.token_ids.is_empty() {
+                                continue;
+                            }
+                        }
+                        Some(_) => finished = true,
+                        None => {}
+                    }
+                    yield response;
+                }
+                first = false;
+
+                let round = &self.generated[round_start..];
+                let accepted = round.iter().zip(&drafts).take_while(|(a, b)| a == b).count();
+                ACCEPTED_TOKENS
+                    .with_label_values(&[&self.model])
+                    .inc_by(accepted as u64);
+                if finished || self.request_ctx.is_stopped() {
+                    return;
+                }
+                if round.is_empty() {
+                    let err = "The target worker ended a round without generating".to_string();
+                    yield Annotated::from_data(LLMEngineOutput::error(err));
+                    return;
+                }
+                if reached_max(self.generated.len()) {
+                    yield Annotated::from_data(LLMEngineOutput::length());
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The request continuing what was generated so far
+    fn next_request(&self) -> PreprocessedRequest {
+        let mut request = self.input.clone();
+        request.token_ids.extend(&self.generated);
+        request
+    }
+
+    /// Up to `len` tokens of the draft model
+    async fn draft_round(
+        &mut self,
+        draft: &ExecutionContext,
+        len: usize,
+    ) -> anyhow::Result<Vec<TokenIdType>> {
+        let mut request = self.next_request();
+        request.stop_conditions.max_tokens = Some(len as u32);
+        request.stop_conditions.min_tokens = None;
+        request.kv_transfer_params = None;
+        request.annotations.clear();
+        // A worker the caller pinned is a target worker
+        if let Some(routing) = request
+            .nvext
+            .as_mut()
+            .and_then(|nvext| nvext.routing.as_mut())
+        {
+            routing.backend_instance_id = None;
+        }
+        match self.draft_instance {
+            Some(instance_id) => request.set_backend_instance_id(instance_id),
+            None => request
+                .annotations
+                .push(ANNOTATION_WORKER_INSTANCE_ID.to_string()),
+        }
+
+        let (mut responses, _forwarding) = self.send(draft, request).await?;
+        let mut tokens = Vec::with_capacity(len);
+        while let Some(response) = responses.next().await {
+            if let Some(instance_id) = worker_instance_id(&response) {
+                self.draft_instance.get_or_insert(instance_id);
+                continue;
+            }
+            let Some(output) = response.ok().map_err(anyhow::Error::msg)?.data else {
+                continue;
+            };
+            if let Some(FinishReason::Error(err)) = output.finish_reason {
+                anyhow::bail!(err);
+            }
+            tokens.extend(output.token_ids);
+        }
+        tokens.truncate(len);
+        Ok(tokens)
+    }
+
+    /// Check `drafts` on the target worker, which generates one token more. The first round has
+    /// the caller's annotations and KV transfer, the next ones go to the same worker.
+    async fn target_round(
+        &self,
+        drafts: &[TokenIdType],
+        first: bool,
+    ) -> anyhow::Result<(ManyOut<Annotated<LLMEngineOutput>>, DropGuard)> {
+        let mut request = self.next_request();
+        let generated = self.generated.len();
+        let max_tokens = self
+            .input
+            .stop_conditions
+            .max_tokens
+            .map_or(drafts.len() + 1, |max| {
+                (max as usize)
+                    .saturating_sub(generated)
+                    .min(drafts.len() + 1)
+            });
+        request.stop_conditions.max_tokens = Some(max_tokens as u32);
+        request.stop_conditions.min_tokens = self
+            .input
+            .stop_conditions
+            .min_tokens
+            .map(|min| (min as usize).saturating_sub(generated).min(max_tokens) as u32);
+        request.draft_token_ids = drafts.to_vec();
+        if first {
+            if !self.annotate {
+                request
+                    .annotations
+                    .push(ANNOTATION_WORKER_INSTANCE_ID.to_string());
+            }
+        } else {
+            request.annotations.clear();
+            request.kv_transfer_params = None;
+        }
+        if let Some(instance_id) = self.target_instance {
+            request.set_backend_instance_id(instance_id);
+        }
+        self.send(&self.target, request).await
+    }
+
+    /// Send `request` with its own controller, stop and kill of the request are forwarded until
+    /// the guard drops
+    async fn send(
+        &self,
+        engine: &ExecutionContext,
+        request: PreprocessedRequest,
+    ) -> anyhow::Result<(ManyOut<Annotated<LLMEngineOutput>>, DropGuard)> {
+        let request = Context::with_id(request, self.request_ctx.id().to_string());
+        let forwarding = CancellationToken::new();
+        tokio::spawn(forward_cancellation(
+            self.request_ctx.clone(),
+            request.context(),
+            forwarding.clone(),
+        ));
+        let forwarding = forwarding.drop_guard();
+        let responses = engine.generate(request).await?;
+        Ok((responses, forwarding))
+    }
+}
+
+/// The worker of a response stream, from its [`ANNOTATION_WORKER_INSTANCE_ID`] annotation
+fn worker_instance_id(response: &Annotated<LLMEngineOutput>) -> Option<i64> {
+    if response.event.as_deref() != Some(ANNOTATION_WORKER_INSTANCE_ID) {
+        return None;
+    }
+    let comment = response.comment.as_ref()?.first()?;
+    serde_json::from_str(comment).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::common::{SamplingOptions, StopConditions};
+    use std::sync::Mutex;
+
+    const PROMPT: [TokenIdType; 3] = [1, 2, 3];
+
+    /// What the target model generates after the prompt
+    const TARGET: [TokenIdType; 10] = [10, 11, 12, 13, 14, 15, 16, 17, 18, 19];
+
+    /// Generates [`TARGET`]. As a draft worker, guesses positions 2, 6 and 10 wrong. Given draft
+    /// tokens, stops after the first one it doesn't generate itself.
+    #[derive(Default)]
+    struct Worker {
+        draft: bool,
+        requests: Mutex<Vec<PreprocessedRequest>>,
+    }
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+        for Worker
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<PreprocessedRequest>,
+        ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push(PreprocessedRequest::clone(&request));
+            let start = request.token_ids.len() - PROMPT.len();
+            let max_tokens = request.stop_conditions.max_tokens.unwrap_or(u32::MAX) as usize;
+            let drafts = &request.draft_token_ids;
+            let mut outputs = Vec::new();
+            for (i, position) in (start..).enumerate() {
+                if position >= TARGET.len() {
+                    outputs.push(LLMEngineOutput::stop());
+                    break;
+                }
+                if i == max_tokens {
+                    outputs.push(LLMEngineOutput::length());
+                    break;
+                }
+                let mut token = TARGET[position];
+                if self.draft && position % 4 == 2 {
+                    token = 0;
+                }
+                outputs.push(LLMEngineOutput {
+                    token_ids: vec![token],
+                    finish_reason: None,
+                    ..LLMEngineOutput::stop()
+                });
+                if !drafts.is_empty() && drafts.get(i) != Some(&token) {
+                    outputs.push(LLMEngineOutput::length());
+                    break;
+                }
+            }
+            let responses: Vec<_> = outputs.into_iter().map(Annotated::from_data).collect();
+            Ok(ResponseStream::new(
+                Box::pin(futures::stream::iter(responses)),
+                request.context(),
+            ))
+        }
+    }
+
+    fn request(max_tokens: Option<u32>) -> SingleIn<PreprocessedRequest> {
+        let request = PreprocessedRequest::builder()
+            .token_ids(PROMPT.to_vec())
+            .stop_conditions(StopConditions {
+                max_tokens,
+                ..Default::default()
+            })
+            .sampling_options(SamplingOptions::default())
+            .build()
+            .unwrap();
+        Context::new(request)
+    }
+
+    async fn generate(
+        router: &SpeculativeRouter,
+        max_tokens: Option<u32>,
+    ) -> (Vec<TokenIdType>, Option<FinishReason>) {
+        let outputs: Vec<_> = router
+            .generate(request(max_tokens))
+            .await
+            .unwrap()
+            .filter_map(|response| async move { response.data })
+            .collect()
+            .await;
+        let tokens = outputs.iter().flat_map(|output| output.token_ids.clone());
+        let finish_reason = outputs
+            .last()
+            .and_then(|output| output.finish_reason.clone());
+        (tokens.collect(), finish_reason)
+    }
+
+    #[tokio::test]
+    async fn test_speculative_router() {
+        let target = Arc::new(Worker::default());
+        let draft = Arc::new(Worker {
+            draft: true,
+            ..Default::default()
+        });
+        let workers = Arc::new(DraftWorkers::default());
+        let router = SpeculativeRouter::new(
+            "test_speculative_router",
+            target.clone(),
+            workers.clone(),
+            SpeculativeConfig {
+                num_speculative_tokens: 3,
+            },
+        );
+
+        // No draft workers yet
+        let (tokens, finish_reason) = generate(&router, None).await;
+        assert_eq!(tokens, TARGET);
+        assert_eq!(finish_reason, Some(FinishReason::Stop));
+        assert!(target.requests.lock().unwrap()[0]
+            .draft_token_ids
+            .is_empty());
+        target.requests.lock().unwrap().clear();
+
+        // Three rounds of up to four tokens
+        workers.set(draft.clone());
+        let (tokens, finish_reason) = generate(&router, None).await;
+        assert_eq!(tokens, TARGET);
+        assert_eq!(finish_reason, Some(FinishReason::Stop));
+        let verified: Vec<_> = target
+            .requests
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|request| (request.token_ids.len(), request.draft_token_ids))
+            .collect();
+        assert_eq!(
+            verified,
+            vec![
+                (3, vec![10, 11, 0]),
+                (6, vec![13, 14, 15]),
+                (10, vec![17, 18, 19])
+            ]
+        );
+
+        // The last round guesses no more than the token limit leaves
+        let (tokens, finish_reason) = generate(&router, Some(5)).await;
+        assert_eq!(tokens, TARGET[..5]);
+        assert_eq!(finish_reason, Some(FinishReason::Length));
+        let requests = target.requests.lock().unwrap();
+        assert_eq!(requests[1].draft_token_ids, vec![13]);
+        assert_eq!(requests[1].stop_conditions.max_tokens, Some(2));
+    }
+}
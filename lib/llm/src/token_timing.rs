@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! When each token was generated, measured on the worker.
+//!
+//! Inter-token latency measured by a client includes the network and the frontend, and comparing
+//! timestamps from different hosts suffers from clock skew. Instead the worker notes when each
+//! token left the engine, on its monotonic clock, as an offset from when it received the request.
+//!
+//! A client asks for it with the `token_timestamps` annotation in `nvext.annotations`. The
+//! offsets then follow the last token as a [`TokenTimestamps`] annotation event, which the audit
+//! log records too.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_stream::stream;
+use async_trait::async_trait;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ExecutionContext;
+use crate::preprocessor::PreprocessedRequest;
+use crate::protocols::common::llm_backend::LLMEngineOutput;
+
+pub const ANNOTATION_TOKEN_TIMESTAMPS: &str = "token_timestamps";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenTimestamps {
+    /// Microseconds from when the worker received the request to when each token was generated,
+    /// one per output token. Tokens generated together share a timestamp.
+    pub offsets_us: Vec<u64>,
+}
+
+impl TokenTimestamps {
+    /// Microseconds from the request to the first token
+    pub fn time_to_first_token_us(&self) -> Option<u64> {
+        self.offsets_us.first().copied()
+    }
+
+    /// Microseconds between each token and the one before it
+    pub fn inter_token_latencies_us(&self) -> Vec<u64> {
+        self.offsets_us
+            .windows(2)
+            .map(|w| w[1].saturating_sub(w[0]))
+            .collect()
+    }
+}
+
+/// Wraps an engine and adds [`TokenTimestamps`] to the responses of requests that ask for them
+pub struct TokenTimingEngine {
+    inner: ExecutionContext,
+}
+
+impl TokenTimingEngine {
+    pub fn new(inner: ExecutionContext) -> ExecutionContext {
+        Arc::new(TokenTimingEngine { inner })
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for TokenTimingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        if !request.has_annotation(ANNOTATION_TOKEN_TIMESTAMPS) {
+            return self.inner.generate(request).await;
+        }
+        let start = Instant::now();
+        let mut response = self.inner.generate(request).await?;
+        let ctx = response.context();
+
+        let output = stream! {
+            let mut timestamps = TokenTimestamps::default();
+            while let Some(item) = response.next().await {
+                if let Some(data) = item.data.as_ref() {
+                    let offset = start.elapsed().as_micros() as u64;
+                    timestamps
+                        .offsets_us
+                        .extend(std::iter::repeat_n(offset, data.token_ids.len()));
+                }
+                yield item;
+            }
+            match Annotated::from_annotation(ANNOTATION_TOKEN_TIMESTAMPS, &timestamps) {
+                Ok(annotation) => yield annotation,
+                Err(err) => tracing::error!(%err, "Failed serializing token timestamps"),
+            }
+        };
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::common::{SamplingOptions, StopConditions};
+    use dynamo_runtime::pipeline::Context;
+
+    /// Sends two tokens, then one
+    struct TwoChunks;
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+        for TwoChunks
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<PreprocessedRequest>,
+        ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+            let chunks = vec![
+                Annotated::from_data(LLMEngineOutput {
+                    token_ids: vec![1, 2],
+                    ..LLMEngineOutput::stop()
+                }),
+                Annotated::from_data(LLMEngineOutput {
+                    token_ids: vec![3],
+                    ..LLMEngineOutput::stop()
+                }),
+            ];
+            Ok(ResponseStream::new(
+                Box::pin(futures::stream::iter(chunks)),
+                request.context(),
+            ))
+        }
+    }
+
+    fn request(annotations: Vec<String>) -> SingleIn<PreprocessedRequest> {
+        let request = PreprocessedRequest::builder()
+            .token_ids(vec![1])
+            .stop_conditions(StopConditions::default())
+            .sampling_options(SamplingOptions::default())
+            .annotations(annotations)
+            .build()
+            .unwrap();
+        Context::new(request)
+    }
+
+    #[tokio::test]
+    async fn test_token_timestamps() {
+        let engine = TokenTimingEngine::new(Arc::new(TwoChunks));
+
+        let responses: Vec<_> = engine
+            .generate(request(vec![]))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 2);
+
+        let responses: Vec<_> = engine
+            .generate(request(vec![ANNOTATION_TOKEN_TIMESTAMPS.to_string()]))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 3);
+        let annotation = &responses[2];
+        assert_eq!(
+            annotation.event.as_deref(),
+            Some(ANNOTATION_TOKEN_TIMESTAMPS)
+        );
+        let timestamps: TokenTimestamps =
+            serde_json::from_str(&annotation.comment.as_ref().unwrap()[0]).unwrap();
+        assert_eq!(timestamps.offsets_us.len(), 3);
+        assert_eq!(timestamps.offsets_us[0], timestamps.offsets_us[1]);
+        assert_eq!(timestamps.inter_token_latencies_us().len(), 2);
+    }
+}
@@ -14,6 +14,8 @@
 // limitations under the License.
 
 pub mod hf;
+pub mod lazy;
+pub mod registry;
 
 #[cfg(feature = "sentencepiece")]
 pub mod sp;
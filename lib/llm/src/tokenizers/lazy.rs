@@ -0,0 +1,90 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A model's tokenizer and chat template, loaded the first time they are needed.
+//!
+//! A frontend that forwards requests to workers which tokenize themselves doesn't need the
+//! tokenizer to serve them, only to answer `/v1/tokenize`. The files of a card published by a
+//! worker are downloaded from the NATS object store it refers to on first use.
+
+use std::sync::Arc;
+
+use dynamo_runtime::transports::nats;
+use tokio::sync::OnceCell;
+
+use super::registry::{self, SharedTokenizer};
+use super::Result;
+use crate::model_card::model::{ModelDeploymentCard, SpecialTokens};
+use crate::preprocessor::prompt::{OAIPromptFormatter, PromptFormatter};
+use crate::protocols::TokenIdType;
+
+pub struct LoadedTokenizer {
+    pub tokenizer: Arc<SharedTokenizer>,
+
+    /// The chat template. None if the model doesn't have one.
+    pub formatter: Option<Arc<dyn OAIPromptFormatter>>,
+
+    /// Only loaded if the card's special tokens need it, to add or remove it
+    pub bos_token_id: Option<TokenIdType>,
+}
+
+pub struct LazyTokenizer {
+    card: ModelDeploymentCard,
+    nats_client: Option<nats::Client>,
+    loaded: OnceCell<Arc<LoadedTokenizer>>,
+}
+
+impl LazyTokenizer {
+    /// `nats_client` downloads the files of a card published by a worker. Cards of local models
+    /// don't need it.
+    pub fn new(card: ModelDeploymentCard, nats_client: Option<nats::Client>) -> Self {
+        LazyTokenizer {
+            card,
+            nats_client,
+            loaded: OnceCell::new(),
+        }
+    }
+
+    /// Maximum number of tokens in a request to the model, prompt and completion
+    pub fn context_length(&self) -> usize {
+        self.card.context_length
+    }
+
+    /// How the preprocessor handles the model's special tokens
+    pub fn special_tokens(&self) -> &SpecialTokens {
+        &self.card.special_tokens
+    }
+
+    /// The tokenizer and chat template, loading them on the first call. If loading fails the
+    /// next call tries again.
+    pub async fn get(&self) -> Result<Arc<LoadedTokenizer>> {
+        self.loaded.get_or_try_init(|| self.load()).await.cloned()
+    }
+
+    async fn load(&self) -> Result<Arc<LoadedTokenizer>> {
+        let mut card = self.card.clone();
+        // Deleted on return, the tokenizer and chat template are in memory by then
+        let _cache_dir = match &self.nats_client {
+            Some(nats_client) => Some(card.move_from_nats(nats_client.clone()).await?),
+            None => None,
+        };
+        let tokenizer = registry::load(&card)?;
+        tokenizer.wait().await?;
+        let bos_token_id = match (&card.model_info, card.special_tokens.add_bos) {
+            (Some(model_info), Some(_)) => Some(model_info.get_model_info().await?.bos_token_id()),
+            _ => None,
+        };
+        let formatter = if card.prompt_formatter.is_some() {
+            let PromptFormatter::OAI(formatter) = PromptFormatter::from_mdc(card).await?;
+            Some(formatter)
+        } else {
+            None
+        };
+        tracing::debug!(model = self.card.display_name, "Loaded tokenizer");
+        Ok(Arc::new(LoadedTokenizer {
+            tokenizer,
+            formatter,
+            bos_token_id,
+        }))
+    }
+}
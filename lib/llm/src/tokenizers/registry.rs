@@ -0,0 +1,190 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tokenizers loaded in the background and shared by everything serving the same model.
+//!
+//! Large tokenizers can take many seconds to load. [`load`] returns immediately with a
+//! [`SharedTokenizer`] and loads the tokenizer on its own thread. The preprocessor and the backend
+//! of every pipeline built from the same [`ModelDeploymentCard`] get the same instance, keyed by
+//! the card's checksum, so it is only loaded once.
+//!
+//! The status of every tokenizer is available from [`statuses`] for readiness checks.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock, Weak};
+use std::time::Instant;
+
+use tokio::sync::Notify;
+
+use super::traits::{self, Decoder, Encoder};
+use super::{Encoding, HuggingFaceTokenizer, Result};
+use crate::model_card::model::ModelDeploymentCard;
+use crate::protocols::TokenIdType;
+
+/// Card checksum to tokenizer. Weak so that tokenizers of removed models are freed.
+static REGISTRY: LazyLock<Mutex<HashMap<String, Weak<SharedTokenizer>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenizerStatus {
+    Loading,
+    Ready,
+    Failed(String),
+}
+
+/// A tokenizer that may still be loading
+pub struct SharedTokenizer {
+    model_name: String,
+    loaded: OnceLock<std::result::Result<Arc<HuggingFaceTokenizer>, String>>,
+    notify: Notify,
+}
+
+impl SharedTokenizer {
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    pub fn status(&self) -> TokenizerStatus {
+        match self.loaded.get() {
+            None => TokenizerStatus::Loading,
+            Some(Ok(_)) => TokenizerStatus::Ready,
+            Some(Err(err)) => TokenizerStatus::Failed(err.clone()),
+        }
+    }
+
+    /// The tokenizer, once loaded. Fails if loading failed.
+    pub async fn wait(&self) -> Result<Arc<HuggingFaceTokenizer>> {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // Register interest before checking, so we can't miss the notification
+        notified.as_mut().enable();
+        if self.loaded.get().is_none() {
+            notified.await;
+        }
+        self.loaded_tokenizer()
+    }
+
+    /// Block the thread until loaded. Only for the sync [`traits::Tokenizer`] methods, async code
+    /// should call [`SharedTokenizer::wait`] first.
+    fn blocking_get(&self) -> Result<Arc<HuggingFaceTokenizer>> {
+        self.loaded.wait();
+        self.loaded_tokenizer()
+    }
+
+    fn loaded_tokenizer(&self) -> Result<Arc<HuggingFaceTokenizer>> {
+        match self.loaded.get() {
+            Some(Ok(tokenizer)) => Ok(tokenizer.clone()),
+            Some(Err(err)) => anyhow::bail!(
+                "Tokenizer for model {} failed to load: {err}",
+                self.model_name
+            ),
+            None => anyhow::bail!("Tokenizer for model {} is still loading", self.model_name),
+        }
+    }
+}
+
+impl Encoder for SharedTokenizer {
+    fn encode(&self, input: &str) -> Result<Encoding> {
+        self.blocking_get()?.encode(input)
+    }
+}
+
+impl Decoder for SharedTokenizer {
+    fn decode(&self, token_ids: &[TokenIdType], skip_special_tokens: bool) -> Result<String> {
+        self.blocking_get()?.decode(token_ids, skip_special_tokens)
+    }
+}
+
+impl traits::Tokenizer for SharedTokenizer {}
+
+/// The tokenizer for this card. Starts loading it if it isn't already loaded or loading.
+/// A tokenizer that failed to load is loaded again.
+pub fn load(card: &ModelDeploymentCard) -> Result<Arc<SharedTokenizer>> {
+    if !card.has_tokenizer() {
+        anyhow::bail!("Blank ModelDeploymentCard does not have a tokenizer");
+    }
+    let key = card.mdcsum();
+
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|_, tokenizer| tokenizer.strong_count() > 0);
+    if let Some(existing) = registry.get(&key).and_then(Weak::upgrade) {
+        if !matches!(existing.status(), TokenizerStatus::Failed(_)) {
+            return Ok(existing);
+        }
+    }
+
+    let shared = Arc::new(SharedTokenizer {
+        model_name: card.display_name.clone(),
+        loaded: OnceLock::new(),
+        notify: Notify::new(),
+    });
+    registry.insert(key, Arc::downgrade(&shared));
+    drop(registry);
+
+    let card = card.clone();
+    let loading = shared.clone();
+    // A thread rather than spawn_blocking so this doesn't need a tokio runtime
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let result = card
+            .tokenizer_hf()
+            .map(|t| Arc::new(HuggingFaceTokenizer::from_tokenizer(t)))
+            .map_err(|err| format!("{err:#}"));
+        match &result {
+            Ok(_) => tracing::debug!(
+                model = loading.model_name,
+                elapsed_ms = start.elapsed().as_millis(),
+                "Tokenizer loaded"
+            ),
+            Err(err) => {
+                tracing::error!(model = loading.model_name, %err, "Tokenizer failed to load")
+            }
+        }
+        let _ = loading.loaded.set(result);
+        loading.notify.notify_waiters();
+    });
+
+    Ok(shared)
+}
+
+/// Model name and status of every tokenizer in use
+pub fn statuses() -> Vec<(String, TokenizerStatus)> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(Weak::upgrade)
+        .map(|t| (t.model_name.clone(), t.status()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shared_load() {
+        let card = ModelDeploymentCard::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/sample-models/TinyLlama_v1.1"
+        ))
+        .await
+        .unwrap();
+
+        let first = load(&card).unwrap();
+        let second = load(&card).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        first.wait().await.unwrap();
+        assert_eq!(second.status(), TokenizerStatus::Ready);
+        assert!(!second.encode("hello").unwrap().token_ids.is_empty());
+        assert!(statuses()
+            .iter()
+            .any(|(_, status)| *status == TokenizerStatus::Ready));
+    }
+
+    #[test]
+    fn test_blank_card() {
+        assert!(load(&ModelDeploymentCard::with_name_only("blank")).is_err());
+    }
+}
@@ -13,8 +13,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use dynamo_llm::backend::Backend;
+use std::sync::Arc;
+
+use dynamo_llm::backend::{Backend, Decoder, StopTrigger};
 use dynamo_llm::model_card::model::ModelDeploymentCard;
+use dynamo_llm::protocols::common::llm_backend::{LLMEngineOutput, PreprocessedRequest};
+use dynamo_llm::protocols::common::{SamplingOptions, StopConditions};
+use dynamo_llm::protocols::TokenIdType;
+use dynamo_llm::token_timing::{TokenTimestamps, TokenTimingEngine, ANNOTATION_TOKEN_TIMESTAMPS};
+use dynamo_llm::tokenizers::traits::Encoder;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Context, Error, ManyOut, Operator, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
 
 #[tokio::test]
 async fn test_sequence_factory() {
@@ -32,3 +43,129 @@ async fn test_sequence_factory() {
     let output = decode_stream.step(1).unwrap();
     assert_eq!(output, None);
 }
+
+/// Decode `token_ids` one at a time like a streaming engine sends them, until a stop condition
+fn decode_streaming(mut decoder: Decoder, token_ids: &[u32]) -> (String, Option<StopTrigger>) {
+    let mut text = String::new();
+    for token_id in token_ids {
+        let result = decoder.process_token_ids(&[*token_id]).unwrap();
+        text.push_str(&result.text.unwrap_or_default());
+        if result.stop_trigger.is_some() {
+            return (text, result.stop_trigger);
+        }
+    }
+    text.push_str(&decoder.flush().unwrap_or_default());
+    (text, None)
+}
+
+#[tokio::test]
+async fn test_stop_sequences() {
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/TinyLlama_v1.1")
+        .await
+        .unwrap();
+    let operator = Backend::from_mdc(mdc).await.unwrap();
+    let tokenizer = operator.tokenizer.as_ref().unwrap();
+    let token_ids = tokenizer
+        .encode("The quick brown fox jumps over the lazy dog")
+        .unwrap()
+        .token_ids;
+    let decoder = |stop: &[&str]| {
+        let stop_conditions = StopConditions {
+            stop: Some(stop.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        };
+        Decoder::new(tokenizer.decode_stream(false), stop_conditions)
+    };
+
+    let (full, stop_trigger) = decode_streaming(decoder(&[]), &token_ids);
+    assert!(stop_trigger.is_none());
+
+    // spans several tokens, and nothing of it is released before the match
+    let (text, stop_trigger) = decode_streaming(decoder(&["fox jum", "lazy"]), &token_ids);
+    assert_eq!(text, full[..full.find("fox jum").unwrap()]);
+    assert!(matches!(
+        stop_trigger,
+        Some(StopTrigger::HiddenStopSequenceDetected(seq)) if seq == "fox jum"
+    ));
+
+    // a partial match at the end is released when the stream ends
+    let (text, stop_trigger) = decode_streaming(decoder(&["dog!"]), &token_ids);
+    assert_eq!(text, full);
+    assert!(stop_trigger.is_none());
+}
+
+/// Sends its tokens one at a time, without decoding them
+struct TokenEngine(Vec<TokenIdType>);
+
+#[async_trait::async_trait]
+impl AsyncEngine<SingleIn<PreprocessedRequest>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for TokenEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<PreprocessedRequest>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let chunks: Vec<_> = self
+            .0
+            .iter()
+            .map(|token_id| {
+                Annotated::from_data(LLMEngineOutput {
+                    token_ids: vec![*token_id],
+                    finish_reason: None,
+                    ..LLMEngineOutput::stop()
+                })
+            })
+            .chain([Annotated::from_data(LLMEngineOutput::stop())])
+            .collect();
+        Ok(ResponseStream::new(
+            Box::pin(futures::stream::iter(chunks)),
+            request.context(),
+        ))
+    }
+}
+
+#[tokio::test]
+async fn test_stop_sequence_keeps_token_timestamps() {
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/TinyLlama_v1.1")
+        .await
+        .unwrap();
+    let operator = Backend::from_mdc(mdc).await.unwrap();
+    let token_ids = operator
+        .tokenizer
+        .as_ref()
+        .unwrap()
+        .encode("The quick brown fox jumps over the lazy dog")
+        .unwrap()
+        .token_ids;
+    let engine = TokenTimingEngine::new(Arc::new(TokenEngine(token_ids)));
+    let request = PreprocessedRequest::builder()
+        .token_ids(vec![1])
+        .stop_conditions(StopConditions {
+            stop: Some(vec!["fox".to_string()]),
+            ..Default::default()
+        })
+        .sampling_options(SamplingOptions::default())
+        .annotations(vec![ANNOTATION_TOKEN_TIMESTAMPS.to_string()])
+        .build()
+        .unwrap();
+
+    let responses: Vec<_> = operator
+        .generate(Context::new(request), engine)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    let text: String = responses
+        .iter()
+        .filter_map(|r| r.data.as_ref()?.text.clone())
+        .collect();
+    assert!(!text.contains("fox"), "{text}");
+    assert!(!text.contains("lazy"), "{text}");
+
+    // The timestamps of every token the engine sent come after the stop
+    let last = responses.last().unwrap();
+    assert_eq!(last.event.as_deref(), Some(ANNOTATION_TOKEN_TIMESTAMPS));
+    let timestamps: TokenTimestamps =
+        serde_json::from_str(&last.comment.as_ref().unwrap()[0]).unwrap();
+    assert!(!timestamps.offsets_us.is_empty());
+}
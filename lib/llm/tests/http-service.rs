@@ -25,6 +25,7 @@ use dynamo_llm::protocols::{
     openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
         completions::{CompletionResponse, NvCreateCompletionRequest},
+        embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse},
     },
     Annotated,
 };
@@ -69,6 +70,7 @@ impl
 
                 let output = NvCreateChatCompletionStreamResponse {
                     inner,
+                    nvext: None,
                 };
 
                 yield Annotated::from_data(output);
@@ -115,6 +117,46 @@ impl AsyncEngine<SingleIn<NvCreateCompletionRequest>, ManyOut<Annotated<Completi
     }
 }
 
+/// Returns one vector per input, filled with the input's length
+struct PoolingEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateEmbeddingRequest>,
+        ManyOut<Annotated<NvCreateEmbeddingResponse>>,
+        Error,
+    > for PoolingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateEmbeddingRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateEmbeddingResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let inputs = match request.inner.input {
+            async_openai::types::EmbeddingInput::String(s) => vec![s],
+            async_openai::types::EmbeddingInput::StringArray(v) => v,
+            _ => anyhow::bail!("PoolingEngine only takes text"),
+        };
+        let mut response = NvCreateEmbeddingResponse::empty();
+        response.inner.model = request.inner.model;
+        response.inner.data = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, s)| async_openai::types::Embedding {
+                index: i as u32,
+                object: "embedding".to_string(),
+                embedding: vec![s.len() as f32; 4],
+            })
+            .collect();
+        let stream = stream! {
+            yield Annotated::from_data(response);
+        };
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
 fn compare_counter(
     metrics: &Metrics,
     model: &str,
@@ -139,6 +181,7 @@ fn compute_index(endpoint: &Endpoint, request_type: &RequestType, status: &Statu
         Endpoint::Completions => 0,
         Endpoint::ChatCompletions => 1,
         Endpoint::Embeddings => todo!(),
+        Endpoint::Responses => todo!(),
     };
 
     let request_type = match request_type {
@@ -465,3 +508,63 @@ async fn test_http_service() {
     cancel_token.cancel();
     task.await.unwrap().unwrap();
 }
+
+#[tokio::test]
+async fn test_http_service_embeddings() {
+    let service = HttpService::builder().port(8990).build().unwrap();
+    let state = service.state_clone();
+    let manager = state.manager();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    let result = manager.add_embeddings_model("embedder", Arc::new(PoolingEngine {}));
+    assert!(result.is_ok());
+
+    let client = reqwest::Client::new();
+    let request = async_openai::types::CreateEmbeddingRequestArgs::default()
+        .model("embedder")
+        .input(vec!["a", "abc"])
+        .build()
+        .expect("Failed to build request");
+
+    let response = client
+        .post("http://localhost:8990/v1/embeddings")
+        .json(&request)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+
+    let response: async_openai::types::CreateEmbeddingResponse = response.json().await.unwrap();
+    assert_eq!(response.model, "embedder");
+    assert_eq!(response.data.len(), 2);
+    assert_eq!(response.data[1].index, 1);
+    assert_eq!(response.data[1].embedding, vec![3.0; 4]);
+
+    // Embedding models are listed alongside chat models
+    let models: serde_json::Value = client
+        .get("http://localhost:8990/v1/models")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(models["data"][0]["id"], "embedder");
+
+    // An unknown model is a 404, not a 500
+    let mut unknown = request.clone();
+    unknown.model = "nope".to_string();
+    let response = client
+        .post("http://localhost:8990/v1/embeddings")
+        .json(&unknown)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
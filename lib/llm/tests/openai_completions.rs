@@ -36,7 +36,11 @@ impl CompletionSample {
 
         let inner = builder.build().unwrap();
 
-        let request = NvCreateCompletionRequest { inner, nvext: None };
+        let request = NvCreateCompletionRequest {
+            inner,
+            nvext: None,
+            prompt_token_ids: None,
+        };
 
         Ok(Self {
             request,
@@ -124,3 +128,25 @@ fn build_samples() -> Result<Vec<CompletionSample>, String> {
 
     Ok(samples)
 }
+
+#[test]
+fn token_id_prompts_skip_the_template() {
+    use dynamo_llm::preprocessor::prompt::OAIChatLikeRequest;
+
+    let request = |prompt: serde_json::Value| -> NvCreateCompletionRequest {
+        serde_json::from_value(serde_json::json!({"model": "gpt-3.5-turbo", "prompt": prompt}))
+            .unwrap()
+    };
+
+    // Token ids of large vocabularies don't fit in a u16
+    let tokens = request(serde_json::json!([1, 2, 128000]));
+    assert_eq!(tokens.prompt_token_ids(), Some(vec![1, 2, 128000]));
+    let json = serde_json::to_value(&tokens).unwrap();
+    assert_eq!(json["prompt"], serde_json::json!([1, 2, 128000]));
+
+    let nested = request(serde_json::json!([[5, 6]]));
+    assert_eq!(nested.prompt_token_ids(), Some(vec![5, 6]));
+
+    let text = request(serde_json::json!("What is the meaning of life?"));
+    assert_eq!(text.prompt_token_ids(), None);
+}
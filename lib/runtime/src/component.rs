@@ -52,6 +52,7 @@ use validator::{Validate, ValidationError};
 mod client;
 #[allow(clippy::module_inception)]
 mod component;
+mod control;
 mod endpoint;
 mod namespace;
 mod registry;
@@ -87,6 +88,41 @@ pub struct Instance {
     pub namespace: String,
     pub instance_id: i64,
     pub transport: TransportType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    /// Most requests the instance serves at once. Routers hold back the requests beyond it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<u32>,
+    /// What the instance runs, for routers to check before sending it requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<WorkerDescription>,
+    /// What an hour of a GPU of the instance's pool costs, e.g. less on spot than on on-demand
+    /// capacity. Budget routing prefers the cheaper pools.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_per_gpu_hour: Option<f64>,
+}
+
+/// What a worker runs, as it describes itself. Every field is optional, workers fill in what
+/// they know.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct WorkerDescription {
+    /// The engine, e.g. `vllm`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    /// The version of the engine, e.g. `0.9.1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine_version: Option<String>,
+    /// The build of the worker, e.g. a commit or an image tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// The CUDA version the engine uses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cuda_version: Option<String>,
+    /// The version of the GPU driver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver_version: Option<String>,
 }
 
 impl Instance {
@@ -345,6 +381,12 @@ impl Endpoint {
         )
     }
 
+    /// Subject of the work queue of the [Endpoint], which the workers serving it in pull mode
+    /// take requests from
+    pub fn queue_subject(&self) -> String {
+        format!("queue.{}", self.subject())
+    }
+
     pub async fn client(&self) -> Result<client::Client> {
         if self.is_static {
             client::Client::new_static(self.clone()).await
@@ -132,6 +132,53 @@ impl Client {
         matches!(self.instance_source.as_ref(), InstanceSource::Static)
     }
 
+    /// A client of the same endpoint that only sees the instances `admit` accepts. `admit` runs
+    /// each time the instances change. A static client is returned as is.
+    pub fn filtered<F>(&self, admit: F) -> Client
+    where
+        F: Fn(&Instance) -> bool + Send + 'static,
+    {
+        let InstanceSource::Dynamic(source_rx) = self.instance_source.as_ref() else {
+            return self.clone();
+        };
+        let filter = move |instances: &[Instance]| -> Vec<Instance> {
+            instances
+                .iter()
+                .filter(|instance| admit(instance))
+                .cloned()
+                .collect()
+        };
+        let mut source_rx = source_rx.clone();
+        let (watch_tx, watch_rx) =
+            tokio::sync::watch::channel(filter(&source_rx.borrow_and_update()));
+
+        // Keeps the source alive, so that other clients of the endpoint share it
+        let source = self.instance_source.clone();
+        let secondary = self.endpoint.component.drt.runtime.secondary().clone();
+        secondary.spawn(async move {
+            let _source = source;
+            loop {
+                tokio::select! {
+                    _ = watch_tx.closed() => break,
+                    changed = source_rx.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                }
+                let admitted = filter(&source_rx.borrow_and_update());
+                if watch_tx.send(admitted).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Client {
+            endpoint: self.endpoint.clone(),
+            instance_source: Arc::new(InstanceSource::Dynamic(watch_rx)),
+        }
+    }
+
     async fn get_or_create_dynamic_instance_source(
         etcd_client: &EtcdClient,
         endpoint: &Endpoint,
@@ -43,6 +43,9 @@ impl EventPublisher for Component {
         bytes: Vec<u8>,
     ) -> Result<()> {
         let subject = format!("{}.{}", self.subject(), event_name.as_ref());
+        if crate::fault_injection::drop_nats_message(&subject) {
+            return Ok(());
+        }
         Ok(self
             .drt()
             .nats_client()
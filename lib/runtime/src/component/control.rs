@@ -0,0 +1,142 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Control-plane messages to the instances of a [`Component`], separate from the requests its
+//! endpoints serve. For example to change what a worker serves while it runs.
+//!
+//! A message is handled by one instance: either the instance with a given id, or any one instance
+//! of the component. The handler's result, `Ok` or the error message, is the reply.
+//!
+//! With a cluster key, messages must be signed, see [`crate::control_signing`].
+
+use std::future::Future;
+
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+
+use super::*;
+use crate::control_signing;
+use crate::traits::events::EventPublisher;
+
+/// NATS queue group of all the instances of a component, so that a message not addressed to a
+/// specific instance is only handled once
+const CONTROL_QUEUE_GROUP: &str = "control";
+
+impl Component {
+    fn control_subject(&self, instance_id: Option<i64>) -> String {
+        let subject = format!("{}.control", EventPublisher::subject(self));
+        match instance_id {
+            Some(instance_id) => format!("{subject}.{instance_id:x}"),
+            None => subject,
+        }
+    }
+
+    /// Send a control message and wait up to `timeout` for the reply. With no `instance_id` any
+    /// one instance of the component handles it.
+    pub async fn control_request<Req, Resp>(
+        &self,
+        instance_id: Option<i64>,
+        request: &Req,
+        timeout: Duration,
+    ) -> Result<Resp>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+    {
+        let subject = self.control_subject(instance_id);
+        let payload = serde_json::to_vec(request)?;
+        let headers =
+            control_signing::sign(self.drt().control_signer().as_deref(), &subject, &payload);
+        // The client's own request timeout would cut longer ones short
+        let request = async_nats::Request::new()
+            .headers(headers)
+            .payload(payload.into())
+            .timeout(Some(timeout));
+        let reply = self
+            .drt()
+            .nats_client()
+            .client()
+            .send_request(subject.clone(), request)
+            .await
+            .map_err(|err| match err.kind() {
+                async_nats::RequestErrorKind::TimedOut => {
+                    error!("No reply to control message on {subject} within {timeout:?}")
+                }
+                _ => error!("Control message on {subject} failed: {err}"),
+            })?;
+        let reply: std::result::Result<Resp, String> = serde_json::from_slice(&reply.payload)?;
+        reply.map_err(|err| error!("{err}"))
+    }
+
+    /// Handle the control messages sent to this component, or to this instance, until the runtime
+    /// shuts down. The instance id is the primary lease id, so a static component can't do this.
+    pub async fn serve_control<Req, Resp, F, Fut>(&self, handler: F) -> Result<()>
+    where
+        Req: DeserializeOwned + Send + 'static,
+        Resp: Serialize + Send + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp>> + Send + 'static,
+    {
+        let Some(lease) = self.drt().primary_lease() else {
+            return Err(error!(
+                "Static component {self} has no instance id for control messages"
+            ));
+        };
+        let client = self.drt().nats_client().client().clone();
+        let shared = client
+            .queue_subscribe(self.control_subject(None), CONTROL_QUEUE_GROUP.to_string())
+            .await?;
+        let own = client
+            .subscribe(self.control_subject(Some(lease.id())))
+            .await?;
+        let mut messages = futures::stream::select(shared, own);
+
+        let cancel_token = self.drt().child_token();
+        let signer = self.drt().control_signer();
+        let handler = Arc::new(handler);
+        loop {
+            let message = tokio::select! {
+                _ = cancel_token.cancelled() => return Ok(()),
+                message = messages.next() => match message {
+                    Some(message) => message,
+                    None => return Ok(()),
+                },
+            };
+            let Some(reply_to) = message.reply.clone() else {
+                tracing::warn!(subject = %message.subject, "Control message without a reply subject");
+                continue;
+            };
+            let handler = handler.clone();
+            let client = client.clone();
+            let signer = signer.clone();
+            tokio::spawn(async move {
+                let verified = control_signing::verify(
+                    signer.as_deref(),
+                    &message.subject,
+                    message.headers.as_ref(),
+                    &message.payload,
+                );
+                let result = match verified {
+                    Ok(()) => match serde_json::from_slice::<Req>(&message.payload) {
+                        Ok(request) => handler(request).await.map_err(|err| format!("{err:#}")),
+                        Err(err) => Err(format!("Invalid control message: {err}")),
+                    },
+                    Err(err) => {
+                        tracing::warn!(%err, "Control message refused");
+                        Err(err.to_string())
+                    }
+                };
+                let reply = match serde_json::to_vec(&result) {
+                    Ok(reply) => reply,
+                    Err(err) => {
+                        tracing::error!(%err, "Failed serializing control message reply");
+                        return;
+                    }
+                };
+                if let Err(err) = client.publish(reply_to, reply.into()).await {
+                    tracing::warn!(%err, "Failed replying to control message");
+                }
+            });
+        }
+    }
+}
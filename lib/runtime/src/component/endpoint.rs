@@ -16,6 +16,11 @@
 use derive_getters::Dissolve;
 
 use super::*;
+use crate::lifecycle::LifecycleStage;
+use crate::pipeline::network::egress::queue::WorkQueue;
+use crate::pipeline::network::ingress::pull_endpoint::PullEndpoint;
+use crate::standby;
+use tokio_util::sync::CancellationToken;
 
 pub use async_nats::service::endpoint::Stats as EndpointStats;
 
@@ -36,6 +41,24 @@ pub struct EndpointConfig {
     #[educe(Debug(ignore))]
     handler: Arc<dyn PushWorkHandler>,
 
+    /// Most requests the instance serves at once, advertised to the routers which hold back the
+    /// requests beyond it. Unlimited if None.
+    #[builder(default)]
+    max_concurrent_requests: Option<u32>,
+
+    /// Also take requests from the endpoint's work queue, at most this many at once, for the
+    /// routers in [`crate::pipeline::RouterMode::Pull`]. Off if None.
+    #[builder(default)]
+    pull_capacity: Option<u32>,
+
+    /// What the instance runs, advertised to the routers
+    #[builder(default)]
+    description: Option<WorkerDescription>,
+
+    /// What an hour of a GPU of the instance costs, advertised to the routers for budget routing
+    #[builder(default)]
+    cost_per_gpu_hour: Option<f64>,
+
     /// Stats handler
     #[educe(Debug(ignore))]
     #[builder(default, private)]
@@ -55,12 +78,33 @@ impl EndpointConfigBuilder {
     }
 
     pub async fn start(self) -> Result<()> {
-        let (endpoint, lease, handler, stats_handler) = self.build_internal()?.dissolve();
+        let (
+            endpoint,
+            lease,
+            handler,
+            max_concurrent_requests,
+            pull_capacity,
+            description,
+            cost_per_gpu_hour,
+            stats_handler,
+        ) = self.build_internal()?.dissolve();
+        if max_concurrent_requests == Some(0) {
+            anyhow::bail!("max_concurrent_requests must be at least 1");
+        }
+        if pull_capacity == Some(0) {
+            anyhow::bail!("pull_capacity must be at least 1");
+        }
         let lease = lease.or(endpoint.drt().primary_lease());
         let lease_id = lease.as_ref().map(|l| l.id()).unwrap_or(0);
 
         tracing::debug!("Starting endpoint: {}", endpoint.etcd_path(lease_id));
 
+        endpoint
+            .drt()
+            .runtime()
+            .run_lifecycle_hooks(LifecycleStage::PreServe)
+            .await?;
+
         let service_name = endpoint.component.service_name();
 
         // acquire the registry lock
@@ -100,14 +144,48 @@ impl EndpointConfigBuilder {
             .map(|l| l.child_token())
             .unwrap_or_else(|| endpoint.drt().child_token());
 
+        let drain_token = CancellationToken::new();
+        let payload_key = endpoint
+            .drt()
+            .payload_key(endpoint.component.namespace.name());
+        let pull_endpoint = match pull_capacity {
+            Some(capacity) => {
+                let queue = WorkQueue::new(&endpoint).await?.subscribe().await?;
+                let pull_endpoint = PullEndpoint::builder()
+                    .service_handler(handler.clone())
+                    .cancellation_token(cancel_token.clone())
+                    .drain_token(drain_token.clone())
+                    .payload_key(payload_key.clone())
+                    .capacity(capacity)
+                    .build()
+                    .map_err(|e| anyhow::anyhow!("Failed to build pull endpoint: {e}"))?;
+                Some((pull_endpoint, queue))
+            }
+            None => None,
+        };
         let push_endpoint = PushEndpoint::builder()
             .service_handler(handler)
             .cancellation_token(cancel_token.clone())
+            .drain_token(drain_token.clone())
+            .payload_key(payload_key)
             .build()
             .map_err(|e| anyhow::anyhow!("Failed to build push endpoint: {e}"))?;
 
-        // launch in primary runtime
-        let task = tokio::spawn(push_endpoint.start(service_endpoint));
+        // launch in primary runtime, a drain waits for it to finish
+        let drain_guard = endpoint.drt().runtime().drain_guard();
+        let mut task = tokio::spawn(async move {
+            let _drain_guard = drain_guard;
+            match pull_endpoint {
+                Some((pull_endpoint, queue)) => {
+                    tokio::try_join!(
+                        push_endpoint.start(service_endpoint),
+                        pull_endpoint.start(Arc::new(queue))
+                    )?;
+                    Ok(())
+                }
+                None => push_endpoint.start(service_endpoint).await,
+            }
+        });
 
         // make the components service endpoint discovery in etcd
 
@@ -118,11 +196,40 @@ impl EndpointConfigBuilder {
             namespace: endpoint.component.namespace.name.clone(),
             instance_id: lease_id,
             transport: TransportType::NatsTcp(endpoint.subject_to(lease_id)),
+            region: endpoint.drt().locality().region.clone(),
+            zone: endpoint.drt().locality().zone.clone(),
+            max_concurrent_requests,
+            description,
+            cost_per_gpu_hour,
         };
 
         let info = serde_json::to_vec_pretty(&info)?;
 
         if let Some(etcd_client) = &endpoint.component.drt.etcd_client {
+            // A standby worker serves, but only becomes routable once activated
+            if endpoint.drt().is_standby() {
+                let activated = match standby::stand_by(
+                    endpoint.drt(),
+                    etcd_client,
+                    &endpoint.etcd_path(lease_id),
+                    info.clone(),
+                    lease_id,
+                    &cancel_token,
+                )
+                .await
+                {
+                    Ok(activated) => activated,
+                    Err(err) => {
+                        tracing::error!("Failed to register standby endpoint: {err:?}");
+                        cancel_token.cancel();
+                        return Err(error!("Failed to register standby endpoint"));
+                    }
+                };
+                if !activated {
+                    drain_token.cancel();
+                    return task.await?;
+                }
+            }
             if let Err(e) = etcd_client
                 .kv_create(endpoint.etcd_path(lease_id), info, Some(lease_id))
                 .await
@@ -132,6 +239,37 @@ impl EndpointConfigBuilder {
                 return Err(error!("Failed to register discoverable service"));
             }
         }
+        if let Err(err) = endpoint
+            .drt()
+            .runtime()
+            .run_lifecycle_hooks(LifecycleStage::PostRegister)
+            .await
+        {
+            cancel_token.cancel();
+            return Err(err);
+        }
+        crate::error_reporting::set_context(
+            &endpoint.component.namespace.name,
+            &endpoint.component.name,
+            lease_id,
+        );
+
+        let runtime_drain = endpoint.drt().runtime().drain_token();
+        tokio::select! {
+            result = &mut task => return result?,
+            _ = runtime_drain.cancelled() => {}
+        }
+
+        // Unregister first so that routers stop picking us, then stop taking requests
+        if let Some(etcd_client) = &endpoint.component.drt.etcd_client {
+            if let Err(err) = etcd_client
+                .kv_delete(endpoint.etcd_path(lease_id), None)
+                .await
+            {
+                tracing::warn!(%err, "Failed to unregister endpoint for draining");
+            }
+        }
+        drain_token.cancel();
         task.await??;
 
         Ok(())
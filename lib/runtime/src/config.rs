@@ -26,6 +26,12 @@ use validator::Validate;
 pub struct WorkerConfig {
     /// Grace shutdown period for http-service.
     pub graceful_shutdown_timeout: u64,
+
+    /// How long to wait for in-flight requests when draining, in seconds. See [`crate::drain`].
+    pub drain_timeout: u64,
+
+    /// Start in standby, registered but not routable until activated. See [`crate::standby`].
+    pub standby: bool,
 }
 
 impl WorkerConfig {
@@ -49,6 +55,8 @@ impl Default for WorkerConfig {
             } else {
                 30 // Release build: 30 seconds
             },
+            drain_timeout: 30,
+            standby: false,
         }
     }
 }
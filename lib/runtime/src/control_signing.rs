@@ -0,0 +1,270 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sign control-plane messages, so that only the holders of the cluster key can drive workers.
+//!
+//! Any process connected to NATS can otherwise drain a worker, activate a standby one or send
+//! control messages to a component. With a cluster key, the sender of such a message adds a
+//! keyed BLAKE3 MAC of the subject, a timestamp, a random nonce and the payload in NATS headers.
+//! The worker checks it, and rejects messages that are unsigned, signed with another key, signed
+//! for another subject, older than [`MAX_AGE`], or seen before.
+//!
+//! Configured from the environment when the [`crate::DistributedRuntime`] is created:
+//! - `DYN_CONTROL_SIGNING_KEY_FILE`: file with the 32 byte cluster key in hex, e.g. the output of
+//!   `openssl rand -hex 32`.
+//!
+//! Every process of the cluster that sends or serves control messages must have the key. A worker
+//! without a key takes messages signed or not, as before. The clocks of the cluster must agree
+//! within [`MAX_AGE`].
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_nats::HeaderMap;
+use figment::{
+    providers::{Env, Serialized},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::payload_encryption::parse_hex_key;
+use crate::{error, Result};
+
+/// How old a message may be, and how far in the future, before it is rejected. The nonces are
+/// remembered that long.
+pub const MAX_AGE: Duration = Duration::from_secs(30);
+
+const TIMESTAMP_HEADER: &str = "Dyn-Control-Timestamp";
+const NONCE_HEADER: &str = "Dyn-Control-Nonce";
+const SIGNATURE_HEADER: &str = "Dyn-Control-Signature";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ControlSigningConfig {
+    /// File with the cluster key, see the [module docs](self)
+    pub key_file: Option<PathBuf>,
+}
+
+impl ControlSigningConfig {
+    /// Read the configuration from `DYN_CONTROL_SIGNING_*` environment variables.
+    /// Panics on invalid configuration.
+    pub fn from_settings() -> Self {
+        Figment::new()
+            .merge(Serialized::defaults(Self::default()))
+            .merge(Env::prefixed("DYN_CONTROL_SIGNING_"))
+            .extract()
+            .unwrap() // safety: Called on startup, so panic is reasonable
+    }
+}
+
+/// Signs and checks control messages with the cluster key
+pub struct ControlSigner {
+    key: [u8; 32],
+    /// The nonces of the messages accepted within [`MAX_AGE`], to their timestamp in ms
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl std::fmt::Debug for ControlSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never the key
+        f.debug_struct("ControlSigner").finish_non_exhaustive()
+    }
+}
+
+impl ControlSigner {
+    pub fn new(key: [u8; 32]) -> Self {
+        ControlSigner {
+            key,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The signer of the key `config` points to, None if it has no key file
+    pub fn load(config: &ControlSigningConfig) -> Result<Option<Self>> {
+        let Some(key) = Self::load_key(config)? else {
+            return Ok(None);
+        };
+        tracing::info!("Signing control messages");
+        Ok(Some(ControlSigner::new(key)))
+    }
+
+    /// The cluster key `config` points to, None if it has no key file. Other uses of the key must
+    /// derive their own from it with [`blake3::derive_key`].
+    pub fn load_key(config: &ControlSigningConfig) -> Result<Option<[u8; 32]>> {
+        let Some(path) = &config.key_file else {
+            return Ok(None);
+        };
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            error!(
+                "Failed reading control signing key from {}: {err}",
+                path.display()
+            )
+        })?;
+        let key = parse_hex_key(contents.trim()).ok_or_else(|| {
+            error!(
+                "The control signing key in {} must be 64 hex digits",
+                path.display()
+            )
+        })?;
+        Ok(Some(key))
+    }
+
+    fn mac(&self, subject: &str, timestamp: u64, nonce: &str, payload: &[u8]) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update(subject.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(timestamp.to_string().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(nonce.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(payload);
+        hasher.finalize()
+    }
+
+    /// The headers that sign `payload` for `subject`
+    pub fn sign(&self, subject: &str, payload: &[u8]) -> HeaderMap {
+        self.sign_at(
+            subject,
+            payload,
+            unix_millis(),
+            &uuid::Uuid::new_v4().to_string(),
+        )
+    }
+
+    fn sign_at(&self, subject: &str, payload: &[u8], timestamp: u64, nonce: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, timestamp.to_string().as_str());
+        headers.insert(NONCE_HEADER, nonce);
+        headers.insert(
+            SIGNATURE_HEADER,
+            self.mac(subject, timestamp, nonce, payload)
+                .to_hex()
+                .as_str(),
+        );
+        headers
+    }
+
+    /// Check that `headers` sign `payload` for `subject`, and that the message is new
+    pub fn verify(&self, subject: &str, headers: Option<&HeaderMap>, payload: &[u8]) -> Result<()> {
+        self.verify_at(subject, headers, payload, unix_millis())
+    }
+
+    fn verify_at(
+        &self,
+        subject: &str,
+        headers: Option<&HeaderMap>,
+        payload: &[u8],
+        now: u64,
+    ) -> Result<()> {
+        let header = |name: &str| {
+            headers
+                .and_then(|headers| headers.get(name))
+                .map(|v| v.as_str())
+        };
+        let (Some(timestamp), Some(nonce), Some(signature)) = (
+            header(TIMESTAMP_HEADER),
+            header(NONCE_HEADER),
+            header(SIGNATURE_HEADER),
+        ) else {
+            return Err(error!("Control message on {subject} is not signed"));
+        };
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| error!("Control message on {subject} has an invalid timestamp"))?;
+        let signature = blake3::Hash::from_hex(signature)
+            .map_err(|_| error!("Control message on {subject} has an invalid signature"))?;
+        // Constant time comparison
+        if signature != self.mac(subject, timestamp, nonce, payload) {
+            return Err(error!(
+                "Control message on {subject} has a wrong signature, is the key the same everywhere?"
+            ));
+        }
+        let max_age = MAX_AGE.as_millis() as u64;
+        if timestamp.abs_diff(now) > max_age {
+            return Err(error!(
+                "Control message on {subject} is {}ms old, more than {MAX_AGE:?}",
+                now as i128 - timestamp as i128
+            ));
+        }
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, seen_at| seen_at.abs_diff(now) <= max_age);
+        if seen.insert(nonce.to_string(), timestamp).is_some() {
+            return Err(error!("Control message on {subject} is a replay"));
+        }
+        Ok(())
+    }
+}
+
+/// The headers that sign `payload` for `subject` with `signer`, none if there is no key
+pub fn sign(signer: Option<&ControlSigner>, subject: &str, payload: &[u8]) -> HeaderMap {
+    match signer {
+        Some(signer) => signer.sign(subject, payload),
+        None => HeaderMap::new(),
+    }
+}
+
+/// Check a control message with `signer`. Without a key, every message is taken.
+pub fn verify(
+    signer: Option<&ControlSigner>,
+    subject: &str,
+    headers: Option<&HeaderMap>,
+    payload: &[u8],
+) -> Result<()> {
+    match signer {
+        Some(signer) => signer.verify(subject, headers, payload),
+        None => Ok(()),
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBJECT: &str = "dynamo.drain.1f";
+
+    #[test]
+    fn test_sign_verify() {
+        let signer = ControlSigner::new([7; 32]);
+        let now = 1_750_000_000_000;
+        let headers = signer.sign_at(SUBJECT, b"{}", now, "nonce-1");
+        signer
+            .verify_at(SUBJECT, Some(&headers), b"{}", now + 1000)
+            .unwrap();
+
+        // The same message again
+        assert!(signer
+            .verify_at(SUBJECT, Some(&headers), b"{}", now + 2000)
+            .is_err());
+
+        // Another payload, subject or key, no signature, too old
+        let headers = signer.sign_at(SUBJECT, b"{}", now, "nonce-2");
+        assert!(signer
+            .verify_at(SUBJECT, Some(&headers), b"{\"kill\": true}", now)
+            .is_err());
+        assert!(signer
+            .verify_at("dynamo.drain.2f", Some(&headers), b"{}", now)
+            .is_err());
+        assert!(ControlSigner::new([8; 32])
+            .verify_at(SUBJECT, Some(&headers), b"{}", now)
+            .is_err());
+        assert!(signer.verify_at(SUBJECT, None, b"{}", now).is_err());
+        let stale = now + MAX_AGE.as_millis() as u64 + 1;
+        assert!(signer
+            .verify_at(SUBJECT, Some(&headers), b"{}", stale)
+            .is_err());
+        signer
+            .verify_at(SUBJECT, Some(&headers), b"{}", now)
+            .unwrap();
+
+        // Without a key anything goes
+        verify(None, SUBJECT, None, b"{}").unwrap();
+    }
+}
@@ -16,8 +16,17 @@
 pub use crate::component::Component;
 use crate::{
     component::{self, ComponentBuilder, Endpoint, InstanceSource, Namespace},
+    config::WorkerConfig,
+    control_signing::{ControlSigner, ControlSigningConfig},
     discovery::DiscoveryClient,
+    drain,
+    error_reporting::{self, ErrorReportingConfig},
+    fault_injection,
+    lifecycle::LifecycleStage,
+    locality::{Locality, LocalityConfig, ZonePolicy},
+    payload_encryption::{PayloadEncryptionConfig, PayloadKey, PayloadKeys},
     service::ServiceClient,
+    standby,
     transports::{etcd, nats, tcp},
     ErrorContext,
 };
@@ -33,7 +42,22 @@ use tokio_util::sync::CancellationToken;
 impl DistributedRuntime {
     pub async fn new(runtime: Runtime, config: DistributedConfig) -> Result<Self> {
         let secondary = runtime.secondary();
-        let (etcd_config, nats_config, is_static) = config.dissolve();
+        let (
+            etcd_config,
+            nats_config,
+            is_static,
+            error_reporting_config,
+            locality_config,
+            standby,
+            payload_encryption_config,
+            control_signing_config,
+        ) = config.dissolve();
+        let payload_keys = PayloadKeys::load(&payload_encryption_config)?;
+        let control_signer = ControlSigner::load(&control_signing_config)?.map(Arc::new);
+
+        if let Err(err) = error_reporting::init(&error_reporting_config, &secondary) {
+            tracing::warn!(%err, "Error reporting disabled, invalid configuration");
+        }
 
         let runtime_clone = runtime.clone();
 
@@ -55,6 +79,10 @@ impl DistributedRuntime {
             )
         };
 
+        if let Err(err) = fault_injection::init(etcd_client.as_ref()).await {
+            tracing::error!(%err, "Fault injection not started");
+        }
+
         let nats_client = secondary
             .spawn(async move {
                 let client = nats_config.clone().connect().await.context(format!(
@@ -65,7 +93,22 @@ impl DistributedRuntime {
             })
             .await??;
 
-        Ok(Self {
+        let locality = locality_config.resolve().await;
+        if locality.zone.is_some() {
+            tracing::info!(
+                region = locality.region.as_deref().unwrap_or("unknown"),
+                zone = locality.zone.as_deref().unwrap_or("unknown"),
+                policy = ?locality_config.policy,
+                "Zone aware routing"
+            );
+        }
+
+        let activated = CancellationToken::new();
+        if !standby || is_static {
+            activated.cancel();
+        }
+
+        let drt = Self {
             runtime,
             etcd_client,
             nats_client,
@@ -73,7 +116,35 @@ impl DistributedRuntime {
             component_registry: component::Registry::new(),
             is_static,
             instance_sources: Arc::new(Mutex::new(HashMap::new())),
-        })
+            locality: Arc::new(locality),
+            zone_policy: locality_config.policy,
+            activated,
+            payload_keys: Arc::new(payload_keys),
+            control_signer,
+        };
+
+        // The instance id is the primary lease id, static workers can't be asked to drain
+        if let Some(lease) = drt.primary_lease() {
+            drt.runtime.tasks().spawn_on(
+                "drain requests",
+                drain::serve_drain_requests(
+                    drt.runtime.clone(),
+                    drt.nats_client.client().clone(),
+                    drt.control_signer(),
+                    lease.id(),
+                ),
+                &secondary,
+            );
+            if drt.is_standby() {
+                drt.runtime.tasks().spawn_on(
+                    "activation requests",
+                    standby::serve_activation_requests(drt.clone(), lease.id()),
+                    &secondary,
+                );
+            }
+        }
+
+        Ok(drt)
     }
 
     pub async fn from_settings(runtime: Runtime) -> Result<Self> {
@@ -95,6 +166,15 @@ impl DistributedRuntime {
         self.runtime.primary_token()
     }
 
+    /// See [`Runtime::add_lifecycle_hook`]
+    pub fn add_lifecycle_hook<F, Fut>(&self, stage: LifecycleStage, hook: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        self.runtime.add_lifecycle_hook(stage, hook)
+    }
+
     /// The etcd lease all our components will be attached to.
     /// Not available for static workers.
     pub fn primary_lease(&self) -> Option<etcd::Lease> {
@@ -159,6 +239,26 @@ impl DistributedRuntime {
         self.runtime.child_token()
     }
 
+    /// The region and zone we run in
+    pub fn locality(&self) -> &Locality {
+        &self.locality
+    }
+
+    /// Which instances our routers may pick, given their zone
+    pub fn zone_policy(&self) -> ZonePolicy {
+        self.zone_policy
+    }
+
+    /// The key to encrypt the payloads of `namespace` with, None if they are sent in the clear
+    pub fn payload_key(&self, namespace: &str) -> Option<Arc<PayloadKey>> {
+        self.payload_keys.get(namespace)
+    }
+
+    /// Signs and checks control messages, None if the cluster has no key
+    pub fn control_signer(&self) -> Option<Arc<ControlSigner>> {
+        self.control_signer.clone()
+    }
+
     pub fn instance_sources(&self) -> Arc<Mutex<HashMap<Endpoint, Weak<InstanceSource>>>> {
         self.instance_sources.clone()
     }
@@ -169,6 +269,12 @@ pub struct DistributedConfig {
     pub etcd_config: etcd::ClientOptions,
     pub nats_config: nats::ClientOptions,
     pub is_static: bool,
+    pub error_reporting_config: ErrorReportingConfig,
+    pub locality_config: LocalityConfig,
+    /// Start in standby, see [`standby`]
+    pub standby: bool,
+    pub payload_encryption_config: PayloadEncryptionConfig,
+    pub control_signing_config: ControlSigningConfig,
 }
 
 impl DistributedConfig {
@@ -177,6 +283,11 @@ impl DistributedConfig {
             etcd_config: etcd::ClientOptions::default(),
             nats_config: nats::ClientOptions::default(),
             is_static,
+            error_reporting_config: ErrorReportingConfig::from_settings(),
+            locality_config: LocalityConfig::from_settings(),
+            standby: WorkerConfig::from_settings().standby,
+            payload_encryption_config: PayloadEncryptionConfig::from_settings(),
+            control_signing_config: ControlSigningConfig::from_settings(),
         }
     }
 
@@ -185,6 +296,11 @@ impl DistributedConfig {
             etcd_config: etcd::ClientOptions::default(),
             nats_config: nats::ClientOptions::default(),
             is_static: false,
+            error_reporting_config: ErrorReportingConfig::from_settings(),
+            locality_config: LocalityConfig::from_settings(),
+            standby: false,
+            payload_encryption_config: PayloadEncryptionConfig::from_settings(),
+            control_signing_config: ControlSigningConfig::from_settings(),
         };
 
         config.etcd_config.attach_lease = false;
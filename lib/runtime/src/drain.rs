@@ -0,0 +1,202 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Graceful drain takes a worker out of service without dropping the requests it is serving.
+//!
+//! Draining starts with [`Runtime::drain`](crate::Runtime::drain). A [`Worker`](crate::Worker)
+//! calls it on `SIGTERM` or `Ctrl+C`, and a distributed runtime when another process asks it to
+//! with [`DistributedRuntime::request_drain`]. Every endpoint of the runtime then:
+//!
+//! 1. removes its instance from etcd, so that routers stop sending it requests,
+//! 2. stops its NATS service endpoint, so it accepts no new requests,
+//! 3. waits for the requests in flight to finish.
+//!
+//! The drain is done once every endpoint stopped, or at the deadline. The worker then shuts down,
+//! which ends the requests still running. The deadline is `DYN_WORKER_DRAIN_TIMEOUT` seconds, 30 by
+//! default.
+//!
+//! With a cluster key, drain requests must be signed, see [`crate::control_signing`].
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::WorkerConfig;
+use crate::control_signing::{self, ControlSigner};
+use crate::{DistributedRuntime, Result, Runtime};
+
+/// How long to wait for an instance to confirm it started draining
+const DRAIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Drain state shared by the endpoints of a [`Runtime`]
+#[derive(Debug)]
+pub(crate) struct Drain {
+    /// Cancelled when draining starts. Separate from the primary token, shutting down is not
+    /// draining.
+    token: CancellationToken,
+
+    /// Number of endpoints still serving
+    active: watch::Sender<usize>,
+}
+
+impl Drain {
+    pub(crate) fn new() -> Self {
+        Drain {
+            token: CancellationToken::new(),
+            active: watch::channel(0).0,
+        }
+    }
+
+    pub(crate) fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Count an endpoint as serving until the guard is dropped
+    pub(crate) fn guard(self: &Arc<Self>) -> DrainGuard {
+        self.active.send_modify(|active| *active += 1);
+        DrainGuard(self.clone())
+    }
+
+    /// Start draining and wait up to `timeout` for every endpoint to stop. Returns false if some
+    /// were still serving at the deadline.
+    pub(crate) async fn drain(&self, timeout: Duration) -> bool {
+        self.token.cancel();
+        let mut active = self.active.subscribe();
+        tokio::time::timeout(timeout, active.wait_for(|active| *active == 0))
+            .await
+            .is_ok()
+    }
+}
+
+pub(crate) struct DrainGuard(Arc<Drain>);
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.0.active.send_modify(|active| *active -= 1);
+    }
+}
+
+fn drain_subject(instance_id: i64) -> String {
+    format!("dynamo.drain.{instance_id:x}")
+}
+
+impl DistributedRuntime {
+    /// Ask the instance `instance_id` to drain and shut down. Returns once it started draining,
+    /// not once it is done.
+    pub async fn request_drain(&self, instance_id: i64) -> Result<()> {
+        let subject = drain_subject(instance_id);
+        let headers = control_signing::sign(self.control_signer().as_deref(), &subject, &[]);
+        let request = async_nats::Request::new()
+            .headers(headers)
+            .timeout(Some(DRAIN_REQUEST_TIMEOUT));
+        let reply = self
+            .nats_client()
+            .client()
+            .send_request(subject.clone(), request)
+            .await
+            .map_err(|err| match err.kind() {
+                async_nats::RequestErrorKind::TimedOut => anyhow::anyhow!(
+                    "Instance {instance_id:x} did not confirm draining within {DRAIN_REQUEST_TIMEOUT:?}"
+                ),
+                _ => anyhow::anyhow!("Drain request on {subject} failed: {err}"),
+            })?;
+        // An empty reply confirms, otherwise it says why the request was refused
+        if !reply.payload.is_empty() {
+            anyhow::bail!(
+                "Instance {instance_id:x} refused to drain: {}",
+                String::from_utf8_lossy(&reply.payload)
+            );
+        }
+        tracing::debug!(subject, "Drain request confirmed");
+        Ok(())
+    }
+}
+
+/// Answer the drain requests sent to this instance, the one holding the primary lease `lease_id`.
+/// On the first one that `signer` accepts, the runtime drains and shuts down.
+pub(crate) async fn serve_drain_requests(
+    runtime: Runtime,
+    nats: async_nats::Client,
+    signer: Option<Arc<ControlSigner>>,
+    lease_id: i64,
+) -> Result<()> {
+    let subject = drain_subject(lease_id);
+    let mut requests = nats.subscribe(subject.clone()).await?;
+    let cancel_token = runtime.child_token();
+    loop {
+        let request = tokio::select! {
+            _ = cancel_token.cancelled() => return Ok(()),
+            request = requests.next() => match request {
+                Some(request) => request,
+                None => anyhow::bail!("Drain requests subscription closed"),
+            },
+        };
+        let verified = control_signing::verify(
+            signer.as_deref(),
+            &subject,
+            request.headers.as_ref(),
+            &request.payload,
+        );
+        let reply = match &verified {
+            Ok(()) => Bytes::new(),
+            Err(err) => {
+                tracing::warn!(%err, "Drain request refused");
+                Bytes::from(err.to_string())
+            }
+        };
+        if let Some(reply_to) = request.reply {
+            if let Err(err) = nats.publish(reply_to, reply).await {
+                tracing::warn!(%err, "Failed confirming drain request");
+            }
+        }
+        if verified.is_ok() {
+            break;
+        }
+    }
+
+    let timeout = Duration::from_secs(WorkerConfig::from_settings().drain_timeout);
+    tracing::info!("Drain requested, draining for up to {timeout:?}");
+    if !runtime.drain(timeout).await {
+        tracing::warn!("Requests still in flight after {timeout:?}, shutting down anyway");
+    }
+    runtime.shutdown();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_endpoints() {
+        let drain = Arc::new(Drain::new());
+        let first = drain.guard();
+        let second = drain.guard();
+        let token = drain.token();
+
+        let waiting = {
+            let drain = drain.clone();
+            tokio::spawn(async move { drain.drain(Duration::from_secs(5)).await })
+        };
+        token.cancelled().await;
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        drop(second);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_drain_deadline() {
+        let drain = Arc::new(Drain::new());
+        let serving = drain.guard();
+        assert!(!drain.drain(Duration::from_millis(10)).await);
+        // Nothing left to wait for
+        drop(serving);
+        assert!(drain.drain(Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,446 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Send panics, subprocess crashes and error logs to an external error tracker.
+//!
+//! Configured from the environment when the [`crate::DistributedRuntime`] is created:
+//! - `DYN_ERROR_REPORTING_WEBHOOK_URL`: POST each report as JSON to this URL.
+//! - `DYN_ERROR_REPORTING_SENTRY_DSN`: Send each report as an event to Sentry, or anything that
+//!   speaks the Sentry store API.
+//! - `DYN_ERROR_REPORTING_CAPTURE_ERROR_LOGS`: Also report `tracing::error!` events. Default true.
+//!
+//! Other sinks can be added with [`add_sink`]. Reports carry the namespace, component and
+//! instance id of the worker once it has started an endpoint. Identical reports are only sent
+//! once a minute so an error in a hot loop does not flood the tracker.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use figment::{
+    providers::{Env, Serialized},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Don't send the same report more often than this
+const DEDUPE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long a sink gets to deliver a report
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorReportingConfig {
+    /// POST reports as JSON to this URL
+    pub webhook_url: Option<String>,
+
+    /// Sentry DSN, `https://<key>@<host>/<project>`
+    pub sentry_dsn: Option<String>,
+
+    /// Report `tracing::error!` events, not only panics and crashes
+    pub capture_error_logs: bool,
+}
+
+impl Default for ErrorReportingConfig {
+    fn default() -> Self {
+        ErrorReportingConfig {
+            webhook_url: None,
+            sentry_dsn: None,
+            capture_error_logs: true,
+        }
+    }
+}
+
+impl ErrorReportingConfig {
+    /// Read the configuration from `DYN_ERROR_REPORTING_*` environment variables.
+    /// Panics on invalid configuration.
+    pub fn from_settings() -> Self {
+        Figment::new()
+            .merge(Serialized::defaults(Self::default()))
+            .merge(Env::prefixed("DYN_ERROR_REPORTING_"))
+            .extract()
+            .unwrap() // safety: Called on startup, so panic is reasonable
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    Panic,
+    SubprocessCrash,
+    Error,
+}
+
+/// Where the report came from
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReportContext {
+    pub namespace: Option<String>,
+    pub component: Option<String>,
+    pub instance_id: Option<i64>,
+    pub pid: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub kind: ReportKind,
+    pub message: String,
+    /// `file:line` if known
+    pub location: Option<String>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub context: ReportContext,
+}
+
+/// Somewhere to send error reports
+#[async_trait]
+pub trait ErrorSink: Send + Sync {
+    async fn report(&self, report: &ErrorReport) -> anyhow::Result<()>;
+}
+
+struct Reporter {
+    tx: mpsc::UnboundedSender<ErrorReport>,
+    sinks: Arc<RwLock<Vec<Arc<dyn ErrorSink>>>>,
+    context: RwLock<ReportContext>,
+    capture_error_logs: bool,
+    /// Keyed by message only, so logging an error right after reporting it sends it once
+    last_sent: Mutex<HashMap<String, Instant>>,
+}
+
+impl Reporter {
+    fn build_report(
+        &self,
+        kind: ReportKind,
+        message: String,
+        location: Option<String>,
+    ) -> Option<ErrorReport> {
+        if self.sinks.read().unwrap().is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut last_sent = self.last_sent.lock().unwrap();
+        last_sent.retain(|_, t| now.duration_since(*t) < DEDUPE_WINDOW);
+        if last_sent.insert(message.clone(), now).is_some() {
+            return None;
+        }
+        drop(last_sent);
+        Some(ErrorReport {
+            kind,
+            message,
+            location,
+            timestamp: chrono::Utc::now(),
+            context: self.context.read().unwrap().clone(),
+        })
+    }
+}
+
+/// Start error reporting. Called by [`crate::DistributedRuntime::new`], later calls do nothing.
+pub fn init(config: &ErrorReportingConfig, handle: &tokio::runtime::Handle) -> anyhow::Result<()> {
+    if REPORTER.get().is_some() {
+        return Ok(());
+    }
+
+    let mut sinks: Vec<Arc<dyn ErrorSink>> = vec![];
+    if let Some(url) = &config.webhook_url {
+        sinks.push(Arc::new(WebhookSink::new(url)?));
+    }
+    if let Some(dsn) = &config.sentry_dsn {
+        sinks.push(Arc::new(SentrySink::new(dsn)?));
+    }
+    let sinks = Arc::new(RwLock::new(sinks));
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<ErrorReport>();
+    let reporter = Reporter {
+        tx,
+        sinks: sinks.clone(),
+        context: RwLock::new(ReportContext {
+            pid: std::process::id(),
+            ..Default::default()
+        }),
+        capture_error_logs: config.capture_error_logs,
+        last_sent: Mutex::new(HashMap::new()),
+    };
+    if REPORTER.set(reporter).is_err() {
+        // Another runtime got there first
+        return Ok(());
+    }
+
+    handle.spawn(async move {
+        while let Some(report) = rx.recv().await {
+            let current = sinks.read().unwrap().clone();
+            send_all(&current, &report).await;
+        }
+    });
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report_panic(info);
+        previous(info);
+    }));
+
+    Ok(())
+}
+
+/// Send reports to `sink` as well as the configured ones. Fails if [`init`] was not called.
+pub fn add_sink(sink: Arc<dyn ErrorSink>) -> anyhow::Result<()> {
+    let Some(reporter) = REPORTER.get() else {
+        anyhow::bail!("Error reporting is not initialized");
+    };
+    reporter.sinks.write().unwrap().push(sink);
+    Ok(())
+}
+
+/// Attach the worker's identity to future reports
+pub fn set_context(namespace: &str, component: &str, instance_id: i64) {
+    if let Some(reporter) = REPORTER.get() {
+        let mut context = reporter.context.write().unwrap();
+        context.namespace = Some(namespace.to_string());
+        context.component = Some(component.to_string());
+        context.instance_id = Some(instance_id);
+    }
+}
+
+/// Queue a report for the sinks. Does nothing if error reporting is not initialized.
+pub fn report(kind: ReportKind, message: impl Into<String>, location: Option<String>) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if let Some(report) = reporter.build_report(kind, message.into(), location) {
+        // Only fails if the runtime is shutting down
+        let _ = reporter.tx.send(report);
+    }
+}
+
+async fn send_all(sinks: &[Arc<dyn ErrorSink>], report: &ErrorReport) {
+    for sink in sinks {
+        match tokio::time::timeout(SEND_TIMEOUT, sink.report(report)).await {
+            Ok(Ok(())) => {}
+            // warn, not error, so we don't report our own failures
+            Ok(Err(err)) => tracing::warn!(%err, "Failed sending error report"),
+            Err(_) => tracing::warn!("Timeout sending error report"),
+        }
+    }
+}
+
+/// The process may be about to exit, so send the report before returning instead of queueing it.
+/// Uses a new thread because the panic could be on a runtime thread.
+fn report_panic(info: &std::panic::PanicHookInfo<'_>) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = info.payload().downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    };
+    let location = info
+        .location()
+        .map(|l| format!("{}:{}", l.file(), l.line()));
+    let Some(report) = reporter.build_report(ReportKind::Panic, message, location) else {
+        return;
+    };
+    let sinks = reporter.sinks.read().unwrap().clone();
+    let sender = std::thread::spawn(move || {
+        let Ok(rt) = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        else {
+            return;
+        };
+        rt.block_on(send_all(&sinks, &report));
+    });
+    let _ = sender.join();
+}
+
+/// Forwards `tracing::error!` events to the reporter. Installed by [`crate::logging::init`].
+pub(crate) struct ErrorReportingLayer;
+
+impl<S: Subscriber> Layer<S> for ErrorReportingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if *metadata.level() != Level::ERROR || metadata.target().starts_with(module_path!()) {
+            return;
+        }
+        let Some(reporter) = REPORTER.get() else {
+            return;
+        };
+        if !reporter.capture_error_logs {
+            return;
+        }
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let location = metadata
+            .file()
+            .map(|f| format!("{f}:{}", metadata.line().unwrap_or(0)));
+        report(ReportKind::Error, visitor.message, location);
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            let sep = if self.message.is_empty() { "" } else { " " };
+            self.message = format!("{}{sep}{}={value:?}", self.message, field.name());
+        }
+    }
+}
+
+fn http_client() -> anyhow::Result<reqwest::Client> {
+    Ok(reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?)
+}
+
+/// POST each [`ErrorReport`] as JSON
+pub struct WebhookSink {
+    url: url::Url,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(WebhookSink {
+            url: url.parse()?,
+            client: http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl ErrorSink for WebhookSink {
+    async fn report(&self, report: &ErrorReport) -> anyhow::Result<()> {
+        self.client
+            .post(self.url.clone())
+            .json(report)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Send each [`ErrorReport`] as an event to a Sentry compatible store endpoint
+pub struct SentrySink {
+    store_url: url::Url,
+    auth_header: String,
+    client: reqwest::Client,
+}
+
+impl SentrySink {
+    pub fn new(dsn: &str) -> anyhow::Result<Self> {
+        let (store_url, key) = parse_dsn(dsn)?;
+        Ok(SentrySink {
+            store_url,
+            auth_header: format!(
+                "Sentry sentry_version=7, sentry_client=dynamo/{}, sentry_key={key}",
+                env!("CARGO_PKG_VERSION")
+            ),
+            client: http_client()?,
+        })
+    }
+}
+
+/// Split a DSN `<scheme>://<key>@<host>[:port][/<path>]/<project>` into the store URL and key
+fn parse_dsn(dsn: &str) -> anyhow::Result<(url::Url, String)> {
+    let url: url::Url = dsn.parse()?;
+    let key = url.username();
+    if key.is_empty() {
+        anyhow::bail!("Sentry DSN is missing the public key");
+    }
+    let Some(host) = url.host_str() else {
+        anyhow::bail!("Sentry DSN is missing the host");
+    };
+    let path = url.path().trim_end_matches('/');
+    let Some((prefix, project)) = path.rsplit_once('/') else {
+        anyhow::bail!("Sentry DSN is missing the project id");
+    };
+    if project.is_empty() {
+        anyhow::bail!("Sentry DSN is missing the project id");
+    }
+    let port = url.port().map(|p| format!(":{p}")).unwrap_or_default();
+    let store_url = format!(
+        "{}://{host}{port}{prefix}/api/{project}/store/",
+        url.scheme()
+    )
+    .parse()?;
+    Ok((store_url, key.to_string()))
+}
+
+#[async_trait]
+impl ErrorSink for SentrySink {
+    async fn report(&self, report: &ErrorReport) -> anyhow::Result<()> {
+        let ctx = &report.context;
+        let event = serde_json::json!({
+            "event_id": uuid::Uuid::new_v4().simple().to_string(),
+            "timestamp": report.timestamp.to_rfc3339(),
+            "level": if report.kind == ReportKind::Error { "error" } else { "fatal" },
+            "platform": "other",
+            "culprit": report.location,
+            "message": { "formatted": report.message },
+            "tags": {
+                "kind": report.kind,
+                "namespace": ctx.namespace,
+                "component": ctx.component,
+                "instance_id": ctx.instance_id.map(|id| format!("{id:x}")),
+                "pid": ctx.pid.to_string(),
+            },
+        });
+        self.client
+            .post(self.store_url.clone())
+            .header("X-Sentry-Auth", &self.auth_header)
+            .json(&event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dsn() {
+        let (url, key) = parse_dsn("https://abc123@o1.ingest.sentry.io/42").unwrap();
+        assert_eq!(key, "abc123");
+        assert_eq!(url.as_str(), "https://o1.ingest.sentry.io/api/42/store/");
+
+        let (url, _) = parse_dsn("http://k@localhost:9000/sentry/7").unwrap();
+        assert_eq!(url.as_str(), "http://localhost:9000/sentry/api/7/store/");
+
+        assert!(parse_dsn("https://o1.ingest.sentry.io/42").is_err());
+        assert!(parse_dsn("https://abc@o1.ingest.sentry.io/").is_err());
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        temp_env::with_vars(
+            [
+                (
+                    "DYN_ERROR_REPORTING_WEBHOOK_URL",
+                    Some("http://hooks/errors"),
+                ),
+                ("DYN_ERROR_REPORTING_CAPTURE_ERROR_LOGS", Some("false")),
+            ],
+            || {
+                let config = ErrorReportingConfig::from_settings();
+                assert_eq!(config.webhook_url.as_deref(), Some("http://hooks/errors"));
+                assert!(config.sentry_dsn.is_none());
+                assert!(!config.capture_error_logs);
+            },
+        );
+    }
+}
@@ -0,0 +1,252 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fault injection for chaos testing routing and recovery in staging.
+//!
+//! Only active in builds with the `fault-injection` feature. Without it every check here is a
+//! no-op and [`set`] fails, so production builds can't inject faults by accident.
+//!
+//! The faults are described by a [`FaultConfig`] and can be changed while running:
+//! - at startup from the `DYN_FAULT_INJECTION` environment variable, as JSON,
+//! - in etcd, as JSON under [`FAULT_INJECTION_KEY`]. Every process connected to etcd follows it,
+//!   deleting the key stops injecting faults:
+//!
+//! ```text
+//! etcdctl put /dynamo/fault_injection '{"nats_drop_rate": 0.05, "etcd_delay_ms": 200}'
+//! etcdctl del /dynamo/fault_injection
+//! ```
+//!
+//! - from code with [`set`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::transports::etcd::{self, WatchEvent};
+use crate::{error, Result};
+
+/// etcd key holding the [`FaultConfig`] as JSON
+pub const FAULT_INJECTION_KEY: &str = "/dynamo/fault_injection";
+
+/// Environment variable with the initial [`FaultConfig`] as JSON
+pub const FAULT_INJECTION_ENV: &str = "DYN_FAULT_INJECTION";
+
+/// How often [`spawn_subprocess_killer`] rolls the dice
+const KILL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which faults to inject. Rates are probabilities between 0 and 1, the default injects nothing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// Fraction of outgoing NATS requests and events silently dropped
+    pub nats_drop_rate: f64,
+
+    /// Delay added to every etcd request
+    pub etcd_delay_ms: u64,
+
+    /// Chance each minute that an engine subprocess is killed with SIGKILL
+    pub subprocess_kill_rate: f64,
+
+    /// Fraction of published KV cache events corrupted before they are sent
+    pub kv_event_corrupt_rate: f64,
+}
+
+impl FaultConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, rate) in [
+            ("nats_drop_rate", self.nats_drop_rate),
+            ("subprocess_kill_rate", self.subprocess_kill_rate),
+            ("kv_event_corrupt_rate", self.kv_event_corrupt_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(error!(
+                    "Fault injection {name} must be between 0 and 1, got {rate}"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fast path: false unless some fault is configured
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static FAULTS: LazyLock<RwLock<FaultConfig>> = LazyLock::new(Default::default);
+
+/// Is this build able to inject faults
+pub const fn is_supported() -> bool {
+    cfg!(feature = "fault-injection")
+}
+
+/// Replace the faults being injected
+pub fn set(config: FaultConfig) -> Result<()> {
+    if !is_supported() {
+        return Err(error!(
+            "Fault injection requires building with the 'fault-injection' feature"
+        ));
+    }
+    config.validate()?;
+    let active = config != FaultConfig::default();
+    if active {
+        tracing::warn!(?config, "Injecting faults");
+    } else {
+        tracing::info!("Fault injection stopped");
+    }
+    *FAULTS.write().unwrap() = config;
+    ACTIVE.store(active, Ordering::Release);
+    Ok(())
+}
+
+/// The faults being injected
+pub fn current() -> FaultConfig {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return FaultConfig::default();
+    }
+    FAULTS.read().unwrap().clone()
+}
+
+/// Load [`FAULT_INJECTION_ENV`], then follow [`FAULT_INJECTION_KEY`] in etcd if we have a client.
+/// Does nothing in builds without the feature.
+pub(crate) async fn init(etcd_client: Option<&etcd::Client>) -> Result<()> {
+    if !is_supported() {
+        return Ok(());
+    }
+    if let Ok(json) = std::env::var(FAULT_INJECTION_ENV) {
+        let config = serde_json::from_str(&json)
+            .map_err(|err| error!("Invalid {FAULT_INJECTION_ENV}: {err}"))?;
+        set(config)?;
+    }
+    let Some(etcd_client) = etcd_client else {
+        return Ok(());
+    };
+    let watcher = etcd_client
+        .kv_get_and_watch_prefix(FAULT_INJECTION_KEY)
+        .await?;
+    let (_prefix, _watcher, mut receiver) = watcher.dissolve();
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            let result = match event {
+                WatchEvent::Put(kv) => serde_json::from_slice(kv.value())
+                    .map_err(|err| error!("Invalid fault injection config in etcd: {err}"))
+                    .and_then(set),
+                WatchEvent::Delete(_) => set(FaultConfig::default()),
+            };
+            if let Err(err) = result {
+                tracing::error!(%err, "Fault injection config not applied");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+/// Should this outgoing NATS message be dropped
+pub fn drop_nats_message(subject: &str) -> bool {
+    if !is_supported() || !ACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+    let drop = roll(FAULTS.read().unwrap().nats_drop_rate);
+    if drop {
+        tracing::debug!(subject, "Fault injection: dropping NATS message");
+    }
+    drop
+}
+
+/// Wait before an etcd request, if configured
+pub async fn etcd_delay() {
+    if !is_supported() || !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    let delay_ms = FAULTS.read().unwrap().etcd_delay_ms;
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+}
+
+/// Should this KV cache event be corrupted. The caller knows how.
+pub fn corrupt_kv_event() -> bool {
+    if !is_supported() || !ACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+    let corrupt = roll(FAULTS.read().unwrap().kv_event_corrupt_rate);
+    if corrupt {
+        tracing::debug!("Fault injection: corrupting KV event");
+    }
+    corrupt
+}
+
+/// Randomly kill process `pid` at the configured rate, until `cancel_token` is cancelled.
+/// Does nothing in builds without the feature.
+pub fn spawn_subprocess_killer(pid: u32, cancel_token: CancellationToken) {
+    if !is_supported() {
+        return;
+    }
+    // Rate is per minute
+    let per_check = KILL_CHECK_INTERVAL.as_secs_f64() / 60.0;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => return,
+                _ = tokio::time::sleep(KILL_CHECK_INTERVAL) => {}
+            }
+            if !roll(current().subprocess_kill_rate * per_check) {
+                continue;
+            }
+            tracing::warn!(pid, "Fault injection: killing subprocess");
+            let pid = nix::unistd::Pid::from_raw(pid as i32);
+            if let Err(err) = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL) {
+                tracing::error!(%err, "Fault injection: failed to kill subprocess");
+            }
+            return;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config: FaultConfig = serde_json::from_str(r#"{"nats_drop_rate": 0.5}"#).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.etcd_delay_ms, 0);
+
+        let config = FaultConfig {
+            kv_event_corrupt_rate: 1.5,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn test_set() {
+        set(FaultConfig {
+            kv_event_corrupt_rate: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(corrupt_kv_event());
+        assert!(!drop_nats_message("test"));
+
+        set(FaultConfig::default()).unwrap();
+        assert!(!corrupt_kv_event());
+    }
+
+    #[cfg(not(feature = "fault-injection"))]
+    #[test]
+    fn test_unsupported() {
+        assert!(set(FaultConfig {
+            nats_drop_rate: 1.0,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(!drop_nats_message("test"));
+    }
+}
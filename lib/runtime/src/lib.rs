@@ -34,9 +34,18 @@ mod config;
 pub use config::RuntimeConfig;
 
 pub mod component;
+pub mod control_signing;
 pub mod discovery;
+pub mod drain;
 pub mod engine;
+pub mod error_reporting;
+pub mod fault_injection;
+pub mod lifecycle;
+pub mod locality;
 pub mod logging;
+pub mod observability;
+pub mod otel;
+pub mod payload_encryption;
 pub mod pipeline;
 pub mod prelude;
 pub mod protocols;
@@ -44,6 +53,8 @@ pub mod runnable;
 pub mod runtime;
 pub mod service;
 pub mod slug;
+pub mod standby;
+pub mod tasks;
 pub mod traits;
 pub mod transports;
 pub mod utils;
@@ -70,6 +81,9 @@ pub struct Runtime {
     primary: RuntimeType,
     secondary: RuntimeType,
     cancellation_token: CancellationToken,
+    lifecycle: Arc<lifecycle::LifecycleHooks>,
+    drain: Arc<drain::Drain>,
+    tasks: tasks::TaskRegistry,
 }
 
 /// Distributed [Runtime] which provides access to shared resources across the cluster, this includes
@@ -96,4 +110,17 @@ pub struct DistributedRuntime {
     is_static: bool,
 
     instance_sources: Arc<Mutex<HashMap<Endpoint, Weak<InstanceSource>>>>,
+
+    // Where we run, and which instances our routers prefer because of it
+    locality: Arc<locality::Locality>,
+    zone_policy: locality::ZonePolicy,
+
+    // Cancelled once the worker is active, see [`standby`]
+    activated: CancellationToken,
+
+    // The keys of the namespaces whose payloads are encrypted, see [`payload_encryption`]
+    payload_keys: Arc<payload_encryption::PayloadKeys>,
+
+    // Signs and checks control messages, None without a cluster key, see [`control_signing`]
+    control_signer: Option<Arc<control_signing::ControlSigner>>,
 }
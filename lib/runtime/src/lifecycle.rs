@@ -0,0 +1,206 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lifecycle hooks let applications embedding dynamo run their own async code at fixed points of
+//! the [`Runtime`](crate::Runtime) lifecycle, for example to open a database pool before the first
+//! request is served, or to release a GPU lock once everything has stopped.
+//!
+//! Register hooks with [`Runtime::add_lifecycle_hook`](crate::Runtime::add_lifecycle_hook). Each
+//! [`LifecycleStage`] runs once per runtime:
+//!
+//! - [`LifecycleStage::PreServe`]: before the first endpoint starts handling requests.
+//! - [`LifecycleStage::PostRegister`]: after the first endpoint is registered in etcd and clients
+//!   can discover it.
+//! - [`LifecycleStage::PreDrain`]: when graceful shutdown starts, before in-flight requests are
+//!   drained.
+//! - [`LifecycleStage::PostShutdown`]: after the application returned, or the graceful shutdown
+//!   timed out.
+//!
+//! Startup hooks run in the order they were registered, shutdown hooks in reverse order, so that
+//! resources are released in the opposite order they were acquired. A startup hook that fails
+//! stops the endpoint from starting. Shutdown hooks that fail are logged and the remaining hooks
+//! still run.
+//!
+//! [`Worker`](crate::Worker) runs the shutdown stages. Applications that manage their own runtime
+//! call [`Runtime::run_lifecycle_hooks`](crate::Runtime::run_lifecycle_hooks) themselves.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+use futures::Future;
+use tokio::sync::OnceCell;
+
+use crate::{error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LifecycleStage {
+    PreServe,
+    PostRegister,
+    PreDrain,
+    PostShutdown,
+}
+
+impl LifecycleStage {
+    fn is_shutdown(&self) -> bool {
+        matches!(
+            self,
+            LifecycleStage::PreDrain | LifecycleStage::PostShutdown
+        )
+    }
+}
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, Result<()>> + Send>;
+
+pub(crate) struct LifecycleHooks {
+    /// Hooks not run yet. The stage's entry is removed when it runs.
+    pending: Mutex<HashMap<LifecycleStage, Vec<Hook>>>,
+    /// Outcome of each stage once it ran, shared by concurrent callers
+    ran: HashMap<LifecycleStage, OnceCell<std::result::Result<(), String>>>,
+}
+
+impl std::fmt::Debug for LifecycleHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pending = self.pending.lock().unwrap();
+        let counts: HashMap<_, _> = pending.iter().map(|(s, h)| (*s, h.len())).collect();
+        f.debug_struct("LifecycleHooks")
+            .field("pending", &counts)
+            .finish()
+    }
+}
+
+impl LifecycleHooks {
+    pub(crate) fn new() -> Self {
+        let stages = [
+            LifecycleStage::PreServe,
+            LifecycleStage::PostRegister,
+            LifecycleStage::PreDrain,
+            LifecycleStage::PostShutdown,
+        ];
+        LifecycleHooks {
+            pending: Mutex::new(stages.iter().map(|s| (*s, vec![])).collect()),
+            ran: stages.iter().map(|s| (*s, OnceCell::new())).collect(),
+        }
+    }
+
+    /// Fails if `stage` already ran
+    pub(crate) fn add<F, Fut>(&self, stage: LifecycleStage, hook: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(hooks) = pending.get_mut(&stage) else {
+            return Err(error!("Lifecycle stage {stage:?} already ran"));
+        };
+        hooks.push(Box::new(move || Box::pin(hook())));
+        Ok(())
+    }
+
+    /// Run the hooks of `stage`, the first time only. Later and concurrent calls wait for the
+    /// first one and get its result.
+    pub(crate) async fn run(&self, stage: LifecycleStage) -> Result<()> {
+        let outcome = self.ran[&stage]
+            .get_or_init(|| async {
+                let mut hooks = self
+                    .pending
+                    .lock()
+                    .unwrap()
+                    .remove(&stage)
+                    .unwrap_or_default();
+                if stage.is_shutdown() {
+                    hooks.reverse();
+                }
+                if !hooks.is_empty() {
+                    tracing::debug!(?stage, count = hooks.len(), "Running lifecycle hooks");
+                }
+                for hook in hooks {
+                    if let Err(err) = hook().await {
+                        if !stage.is_shutdown() {
+                            return Err(format!("{err:#}"));
+                        }
+                        tracing::error!(?stage, "Lifecycle hook failed: {err:#}");
+                    }
+                }
+                Ok(())
+            })
+            .await;
+        outcome
+            .clone()
+            .map_err(|err| error!("{stage:?} lifecycle hook failed: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn recorder(
+        hooks: &LifecycleHooks,
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        stage: LifecycleStage,
+        name: &'static str,
+    ) {
+        let log = log.clone();
+        hooks
+            .add(stage, move || async move {
+                log.lock().unwrap().push(name);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_order_and_once() {
+        let hooks = LifecycleHooks::new();
+        let log = Arc::new(Mutex::new(vec![]));
+        recorder(&hooks, &log, LifecycleStage::PreServe, "pool");
+        recorder(&hooks, &log, LifecycleStage::PreServe, "gpu");
+        recorder(&hooks, &log, LifecycleStage::PostShutdown, "close pool");
+        recorder(&hooks, &log, LifecycleStage::PostShutdown, "release gpu");
+
+        hooks.run(LifecycleStage::PreServe).await.unwrap();
+        hooks.run(LifecycleStage::PreServe).await.unwrap();
+        hooks.run(LifecycleStage::PostShutdown).await.unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["pool", "gpu", "release gpu", "close pool"]
+        );
+
+        assert!(hooks
+            .add(LifecycleStage::PreServe, || async { Ok(()) })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let hooks = LifecycleHooks::new();
+        let log = Arc::new(Mutex::new(vec![]));
+        hooks
+            .add(LifecycleStage::PreServe, || async { Err(error!("no db")) })
+            .unwrap();
+        recorder(
+            &hooks,
+            &log,
+            LifecycleStage::PreServe,
+            "after startup failure",
+        );
+        hooks
+            .add(LifecycleStage::PreDrain, || async { Err(error!("busy")) })
+            .unwrap();
+        recorder(
+            &hooks,
+            &log,
+            LifecycleStage::PreDrain,
+            "before shutdown failure",
+        );
+
+        // Startup stops at the first failure, and keeps failing
+        assert!(hooks.run(LifecycleStage::PreServe).await.is_err());
+        assert!(hooks.run(LifecycleStage::PreServe).await.is_err());
+        // Shutdown carries on
+        hooks.run(LifecycleStage::PreDrain).await.unwrap();
+        assert_eq!(*log.lock().unwrap(), ["before shutdown failure"]);
+    }
+}
@@ -0,0 +1,314 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Region and zone awareness.
+//!
+//! Every [`Instance`] carries the region and zone of the process that registered it. Routers
+//! prefer instances in their own zone according to the [`ZonePolicy`], because requests that cross
+//! zones are slower and, on most clouds, billed.
+//!
+//! The locality comes from `DYN_LOCALITY_REGION` and `DYN_LOCALITY_ZONE`. With
+//! `DYN_LOCALITY_DETECT=true` it is read from the AWS or GCP instance metadata service instead,
+//! when not set explicitly. `DYN_LOCALITY_POLICY` is one of `any`, `prefer-local` (the default) or
+//! `local-only`.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use figment::{
+    providers::{Env, Serialized},
+    Figment,
+};
+use prometheus::{IntCounterVec, Opts, Registry};
+use serde::{Deserialize, Serialize};
+
+use crate::component::Instance;
+use crate::{error, Result};
+
+/// How long to wait for a cloud metadata service. Off-cloud there is nothing listening.
+const METADATA_TIMEOUT: Duration = Duration::from_millis(500);
+
+const AWS_METADATA: &str = "http://169.254.169.254/latest";
+const GCP_METADATA: &str = "http://metadata.google.internal/computeMetadata/v1/instance/zone";
+
+static ROUTED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dynamo_routed_requests_total",
+            "Requests routed to a worker, by where the worker is relative to the router",
+        ),
+        &["locality"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Where a process runs. Either can be unknown.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Locality {
+    pub region: Option<String>,
+    pub zone: Option<String>,
+}
+
+/// Which instances a router may pick, relative to its own zone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZonePolicy {
+    /// Ignore zones
+    Any,
+
+    /// Instances in our zone if there are any, otherwise spill over to the others
+    #[default]
+    PreferLocal,
+
+    /// Only instances in our zone, fail if there are none. Instances with an unknown zone are
+    /// never picked.
+    LocalOnly,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalityConfig {
+    pub region: Option<String>,
+    pub zone: Option<String>,
+
+    /// Ask the cloud metadata service for whichever of region and zone isn't set
+    pub detect: bool,
+
+    pub policy: ZonePolicy,
+}
+
+impl LocalityConfig {
+    /// Read the configuration from `DYN_LOCALITY_*` environment variables.
+    /// Panics on invalid configuration.
+    pub fn from_settings() -> Self {
+        Figment::new()
+            .merge(Serialized::defaults(Self::default()))
+            .merge(Env::prefixed("DYN_LOCALITY_"))
+            .extract()
+            .unwrap() // safety: Called on startup, so panic is reasonable
+    }
+
+    /// Our locality, asking the cloud metadata service if configured
+    pub async fn resolve(&self) -> Locality {
+        let configured = Locality {
+            region: self.region.clone(),
+            zone: self.zone.clone(),
+        };
+        if !self.detect || (configured.region.is_some() && configured.zone.is_some()) {
+            return configured;
+        }
+        match detect().await {
+            Ok(detected) => {
+                tracing::debug!(?detected, "Detected locality from cloud metadata");
+                Locality {
+                    region: configured.region.or(detected.region),
+                    zone: configured.zone.or(detected.zone),
+                }
+            }
+            Err(err) => {
+                tracing::warn!(%err, "Could not detect region and zone");
+                configured
+            }
+        }
+    }
+}
+
+/// Where an instance is relative to us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hop {
+    SameZone,
+    CrossZone,
+    CrossRegion,
+    Unknown,
+}
+
+impl Hop {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hop::SameZone => "same_zone",
+            Hop::CrossZone => "cross_zone",
+            Hop::CrossRegion => "cross_region",
+            Hop::Unknown => "unknown",
+        }
+    }
+}
+
+impl Locality {
+    pub fn hop_to(&self, instance: &Instance) -> Hop {
+        if let (Some(ours), Some(theirs)) = (&self.region, &instance.region) {
+            if ours != theirs {
+                return Hop::CrossRegion;
+            }
+        }
+        match (&self.zone, &instance.zone) {
+            (Some(ours), Some(theirs)) if ours == theirs => Hop::SameZone,
+            (Some(_), Some(_)) => Hop::CrossZone,
+            _ => Hop::Unknown,
+        }
+    }
+
+    /// The instances `policy` allows us to route to
+    pub fn filter(&self, policy: ZonePolicy, instances: Vec<Instance>) -> Vec<Instance> {
+        if policy == ZonePolicy::Any || self.zone.is_none() {
+            return instances;
+        }
+        let (local, other): (Vec<_>, Vec<_>) = instances
+            .into_iter()
+            .partition(|instance| self.hop_to(instance) == Hop::SameZone);
+        match policy {
+            ZonePolicy::PreferLocal if local.is_empty() => other,
+            _ => local,
+        }
+    }
+
+    /// Count a request routed to `instance` in the `dynamo_routed_requests_total` metric
+    pub fn record_routed(&self, instance: &Instance) {
+        ROUTED_REQUESTS
+            .with_label_values(&[self.hop_to(instance).as_str()])
+            .inc();
+    }
+}
+
+/// Add the routing locality metrics to `registry`
+pub fn register_metrics(registry: &Registry) -> Result<()> {
+    registry.register(Box::new(ROUTED_REQUESTS.clone()))?;
+    Ok(())
+}
+
+/// Region and zone from the AWS or GCP instance metadata service
+async fn detect() -> Result<Locality> {
+    let client = reqwest::Client::builder()
+        .timeout(METADATA_TIMEOUT)
+        .build()?;
+    match detect_aws(&client).await {
+        Ok(locality) => Ok(locality),
+        Err(aws_err) => detect_gcp(&client)
+            .await
+            .map_err(|gcp_err| error!("AWS: {aws_err}. GCP: {gcp_err}")),
+    }
+}
+
+async fn detect_aws(client: &reqwest::Client) -> Result<Locality> {
+    // IMDSv2 needs a session token
+    let token = client
+        .put(format!("{AWS_METADATA}/api/token"))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let get = |path: &'static str| {
+        client
+            .get(format!("{AWS_METADATA}/meta-data/placement/{path}"))
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+    };
+    let zone = get("availability-zone")
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let region = get("region").await?.error_for_status()?.text().await?;
+    Ok(Locality {
+        region: Some(region),
+        zone: Some(zone),
+    })
+}
+
+async fn detect_gcp(client: &reqwest::Client) -> Result<Locality> {
+    // projects/<number>/zones/us-central1-a
+    let path = client
+        .get(GCP_METADATA)
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(gcp_locality(&path))
+}
+
+fn gcp_locality(path: &str) -> Locality {
+    let zone = path.rsplit('/').next().unwrap_or_default().to_string();
+    // The region is the zone without its last part
+    let region = zone.rsplit_once('-').map(|(region, _)| region.to_string());
+    Locality {
+        region,
+        zone: Some(zone),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::TransportType;
+
+    fn instance(id: i64, region: &str, zone: Option<&str>) -> Instance {
+        Instance {
+            component: "backend".to_string(),
+            endpoint: "generate".to_string(),
+            namespace: "test".to_string(),
+            instance_id: id,
+            transport: TransportType::NatsTcp(format!("test.backend.generate-{id}")),
+            region: Some(region.to_string()),
+            zone: zone.map(str::to_string),
+            max_concurrent_requests: None,
+            description: None,
+            cost_per_gpu_hour: None,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let us = Locality {
+            region: Some("us-east-1".to_string()),
+            zone: Some("us-east-1a".to_string()),
+        };
+        let instances = vec![
+            instance(1, "us-east-1", Some("us-east-1a")),
+            instance(2, "us-east-1", Some("us-east-1b")),
+            instance(3, "us-east-1", None),
+            instance(4, "eu-west-1", Some("eu-west-1a")),
+        ];
+        let hops: Vec<_> = instances.iter().map(|i| us.hop_to(i)).collect();
+        assert_eq!(
+            hops,
+            [
+                Hop::SameZone,
+                Hop::CrossZone,
+                Hop::Unknown,
+                Hop::CrossRegion
+            ]
+        );
+
+        let ids = |instances: Vec<Instance>| instances.iter().map(|i| i.id()).collect::<Vec<_>>();
+        assert_eq!(
+            ids(us.filter(ZonePolicy::PreferLocal, instances.clone())),
+            [1]
+        );
+        assert_eq!(
+            ids(us.filter(ZonePolicy::Any, instances.clone())),
+            [1, 2, 3, 4]
+        );
+
+        // Spill over when there is nothing local
+        let remote = instances[1..].to_vec();
+        assert_eq!(
+            ids(us.filter(ZonePolicy::PreferLocal, remote.clone())),
+            [2, 3, 4]
+        );
+        assert!(us.filter(ZonePolicy::LocalOnly, remote).is_empty());
+
+        // Without a zone of our own there is nothing to prefer
+        let unknown = Locality::default();
+        assert_eq!(unknown.filter(ZonePolicy::LocalOnly, instances).len(), 4);
+    }
+
+    #[test]
+    fn test_gcp_locality() {
+        let locality = gcp_locality("projects/1234/zones/us-central1-a");
+        assert_eq!(locality.zone.as_deref(), Some("us-central1-a"));
+        assert_eq!(locality.region.as_deref(), Some("us-central1"));
+    }
+}
@@ -41,6 +41,8 @@
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Once;
 
+use crate::error_reporting::ErrorReportingLayer;
+
 use figment::{
     providers::{Format, Serialized, Toml},
     Figment,
@@ -96,49 +98,72 @@ impl Default for LoggingConfig {
     }
 }
 
-/// Initialize the logger
+/// Initialize the logger. Also exports the spans if enabled, see [`crate::otel`].
 pub fn init() {
     INIT.call_once(|| {
         let config = load_config();
 
-        // Examples to remove noise
-        // .add_directive("rustls=warn".parse()?)
-        // .add_directive("tokio_util::codec=warn".parse()?)
-        let mut filter_layer = EnvFilter::builder()
-            .with_default_directive(config.log_level.parse().unwrap())
-            .with_env_var(FILTER_ENV)
-            .from_env_lossy();
-
-        // apply the log_filters from the config files
-        for (module, level) in config.log_filters {
-            match format!("{module}={level}").parse::<Directive>() {
-                Ok(d) => {
-                    filter_layer = filter_layer.add_directive(d);
-                }
-                Err(e) => {
-                    eprintln!("Failed parsing filter '{level}' for module '{module}': {e}");
-                }
+        // Spans are filtered like logs, with a filter of their own
+        let otel_layer = match crate::otel::layer() {
+            Ok(layer) => layer.map(|l| l.with_filter(filter(&config))),
+            Err(err) => {
+                eprintln!(
+                    "Failed setting up OpenTelemetry export, spans are not exported: {err:#}"
+                );
+                None
             }
-        }
+        };
 
         if crate::config::jsonl_logging_enabled() {
             let l = fmt::layer()
                 .with_ansi(false) // ansi terminal escapes and colors always disabled
                 .event_format(CustomJsonFormatter::new())
                 .with_writer(std::io::stderr)
-                .with_filter(filter_layer);
-            tracing_subscriber::registry().with(l).init();
+                .with_filter(filter(&config));
+            tracing_subscriber::registry()
+                .with(l)
+                .with(otel_layer)
+                .with(ErrorReportingLayer)
+                .init();
         } else {
             let l = fmt::layer()
                 .with_ansi(!crate::config::disable_ansi_logging())
                 .event_format(fmt::format().compact().with_timer(TimeFormatter::new()))
                 .with_writer(std::io::stderr)
-                .with_filter(filter_layer);
-            tracing_subscriber::registry().with(l).init();
+                .with_filter(filter(&config));
+            tracing_subscriber::registry()
+                .with(l)
+                .with(otel_layer)
+                .with(ErrorReportingLayer)
+                .init();
         };
     });
 }
 
+/// The filter from `DYN_LOG` and the configuration
+fn filter(config: &LoggingConfig) -> EnvFilter {
+    // Examples to remove noise
+    // .add_directive("rustls=warn".parse()?)
+    // .add_directive("tokio_util::codec=warn".parse()?)
+    let mut filter_layer = EnvFilter::builder()
+        .with_default_directive(config.log_level.parse().unwrap())
+        .with_env_var(FILTER_ENV)
+        .from_env_lossy();
+
+    // apply the log_filters from the config files
+    for (module, level) in &config.log_filters {
+        match format!("{module}={level}").parse::<Directive>() {
+            Ok(d) => {
+                filter_layer = filter_layer.add_directive(d);
+            }
+            Err(e) => {
+                eprintln!("Failed parsing filter '{level}' for module '{module}': {e}");
+            }
+        }
+    }
+    filter_layer
+}
+
 /// Log a message with file and line info
 /// Used by Python wrapper
 pub fn log_message(level: &str, message: &str, module: &str, file: &str, line: u32) {
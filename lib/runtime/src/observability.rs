@@ -0,0 +1,302 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resource metrics of the process, so that regressions in the Rust layer show up independently
+//! of the engines and GPUs.
+//!
+//! - Tokio, per runtime (`primary` and `secondary`): worker threads, alive tasks, the depth of the
+//!   global queue, and how long a new task waits before it is first polled. That delay grows when
+//!   other tasks poll for too long or the runtime is saturated.
+//! - Memory: the resident set size and, with glibc, the bytes malloc handed out and holds free.
+//! - File descriptors: how many are open, and the limit.
+//!
+//! [`start`] samples them every [`SAMPLE_INTERVAL`], [`register_metrics`] exports them.
+
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use prometheus::{Histogram, HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts, Registry};
+use tokio::runtime::Handle;
+
+use crate::{Result, Runtime};
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+static TOKIO_WORKERS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "dynamo_tokio_workers",
+            "Worker threads of the tokio runtime",
+        ),
+        &["runtime"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static TOKIO_ALIVE_TASKS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "dynamo_tokio_alive_tasks",
+            "Tasks spawned on the tokio runtime that have not finished",
+        ),
+        &["runtime"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static TOKIO_GLOBAL_QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "dynamo_tokio_global_queue_depth",
+            "Tasks waiting in the global queue of the tokio runtime",
+        ),
+        &["runtime"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static TOKIO_SCHEDULE_DELAY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "dynamo_tokio_schedule_delay_seconds",
+            "How long a newly spawned task waited before it was first polled",
+        )
+        .buckets(vec![
+            0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+        ]),
+        &["runtime"],
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static RESIDENT_MEMORY: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_process_resident_memory_bytes",
+        "Resident set size of the process",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static THREADS: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_process_threads",
+        "OS threads of the process, the tokio workers included",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static OPEN_FDS: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_process_open_fds",
+        "File descriptors the process has open",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static MAX_FDS: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_process_max_fds",
+        "Soft limit of open file descriptors",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static ALLOCATED: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_allocator_allocated_bytes",
+        "Bytes handed out by malloc and not freed yet",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+static ALLOCATOR_FREE: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "dynamo_allocator_free_bytes",
+        "Bytes malloc holds free, not returned to the OS",
+    )
+    .unwrap() // safety: Static and valid
+});
+
+/// Add the runtime and process metrics to `registry`. They stay at zero unless [`start`] was
+/// called.
+pub fn register_metrics(registry: &Registry) -> Result<()> {
+    registry.register(Box::new(TOKIO_WORKERS.clone()))?;
+    registry.register(Box::new(TOKIO_ALIVE_TASKS.clone()))?;
+    registry.register(Box::new(TOKIO_GLOBAL_QUEUE_DEPTH.clone()))?;
+    registry.register(Box::new(TOKIO_SCHEDULE_DELAY.clone()))?;
+    registry.register(Box::new(RESIDENT_MEMORY.clone()))?;
+    registry.register(Box::new(THREADS.clone()))?;
+    registry.register(Box::new(OPEN_FDS.clone()))?;
+    registry.register(Box::new(MAX_FDS.clone()))?;
+    registry.register(Box::new(ALLOCATED.clone()))?;
+    registry.register(Box::new(ALLOCATOR_FREE.clone()))?;
+    Ok(())
+}
+
+/// Sample the metrics of `runtime` and the process until it shuts down
+pub fn start(runtime: &Runtime) {
+    let cancel = runtime.primary_token();
+    for (name, handle) in [
+        ("primary", runtime.primary()),
+        ("secondary", runtime.secondary()),
+    ] {
+        let cancel = cancel.clone();
+        let with_process = name == "primary";
+        runtime.tasks().spawn_on(
+            format!("{name} runtime metrics"),
+            async move {
+                let schedule_delay = TOKIO_SCHEDULE_DELAY.with_label_values(&[name]);
+                let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                    sample_tokio(name, &schedule_delay).await;
+                    if with_process {
+                        sample_process();
+                    }
+                }
+            },
+            &handle,
+        );
+    }
+}
+
+/// Sample the tokio runtime this is running on
+async fn sample_tokio(name: &str, schedule_delay: &Histogram) {
+    let metrics = Handle::current().metrics();
+    TOKIO_WORKERS
+        .with_label_values(&[name])
+        .set(metrics.num_workers() as i64);
+    TOKIO_ALIVE_TASKS
+        .with_label_values(&[name])
+        .set(metrics.num_alive_tasks() as i64);
+    TOKIO_GLOBAL_QUEUE_DEPTH
+        .with_label_values(&[name])
+        .set(metrics.global_queue_depth() as i64);
+
+    let spawned = Instant::now();
+    if let Ok(delay) = tokio::spawn(async move { spawned.elapsed() }).await {
+        schedule_delay.observe(delay.as_secs_f64());
+    }
+}
+
+fn sample_process() {
+    let status = ProcessStatus::read();
+    if let Some(rss) = status.resident_bytes {
+        RESIDENT_MEMORY.set(rss as i64);
+    }
+    if let Some(threads) = status.threads {
+        THREADS.set(threads as i64);
+    }
+    if let Ok(fds) = std::fs::read_dir("/proc/self/fd") {
+        // Not counting the one listing them
+        OPEN_FDS.set(fds.count().saturating_sub(1) as i64);
+    }
+    if let Some(max_fds) = max_fds() {
+        MAX_FDS.set(max_fds as i64);
+    }
+    if let Some((allocated, free)) = allocator_stats() {
+        ALLOCATED.set(allocated as i64);
+        ALLOCATOR_FREE.set(free as i64);
+    }
+}
+
+/// What we use of `/proc/self/status`. Empty off Linux.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ProcessStatus {
+    resident_bytes: Option<u64>,
+    threads: Option<u64>,
+}
+
+impl ProcessStatus {
+    fn read() -> Self {
+        std::fs::read_to_string("/proc/self/status")
+            .map(|status| Self::parse(&status))
+            .unwrap_or_default()
+    }
+
+    fn parse(status: &str) -> Self {
+        let mut parsed = ProcessStatus::default();
+        for line in status.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let number = value
+                .split_whitespace()
+                .next()
+                .and_then(|n| n.parse::<u64>().ok());
+            match key {
+                "VmRSS" => parsed.resident_bytes = number.map(|kib| kib * 1024),
+                "Threads" => parsed.threads = number,
+                _ => {}
+            }
+        }
+        parsed
+    }
+}
+
+/// The soft limit of open file descriptors, from `/proc/self/limits`
+fn max_fds() -> Option<u64> {
+    let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with("Max open files"))?;
+    line.trim_start_matches("Max open files")
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Bytes allocated and held free by glibc malloc. None before glibc 2.33, which added
+/// `mallinfo2`: the older `mallinfo` counts in an `int` and wraps past 2 GiB.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn allocator_stats() -> Option<(usize, usize)> {
+    type MallInfo2 = unsafe extern "C" fn() -> libc::mallinfo2;
+    // Linking mallinfo2 would keep the binary from starting on older glibc, so look it up
+    static MALLINFO2: std::sync::OnceLock<Option<MallInfo2>> = std::sync::OnceLock::new();
+    let mallinfo2 = (*MALLINFO2.get_or_init(|| {
+        // SAFETY: the name is a C string
+        let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, c"mallinfo2".as_ptr()) };
+        // SAFETY: glibc's mallinfo2 has this signature
+        (!symbol.is_null())
+            .then(|| unsafe { std::mem::transmute::<*mut libc::c_void, MallInfo2>(symbol) })
+    }))?;
+    // SAFETY: mallinfo2 only reads the allocator's own statistics
+    let info = unsafe { mallinfo2() };
+    // uordblks is in use from the heap arenas, hblkhd in use from separate mmaps
+    Some((info.uordblks + info.hblkhd, info.fordblks))
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn allocator_stats() -> Option<(usize, usize)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let status = "Name:\tdynamo-run\nVmPeak:\t 2048 kB\nVmRSS:\t  1536 kB\nThreads:\t42\n";
+        assert_eq!(
+            ProcessStatus::parse(status),
+            ProcessStatus {
+                resident_bytes: Some(1536 * 1024),
+                threads: Some(42),
+            }
+        );
+        assert_eq!(ProcessStatus::parse(""), ProcessStatus::default());
+    }
+
+    #[tokio::test]
+    async fn test_sample_tokio() {
+        let schedule_delay = TOKIO_SCHEDULE_DELAY.with_label_values(&["test"]);
+        sample_tokio("test", &schedule_delay).await;
+        assert_eq!(schedule_delay.get_sample_count(), 1);
+        assert_eq!(TOKIO_WORKERS.with_label_values(&["test"]).get(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Distributed tracing with OpenTelemetry.
+//!
+//! With `DYN_OTEL_EXPORT_ENABLED` set to a truthy value, [`crate::logging::init`] exports the
+//! `tracing` spans over OTLP (HTTP and protobuf), e.g. to Jaeger. The exporter is configured with
+//! the standard OpenTelemetry environment variables:
+//! - `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` or `OTEL_EXPORTER_OTLP_ENDPOINT`, default
+//!   `http://localhost:4318`,
+//! - `OTEL_SERVICE_NAME`, default `dynamo`,
+//! - `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`, default every trace.
+//!
+//! A request's trace context travels with it as W3C `traceparent` and `tracestate` entries:
+//! [`inject`] it where a request leaves the process, [`set_parent`] where it arrives. Both do
+//! nothing when export is disabled.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// ENV to turn on exporting spans
+const EXPORT_ENABLED_ENV: &str = "DYN_OTEL_EXPORT_ENABLED";
+
+/// Service name when `OTEL_SERVICE_NAME` isn't set
+const DEFAULT_SERVICE_NAME: &str = "dynamo";
+
+/// Set once export is on, to flush the spans on shutdown
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// The trace context of a request, as W3C trace context headers
+pub type TraceCarrier = HashMap<String, String>;
+
+/// Check whether spans are exported.
+/// Set the `DYN_OTEL_EXPORT_ENABLED` environment variable a [`crate::config::is_truthy`] value
+pub fn export_enabled() -> bool {
+    crate::config::env_is_truthy(EXPORT_ENABLED_ENV)
+}
+
+/// The layer exporting spans, if enabled. Sets the global tracer provider and propagator.
+pub(crate) fn layer<S>() -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if !export_enabled() {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let mut resource = Resource::builder();
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(DEFAULT_SERVICE_NAME);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+    let tracer = provider.tracer("dynamo");
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    let _ = PROVIDER.set(provider);
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Export the spans that are still buffered. Call before the process exits.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            eprintln!("Failed flushing trace spans: {err}");
+        }
+    }
+}
+
+/// The trace context of `span`, to send along with a request. None if export is disabled.
+pub fn inject(span: &tracing::Span) -> Option<TraceCarrier> {
+    PROVIDER.get()?;
+    let mut carrier = TraceCarrier::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut carrier)
+    });
+    (!carrier.is_empty()).then_some(carrier)
+}
+
+/// Make `span` continue the trace of `carrier`, from the process that sent the request.
+/// Call it before `span` has children.
+pub fn set_parent(span: &tracing::Span, carrier: &TraceCarrier) {
+    if PROVIDER.get().is_none() || carrier.is_empty() {
+        return;
+    }
+    let parent =
+        opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(carrier));
+    span.set_parent(parent);
+}
@@ -0,0 +1,298 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encrypt request and response payloads end to end, between router and worker.
+//!
+//! Requests travel through the NATS servers, so anyone with access to the broker can read the
+//! prompts. With a key for a namespace, the routers of its endpoints encrypt each request with
+//! ChaCha20-Poly1305 and the workers decrypt it, and the other way round for the responses. The
+//! namespace, the direction and the request id are authenticated too: a payload doesn't open in
+//! another namespace even if they share a key, and a request or response can't be replayed as
+//! the other or as part of another request. The control message of a request, with its id and
+//! where to send the responses, stays in the clear, so that a worker that can't decrypt the
+//! request can still fail it. So do the control plane, KV events and load metrics.
+//!
+//! Configured from the environment when the [`crate::DistributedRuntime`] is created:
+//! - `DYN_PAYLOAD_ENCRYPTION_KEYS_FILE`: JSON file of the 32 byte key of each namespace, in hex,
+//!   e.g. `{"dynamo": "<openssl rand -hex 32>"}`.
+//!
+//! Routers and workers of a namespace must have the same key. A worker with a key rejects
+//! requests that are not encrypted, and one without a key rejects those that are, so a
+//! misconfigured process fails its requests instead of sending prompts in the clear.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use figment::{
+    providers::{Env, Serialized},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{error, Result};
+
+/// Starts every encrypted payload, so that a payload is never taken for the other kind
+const MAGIC: &[u8] = b"DYNSEAL1";
+
+/// Bytes of the nonce, after [`MAGIC`]
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PayloadEncryptionConfig {
+    /// JSON file with the key of each namespace, see the [module docs](self)
+    pub keys_file: Option<PathBuf>,
+}
+
+impl PayloadEncryptionConfig {
+    /// Read the configuration from `DYN_PAYLOAD_ENCRYPTION_*` environment variables.
+    /// Panics on invalid configuration.
+    pub fn from_settings() -> Self {
+        Figment::new()
+            .merge(Serialized::defaults(Self::default()))
+            .merge(Env::prefixed("DYN_PAYLOAD_ENCRYPTION_"))
+            .extract()
+            .unwrap() // safety: Called on startup, so panic is reasonable
+    }
+}
+
+/// Which way a payload travels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the router to the worker
+    Request,
+    /// From the worker back to the router
+    Response,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Request => "request",
+            Direction::Response => "response",
+        }
+    }
+}
+
+/// The key of a namespace
+pub struct PayloadKey {
+    namespace: String,
+    cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for PayloadKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never the key
+        f.debug_struct("PayloadKey")
+            .field("namespace", &self.namespace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PayloadKey {
+    pub fn new(namespace: &str, key: &[u8; 32]) -> Self {
+        PayloadKey {
+            namespace: namespace.to_string(),
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// What a payload is authenticated with besides itself, see the [module docs](self)
+    fn associated_data(&self, direction: Direction, request_id: &str) -> Vec<u8> {
+        let mut aad = Vec::new();
+        for part in [&self.namespace, direction.as_str(), request_id] {
+            // Length prefixed, so that the parts can't run into each other
+            aad.extend_from_slice(&(part.len() as u64).to_le_bytes());
+            aad.extend_from_slice(part.as_bytes());
+        }
+        aad
+    }
+
+    fn seal(&self, direction: Direction, request_id: &str, payload: &[u8]) -> Result<Bytes> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: payload,
+                    aad: &self.associated_data(direction, request_id),
+                },
+            )
+            .map_err(|_| error!("Failed encrypting payload of namespace {}", self.namespace))?;
+        let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed.into())
+    }
+
+    fn open(&self, direction: Direction, request_id: &str, sealed: &[u8]) -> Result<Bytes> {
+        let Some((nonce, ciphertext)) = sealed
+            .strip_prefix(MAGIC)
+            .and_then(|rest| rest.split_at_checked(NONCE_LEN))
+        else {
+            return Err(error!(
+                "Payload of namespace {} is not encrypted, but it has a payload key",
+                self.namespace
+            ));
+        };
+        let payload = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &self.associated_data(direction, request_id),
+                },
+            )
+            .map_err(|_| {
+                error!(
+                    "Failed decrypting {} {request_id} of namespace {}, is its key the same \
+                     everywhere?",
+                    direction.as_str(),
+                    self.namespace
+                )
+            })?;
+        Ok(payload.into())
+    }
+}
+
+/// Encrypt `payload` of request `request_id` going in `direction` with `key`, or leave it as is
+/// if there is no key
+pub fn seal(
+    key: Option<&PayloadKey>,
+    direction: Direction,
+    request_id: &str,
+    payload: Bytes,
+) -> Result<Bytes> {
+    match key {
+        Some(key) => key.seal(direction, request_id, &payload),
+        None => Ok(payload),
+    }
+}
+
+/// Decrypt `payload` with `key`, it must have been sealed for the same `direction` and
+/// `request_id`. Without a key, `payload` must not be encrypted.
+pub fn open(
+    key: Option<&PayloadKey>,
+    direction: Direction,
+    request_id: &str,
+    payload: Bytes,
+) -> Result<Bytes> {
+    match key {
+        Some(key) => key.open(direction, request_id, &payload),
+        None if payload.starts_with(MAGIC) => Err(error!(
+            "Payload is encrypted, but there is no payload key for its namespace"
+        )),
+        None => Ok(payload),
+    }
+}
+
+/// The keys of the namespaces, see the [module docs](self)
+#[derive(Debug, Default)]
+pub struct PayloadKeys {
+    keys: HashMap<String, Arc<PayloadKey>>,
+}
+
+impl PayloadKeys {
+    /// The keys `config` points to, none if it has no keys file
+    pub fn load(config: &PayloadEncryptionConfig) -> Result<Self> {
+        let Some(path) = &config.keys_file else {
+            return Ok(PayloadKeys::default());
+        };
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            error!(
+                "Failed reading payload encryption keys from {}: {err}",
+                path.display()
+            )
+        })?;
+        let keys = Self::from_json(&contents).map_err(|err| {
+            error!(
+                "Invalid payload encryption keys in {}: {err}",
+                path.display()
+            )
+        })?;
+        let mut namespaces: Vec<&str> = keys.keys.keys().map(String::as_str).collect();
+        namespaces.sort_unstable();
+        tracing::info!(?namespaces, "Encrypting request and response payloads");
+        Ok(keys)
+    }
+
+    fn from_json(json: &str) -> Result<Self> {
+        let hex_keys: HashMap<String, String> = serde_json::from_str(json)?;
+        let mut keys = HashMap::with_capacity(hex_keys.len());
+        for (namespace, hex_key) in hex_keys {
+            let key = parse_hex_key(hex_key.trim())
+                .ok_or_else(|| error!("The key of {namespace} must be 64 hex digits"))?;
+            keys.insert(
+                namespace.clone(),
+                Arc::new(PayloadKey::new(&namespace, &key)),
+            );
+        }
+        Ok(PayloadKeys { keys })
+    }
+
+    /// The key of `namespace`, None if its payloads are not encrypted
+    pub fn get(&self, namespace: &str) -> Option<Arc<PayloadKey>> {
+        self.keys.get(namespace).cloned()
+    }
+}
+
+pub(crate) fn parse_hex_key(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_seal_open() {
+        let keys = PayloadKeys::from_json(&format!(r#"{{"dynamo": "{KEY}"}}"#)).unwrap();
+        let key = keys.get("dynamo").unwrap();
+        assert!(keys.get("other").is_none());
+
+        let payload = Bytes::from_static(b"{\"prompt\": \"secret\"}");
+        let sealed = seal(Some(&key), Direction::Request, "r1", payload.clone()).unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+        let opened = open(Some(&key), Direction::Request, "r1", sealed.clone()).unwrap();
+        assert_eq!(opened, payload);
+
+        // Nonces are random, the same payload never encrypts the same
+        let again = seal(Some(&key), Direction::Request, "r1", payload.clone()).unwrap();
+        assert_ne!(again, sealed);
+
+        // Plain payloads and payloads of another namespace are rejected
+        assert!(open(Some(&key), Direction::Request, "r1", payload.clone()).is_err());
+        assert!(open(None, Direction::Request, "r1", sealed.clone()).is_err());
+        let other = PayloadKey::new("other", &parse_hex_key(KEY).unwrap());
+        assert!(open(Some(&other), Direction::Request, "r1", sealed.clone()).is_err());
+
+        // So are payloads of another request, or going the other way
+        assert!(open(Some(&key), Direction::Request, "r2", sealed.clone()).is_err());
+        assert!(open(Some(&key), Direction::Response, "r1", sealed).is_err());
+
+        let opened = open(None, Direction::Response, "r1", payload.clone()).unwrap();
+        assert_eq!(opened, payload);
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        assert!(PayloadKeys::from_json(r#"{"dynamo": "0011"}"#).is_err());
+        let not_hex = "z".repeat(64);
+        assert!(PayloadKeys::from_json(&format!(r#"{{"dynamo": "{not_hex}"}}"#)).is_err());
+    }
+}
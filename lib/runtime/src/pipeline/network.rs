@@ -20,6 +20,7 @@ pub mod egress;
 pub mod ingress;
 pub mod tcp;
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, OnceLock};
 
 use anyhow::Result;
@@ -32,6 +33,8 @@ use futures::StreamExt;
 use super::{AsyncEngine, AsyncEngineContext, AsyncEngineContextProvider, ResponseStream};
 use serde::{Deserialize, Serialize};
 
+use crate::payload_encryption::PayloadKey;
+
 use super::{
     context, AsyncTransportEngine, Context, Data, Error, ManyOut, PipelineError, PipelineIO,
     SegmentSource, ServiceBackend, ServiceEngine, SingleIn, Source,
@@ -72,6 +75,50 @@ pub struct ResponseStreamPrologue {
     error: Option<String>,
 }
 
+/// Header of a numbered response data frame. A frame with both a header and data is a numbered
+/// data frame, control messages have no data.
+///
+/// The worker numbers the frames of a response stream from 0 if the receiver asked for it when
+/// registering the stream. The receiver drops duplicates and fails the stream on a gap, so a
+/// response is delivered exactly once and in order, or ends with an error.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameOrder {
+    /// The frame we expected, forward it
+    Next,
+    /// Already received, drop it
+    Duplicate,
+}
+
+/// Checks the order of the numbered frames of one response stream
+#[derive(Debug, Default)]
+pub(crate) struct FrameSequence {
+    next: u64,
+}
+
+impl FrameSequence {
+    /// Check the header of the next frame received. An error means frames were lost.
+    pub(crate) fn check(&mut self, header: &[u8]) -> Result<FrameOrder> {
+        let FrameHeader { seq } = serde_json::from_slice(header)?;
+        if seq < self.next {
+            return Ok(FrameOrder::Duplicate);
+        }
+        if seq > self.next {
+            anyhow::bail!(
+                "Lost response frames {} to {} of the stream",
+                self.next,
+                seq - 1
+            );
+        }
+        self.next += 1;
+        Ok(FrameOrder::Next)
+    }
+}
+
 pub type StreamProvider<T> = tokio::sync::oneshot::Receiver<Result<T, String>>;
 
 /// The [`RegisteredStream`] object is acquired from a [`StreamProvider`] and is used to provide
@@ -146,11 +193,21 @@ pub trait ResponseService {
 pub struct StreamSender {
     tx: tokio::sync::mpsc::Sender<TwoPartMessage>,
     prologue: Option<ResponseStreamPrologue>,
+    /// Number of the next data frame, if the receiver wants them numbered
+    next_seq: Option<AtomicU64>,
 }
 
 impl StreamSender {
     pub async fn send(&self, data: Bytes) -> Result<()> {
-        Ok(self.tx.send(TwoPartMessage::from_data(data)).await?)
+        let message = match &self.next_seq {
+            Some(next_seq) => {
+                let seq = next_seq.fetch_add(1, Ordering::Relaxed);
+                let header = serde_json::to_vec(&FrameHeader { seq })?;
+                TwoPartMessage::from_parts(header.into(), data)
+            }
+            None => TwoPartMessage::from_data(data),
+        };
+        Ok(self.tx.send(message).await?)
     }
 
     pub async fn send_control(&self, control: ControlMessage) -> Result<()> {
@@ -274,6 +331,10 @@ struct RequestControlMessage {
     request_type: RequestType,
     response_type: ResponseType,
     connection_info: ConnectionInfo,
+
+    /// The sender's trace context, see [`crate::otel`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trace_context: Option<crate::otel::TraceCarrier>,
 }
 
 pub struct Ingress<Req: PipelineIO, Resp: PipelineIO> {
@@ -321,5 +382,33 @@ impl<Req: PipelineIO, Resp: PipelineIO> Ingress<Req, Resp> {
 
 #[async_trait]
 pub trait PushWorkHandler: Send + Sync {
-    async fn handle_payload(&self, payload: Bytes) -> Result<(), PipelineError>;
+    /// Serve the request in `payload`. Requests and responses are encrypted with `payload_key`
+    /// if given, see [`crate::payload_encryption`].
+    async fn handle_payload(
+        &self,
+        payload: Bytes,
+        payload_key: Option<Arc<PayloadKey>>,
+    ) -> Result<(), PipelineError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(seq: u64) -> Vec<u8> {
+        serde_json::to_vec(&FrameHeader { seq }).unwrap()
+    }
+
+    #[test]
+    fn test_frame_sequence() {
+        let mut sequence = FrameSequence::default();
+        assert_eq!(sequence.check(&header(0)).unwrap(), FrameOrder::Next);
+        assert_eq!(sequence.check(&header(1)).unwrap(), FrameOrder::Next);
+        assert_eq!(sequence.check(&header(1)).unwrap(), FrameOrder::Duplicate);
+        assert_eq!(sequence.check(&header(0)).unwrap(), FrameOrder::Duplicate);
+        assert_eq!(sequence.check(&header(2)).unwrap(), FrameOrder::Next);
+        let err = sequence.check(&header(5)).unwrap_err();
+        assert_eq!(err.to_string(), "Lost response frames 3 to 4 of the stream");
+        assert!(sequence.check(b"{}").is_err());
+    }
 }
@@ -15,5 +15,6 @@
 
 pub mod addressed_router;
 pub mod push_router;
+pub mod queue;
 
 use super::*;
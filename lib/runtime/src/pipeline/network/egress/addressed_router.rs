@@ -15,32 +15,14 @@
 
 use async_nats::client::Client;
 use tracing as log;
+use tracing::Instrument as _;
 
+use super::queue::{self, WorkQueue};
 use super::*;
+use crate::payload_encryption::{self, Direction, PayloadKey};
+use crate::protocols::annotated::Annotated;
 use crate::Result;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum RequestType {
-    SingleIn,
-    ManyIn,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum ResponseType {
-    SingleOut,
-    ManyOut,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RequestControlMessage {
-    id: String,
-    request_type: RequestType,
-    response_type: ResponseType,
-    connection_info: ConnectionInfo,
-}
-
 pub struct AddressedRequest<T> {
     request: T,
     address: String,
@@ -62,35 +44,40 @@ pub struct AddressedPushRouter {
 
     // todo: generalize with a generic
     resp_transport: Arc<tcp::server::TcpStreamServer>,
+
+    /// Encrypts the requests and decrypts the responses, see [`crate::payload_encryption`]
+    payload_key: Option<Arc<PayloadKey>>,
 }
 
 impl AddressedPushRouter {
     pub fn new(
         req_transport: Client,
         resp_transport: Arc<tcp::server::TcpStreamServer>,
+        payload_key: Option<Arc<PayloadKey>>,
     ) -> Result<Arc<Self>> {
         Ok(Arc::new(Self {
             req_transport,
             resp_transport,
+            payload_key,
         }))
     }
 }
 
-#[async_trait]
-impl<T, U> AsyncEngine<SingleIn<AddressedRequest<T>>, ManyOut<U>, Error> for AddressedPushRouter
-where
-    T: Data + Serialize,
-    U: Data + for<'de> Deserialize<'de>,
-{
-    async fn generate(&self, request: SingleIn<AddressedRequest<T>>) -> Result<ManyOut<U>, Error> {
-        let request_id = request.context().id().to_string();
-        let (addressed_request, context) = request.transfer(());
-        let (request, address) = addressed_request.into_parts();
-        let engine_ctx = context.context();
+impl AddressedPushRouter {
+    /// Register the response stream of `request` with the data plane, and package the request with
+    /// the connection info for the request plane. The worker that takes the buffer connects back
+    /// to the returned [`StreamProvider`].
+    async fn package<T: Serialize>(
+        &self,
+        request: &T,
+        engine_ctx: Arc<dyn AsyncEngineContext>,
+        span: &tracing::Span,
+    ) -> Result<(Bytes, StreamProvider<StreamReceiver>)> {
+        let request_id = engine_ctx.id().to_string();
 
         // registration options for the data plane in a singe in / many out configuration
         let options = StreamOptions::builder()
-            .context(engine_ctx.clone())
+            .context(engine_ctx)
             .enable_request_stream(false)
             .enable_response_stream(true)
             .build()
@@ -116,17 +103,25 @@ where
         // todo -- this object should be automatically created by the register call, and achieved by to the two into_parts()
         // calls. all the information here is provided by the [`StreamOptions`] object and/or the dataplane object
         let control_message = RequestControlMessage {
-            id: engine_ctx.id().to_string(),
+            id: request_id.clone(),
             request_type: RequestType::SingleIn,
             response_type: ResponseType::ManyOut,
             connection_info,
+            trace_context: crate::otel::inject(span),
         };
 
         // next build the two part message where we package the connection info and the request into
         // a single Vec<u8> that can be sent over the wire.
         // --- package this up in the WorkQueuePublisher ---
         let ctrl = serde_json::to_vec(&control_message)?;
-        let data = serde_json::to_vec(&request)?;
+        let data = serde_json::to_vec(request)?;
+        // the control message stays in the clear, so that a worker can fail what it can't open
+        let data = payload_encryption::seal(
+            self.payload_key.as_deref(),
+            Direction::Request,
+            &request_id,
+            data.into(),
+        )?;
 
         log::trace!(
             request_id,
@@ -135,7 +130,7 @@ where
             data.len()
         );
 
-        let msg = TwoPartMessage::from_parts(ctrl.into(), data.into());
+        let msg = TwoPartMessage::from_parts(ctrl.into(), data);
 
         // the request plane / work queue should provide a two part message codec that can be used
         // or it should take a two part message directly
@@ -143,36 +138,142 @@ where
         let codec = TwoPartCodec::default();
         let buffer = codec.encode_message(msg)?;
 
+        Ok((buffer, response_stream_provider))
+    }
+
+    /// The responses of a request, once the worker that took it connected back
+    async fn responses<U>(
+        &self,
+        response_stream_provider: StreamProvider<StreamReceiver>,
+        engine_ctx: Arc<dyn AsyncEngineContext>,
+        span: tracing::Span,
+    ) -> Result<ManyOut<U>, Error>
+    where
+        U: Data + for<'de> Deserialize<'de>,
+    {
+        log::trace!(request_id = engine_ctx.id(), "awaiting transport handshake");
+        let response_stream = response_stream_provider
+            .instrument(span)
+            .await
+            .map_err(|_| PipelineError::DetatchedStreamReceiver)?
+            .map_err(PipelineError::ConnectionFailed)?;
+
+        let mut messages = tokio_stream::wrappers::ReceiverStream::new(response_stream.rx);
+
+        let payload_key = self.payload_key.clone();
+        let context = engine_ctx.clone();
+        let stream = async_stream::stream! {
+            while let Some(msg) = messages.next().await {
+                let request_id = context.id();
+                let msg = match payload_encryption::open(
+                    payload_key.as_deref(),
+                    Direction::Response,
+                    request_id,
+                    msg,
+                ) {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        // A response that doesn't open can't be trusted, and neither can the rest
+                        log::error!(request_id, %err, "Failing the response stream");
+                        // Annotated errors reach the client, other response types drop it
+                        let error = serde_json::to_vec(&Annotated::<()>::from_error(err.to_string()))
+                            .expect("an annotated error always serializes");
+                        if let Ok(error) = serde_json::from_slice::<U>(&error) {
+                            yield error;
+                        }
+                        context.kill();
+                        break;
+                    }
+                };
+                match serde_json::from_slice::<U>(&msg) {
+                    Ok(r) => yield r,
+                    Err(err) => {
+                        let json_str = String::from_utf8_lossy(&msg);
+                        log::warn!(%err, %json_str, "Failed deserializing JSON to response");
+                    }
+                }
+            }
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), engine_ctx))
+    }
+
+    /// Put `request` on `queue`, for the first worker with a free slot to take, instead of sending
+    /// it to a worker. The same message as [`AsyncEngine::generate`] sends, see [`WorkQueue`].
+    pub async fn enqueue<T, U>(
+        &self,
+        request: SingleIn<T>,
+        queue: &WorkQueue,
+    ) -> Result<ManyOut<U>, Error>
+    where
+        T: Data + Serialize,
+        U: Data + for<'de> Deserialize<'de>,
+    {
+        let request_id = request.context().id().to_string();
+        let (request, context) = request.transfer(());
+        let engine_ctx = context.context();
+        let span = log::info_span!("work_queue_request", request_id, subject = %queue.subject());
+
+        let (buffer, response_stream_provider) =
+            self.package(&request, engine_ctx.clone(), &span).await?;
+
+        log::trace!(request_id, "enqueueing two-part message to the work queue");
+        let sequence = queue.publish(buffer).instrument(span.clone()).await?;
+
+        let responses = self.responses(response_stream_provider, engine_ctx.clone(), span);
+        let error = tokio::select! {
+            responses = responses => return responses,
+            _ = engine_ctx.stopped() => {
+                anyhow::anyhow!("Request {request_id} was cancelled before a worker took it")
+            }
+            _ = tokio::time::sleep(queue::MAX_QUEUE_WAIT) => anyhow::anyhow!(
+                "No worker took request {request_id} within {}s",
+                queue::MAX_QUEUE_WAIT.as_secs()
+            ),
+        };
+        // Workers would skip it once past its deadline, but it would hold a place in the queue
+        if let Err(err) = queue.delete(sequence).await {
+            log::warn!(request_id, %err, "Failed deleting a request from the work queue");
+        }
+        Err(error)
+    }
+}
+
+#[async_trait]
+impl<T, U> AsyncEngine<SingleIn<AddressedRequest<T>>, ManyOut<U>, Error> for AddressedPushRouter
+where
+    T: Data + Serialize,
+    U: Data + for<'de> Deserialize<'de>,
+{
+    async fn generate(&self, request: SingleIn<AddressedRequest<T>>) -> Result<ManyOut<U>, Error> {
+        let request_id = request.context().id().to_string();
+        let (addressed_request, context) = request.transfer(());
+        let (request, address) = addressed_request.into_parts();
+        let engine_ctx = context.context();
+        let span = log::info_span!("nats_request", request_id, subject = %address);
+
+        let (buffer, response_stream_provider) =
+            self.package(&request, engine_ctx.clone(), &span).await?;
+
         // TRANSPORT ABSTRACT REQUIRED - END HERE
 
         log::trace!(request_id, "enqueueing two-part message to nats");
 
+        if crate::fault_injection::drop_nats_message(&address) {
+            return Err(anyhow::anyhow!(
+                "NATS request to {address} dropped by fault injection"
+            ));
+        }
+
         // we might need to add a timeout on this if there is no subscriber to the subject; however, I think nats
         // will handle this for us
         let _response = self
             .req_transport
             .request(address.to_string(), buffer)
+            .instrument(span.clone())
             .await?;
 
-        log::trace!(request_id, "awaiting transport handshake");
-        let response_stream = response_stream_provider
+        self.responses(response_stream_provider, engine_ctx, span)
             .await
-            .map_err(|_| PipelineError::DetatchedStreamReceiver)?
-            .map_err(PipelineError::ConnectionFailed)?;
-
-        let stream = tokio_stream::wrappers::ReceiverStream::new(response_stream.rx);
-
-        let stream = stream.filter_map(|msg| async move {
-            match serde_json::from_slice::<U>(&msg) {
-                Ok(r) => Some(r),
-                Err(err) => {
-                    let json_str = String::from_utf8_lossy(&msg);
-                    log::warn!(%err, %json_str, "Failed deserializing JSON to response");
-                    None
-                }
-            }
-        });
-
-        Ok(ResponseStream::new(Box::pin(stream), engine_ctx))
     }
 }
@@ -14,20 +14,24 @@
 // limitations under the License.
 
 use async_trait::async_trait;
+use futures::StreamExt;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     marker::PhantomData,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
+use super::queue::WorkQueue;
 use crate::{
-    component::{Client, Endpoint, InstanceSource},
-    engine::{AsyncEngine, Data},
-    pipeline::{AddressedPushRouter, AddressedRequest, Error, ManyOut, SingleIn},
+    component::{Client, Endpoint, Instance, InstanceSource},
+    engine::{AsyncEngine, AsyncEngineContextProvider, Data},
+    pipeline::{AddressedPushRouter, AddressedRequest, Error, ManyOut, ResponseStream, SingleIn},
     traits::DistributedRuntimeProvider,
 };
 
@@ -56,6 +60,15 @@ where
     /// addresses it, then passes it to AddressedPushRouter which does the network traffic.
     addressed: Arc<AddressedPushRouter>,
 
+    /// The free slots of the instances that advertise `max_concurrent_requests`
+    slots: Arc<InstanceSlots>,
+
+    /// The requests in flight from this router, by instance
+    inflight: Arc<InflightRequests>,
+
+    /// The work queue of the endpoint, in [`RouterMode::Pull`]
+    queue: Option<Arc<WorkQueue>>,
+
     /// An internal Rust type. This says that PushRouter is generic over the T and U types,
     /// which are the input and output types of it's `generate` function. It allows the
     /// compiler to specialize us at compile time.
@@ -67,9 +80,16 @@ pub enum RouterMode {
     #[default]
     RoundRobin,
     Random,
+    /// The instance with the fewest requests in flight from this router
+    LeastLoaded,
     Direct(i64),
+    /// Put the requests on the endpoint's work queue, the instances take them as they have free
+    /// slots. See [`super::queue`].
+    Pull,
     // Marker value, KV routing itself is in dynamo-llm
     KV,
+    // Marker value, budget routing by the cost of the instances is in dynamo-llm
+    Budget,
 }
 
 impl RouterMode {
@@ -82,6 +102,9 @@ async fn addressed_router(endpoint: &Endpoint) -> anyhow::Result<Arc<AddressedPu
     AddressedPushRouter::new(
         endpoint.drt().nats_client.client().clone(),
         endpoint.drt().tcp_server().await?,
+        endpoint
+            .drt()
+            .payload_key(endpoint.component().namespace().name()),
     )
 }
 
@@ -92,84 +115,242 @@ where
 {
     pub async fn from_client(client: Client, router_mode: RouterMode) -> anyhow::Result<Self> {
         let addressed = addressed_router(&client.endpoint).await?;
+        let queue = match router_mode {
+            RouterMode::Pull => Some(Arc::new(WorkQueue::new(&client.endpoint).await?)),
+            _ => None,
+        };
         Ok(PushRouter {
             client,
             addressed,
             router_mode,
             round_robin_counter: Arc::new(AtomicU64::new(0)),
+            slots: Arc::new(InstanceSlots::default()),
+            inflight: Arc::new(InflightRequests::default()),
+            queue,
             _phantom: PhantomData,
         })
     }
 
-    /// Issue a request to the next available instance in a round-robin fashion
-    pub async fn round_robin(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
-        let counter = self.round_robin_counter.fetch_add(1, Ordering::Relaxed);
-
-        let instance_id = {
-            let instances = self.client.instances();
-            let count = instances.len();
-            if count == 0 {
-                return Err(anyhow::anyhow!(
-                    "no instances found for endpoint {:?}",
-                    self.client.endpoint.etcd_root()
-                ));
+    /// The instances the zone policy lets us pick from. Never empty.
+    fn candidates(&self) -> anyhow::Result<Vec<Instance>> {
+        let instances = self.client.instances();
+        if instances.is_empty() {
+            return Err(anyhow::anyhow!(
+                "no instances found for endpoint {:?}",
+                self.client.endpoint.etcd_root()
+            ));
+        }
+        let drt = self.client.endpoint.drt();
+        let candidates = drt.locality().filter(drt.zone_policy(), instances);
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!(
+                "no instances in zone {} for endpoint {:?}, and the zone policy forbids other zones",
+                drt.locality().zone.as_deref().unwrap_or_default(),
+                self.client.endpoint.etcd_root()
+            ));
+        }
+        Ok(candidates)
+    }
+
+    /// Record where the request goes, for the cross-zone traffic metrics
+    fn routed_to(&self, instance: &Instance) -> i64 {
+        self.client
+            .endpoint
+            .drt()
+            .locality()
+            .record_routed(instance);
+        instance.id()
+    }
+
+    /// Take a slot of an instance, the one at `offset` in `instances` or the next one with a free
+    /// slot. Waits for the first slot to free up if all instances are full.
+    async fn pick(&self, instances: &[Instance], offset: usize) -> (i64, Slot) {
+        self.slots.forget_gone(&self.client.instances());
+        for i in 0..instances.len() {
+            let instance = &instances[(offset + i) % instances.len()];
+            if let Some(slot) = self.slots.try_acquire(instance) {
+                return (self.routed_to(instance), slot);
             }
-            let offset = counter % count as u64;
-            instances[offset as usize].id()
-        };
-        tracing::trace!("round robin router selected {instance_id}");
+        }
+        tracing::debug!(
+            "all instances of {:?} are at their concurrency limit, waiting for a slot",
+            self.client.endpoint.etcd_root()
+        );
+        let waits = instances.iter().map(|instance| {
+            Box::pin(async move { (instance, self.slots.acquire(instance).await) })
+        });
+        let ((instance, slot), _, _) = futures::future::select_all(waits).await;
+        (self.routed_to(instance), slot)
+    }
 
+    /// Send `request` to `instance_id`, holding `slot` until its responses end
+    async fn send(
+        &self,
+        request: SingleIn<T>,
+        instance_id: i64,
+        slot: Slot,
+    ) -> anyhow::Result<ManyOut<U>> {
         let subject = self.client.endpoint.subject_to(instance_id);
         let request = request.map(|req| AddressedRequest::new(req, subject));
 
-        self.addressed.generate(request).await
+        let inflight = self.inflight.start(instance_id);
+        let responses = self.addressed.generate(request).await?;
+        Ok(slot.hold(responses, inflight))
+    }
+
+    /// The offset in `instances` of the one with the fewest requests in flight. Ties go round
+    /// robin. `instances` must not be empty.
+    fn least_loaded_offset(&self, instances: &[Instance]) -> usize {
+        let counter = self.round_robin_counter.fetch_add(1, Ordering::Relaxed) as usize;
+        (0..instances.len())
+            .map(|i| (counter + i) % instances.len())
+            .min_by_key(|offset| self.inflight.count(instances[*offset].id()))
+            .unwrap_or_default()
+    }
+
+    /// Take a slot of the instance `mode` picks: round robin, least loaded or random
+    async fn select(&self, mode: RouterMode) -> anyhow::Result<(i64, Slot)> {
+        let instances = self.candidates()?;
+        let offset = match mode {
+            RouterMode::LeastLoaded => self.least_loaded_offset(&instances),
+            RouterMode::Random => rand::rng().random::<u64>() as usize % instances.len(),
+            _ => {
+                self.round_robin_counter.fetch_add(1, Ordering::Relaxed) as usize % instances.len()
+            }
+        };
+        let (instance_id, slot) = self.pick(&instances, offset).await;
+        tracing::trace!("{mode:?} router selected {instance_id}");
+        Ok((instance_id, slot))
+    }
+
+    /// Issue a request to the next available instance in a round-robin fashion
+    pub async fn round_robin(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
+        let (instance_id, slot) = self.select(RouterMode::RoundRobin).await?;
+        self.send(request, instance_id, slot).await
+    }
+
+    /// Issue a request to the instance with the fewest requests in flight from this router
+    pub async fn least_loaded(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
+        let (instance_id, slot) = self.select(RouterMode::LeastLoaded).await?;
+        self.send(request, instance_id, slot).await
     }
 
     /// Issue a request to a random endpoint
     pub async fn random(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
-        let instance_id = {
-            let instances = self.client.instances();
-            let count = instances.len();
-            if count == 0 {
-                return Err(anyhow::anyhow!(
-                    "no instances found for endpoint {:?}",
-                    self.client.endpoint.etcd_root()
-                ));
+        let (instance_id, slot) = self.select(RouterMode::Random).await?;
+        self.send(request, instance_id, slot).await
+    }
+
+    /// Send `request` where the router mode says, like `generate`, and tell which instance it
+    /// went to. None when that isn't known: for static endpoints, and in pull mode, where the
+    /// first instance with a free slot takes it.
+    pub async fn route(&self, request: SingleIn<T>) -> anyhow::Result<(Option<i64>, ManyOut<U>)> {
+        // The work queue is the same for static and dynamic endpoints
+        if self.router_mode == RouterMode::Pull {
+            return Ok((None, self.pull(request).await?));
+        }
+        if let InstanceSource::Static = self.client.instance_source.as_ref() {
+            return Ok((None, self.r#static(request).await?));
+        }
+        match self.router_mode {
+            RouterMode::Random | RouterMode::RoundRobin | RouterMode::LeastLoaded => {
+                let (instance_id, slot) = self.select(self.router_mode).await?;
+                let responses = self.send(request, instance_id, slot).await?;
+                Ok((Some(instance_id), responses))
             }
-            let counter = rand::rng().random::<u64>();
-            let offset = counter % count as u64;
-            instances[offset as usize].id()
-        };
-        tracing::trace!("random router selected {instance_id}");
+            RouterMode::Direct(instance_id) => {
+                Ok((Some(instance_id), self.direct(request, instance_id).await?))
+            }
+            RouterMode::Pull => Ok((None, self.pull(request).await?)),
+            RouterMode::KV => {
+                anyhow::bail!("KV routing should not call generate on PushRouter");
+            }
+            RouterMode::Budget => {
+                anyhow::bail!("Budget routing should not call generate on PushRouter");
+            }
+        }
+    }
 
-        let subject = self.client.endpoint.subject_to(instance_id);
-        let request = request.map(|req| AddressedRequest::new(req, subject));
+    /// The instances the zone policy lets us pick from, with the number of requests this router
+    /// has in flight to each. For routers that pick the instance themselves, then send with
+    /// [`PushRouter::direct`].
+    pub fn loads(&self) -> anyhow::Result<Vec<(Instance, usize)>> {
+        Ok(self
+            .candidates()?
+            .into_iter()
+            .map(|instance| {
+                let inflight = self.inflight.count(instance.id());
+                (instance, inflight)
+            })
+            .collect())
+    }
 
-        self.addressed.generate(request).await
+    /// Pick an instance that is not in `exclude`, at random in random mode, the least loaded in
+    /// least loaded mode and round robin otherwise, preferring those with a free slot. Used to send a request again somewhere
+    /// else, e.g. after the first instance timed out.
+    pub fn select_excluding(&self, exclude: &[i64]) -> anyhow::Result<i64> {
+        let mut instances: Vec<Instance> = self
+            .candidates()?
+            .into_iter()
+            .filter(|instance| !exclude.contains(&instance.id()))
+            .collect();
+        if instances.is_empty() {
+            return Err(anyhow::anyhow!(
+                "no instances left to try for endpoint {:?}, already tried {exclude:?}",
+                self.client.endpoint.etcd_root()
+            ));
+        }
+        if instances
+            .iter()
+            .any(|instance| self.slots.has_free(instance))
+        {
+            instances.retain(|instance| self.slots.has_free(instance));
+        }
+        let offset = match self.router_mode {
+            RouterMode::Random => rand::rng().random::<u64>() as usize % instances.len(),
+            RouterMode::LeastLoaded => self.least_loaded_offset(&instances),
+            _ => {
+                self.round_robin_counter.fetch_add(1, Ordering::Relaxed) as usize % instances.len()
+            }
+        };
+        Ok(self.routed_to(&instances[offset]))
     }
 
-    /// Issue a request to a specific endpoint
+    /// Issue a request to a specific endpoint. Waits for a slot if the instance is at its
+    /// concurrency limit.
     pub async fn direct(
         &self,
         request: SingleIn<T>,
         instance_id: i64,
     ) -> anyhow::Result<ManyOut<U>> {
-        let found = {
-            let instances = self.client.instances();
-            instances.iter().any(|ep| ep.id() == instance_id)
-        };
-
-        if !found {
+        let Some(instance) = self
+            .client
+            .instances()
+            .into_iter()
+            .find(|ep| ep.id() == instance_id)
+        else {
             return Err(anyhow::anyhow!(
                 "instance_id={instance_id} not found for endpoint {:?}",
                 self.client.endpoint.etcd_root()
             ));
-        }
+        };
+        let slot = self.slots.acquire(&instance).await;
+        self.routed_to(&instance);
 
-        let subject = self.client.endpoint.subject_to(instance_id);
-        let request = request.map(|req| AddressedRequest::new(req, subject));
+        self.send(request, instance_id, slot).await
+    }
 
-        self.addressed.generate(request).await
+    /// Put `request` on the endpoint's work queue, for the first instance with a free slot. Waits
+    /// in the queue while there is none.
+    pub async fn pull(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
+        let Some(queue) = self.queue.as_ref() else {
+            anyhow::bail!(
+                "The router of {:?} is not in pull mode",
+                self.client.endpoint.etcd_root()
+            );
+        };
+        tracing::trace!("queueing request on {}", queue.subject());
+        self.addressed.enqueue(request, queue).await
     }
 
     pub async fn r#static(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
@@ -187,17 +368,186 @@ where
     T: Data + Serialize,
     U: Data + for<'de> Deserialize<'de>,
 {
+    #[tracing::instrument(name = "route", skip_all, fields(request_id = request.id()))]
     async fn generate(&self, request: SingleIn<T>) -> Result<ManyOut<U>, Error> {
-        match self.client.instance_source.as_ref() {
-            InstanceSource::Static => self.r#static(request).await,
-            InstanceSource::Dynamic(_) => match self.router_mode {
-                RouterMode::Random => self.random(request).await,
-                RouterMode::RoundRobin => self.round_robin(request).await,
-                RouterMode::Direct(instance_id) => self.direct(request, instance_id).await,
-                RouterMode::KV => {
-                    anyhow::bail!("KV routing should not call generate on PushRouter");
-                }
-            },
+        let (_, responses) = self.route(request).await?;
+        Ok(responses)
+    }
+}
+
+/// A request's slot of an instance, freed when dropped
+struct Slot(Option<OwnedSemaphorePermit>);
+
+impl Slot {
+    /// Keep the slot, and count the request in flight, until `responses` ends or is dropped
+    fn hold<U: Data>(self, responses: ManyOut<U>, inflight: InflightRequest) -> ManyOut<U> {
+        let ctx = responses.context();
+        let responses = responses.map(move |response| {
+            let _held = (&self, &inflight);
+            response
+        });
+        ResponseStream::new(Box::pin(responses), ctx)
+    }
+}
+
+/// How many requests this router has in flight to each instance
+#[derive(Default)]
+struct InflightRequests {
+    counts: Mutex<HashMap<i64, usize>>,
+}
+
+impl InflightRequests {
+    fn count(&self, instance_id: i64) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(&instance_id)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Count a request to `instance_id` until the returned guard is dropped
+    fn start(self: &Arc<Self>, instance_id: i64) -> InflightRequest {
+        *self.counts.lock().unwrap().entry(instance_id).or_default() += 1;
+        InflightRequest {
+            requests: self.clone(),
+            instance_id,
         }
     }
 }
+
+/// A request in flight, see [`InflightRequests::start`]
+struct InflightRequest {
+    requests: Arc<InflightRequests>,
+    instance_id: i64,
+}
+
+impl Drop for InflightRequest {
+    fn drop(&mut self) {
+        let mut counts = self.requests.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.instance_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.instance_id);
+            }
+        }
+    }
+}
+
+/// A semaphore per instance that advertises `max_concurrent_requests`, so that a router doesn't
+/// send an instance more requests than it takes before its load metrics show it is busy
+#[derive(Default)]
+struct InstanceSlots {
+    /// The limit each semaphore was created with, by instance id
+    semaphores: Mutex<HashMap<i64, (u32, Arc<Semaphore>)>>,
+}
+
+impl InstanceSlots {
+    /// The semaphore of `instance`, None if it takes any number of requests
+    fn semaphore(&self, instance: &Instance) -> Option<Arc<Semaphore>> {
+        let max = instance.max_concurrent_requests.filter(|max| *max > 0)?;
+        let mut semaphores = self.semaphores.lock().unwrap();
+        let (limit, semaphore) = semaphores
+            .entry(instance.id())
+            .or_insert_with(|| (max, Arc::new(Semaphore::new(max as usize))));
+        if *limit != max {
+            // Registered again with another limit. Requests in flight hold the old semaphore.
+            *limit = max;
+            *semaphore = Arc::new(Semaphore::new(max as usize));
+        }
+        Some(semaphore.clone())
+    }
+
+    /// A slot of `instance`, None if all are taken
+    fn try_acquire(&self, instance: &Instance) -> Option<Slot> {
+        match self.semaphore(instance) {
+            Some(semaphore) => semaphore.try_acquire_owned().ok().map(|p| Slot(Some(p))),
+            None => Some(Slot(None)),
+        }
+    }
+
+    /// A slot of `instance`, once one is free
+    async fn acquire(&self, instance: &Instance) -> Slot {
+        match self.semaphore(instance) {
+            // safety: The semaphores are never closed
+            Some(semaphore) => Slot(Some(semaphore.acquire_owned().await.unwrap())),
+            None => Slot(None),
+        }
+    }
+
+    fn has_free(&self, instance: &Instance) -> bool {
+        self.semaphore(instance)
+            .is_none_or(|semaphore| semaphore.available_permits() > 0)
+    }
+
+    /// Drop the semaphores of the instances not in `instances` anymore
+    fn forget_gone(&self, instances: &[Instance]) {
+        self.semaphores
+            .lock()
+            .unwrap()
+            .retain(|id, _| instances.iter().any(|instance| instance.id() == *id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::TransportType;
+
+    fn instance(id: i64, max_concurrent_requests: Option<u32>) -> Instance {
+        Instance {
+            component: "backend".to_string(),
+            endpoint: "generate".to_string(),
+            namespace: "test".to_string(),
+            instance_id: id,
+            transport: TransportType::NatsTcp(format!("test.backend.generate-{id}")),
+            region: None,
+            zone: None,
+            max_concurrent_requests,
+            description: None,
+            cost_per_gpu_hour: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_instance_slots() {
+        let slots = InstanceSlots::default();
+        let unlimited = instance(1, None);
+        let limited = instance(2, Some(2));
+
+        let held: Vec<Slot> = (0..10)
+            .filter_map(|_| slots.try_acquire(&unlimited))
+            .collect();
+        assert_eq!(held.len(), 10);
+
+        let first = slots.try_acquire(&limited).unwrap();
+        let _second = slots.try_acquire(&limited).unwrap();
+        assert!(slots.try_acquire(&limited).is_none());
+        assert!(!slots.has_free(&limited));
+
+        // A slot is given back when its request ends
+        drop(first);
+        let _third = slots.acquire(&limited).await;
+        assert!(slots.try_acquire(&limited).is_none());
+
+        // A new limit starts over
+        assert!(slots.try_acquire(&instance(2, Some(3))).is_some());
+
+        slots.forget_gone(&[unlimited]);
+        assert!(slots.semaphores.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inflight_requests() {
+        let inflight = Arc::new(InflightRequests::default());
+        let first = inflight.start(1);
+        let _second = inflight.start(1);
+        let _third = inflight.start(2);
+        assert_eq!(inflight.count(1), 2);
+        assert_eq!(inflight.count(2), 1);
+        assert_eq!(inflight.count(3), 0);
+
+        drop(first);
+        assert_eq!(inflight.count(1), 1);
+    }
+}
@@ -12,3 +12,190 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+
+//! The work queue of an endpoint, for pull mode.
+//!
+//! In pull mode the routers don't pick a worker, they put each request on a NATS JetStream stream
+//! of the endpoint, see [`super::push_router::RouterMode::Pull`]. The workers take requests from
+//! it only while they have a free slot, see [`super::super::ingress::pull_endpoint`], so each
+//! worker gets as much work as its own capacity allows. The messages are the same as in push mode,
+//! the responses stream back to the router over TCP.
+//!
+//! A router waits [`MAX_QUEUE_WAIT`] for a worker to take its request, and the request carries
+//! that deadline so that workers skip it after the router gave up. A request the router gave up
+//! on, or whose client went away, is deleted from the queue.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_nats::jetstream;
+
+use super::*;
+use crate::component::Endpoint;
+use crate::slug::Slug;
+use crate::traits::DistributedRuntimeProvider;
+
+/// How long a router waits for a worker to take a request
+pub const MAX_QUEUE_WAIT: Duration = Duration::from_secs(60);
+
+/// Requests not taken by then are dropped, the router gave up on them long before
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Header with the time, in ms since the epoch, after which a request is not served
+const DEADLINE_HEADER: &str = "Dyn-Queue-Deadline";
+
+/// How long a worker waits for a request in one fetch
+const FETCH_EXPIRES: Duration = Duration::from_secs(10);
+
+/// All the workers of an endpoint share the one consumer, each request goes to one of them
+const CONSUMER_NAME: &str = "workers";
+
+/// The JetStream stream holding the requests of an endpoint until a worker takes them
+pub struct WorkQueue {
+    jetstream: jetstream::Context,
+    stream: jetstream::stream::Stream,
+    subject: String,
+}
+
+impl WorkQueue {
+    /// The work queue of `endpoint`, created if it doesn't exist yet
+    pub async fn new(endpoint: &Endpoint) -> Result<Self> {
+        let jetstream = endpoint.drt().nats_client().jetstream().clone();
+        let subject = endpoint.queue_subject();
+        let name = format!(
+            "{}_{}_queue",
+            endpoint.component().service_name(),
+            Slug::slugify(endpoint.name())
+        );
+        let stream = jetstream
+            .get_or_create_stream(jetstream::stream::Config {
+                name,
+                subjects: vec![subject.clone()],
+                retention: jetstream::stream::RetentionPolicy::WorkQueue,
+                max_age: MAX_AGE,
+                ..Default::default()
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed creating work queue of {subject}: {err}"))?;
+        Ok(WorkQueue {
+            jetstream,
+            stream,
+            subject,
+        })
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Add a packaged request to be taken within [`MAX_QUEUE_WAIT`], returns its sequence number
+    /// once JetStream stored it
+    pub async fn publish(&self, buffer: Bytes) -> Result<u64> {
+        let deadline = unix_millis() + MAX_QUEUE_WAIT.as_millis() as u64;
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert(DEADLINE_HEADER, deadline.to_string().as_str());
+        let ack = self
+            .jetstream
+            .publish_with_headers(self.subject.clone(), headers, buffer)
+            .await?
+            .await?;
+        Ok(ack.sequence)
+    }
+
+    /// Remove the request `sequence` if no worker took it yet
+    pub async fn delete(&self, sequence: u64) -> Result<()> {
+        match self.stream.delete_message(sequence).await {
+            Ok(_) => Ok(()),
+            // Taken and acked already
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    jetstream::stream::DeleteMessageErrorKind::JetStream(_)
+                ) =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The consumer a worker takes the requests from
+    pub async fn subscribe(&self) -> Result<WorkQueueSubscriber> {
+        let consumer = self
+            .stream
+            .get_or_create_consumer(
+                CONSUMER_NAME,
+                jetstream::consumer::pull::Config {
+                    durable_name: Some(CONSUMER_NAME.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|err| {
+                anyhow::anyhow!("Failed subscribing to work queue {}: {err}", self.subject)
+            })?;
+        Ok(WorkQueueSubscriber { consumer })
+    }
+}
+
+/// Takes the requests of a [`WorkQueue`] one at a time, so that a worker never holds more than
+/// it has slots for
+pub struct WorkQueueSubscriber {
+    consumer: jetstream::consumer::PullConsumer,
+}
+
+#[async_trait]
+impl WorkQueueConsumer for WorkQueueSubscriber {
+    async fn dequeue(&self) -> Result<Bytes, String> {
+        loop {
+            let mut batch = self
+                .consumer
+                .fetch()
+                .max_messages(1)
+                .expires(FETCH_EXPIRES)
+                .messages()
+                .await
+                .map_err(|err| err.to_string())?;
+            let Some(message) = batch.next().await else {
+                continue;
+            };
+            let message = message.map_err(|err| err.to_string())?;
+            // Acked when taken, like the push endpoint answers the NATS request right away. A
+            // worker that fails mid-request fails its response stream, the router handles that.
+            message.ack().await.map_err(|err| err.to_string())?;
+            if is_expired(message.headers.as_ref(), unix_millis()) {
+                tracing::debug!("Skipping a queued request its router gave up on");
+                continue;
+            }
+            return Ok(message.payload.clone());
+        }
+    }
+}
+
+/// Whether the request with `headers` is past its deadline at `now`. Requests without one, from
+/// older routers, never are.
+fn is_expired(headers: Option<&async_nats::HeaderMap>, now: u64) -> bool {
+    headers
+        .and_then(|headers| headers.get(DEADLINE_HEADER))
+        .and_then(|deadline| deadline.as_str().parse::<u64>().ok())
+        .is_some_and(|deadline| deadline < now)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_expired() {
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert(DEADLINE_HEADER, "1000");
+        assert!(!is_expired(Some(&headers), 1000));
+        assert!(is_expired(Some(&headers), 1001));
+        assert!(!is_expired(None, 1001));
+    }
+}
@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod pull_endpoint;
 pub mod push_endpoint;
 pub mod push_handler;
 
@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use super::*;
+use crate::payload_encryption::PayloadKey;
+use anyhow::Result;
+use derive_builder::Builder;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+/// Wait before taking from the queue again after it failed, e.g. while NATS reconnects
+const DEQUEUE_RETRY: Duration = Duration::from_secs(1);
+
+/// Takes requests from the work queue of an endpoint while there is a free slot, see
+/// [`super::super::egress::queue`]. The requests are the same as those of a
+/// [`super::push_endpoint::PushEndpoint`].
+#[derive(Builder)]
+pub struct PullEndpoint {
+    pub service_handler: Arc<dyn PushWorkHandler>,
+    pub cancellation_token: CancellationToken,
+    /// Stop taking new requests but finish the ones in flight, see [`crate::drain`]
+    #[builder(default)]
+    pub drain_token: CancellationToken,
+    /// Decrypts the requests and encrypts the responses, see [`crate::payload_encryption`]
+    #[builder(default)]
+    pub payload_key: Option<Arc<PayloadKey>>,
+    /// Most requests served at once
+    pub capacity: u32,
+}
+
+impl PullEndpoint {
+    pub fn builder() -> PullEndpointBuilder {
+        PullEndpointBuilder::default()
+    }
+
+    pub async fn start(self, queue: Arc<dyn WorkQueueConsumer + Send + Sync>) -> Result<()> {
+        if self.capacity == 0 {
+            anyhow::bail!("The capacity of a pull endpoint must be at least 1");
+        }
+        let slots = Arc::new(Semaphore::new(self.capacity as usize));
+
+        loop {
+            // Only take a request once there is a slot for it, the rest stay queued for the
+            // other workers
+            let permit = tokio::select! {
+                biased;
+
+                _ = self.cancellation_token.cancelled() => break,
+                _ = self.drain_token.cancelled() => break,
+                // safety: The semaphore is never closed
+                permit = slots.clone().acquire_owned() => permit.unwrap(),
+            };
+
+            let payload = tokio::select! {
+                biased;
+
+                _ = self.cancellation_token.cancelled() => break,
+                _ = self.drain_token.cancelled() => break,
+                payload = queue.dequeue() => payload,
+            };
+            let payload = match payload {
+                Ok(payload) => payload,
+                Err(err) => {
+                    tracing::warn!(%err, "Failed taking a request from the work queue");
+                    tokio::select! {
+                        _ = self.cancellation_token.cancelled() => break,
+                        _ = tokio::time::sleep(DEQUEUE_RETRY) => continue,
+                    }
+                }
+            };
+
+            let ingress = self.service_handler.clone();
+            let payload_key = self.payload_key.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                tracing::trace!("handling queued request");
+                if let Err(e) = ingress.handle_payload(payload, payload_key).await {
+                    tracing::warn!("Failed to handle queued request: {:?}", e);
+                }
+            });
+        }
+
+        // await for all inflight requests to complete
+        tracing::info!(
+            "Waiting for {} inflight queued requests to complete",
+            self.capacity as usize - slots.available_permits()
+        );
+        // safety: The semaphore is never closed
+        let _all = slots.acquire_many(self.capacity).await.unwrap();
+        tracing::info!("All inflight queued requests completed");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::{mpsc, Mutex};
+
+    struct ChannelQueue(Mutex<mpsc::UnboundedReceiver<Bytes>>);
+
+    #[async_trait]
+    impl WorkQueueConsumer for ChannelQueue {
+        async fn dequeue(&self) -> Result<Bytes, String> {
+            // A closed channel is an empty queue
+            match self.0.lock().await.recv().await {
+                Some(payload) => Ok(payload),
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct SlowHandler {
+        running: AtomicUsize,
+        most_running: AtomicUsize,
+        handled: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl PushWorkHandler for SlowHandler {
+        async fn handle_payload(
+            &self,
+            _payload: Bytes,
+            _payload_key: Option<Arc<PayloadKey>>,
+        ) -> Result<(), PipelineError> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pull_endpoint_capacity() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        for i in 0..10 {
+            tx.send(Bytes::from(format!("request {i}"))).unwrap();
+        }
+        let queue = Arc::new(ChannelQueue(Mutex::new(rx)));
+        let handler = Arc::new(SlowHandler::default());
+        let drain_token = CancellationToken::new();
+        let endpoint = PullEndpoint::builder()
+            .service_handler(handler.clone())
+            .cancellation_token(CancellationToken::new())
+            .drain_token(drain_token.clone())
+            .capacity(3)
+            .build()
+            .unwrap();
+        let task = tokio::spawn(endpoint.start(queue));
+
+        while handler.handled.load(Ordering::SeqCst) < 10 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(handler.most_running.load(Ordering::SeqCst), 3);
+
+        // A drain stops taking requests and waits for those in flight
+        tx.send(Bytes::from("last")).unwrap();
+        drain_token.cancel();
+        task.await.unwrap().unwrap();
+        assert_eq!(handler.running.load(Ordering::SeqCst), 0);
+    }
+}
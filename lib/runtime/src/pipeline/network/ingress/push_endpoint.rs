@@ -16,6 +16,7 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use super::*;
+use crate::payload_encryption::PayloadKey;
 use anyhow::Result;
 use async_nats::service::endpoint::Endpoint;
 use derive_builder::Builder;
@@ -26,11 +27,21 @@ use tokio_util::sync::CancellationToken;
 pub struct PushEndpoint {
     pub service_handler: Arc<dyn PushWorkHandler>,
     pub cancellation_token: CancellationToken,
+    /// Stop taking new requests but finish the ones in flight, see [`crate::drain`]
+    #[builder(default)]
+    pub drain_token: CancellationToken,
+    /// Decrypts the requests and encrypts the responses, see [`crate::payload_encryption`]
+    #[builder(default)]
+    pub payload_key: Option<Arc<PayloadKey>>,
 }
 
 /// version of crate
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Requests with this header are answered and otherwise ignored, to check that an instance can be
+/// reached over NATS
+pub const PROBE_HEADER: &str = "Dyn-Probe";
+
 impl PushEndpoint {
     pub fn builder() -> PushEndpointBuilder {
         PushEndpointBuilder::default()
@@ -59,6 +70,14 @@ impl PushEndpoint {
                     }
                     break;
                 }
+
+                _ = self.drain_token.cancelled() => {
+                    tracing::info!("Draining service");
+                    if let Err(e) = endpoint.stop().await {
+                        tracing::warn!("Failed to stop NATS service: {:?}", e);
+                    }
+                    break;
+                }
             };
 
             if let Some(req) = req {
@@ -66,8 +85,17 @@ impl PushEndpoint {
                 if let Err(e) = req.respond(Ok(response.into())).await {
                     tracing::warn!("Failed to respond to request; this may indicate the request has shutdown: {:?}", e);
                 }
+                let is_probe = req
+                    .message
+                    .headers
+                    .as_ref()
+                    .is_some_and(|headers| headers.get(PROBE_HEADER).is_some());
+                if is_probe {
+                    continue;
+                }
 
                 let ingress = self.service_handler.clone();
+                let payload_key = self.payload_key.clone();
                 let worker_id = "".to_string();
 
                 // increment the inflight counter
@@ -77,7 +105,9 @@ impl PushEndpoint {
 
                 tokio::spawn(async move {
                     tracing::trace!(worker_id, "handling new request");
-                    let result = ingress.handle_payload(req.message.payload).await;
+                    let result = ingress
+                        .handle_payload(req.message.payload, payload_key)
+                        .await;
                     match result {
                         Ok(_) => {
                             tracing::trace!(worker_id, "request handled successfully");
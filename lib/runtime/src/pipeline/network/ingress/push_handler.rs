@@ -14,7 +14,9 @@
 // limitations under the License.
 
 use super::*;
+use crate::payload_encryption::{self, Direction, PayloadKey};
 use serde::{Deserialize, Serialize};
+use tracing::Instrument as _;
 
 #[async_trait]
 impl<T: Data, U: Data> PushWorkHandler for Ingress<SingleIn<T>, ManyOut<U>>
@@ -22,7 +24,11 @@ where
     T: Data + for<'de> Deserialize<'de> + std::fmt::Debug,
     U: Data + Serialize + std::fmt::Debug,
 {
-    async fn handle_payload(&self, payload: Bytes) -> Result<(), PipelineError> {
+    async fn handle_payload(
+        &self,
+        payload: Bytes,
+        payload_key: Option<Arc<PayloadKey>>,
+    ) -> Result<(), PipelineError> {
         // decode the control message and the request
         let msg = TwoPartCodec::default()
             .decode_message(payload)?
@@ -46,6 +52,15 @@ where
                         ));
                     }
                 };
+                let data = match payload_encryption::open(
+                    payload_key.as_deref(),
+                    Direction::Request,
+                    &control_msg.id,
+                    data,
+                ) {
+                    Ok(data) => data,
+                    Err(err) => return Err(self.reject(control_msg, err.to_string()).await),
+                };
                 let request: T = serde_json::from_slice(&data)?;
                 (control_msg, request)
             }
@@ -54,6 +69,47 @@ where
             }
         };
 
+        // continue the sender's trace for the rest of the request
+        let span = tracing::info_span!("handle_request", request_id = %control_msg.id);
+        if let Some(trace_context) = control_msg.trace_context.as_ref() {
+            crate::otel::set_parent(&span, trace_context);
+        }
+        self.handle_request(control_msg, request, payload_key)
+            .instrument(span)
+            .await
+    }
+}
+
+impl<T: Data, U: Data> Ingress<SingleIn<T>, ManyOut<U>>
+where
+    T: Data + for<'de> Deserialize<'de> + std::fmt::Debug,
+    U: Data + Serialize + std::fmt::Debug,
+{
+    /// Fail the request of `control_msg` with `error`, so that the router isn't left waiting for
+    /// a worker to connect back
+    async fn reject(&self, control_msg: RequestControlMessage, error: String) -> PipelineError {
+        tracing::error!(request_id = %control_msg.id, %error, "Rejecting request");
+        let context = Context::with_id((), control_msg.id);
+        match tcp::client::TcpClient::create_response_steam(
+            context.context(),
+            control_msg.connection_info,
+        )
+        .await
+        {
+            Ok(mut publisher) => {
+                let _result = publisher.send_prologue(Some(error.clone())).await;
+            }
+            Err(err) => tracing::warn!(%err, "Failed telling the router its request failed"),
+        }
+        PipelineError::Generic(error)
+    }
+
+    async fn handle_request(
+        &self,
+        control_msg: RequestControlMessage,
+        request: T,
+        payload_key: Option<Arc<PayloadKey>>,
+    ) -> Result<(), PipelineError> {
         // extend request with context
         tracing::trace!("received control message: {:?}", control_msg);
         tracing::trace!("received request: {:?}", request);
@@ -101,7 +157,20 @@ where
             tracing::trace!("Sending response: {:?}", resp);
             let resp_bytes = serde_json::to_vec(&resp)
                 .expect("fatal error: invalid response object - this should never happen");
-            if (publisher.send(resp_bytes.into()).await).is_err() {
+            let resp_bytes = match payload_encryption::seal(
+                payload_key.as_deref(),
+                Direction::Response,
+                context.id(),
+                resp_bytes.into(),
+            ) {
+                Ok(resp_bytes) => resp_bytes,
+                Err(err) => {
+                    tracing::error!(%err, "Failed encrypting response");
+                    context.stop_generating();
+                    break;
+                }
+            };
+            if (publisher.send(resp_bytes).await).is_err() {
                 tracing::error!("Failed to publish response for stream {}", context.id());
                 context.stop_generating();
                 break;
@@ -49,6 +49,10 @@ pub struct TcpStreamConnectionInfo {
     pub subject: String,
     pub context: String,
     pub stream_type: StreamType,
+    /// The receiver wants the data frames numbered, see [`super::FrameHeader`]. False when the
+    /// receiver predates numbering.
+    #[serde(default)]
+    pub sequenced: bool,
 }
 
 impl From<TcpStreamConnectionInfo> for ConnectionInfo {
@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 
 use futures::{SinkExt, StreamExt};
@@ -95,6 +96,7 @@ impl TcpClient {
             ));
         }
 
+        let sequenced = info.sequenced;
         let stream = TcpClient::connect(&info.address).await?;
         let (read_half, write_half) = tokio::io::split(stream);
 
@@ -193,6 +195,7 @@ impl TcpClient {
         let stream_sender = StreamSender {
             tx: bytes_tx,
             prologue,
+            next_seq: sequenced.then(|| AtomicU64::new(0)),
         };
 
         Ok(stream_sender)
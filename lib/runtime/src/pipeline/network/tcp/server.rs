@@ -44,10 +44,11 @@ use crate::pipeline::{
     network::{
         codec::{TwoPartMessage, TwoPartMessageType},
         tcp::StreamType,
-        ResponseService, ResponseStreamPrologue,
+        FrameOrder, FrameSequence, ResponseService, ResponseStreamPrologue,
     },
     PipelineError,
 };
+use crate::protocols::annotated::Annotated;
 use crate::{error, ErrorContext, Result};
 
 #[allow(dead_code)]
@@ -232,6 +233,7 @@ impl ResponseService for TcpStreamServer {
                     subject: sender_subject.clone(),
                     context: options.context.id().to_string(),
                     stream_type: StreamType::Request,
+                    sequenced: false,
                 }
                 .into(),
                 stream_provider: pending_sender_rx,
@@ -262,6 +264,7 @@ impl ResponseService for TcpStreamServer {
                     subject: receiver_subject.clone(),
                     context: options.context.id().to_string(),
                     stream_type: StreamType::Response,
+                    sequenced: true,
                 }
                 .into(),
                 stream_provider: pending_recver_rx,
@@ -496,6 +499,7 @@ async fn tcp_listener(
     ) {
         // loop over reading the tcp stream and checking if the writer is closed
         let mut can_stop = true;
+        let mut sequence = FrameSequence::default();
         loop {
             tokio::select! {
                 biased;
@@ -523,8 +527,26 @@ async fn tcp_listener(
                         Some(Ok(msg)) => {
                             let (header, data) = msg.into_parts();
 
-                            // received a control message
-                            if !header.is_empty() {
+                            if !header.is_empty() && !data.is_empty() {
+                                // a numbered data frame
+                                match sequence.check(&header) {
+                                    Ok(FrameOrder::Next) => {}
+                                    Ok(FrameOrder::Duplicate) => {
+                                        tracing::debug!(request_id = context.id(), "dropping duplicate response frame");
+                                        continue;
+                                    }
+                                    Err(err) => {
+                                        tracing::error!(request_id = context.id(), "{err:#}");
+                                        // Annotated errors reach the client, other response types drop it
+                                        let error = serde_json::to_vec(&Annotated::<()>::from_error(format!("{err:#}")))
+                                            .expect("an annotated error always serializes");
+                                        let _ = response_tx.send(error.into()).await;
+                                        control_tx.send(ControlMessage::Kill).await.expect("the control channel should not be closed");
+                                        break;
+                                    }
+                                }
+                            } else if !header.is_empty() {
+                                // received a control message
                                 match process_control_message(header) {
                                     Ok(ControlAction::Continue) => {}
                                     Ok(ControlAction::Shutdown) => {
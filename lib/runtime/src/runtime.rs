@@ -27,6 +27,9 @@
 
 use super::{error, Result, Runtime, RuntimeType};
 use crate::config::{self, RuntimeConfig};
+use crate::drain::{Drain, DrainGuard};
+use crate::lifecycle::{LifecycleHooks, LifecycleStage};
+use crate::tasks::TaskRegistry;
 
 use futures::Future;
 use once_cell::sync::OnceCell;
@@ -56,6 +59,9 @@ impl Runtime {
             primary: runtime,
             secondary,
             cancellation_token,
+            lifecycle: Arc::new(LifecycleHooks::new()),
+            drain: Arc::new(Drain::new()),
+            tasks: TaskRegistry::new(),
         })
     }
 
@@ -111,6 +117,47 @@ impl Runtime {
         self.cancellation_token.child_token()
     }
 
+    /// Run `hook` at `stage` of the lifecycle, see [`crate::lifecycle`].
+    /// Fails if that stage already ran.
+    pub fn add_lifecycle_hook<F, Fut>(&self, stage: LifecycleStage, hook: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.lifecycle.add(stage, hook)
+    }
+
+    /// Run the hooks registered for `stage`, if it hasn't run yet. Endpoints and [`crate::Worker`]
+    /// call this, applications that drive their own shutdown call it for the shutdown stages.
+    pub async fn run_lifecycle_hooks(&self, stage: LifecycleStage) -> Result<()> {
+        self.lifecycle.run(stage).await
+    }
+
+    /// Take the endpoints of this runtime out of service, see [`crate::drain`]. Runs the pre-drain
+    /// lifecycle hooks, then waits up to `timeout` for the endpoints to finish their in-flight
+    /// requests. Returns false if some requests were still running at the deadline. The runtime
+    /// keeps running, call [`Runtime::shutdown`] next.
+    pub async fn drain(&self, timeout: std::time::Duration) -> bool {
+        // Shutdown hooks log their own errors
+        let _ = self.run_lifecycle_hooks(LifecycleStage::PreDrain).await;
+        self.drain.drain(timeout).await
+    }
+
+    /// Cancelled once [`Runtime::drain`] was called
+    pub fn drain_token(&self) -> CancellationToken {
+        self.drain.token()
+    }
+
+    /// Counts an endpoint as serving for [`Runtime::drain`] until dropped
+    pub(crate) fn drain_guard(&self) -> DrainGuard {
+        self.drain.guard()
+    }
+
+    /// The long-lived tasks of this runtime, see [`crate::tasks`]
+    pub fn tasks(&self) -> &TaskRegistry {
+        &self.tasks
+    }
+
     /// Shuts down the [`Runtime`] instance
     pub fn shutdown(&self) {
         self.cancellation_token.cancel();
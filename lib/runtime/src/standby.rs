@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A standby worker has its model loaded and its endpoints serving, but no router sends it
+//! requests because its instances aren't registered. Activating it registers them, so scaling up
+//! from a pool of standby workers takes an etcd write instead of a model load.
+//!
+//! A worker starts in standby with `DYN_WORKER_STANDBY=true`. Its endpoints then register under
+//! [`STANDBY_ROOT_PATH`] instead of [`INSTANCE_ROOT_PATH`], with the same key and [`Instance`],
+//! which lists the pool. [`DistributedRuntime::request_activation`] activates the worker with
+//! that instance id, and a worker can activate itself with [`DistributedRuntime::activate`]. Each
+//! endpoint then removes its standby key and registers its instance.
+//!
+//! A standby worker can be drained like an active one, it removes its standby keys.
+//!
+//! With a cluster key, activation requests must be signed, see [`crate::control_signing`].
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::component::{Instance, INSTANCE_ROOT_PATH};
+use crate::control_signing;
+use crate::transports::etcd;
+use crate::{DistributedRuntime, Result};
+
+/// Standby endpoints register here, instead of under [`INSTANCE_ROOT_PATH`]
+pub const STANDBY_ROOT_PATH: &str = "standby";
+
+/// How long to wait for an instance to confirm it is active
+const ACTIVATION_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn activation_subject(instance_id: i64) -> String {
+    format!("dynamo.activate.{instance_id:x}")
+}
+
+/// The standby key of the endpoint whose instance key is `instance_path`
+fn standby_path(instance_path: &str) -> String {
+    match instance_path.strip_prefix(INSTANCE_ROOT_PATH) {
+        Some(rest) => format!("{STANDBY_ROOT_PATH}{rest}"),
+        None => format!("{STANDBY_ROOT_PATH}/{instance_path}"),
+    }
+}
+
+impl DistributedRuntime {
+    /// True until the worker is activated, if it started in standby
+    pub fn is_standby(&self) -> bool {
+        !self.activated.is_cancelled()
+    }
+
+    /// Make the endpoints of this standby worker routable. Does nothing on an active worker.
+    pub fn activate(&self) {
+        if self.is_standby() {
+            tracing::info!("Activating standby worker");
+        }
+        self.activated.cancel();
+    }
+
+    /// Cancelled once the worker is active, from the start unless it started in standby
+    pub fn activated_token(&self) -> CancellationToken {
+        self.activated.clone()
+    }
+
+    /// The endpoints of the standby workers, from etcd
+    pub async fn standby_instances(&self) -> Result<Vec<Instance>> {
+        let Some(etcd_client) = self.etcd_client() else {
+            anyhow::bail!("Listing standby workers requires etcd");
+        };
+        let mut instances = Vec::new();
+        for kv in etcd_client.kv_get_prefix(STANDBY_ROOT_PATH).await? {
+            match serde_json::from_slice::<Instance>(kv.value()) {
+                Ok(instance) => instances.push(instance),
+                Err(err) => {
+                    let key = kv.key_str().unwrap_or_default();
+                    tracing::warn!(%err, key, "Invalid standby instance in etcd");
+                }
+            }
+        }
+        Ok(instances)
+    }
+
+    /// Ask the standby worker `instance_id` to activate. Returns once it started registering its
+    /// instances. Succeeds if it was active already.
+    pub async fn request_activation(&self, instance_id: i64) -> Result<()> {
+        let subject = activation_subject(instance_id);
+        let headers = control_signing::sign(self.control_signer().as_deref(), &subject, &[]);
+        let request = async_nats::Request::new()
+            .headers(headers)
+            .timeout(Some(ACTIVATION_REQUEST_TIMEOUT));
+        let reply = self
+            .nats_client()
+            .client()
+            .send_request(subject.clone(), request)
+            .await
+            .map_err(|err| match err.kind() {
+                async_nats::RequestErrorKind::TimedOut => anyhow::anyhow!(
+                    "Instance {instance_id:x} did not confirm activation within {ACTIVATION_REQUEST_TIMEOUT:?}"
+                ),
+                _ => anyhow::anyhow!("Activation request on {subject} failed: {err}"),
+            })?;
+        // An empty reply confirms, otherwise it says why the request was refused
+        if !reply.payload.is_empty() {
+            anyhow::bail!(
+                "Instance {instance_id:x} refused to activate: {}",
+                String::from_utf8_lossy(&reply.payload)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Answer the activation requests sent to this instance, the one holding the primary lease
+/// `lease_id`, until the runtime shuts down
+pub(crate) async fn serve_activation_requests(
+    drt: DistributedRuntime,
+    lease_id: i64,
+) -> Result<()> {
+    let nats = drt.nats_client().client().clone();
+    let signer = drt.control_signer();
+    let subject = activation_subject(lease_id);
+    let mut requests = nats.subscribe(subject.clone()).await?;
+    let cancel_token = drt.runtime().child_token();
+    loop {
+        let request = tokio::select! {
+            _ = cancel_token.cancelled() => return Ok(()),
+            request = requests.next() => match request {
+                Some(request) => request,
+                None => anyhow::bail!("Activation requests subscription closed"),
+            },
+        };
+        let reply = match control_signing::verify(
+            signer.as_deref(),
+            &subject,
+            request.headers.as_ref(),
+            &request.payload,
+        ) {
+            Ok(()) => {
+                drt.activate();
+                Bytes::new()
+            }
+            Err(err) => {
+                tracing::warn!(%err, "Activation request refused");
+                Bytes::from(err.to_string())
+            }
+        };
+        if let Some(reply_to) = request.reply {
+            if let Err(err) = nats.publish(reply_to, reply).await {
+                tracing::warn!(%err, "Failed confirming activation request");
+            }
+        }
+    }
+}
+
+/// Register the endpoint of a standby worker as standby and wait until the worker is activated.
+/// Returns false if it drained or shut down first. Removes the standby key either way.
+pub(crate) async fn stand_by(
+    drt: &DistributedRuntime,
+    etcd_client: &etcd::Client,
+    instance_path: &str,
+    info: Vec<u8>,
+    lease_id: i64,
+    cancel_token: &CancellationToken,
+) -> Result<bool> {
+    let path = standby_path(instance_path);
+    etcd_client
+        .kv_create(path.clone(), info, Some(lease_id))
+        .await?;
+    tracing::info!(path, "Standing by until activated");
+    let activated = tokio::select! {
+        _ = drt.activated.cancelled() => true,
+        _ = drt.runtime().drain_token().cancelled() => false,
+        _ = cancel_token.cancelled() => false,
+    };
+    if let Err(err) = etcd_client.kv_delete(path, None).await {
+        tracing::warn!(%err, "Failed to remove standby registration");
+    }
+    Ok(activated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standby_path() {
+        assert_eq!(
+            standby_path("instances/dynamo/backend/generate:1a"),
+            "standby/dynamo/backend/generate:1a"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Registry of the long-lived tasks of a [`Runtime`](crate::Runtime): watchers, event loops,
+//! keep-alives. A task spawned with [`TaskRegistry::spawn`] is tracked by name until it ends, and
+//! how it ended is kept, so that a loop that died is visible in [`TaskRegistry::list`] and in the
+//! logs instead of silently gone.
+//!
+//! A task that fails or panics is logged as an error and makes the registry unhealthy. Loops that
+//! should run as long as the runtime return an error when they stop for any other reason, for
+//! example their subscription closing. [`Worker`](crate::Worker) waits for the tasks to end after
+//! the application returned, and logs those still running.
+//!
+//! Short tasks, like one per request, should not be tracked: the registry keeps every task it
+//! saw.
+
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::{Future, FutureExt};
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+
+/// How a tracked task is doing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+
+    /// Returned successfully
+    Finished,
+
+    Failed {
+        error: String,
+    },
+
+    Panicked {
+        error: String,
+    },
+
+    /// Dropped before it finished, because it was aborted or its tokio runtime shut down
+    Aborted,
+}
+
+impl TaskStatus {
+    /// Neither failed nor panicked
+    pub fn is_healthy(&self) -> bool {
+        !matches!(
+            self,
+            TaskStatus::Failed { .. } | TaskStatus::Panicked { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: String,
+    pub status: TaskStatus,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+/// What a tracked task returns: nothing, or a `Result` whose error is reported
+pub trait TaskResult: Send + 'static {
+    fn into_result(self) -> Result<(), String>;
+}
+
+impl TaskResult for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: std::fmt::Display + Send + 'static> TaskResult for Result<(), E> {
+    fn into_result(self) -> Result<(), String> {
+        // `{:#}` includes the context of anyhow errors
+        self.map_err(|err| format!("{err:#}"))
+    }
+}
+
+/// The long-lived tasks of a runtime, see the [module docs](self). Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct TaskRegistry {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    next_id: AtomicU64,
+    tasks: Mutex<BTreeMap<u64, TaskInfo>>,
+    /// Number of tasks still running
+    running: watch::Sender<usize>,
+}
+
+impl TaskRegistry {
+    pub(crate) fn new() -> Self {
+        TaskRegistry {
+            inner: Arc::new(Inner {
+                next_id: AtomicU64::new(1),
+                tasks: Mutex::new(BTreeMap::new()),
+                running: watch::channel(0).0,
+            }),
+        }
+    }
+
+    /// Spawn `task` on the current tokio runtime and track it as `name`
+    pub fn spawn<F>(&self, name: impl Into<String>, task: F) -> tokio::task::JoinHandle<()>
+    where
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        self.spawn_on(name, task, &Handle::current())
+    }
+
+    /// Spawn `task` on `handle` and track it as `name`
+    pub fn spawn_on<F>(
+        &self,
+        name: impl Into<String>,
+        task: F,
+        handle: &Handle,
+    ) -> tokio::task::JoinHandle<()>
+    where
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        let guard = self.start(name.into());
+        handle.spawn(async move {
+            let status = match AssertUnwindSafe(task).catch_unwind().await {
+                Ok(result) => match result.into_result() {
+                    Ok(()) => TaskStatus::Finished,
+                    Err(error) => TaskStatus::Failed { error },
+                },
+                Err(panic) => TaskStatus::Panicked {
+                    error: panic_message(panic),
+                },
+            };
+            guard.finish(status);
+        })
+    }
+
+    fn start(&self, name: String) -> TaskGuard {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!(id, %name, "Task started");
+        self.inner.tasks.lock().unwrap().insert(
+            id,
+            TaskInfo {
+                id,
+                name,
+                status: TaskStatus::Running,
+                started_at: Utc::now(),
+                ended_at: None,
+            },
+        );
+        self.inner.running.send_modify(|running| *running += 1);
+        TaskGuard {
+            inner: self.inner.clone(),
+            id,
+            finished: false,
+        }
+    }
+
+    /// Every task spawned so far, in the order they started
+    pub fn list(&self) -> Vec<TaskInfo> {
+        self.inner.tasks.lock().unwrap().values().cloned().collect()
+    }
+
+    /// True if no task failed or panicked
+    pub fn is_healthy(&self) -> bool {
+        self.inner
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .all(|task| task.status.is_healthy())
+    }
+
+    /// Wait up to `timeout` for every task to end. Returns the names of those still running.
+    pub async fn join(&self, timeout: Duration) -> Vec<String> {
+        let mut running = self.inner.running.subscribe();
+        if tokio::time::timeout(timeout, running.wait_for(|running| *running == 0))
+            .await
+            .is_ok()
+        {
+            return vec![];
+        }
+        self.list()
+            .into_iter()
+            .filter(|task| task.status == TaskStatus::Running)
+            .map(|task| task.name)
+            .collect()
+    }
+}
+
+/// Records how the task ended. Dropped without [`TaskGuard::finish`] if the task was aborted.
+struct TaskGuard {
+    inner: Arc<Inner>,
+    id: u64,
+    finished: bool,
+}
+
+impl TaskGuard {
+    fn finish(mut self, status: TaskStatus) {
+        self.finished = true;
+        self.record(status);
+    }
+
+    fn record(&self, status: TaskStatus) {
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        let Some(task) = tasks.get_mut(&self.id) else {
+            return;
+        };
+        match &status {
+            TaskStatus::Finished => tracing::debug!(name = %task.name, "Task finished"),
+            TaskStatus::Failed { error } => {
+                tracing::error!(name = %task.name, "Task failed: {error}")
+            }
+            TaskStatus::Panicked { error } => {
+                tracing::error!(name = %task.name, "Task panicked: {error}")
+            }
+            TaskStatus::Aborted => tracing::debug!(name = %task.name, "Task aborted"),
+            TaskStatus::Running => {}
+        }
+        task.status = status;
+        task.ended_at = Some(Utc::now());
+        drop(tasks);
+        self.inner.running.send_modify(|running| *running -= 1);
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.record(TaskStatus::Aborted);
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_util::sync::CancellationToken;
+
+    fn status(registry: &TaskRegistry, name: &str) -> TaskStatus {
+        registry
+            .list()
+            .into_iter()
+            .find(|task| task.name == name)
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_task_outcomes() {
+        let registry = TaskRegistry::new();
+
+        registry.spawn("finishes", async {}).await.unwrap();
+        registry
+            .spawn("fails", async { Err::<(), _>(anyhow::anyhow!("boom")) })
+            .await
+            .unwrap();
+        registry
+            .spawn("panics", async { panic!("oops") })
+            .await
+            .unwrap();
+        let aborted = registry.spawn("aborted", std::future::pending::<()>());
+        aborted.abort();
+        let _ = aborted.await;
+
+        assert_eq!(status(&registry, "finishes"), TaskStatus::Finished);
+        assert_eq!(
+            status(&registry, "fails"),
+            TaskStatus::Failed {
+                error: "boom".to_string()
+            }
+        );
+        assert_eq!(
+            status(&registry, "panics"),
+            TaskStatus::Panicked {
+                error: "oops".to_string()
+            }
+        );
+        assert_eq!(status(&registry, "aborted"), TaskStatus::Aborted);
+        assert!(!registry.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_join_on_shutdown() {
+        let shutdown = CancellationToken::new();
+        let registry = TaskRegistry::new();
+
+        let token = shutdown.clone();
+        registry.spawn("watcher", async move { token.cancelled().await });
+        registry.spawn("stuck", std::future::pending::<()>());
+        assert_eq!(status(&registry, "watcher"), TaskStatus::Running);
+        assert!(registry.is_healthy());
+
+        shutdown.cancel();
+        let still_running = registry.join(Duration::from_millis(50)).await;
+        assert_eq!(still_running, vec!["stuck".to_string()]);
+        assert_eq!(status(&registry, "watcher"), TaskStatus::Finished);
+    }
+}
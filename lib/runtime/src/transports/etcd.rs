@@ -13,13 +13,37 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{error, CancellationToken, ErrorContext, Result, Runtime};
+//! etcd transport
+//!
+//! The following environment variables are used to configure the etcd client:
+//!
+//! - `ETCD_ENDPOINTS`: comma separated etcd endpoints, the client fails over between them
+//! - `ETCD_DIAL_TIMEOUT_SECS`: how long connecting to an endpoint may take
+//!
+//! For authentication, see [`EtcdAuth`]:
+//!
+//! - `ETCD_AUTH_USERNAME`: the username
+//! - `ETCD_AUTH_PASSWORD`: the password, or `ETCD_AUTH_PASSWORD_FILE` with a file containing it
+//!
+//! For TLS, see [`EtcdTls`]:
+//!
+//! - `ETCD_TLS_CA_FILE`: verify the servers with this CA
+//! - `ETCD_TLS_CERT_FILE` and `ETCD_TLS_KEY_FILE`: the client certificate and its key
+//! - `ETCD_TLS_DOMAIN`: the name to verify the servers' certificates for
+//! - `ETCD_AUTH_CA`, `ETCD_AUTH_CLIENT_CERT` and `ETCD_AUTH_CLIENT_KEY`: the same certificates
+//!   and key inline, instead of in files
+//!
+//! The settings are checked when connecting, an invalid combination fails with an error naming
+//! the setting rather than a connection failure.
+
+use crate::{error, fault_injection, CancellationToken, ErrorContext, Result, Runtime};
 
 use async_nats::jetstream::kv;
 use derive_builder::Builder;
 use derive_getters::Dissolve;
 use futures::StreamExt;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 use validator::Validate;
@@ -107,13 +131,15 @@ impl Client {
     /// Create a new etcd client and tie the primary [`CancellationToken`] to the primary etcd lease.
     async fn create(config: ClientOptions, runtime: Runtime) -> Result<Self> {
         let token = runtime.primary_token();
-        let client =
-            etcd_client::Client::connect(config.etcd_url, config.etcd_connect_options).await?;
+        let connect_options = config.connect_options()?;
+        let client = etcd_client::Client::connect(&config.etcd_url, connect_options)
+            .await
+            .with_context(|| format!("connecting to etcd at {}", config.etcd_url.join(", ")))?;
 
         let lease_id = if config.attach_lease {
             let lease_client = client.lease_client();
 
-            let lease = create_lease(lease_client, 10, token)
+            let lease = create_lease(lease_client, 10, token, runtime.tasks())
                 .await
                 .context("creating primary lease")?;
 
@@ -152,9 +178,10 @@ impl Client {
     pub async fn create_lease(&self, ttl: i64) -> Result<Lease> {
         let token = self.runtime.child_token();
         let lease_client = self.client.lease_client();
+        let tasks = self.runtime.tasks().clone();
         self.runtime
             .secondary()
-            .spawn(create_lease(lease_client, ttl, token))
+            .spawn(async move { create_lease(lease_client, ttl, token, &tasks).await })
             .await?
     }
 
@@ -167,12 +194,25 @@ impl Client {
             .await?
     }
 
+    /// The seconds left of the lease `lease_id`, or None if it expired or was revoked
+    pub async fn lease_time_to_live(&self, lease_id: i64) -> Result<Option<i64>> {
+        let mut lease_client = self.client.lease_client();
+        let response = self
+            .runtime
+            .secondary()
+            .spawn(async move { lease_client.time_to_live(lease_id, None).await })
+            .await??;
+        // etcd answers -1 for a lease it doesn't know
+        Ok((response.ttl() >= 0).then_some(response.ttl()))
+    }
+
     pub async fn kv_create(
         &self,
         key: String,
         value: Vec<u8>,
         lease_id: Option<i64>,
     ) -> Result<()> {
+        fault_injection::etcd_delay().await;
         let id = lease_id.unwrap_or(self.lease_id());
         let put_options = PutOptions::new().with_lease(id);
 
@@ -203,6 +243,7 @@ impl Client {
         value: Vec<u8>,
         lease_id: Option<i64>,
     ) -> Result<()> {
+        fault_injection::etcd_delay().await;
         let id = lease_id.unwrap_or(self.lease_id());
         let put_options = PutOptions::new().with_lease(id);
 
@@ -242,12 +283,54 @@ impl Client {
         }
     }
 
+    /// Create `key` as [`Client::kv_create`] does and, in the same transaction, put each of
+    /// `others` without a lease if its version is still the one given, 0 for a key that must not
+    /// exist yet. Either everything is written or nothing is.
+    ///
+    /// Returns false if one of `others` changed since its version was read: read it again and
+    /// retry. Fails if `key` exists.
+    pub async fn kv_create_with(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        lease_id: Option<i64>,
+        others: Vec<(String, Vec<u8>, i64)>,
+    ) -> Result<bool> {
+        fault_injection::etcd_delay().await;
+        let id = lease_id.unwrap_or(self.lease_id());
+        let mut compares = vec![Compare::version(key.as_str(), CompareOp::Equal, 0)];
+        let mut puts = vec![TxnOp::put(
+            key.as_str(),
+            value,
+            Some(PutOptions::new().with_lease(id)),
+        )];
+        for (other_key, other_value, version) in others {
+            compares.push(Compare::version(
+                other_key.as_str(),
+                CompareOp::Equal,
+                version,
+            ));
+            puts.push(TxnOp::put(other_key, other_value, None));
+        }
+        let txn = Txn::new().when(compares).and_then(puts);
+        let result = self.client.kv_client().txn(txn).await?;
+        if result.succeeded() {
+            return Ok(true);
+        }
+        // Which compare failed?
+        if !self.kv_get(key.as_str(), None).await?.is_empty() {
+            return Err(error!("failed to create key {key}, it exists"));
+        }
+        Ok(false)
+    }
+
     pub async fn kv_put(
         &self,
         key: impl AsRef<str>,
         value: impl AsRef<[u8]>,
         lease_id: Option<i64>,
     ) -> Result<()> {
+        fault_injection::etcd_delay().await;
         let id = lease_id.unwrap_or(self.lease_id());
         let put_options = PutOptions::new().with_lease(id);
         let _ = self
@@ -264,6 +347,7 @@ impl Client {
         value: impl AsRef<[u8]>,
         options: Option<PutOptions>,
     ) -> Result<PutResponse> {
+        fault_injection::etcd_delay().await;
         let options = options
             .unwrap_or_default()
             .with_lease(self.primary_lease().id());
@@ -279,6 +363,7 @@ impl Client {
         key: impl Into<Vec<u8>>,
         options: Option<GetOptions>,
     ) -> Result<Vec<KeyValue>> {
+        fault_injection::etcd_delay().await;
         let mut get_response = self.client.kv_client().get(key, options).await?;
         Ok(get_response.take_kvs())
     }
@@ -288,6 +373,7 @@ impl Client {
         key: impl Into<Vec<u8>>,
         options: Option<DeleteOptions>,
     ) -> Result<i64> {
+        fault_injection::etcd_delay().await;
         self.client
             .kv_client()
             .delete(key, options)
@@ -297,6 +383,7 @@ impl Client {
     }
 
     pub async fn kv_get_prefix(&self, prefix: impl AsRef<str>) -> Result<Vec<KeyValue>> {
+        fault_injection::etcd_delay().await;
         let mut get_response = self
             .client
             .kv_client()
@@ -310,6 +397,7 @@ impl Client {
         &self,
         prefix: impl AsRef<str> + std::fmt::Display,
     ) -> Result<PrefixWatcher> {
+        fault_injection::etcd_delay().await;
         let mut kv_client = self.client.kv_client();
         let mut watch_client = self.client.watch_client();
 
@@ -342,21 +430,29 @@ impl Client {
 
         let (tx, rx) = mpsc::channel(32);
 
-        self.runtime.secondary().spawn(async move {
+        let task_name = format!("etcd watch {}", prefix.as_ref());
+        let cancel_token = self.runtime.primary_token();
+        let watch_prefix = prefix.as_ref().to_string();
+        let watch = async move {
             for kv in kvs {
                 if tx.send(WatchEvent::Put(kv)).await.is_err() {
                     // receiver is already closed
-                    return;
+                    return Ok(());
                 }
             }
 
             loop {
                 tokio::select! {
                     maybe_resp = watch_stream.next() => {
-                        // Early return for None or Err cases
-                        let Some(Ok(response)) = maybe_resp else {
-                            tracing::info!("kv watch stream closed");
-                            return;
+                        // The receivers would silently stop getting events
+                        let response = match maybe_resp {
+                            Some(Ok(response)) => response,
+                            Some(Err(err)) => {
+                                return Err(error!("kv watch stream for {watch_prefix} failed: {err}"));
+                            }
+                            None => {
+                                return Err(error!("kv watch stream for {watch_prefix} closed"));
+                            }
                         };
 
                         // Process events
@@ -371,12 +467,12 @@ impl Client {
                                 etcd_client::EventType::Put => {
                                     if let Err(err) = tx.send(WatchEvent::Put(kv.clone())).await {
                                         tracing::error!("kv watcher error forwarding WatchEvent::Put: {err}");
-                                        return;
+                                        return Ok(());
                                     }
                                 }
                                 etcd_client::EventType::Delete => {
                                     if tx.send(WatchEvent::Delete(kv.clone())).await.is_err() {
-                                        return;
+                                        return Ok(());
                                     }
                                 }
                             }
@@ -384,11 +480,17 @@ impl Client {
                     }
                     _ = tx.closed() => {
                         tracing::debug!("no more receivers, stopping watcher");
-                        return;
+                        return Ok(());
+                    }
+                    _ = cancel_token.cancelled() => {
+                        return Ok(());
                     }
                 }
             }
-        });
+        };
+        self.runtime
+            .tasks()
+            .spawn_on(task_name, watch, &self.runtime.secondary());
         Ok(PrefixWatcher {
             prefix: prefix.as_ref().to_string(),
             watcher,
@@ -413,12 +515,24 @@ pub enum WatchEvent {
 /// ETCD client configuration options
 #[derive(Debug, Clone, Builder, Validate)]
 pub struct ClientOptions {
+    /// Requests are spread over these endpoints, the client fails over between them
     #[validate(length(min = 1))]
     pub etcd_url: Vec<String>,
 
+    /// Extended with `auth`, `tls` and `dial_timeout`
     #[builder(default)]
     pub etcd_connect_options: Option<ConnectOptions>,
 
+    #[builder(default)]
+    pub auth: Option<EtcdAuth>,
+
+    #[builder(default)]
+    pub tls: Option<EtcdTls>,
+
+    /// How long connecting to an endpoint may take
+    #[builder(default)]
+    pub dial_timeout: Option<Duration>,
+
     /// If true, the client will attach a lease to the primary [`CancellationToken`].
     #[builder(default = "true")]
     pub attach_lease: bool,
@@ -426,43 +540,204 @@ pub struct ClientOptions {
 
 impl Default for ClientOptions {
     fn default() -> Self {
-        let mut connect_options = None;
-
-        if let (Ok(username), Ok(password)) = (
-            std::env::var("ETCD_AUTH_USERNAME"),
-            std::env::var("ETCD_AUTH_PASSWORD"),
-        ) {
-            // username and password are set
-            connect_options = Some(ConnectOptions::new().with_user(username, password));
-        } else if let (Ok(ca), Ok(cert), Ok(key)) = (
-            std::env::var("ETCD_AUTH_CA"),
-            std::env::var("ETCD_AUTH_CLIENT_CERT"),
-            std::env::var("ETCD_AUTH_CLIENT_KEY"),
-        ) {
-            // TLS is set
-            connect_options = Some(
-                ConnectOptions::new().with_tls(
-                    TlsOptions::new()
-                        .ca_certificate(Certificate::from_pem(ca))
-                        .identity(Identity::from_pem(cert, key)),
-                ),
-            );
-        }
-
+        let tls = EtcdTls::from_env();
         ClientOptions {
-            etcd_url: default_servers(),
-            etcd_connect_options: connect_options,
+            etcd_url: default_servers(tls.is_some()),
+            etcd_connect_options: None,
+            auth: EtcdAuth::from_env(),
+            tls,
+            dial_timeout: std::env::var("ETCD_DIAL_TIMEOUT_SECS")
+                .ok()
+                .and_then(|secs| match secs.parse() {
+                    Ok(secs) => Some(Duration::from_secs(secs)),
+                    Err(_) => {
+                        tracing::warn!(value = secs, "Ignoring invalid ETCD_DIAL_TIMEOUT_SECS");
+                        None
+                    }
+                }),
             attach_lease: true,
         }
     }
 }
 
-fn default_servers() -> Vec<String> {
+impl ClientOptions {
+    /// Check the settings and turn them into [`ConnectOptions`], so that mistakes are reported
+    /// as such instead of as a failure to connect
+    fn connect_options(&self) -> Result<Option<ConnectOptions>> {
+        self.validate()
+            .map_err(|err| error!("Invalid etcd client options: {err}"))?;
+        for url in &self.etcd_url {
+            validate_endpoint(url, self.tls.is_some())?;
+        }
+        if self.auth.is_none() && self.tls.is_none() && self.dial_timeout.is_none() {
+            return Ok(self.etcd_connect_options.clone());
+        }
+
+        let mut options = self.etcd_connect_options.clone().unwrap_or_default();
+        if let Some(auth) = &self.auth {
+            let (username, password) = auth.credentials()?;
+            options = options.with_user(username, password);
+        }
+        if let Some(tls) = &self.tls {
+            options = options.with_tls(tls.tls_options()?);
+        }
+        if let Some(timeout) = self.dial_timeout {
+            options = options.with_connect_timeout(timeout);
+        }
+        Ok(Some(options))
+    }
+}
+
+/// Endpoints are `host:port`, optionally with an `http://` or `https://` scheme
+fn validate_endpoint(url: &str, tls: bool) -> Result<()> {
+    let invalid = |reason: &str| error!("Invalid etcd endpoint {url:?}: {reason}");
+    let with_scheme = match url.split_once("://") {
+        Some(("http", _)) if tls => {
+            return Err(invalid(
+                "etcd TLS is configured, use https:// or leave out the scheme",
+            ))
+        }
+        Some(("http" | "https", _)) => url.to_string(),
+        Some((scheme, _)) => return Err(invalid(&format!("unsupported scheme {scheme}"))),
+        None => format!("http://{url}"),
+    };
+    let parsed = url::Url::parse(&with_scheme).map_err(|err| invalid(&err.to_string()))?;
+    if parsed.host_str().is_none_or(str::is_empty) {
+        return Err(invalid("missing host"));
+    }
+    Ok(())
+}
+
+/// Username and password authentication, from `ETCD_AUTH_USERNAME` and `ETCD_AUTH_PASSWORD` or
+/// `ETCD_AUTH_PASSWORD_FILE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EtcdAuth {
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+}
+
+/// A password, key or other secret, given directly or in a file
+#[derive(Clone, PartialEq, Eq)]
+pub enum Secret {
+    Inline(String),
+    File(PathBuf),
+}
+
+impl Secret {
+    fn read(&self, what: &str) -> Result<String> {
+        match self {
+            Secret::Inline(value) => Ok(value.clone()),
+            Secret::File(path) => std::fs::read_to_string(path)
+                .map(|value| value.trim_end().to_string())
+                .with_context(|| format!("Failed reading etcd {what} from {}", path.display())),
+        }
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secret::Inline(_) => write!(f, "Inline(<redacted>)"),
+            Secret::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+impl EtcdAuth {
+    /// None if neither username nor password are set
+    pub fn from_env() -> Option<Self> {
+        let username = std::env::var("ETCD_AUTH_USERNAME").ok();
+        let password = std::env::var("ETCD_AUTH_PASSWORD")
+            .ok()
+            .map(Secret::Inline)
+            .or_else(|| {
+                std::env::var("ETCD_AUTH_PASSWORD_FILE")
+                    .ok()
+                    .map(|path| Secret::File(PathBuf::from(path)))
+            });
+        (username.is_some() || password.is_some()).then_some(EtcdAuth { username, password })
+    }
+
+    fn credentials(&self) -> Result<(String, String)> {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Ok((username.clone(), password.read("password")?)),
+            _ => Err(error!(
+                "etcd authentication needs both a username (ETCD_AUTH_USERNAME) and a password (ETCD_AUTH_PASSWORD or ETCD_AUTH_PASSWORD_FILE)"
+            )),
+        }
+    }
+}
+
+/// TLS to the etcd servers. PEM certificates and keys, from the files in `ETCD_TLS_CA_FILE`,
+/// `ETCD_TLS_CERT_FILE` and `ETCD_TLS_KEY_FILE`, or inline in `ETCD_AUTH_CA`,
+/// `ETCD_AUTH_CLIENT_CERT` and `ETCD_AUTH_CLIENT_KEY`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EtcdTls {
+    /// Verify the servers with this CA
+    pub ca: Option<Secret>,
+
+    /// Client certificate and key, for servers with `--client-cert-auth`
+    pub client_cert: Option<Secret>,
+    pub client_key: Option<Secret>,
+
+    /// Verify the servers' certificates for this name instead of the endpoint's host
+    pub domain: Option<String>,
+}
+
+impl EtcdTls {
+    /// None if no TLS setting is set
+    pub fn from_env() -> Option<Self> {
+        let secret = |file_var: &str, inline_var: &str| {
+            std::env::var(file_var)
+                .ok()
+                .map(|path| Secret::File(PathBuf::from(path)))
+                .or_else(|| std::env::var(inline_var).ok().map(Secret::Inline))
+        };
+        let tls = EtcdTls {
+            ca: secret("ETCD_TLS_CA_FILE", "ETCD_AUTH_CA"),
+            client_cert: secret("ETCD_TLS_CERT_FILE", "ETCD_AUTH_CLIENT_CERT"),
+            client_key: secret("ETCD_TLS_KEY_FILE", "ETCD_AUTH_CLIENT_KEY"),
+            domain: std::env::var("ETCD_TLS_DOMAIN").ok(),
+        };
+        (tls != EtcdTls::default()).then_some(tls)
+    }
+
+    fn tls_options(&self) -> Result<TlsOptions> {
+        let mut options = TlsOptions::new();
+        if let Some(ca) = &self.ca {
+            options = options.ca_certificate(Certificate::from_pem(ca.read("CA certificate")?));
+        }
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                options = options.identity(Identity::from_pem(
+                    cert.read("client certificate")?,
+                    key.read("client key")?,
+                ));
+            }
+            (None, None) => {}
+            _ => {
+                return Err(error!(
+                    "etcd client certificate (ETCD_TLS_CERT_FILE) and key (ETCD_TLS_KEY_FILE) must be set together"
+                ))
+            }
+        }
+        if let Some(domain) = &self.domain {
+            options = options.domain_name(domain);
+        }
+        Ok(options)
+    }
+}
+
+/// The endpoints in `ETCD_ENDPOINTS`, comma separated, or the local default
+fn default_servers(tls: bool) -> Vec<String> {
     match std::env::var("ETCD_ENDPOINTS") {
         Ok(possible_list_of_urls) => possible_list_of_urls
             .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
             .map(|s| s.to_string())
             .collect(),
+        Err(_) if tls => vec!["https://localhost:2379".to_string()],
         Err(_) => vec!["http://localhost:2379".to_string()],
     }
 }
@@ -602,6 +877,73 @@ impl KvCache {
     }
 }
 
+#[cfg(test)]
+mod test_options {
+    use super::*;
+
+    fn options(urls: &[&str]) -> ClientOptions {
+        ClientOptions {
+            etcd_url: urls.iter().map(|url| url.to_string()).collect(),
+            etcd_connect_options: None,
+            auth: None,
+            tls: None,
+            dial_timeout: None,
+            attach_lease: false,
+        }
+    }
+
+    #[test]
+    fn test_endpoints() {
+        for url in [
+            "localhost:2379",
+            "http://10.0.0.1:2379",
+            "https://etcd-0:2379",
+        ] {
+            assert!(validate_endpoint(url, false).is_ok(), "{url}");
+        }
+        assert!(validate_endpoint("https://etcd-0:2379", true).is_ok());
+        assert!(validate_endpoint("etcd-0:2379", true).is_ok());
+        assert!(validate_endpoint("http://etcd-0:2379", true).is_err());
+        assert!(validate_endpoint("nats://etcd-0:2379", false).is_err());
+        assert!(options(&[]).connect_options().is_err());
+    }
+
+    #[test]
+    fn test_incomplete_settings() {
+        let mut config = options(&["etcd-0:2379", "etcd-1:2379"]);
+        config.auth = Some(EtcdAuth {
+            username: Some("dynamo".to_string()),
+            password: None,
+        });
+        let err = config.connect_options().unwrap_err().to_string();
+        assert!(err.contains("ETCD_AUTH_PASSWORD"), "{err}");
+
+        config.auth = None;
+        config.tls = Some(EtcdTls {
+            client_cert: Some(Secret::Inline("cert".to_string())),
+            ..Default::default()
+        });
+        let err = config.connect_options().unwrap_err().to_string();
+        assert!(err.contains("ETCD_TLS_KEY_FILE"), "{err}");
+
+        config.tls = Some(EtcdTls {
+            ca: Some(Secret::File(PathBuf::from("/nonexistent/ca.pem"))),
+            ..Default::default()
+        });
+        let err = config.connect_options().unwrap_err().to_string();
+        assert!(err.contains("/nonexistent/ca.pem"), "{err}");
+    }
+
+    #[test]
+    fn test_secret_not_in_debug() {
+        let auth = EtcdAuth {
+            username: Some("dynamo".to_string()),
+            password: Some(Secret::Inline("hunter2".to_string())),
+        };
+        assert!(!format!("{auth:?}").contains("hunter2"));
+    }
+}
+
 #[cfg(feature = "integration")]
 #[cfg(test)]
 mod tests {
@@ -653,6 +995,35 @@ mod tests {
             .await;
         assert!(result.is_err(), "");
 
+        // Create a key and put another only if unchanged
+        let other = "__integration_test_other";
+        let created = client
+            .kv_create_with(
+                format!("{key}_with"),
+                value.to_vec(),
+                Some(lease_id),
+                vec![(other.to_string(), value.to_vec(), 0)],
+            )
+            .await?;
+        assert!(created);
+        assert_eq!(client.kv_get(other, None).await?.len(), 1);
+
+        // The other key now has version 1, so nothing is written
+        let created = client
+            .kv_create_with(
+                format!("{key}_with2"),
+                value.to_vec(),
+                Some(lease_id),
+                vec![(other.to_string(), value.to_vec(), 0)],
+            )
+            .await?;
+        assert!(!created);
+        assert!(client
+            .kv_get(format!("{key}_with2"), None)
+            .await?
+            .is_empty());
+        client.kv_delete(other, None).await?;
+
         Ok(())
     }
 
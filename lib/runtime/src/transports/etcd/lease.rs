@@ -14,12 +14,15 @@
 // limitations under the License.
 
 use super::*;
+use crate::tasks::TaskRegistry;
 
 /// Create a [`Lease`] with a given time-to-live (TTL) attached to the [`CancellationToken`].
+/// The keep-alive task is tracked in `tasks`.
 pub async fn create_lease(
     mut lease_client: LeaseClient,
     ttl: i64,
     token: CancellationToken,
+    tasks: &TaskRegistry,
 ) -> Result<Lease> {
     let lease = lease_client.grant(ttl, None).await?;
 
@@ -28,14 +31,16 @@ pub async fn create_lease(
     let child = token.child_token();
     let clone = token.clone();
 
-    tokio::spawn(async move {
-        match keep_alive(lease_client, id, ttl, child).await {
+    tasks.spawn(format!("etcd lease {id:x} keep alive"), async move {
+        let result = keep_alive(lease_client, id, ttl, child).await;
+        match &result {
             Ok(_) => tracing::trace!("keep alive task exited successfully"),
             Err(e) => {
                 tracing::info!("keep alive task failed: {:?}", e);
                 token.cancel();
             }
         }
+        result
     });
 
     Ok(Lease {
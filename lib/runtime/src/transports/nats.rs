@@ -25,9 +25,24 @@
 //! - `NATS_AUTH_PASSWORD`: the password for authentication
 //! - `NATS_AUTH_TOKEN`: the token for authentication
 //! - `NATS_AUTH_NKEY`: the nkey for authentication
+//! - `NATS_AUTH_NKEY_FILE`: a file with the nkey seed, instead of `NATS_AUTH_NKEY`
 //! - `NATS_AUTH_CREDENTIALS_FILE`: the path to the credentials file
 //!
 //! Note: `NATS_AUTH_USERNAME` and `NATS_AUTH_PASSWORD` must be used together.
+//!
+//! For TLS, see [`NatsTls`]:
+//!
+//! - `NATS_TLS_CA_FILE`: trust servers signed by this CA, in addition to the system roots
+//! - `NATS_TLS_CERT_FILE` and `NATS_TLS_KEY_FILE`: the client certificate and its key
+//! - `NATS_TLS_REQUIRED`: refuse to connect without TLS
+//! - `NATS_TLS_FIRST`: start with the TLS handshake, for servers with `handshake_first`
+//!
+//! For reconnecting, see [`NatsReconnect`]:
+//!
+//! - `NATS_MAX_RECONNECTS`: give up after this many failed attempts in a row, default never
+//! - `NATS_RECONNECT_MAX_DELAY_MS`: the longest wait between attempts
+//! - `NATS_CONNECTION_TIMEOUT_SECS`: how long one attempt may take
+//! - `NATS_RETRY_ON_INITIAL_CONNECT`: also keep trying if NATS is down at startup
 use crate::Result;
 
 use async_nats::{client, jetstream, Subscriber};
@@ -218,6 +233,12 @@ pub struct ClientOptions {
 
     #[builder(default)]
     auth: NatsAuth,
+
+    #[builder(default)]
+    tls: NatsTls,
+
+    #[builder(default)]
+    reconnect: NatsReconnect,
 }
 
 fn default_server() -> String {
@@ -228,11 +249,17 @@ fn default_server() -> String {
     "nats://localhost:4222".to_string()
 }
 
+/// One server, or a comma separated list of the servers of a cluster
 fn validate_nats_server(server: &str) -> Result<(), ValidationError> {
-    if server.starts_with("nats://") {
+    if server
+        .split(',')
+        .all(|s| s.starts_with("nats://") || s.starts_with("tls://"))
+    {
         Ok(())
     } else {
-        Err(ValidationError::new("server must start with 'nats://'"))
+        Err(ValidationError::new(
+            "server must start with 'nats://' or 'tls://'",
+        ))
     }
 }
 
@@ -247,17 +274,45 @@ impl ClientOptions {
     pub async fn connect(self) -> Result<Client> {
         self.validate()?;
 
-        let client = match self.auth {
+        let mut client = match self.auth {
             NatsAuth::UserPass(username, password) => {
                 async_nats::ConnectOptions::with_user_and_password(username, password)
             }
             NatsAuth::Token(token) => async_nats::ConnectOptions::with_token(token),
             NatsAuth::NKey(nkey) => async_nats::ConnectOptions::with_nkey(nkey),
+            NatsAuth::NKeyFile(path) => {
+                let seed = tokio::fs::read_to_string(&path).await.map_err(|err| {
+                    anyhow::anyhow!("Failed reading NATS nkey seed {}: {err}", path.display())
+                })?;
+                async_nats::ConnectOptions::with_nkey(seed.trim().to_string())
+            }
             NatsAuth::CredentialsFile(path) => {
                 async_nats::ConnectOptions::with_credentials_file(path).await?
             }
         };
 
+        if let Some(ca_file) = self.tls.ca_file {
+            client = client.add_root_certificates(ca_file);
+        }
+        if let Some((cert_file, key_file)) = self.tls.client_cert {
+            client = client.add_client_certificate(cert_file, key_file);
+        }
+        if self.tls.required {
+            client = client.require_tls(true);
+        }
+        if self.tls.first {
+            client = client.tls_first();
+        }
+
+        let max_delay = self.reconnect.max_delay;
+        client = client
+            .max_reconnects(self.reconnect.max_reconnects)
+            .connection_timeout(self.reconnect.connection_timeout)
+            .reconnect_delay_callback(move |attempts| reconnect_delay(attempts, max_delay));
+        if self.reconnect.retry_on_initial_connect {
+            client = client.retry_on_initial_connect();
+        }
+
         let client = client.connect(self.server).await?;
         let js_ctx = jetstream::new(client.clone());
 
@@ -270,6 +325,8 @@ impl Default for ClientOptions {
         ClientOptions {
             server: default_server(),
             auth: NatsAuth::default(),
+            tls: NatsTls::default(),
+            reconnect: NatsReconnect::default(),
         }
     }
 }
@@ -279,6 +336,8 @@ pub enum NatsAuth {
     UserPass(String, String),
     Token(String),
     NKey(String),
+    /// A file with the nkey seed
+    NKeyFile(PathBuf),
     CredentialsFile(PathBuf),
 }
 
@@ -290,6 +349,7 @@ impl std::fmt::Debug for NatsAuth {
             }
             NatsAuth::Token(_token) => write!(f, "Token(<redacted>)"),
             NatsAuth::NKey(_nkey) => write!(f, "NKey(<redacted>)"),
+            NatsAuth::NKeyFile(path) => write!(f, "NKeyFile({:?})", path),
             NatsAuth::CredentialsFile(path) => write!(f, "CredentialsFile({:?})", path),
         }
     }
@@ -312,6 +372,10 @@ impl Default for NatsAuth {
             return NatsAuth::NKey(nkey);
         }
 
+        if let Ok(path) = std::env::var("NATS_AUTH_NKEY_FILE") {
+            return NatsAuth::NKeyFile(PathBuf::from(path));
+        }
+
         if let Ok(path) = std::env::var("NATS_AUTH_CREDENTIALS_FILE") {
             return NatsAuth::CredentialsFile(PathBuf::from(path));
         }
@@ -320,6 +384,116 @@ impl Default for NatsAuth {
     }
 }
 
+/// TLS for the NATS connection. The files are PEM. A `tls://` server URL also turns TLS on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatsTls {
+    /// Trust servers signed by this CA, in addition to the system roots
+    pub ca_file: Option<PathBuf>,
+
+    /// Client certificate and its key, for servers that verify clients
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+
+    /// Refuse to connect without TLS
+    pub required: bool,
+
+    /// Start with the TLS handshake instead of waiting for the server's INFO, for servers
+    /// configured with `handshake_first`
+    pub first: bool,
+}
+
+impl NatsTls {
+    pub fn from_env() -> Self {
+        let cert = std::env::var("NATS_TLS_CERT_FILE").ok();
+        let key = std::env::var("NATS_TLS_KEY_FILE").ok();
+        NatsTls {
+            ca_file: std::env::var("NATS_TLS_CA_FILE").ok().map(PathBuf::from),
+            client_cert: cert
+                .zip(key)
+                .map(|(cert, key)| (PathBuf::from(cert), PathBuf::from(key))),
+            required: crate::config::env_is_truthy("NATS_TLS_REQUIRED"),
+            first: crate::config::env_is_truthy("NATS_TLS_FIRST"),
+        }
+    }
+}
+
+/// How to reconnect after losing the connection to NATS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatsReconnect {
+    /// Give up after this many failed attempts in a row. None never gives up.
+    pub max_reconnects: Option<usize>,
+
+    /// The wait between attempts doubles up to this
+    pub max_delay: time::Duration,
+
+    /// How long one attempt may take
+    pub connection_timeout: time::Duration,
+
+    /// Also keep trying in the background if NATS can't be reached at startup, instead of
+    /// failing to start
+    pub retry_on_initial_connect: bool,
+}
+
+impl NatsReconnect {
+    pub fn from_env() -> Self {
+        let mut reconnect = NatsReconnect::default_values();
+        if let Some(max) = env_parse::<usize>("NATS_MAX_RECONNECTS") {
+            reconnect.max_reconnects = Some(max);
+        }
+        if let Some(ms) = env_parse::<u64>("NATS_RECONNECT_MAX_DELAY_MS") {
+            reconnect.max_delay = time::Duration::from_millis(ms);
+        }
+        if let Some(secs) = env_parse::<u64>("NATS_CONNECTION_TIMEOUT_SECS") {
+            reconnect.connection_timeout = time::Duration::from_secs(secs);
+        }
+        reconnect.retry_on_initial_connect =
+            crate::config::env_is_truthy("NATS_RETRY_ON_INITIAL_CONNECT");
+        reconnect
+    }
+
+    /// The defaults of the NATS client
+    fn default_values() -> Self {
+        NatsReconnect {
+            max_reconnects: None,
+            max_delay: time::Duration::from_secs(4),
+            connection_timeout: time::Duration::from_secs(5),
+            retry_on_initial_connect: false,
+        }
+    }
+}
+
+impl Default for NatsTls {
+    fn default() -> Self {
+        NatsTls::from_env()
+    }
+}
+
+impl Default for NatsReconnect {
+    fn default() -> Self {
+        NatsReconnect::from_env()
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            log::warn!(setting = name, value, "Ignoring invalid NATS setting");
+            None
+        }
+    }
+}
+
+/// Wait before reconnect attempt `attempts`: none for the first, then doubling from 10ms up to
+/// `max_delay`
+fn reconnect_delay(attempts: usize, max_delay: time::Duration) -> time::Duration {
+    if attempts <= 1 {
+        return time::Duration::ZERO;
+    }
+    let exp = (attempts - 2).min(32) as u32;
+    time::Duration::from_millis(10u64.saturating_mul(1u64 << exp)).min(max_delay)
+}
+
 /// Is this file name / url in the NATS object store?
 /// Checks the name only, does not go to the store.
 pub fn is_nats_url(s: &str) -> bool {
@@ -539,4 +713,49 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn test_client_options_secured() {
+        Jail::expect_with(|jail| {
+            jail.set_env("NATS_SERVER", "tls://nats-0:4222,tls://nats-1:4222");
+            jail.set_env("NATS_AUTH_CREDENTIALS_FILE", "/etc/nats/user.creds");
+            jail.set_env("NATS_TLS_CA_FILE", "/etc/nats/ca.pem");
+            jail.set_env("NATS_TLS_CERT_FILE", "/etc/nats/client.pem");
+            jail.set_env("NATS_TLS_KEY_FILE", "/etc/nats/client-key.pem");
+            jail.set_env("NATS_TLS_REQUIRED", "true");
+            jail.set_env("NATS_MAX_RECONNECTS", "10");
+            jail.set_env("NATS_RECONNECT_MAX_DELAY_MS", "2000");
+
+            let opts = ClientOptions::builder().build().unwrap();
+            assert!(opts.validate().is_ok());
+            assert_eq!(
+                opts.auth,
+                NatsAuth::CredentialsFile(PathBuf::from("/etc/nats/user.creds"))
+            );
+            assert_eq!(opts.tls.ca_file, Some(PathBuf::from("/etc/nats/ca.pem")));
+            assert!(opts.tls.client_cert.is_some());
+            assert!(opts.tls.required);
+            assert!(!opts.tls.first);
+            assert_eq!(opts.reconnect.max_reconnects, Some(10));
+            assert_eq!(opts.reconnect.max_delay, time::Duration::from_secs(2));
+            assert!(!opts.reconnect.retry_on_initial_connect);
+
+            Ok(())
+        });
+
+        let opts = ClientOptions::builder()
+            .server("http://localhost:4222")
+            .build()
+            .unwrap();
+        assert!(opts.validate().is_err());
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        let max = time::Duration::from_secs(4);
+        assert_eq!(reconnect_delay(1, max), time::Duration::ZERO);
+        assert_eq!(reconnect_delay(2, max), time::Duration::from_millis(10));
+        assert_eq!(reconnect_delay(4, max), time::Duration::from_millis(40));
+        assert_eq!(reconnect_delay(1000, max), max);
+    }
 }
@@ -24,6 +24,10 @@
 //! the calling thread until the application completes or is canceled. The method initialized
 //! the signal handler used to trap `SIGINT` and `SIGTERM` signals and trigger a graceful shutdown.
 //!
+//! On a signal the worker first drains: its endpoints are removed from etcd, stop taking new
+//! requests, and get up to `DYN_WORKER_DRAIN_TIMEOUT` seconds to finish the requests in flight, see
+//! [crate::drain]. A second `Ctrl+C` skips the drain.
+//!
 //! On termination, the user application is given a graceful shutdown period of controlled by
 //! the [DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT] environment variable. If the application does not
 //! shutdown in time, the worker will terminate the application with an exit code of 911.
@@ -33,6 +37,8 @@
 //! in release, the default is [DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_RELEASE].
 
 use super::{error, CancellationToken, Result, Runtime, RuntimeConfig};
+use crate::config::WorkerConfig;
+use crate::lifecycle::LifecycleStage;
 
 use futures::Future;
 use once_cell::sync::OnceCell;
@@ -48,6 +54,11 @@ const SHUTDOWN_MESSAGE: &str =
 const SHUTDOWN_TIMEOUT_MESSAGE: &str =
     "Use DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT to control the graceful shutdown timeout";
 
+const DRAIN_TIMEOUT_MESSAGE: &str = "Use DYN_WORKER_DRAIN_TIMEOUT to control the drain timeout";
+
+/// How long to wait for the runtime's tracked tasks once the application returned
+const TASK_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Environment variable to control the graceful shutdown timeout
 pub const DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT: &str = "DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT";
 
@@ -103,6 +114,7 @@ impl Worker {
         let runtime = self.runtime.clone();
         runtime.secondary().block_on(self.execute_internal(f))??;
         runtime.shutdown();
+        crate::otel::shutdown();
         Ok(())
     }
 
@@ -115,6 +127,7 @@ impl Worker {
         let task = self.execute_internal(f);
         task.await??;
         runtime.shutdown();
+        crate::otel::shutdown();
         Ok(())
     }
 
@@ -142,9 +155,10 @@ impl Worker {
 
         INIT.set(Mutex::new(Some(secondary.spawn(async move {
             // start signal handler
-            tokio::spawn(signal_handler(runtime.cancellation_token.clone()));
+            tokio::spawn(signal_handler(runtime.clone()));
 
             let cancel_token = runtime.child_token();
+            let lifecycle_runtime = runtime.clone();
             let (mut app_tx, app_rx) = tokio::sync::oneshot::channel::<()>();
 
             // spawn a task to run the application
@@ -170,9 +184,21 @@ impl Worker {
 
                 _ = tokio::time::sleep(tokio::time::Duration::from_secs(timeout)) => {
                     tracing::debug!("Application did not shutdown in time; terminating");
+                    shutdown_hooks(&lifecycle_runtime).await;
                     std::process::exit(911);
                 }
-            }?;
+            };
+            // Long-lived tasks stop with the runtime
+            lifecycle_runtime.shutdown();
+            let still_running = lifecycle_runtime.tasks().join(TASK_JOIN_TIMEOUT).await;
+            if !still_running.is_empty() {
+                tracing::warn!(
+                    ?still_running,
+                    "Tasks did not stop within {TASK_JOIN_TIMEOUT:?}"
+                );
+            }
+            shutdown_hooks(&lifecycle_runtime).await;
+            let result = result?;
 
             match &result {
                 Ok(_) => {
@@ -206,8 +232,18 @@ impl Worker {
     }
 }
 
-/// Catch signals and trigger a shutdown
-async fn signal_handler(cancel_token: CancellationToken) -> Result<()> {
+/// Run the shutdown lifecycle hooks that haven't run yet. Pre-drain hasn't run if the application
+/// returned by itself. Shutdown hooks log their own errors.
+async fn shutdown_hooks(runtime: &Runtime) {
+    let _ = runtime.run_lifecycle_hooks(LifecycleStage::PreDrain).await;
+    let _ = runtime
+        .run_lifecycle_hooks(LifecycleStage::PostShutdown)
+        .await;
+}
+
+/// Catch signals, drain and trigger a shutdown
+async fn signal_handler(runtime: Runtime) -> Result<()> {
+    let cancel_token = runtime.primary_token();
     let ctrl_c = async {
         signal::ctrl_c().await?;
         anyhow::Ok(())
@@ -220,18 +256,40 @@ async fn signal_handler(cancel_token: CancellationToken) -> Result<()> {
         anyhow::Ok(())
     };
 
-    tokio::select! {
+    let signaled = tokio::select! {
         _ = ctrl_c => {
             tracing::info!("Ctrl+C received, starting graceful shutdown");
+            true
         },
         _ = sigterm => {
             tracing::info!("SIGTERM received, starting graceful shutdown");
+            true
         },
         _ = cancel_token.cancelled() => {
             tracing::debug!("CancellationToken triggered; shutting down");
+            false
         },
+    };
+
+    if signaled {
+        // Finish the requests in flight before shutting down, unless asked twice
+        let timeout = Duration::from_secs(WorkerConfig::from_settings().drain_timeout);
+        tokio::select! {
+            drained = runtime.drain(timeout) => {
+                if !drained {
+                    tracing::warn!("Requests still in flight after {timeout:?}; {DRAIN_TIMEOUT_MESSAGE}");
+                }
+            },
+            _ = signal::ctrl_c() => {
+                tracing::info!("Ctrl+C received again, skipping drain");
+            },
+            _ = cancel_token.cancelled() => {},
+        }
     }
 
+    // Shutdown hooks log their own errors
+    let _ = runtime.run_lifecycle_hooks(LifecycleStage::PreDrain).await;
+
     // trigger a shutdown
     cancel_token.cancel();
 